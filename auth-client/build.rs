@@ -0,0 +1,13 @@
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    // Only the frozen v1 API is wrapped, so clients keep working across server upgrades. The
+    // error details tell the client when to retry.
+    tonic_build::configure().build_server(false).compile(
+        &[
+            "../proto/authentication/v1/authentication.proto",
+            "../proto/google/rpc/error_details.proto",
+            "../proto/google/rpc/status.proto",
+        ],
+        &["../proto"],
+    )?;
+    Ok(())
+}
@@ -0,0 +1,126 @@
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicU8, Ordering};
+use std::sync::Arc;
+use std::task::{Context, Poll};
+
+use tonic::body::BoxBody;
+use tonic::transport::{Body, Channel};
+use tower::Service;
+
+use crate::circuit_breaker::CircuitBreaker;
+
+/// Whether the auth service could be reached, as of the last call.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ConnectionState {
+    /// No call was made yet, lazily connected clients only connect on their first call.
+    Idle,
+    /// The last call reached the auth service, whether or not it succeeded.
+    Connected,
+    /// The last call couldn't reach the auth service. The next call reconnects.
+    Disconnected,
+}
+
+impl ConnectionState {
+    fn from_u8(state: u8) -> Self {
+        match state {
+            1 => Self::Connected,
+            2 => Self::Disconnected,
+            _ => Self::Idle,
+        }
+    }
+}
+
+/// A channel recording whether its calls reach the server, and turning calls away while its
+/// circuit breaker is open. Clones share the connection, its state and the circuit breaker.
+#[derive(Clone, Debug)]
+pub(crate) struct TrackedChannel {
+    channel: Channel,
+    state: Arc<AtomicU8>,
+    circuit_breaker: Option<CircuitBreaker>,
+}
+
+impl TrackedChannel {
+    pub(crate) fn new(channel: Channel, state: ConnectionState) -> Self {
+        Self {
+            channel,
+            state: Arc::new(AtomicU8::new(state as u8)),
+            circuit_breaker: None,
+        }
+    }
+
+    pub(crate) fn with_circuit_breaker(mut self, circuit_breaker: CircuitBreaker) -> Self {
+        self.circuit_breaker = Some(circuit_breaker);
+        self
+    }
+
+    pub(crate) fn state(&self) -> ConnectionState {
+        ConnectionState::from_u8(self.state.load(Ordering::Relaxed))
+    }
+}
+
+impl Service<http::Request<BoxBody>> for TrackedChannel {
+    type Response = http::Response<Body>;
+    type Error = Box<dyn std::error::Error + Send + Sync>;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.channel.poll_ready(cx).map_err(Into::into)
+    }
+
+    fn call(&mut self, request: http::Request<BoxBody>) -> Self::Future {
+        if let Some(Err(open)) = self.circuit_breaker.as_ref().map(CircuitBreaker::allow) {
+            return Box::pin(std::future::ready(Err(open.into())));
+        }
+        let state = self.state.clone();
+        let circuit_breaker = self.circuit_breaker.clone();
+        let response = self.channel.call(request);
+
+        Box::pin(async move {
+            // Failed calls still get a response, only transport errors mean the server is
+            // unreachable
+            let response = response.await;
+            let new_state = match response {
+                Ok(_) => ConnectionState::Connected,
+                Err(_) => ConnectionState::Disconnected,
+            };
+            state.store(new_state as u8, Ordering::Relaxed);
+            if let Some(circuit_breaker) = circuit_breaker {
+                circuit_breaker.record(is_answered(&response));
+            }
+            Ok(response?)
+        })
+    }
+}
+
+/// Whether the server answered rather than being unreachable, overloaded or too slow, whether by
+/// itself or by a proxy in front of it. Failed calls are answered with their status in the
+/// headers, as there's no response body.
+fn is_answered(response: &Result<http::Response<Body>, tonic::transport::Error>) -> bool {
+    let Ok(response) = response else {
+        return false;
+    };
+    let grpc_status = response
+        .headers()
+        .get("grpc-status")
+        .map(|status| status.as_bytes());
+    let unavailable = matches!(grpc_status, Some(b"14" | b"4"));
+    let proxy_error = matches!(response.status().as_u16(), 502..=504);
+    !unavailable && !proxy_error
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::AuthClient;
+
+    #[tokio::test]
+    async fn should_report_unreachable_server() {
+        // Nothing listens on the discard port
+        let client = AuthClient::connect_lazy("http://127.0.0.1:9").unwrap();
+        assert_eq!(client.connection_state(), ConnectionState::Idle);
+
+        client.validate_session("token").await.unwrap_err();
+        assert_eq!(client.connection_state(), ConnectionState::Disconnected);
+    }
+}
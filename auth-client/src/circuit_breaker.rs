@@ -0,0 +1,185 @@
+use std::fmt;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// Whether a circuit breaker lets calls through.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CircuitState {
+    /// Calls go through, the auth service seems up.
+    Closed,
+    /// Calls fail right away, the auth service seems down.
+    Open,
+    /// One trial call goes through to check whether the auth service is back, the others fail
+    /// right away.
+    HalfOpen,
+}
+
+#[derive(Debug)]
+enum State {
+    Closed {
+        failures: u32,
+    },
+    Open {
+        until: Instant,
+    },
+    /// `trial_started` is when the last trial call was let through.
+    HalfOpen {
+        trial_started: Instant,
+    },
+}
+
+/// Stops calling the auth service for a while once calls keep failing to reach it, so callers
+/// fail fast with `AuthError::CircuitOpen` instead of each waiting for a timeout. After
+/// `open_duration` a single trial call is let through, closing the circuit again if it reaches
+/// the auth service. Calls the auth service answers count as reaching it, even if they failed,
+/// unless it answered `UNAVAILABLE` or `DEADLINE_EXCEEDED`. Clones share the circuit.
+#[derive(Clone, Debug)]
+pub struct CircuitBreaker {
+    failure_threshold: u32,
+    open_duration: Duration,
+    state: Arc<Mutex<State>>,
+}
+
+impl CircuitBreaker {
+    /// Opens the circuit after `failure_threshold` calls in a row fail, for `open_duration`.
+    pub fn new(failure_threshold: u32, open_duration: Duration) -> Self {
+        Self {
+            failure_threshold: failure_threshold.max(1),
+            open_duration,
+            state: Arc::new(Mutex::new(State::Closed { failures: 0 })),
+        }
+    }
+
+    pub fn state(&self) -> CircuitState {
+        match *self.state.lock().unwrap() {
+            State::Closed { .. } => CircuitState::Closed,
+            State::Open { until } if Instant::now() < until => CircuitState::Open,
+            State::Open { .. } | State::HalfOpen { .. } => CircuitState::HalfOpen,
+        }
+    }
+
+    /// Lets a call through, or says how long until the next trial call if the circuit is open.
+    pub(crate) fn allow(&self) -> Result<(), CircuitOpen> {
+        let now = Instant::now();
+        let mut state = self.state.lock().unwrap();
+        match *state {
+            State::Closed { .. } => Ok(()),
+            State::Open { until } if now < until => Err(CircuitOpen {
+                retry_after: until - now,
+            }),
+            // A trial call that never finished, e.g. because it was cancelled, doesn't block
+            // the next one for longer than the circuit stays open
+            State::HalfOpen { trial_started } if now < trial_started + self.open_duration => {
+                Err(CircuitOpen {
+                    retry_after: trial_started + self.open_duration - now,
+                })
+            }
+            State::Open { .. } | State::HalfOpen { .. } => {
+                *state = State::HalfOpen { trial_started: now };
+                Ok(())
+            }
+        }
+    }
+
+    /// Records whether a call that was let through reached the auth service.
+    pub(crate) fn record(&self, reached: bool) {
+        let mut state = self.state.lock().unwrap();
+        *state = match (&*state, reached) {
+            (_, true) => State::Closed { failures: 0 },
+            (State::Closed { failures }, false) if failures + 1 < self.failure_threshold => {
+                State::Closed {
+                    failures: failures + 1,
+                }
+            }
+            (_, false) => State::Open {
+                until: Instant::now() + self.open_duration,
+            },
+        };
+    }
+}
+
+impl Default for CircuitBreaker {
+    fn default() -> Self {
+        Self::new(5, Duration::from_secs(10))
+    }
+}
+
+/// Why a call was turned away without being made.
+#[derive(Debug)]
+pub(crate) struct CircuitOpen {
+    pub(crate) retry_after: Duration,
+}
+
+impl fmt::Display for CircuitOpen {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "The circuit breaker is open")
+    }
+}
+
+impl std::error::Error for CircuitOpen {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{AuthClient, AuthError};
+
+    #[test]
+    fn should_open_after_consecutive_failures() {
+        let breaker = CircuitBreaker::new(3, Duration::from_secs(60));
+
+        breaker.record(false);
+        breaker.record(false);
+        // Calls reaching the auth service reset the count
+        breaker.record(true);
+        breaker.record(false);
+        breaker.record(false);
+        assert_eq!(breaker.state(), CircuitState::Closed);
+        assert!(breaker.allow().is_ok());
+
+        breaker.record(false);
+        assert_eq!(breaker.state(), CircuitState::Open);
+        let retry_after = breaker.allow().unwrap_err().retry_after;
+        assert!(retry_after <= Duration::from_secs(60));
+        assert!(retry_after > Duration::from_secs(59));
+    }
+
+    #[test]
+    fn should_let_one_trial_call_through_when_half_open() {
+        let breaker = CircuitBreaker::new(1, Duration::from_millis(20));
+        breaker.record(false);
+        assert!(breaker.allow().is_err());
+
+        std::thread::sleep(Duration::from_millis(25));
+        assert_eq!(breaker.state(), CircuitState::HalfOpen);
+        assert!(breaker.allow().is_ok());
+        assert!(breaker.allow().is_err());
+
+        // A failed trial opens the circuit again, a successful one closes it
+        breaker.record(false);
+        assert_eq!(breaker.state(), CircuitState::Open);
+        std::thread::sleep(Duration::from_millis(25));
+        assert!(breaker.allow().is_ok());
+        breaker.record(true);
+        assert_eq!(breaker.state(), CircuitState::Closed);
+        assert!(breaker.allow().is_ok());
+    }
+
+    #[tokio::test]
+    async fn should_fail_fast_while_open() {
+        // Nothing listens on the discard port
+        let breaker = CircuitBreaker::new(2, Duration::from_secs(60));
+        let client = AuthClient::connect_lazy("http://127.0.0.1:9")
+            .unwrap()
+            .with_circuit_breaker(breaker.clone());
+
+        for _ in 0..2 {
+            let e = client.validate_session("token").await.unwrap_err();
+            assert!(matches!(e, AuthError::Rpc(_)));
+        }
+        assert_eq!(breaker.state(), CircuitState::Open);
+
+        let e = client.validate_session("token").await.unwrap_err();
+        assert!(matches!(e, AuthError::CircuitOpen { .. }));
+        assert!(e.is_transient());
+    }
+}
@@ -0,0 +1,305 @@
+use std::collections::HashSet;
+use std::time::Duration;
+
+use tokio::sync::mpsc::Sender;
+use tonic::transport::{Channel, Endpoint};
+use tower::discover::Change;
+
+use crate::AuthError;
+
+/// How many endpoint changes are buffered for the balancing channel.
+const CHANGE_BUFFER: usize = 64;
+
+/// Finds the addresses the auth service's replicas listen on.
+#[tonic::async_trait]
+pub trait Resolve: Send + Sync + 'static {
+    /// `host:port` of each replica, e.g. `10.0.0.7:50051`.
+    async fn resolve(&self) -> Result<Vec<String>, String>;
+}
+
+/// Always the same replicas, e.g. for a fixed set of instances.
+#[tonic::async_trait]
+impl Resolve for Vec<String> {
+    async fn resolve(&self) -> Result<Vec<String>, String> {
+        Ok(self.clone())
+    }
+}
+
+/// A channel spreading calls over the replicas `resolver` finds, resolving them again every
+/// `interval` to pick up new replicas and drop removed ones. Fails if the first resolution fails
+/// or finds no replicas. Later failures keep the replicas found last.
+pub(crate) async fn balanced_channel(
+    resolver: impl Resolve,
+    interval: Duration,
+) -> Result<Channel, AuthError> {
+    let addresses = resolve(&resolver).await.map_err(AuthError::Discovery)?;
+    let (channel, changes) = Channel::balance_channel(CHANGE_BUFFER);
+    let mut current = HashSet::new();
+    apply(&changes, &mut current, addresses).await;
+
+    tokio::spawn(async move {
+        let mut ticks = tokio::time::interval(interval);
+        ticks.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+        ticks.tick().await;
+        // Stops once every clone of the client is dropped
+        while !changes.is_closed() {
+            ticks.tick().await;
+            if let Ok(addresses) = resolve(&resolver).await {
+                apply(&changes, &mut current, addresses).await;
+            }
+        }
+    });
+    Ok(channel)
+}
+
+async fn resolve(resolver: &impl Resolve) -> Result<Vec<String>, String> {
+    let addresses = resolver.resolve().await?;
+    match addresses.is_empty() {
+        true => Err("Error, no auth service replicas were found".to_owned()),
+        false => Ok(addresses),
+    }
+}
+
+/// Adds the replicas at `addresses` that aren't in `current` to the channel and removes the
+/// ones that are gone.
+async fn apply(
+    changes: &Sender<Change<String, Endpoint>>,
+    current: &mut HashSet<String>,
+    addresses: Vec<String>,
+) {
+    let addresses: HashSet<String> = addresses.into_iter().collect();
+    for removed in current.difference(&addresses) {
+        let _ = changes.send(Change::Remove(removed.clone())).await;
+    }
+    for added in addresses.difference(current) {
+        if let Ok(endpoint) = Endpoint::from_shared(format!("http://{added}")) {
+            let _ = changes.send(Change::Insert(added.clone(), endpoint)).await;
+        }
+    }
+    *current = addresses;
+}
+
+/// `host:port`, with IPv6 addresses in brackets.
+#[cfg(any(feature = "dns-srv", feature = "consul"))]
+fn authority(host: &str, port: u16) -> String {
+    match host.contains(':') && !host.starts_with('[') {
+        true => format!("[{host}]:{port}"),
+        false => format!("{host}:{port}"),
+    }
+}
+
+/// Finds the replicas by the DNS SRV records of a name, e.g. `_grpc._tcp.auth.example.com`.
+/// Only the records with the lowest priority are used, calls are spread evenly over them
+/// regardless of their weight.
+#[cfg(feature = "dns-srv")]
+pub struct SrvResolver {
+    name: String,
+    resolver: hickory_resolver::TokioAsyncResolver,
+}
+
+#[cfg(feature = "dns-srv")]
+impl SrvResolver {
+    /// Looks up `name` with the system's DNS configuration, e.g. `/etc/resolv.conf`.
+    pub fn new(name: impl Into<String>) -> Result<Self, AuthError> {
+        let resolver = hickory_resolver::TokioAsyncResolver::tokio_from_system_conf()
+            .map_err(|e| AuthError::Discovery(format!("Failed to read the DNS config.\n{e}")))?;
+        Ok(Self {
+            name: name.into(),
+            resolver,
+        })
+    }
+}
+
+#[cfg(feature = "dns-srv")]
+#[tonic::async_trait]
+impl Resolve for SrvResolver {
+    async fn resolve(&self) -> Result<Vec<String>, String> {
+        let records = self
+            .resolver
+            .srv_lookup(self.name.as_str())
+            .await
+            .map_err(|e| format!("Failed to look up {}.\n{e}", self.name))?;
+        let priority = records.iter().map(|record| record.priority()).min();
+        Ok(records
+            .iter()
+            .filter(|record| Some(record.priority()) == priority)
+            .map(|record| {
+                let target = record.target().to_utf8();
+                authority(target.trim_end_matches('.'), record.port())
+            })
+            .collect())
+    }
+}
+
+/// Finds the replicas passing their health checks in Consul's catalog.
+#[cfg(feature = "consul")]
+pub struct ConsulResolver {
+    client: hyper::Client<hyper::client::HttpConnector>,
+    url: http::Uri,
+    token: Option<http::HeaderValue>,
+}
+
+#[cfg(feature = "consul")]
+impl ConsulResolver {
+    /// Looks up `service` in the Consul agent at `consul`, e.g. `http://127.0.0.1:8500`.
+    pub fn new(consul: &str, service: &str) -> Result<Self, AuthError> {
+        let url = format!(
+            "{}/v1/health/service/{}?passing=true",
+            consul.trim_end_matches('/'),
+            service
+        )
+        .parse()
+        .map_err(|e| AuthError::Discovery(format!("Error, invalid Consul address.\n{e}")))?;
+        Ok(Self {
+            client: hyper::Client::new(),
+            url,
+            token: None,
+        })
+    }
+
+    /// Authenticates with a Consul ACL token.
+    pub fn with_token(mut self, token: &str) -> Result<Self, AuthError> {
+        self.token = Some(
+            token
+                .parse()
+                .map_err(|_| AuthError::Discovery("Error, invalid Consul token".to_owned()))?,
+        );
+        Ok(self)
+    }
+}
+
+#[cfg(feature = "consul")]
+#[tonic::async_trait]
+impl Resolve for ConsulResolver {
+    async fn resolve(&self) -> Result<Vec<String>, String> {
+        let mut request = http::Request::get(self.url.clone());
+        if let Some(token) = &self.token {
+            request = request.header("x-consul-token", token);
+        }
+        let request = request
+            .body(hyper::Body::empty())
+            .map_err(|e| format!("Failed to build the Consul request.\n{e}"))?;
+
+        let response = self
+            .client
+            .request(request)
+            .await
+            .map_err(|e| format!("Failed to reach Consul.\n{e}"))?;
+        if !response.status().is_success() {
+            return Err(format!("Consul returned {}", response.status()));
+        }
+        let body = hyper::body::to_bytes(response.into_body())
+            .await
+            .map_err(|e| format!("Failed to read Consul's response.\n{e}"))?;
+        parse_consul_services(&body)
+    }
+}
+
+/// The addresses in a response of Consul's `/v1/health/service` endpoint. Services registered
+/// without an address listen on their node's.
+#[cfg(feature = "consul")]
+fn parse_consul_services(body: &[u8]) -> Result<Vec<String>, String> {
+    let entries: serde_json::Value = serde_json::from_slice(body)
+        .map_err(|e| format!("Error, invalid response from Consul.\n{e}"))?;
+    let entries = entries
+        .as_array()
+        .ok_or("Error, Consul returned no list of services")?;
+
+    entries
+        .iter()
+        .map(|entry| {
+            let service = &entry["Service"];
+            let address = service["Address"]
+                .as_str()
+                .filter(|address| !address.is_empty())
+                .or(entry["Node"]["Address"].as_str())
+                .ok_or("Error, a Consul service has no address")?;
+            let port = service["Port"]
+                .as_u64()
+                .and_then(|port| u16::try_from(port).ok())
+                .ok_or("Error, a Consul service has no port")?;
+            Ok(authority(address, port))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use super::*;
+
+    struct Replicas(Arc<Mutex<Vec<String>>>);
+
+    #[tonic::async_trait]
+    impl Resolve for Replicas {
+        async fn resolve(&self) -> Result<Vec<String>, String> {
+            Ok(self.0.lock().unwrap().clone())
+        }
+    }
+
+    #[tokio::test]
+    async fn should_apply_added_and_removed_replicas() {
+        let (changes, mut received) = tokio::sync::mpsc::channel(CHANGE_BUFFER);
+        let mut current = HashSet::new();
+
+        apply(
+            &changes,
+            &mut current,
+            vec!["a:1".to_owned(), "b:1".to_owned()],
+        )
+        .await;
+        apply(
+            &changes,
+            &mut current,
+            vec!["b:1".to_owned(), "c:1".to_owned()],
+        )
+        .await;
+        drop(changes);
+
+        let mut applied = Vec::new();
+        while let Some(change) = received.recv().await {
+            applied.push(match change {
+                Change::Insert(address, _) => format!("+{address}"),
+                Change::Remove(address) => format!("-{address}"),
+            });
+        }
+        applied[..2].sort();
+        assert_eq!(applied, ["+a:1", "+b:1", "-a:1", "+c:1"]);
+        assert_eq!(current, HashSet::from(["b:1".to_owned(), "c:1".to_owned()]));
+    }
+
+    #[tokio::test]
+    async fn should_fail_without_replicas() {
+        let replicas = Replicas(Arc::new(Mutex::new(Vec::new())));
+
+        let e = balanced_channel(replicas, Duration::from_secs(1))
+            .await
+            .unwrap_err();
+        assert!(matches!(e, AuthError::Discovery(_)));
+        assert!(e.is_transient());
+    }
+
+    #[cfg(any(feature = "dns-srv", feature = "consul"))]
+    #[test]
+    fn should_bracket_ipv6_addresses() {
+        assert_eq!(authority("auth-1", 50051), "auth-1:50051");
+        assert_eq!(authority("fd00::7", 50051), "[fd00::7]:50051");
+    }
+
+    #[cfg(feature = "consul")]
+    #[test]
+    fn should_parse_consul_services() {
+        let body = br#"[
+            {"Node": {"Address": "10.0.0.1"}, "Service": {"Address": "10.0.1.1", "Port": 50051}},
+            {"Node": {"Address": "10.0.0.2"}, "Service": {"Address": "", "Port": 50052}}
+        ]"#;
+
+        assert_eq!(
+            parse_consul_services(body).unwrap(),
+            ["10.0.1.1:50051", "10.0.0.2:50052"]
+        );
+        assert!(parse_consul_services(br#"[{"Service": {"Address": "a"}}]"#).is_err());
+        assert!(parse_consul_services(b"{}").is_err());
+    }
+}
@@ -0,0 +1,200 @@
+use std::fmt;
+use std::time::Duration;
+
+use tonic::Status;
+
+use crate::circuit_breaker::CircuitOpen;
+use crate::proto::{PasswordViolationInfo, StatusCode};
+
+/// Why a call to the auth service failed.
+#[derive(Debug)]
+pub enum AuthError {
+    /// The endpoint couldn't be parsed or connected to.
+    Connect(tonic::transport::Error),
+    /// No replicas of the auth service could be discovered.
+    Discovery(String),
+    /// The call wasn't made as the auth service seems down, see `CircuitBreaker`. The next call
+    /// is let through after `retry_after`.
+    CircuitOpen { retry_after: Duration },
+    /// The RPC itself failed, e.g. the auth service is unreachable or the deadline passed.
+    Rpc(Box<Status>),
+    /// The auth service refused the request, e.g. wrong credentials, a taken username or an
+    /// unknown session.
+    Rejected,
+    /// The credentials were correct but the user has to sign in with a TOTP code.
+    MfaRequired,
+    /// Too many failed sign-ins, the user can try again after `retry_after`.
+    AccountLocked { retry_after: Duration },
+    /// The credentials were correct but an administrator disabled the account.
+    AccountDisabled,
+    /// The user has too many active sessions and has to sign out of one first.
+    SessionLimitReached,
+    /// The new password breaks the password policy, with a message per broken rule.
+    WeakPassword(Vec<String>),
+    /// Sign ups, and maybe sign ins, are refused while the auth service is under maintenance.
+    Maintenance,
+    /// The auth service answered with a status code this client doesn't know.
+    UnknownStatus(i32),
+}
+
+impl AuthError {
+    /// Whether the same call may succeed later without changing it.
+    pub fn is_transient(&self) -> bool {
+        matches!(
+            self,
+            Self::Connect(_)
+                | Self::Discovery(_)
+                | Self::CircuitOpen { .. }
+                | Self::Rpc(_)
+                | Self::Maintenance
+        )
+    }
+}
+
+impl fmt::Display for AuthError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Connect(e) => write!(f, "Failed to connect to the auth service: {}", e),
+            Self::Discovery(e) => write!(f, "Failed to discover the auth service: {}", e),
+            Self::CircuitOpen { retry_after } => write!(
+                f,
+                "The auth service seems down, calls are paused for {} more seconds",
+                retry_after.as_secs()
+            ),
+            Self::Rpc(status) => write!(
+                f,
+                "Auth service call failed with {:?}: {}",
+                status.code(),
+                status.message()
+            ),
+            Self::Rejected => write!(f, "The auth service rejected the request"),
+            Self::MfaRequired => write!(f, "A TOTP code is required"),
+            Self::AccountLocked { retry_after } => write!(
+                f,
+                "The account is locked, try again in {} seconds",
+                retry_after.as_secs()
+            ),
+            Self::AccountDisabled => write!(f, "The account is disabled"),
+            Self::SessionLimitReached => write!(f, "Too many active sessions"),
+            Self::WeakPassword(violations) => {
+                write!(f, "The password is too weak: {}", violations.join(", "))
+            }
+            Self::Maintenance => write!(f, "The auth service is under maintenance"),
+            Self::UnknownStatus(status_code) => {
+                write!(
+                    f,
+                    "The auth service returned unknown status {}",
+                    status_code
+                )
+            }
+        }
+    }
+}
+
+impl std::error::Error for AuthError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Connect(e) => Some(e),
+            Self::Rpc(status) => Some(status.as_ref()),
+            _ => None,
+        }
+    }
+}
+
+impl From<tonic::transport::Error> for AuthError {
+    fn from(e: tonic::transport::Error) -> Self {
+        Self::Connect(e)
+    }
+}
+
+impl From<Status> for AuthError {
+    fn from(status: Status) -> Self {
+        // Calls the circuit breaker turned away fail with the reason as the status' source
+        let circuit_open = std::error::Error::source(&status)
+            .and_then(|source| source.downcast_ref::<CircuitOpen>());
+        match circuit_open {
+            Some(open) => Self::CircuitOpen {
+                retry_after: open.retry_after,
+            },
+            None => Self::Rpc(Box::new(status)),
+        }
+    }
+}
+
+/// Turns a response's status code into `Ok` on success, and the matching error otherwise.
+pub(crate) fn check(status_code: i32) -> Result<(), AuthError> {
+    match StatusCode::from_i32(status_code) {
+        Some(StatusCode::Success) => Ok(()),
+        Some(StatusCode::Failure) => Err(AuthError::Rejected),
+        Some(StatusCode::MfaRequired) => Err(AuthError::MfaRequired),
+        Some(StatusCode::AccountLocked) => Err(AuthError::AccountLocked {
+            retry_after: Duration::ZERO,
+        }),
+        Some(StatusCode::SessionLimitReached) => Err(AuthError::SessionLimitReached),
+        Some(StatusCode::WeakPassword) => Err(AuthError::WeakPassword(Vec::new())),
+        Some(StatusCode::AccountDisabled) => Err(AuthError::AccountDisabled),
+        Some(StatusCode::Maintenance) => Err(AuthError::Maintenance),
+        None => Err(AuthError::UnknownStatus(status_code)),
+    }
+}
+
+/// Like `check`, filling in why a sign up's password was too weak.
+pub(crate) fn check_sign_up(
+    status_code: i32,
+    violations: Vec<PasswordViolationInfo>,
+) -> Result<(), AuthError> {
+    check(status_code).map_err(|e| match e {
+        AuthError::WeakPassword(_) => AuthError::WeakPassword(
+            violations
+                .into_iter()
+                .map(|violation| violation.message)
+                .collect(),
+        ),
+        e => e,
+    })
+}
+
+/// Like `check`, filling in when a locked account can sign in again.
+pub(crate) fn check_sign_in(status_code: i32, retry_after: u64) -> Result<(), AuthError> {
+    check(status_code).map_err(|e| match e {
+        AuthError::AccountLocked { .. } => AuthError::AccountLocked {
+            retry_after: Duration::from_secs(retry_after),
+        },
+        e => e,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn should_map_status_codes_to_errors() {
+        assert!(check(StatusCode::Success.into()).is_ok());
+        assert!(matches!(
+            check(StatusCode::Failure.into()),
+            Err(AuthError::Rejected)
+        ));
+        assert!(matches!(
+            check(StatusCode::AccountDisabled.into()),
+            Err(AuthError::AccountDisabled)
+        ));
+        let maintenance = check(StatusCode::Maintenance.into()).unwrap_err();
+        assert!(matches!(maintenance, AuthError::Maintenance));
+        assert!(maintenance.is_transient());
+        assert!(matches!(check(42), Err(AuthError::UnknownStatus(42))));
+
+        assert!(matches!(
+            check_sign_in(StatusCode::AccountLocked.into(), 30),
+            Err(AuthError::AccountLocked { retry_after }) if retry_after == Duration::from_secs(30)
+        ));
+        let violations = vec![PasswordViolationInfo {
+            r#type: 0,
+            message: "Password must be at least 8 characters".to_owned(),
+        }];
+        assert!(matches!(
+            check_sign_up(StatusCode::WeakPassword.into(), violations),
+            Err(AuthError::WeakPassword(violations)) if violations == ["Password must be at least 8 characters"]
+        ));
+    }
+}
@@ -0,0 +1,373 @@
+//! Typed client for the auth service. Wraps the generated gRPC client so callers get `Result`s
+//! with an [`AuthError`] instead of checking each response's status code.
+
+use std::fmt;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use rand_core::{OsRng, RngCore};
+use sha2::{Digest, Sha256};
+use tonic::transport::{Channel, Endpoint};
+use tonic::Request;
+
+use channel::TrackedChannel;
+use error::{check, check_sign_in, check_sign_up};
+use proto::auth_client::AuthClient as GrpcAuthClient;
+use proto::{
+    GetSignUpChallengeRequest, IntrospectTokenRequest, RefreshSessionRequest, RevokeTokenRequest,
+    SignInRequest, SignOutRequest, SignUpRequest, ValidateSessionRequest,
+};
+
+pub use channel::ConnectionState;
+pub use circuit_breaker::{CircuitBreaker, CircuitState};
+#[cfg(feature = "consul")]
+pub use discovery::ConsulResolver;
+pub use discovery::Resolve;
+#[cfg(feature = "dns-srv")]
+pub use discovery::SrvResolver;
+pub use error::AuthError;
+pub use retry::{RetryBudget, RetryPolicy};
+pub use token_manager::{TokenInterceptor, TokenManager};
+
+mod channel;
+mod circuit_breaker;
+mod discovery;
+mod error;
+mod retry;
+mod token_manager;
+
+/// The generated types of the v1 API, for RPCs this client doesn't wrap.
+pub mod proto {
+    tonic::include_proto!("authentication.v1");
+}
+
+/// The standard error details the auth service attaches to failed calls.
+pub mod rpc {
+    tonic::include_proto!("google.rpc");
+}
+
+/// Shown in place of tokens.
+const REDACTED: &str = "[REDACTED]";
+
+/// A signed in user's session.
+#[derive(Clone, PartialEq, Eq)]
+pub struct Session {
+    pub user_uuid: String,
+    pub session_token: String,
+    /// Gets a new session token with `refresh_session` once this one expires.
+    pub refresh_token: String,
+}
+
+impl fmt::Debug for Session {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Session")
+            .field("user_uuid", &self.user_uuid)
+            .field("session_token", &format_args!("{}", REDACTED))
+            .field("refresh_token", &format_args!("{}", REDACTED))
+            .finish()
+    }
+}
+
+/// Who an active session belongs to and until when.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ValidSession {
+    pub user_uuid: String,
+    pub expires_at: SystemTime,
+}
+
+/// What `introspect_token` knows about an active token.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TokenInfo {
+    pub user_uuid: String,
+    pub issued_at: SystemTime,
+    pub expires_at: SystemTime,
+    /// Names of the groups the user is a member of, sorted.
+    pub groups: Vec<String>,
+}
+
+/// Client for the auth service. Cheap to clone, clones share the connection, its state and the
+/// retry budget, so one client should be created per auth service and shared. Calls reconnect
+/// by themselves once the connection is lost.
+#[derive(Clone, Debug)]
+pub struct AuthClient {
+    client: GrpcAuthClient<TrackedChannel>,
+    channel: TrackedChannel,
+    retry_policy: RetryPolicy,
+}
+
+impl AuthClient {
+    /// Connects to the auth service at `endpoint`, e.g. `http://auth:50051`, failing if it can't
+    /// be reached.
+    pub async fn connect(endpoint: impl Into<String>) -> Result<Self, AuthError> {
+        let channel = Endpoint::from_shared(endpoint.into())?.connect().await?;
+        Ok(Self::with_channel(channel, ConnectionState::Connected))
+    }
+
+    /// Connects to the auth service at `endpoint` on the first call, so the client can be
+    /// created before the auth service is up. Only fails if the endpoint is invalid.
+    pub fn connect_lazy(endpoint: impl Into<String>) -> Result<Self, AuthError> {
+        let channel = Endpoint::from_shared(endpoint.into())?.connect_lazy();
+        Ok(Self::new(channel))
+    }
+
+    /// Spreads calls over the auth service's replicas `resolver` finds, e.g. an `SrvResolver`,
+    /// resolving them again every `interval`. Fails if no replicas are found at first.
+    pub async fn discover(resolver: impl Resolve, interval: Duration) -> Result<Self, AuthError> {
+        let channel = discovery::balanced_channel(resolver, interval).await?;
+        Ok(Self::new(channel))
+    }
+
+    /// Calls the auth service over `channel`, e.g. to configure TLS or timeouts on its endpoint.
+    pub fn new(channel: Channel) -> Self {
+        Self::with_channel(channel, ConnectionState::Idle)
+    }
+
+    fn with_channel(channel: Channel, state: ConnectionState) -> Self {
+        let channel = TrackedChannel::new(channel, state);
+        Self {
+            client: GrpcAuthClient::new(channel.clone()),
+            channel,
+            retry_policy: RetryPolicy::none(),
+        }
+    }
+
+    /// Whether the last call reached the auth service, for health reporting.
+    pub fn connection_state(&self) -> ConnectionState {
+        self.channel.state()
+    }
+
+    /// Retries idempotent calls, `sign_up` (made idempotent with an idempotency key),
+    /// `sign_out`, `validate_session`, `introspect_token`, `revoke_token` and the
+    /// administrators' calls, by `retry_policy`. Other calls are only made once.
+    pub fn with_retry_policy(mut self, retry_policy: RetryPolicy) -> Self {
+        self.retry_policy = retry_policy;
+        self
+    }
+
+    /// Fails calls fast while the auth service seems down, see `CircuitBreaker`. Each attempt of
+    /// a retried call counts on its own.
+    pub fn with_circuit_breaker(mut self, circuit_breaker: CircuitBreaker) -> Self {
+        self.channel = self.channel.with_circuit_breaker(circuit_breaker);
+        self.client = GrpcAuthClient::new(self.channel.clone());
+        self
+    }
+
+    /// Signs up a new user, solving the sign up challenge when the auth service issues one.
+    /// `email` may be empty.
+    pub async fn sign_up(
+        &self,
+        username: &str,
+        password: &str,
+        email: &str,
+    ) -> Result<(), AuthError> {
+        let mut client = self.client.clone();
+        let challenge = client
+            .get_sign_up_challenge(Request::new(GetSignUpChallengeRequest {}))
+            .await?
+            .into_inner();
+        // Deployments without a challenge fail the request, leaving the challenge ID empty
+        let challenge_solution = match challenge.challenge_id.is_empty() {
+            true => String::new(),
+            false => solve_challenge(&challenge.challenge_id, challenge.difficulty),
+        };
+
+        // Retries send the same key, so they get the first attempt's result if it got through
+        // rather than failing because the username is taken by then
+        let idempotency_key = format!("{:016x}{:016x}", OsRng.next_u64(), OsRng.next_u64());
+        self.retry_policy
+            .retry(|| async {
+                let response = self
+                    .client
+                    .clone()
+                    .sign_up(Request::new(SignUpRequest {
+                        username: username.to_owned(),
+                        password: password.to_owned(),
+                        challenge_id: challenge.challenge_id.clone(),
+                        challenge_solution: challenge_solution.clone(),
+                        email: email.to_owned(),
+                        idempotency_key: idempotency_key.clone(),
+                    }))
+                    .await?
+                    .into_inner();
+                check_sign_up(response.status_code, response.password_violations)
+            })
+            .await
+    }
+
+    /// Signs in with a username or email. Fails with `MfaRequired` once the user enabled TOTP,
+    /// see `sign_in_with_totp`.
+    pub async fn sign_in(&self, username: &str, password: &str) -> Result<Session, AuthError> {
+        self.sign_in_with_totp(username, password, "").await
+    }
+
+    pub async fn sign_in_with_totp(
+        &self,
+        username: &str,
+        password: &str,
+        totp_code: &str,
+    ) -> Result<Session, AuthError> {
+        let response = self
+            .client
+            .clone()
+            .sign_in(Request::new(SignInRequest {
+                username: username.to_owned(),
+                password: password.to_owned(),
+                totp_code: totp_code.to_owned(),
+            }))
+            .await?
+            .into_inner();
+        check_sign_in(response.status_code, response.retry_after)?;
+
+        Ok(Session {
+            user_uuid: response.user_uuid,
+            session_token: response.session_token,
+            refresh_token: response.refresh_token,
+        })
+    }
+
+    pub async fn sign_out(&self, session_token: &str) -> Result<(), AuthError> {
+        self.retry_policy
+            .retry(|| async {
+                let response = self
+                    .client
+                    .clone()
+                    .sign_out(Request::new(SignOutRequest {
+                        session_token: session_token.to_owned(),
+                    }))
+                    .await?
+                    .into_inner();
+                check(response.status_code)
+            })
+            .await
+    }
+
+    /// Fails with `Rejected` if the session is unknown, expired or signed out.
+    pub async fn validate_session(&self, session_token: &str) -> Result<ValidSession, AuthError> {
+        self.retry_policy
+            .retry(|| async {
+                let response = self
+                    .client
+                    .clone()
+                    .validate_session(Request::new(ValidateSessionRequest {
+                        session_token: session_token.to_owned(),
+                    }))
+                    .await?
+                    .into_inner();
+                check(response.status_code)?;
+
+                Ok(ValidSession {
+                    user_uuid: response.user_uuid,
+                    expires_at: timestamp(response.expires_at),
+                })
+            })
+            .await
+    }
+
+    /// Returns a new session token, replacing the refresh token's current session.
+    pub async fn refresh_session(&self, refresh_token: &str) -> Result<String, AuthError> {
+        let response = self
+            .client
+            .clone()
+            .refresh_session(Request::new(RefreshSessionRequest {
+                refresh_token: refresh_token.to_owned(),
+            }))
+            .await?
+            .into_inner();
+        check(response.status_code)?;
+
+        Ok(response.session_token)
+    }
+
+    /// Returns `None` if the session or refresh token isn't active, whether unknown, expired or
+    /// revoked.
+    pub async fn introspect_token(&self, token: &str) -> Result<Option<TokenInfo>, AuthError> {
+        self.retry_policy
+            .retry(|| async {
+                let response = self
+                    .client
+                    .clone()
+                    .introspect_token(Request::new(IntrospectTokenRequest {
+                        token: token.to_owned(),
+                    }))
+                    .await?
+                    .into_inner();
+                check(response.status_code)?;
+
+                Ok(response.active.then(|| TokenInfo {
+                    user_uuid: response.user_uuid,
+                    issued_at: timestamp(response.issued_at),
+                    expires_at: timestamp(response.expires_at),
+                    groups: response.groups,
+                }))
+            })
+            .await
+    }
+
+    /// Revokes a session or refresh token along with its session. Unknown tokens succeed too.
+    pub async fn revoke_token(&self, token: &str) -> Result<(), AuthError> {
+        self.retry_policy
+            .retry(|| async {
+                let response = self
+                    .client
+                    .clone()
+                    .revoke_token(Request::new(RevokeTokenRequest {
+                        token: token.to_owned(),
+                    }))
+                    .await?
+                    .into_inner();
+                check(response.status_code)
+            })
+            .await
+    }
+}
+
+/// Finds a solution whose hash with `challenge_id` starts with `difficulty` zero bits.
+fn solve_challenge(challenge_id: &str, difficulty: u32) -> String {
+    (0u64..)
+        .map(|nonce| nonce.to_string())
+        .find(|nonce| {
+            leading_zero_bits(&Sha256::digest(format!("{}:{}", challenge_id, nonce))) >= difficulty
+        })
+        .unwrap_or_default()
+}
+
+fn leading_zero_bits(hash: &[u8]) -> u32 {
+    let mut bits = 0;
+    for byte in hash {
+        bits += byte.leading_zeros();
+        if *byte != 0 {
+            break;
+        }
+    }
+    bits
+}
+
+fn timestamp(secs: u64) -> SystemTime {
+    UNIX_EPOCH + Duration::from_secs(secs)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn should_solve_challenge() {
+        let solution = solve_challenge("challenge", 8);
+
+        let hash = Sha256::digest(format!("challenge:{}", solution));
+        assert!(leading_zero_bits(&hash) >= 8);
+    }
+
+    #[test]
+    fn should_redact_tokens_in_debug_output() {
+        let session = Session {
+            user_uuid: "uuid".to_owned(),
+            session_token: "session".to_owned(),
+            refresh_token: "refresh".to_owned(),
+        };
+
+        assert_eq!(
+            format!("{:?}", session),
+            "Session { user_uuid: \"uuid\", session_token: [REDACTED], refresh_token: [REDACTED] }"
+        );
+    }
+}
@@ -0,0 +1,272 @@
+use std::future::Future;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use prost::Message;
+use rand_core::{OsRng, RngCore};
+use tonic::{Code, Status};
+
+use crate::error::AuthError;
+use crate::rpc;
+
+/// Limits retries while the auth service keeps failing, so clients don't multiply its load when
+/// it's struggling. Works like gRPC's retry throttling: each failed call takes a token, each
+/// successful one gives back a fraction of one, and calls are only retried while more than half
+/// the tokens are left. Shared by clones of the policy it's in.
+#[derive(Debug)]
+pub struct RetryBudget {
+    max_tokens: f64,
+    token_ratio: f64,
+    tokens: Mutex<f64>,
+}
+
+impl RetryBudget {
+    /// A full budget of `max_tokens`, getting back `token_ratio` tokens per successful call.
+    pub fn new(max_tokens: u32, token_ratio: f64) -> Self {
+        Self {
+            max_tokens: f64::from(max_tokens),
+            token_ratio,
+            tokens: Mutex::new(f64::from(max_tokens)),
+        }
+    }
+
+    fn on_success(&self) {
+        let mut tokens = self.tokens.lock().unwrap();
+        *tokens = (*tokens + self.token_ratio).min(self.max_tokens);
+    }
+
+    /// Takes a token for a failed call and returns whether it may be retried.
+    fn on_failure(&self) -> bool {
+        let mut tokens = self.tokens.lock().unwrap();
+        *tokens = (*tokens - 1.0).max(0.0);
+        *tokens > self.max_tokens / 2.0
+    }
+}
+
+impl Default for RetryBudget {
+    fn default() -> Self {
+        Self::new(10, 0.1)
+    }
+}
+
+/// How idempotent calls are retried when the auth service is unavailable or asks to be called
+/// again later. Waits grow exponentially with full jitter, unless the server says how long to
+/// wait in a `google.rpc.RetryInfo` detail.
+#[derive(Clone, Debug)]
+pub struct RetryPolicy {
+    /// Calls made at most, the first one included.
+    pub max_attempts: u32,
+    /// Longest wait before the first retry.
+    pub initial_backoff: Duration,
+    /// Longest wait before any retry. Calls aren't retried if the server asks to wait longer.
+    pub max_backoff: Duration,
+    /// How much the longest wait grows per retry.
+    pub multiplier: f64,
+    pub budget: Arc<RetryBudget>,
+}
+
+impl RetryPolicy {
+    /// Makes each call once.
+    pub fn none() -> Self {
+        Self {
+            max_attempts: 1,
+            ..Self::default()
+        }
+    }
+
+    /// Calls `call` until it succeeds, fails in a way a retry won't fix, or the attempts or budget
+    /// run out.
+    pub(crate) async fn retry<T, F, Fut>(&self, mut call: F) -> Result<T, AuthError>
+    where
+        F: FnMut() -> Fut,
+        Fut: Future<Output = Result<T, AuthError>>,
+    {
+        let mut attempt = 1;
+        loop {
+            let status = match call().await {
+                Err(AuthError::Rpc(status)) if is_retryable(&status) => status,
+                // The call wasn't made, so it says nothing about whether retries would work
+                Err(e @ AuthError::CircuitOpen { .. }) => return Err(e),
+                result => {
+                    self.budget.on_success();
+                    return result;
+                }
+            };
+
+            if !self.budget.on_failure() || attempt >= self.max_attempts {
+                return Err(AuthError::Rpc(status));
+            }
+            let delay = match retry_delay(&status) {
+                Some(delay) if delay > self.max_backoff => return Err(AuthError::Rpc(status)),
+                Some(delay) => delay,
+                None => self.backoff(attempt).mul_f64(jitter()),
+            };
+            tokio::time::sleep(delay).await;
+            attempt += 1;
+        }
+    }
+
+    /// Longest wait before retrying after the `attempt`th call, counting from 1.
+    fn backoff(&self, attempt: u32) -> Duration {
+        let exponent = i32::try_from(attempt - 1).unwrap_or(i32::MAX);
+        let backoff = self.initial_backoff.as_secs_f64() * self.multiplier.powi(exponent);
+        Duration::try_from_secs_f64(backoff)
+            .unwrap_or(self.max_backoff)
+            .min(self.max_backoff)
+    }
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_attempts: 3,
+            initial_backoff: Duration::from_millis(100),
+            max_backoff: Duration::from_secs(5),
+            multiplier: 2.0,
+            budget: Arc::new(RetryBudget::default()),
+        }
+    }
+}
+
+/// Whether the call didn't reach the auth service, or the auth service asked to call it again.
+fn is_retryable(status: &Status) -> bool {
+    status.code() == Code::Unavailable || retry_delay(status).is_some()
+}
+
+/// How long the server asked to wait in a `google.rpc.RetryInfo` detail.
+fn retry_delay(status: &Status) -> Option<Duration> {
+    let details = rpc::Status::decode(status.details()).ok()?;
+    let retry_info = details
+        .details
+        .iter()
+        .find(|detail| detail.type_url == "type.googleapis.com/google.rpc.RetryInfo")?;
+    let retry_delay = rpc::RetryInfo::decode(retry_info.value.as_slice())
+        .ok()?
+        .retry_delay?;
+
+    Some(Duration::new(
+        u64::try_from(retry_delay.seconds).ok()?,
+        u32::try_from(retry_delay.nanos).ok()?,
+    ))
+}
+
+/// A random factor between 0 and 1.
+fn jitter() -> f64 {
+    OsRng.next_u64() as f64 / u64::MAX as f64
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicU32, Ordering};
+
+    use super::*;
+
+    fn unavailable() -> AuthError {
+        AuthError::from(Status::unavailable("Connection refused"))
+    }
+
+    fn rate_limited(retry_delay: Duration) -> AuthError {
+        let retry_info = rpc::RetryInfo {
+            retry_delay: Some(prost_types::Duration {
+                seconds: retry_delay.as_secs() as i64,
+                nanos: retry_delay.subsec_nanos() as i32,
+            }),
+        };
+        let details = rpc::Status {
+            code: Code::ResourceExhausted as i32,
+            message: "Too many requests".to_owned(),
+            details: vec![prost_types::Any {
+                type_url: "type.googleapis.com/google.rpc.RetryInfo".to_owned(),
+                value: retry_info.encode_to_vec(),
+            }],
+        };
+        AuthError::from(Status::with_details(
+            Code::ResourceExhausted,
+            "Too many requests",
+            details.encode_to_vec().into(),
+        ))
+    }
+
+    fn policy() -> RetryPolicy {
+        RetryPolicy {
+            initial_backoff: Duration::ZERO,
+            max_backoff: Duration::from_millis(10),
+            ..RetryPolicy::default()
+        }
+    }
+
+    async fn call_failing(policy: &RetryPolicy, errors: Vec<AuthError>) -> (u32, bool) {
+        let calls = AtomicU32::new(0);
+        let errors = Mutex::new(errors);
+        let result = policy
+            .retry(|| async {
+                calls.fetch_add(1, Ordering::SeqCst);
+                match errors.lock().unwrap().pop() {
+                    Some(e) => Err(e),
+                    None => Ok(()),
+                }
+            })
+            .await;
+        (calls.into_inner(), result.is_ok())
+    }
+
+    #[tokio::test]
+    async fn should_retry_unavailable_calls() {
+        let (calls, succeeded) = call_failing(&policy(), vec![unavailable(), unavailable()]).await;
+        assert_eq!((calls, succeeded), (3, true));
+
+        let (calls, succeeded) =
+            call_failing(&policy(), vec![unavailable(), unavailable(), unavailable()]).await;
+        assert_eq!((calls, succeeded), (3, false));
+    }
+
+    #[tokio::test]
+    async fn should_not_retry_rejected_calls() {
+        let (calls, succeeded) = call_failing(&policy(), vec![AuthError::Rejected]).await;
+        assert_eq!((calls, succeeded), (1, false));
+
+        let (calls, succeeded) =
+            call_failing(&policy(), vec![AuthError::from(Status::internal("Oops"))]).await;
+        assert_eq!((calls, succeeded), (1, false));
+    }
+
+    #[tokio::test]
+    async fn should_honor_retry_info() {
+        let (calls, succeeded) =
+            call_failing(&policy(), vec![rate_limited(Duration::from_millis(5))]).await;
+        assert_eq!((calls, succeeded), (2, true));
+
+        // Waiting longer than the policy allows isn't worth it
+        let (calls, succeeded) =
+            call_failing(&policy(), vec![rate_limited(Duration::from_secs(30))]).await;
+        assert_eq!((calls, succeeded), (1, false));
+    }
+
+    #[tokio::test]
+    async fn should_stop_retrying_when_budget_runs_out() {
+        let policy = RetryPolicy {
+            budget: Arc::new(RetryBudget::new(4, 1.0)),
+            ..policy()
+        };
+
+        // 4 tokens: the first failure leaves 3, above half, the second leaves 2, which isn't
+        let (calls, _) = call_failing(&policy, vec![unavailable(), unavailable()]).await;
+        assert_eq!(calls, 2);
+
+        // Successful calls fill the budget back up
+        call_failing(&policy, Vec::new()).await;
+        call_failing(&policy, Vec::new()).await;
+        let (calls, succeeded) = call_failing(&policy, vec![unavailable()]).await;
+        assert_eq!((calls, succeeded), (2, true));
+    }
+
+    #[test]
+    fn should_back_off_exponentially() {
+        let policy = RetryPolicy::default();
+
+        assert_eq!(policy.backoff(1), Duration::from_millis(100));
+        assert_eq!(policy.backoff(2), Duration::from_millis(200));
+        assert_eq!(policy.backoff(3), Duration::from_millis(400));
+        assert_eq!(policy.backoff(100), Duration::from_secs(5));
+    }
+}
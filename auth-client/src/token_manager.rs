@@ -0,0 +1,227 @@
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, SystemTime};
+
+use tokio::task::JoinHandle;
+use tonic::metadata::MetadataValue;
+use tonic::service::Interceptor;
+use tonic::{Request, Status};
+
+use crate::{AuthClient, AuthError, Session};
+
+/// How long before a session expires it's refreshed by default.
+const DEFAULT_REFRESH_MARGIN: Duration = Duration::from_secs(60);
+
+/// Shortest wait between background refreshes, e.g. while they fail but may work later, or when
+/// new sessions seem to expire right away because of clock skew.
+const MIN_REFRESH_INTERVAL: Duration = Duration::from_secs(5);
+
+/// The session token currently in use.
+#[derive(Debug)]
+struct CachedToken {
+    session_token: String,
+    expires_at: SystemTime,
+}
+
+/// Keeps a signed in service's session token fresh, refreshing it with the refresh token before
+/// it expires. Cheap to clone, clones share the token.
+#[derive(Clone)]
+pub struct TokenManager {
+    client: AuthClient,
+    user_uuid: String,
+    refresh_token: Arc<str>,
+    refresh_margin: Duration,
+    cached: Arc<RwLock<CachedToken>>,
+    refreshing: Arc<tokio::sync::Mutex<()>>,
+}
+
+impl TokenManager {
+    /// Signs in and manages the new session.
+    pub async fn sign_in(
+        client: AuthClient,
+        username: &str,
+        password: &str,
+    ) -> Result<Self, AuthError> {
+        let session = client.sign_in(username, password).await?;
+        Self::from_session(client, session).await
+    }
+
+    /// Manages a session signed in before, looking up when it expires.
+    pub async fn from_session(client: AuthClient, session: Session) -> Result<Self, AuthError> {
+        let expires_at = client
+            .validate_session(&session.session_token)
+            .await?
+            .expires_at;
+        Ok(Self::new(client, session, expires_at))
+    }
+
+    fn new(client: AuthClient, session: Session, expires_at: SystemTime) -> Self {
+        Self {
+            client,
+            user_uuid: session.user_uuid,
+            refresh_token: session.refresh_token.into(),
+            refresh_margin: DEFAULT_REFRESH_MARGIN,
+            cached: Arc::new(RwLock::new(CachedToken {
+                session_token: session.session_token,
+                expires_at,
+            })),
+            refreshing: Arc::new(tokio::sync::Mutex::new(())),
+        }
+    }
+
+    /// Refreshes sessions `refresh_margin` before they expire, or halfway through if they're
+    /// shorter than that.
+    pub fn with_refresh_margin(mut self, refresh_margin: Duration) -> Self {
+        self.refresh_margin = refresh_margin;
+        self
+    }
+
+    pub fn user_uuid(&self) -> &str {
+        &self.user_uuid
+    }
+
+    /// The cached session token, refreshed first if it's about to expire.
+    pub async fn session_token(&self) -> Result<String, AuthError> {
+        if SystemTime::now() >= self.refresh_at() {
+            self.refresh().await?;
+        }
+        Ok(self.cached.read().unwrap().session_token.clone())
+    }
+
+    /// Replaces the session with a new one. Concurrent refreshes wait for the first one rather
+    /// than each replacing the session.
+    pub async fn refresh(&self) -> Result<(), AuthError> {
+        let previous_token = self.cached.read().unwrap().session_token.clone();
+        let _refreshing = self.refreshing.lock().await;
+        if self.cached.read().unwrap().session_token != previous_token {
+            return Ok(());
+        }
+
+        let session_token = self.client.refresh_session(&self.refresh_token).await?;
+        let expires_at = self
+            .client
+            .validate_session(&session_token)
+            .await?
+            .expires_at;
+        *self.cached.write().unwrap() = CachedToken {
+            session_token,
+            expires_at,
+        };
+        Ok(())
+    }
+
+    /// Refreshes the session in the background before it expires, so `interceptor` never attaches
+    /// an expired token. Stops once the refresh token is rejected, e.g. because it expired or was
+    /// revoked.
+    pub fn spawn_refresh(&self) -> JoinHandle<()> {
+        let manager = self.clone();
+        tokio::spawn(async move {
+            let mut min_wait = Duration::ZERO;
+            loop {
+                let wait = manager
+                    .refresh_at()
+                    .duration_since(SystemTime::now())
+                    .unwrap_or_default();
+                tokio::time::sleep(wait.max(min_wait)).await;
+
+                if let Err(e) = manager.refresh().await {
+                    if !e.is_transient() {
+                        break;
+                    }
+                }
+                min_wait = MIN_REFRESH_INTERVAL;
+            }
+        })
+    }
+
+    /// Attaches the cached session token as `authorization: Bearer <token>` to calls made with
+    /// it, e.g. `GreeterClient::with_interceptor(channel, token_manager.interceptor())`.
+    pub fn interceptor(&self) -> TokenInterceptor {
+        TokenInterceptor {
+            cached: self.cached.clone(),
+        }
+    }
+
+    /// When the cached session should be refreshed.
+    fn refresh_at(&self) -> SystemTime {
+        let expires_at = self.cached.read().unwrap().expires_at;
+        let remaining = expires_at
+            .duration_since(SystemTime::now())
+            .unwrap_or_default();
+        expires_at - self.refresh_margin.min(remaining / 2)
+    }
+}
+
+/// Attaches a `TokenManager`'s session token to outgoing calls.
+#[derive(Clone)]
+pub struct TokenInterceptor {
+    cached: Arc<RwLock<CachedToken>>,
+}
+
+impl Interceptor for TokenInterceptor {
+    fn call(&mut self, mut request: Request<()>) -> Result<Request<()>, Status> {
+        let authorization = format!("Bearer {}", self.cached.read().unwrap().session_token);
+        let authorization = MetadataValue::try_from(authorization)
+            .map_err(|_| Status::unauthenticated("Error, the session token is invalid"))?;
+        request
+            .metadata_mut()
+            .insert("authorization", authorization);
+        Ok(request)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn expiring_in(expires_in: Duration) -> TokenManager {
+        let session = Session {
+            user_uuid: "uuid".to_owned(),
+            session_token: "session".to_owned(),
+            refresh_token: "refresh".to_owned(),
+        };
+        let client = AuthClient::connect_lazy("http://127.0.0.1:9").unwrap();
+        TokenManager::new(client, session, SystemTime::now() + expires_in)
+    }
+
+    #[tokio::test]
+    async fn should_attach_session_token() {
+        let token_manager = expiring_in(Duration::from_secs(3600));
+
+        let request = token_manager.interceptor().call(Request::new(())).unwrap();
+
+        assert_eq!(
+            request.metadata().get("authorization").unwrap(),
+            "Bearer session"
+        );
+        assert_eq!(token_manager.session_token().await.unwrap(), "session");
+    }
+
+    #[tokio::test]
+    async fn should_refresh_before_expiry() {
+        let token_manager =
+            expiring_in(Duration::from_secs(3600)).with_refresh_margin(Duration::from_secs(60));
+        let expires_at = token_manager.cached.read().unwrap().expires_at;
+        assert_eq!(
+            token_manager.refresh_at(),
+            expires_at - Duration::from_secs(60)
+        );
+
+        // Sessions shorter than the margin are refreshed halfway through
+        let token_manager =
+            expiring_in(Duration::from_secs(60)).with_refresh_margin(Duration::from_secs(600));
+        let refresh_in = token_manager
+            .refresh_at()
+            .duration_since(SystemTime::now())
+            .unwrap();
+        assert!(refresh_in <= Duration::from_secs(30));
+        assert!(refresh_in > Duration::from_secs(25));
+
+        // Expired sessions are refreshed on use, failing while the auth service is unreachable
+        let token_manager = expiring_in(Duration::ZERO);
+        assert!(token_manager
+            .session_token()
+            .await
+            .unwrap_err()
+            .is_transient());
+    }
+}
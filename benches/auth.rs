@@ -0,0 +1,329 @@
+//! Benchmarks the auth service's hot paths, so storage and locking changes can be compared
+//! against a baseline: `cargo bench --bench auth -- --save-baseline main` before a change, then
+//! `cargo bench --bench auth -- --baseline main` with it.
+//!
+//! Passwords are hashed with a single PBKDF2 round, so the benches time the stores and their
+//! locks rather than hashing, whose cost `PASSWORD_HASH_BENCHMARK_MILLIS` suggests.
+
+// The auth service is a binary, so the modules it's made of are compiled in here as well, and
+// not all of their items are used.
+#![allow(dead_code, unused_imports)]
+
+#[path = "../src/auth-service/access_log_layer.rs"]
+mod access_log_layer;
+#[path = "../src/auth-service/api_versions.rs"]
+mod api_versions;
+#[path = "../src/auth-service/auth.rs"]
+mod auth;
+#[path = "../src/auth-service/breached_passwords.rs"]
+mod breached_passwords;
+#[path = "../src/auth-service/challenges.rs"]
+mod challenges;
+#[path = "../src/auth-service/clock.rs"]
+mod clock;
+#[path = "../src/auth-service/config.rs"]
+mod config;
+#[path = "../src/auth-service/error.rs"]
+mod error;
+#[path = "../src/auth-service/expiry_wheel.rs"]
+mod expiry_wheel;
+#[path = "../src/auth-service/groups.rs"]
+mod groups;
+#[path = "../src/auth-service/idempotency.rs"]
+mod idempotency;
+#[path = "../src/auth-service/lockouts.rs"]
+mod lockouts;
+#[path = "../src/logging.rs"]
+mod logging;
+#[path = "../src/auth-service/login_history.rs"]
+mod login_history;
+#[path = "../src/auth-service/magic_links.rs"]
+mod magic_links;
+#[path = "../src/auth-service/mailer.rs"]
+mod mailer;
+#[path = "../src/auth-service/maintenance.rs"]
+mod maintenance;
+#[path = "../src/auth-service/metrics.rs"]
+mod metrics;
+#[path = "../src/auth-service/mfa.rs"]
+mod mfa;
+#[cfg(any(feature = "postgres", feature = "sqlite"))]
+#[path = "../src/auth-service/migrations.rs"]
+mod migrations;
+#[path = "../src/auth-service/oidc.rs"]
+mod oidc;
+#[path = "../src/auth-service/password_hashing.rs"]
+mod password_hashing;
+#[path = "../src/auth-service/password_policy.rs"]
+mod password_policy;
+#[path = "../src/auth-service/password_resets.rs"]
+mod password_resets;
+#[path = "../src/auth-service/random.rs"]
+mod random;
+#[path = "../src/auth-service/rate_limits.rs"]
+mod rate_limits;
+#[path = "../src/request_id.rs"]
+mod request_id;
+#[path = "../src/auth-service/runtime_stats.rs"]
+mod runtime_stats;
+#[path = "../src/auth-service/secret.rs"]
+mod secret;
+#[path = "../src/auth-service/session_auth.rs"]
+mod session_auth;
+#[path = "../src/auth-service/sessions.rs"]
+mod sessions;
+#[path = "../src/settings.rs"]
+mod settings;
+#[path = "../src/auth-service/storage_retry.rs"]
+mod storage_retry;
+#[path = "../src/auth-service/token_signing.rs"]
+mod token_signing;
+#[path = "../src/auth-service/user_ids.rs"]
+mod user_ids;
+#[path = "../src/auth-service/users.rs"]
+mod users;
+#[path = "../src/auth-service/validation.rs"]
+mod validation;
+
+use std::future::Future;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+use criterion::{criterion_group, criterion_main, BatchSize, Criterion, Throughput};
+use tokio::runtime::Runtime;
+use tokio::sync::{Mutex, RwLock};
+use tokio::task::JoinSet;
+use tonic::Request;
+
+use auth::authentication::auth_server::Auth;
+use auth::authentication::{SignInRequest, StatusCode};
+use auth::AuthService;
+use password_hashing::Pbkdf2Hasher;
+use sessions::{ClientMetadata, Sessions, SessionsImpl};
+use users::{Users, UsersImpl};
+
+/// Users and sessions already stored, so lookups search a realistically sized store.
+const EXISTING: usize = 10_000;
+
+/// Tasks validating sessions at once in `concurrent_validation_benches`.
+const VALIDATING_TASKS: usize = 64;
+const VALIDATIONS_PER_TASK: usize = 100;
+
+// The stores are timed behind the read/write locks `AuthService` keeps them behind.
+
+fn runtime() -> Runtime {
+    tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .expect("should build runtime")
+}
+
+/// A runtime whose tasks run in parallel, so they contend for the stores' locks.
+fn multi_thread_runtime() -> Runtime {
+    tokio::runtime::Builder::new_multi_thread()
+        .enable_all()
+        .build()
+        .expect("should build runtime")
+}
+
+/// In-memory users `user0` to `user{EXISTING - 1}`, all with the password `password`.
+async fn users() -> UsersImpl {
+    let mut users = UsersImpl::default().with_password_hasher(Box::new(Pbkdf2Hasher::new(1)));
+    for i in 0..EXISTING {
+        users
+            .create_user(format!("user{i}"), "password".to_owned(), String::new())
+            .await
+            .expect("should create user");
+    }
+    users
+}
+
+fn users_benches(c: &mut Criterion) {
+    let runtime = runtime();
+    let users = RwLock::new(runtime.block_on(users()));
+
+    let created = AtomicUsize::new(0);
+    c.bench_function("create_user", |b| {
+        b.to_async(&runtime).iter_batched(
+            || format!("new{}", created.fetch_add(1, Ordering::Relaxed)),
+            |username| async {
+                users
+                    .write()
+                    .await
+                    .create_user(username, "password".to_owned(), String::new())
+                    .await
+                    .expect("should create user")
+            },
+            BatchSize::SmallInput,
+        )
+    });
+
+    c.bench_function("get_user_uuid", |b| {
+        b.to_async(&runtime).iter(|| async {
+            users
+                .read()
+                .await
+                .get_user_uuid("user5000".to_owned(), "password".to_owned())
+                .await
+                .expect("should find user")
+        })
+    });
+}
+
+fn sessions_benches(c: &mut Criterion) {
+    let runtime = runtime();
+    let client = ClientMetadata::default();
+    let sessions = RwLock::new(SessionsImpl::default());
+    let session_tokens: Vec<String> = runtime.block_on(async {
+        let mut sessions = sessions.write().await;
+        let mut session_tokens = Vec::with_capacity(EXISTING);
+        for i in 0..EXISTING {
+            let session_token = sessions
+                .create_session(&format!("user{i}"), client.clone())
+                .await
+                .unwrap();
+            session_tokens.push(session_token);
+        }
+        session_tokens
+    });
+
+    c.bench_function("validate_session", |b| {
+        b.to_async(&runtime).iter(|| async {
+            sessions
+                .read()
+                .await
+                .get_session(&session_tokens[EXISTING / 2])
+                .await
+                .expect("should find session")
+        })
+    });
+
+    c.bench_function("create_session", |b| {
+        b.to_async(&runtime).iter(|| async {
+            sessions
+                .write()
+                .await
+                .create_session("user0", client.clone())
+                .await
+        })
+    });
+}
+
+/// Validates `session_tokens` with `validate` from `VALIDATING_TASKS` tasks at once.
+async fn validate_concurrently<F, Fut>(session_tokens: Arc<Vec<String>>, validate: F)
+where
+    F: Fn(String) -> Fut + Clone + Send + 'static,
+    Fut: Future<Output = bool> + Send,
+{
+    let mut tasks = JoinSet::new();
+    for task in 0..VALIDATING_TASKS {
+        let session_tokens = session_tokens.clone();
+        let validate = validate.clone();
+        tasks.spawn(async move {
+            for i in 0..VALIDATIONS_PER_TASK {
+                let session_token = &session_tokens[(task * VALIDATIONS_PER_TASK + i) % EXISTING];
+                assert!(validate(session_token.clone()).await);
+            }
+        });
+    }
+    while let Some(result) = tasks.join_next().await {
+        result.expect("should validate sessions");
+    }
+}
+
+/// Validates sessions from many tasks at once behind a `Mutex`, as the auth service did while
+/// `get_session` needed exclusive access, then behind the shared side of a `RwLock`.
+fn concurrent_validation_benches(c: &mut Criterion) {
+    let runtime = multi_thread_runtime();
+    let (sessions, session_tokens) = runtime.block_on(async {
+        let mut sessions = SessionsImpl::default();
+        let mut session_tokens = Vec::with_capacity(EXISTING);
+        for i in 0..EXISTING {
+            let session_token = sessions
+                .create_session(&format!("user{i}"), ClientMetadata::default())
+                .await
+                .unwrap();
+            session_tokens.push(session_token);
+        }
+        (sessions, Arc::new(session_tokens))
+    });
+
+    let mut group = c.benchmark_group("concurrent_validations");
+    group.throughput(Throughput::Elements(
+        (VALIDATING_TASKS * VALIDATIONS_PER_TASK) as u64,
+    ));
+
+    let exclusive = Arc::new(Mutex::new(sessions));
+    group.bench_function("exclusive", |b| {
+        b.to_async(&runtime).iter(|| {
+            let exclusive = exclusive.clone();
+            validate_concurrently(session_tokens.clone(), move |session_token| {
+                let exclusive = exclusive.clone();
+                async move {
+                    exclusive
+                        .lock()
+                        .await
+                        .get_session(&session_token)
+                        .await
+                        .is_some()
+                }
+            })
+        })
+    });
+
+    let sessions = Arc::into_inner(exclusive)
+        .expect("should have finished validating")
+        .into_inner();
+    let shared = Arc::new(RwLock::new(sessions));
+    group.bench_function("shared", |b| {
+        b.to_async(&runtime).iter(|| {
+            let shared = shared.clone();
+            validate_concurrently(session_tokens.clone(), move |session_token| {
+                let shared = shared.clone();
+                async move {
+                    shared
+                        .read()
+                        .await
+                        .get_session(&session_token)
+                        .await
+                        .is_some()
+                }
+            })
+        })
+    });
+
+    group.finish();
+}
+
+fn sign_in_benches(c: &mut Criterion) {
+    let runtime = runtime();
+    let users = runtime.block_on(users());
+    let auth_service = AuthService::new(
+        Box::new(RwLock::new(users)),
+        Box::new(RwLock::new(SessionsImpl::default())),
+    );
+
+    c.bench_function("sign_in", |b| {
+        b.to_async(&runtime).iter(|| async {
+            let request = Request::new(SignInRequest {
+                username: "user5000".to_owned(),
+                password: "password".to_owned(),
+                ..Default::default()
+            });
+            let response = auth_service
+                .sign_in(request)
+                .await
+                .expect("should answer sign in");
+            assert_eq!(response.get_ref().status_code, StatusCode::Success as i32);
+        })
+    });
+}
+
+criterion_group!(
+    benches,
+    users_benches,
+    sessions_benches,
+    concurrent_validation_benches,
+    sign_in_benches
+);
+criterion_main!(benches);
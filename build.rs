@@ -1,4 +1,24 @@
+use std::env;
+use std::path::PathBuf;
+
 fn main() -> Result<(), Box<dyn std::error::Error>> {
-    tonic_build::compile_protos("proto/authentication.proto")?;
+    // SQL migrations are embedded in the auth service
+    println!("cargo:rerun-if-changed=migrations");
+
+    // The descriptor set lets the auth service answer gRPC reflection requests
+    let out_dir = PathBuf::from(env::var("OUT_DIR")?);
+    tonic_build::configure()
+        .file_descriptor_set_path(out_dir.join("authentication_descriptor.bin"))
+        .compile(
+            &[
+                "proto/authentication/v1/authentication.proto",
+                "proto/authentication/v2/authentication.proto",
+                "proto/authentication/admin/v1/admin.proto",
+                "proto/google/rpc/error_details.proto",
+                "proto/google/rpc/status.proto",
+                "proto/health.proto",
+            ],
+            &["proto"],
+        )?;
     Ok(())
 }
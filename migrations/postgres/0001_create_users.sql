@@ -0,0 +1,32 @@
+-- Tables are only created if missing, so databases set up before migrations were tracked are
+-- adopted as they are.
+CREATE TABLE IF NOT EXISTS users (
+    user_uuid TEXT PRIMARY KEY,
+    username TEXT NOT NULL UNIQUE,
+    password TEXT NOT NULL,
+    display_name TEXT NOT NULL,
+    email TEXT NOT NULL,
+    created_at BIGINT NOT NULL,
+    deleted_at BIGINT,
+    disabled BOOLEAN NOT NULL DEFAULT FALSE
+);
+
+CREATE TABLE IF NOT EXISTS federated_identities (
+    issuer TEXT NOT NULL,
+    subject TEXT NOT NULL,
+    user_uuid TEXT NOT NULL REFERENCES users (user_uuid) ON DELETE CASCADE,
+    PRIMARY KEY (issuer, subject)
+);
+
+-- Emails are optional, but unique ignoring case when set.
+CREATE UNIQUE INDEX IF NOT EXISTS users_email ON users (lower(email)) WHERE email <> '';
+
+CREATE TABLE IF NOT EXISTS user_attributes (
+    user_uuid TEXT NOT NULL REFERENCES users (user_uuid) ON DELETE CASCADE,
+    name TEXT NOT NULL,
+    value TEXT NOT NULL,
+    PRIMARY KEY (user_uuid, name)
+);
+
+-- Keeps `search_users` pages cheap however far in they are.
+CREATE INDEX IF NOT EXISTS users_created_at ON users (created_at, user_uuid);
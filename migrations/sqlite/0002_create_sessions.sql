@@ -0,0 +1,22 @@
+-- Only token hashes are stored, so a leaked database file can't be used to hijack sessions.
+CREATE TABLE IF NOT EXISTS sessions (
+    token_hash TEXT PRIMARY KEY,
+    session_id TEXT NOT NULL,
+    user_uuid TEXT NOT NULL,
+    created_at INTEGER NOT NULL,
+    last_seen INTEGER NOT NULL,
+    expires_at INTEGER NOT NULL,
+    ip_address TEXT NOT NULL,
+    user_agent TEXT NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS sessions_user_uuid ON sessions (user_uuid);
+
+CREATE TABLE IF NOT EXISTS refresh_tokens (
+    token_hash TEXT PRIMARY KEY,
+    user_uuid TEXT NOT NULL,
+    session_token_hash TEXT NOT NULL,
+    expires_at INTEGER NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS refresh_tokens_user_uuid ON refresh_tokens (user_uuid);
@@ -0,0 +1,287 @@
+use std::fmt::Write as _;
+use std::future::Future;
+use std::io::Write as _;
+use std::net::SocketAddr;
+use std::pin::Pin;
+use std::str::FromStr;
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant, SystemTime};
+
+use time::format_description::well_known::Rfc3339;
+use time::macros::format_description;
+use time::OffsetDateTime;
+use tonic::transport::server::TcpConnectInfo;
+use tonic::{Code, Status};
+use tower::{Layer, Service};
+
+use crate::request_id::REQUEST_ID_HEADER;
+use crate::session_auth::AuthenticatedUser;
+
+/// How access log lines are written.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum AccessLogFormat {
+    /// Like the Common Log Format of web servers, with the gRPC status code and the duration in
+    /// seconds at the end.
+    Common,
+    /// One JSON object per line.
+    Json,
+}
+
+impl FromStr for AccessLogFormat {
+    type Err = String;
+
+    fn from_str(format: &str) -> Result<Self, Self::Err> {
+        match format {
+            "common" => Ok(AccessLogFormat::Common),
+            "json" => Ok(AccessLogFormat::Json),
+            _ => Err(format!("Error, unknown ACCESS_LOG: {}", format)),
+        }
+    }
+}
+
+/// What's logged about an RPC.
+#[derive(Clone, Debug)]
+struct AccessLogEntry {
+    time: SystemTime,
+    peer: Option<SocketAddr>,
+    path: String,
+    request_id: Option<String>,
+    status: Code,
+    duration: Duration,
+    user_uuid: Option<String>,
+}
+
+impl AccessLogEntry {
+    fn format(&self, format: AccessLogFormat) -> String {
+        match format {
+            AccessLogFormat::Common => self.common(),
+            AccessLogFormat::Json => self.json(),
+        }
+    }
+
+    fn common(&self) -> String {
+        let time = OffsetDateTime::from(self.time)
+            .format(format_description!(
+                "[day]/[month repr:short]/[year]:[hour]:[minute]:[second] +0000"
+            ))
+            .unwrap_or_default();
+
+        format!(
+            "{} - {} [{}] \"POST {} HTTP/2.0\" {} - {:.3}",
+            self.peer
+                .map_or("-".to_owned(), |peer| peer.ip().to_string()),
+            self.user_uuid.as_deref().unwrap_or("-"),
+            time,
+            self.path,
+            self.status as i32,
+            self.duration.as_secs_f64(),
+        )
+    }
+
+    fn json(&self) -> String {
+        let time = OffsetDateTime::from(self.time)
+            .format(&Rfc3339)
+            .unwrap_or_default();
+
+        let mut line = format!("{{\"time\":{}", json_string(&time));
+        let _ = write!(line, ",\"method\":{}", json_string(&self.path));
+        if let Some(peer) = self.peer {
+            let _ = write!(line, ",\"peer\":{}", json_string(&peer.to_string()));
+        }
+        if let Some(request_id) = &self.request_id {
+            let _ = write!(line, ",\"request_id\":{}", json_string(request_id));
+        }
+        let _ = write!(
+            line,
+            ",\"status\":{},\"duration_ms\":{:.3}",
+            self.status as i32,
+            self.duration.as_secs_f64() * 1000.0
+        );
+        if let Some(user_uuid) = &self.user_uuid {
+            let _ = write!(line, ",\"user_uuid\":{}", json_string(user_uuid));
+        }
+        line.push('}');
+        line
+    }
+}
+
+/// Quotes `value` as a JSON string.
+fn json_string(value: &str) -> String {
+    let mut quoted = String::with_capacity(value.len() + 2);
+    quoted.push('"');
+    for c in value.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            '\r' => quoted.push_str("\\r"),
+            '\t' => quoted.push_str("\\t"),
+            c if c.is_control() => {
+                let _ = write!(quoted, "\\u{:04x}", c as u32);
+            }
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+/// The address the request came from, unknown for Unix domain sockets.
+fn peer<B>(request: &http::Request<B>) -> Option<SocketAddr> {
+    let extensions = request.extensions();
+    if let Some(connect_info) = extensions.get::<TcpConnectInfo>() {
+        return connect_info.remote_addr();
+    }
+    #[cfg(feature = "tls")]
+    if let Some(connect_info) =
+        extensions.get::<tonic::transport::server::TlsConnectInfo<TcpConnectInfo>>()
+    {
+        return connect_info.get_ref().remote_addr();
+    }
+    None
+}
+
+/// Writes a line to stdout for every RPC once it's answered, with its method, peer address,
+/// gRPC status code, duration and the user authenticated by `SessionAuthLayer`, if any. Nothing
+/// is logged without a format.
+#[derive(Clone)]
+pub struct AccessLogLayer {
+    format: Option<AccessLogFormat>,
+}
+
+impl AccessLogLayer {
+    pub fn new(format: Option<AccessLogFormat>) -> Self {
+        Self { format }
+    }
+}
+
+impl<S> Layer<S> for AccessLogLayer {
+    type Service = AccessLog<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        AccessLog {
+            inner,
+            format: self.format,
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct AccessLog<S> {
+    inner: S,
+    format: Option<AccessLogFormat>,
+}
+
+impl<S, B, ResBody> Service<http::Request<B>> for AccessLog<S>
+where
+    S: Service<http::Request<B>, Response = http::Response<ResBody>>,
+    S::Future: Send + 'static,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, request: http::Request<B>) -> Self::Future {
+        let format = match self.format {
+            Some(format) => format,
+            None => return Box::pin(self.inner.call(request)),
+        };
+
+        let time = SystemTime::now();
+        let started = Instant::now();
+        let peer = peer(&request);
+        let path = request.uri().path().to_owned();
+        let request_id = request
+            .headers()
+            .get(REQUEST_ID_HEADER)
+            .and_then(|request_id| request_id.to_str().ok())
+            .map(str::to_owned);
+        let response = self.inner.call(request);
+
+        Box::pin(async move {
+            let response = response.await?;
+
+            // Errors are sent as trailers-only responses, with the status in the headers.
+            let entry = AccessLogEntry {
+                time,
+                peer,
+                path,
+                request_id,
+                status: Status::from_header_map(response.headers()).map_or(Code::Ok, |s| s.code()),
+                duration: started.elapsed(),
+                user_uuid: response
+                    .extensions()
+                    .get::<AuthenticatedUser>()
+                    .map(|user| user.user_uuid.clone()),
+            };
+            let _ = writeln!(std::io::stdout().lock(), "{}", entry.format(format));
+
+            Ok(response)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::UNIX_EPOCH;
+
+    use super::*;
+
+    fn entry() -> AccessLogEntry {
+        AccessLogEntry {
+            time: UNIX_EPOCH + Duration::from_secs(1_700_000_000),
+            peer: Some("127.0.0.1:50622".parse().unwrap()),
+            path: "/authentication.v1.Auth/GetProfile".to_owned(),
+            request_id: Some("checkout-42".to_owned()),
+            status: Code::Ok,
+            duration: Duration::from_micros(12_345),
+            user_uuid: Some("123456".to_owned()),
+        }
+    }
+
+    #[test]
+    fn should_format_common_log_line() {
+        assert_eq!(
+            entry().format(AccessLogFormat::Common),
+            "127.0.0.1 - 123456 [14/Nov/2023:22:13:20 +0000] \
+             \"POST /authentication.v1.Auth/GetProfile HTTP/2.0\" 0 - 0.012"
+        );
+
+        let entry = AccessLogEntry {
+            peer: None,
+            status: Code::Unauthenticated,
+            user_uuid: None,
+            ..entry()
+        };
+        assert!(entry
+            .format(AccessLogFormat::Common)
+            .starts_with("- - - [14/Nov/2023:22:13:20 +0000]"));
+        assert!(entry
+            .format(AccessLogFormat::Common)
+            .ends_with("HTTP/2.0\" 16 - 0.012"));
+    }
+
+    #[test]
+    fn should_format_json_line() {
+        assert_eq!(
+            entry().format(AccessLogFormat::Json),
+            "{\"time\":\"2023-11-14T22:13:20Z\",\"method\":\"/authentication.v1.Auth/GetProfile\",\
+             \"peer\":\"127.0.0.1:50622\",\"request_id\":\"checkout-42\",\"status\":0,\
+             \"duration_ms\":12.345,\"user_uuid\":\"123456\"}"
+        );
+
+        let entry = AccessLogEntry {
+            peer: None,
+            request_id: Some("a \"quoted\"\nid".to_owned()),
+            user_uuid: None,
+            ..entry()
+        };
+        let line = entry.format(AccessLogFormat::Json);
+        assert!(line.contains("\"request_id\":\"a \\\"quoted\\\"\\nid\""));
+        assert!(!line.contains("peer") && !line.contains("user_uuid"));
+    }
+}
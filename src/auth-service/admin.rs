@@ -0,0 +1,1662 @@
+use std::pin::Pin;
+use std::sync::Arc;
+use std::time::{Duration, UNIX_EPOCH};
+
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use prost::Message;
+use tokio_stream::wrappers::{errors::BroadcastStreamRecvError, BroadcastStream};
+use tokio_stream::{Stream, StreamExt};
+use tonic::{Code, Request, Response, Status};
+
+use crate::api_versions::{any, rpc};
+use crate::auth::authentication::admin::v1::admin_server::Admin;
+use crate::auth::authentication::admin::v1::*;
+use crate::auth::authentication::v1::{
+    BatchCreateUserResult, BatchCreateUsersRequest, BatchCreateUsersResponse,
+    PasswordViolationInfo, SearchUsersRequest, SearchUsersResponse, SessionEventType, SessionInfo,
+    StatusCode, UserInfo, UserStatus as UserStatusFilter,
+};
+use crate::auth::{unix_timestamp, AuthService};
+use crate::backups::{read_backup, write_backup};
+use crate::error::{SessionError, StorageError};
+use crate::maintenance::Maintenance;
+use crate::metrics::{record_failure, FailureReason};
+use crate::runtime_stats::RuntimeStats;
+use crate::sessions::{SessionEventKind, SessionId};
+use crate::users::{ImportedPassword, ImportedUser, User, UserCursor, UserFilter, UserStatus};
+use crate::validation::validate;
+
+pub use admin_server::AdminServer;
+
+/// Largest message the admin API sends or accepts, so backups of large deployments fit in one.
+pub const MAX_ADMIN_MESSAGE_BYTES: usize = 256 * 1024 * 1024;
+
+/// Maximum number of users accepted by a single `BatchCreateUsers` call.
+pub const MAX_BATCH_CREATE_USERS: usize = 100;
+
+/// Maximum number of users accepted by a single `ImportUsers` call.
+pub const MAX_IMPORT_USERS: usize = 1000;
+/// Users `ExportUsers` reads from the backend at a time.
+const EXPORT_USERS_PAGE_SIZE: usize = 500;
+
+/// Users returned by `ListUsers` when the request doesn't set a page size.
+pub const DEFAULT_LIST_USERS_PAGE_SIZE: usize = 20;
+/// Upper bound on the page size of `ListUsers`.
+pub const MAX_LIST_USERS_PAGE_SIZE: usize = 100;
+
+/// Serves the admin API, which manages users, sessions and lockouts directly. It's only served on
+/// the admin listener, so callers aren't authenticated here: whoever can reach it is an operator.
+pub struct AdminService {
+    auth: Arc<AuthService>,
+    runtime_stats: Arc<RuntimeStats>,
+}
+
+impl AdminService {
+    pub fn new(auth: Arc<AuthService>, runtime_stats: Arc<RuntimeStats>) -> Self {
+        Self {
+            auth,
+            runtime_stats,
+        }
+    }
+}
+
+fn user_info(user: &User) -> UserInfo {
+    UserInfo {
+        user_uuid: user.user_uuid.clone(),
+        username: user.username.clone(),
+        display_name: user.display_name.clone(),
+        email: user.email.clone(),
+        created_at: unix_timestamp(user.created_at),
+        deleted_at: user.deleted_at.map(unix_timestamp).unwrap_or_default(),
+        disabled: user.disabled,
+    }
+}
+
+fn maintenance_mode(maintenance: Maintenance) -> MaintenanceMode {
+    match maintenance {
+        Maintenance::Off => MaintenanceMode::Off,
+        Maintenance::SignUp => MaintenanceMode::SignUp,
+        Maintenance::SignIn => MaintenanceMode::SignIn,
+    }
+}
+
+/// Encodes where a `ListUsers` page ends, so the next page resumes after that user even if
+/// users are added or deleted in between.
+fn page_token(cursor: &UserCursor) -> String {
+    let created_at = cursor
+        .created_at
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_nanos())
+        .unwrap_or_default();
+
+    URL_SAFE_NO_PAD.encode(format!("{}:{}", created_at, cursor.user_uuid))
+}
+
+/// Reverses `page_token`, returning `None` for tokens it didn't make.
+fn parse_page_token(page_token: &str) -> Option<UserCursor> {
+    let decoded = String::from_utf8(URL_SAFE_NO_PAD.decode(page_token).ok()?).ok()?;
+    let (created_at, user_uuid) = decoded.split_once(':')?;
+    let created_at: u128 = created_at.parse().ok()?;
+
+    Some(UserCursor {
+        created_at: UNIX_EPOCH
+            + Duration::new(
+                u64::try_from(created_at / 1_000_000_000).ok()?,
+                (created_at % 1_000_000_000) as u32,
+            ),
+        user_uuid: user_uuid.to_owned(),
+    })
+}
+
+fn user_not_found() -> Status {
+    Status::not_found("Error, user uuid not found")
+}
+
+/// Fails with `INVALID_ARGUMENT` and a `google.rpc.BadRequest` listing the rules the password
+/// breaks, like `SignUp` of the v2 API.
+fn password_rejected(violations: Vec<PasswordViolationInfo>) -> Status {
+    record_failure(FailureReason::InvalidRequest);
+    let message = "The password breaks the password policy";
+    let field_violations = violations
+        .into_iter()
+        .map(|violation| rpc::bad_request::FieldViolation {
+            field: "password".to_owned(),
+            description: violation.message,
+        })
+        .collect();
+    let status = rpc::Status {
+        code: Code::InvalidArgument as i32,
+        message: message.to_owned(),
+        details: vec![any(
+            "google.rpc.BadRequest",
+            &rpc::BadRequest { field_violations },
+        )],
+    };
+    Status::with_details(
+        Code::InvalidArgument,
+        message,
+        status.encode_to_vec().into(),
+    )
+}
+
+/// Reports the outcome of creating or importing one user of a batch.
+fn batch_result(username: String, result: Result<(), StorageError>) -> BatchCreateUserResult {
+    match result {
+        Ok(_) => BatchCreateUserResult {
+            username,
+            status_code: StatusCode::Success.into(),
+            error: "".to_owned(),
+        },
+        Err(error) => BatchCreateUserResult {
+            username,
+            status_code: StatusCode::Failure.into(),
+            error: error.to_string(),
+        },
+    }
+}
+
+/// Like `password_rejected`, for one user of a batch.
+fn batch_password_rejected(
+    username: String,
+    violations: Vec<PasswordViolationInfo>,
+) -> BatchCreateUserResult {
+    record_failure(FailureReason::InvalidRequest);
+    let violations: Vec<String> = violations
+        .into_iter()
+        .map(|violation| violation.message)
+        .collect();
+    BatchCreateUserResult {
+        username,
+        status_code: StatusCode::WeakPassword.into(),
+        error: format!(
+            "The password breaks the password policy: {}",
+            violations.join("; ")
+        ),
+    }
+}
+
+#[tonic::async_trait]
+impl Admin for AdminService {
+    async fn create_user(
+        &self,
+        request: Request<CreateUserRequest>,
+    ) -> Result<Response<CreateUserResponse>, Status> {
+        validate(&request)?;
+        let req = request.into_inner();
+
+        self.auth
+            .check_password(&req.password)
+            .await
+            .map_err(password_rejected)?;
+
+        let hashed_password = self.auth.hash_password(&req.password).await?;
+        let mut users_service = self.auth.users().write().await;
+        users_service
+            .create_user_hashed(req.username.clone(), hashed_password, req.email)
+            .await?;
+        let user_uuid = users_service
+            .lookup_user_uuid(req.username)
+            .await
+            .ok_or_else(user_not_found)?;
+        if !req.display_name.is_empty() {
+            users_service
+                .update_user(user_uuid.clone(), Some(req.display_name), None)
+                .await?;
+        }
+        let user = users_service
+            .get_user(user_uuid)
+            .await
+            .ok_or_else(user_not_found)?;
+
+        tracing::info!(user_uuid = user.user_uuid, "Created user");
+        let reply = CreateUserResponse {
+            user: Some(user_info(&user)),
+        };
+        Ok(Response::new(reply))
+    }
+
+    async fn batch_create_users(
+        &self,
+        request: Request<BatchCreateUsersRequest>,
+    ) -> Result<Response<BatchCreateUsersResponse>, Status> {
+        validate(&request)?;
+        let req = request.into_inner();
+
+        if req.users.len() > MAX_BATCH_CREATE_USERS {
+            record_failure(FailureReason::InvalidRequest);
+            return Err(Status::invalid_argument(format!(
+                "Error, at most {MAX_BATCH_CREATE_USERS} users can be created at once"
+            )));
+        }
+
+        // Check each password like `create_user`, only creating the users whose passwords pass.
+        let mut checked = Vec::with_capacity(req.users.len());
+        for user in req.users {
+            let violations = self.auth.check_password(&user.password).await.err();
+            checked.push((user, violations));
+        }
+
+        let accepted = checked
+            .iter()
+            .filter(|(_, violations)| violations.is_none())
+            .map(|(user, _)| (user.username.clone(), user.password.clone()))
+            .collect();
+        let mut created = self
+            .auth
+            .users()
+            .write()
+            .await
+            .create_users(accepted)
+            .await
+            .into_iter();
+
+        let results: Vec<BatchCreateUserResult> = checked
+            .into_iter()
+            .map(|(user, violations)| match violations {
+                Some(violations) => batch_password_rejected(user.username, violations),
+                None => batch_result(
+                    user.username,
+                    created.next().expect("One result per accepted user"),
+                ),
+            })
+            .collect();
+
+        tracing::info!(
+            users = results.len(),
+            created = results
+                .iter()
+                .filter(|result| result.status_code() == StatusCode::Success)
+                .count(),
+            "Created a batch of users"
+        );
+        let reply = BatchCreateUsersResponse {
+            status_code: StatusCode::Success.into(),
+            results,
+        };
+        Ok(Response::new(reply))
+    }
+
+    async fn get_user(
+        &self,
+        request: Request<GetUserRequest>,
+    ) -> Result<Response<GetUserResponse>, Status> {
+        validate(&request)?;
+        let req = request.into_inner();
+
+        let user = self
+            .auth
+            .users()
+            .read()
+            .await
+            .get_user(req.user_uuid)
+            .await
+            .ok_or_else(user_not_found)?;
+        let groups = self.auth.groups().lock().await.user_groups(&user.user_uuid);
+
+        let reply = GetUserResponse {
+            user: Some(user_info(&user)),
+            attributes: user.attributes.clone().into_iter().collect(),
+            groups,
+        };
+        Ok(Response::new(reply))
+    }
+
+    async fn list_users(
+        &self,
+        request: Request<SearchUsersRequest>,
+    ) -> Result<Response<SearchUsersResponse>, Status> {
+        validate(&request)?;
+        let req = request.into_inner();
+
+        let after = match req.page_token.as_str() {
+            "" => None,
+            token => Some(parse_page_token(token).ok_or_else(|| {
+                record_failure(FailureReason::InvalidRequest);
+                Status::invalid_argument("Invalid page token")
+            })?),
+        };
+
+        let page_size = match req.page_size {
+            0 => DEFAULT_LIST_USERS_PAGE_SIZE,
+            page_size => (page_size as usize).min(MAX_LIST_USERS_PAGE_SIZE),
+        };
+
+        let unix_time = |secs: u64| match secs {
+            0 => None,
+            secs => Some(UNIX_EPOCH + Duration::from_secs(secs)),
+        };
+
+        let filter = UserFilter {
+            username_prefix: req.username_prefix.clone(),
+            email_domain: req.email_domain.clone(),
+            created_after: unix_time(req.created_after),
+            created_before: unix_time(req.created_before),
+            status: match req.status() {
+                UserStatusFilter::Any => None,
+                UserStatusFilter::Active => Some(UserStatus::Active),
+                UserStatusFilter::Deleted => Some(UserStatus::Deleted),
+            },
+        };
+
+        // Ask for one extra user to find out whether there is another page.
+        let mut users = self
+            .auth
+            .users()
+            .read()
+            .await
+            .search_users(&filter, after.as_ref(), page_size + 1)
+            .await;
+
+        let next_page_token = match users.len() > page_size {
+            true => {
+                users.truncate(page_size);
+                users
+                    .last()
+                    .map(|user| page_token(&UserCursor::from(user)))
+                    .unwrap_or_default()
+            }
+            false => "".to_owned(),
+        };
+
+        let reply = SearchUsersResponse {
+            status_code: StatusCode::Success.into(),
+            users: users.iter().map(user_info).collect(),
+            next_page_token,
+        };
+        Ok(Response::new(reply))
+    }
+
+    async fn update_user(
+        &self,
+        request: Request<UpdateUserRequest>,
+    ) -> Result<Response<UpdateUserResponse>, Status> {
+        validate(&request)?;
+        let req = request.into_inner();
+
+        let mut users_service = self.auth.users().write().await;
+        if req.display_name.is_some() || req.email.is_some() {
+            users_service
+                .update_user(req.user_uuid.clone(), req.display_name, req.email)
+                .await?;
+        }
+        if let Some(disabled) = req.disabled {
+            users_service
+                .set_user_disabled(req.user_uuid.clone(), disabled)
+                .await?;
+        }
+        let user = users_service
+            .get_user(req.user_uuid.clone())
+            .await
+            .ok_or_else(user_not_found)?;
+        drop(users_service);
+
+        // Sign the user out everywhere, refresh tokens included.
+        if req.disabled == Some(true) {
+            self.auth
+                .sessions()
+                .write()
+                .await
+                .delete_user_sessions(&req.user_uuid)
+                .await;
+        }
+
+        tracing::info!(user_uuid = req.user_uuid, "Updated user");
+        let reply = UpdateUserResponse {
+            user: Some(user_info(&user)),
+        };
+        Ok(Response::new(reply))
+    }
+
+    async fn delete_user(
+        &self,
+        request: Request<DeleteUserRequest>,
+    ) -> Result<Response<DeleteUserResponse>, Status> {
+        validate(&request)?;
+        let req = request.into_inner();
+
+        // Keep the account restorable until `purge_deleted_users` removes it for good.
+        self.auth
+            .users()
+            .write()
+            .await
+            .soft_delete_user(req.user_uuid.clone())
+            .await?;
+        self.auth
+            .sessions()
+            .write()
+            .await
+            .delete_user_sessions(&req.user_uuid)
+            .await;
+
+        tracing::info!(user_uuid = req.user_uuid, "Deleted user");
+        Ok(Response::new(DeleteUserResponse {}))
+    }
+
+    async fn restore_user(
+        &self,
+        request: Request<RestoreUserRequest>,
+    ) -> Result<Response<RestoreUserResponse>, Status> {
+        validate(&request)?;
+        let req = request.into_inner();
+
+        let mut users_service = self.auth.users().write().await;
+        let user = users_service
+            .get_user(req.user_uuid.clone())
+            .await
+            .ok_or_else(user_not_found)?;
+
+        // Accounts can only be restored until they're due to be purged.
+        let purgeable = user
+            .deleted_at
+            .and_then(|deleted_at| self.auth.clock().now().duration_since(deleted_at).ok())
+            .is_some_and(|elapsed| elapsed >= self.auth.deletion_grace_period());
+        if purgeable {
+            return Err(Status::failed_precondition(
+                "Error, the deletion grace period is over",
+            ));
+        }
+
+        users_service.restore_user(req.user_uuid.clone()).await?;
+        let user = users_service
+            .get_user(req.user_uuid.clone())
+            .await
+            .ok_or_else(user_not_found)?;
+
+        tracing::info!(user_uuid = req.user_uuid, "Restored user");
+        let reply = RestoreUserResponse {
+            user: Some(user_info(&user)),
+        };
+        Ok(Response::new(reply))
+    }
+
+    async fn export_users(
+        &self,
+        request: Request<ExportUsersRequest>,
+    ) -> Result<Response<ExportUsersResponse>, Status> {
+        validate(&request)?;
+
+        let filter = UserFilter {
+            status: Some(UserStatus::Active),
+            ..Default::default()
+        };
+
+        // Page through the users so SQL backends never load more than a page at once.
+        let users_service = self.auth.users().read().await;
+        let mut users = Vec::new();
+        let mut after: Option<UserCursor> = None;
+
+        loop {
+            let page = users_service
+                .search_users(&filter, after.as_ref(), EXPORT_USERS_PAGE_SIZE)
+                .await;
+
+            after = page.last().map(UserCursor::from);
+            let last_page = page.len() < EXPORT_USERS_PAGE_SIZE;
+
+            users.extend(page.into_iter().map(|user| UserRecord {
+                created_at: unix_timestamp(user.created_at),
+                password_hash: user.password_hash().to_owned(),
+                user_uuid: user.user_uuid,
+                username: user.username,
+                email: user.email,
+                display_name: user.display_name,
+                password: "".to_owned(),
+            }));
+
+            if last_page {
+                break;
+            }
+        }
+
+        tracing::info!(users = users.len(), "Exported users");
+        Ok(Response::new(ExportUsersResponse { users }))
+    }
+
+    async fn import_users(
+        &self,
+        request: Request<ImportUsersRequest>,
+    ) -> Result<Response<ImportUsersResponse>, Status> {
+        validate(&request)?;
+        let req = request.into_inner();
+
+        if req.users.len() > MAX_IMPORT_USERS {
+            record_failure(FailureReason::InvalidRequest);
+            return Err(Status::invalid_argument(format!(
+                "Error, at most {MAX_IMPORT_USERS} users can be imported at once"
+            )));
+        }
+
+        let non_empty = |value: String| Some(value).filter(|value| !value.is_empty());
+
+        // Check plaintext passwords like `create_user`, only importing the users whose passwords
+        // pass. Hashes can't be checked.
+        let mut checked = Vec::with_capacity(req.users.len());
+        let mut users = Vec::with_capacity(req.users.len());
+        for user in req.users {
+            let violations = match user.password_hash.is_empty() {
+                true => self.auth.check_password(&user.password).await.err(),
+                false => None,
+            };
+            let rejected = violations.is_some();
+            checked.push((user.username.clone(), violations));
+            if rejected {
+                continue;
+            }
+
+            users.push(ImportedUser {
+                user_uuid: non_empty(user.user_uuid),
+                username: user.username,
+                email: user.email,
+                display_name: non_empty(user.display_name),
+                created_at: match user.created_at {
+                    0 => None,
+                    secs => Some(UNIX_EPOCH + Duration::from_secs(secs)),
+                },
+                password: match user.password_hash.is_empty() {
+                    true => ImportedPassword::Plaintext(user.password.into()),
+                    false => ImportedPassword::Hashed(user.password_hash.into()),
+                },
+            });
+        }
+
+        let mut imported = self
+            .auth
+            .users()
+            .write()
+            .await
+            .import_users(users)
+            .await
+            .into_iter();
+
+        let results: Vec<BatchCreateUserResult> = checked
+            .into_iter()
+            .map(|(username, violations)| match violations {
+                Some(violations) => batch_password_rejected(username, violations),
+                None => batch_result(
+                    username,
+                    imported.next().expect("One result per accepted user"),
+                ),
+            })
+            .collect();
+
+        tracing::info!(
+            users = results.len(),
+            imported = results
+                .iter()
+                .filter(|result| result.status_code() == StatusCode::Success)
+                .count(),
+            "Imported users"
+        );
+        Ok(Response::new(ImportUsersResponse { results }))
+    }
+
+    async fn list_user_sessions(
+        &self,
+        request: Request<ListUserSessionsRequest>,
+    ) -> Result<Response<ListUserSessionsResponse>, Status> {
+        validate(&request)?;
+        let req = request.into_inner();
+
+        let sessions = self
+            .auth
+            .sessions()
+            .read()
+            .await
+            .list_user_sessions(&req.user_uuid)
+            .await?
+            .into_iter()
+            .map(|session| SessionInfo {
+                session_id: session.session_id.to_string(),
+                created_at: unix_timestamp(session.created_at),
+                last_seen: unix_timestamp(session.last_seen),
+                expires_at: unix_timestamp(session.expires_at),
+                ip_address: session.client.ip_address,
+                user_agent: session.client.user_agent,
+                current: false,
+            })
+            .collect();
+
+        let reply = ListUserSessionsResponse { sessions };
+        Ok(Response::new(reply))
+    }
+
+    async fn revoke_session(
+        &self,
+        request: Request<RevokeSessionRequest>,
+    ) -> Result<Response<RevokeSessionResponse>, Status> {
+        validate(&request)?;
+        let req = request.into_inner();
+
+        let session_id: SessionId = req.session_id.parse().map_err(|_| {
+            record_failure(FailureReason::InvalidRequest);
+            Status::invalid_argument("Error, session id is not a UUID")
+        })?;
+
+        let mut sessions_service = self.auth.sessions().write().await;
+        // Sessions that can't be listed, e.g. JWTs, are revoked without checking they exist.
+        let found = match sessions_service.list_user_sessions(&req.user_uuid).await {
+            Ok(sessions) => sessions
+                .iter()
+                .any(|session| session.session_id == session_id),
+            Err(SessionError::ListingUnsupported) => true,
+            Err(e) => return Err(e.into()),
+        };
+        if !found {
+            return Err(Status::not_found("Session not found"));
+        }
+        sessions_service
+            .delete_user_session(&req.user_uuid, session_id)
+            .await;
+
+        tracing::info!(user_uuid = req.user_uuid, %session_id, "Revoked session");
+        Ok(Response::new(RevokeSessionResponse {}))
+    }
+
+    async fn revoke_user_sessions(
+        &self,
+        request: Request<RevokeUserSessionsRequest>,
+    ) -> Result<Response<RevokeUserSessionsResponse>, Status> {
+        validate(&request)?;
+        let req = request.into_inner();
+
+        let mut sessions_service = self.auth.sessions().write().await;
+        // Sessions that can't be listed, e.g. JWTs, are revoked without being counted.
+        let revoked = match sessions_service.list_user_sessions(&req.user_uuid).await {
+            Ok(sessions) => sessions.len(),
+            Err(SessionError::ListingUnsupported) => 0,
+            Err(e) => return Err(e.into()),
+        };
+        sessions_service.delete_user_sessions(&req.user_uuid).await;
+
+        tracing::info!(
+            user_uuid = req.user_uuid,
+            revoked,
+            "Revoked user's sessions"
+        );
+        let reply = RevokeUserSessionsResponse {
+            revoked: revoked as u32,
+        };
+        Ok(Response::new(reply))
+    }
+
+    type WatchSessionEventsStream =
+        Pin<Box<dyn Stream<Item = Result<SessionEventInfo, Status>> + Send>>;
+
+    // Stream items have to be `Result<_, Status>`, however large `Status` is.
+    #[allow(clippy::result_large_err)]
+    async fn watch_session_events(
+        &self,
+        request: Request<WatchSessionEventsRequest>,
+    ) -> Result<Response<Self::WatchSessionEventsStream>, Status> {
+        validate(&request)?;
+
+        let Some(receiver) = self.auth.subscribe_session_events() else {
+            return Err(Status::unimplemented(
+                "Session events aren't published by this sessions backend",
+            ));
+        };
+
+        let stream = BroadcastStream::new(receiver).map(|event| match event {
+            Ok(event) => Ok(SessionEventInfo {
+                event_type: match event.kind {
+                    SessionEventKind::Created => SessionEventType::Created,
+                    SessionEventKind::Deleted => SessionEventType::Deleted,
+                    SessionEventKind::Expired => SessionEventType::Expired,
+                }
+                .into(),
+                session_id: event.session_id.to_string(),
+                user_uuid: event.user_uuid.into(),
+                token_hash: event.token_hash,
+                expires_at: unix_timestamp(event.expires_at),
+                timestamp: unix_timestamp(event.timestamp),
+            }),
+            // The watcher's cache can't be trusted after missing events, so end the stream.
+            Err(BroadcastStreamRecvError::Lagged(missed)) => Err(Status::data_loss(format!(
+                "Missed {} session events, clear the cache and watch again",
+                missed
+            ))),
+        });
+
+        Ok(Response::new(Box::pin(stream)))
+    }
+
+    async fn create_group(
+        &self,
+        request: Request<CreateGroupRequest>,
+    ) -> Result<Response<CreateGroupResponse>, Status> {
+        validate(&request)?;
+        let req = request.into_inner();
+
+        // The name was validated, so the group can only fail to be created for being taken.
+        self.auth
+            .groups()
+            .lock()
+            .await
+            .create_group(&req.name)
+            .map_err(Status::already_exists)?;
+
+        tracing::info!(group = req.name, "Created group");
+        Ok(Response::new(CreateGroupResponse {}))
+    }
+
+    async fn delete_group(
+        &self,
+        request: Request<DeleteGroupRequest>,
+    ) -> Result<Response<DeleteGroupResponse>, Status> {
+        validate(&request)?;
+        let req = request.into_inner();
+
+        self.auth
+            .groups()
+            .lock()
+            .await
+            .delete_group(&req.name)
+            .map_err(Status::not_found)?;
+
+        tracing::info!(group = req.name, "Deleted group");
+        Ok(Response::new(DeleteGroupResponse {}))
+    }
+
+    async fn add_group_member(
+        &self,
+        request: Request<AddGroupMemberRequest>,
+    ) -> Result<Response<AddGroupMemberResponse>, Status> {
+        validate(&request)?;
+        let req = request.into_inner();
+
+        // Only accounts that exist and aren't deleted can join groups.
+        self.auth
+            .users()
+            .read()
+            .await
+            .get_user(req.user_uuid.clone())
+            .await
+            .filter(|user| user.deleted_at.is_none())
+            .ok_or_else(user_not_found)?;
+
+        self.auth
+            .groups()
+            .lock()
+            .await
+            .add_member(&req.group_name, &req.user_uuid)
+            .map_err(Status::not_found)?;
+
+        tracing::info!(
+            group = req.group_name,
+            user_uuid = req.user_uuid,
+            "Added group member"
+        );
+        Ok(Response::new(AddGroupMemberResponse {}))
+    }
+
+    async fn remove_group_member(
+        &self,
+        request: Request<RemoveGroupMemberRequest>,
+    ) -> Result<Response<RemoveGroupMemberResponse>, Status> {
+        validate(&request)?;
+        let req = request.into_inner();
+
+        self.auth
+            .groups()
+            .lock()
+            .await
+            .remove_member(&req.group_name, &req.user_uuid)
+            .map_err(Status::not_found)?;
+
+        tracing::info!(
+            group = req.group_name,
+            user_uuid = req.user_uuid,
+            "Removed group member"
+        );
+        Ok(Response::new(RemoveGroupMemberResponse {}))
+    }
+
+    async fn get_lockout(
+        &self,
+        request: Request<GetLockoutRequest>,
+    ) -> Result<Response<GetLockoutResponse>, Status> {
+        validate(&request)?;
+        let req = request.into_inner();
+
+        let locked_for = self.auth.lockouts().lock().await.locked_for(&req.username);
+
+        // Rounded up, so a lockout with under a second left isn't reported as lifted.
+        let reply = GetLockoutResponse {
+            locked_for: locked_for.map_or(0, |locked_for| {
+                locked_for.as_secs() + u64::from(locked_for.subsec_nanos() > 0)
+            }),
+        };
+        Ok(Response::new(reply))
+    }
+
+    async fn clear_lockout(
+        &self,
+        request: Request<ClearLockoutRequest>,
+    ) -> Result<Response<ClearLockoutResponse>, Status> {
+        validate(&request)?;
+        let req = request.into_inner();
+
+        self.auth.lockouts().lock().await.unlock(&req.username);
+
+        tracing::info!(username = req.username, "Cleared lockout");
+        Ok(Response::new(ClearLockoutResponse {}))
+    }
+
+    async fn get_stats(
+        &self,
+        _request: Request<GetStatsRequest>,
+    ) -> Result<Response<GetStatsResponse>, Status> {
+        match self.runtime_stats.report(&self.auth).await {
+            Ok(stats) => Ok(Response::new(stats)),
+            Err(e) => {
+                tracing::error!(error = %e, "Failed to count users and sessions");
+                Err(e.into())
+            }
+        }
+    }
+
+    async fn backup(
+        &self,
+        request: Request<BackupRequest>,
+    ) -> Result<Response<BackupResponse>, Status> {
+        validate(&request)?;
+
+        let backup = write_backup(&self.auth).await?;
+
+        tracing::info!(
+            users = backup.users,
+            sessions = backup.sessions,
+            "Backed up users and sessions"
+        );
+        let reply = BackupResponse {
+            backup: backup.contents.into_bytes(),
+            users: backup.users as u32,
+            sessions: backup.sessions as u32,
+        };
+        Ok(Response::new(reply))
+    }
+
+    async fn restore(
+        &self,
+        request: Request<RestoreRequest>,
+    ) -> Result<Response<RestoreResponse>, Status> {
+        validate(&request)?;
+        let req = request.into_inner();
+
+        let backup = String::from_utf8(req.backup)
+            .map_err(|_| "Error, not a backup".to_owned())
+            .and_then(|backup| read_backup(&backup))
+            .map_err(|e| {
+                record_failure(FailureReason::InvalidRequest);
+                Status::invalid_argument(e)
+            })?;
+        let restored = backup.restore(&self.auth).await?;
+
+        tracing::info!(
+            users = restored.users,
+            skipped_users = restored.skipped_users,
+            sessions = restored.sessions,
+            "Restored users and sessions"
+        );
+        let reply = RestoreResponse {
+            users: restored.users as u32,
+            skipped_users: restored.skipped_users as u32,
+            sessions: restored.sessions as u32,
+        };
+        Ok(Response::new(reply))
+    }
+
+    async fn get_maintenance_mode(
+        &self,
+        request: Request<GetMaintenanceModeRequest>,
+    ) -> Result<Response<GetMaintenanceModeResponse>, Status> {
+        validate(&request)?;
+
+        let reply = GetMaintenanceModeResponse {
+            mode: maintenance_mode(self.auth.maintenance()).into(),
+        };
+        Ok(Response::new(reply))
+    }
+
+    async fn set_maintenance_mode(
+        &self,
+        request: Request<SetMaintenanceModeRequest>,
+    ) -> Result<Response<SetMaintenanceModeResponse>, Status> {
+        validate(&request)?;
+        let req = request.into_inner();
+
+        let maintenance = match req.mode() {
+            MaintenanceMode::Off => Maintenance::Off,
+            MaintenanceMode::SignUp => Maintenance::SignUp,
+            MaintenanceMode::SignIn => Maintenance::SignIn,
+        };
+        let previous = self.auth.set_maintenance(maintenance);
+
+        tracing::info!(
+            mode = maintenance.as_str(),
+            previous_mode = previous.as_str(),
+            "Set maintenance mode"
+        );
+        let reply = SetMaintenanceModeResponse {
+            previous_mode: maintenance_mode(previous).into(),
+        };
+        Ok(Response::new(reply))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, SystemTime};
+
+    use tokio::sync::{broadcast, Mutex, RwLock};
+
+    use super::*;
+    use crate::auth::authentication::v1::auth_server::Auth;
+    use crate::auth::authentication::v1::{
+        IntrospectTokenRequest, NewUser, RefreshSessionRequest, SignInRequest, SignOutRequest,
+        ValidateSessionRequest,
+    };
+    use crate::clock::ManualClock;
+    use crate::lockouts::LockoutsImpl;
+    use crate::metrics::RpcMetrics;
+    use crate::password_policy::PasswordPolicy;
+    use crate::sessions::{token_hash, ClientMetadata, SessionsImpl, SESSION_EVENTS_CAPACITY};
+    use crate::users::UsersImpl;
+
+    fn admin_service(auth_service: AuthService) -> AdminService {
+        AdminService::new(
+            Arc::new(auth_service),
+            Arc::new(RuntimeStats::new(Arc::new(RpcMetrics::default()))),
+        )
+    }
+
+    fn auth_service() -> AuthService {
+        AuthService::new(
+            Box::new(RwLock::new(UsersImpl::default())),
+            Box::new(RwLock::new(SessionsImpl::default())),
+        )
+    }
+
+    async fn create_user(admin_service: &AdminService, username: &str) -> UserInfo {
+        let request = Request::new(CreateUserRequest {
+            username: username.to_owned(),
+            password: "password".to_owned(),
+            display_name: "Alice".to_owned(),
+            ..Default::default()
+        });
+        let response = admin_service.create_user(request).await.unwrap();
+        response.into_inner().user.unwrap()
+    }
+
+    #[tokio::test]
+    async fn should_manage_users() {
+        let admin_service = admin_service(auth_service());
+        let user = create_user(&admin_service, "alice").await;
+        assert_eq!(user.username, "alice");
+        assert_eq!(user.display_name, "Alice");
+
+        let request = Request::new(UpdateUserRequest {
+            user_uuid: user.user_uuid.clone(),
+            email: Some("alice@example.com".to_owned()),
+            disabled: Some(true),
+            ..Default::default()
+        });
+        let updated = admin_service.update_user(request).await.unwrap();
+        let updated = updated.into_inner().user.unwrap();
+        assert_eq!(updated.email, "alice@example.com");
+        assert!(updated.disabled);
+
+        let request = Request::new(DeleteUserRequest {
+            user_uuid: user.user_uuid.clone(),
+        });
+        admin_service.delete_user(request).await.unwrap();
+
+        // Still found until purged
+        let request = Request::new(GetUserRequest {
+            user_uuid: user.user_uuid.clone(),
+        });
+        let deleted = admin_service.get_user(request).await.unwrap();
+        assert_ne!(deleted.into_inner().user.unwrap().deleted_at, 0);
+
+        let request = Request::new(GetUserRequest {
+            user_uuid: "unknown".to_owned(),
+        });
+        let status = admin_service.get_user(request).await.unwrap_err();
+        assert_eq!(status.code(), Code::NotFound);
+    }
+
+    #[tokio::test]
+    async fn disabled_users_should_be_signed_out_and_blocked() {
+        let admin_service = admin_service(auth_service());
+        create_user(&admin_service, "alice").await;
+
+        let sign_in = |password: &str| {
+            Request::new(SignInRequest {
+                username: "alice".to_owned(),
+                password: password.to_owned(),
+                ..Default::default()
+            })
+        };
+        let signed_in = admin_service.auth.sign_in(sign_in("password")).await;
+        let signed_in = signed_in.unwrap().into_inner();
+
+        let disable = |disabled: bool| {
+            Request::new(UpdateUserRequest {
+                user_uuid: signed_in.user_uuid.clone(),
+                disabled: Some(disabled),
+                ..Default::default()
+            })
+        };
+        admin_service.update_user(disable(true)).await.unwrap();
+
+        let request = Request::new(ValidateSessionRequest {
+            session_token: signed_in.session_token.clone(),
+        });
+        let validated = admin_service.auth.validate_session(request).await.unwrap();
+        assert_eq!(validated.into_inner().status_code(), StatusCode::Failure);
+
+        let request = Request::new(RefreshSessionRequest {
+            refresh_token: signed_in.refresh_token.clone(),
+        });
+        let refreshed = admin_service.auth.refresh_session(request).await.unwrap();
+        assert_eq!(refreshed.into_inner().status_code(), StatusCode::Failure);
+
+        let response = admin_service.auth.sign_in(sign_in("password")).await;
+        let response = response.unwrap().into_inner();
+        assert_eq!(response.status_code(), StatusCode::AccountDisabled);
+        assert!(response.session_token.is_empty());
+
+        // A wrong password doesn't reveal that the account is disabled.
+        let response = admin_service.auth.sign_in(sign_in("wrong password")).await;
+        let response = response.unwrap().into_inner();
+        assert_eq!(response.status_code(), StatusCode::Failure);
+
+        admin_service.update_user(disable(false)).await.unwrap();
+        let response = admin_service.auth.sign_in(sign_in("password")).await;
+        let response = response.unwrap().into_inner();
+        assert_eq!(response.status_code(), StatusCode::Success);
+
+        let request = Request::new(UpdateUserRequest {
+            user_uuid: "unknown".to_owned(),
+            disabled: Some(true),
+            ..Default::default()
+        });
+        let status = admin_service.update_user(request).await.unwrap_err();
+        assert_eq!(status.code(), Code::NotFound);
+    }
+
+    #[tokio::test]
+    async fn should_page_through_users() {
+        let admin_service = admin_service(auth_service());
+        for username in ["user1", "user2", "user3", "other"] {
+            create_user(&admin_service, username).await;
+        }
+
+        let list = |page_token: String| {
+            Request::new(SearchUsersRequest {
+                username_prefix: "user".to_owned(),
+                page_size: 2,
+                page_token,
+                ..Default::default()
+            })
+        };
+
+        let first = admin_service.list_users(list(String::new())).await.unwrap();
+        let first = first.into_inner();
+        assert_eq!(first.users.len(), 2);
+        assert_eq!(first.users[0].username, "user1");
+        assert!(!first.next_page_token.is_empty());
+
+        let second = admin_service.list_users(list(first.next_page_token)).await;
+        let second = second.unwrap().into_inner();
+        assert_eq!(second.users.len(), 1);
+        assert_eq!(second.users[0].username, "user3");
+        assert!(second.next_page_token.is_empty());
+
+        let status = admin_service
+            .list_users(list("not a token".to_owned()))
+            .await
+            .unwrap_err();
+        assert_eq!(status.code(), Code::InvalidArgument);
+    }
+
+    #[tokio::test]
+    async fn should_list_users_by_status() {
+        let admin_service = admin_service(auth_service());
+        let user = create_user(&admin_service, "alice").await;
+        create_user(&admin_service, "bob").await;
+        let request = Request::new(DeleteUserRequest {
+            user_uuid: user.user_uuid.clone(),
+        });
+        admin_service.delete_user(request).await.unwrap();
+
+        let list = |status: UserStatusFilter| {
+            Request::new(SearchUsersRequest {
+                status: status.into(),
+                ..Default::default()
+            })
+        };
+
+        let deleted = admin_service
+            .list_users(list(UserStatusFilter::Deleted))
+            .await;
+        let deleted = deleted.unwrap().into_inner().users;
+        assert_eq!(deleted.len(), 1);
+        assert_eq!(deleted[0].user_uuid, user.user_uuid);
+        assert_ne!(deleted[0].deleted_at, 0);
+
+        let active = admin_service
+            .list_users(list(UserStatusFilter::Active))
+            .await;
+        let active = active.unwrap().into_inner().users;
+        assert_eq!(active.len(), 1);
+        assert_eq!(active[0].username, "bob");
+    }
+
+    #[test]
+    fn page_token_should_round_trip() {
+        let cursor = UserCursor {
+            created_at: UNIX_EPOCH + Duration::new(1_700_000_000, 123_456_789),
+            user_uuid: "user:uuid".to_owned(),
+        };
+
+        assert_eq!(parse_page_token(&page_token(&cursor)), Some(cursor));
+        assert!(parse_page_token("bm90IGEgY3Vyc29y").is_none());
+    }
+
+    #[tokio::test]
+    async fn should_restore_users_within_grace_period() {
+        let admin_service = admin_service(auth_service());
+        let user = create_user(&admin_service, "alice").await;
+
+        let request = Request::new(RestoreUserRequest {
+            user_uuid: user.user_uuid.clone(),
+        });
+        let status = admin_service.restore_user(request).await.unwrap_err();
+        assert_eq!(status.code(), Code::FailedPrecondition);
+
+        let request = Request::new(DeleteUserRequest {
+            user_uuid: user.user_uuid.clone(),
+        });
+        admin_service.delete_user(request).await.unwrap();
+
+        let request = Request::new(RestoreUserRequest {
+            user_uuid: user.user_uuid.clone(),
+        });
+        let restored = admin_service.restore_user(request).await.unwrap();
+        assert_eq!(restored.into_inner().user.unwrap().deleted_at, 0);
+
+        let request = Request::new(RestoreUserRequest {
+            user_uuid: "unknown".to_owned(),
+        });
+        let status = admin_service.restore_user(request).await.unwrap_err();
+        assert_eq!(status.code(), Code::NotFound);
+    }
+
+    #[tokio::test]
+    async fn should_not_restore_users_after_grace_period() {
+        let admin_service =
+            admin_service(auth_service().with_deletion_grace_period(Duration::ZERO));
+        let user = create_user(&admin_service, "alice").await;
+        let request = Request::new(DeleteUserRequest {
+            user_uuid: user.user_uuid.clone(),
+        });
+        admin_service.delete_user(request).await.unwrap();
+
+        let request = Request::new(RestoreUserRequest {
+            user_uuid: user.user_uuid,
+        });
+        let status = admin_service.restore_user(request).await.unwrap_err();
+        assert_eq!(status.code(), Code::FailedPrecondition);
+    }
+
+    #[tokio::test]
+    async fn should_tell_grace_period_with_clock() {
+        let clock = ManualClock::new(SystemTime::now());
+        let admin_service = admin_service(
+            auth_service()
+                .with_deletion_grace_period(Duration::from_secs(60))
+                .with_clock(Box::new(clock.clone())),
+        );
+        let user = create_user(&admin_service, "alice").await;
+        let request = Request::new(DeleteUserRequest {
+            user_uuid: user.user_uuid.clone(),
+        });
+        admin_service.delete_user(request).await.unwrap();
+
+        clock.advance(Duration::from_secs(61));
+
+        let request = Request::new(RestoreUserRequest {
+            user_uuid: user.user_uuid,
+        });
+        let status = admin_service.restore_user(request).await.unwrap_err();
+        assert_eq!(status.code(), Code::FailedPrecondition);
+        assert_eq!(admin_service.auth.purge_deleted_users().await, 1);
+    }
+
+    #[tokio::test]
+    async fn should_reject_taken_usernames_and_weak_passwords() {
+        let mut password_policy = PasswordPolicy::default();
+        password_policy.min_length = 12;
+        let admin_service = admin_service(auth_service().with_password_policy(password_policy));
+
+        let request = Request::new(CreateUserRequest {
+            username: "alice".to_owned(),
+            password: "password".to_owned(),
+            ..Default::default()
+        });
+        let status = admin_service.create_user(request).await.unwrap_err();
+        assert_eq!(status.code(), Code::InvalidArgument);
+
+        let request = || {
+            Request::new(CreateUserRequest {
+                username: "alice".to_owned(),
+                password: "correct horse battery".to_owned(),
+                ..Default::default()
+            })
+        };
+        admin_service.create_user(request()).await.unwrap();
+        let status = admin_service.create_user(request()).await.unwrap_err();
+        assert_eq!(status.code(), Code::AlreadyExists);
+    }
+
+    #[tokio::test]
+    async fn should_revoke_sessions() {
+        let admin_service = admin_service(auth_service());
+        let user = create_user(&admin_service, "alice").await;
+        let mut session_tokens = Vec::new();
+        for _ in 0..3 {
+            let session_token = admin_service
+                .auth
+                .sessions()
+                .write()
+                .await
+                .create_session(&user.user_uuid, ClientMetadata::default())
+                .await
+                .unwrap();
+            session_tokens.push(session_token);
+        }
+
+        let request = Request::new(ListUserSessionsRequest {
+            user_uuid: user.user_uuid.clone(),
+        });
+        let sessions = admin_service.list_user_sessions(request).await.unwrap();
+        let sessions = sessions.into_inner().sessions;
+        assert_eq!(sessions.len(), 3);
+
+        let revoked = admin_service
+            .auth
+            .sessions()
+            .read()
+            .await
+            .peek_session(&session_tokens[0])
+            .await
+            .unwrap();
+        let request = Request::new(RevokeSessionRequest {
+            user_uuid: user.user_uuid.clone(),
+            session_id: revoked.session_id.to_string(),
+        });
+        admin_service.revoke_session(request).await.unwrap();
+        assert!(admin_service
+            .auth
+            .sessions()
+            .read()
+            .await
+            .peek_session(&session_tokens[0])
+            .await
+            .is_none());
+
+        // Only the user's own sessions can be revoked
+        let request = Request::new(RevokeSessionRequest {
+            user_uuid: "someone else".to_owned(),
+            session_id: sessions[1].session_id.clone(),
+        });
+        let status = admin_service.revoke_session(request).await.unwrap_err();
+        assert_eq!(status.code(), Code::NotFound);
+
+        let request = Request::new(RevokeSessionRequest {
+            user_uuid: user.user_uuid.clone(),
+            session_id: "not a uuid".to_owned(),
+        });
+        let status = admin_service.revoke_session(request).await.unwrap_err();
+        assert_eq!(status.code(), Code::InvalidArgument);
+
+        let request = Request::new(RevokeUserSessionsRequest {
+            user_uuid: user.user_uuid.clone(),
+        });
+        let response = admin_service.revoke_user_sessions(request).await.unwrap();
+        assert_eq!(response.into_inner().revoked, 2);
+    }
+
+    #[tokio::test]
+    async fn should_restore_backup() {
+        let backed_up = admin_service(auth_service());
+        let user = create_user(&backed_up, "alice").await;
+        let response = backed_up
+            .backup(Request::new(BackupRequest {}))
+            .await
+            .unwrap();
+        let backup = response.into_inner();
+        assert_eq!(backup.users, 1);
+
+        let restored = admin_service(auth_service());
+        let request = Request::new(RestoreRequest {
+            backup: backup.backup,
+        });
+        let response = restored.restore(request).await.unwrap();
+        assert_eq!(response.into_inner().users, 1);
+        let request = Request::new(GetUserRequest {
+            user_uuid: user.user_uuid,
+        });
+        restored.get_user(request).await.unwrap();
+
+        let request = Request::new(RestoreRequest {
+            backup: b"not a backup".to_vec(),
+        });
+        let status = restored.restore(request).await.unwrap_err();
+        assert_eq!(status.code(), Code::InvalidArgument);
+    }
+
+    #[tokio::test]
+    async fn should_create_batches_of_users() {
+        let admin_service = admin_service(auth_service());
+        create_user(&admin_service, "alice").await;
+
+        let new_user = |username: &str| NewUser {
+            username: username.to_owned(),
+            password: "password".to_owned(),
+        };
+        let request = Request::new(BatchCreateUsersRequest {
+            users: vec![new_user("alice"), new_user("bob")],
+        });
+        let response = admin_service.batch_create_users(request).await.unwrap();
+        let results = response.into_inner().results;
+        assert_eq!(results.len(), 2);
+        assert_eq!(results[0].username, "alice");
+        assert_eq!(results[0].status_code(), StatusCode::Failure);
+        assert!(!results[0].error.is_empty());
+        assert_eq!(results[1].username, "bob");
+        assert_eq!(results[1].status_code(), StatusCode::Success);
+
+        let users = (0..=MAX_BATCH_CREATE_USERS)
+            .map(|i| new_user(&format!("user{i}")))
+            .collect();
+        let request = Request::new(BatchCreateUsersRequest { users });
+        let status = admin_service.batch_create_users(request).await.unwrap_err();
+        assert_eq!(status.code(), Code::InvalidArgument);
+    }
+
+    #[tokio::test]
+    async fn should_reject_weak_passwords_in_batches_and_imports() {
+        let mut password_policy = PasswordPolicy::default();
+        password_policy.min_length = 12;
+        let admin_service = admin_service(auth_service().with_password_policy(password_policy));
+
+        let request = Request::new(BatchCreateUsersRequest {
+            users: vec![
+                NewUser {
+                    username: "alice".to_owned(),
+                    password: "password".to_owned(),
+                },
+                NewUser {
+                    username: "bob".to_owned(),
+                    password: "correct horse battery".to_owned(),
+                },
+            ],
+        });
+        let response = admin_service.batch_create_users(request).await.unwrap();
+        let results = response.into_inner().results;
+        assert_eq!(results[0].status_code(), StatusCode::WeakPassword);
+        assert!(!results[0].error.is_empty());
+        assert_eq!(results[1].username, "bob");
+        assert_eq!(results[1].status_code(), StatusCode::Success);
+
+        let request = Request::new(ImportUsersRequest {
+            users: vec![
+                UserRecord {
+                    username: "carol".to_owned(),
+                    password: "password".to_owned(),
+                    ..Default::default()
+                },
+                UserRecord {
+                    username: "dave".to_owned(),
+                    password: "correct horse battery".to_owned(),
+                    ..Default::default()
+                },
+            ],
+        });
+        let response = admin_service.import_users(request).await.unwrap();
+        let results = response.into_inner().results;
+        assert_eq!(results[0].status_code(), StatusCode::WeakPassword);
+        assert_eq!(results[1].username, "dave");
+        assert_eq!(results[1].status_code(), StatusCode::Success);
+
+        let users_service = admin_service.auth.users().read().await;
+        for (username, created) in [("alice", false), ("bob", true), ("carol", false)] {
+            let user_uuid = users_service.lookup_user_uuid(username.to_owned()).await;
+            assert_eq!(user_uuid.is_some(), created);
+        }
+    }
+
+    #[tokio::test]
+    async fn exported_users_should_sign_in_after_import() {
+        let source = admin_service(auth_service());
+        let user = create_user(&source, "exported").await;
+        let request = Request::new(DeleteUserRequest {
+            user_uuid: create_user(&source, "deleted").await.user_uuid,
+        });
+        source.delete_user(request).await.unwrap();
+
+        let request = Request::new(ExportUsersRequest {});
+        let exported = source.export_users(request).await.unwrap().into_inner();
+        assert_eq!(exported.users.len(), 1);
+        assert_eq!(exported.users[0].username, "exported");
+
+        let destination = admin_service(auth_service());
+        let request = Request::new(ImportUsersRequest {
+            users: exported.users,
+        });
+        let imported = destination.import_users(request).await.unwrap();
+        let results = imported.into_inner().results;
+        assert_eq!(results[0].status_code(), StatusCode::Success);
+
+        let request = Request::new(SignInRequest {
+            username: "exported".to_owned(),
+            password: "password".to_owned(),
+            ..Default::default()
+        });
+        let signed_in = destination.auth.sign_in(request).await.unwrap();
+        let signed_in = signed_in.into_inner();
+        assert_eq!(signed_in.status_code(), StatusCode::Success);
+        assert_eq!(signed_in.user_uuid, user.user_uuid);
+
+        let request = Request::new(ImportUsersRequest {
+            users: vec![UserRecord::default(); MAX_IMPORT_USERS + 1],
+        });
+        let status = destination.import_users(request).await.unwrap_err();
+        assert_eq!(status.code(), Code::InvalidArgument);
+    }
+
+    #[tokio::test]
+    async fn should_manage_groups() {
+        let admin_service = admin_service(auth_service());
+        let user = create_user(&admin_service, "alice").await;
+        let session_token = admin_service
+            .auth
+            .sessions()
+            .write()
+            .await
+            .create_session(&user.user_uuid, ClientMetadata::default())
+            .await
+            .unwrap();
+
+        let add_member = |group_name: &str, user_uuid: &str| {
+            Request::new(AddGroupMemberRequest {
+                group_name: group_name.to_owned(),
+                user_uuid: user_uuid.to_owned(),
+            })
+        };
+        for name in ["engineering", "admins"] {
+            let request = Request::new(CreateGroupRequest {
+                name: name.to_owned(),
+            });
+            admin_service.create_group(request).await.unwrap();
+            let request = add_member(name, &user.user_uuid);
+            admin_service.add_group_member(request).await.unwrap();
+        }
+
+        let introspect = || {
+            Request::new(IntrospectTokenRequest {
+                token: session_token.clone(),
+            })
+        };
+        let response = admin_service.auth.introspect_token(introspect()).await;
+        assert_eq!(
+            response.unwrap().into_inner().groups,
+            vec!["admins", "engineering"]
+        );
+
+        let request = Request::new(RemoveGroupMemberRequest {
+            group_name: "admins".to_owned(),
+            user_uuid: user.user_uuid.clone(),
+        });
+        admin_service.remove_group_member(request).await.unwrap();
+        let request = Request::new(DeleteGroupRequest {
+            name: "engineering".to_owned(),
+        });
+        admin_service.delete_group(request).await.unwrap();
+
+        let response = admin_service.auth.introspect_token(introspect()).await;
+        let response = response.unwrap().into_inner();
+        assert!(response.active);
+        assert!(response.groups.is_empty());
+
+        let request = Request::new(CreateGroupRequest {
+            name: "admins".to_owned(),
+        });
+        let status = admin_service.create_group(request).await.unwrap_err();
+        assert_eq!(status.code(), Code::AlreadyExists);
+
+        let request = Request::new(CreateGroupRequest {
+            name: "site admins".to_owned(),
+        });
+        let status = admin_service.create_group(request).await.unwrap_err();
+        assert_eq!(status.code(), Code::InvalidArgument);
+
+        let request = add_member("admins", "unknown");
+        let status = admin_service.add_group_member(request).await.unwrap_err();
+        assert_eq!(status.code(), Code::NotFound);
+
+        let request = add_member("engineering", &user.user_uuid);
+        let status = admin_service.add_group_member(request).await.unwrap_err();
+        assert_eq!(status.code(), Code::NotFound);
+    }
+
+    #[tokio::test]
+    async fn should_clear_lockouts() {
+        let lockouts_service = Box::new(Mutex::new(LockoutsImpl::new(1, Duration::from_secs(60))));
+        let admin_service = admin_service(auth_service().with_lockouts(lockouts_service));
+        admin_service
+            .auth
+            .lockouts()
+            .lock()
+            .await
+            .record_failure("alice");
+
+        let request = || {
+            Request::new(GetLockoutRequest {
+                username: "alice".to_owned(),
+            })
+        };
+        let response = admin_service.get_lockout(request()).await.unwrap();
+        assert_eq!(response.into_inner().locked_for, 60);
+
+        let request_clear = Request::new(ClearLockoutRequest {
+            username: "alice".to_owned(),
+        });
+        admin_service.clear_lockout(request_clear).await.unwrap();
+        let response = admin_service.get_lockout(request()).await.unwrap();
+        assert_eq!(response.into_inner().locked_for, 0);
+    }
+
+    #[tokio::test]
+    async fn should_switch_maintenance_mode() {
+        let admin_service = admin_service(auth_service());
+
+        let request = Request::new(SetMaintenanceModeRequest {
+            mode: MaintenanceMode::SignIn.into(),
+        });
+        let response = admin_service.set_maintenance_mode(request).await.unwrap();
+        assert_eq!(response.into_inner().previous_mode(), MaintenanceMode::Off);
+        assert_eq!(admin_service.auth.maintenance(), Maintenance::SignIn);
+
+        let request = Request::new(GetMaintenanceModeRequest {});
+        let response = admin_service.get_maintenance_mode(request).await.unwrap();
+        assert_eq!(response.into_inner().mode(), MaintenanceMode::SignIn);
+
+        let request = Request::new(SetMaintenanceModeRequest { mode: 7 });
+        let status = admin_service
+            .set_maintenance_mode(request)
+            .await
+            .unwrap_err();
+        assert_eq!(status.code(), Code::InvalidArgument);
+    }
+
+    #[tokio::test]
+    async fn should_stream_session_events() {
+        let (sender, _) = broadcast::channel(SESSION_EVENTS_CAPACITY);
+        let admin_service = admin_service(
+            AuthService::new(
+                Box::new(RwLock::new(UsersImpl::default())),
+                Box::new(RwLock::new(
+                    SessionsImpl::default().with_events(sender.clone()),
+                )),
+            )
+            .with_session_events(sender),
+        );
+        let user = create_user(&admin_service, "alice").await;
+
+        let request = Request::new(WatchSessionEventsRequest {});
+        let mut stream = admin_service
+            .watch_session_events(request)
+            .await
+            .unwrap()
+            .into_inner();
+
+        let request = Request::new(SignInRequest {
+            username: "alice".to_owned(),
+            password: "password".to_owned(),
+            ..Default::default()
+        });
+        let session_token = admin_service
+            .auth
+            .sign_in(request)
+            .await
+            .unwrap()
+            .into_inner()
+            .session_token;
+        let request = Request::new(SignOutRequest {
+            session_token: session_token.clone(),
+        });
+        admin_service.auth.sign_out(request).await.unwrap();
+
+        let created = stream.next().await.unwrap().unwrap();
+        assert_eq!(created.event_type(), SessionEventType::Created);
+        assert_eq!(created.user_uuid, user.user_uuid);
+        assert_eq!(created.token_hash, token_hash(&session_token));
+
+        let deleted = stream.next().await.unwrap().unwrap();
+        assert_eq!(deleted.event_type(), SessionEventType::Deleted);
+        assert_eq!(deleted.session_id, created.session_id);
+    }
+
+    #[tokio::test]
+    async fn should_fail_to_stream_session_events_without_events() {
+        let admin_service = admin_service(auth_service());
+
+        let request = Request::new(WatchSessionEventsRequest {});
+        let result = admin_service.watch_session_events(request).await;
+
+        assert_eq!(result.err().unwrap().code(), Code::Unimplemented);
+    }
+}
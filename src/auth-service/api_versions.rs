@@ -0,0 +1,471 @@
+use std::sync::Arc;
+
+use prost::Message;
+use tonic::{Code, Request, Response, Status};
+
+use crate::auth::authentication::v1::auth_server::Auth as AuthV1;
+use crate::auth::authentication::v1::*;
+use crate::auth::authentication::v2;
+use crate::auth::authentication::v2::ErrorReason;
+use crate::auth::{AuthServer, AuthService, WatchSessionStream};
+use crate::metrics::{record_failure, track_failure, FailureReason};
+use crate::session_auth::AuthenticatedUser;
+use crate::validation::validate;
+
+pub use v2::auth_server::AuthServer as AuthV2Server;
+
+pub mod rpc {
+    tonic::include_proto!("google.rpc");
+}
+
+/// `ErrorInfo` domain of the auth API's errors.
+const ERROR_DOMAIN: &str = "authentication";
+
+/// Name of the auth service before its API was versioned. Clients still calling it get v1.
+pub const UNVERSIONED_SERVICE_NAME: &str = "authentication.Auth";
+
+/// Routes calls to the unversioned auth service to v1, which is the same API.
+pub fn route_unversioned<B>(mut request: http::Request<B>) -> http::Request<B> {
+    let v1_name = <AuthServer<AuthService> as tonic::server::NamedService>::NAME;
+    let method = request
+        .uri()
+        .path()
+        .strip_prefix('/')
+        .and_then(|path| path.strip_prefix(UNVERSIONED_SERVICE_NAME))
+        .and_then(|path| path.strip_prefix('/'));
+    if let Some(method) = method {
+        if let Ok(uri) = format!("/{v1_name}/{method}").parse() {
+            *request.uri_mut() = uri;
+        }
+    }
+    request
+}
+
+/// Serves the v2 API by calling the v1 implementation and reporting its failures as gRPC errors
+/// with rich error details.
+pub struct AuthV2 {
+    v1: Arc<AuthService>,
+}
+
+impl AuthV2 {
+    pub fn new(v1: Arc<AuthService>) -> Self {
+        Self { v1 }
+    }
+}
+
+/// Implements the v2 methods, which take and return the same messages as their v1 counterparts
+/// unless they're new in v2.
+macro_rules! delegate_to_v1 {
+    ($($method:ident($request:ident) -> $response:ident,)*) => {
+        #[tonic::async_trait]
+        impl v2::auth_server::Auth for AuthV2 {
+            $(
+                async fn $method(
+                    &self,
+                    request: Request<$request>,
+                ) -> Result<Response<$response>, Status> {
+                    let (response, failure) =
+                        track_failure(AuthV1::$method(&*self.v1, request)).await;
+                    // Passed on to the metrics, which track failures in a scope of their own
+                    if let Some(failure) = failure {
+                        record_failure(failure);
+                    }
+                    rich_error(response?, failure)
+                }
+            )*
+
+            type WatchSessionStream = WatchSessionStream;
+
+            async fn watch_session(
+                &self,
+                request: Request<v2::WatchSessionRequest>,
+            ) -> Result<Response<Self::WatchSessionStream>, Status> {
+                validate(&request)?;
+                let session_token = match request.extensions().get::<AuthenticatedUser>() {
+                    Some(authenticated_user) => authenticated_user.session_token.expose().to_owned(),
+                    None => request.into_inner().session_token,
+                };
+                let stream = self.v1.watch_session(&session_token).await?;
+                Ok(Response::new(stream))
+            }
+        }
+    };
+}
+
+delegate_to_v1! {
+    sign_up(SignUpRequest) -> SignUpResponse,
+    sign_in(SignInRequest) -> SignInResponse,
+    sign_out(SignOutRequest) -> SignOutResponse,
+    validate_session(ValidateSessionRequest) -> ValidateSessionResponse,
+    refresh_session(RefreshSessionRequest) -> RefreshSessionResponse,
+    request_password_reset(RequestPasswordResetRequest) -> RequestPasswordResetResponse,
+    confirm_password_reset(ConfirmPasswordResetRequest) -> ConfirmPasswordResetResponse,
+    delete_account(DeleteAccountRequest) -> DeleteAccountResponse,
+    get_profile(GetProfileRequest) -> GetProfileResponse,
+    update_profile(UpdateProfileRequest) -> UpdateProfileResponse,
+    list_active_sessions(ListActiveSessionsRequest) -> ListActiveSessionsResponse,
+    enroll_totp(EnrollTotpRequest) -> EnrollTotpResponse,
+    confirm_totp(ConfirmTotpRequest) -> ConfirmTotpResponse,
+    sign_in_with_id_token(SignInWithIdTokenRequest) -> SignInResponse,
+    get_sign_up_challenge(GetSignUpChallengeRequest) -> GetSignUpChallengeResponse,
+    get_login_history(GetLoginHistoryRequest) -> GetLoginHistoryResponse,
+    request_magic_link(RequestMagicLinkRequest) -> RequestMagicLinkResponse,
+    redeem_magic_link(RedeemMagicLinkRequest) -> SignInResponse,
+    renew_session(RenewSessionRequest) -> RenewSessionResponse,
+    introspect_token(IntrospectTokenRequest) -> IntrospectTokenResponse,
+    revoke_token(RevokeTokenRequest) -> RevokeTokenResponse,
+    set_user_attribute(SetUserAttributeRequest) -> SetUserAttributeResponse,
+    get_user_attributes(GetUserAttributesRequest) -> GetUserAttributesResponse,
+}
+
+/// A v1 response, which reports failures in its status code.
+trait Outcome {
+    /// gRPC code of a `FAILURE` the handler gave no reason for.
+    const FAILURE: Code = Code::FailedPrecondition;
+
+    fn outcome(&self) -> StatusCode;
+
+    /// Seconds until it's worth retrying, if ever.
+    fn retry_after(&self) -> Option<u64> {
+        None
+    }
+
+    /// The password field and why the password was rejected, for `WEAK_PASSWORD`.
+    fn password_violations(&self) -> Option<(&'static str, &[PasswordViolationInfo])> {
+        None
+    }
+}
+
+macro_rules! outcome {
+    ($($response:ident $(=> $failure:ident)?,)*) => {
+        $(
+            impl Outcome for $response {
+                $(const FAILURE: Code = Code::$failure;)?
+
+                fn outcome(&self) -> StatusCode {
+                    self.status_code()
+                }
+            }
+        )*
+    };
+}
+
+outcome! {
+    SignOutResponse => Unauthenticated,
+    ValidateSessionResponse => Unauthenticated,
+    RefreshSessionResponse => Unauthenticated,
+    RenewSessionResponse => Unauthenticated,
+    RequestPasswordResetResponse,
+    DeleteAccountResponse,
+    GetProfileResponse,
+    UpdateProfileResponse,
+    ListActiveSessionsResponse,
+    EnrollTotpResponse,
+    ConfirmTotpResponse,
+    GetSignUpChallengeResponse,
+    GetLoginHistoryResponse,
+    RequestMagicLinkResponse,
+    IntrospectTokenResponse,
+    RevokeTokenResponse,
+    SetUserAttributeResponse,
+    GetUserAttributesResponse,
+}
+
+impl Outcome for SignInResponse {
+    const FAILURE: Code = Code::Unauthenticated;
+
+    fn outcome(&self) -> StatusCode {
+        self.status_code()
+    }
+
+    fn retry_after(&self) -> Option<u64> {
+        Some(self.retry_after).filter(|retry_after| *retry_after > 0)
+    }
+}
+
+impl Outcome for SignUpResponse {
+    fn outcome(&self) -> StatusCode {
+        self.status_code()
+    }
+
+    fn password_violations(&self) -> Option<(&'static str, &[PasswordViolationInfo])> {
+        Some(("password", &self.password_violations))
+    }
+}
+
+impl Outcome for ConfirmPasswordResetResponse {
+    fn outcome(&self) -> StatusCode {
+        self.status_code()
+    }
+
+    fn password_violations(&self) -> Option<(&'static str, &[PasswordViolationInfo])> {
+        Some(("newPassword", &self.password_violations))
+    }
+}
+
+/// Passes on a successful v1 response, but turns any other into a gRPC error whose details say
+/// why it failed: the `ErrorReason` the v1 handler noted in the response's extensions, or else
+/// the status code's name. The code of a `FAILURE` follows its reason, or the `failure` the
+/// handler recorded for the metrics.
+// Handlers have to return `Result<_, Status>`, however large `Status` is.
+#[allow(clippy::result_large_err)]
+fn rich_error<T: Outcome>(
+    response: Response<T>,
+    failure: Option<FailureReason>,
+) -> Result<Response<T>, Status> {
+    let outcome = response.get_ref();
+    let noted_reason = response.extensions().get::<ErrorReason>().copied();
+    let (code, message) = match outcome.outcome() {
+        StatusCode::Success => return Ok(response),
+        StatusCode::Failure => failure_code::<T>(noted_reason, failure),
+        StatusCode::MfaRequired => (Code::Unauthenticated, "A TOTP code is required"),
+        StatusCode::AccountLocked => (Code::ResourceExhausted, "Too many failed sign-ins"),
+        StatusCode::SessionLimitReached => (
+            Code::FailedPrecondition,
+            "The user has too many active sessions",
+        ),
+        StatusCode::WeakPassword => (Code::InvalidArgument, "The password is too weak"),
+        StatusCode::AccountDisabled => (Code::PermissionDenied, "The account is disabled"),
+        StatusCode::Maintenance => (Code::Unavailable, "The service is under maintenance"),
+    };
+
+    let error_reason = match outcome.outcome() {
+        StatusCode::AccountLocked => Some(ErrorReason::AccountLocked),
+        StatusCode::AccountDisabled => Some(ErrorReason::AccountDisabled),
+        _ => noted_reason,
+    };
+    let reason = match error_reason {
+        Some(error_reason) => error_reason.as_str_name(),
+        None => outcome.outcome().as_str_name(),
+    };
+
+    let mut details = vec![any(
+        "google.rpc.ErrorInfo",
+        &rpc::ErrorInfo {
+            reason: reason.to_owned(),
+            domain: ERROR_DOMAIN.to_owned(),
+            metadata: Default::default(),
+        },
+    )];
+    if let Some(retry_after) = outcome.retry_after() {
+        let retry_delay = prost_types::Duration {
+            seconds: retry_after as i64,
+            nanos: 0,
+        };
+        details.push(any(
+            "google.rpc.RetryInfo",
+            &rpc::RetryInfo {
+                retry_delay: Some(retry_delay),
+            },
+        ));
+    }
+    if let Some((field, violations)) = outcome.password_violations() {
+        if !violations.is_empty() {
+            let field_violations = violations
+                .iter()
+                .map(|violation| rpc::bad_request::FieldViolation {
+                    field: field.to_owned(),
+                    description: violation.message.clone(),
+                })
+                .collect();
+            details.push(any(
+                "google.rpc.BadRequest",
+                &rpc::BadRequest { field_violations },
+            ));
+        }
+    }
+
+    let status = rpc::Status {
+        code: code as i32,
+        message: message.to_owned(),
+        details,
+    };
+    Err(Status::with_details(
+        code,
+        message,
+        status.encode_to_vec().into(),
+    ))
+}
+
+/// gRPC code and message of a `FAILURE`. A backend failing takes precedence, as the request may
+/// well have succeeded otherwise.
+fn failure_code<T: Outcome>(
+    error_reason: Option<ErrorReason>,
+    failure: Option<FailureReason>,
+) -> (Code, &'static str) {
+    match (error_reason, failure) {
+        (_, Some(FailureReason::BackendError)) => (Code::Unavailable, "A backend is unavailable"),
+        (Some(ErrorReason::UserNotFound), _) => (Code::Unauthenticated, "The user doesn't exist"),
+        (Some(ErrorReason::WrongPassword), _) => (Code::Unauthenticated, "The password is wrong"),
+        (Some(ErrorReason::UsernameTaken), _) => (Code::AlreadyExists, "The username is taken"),
+        (Some(ErrorReason::SessionExpired), _) => {
+            (Code::Unauthenticated, "The session is not active")
+        }
+        (_, Some(FailureReason::BadCredentials)) => (Code::Unauthenticated, "Invalid credentials"),
+        (_, Some(FailureReason::InvalidRequest)) => (Code::InvalidArgument, "Invalid request"),
+        (_, Some(FailureReason::RateLimited)) => (Code::ResourceExhausted, "Too many requests"),
+        _ => (T::FAILURE, "Failed"),
+    }
+}
+
+/// Packs `message` into an `Any`, e.g. for the details of a `google.rpc.Status`.
+pub fn any(type_name: &str, message: &impl Message) -> prost_types::Any {
+    prost_types::Any {
+        type_url: format!("type.googleapis.com/{type_name}"),
+        value: message.encode_to_vec(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn details(status: &Status) -> Vec<prost_types::Any> {
+        rpc::Status::decode(status.details()).unwrap().details
+    }
+
+    #[test]
+    fn should_route_unversioned_calls_to_v1() {
+        let route = |path: &str| {
+            let request = http::Request::builder().uri(path).body(()).unwrap();
+            route_unversioned(request).uri().path().to_owned()
+        };
+
+        assert_eq!(
+            route("/authentication.Auth/SignIn"),
+            "/authentication.v1.Auth/SignIn"
+        );
+        assert_eq!(
+            route("/authentication.v2.Auth/SignIn"),
+            "/authentication.v2.Auth/SignIn"
+        );
+        assert_eq!(
+            route("/authentication.Authz/SignIn"),
+            "/authentication.Authz/SignIn"
+        );
+    }
+
+    #[test]
+    fn should_pass_on_success() {
+        let response = SignOutResponse {
+            status_code: StatusCode::Success.into(),
+        };
+        assert!(rich_error(Response::new(response), None).is_ok());
+    }
+
+    #[test]
+    fn should_report_failure_reason() {
+        let response = SignOutResponse {
+            status_code: StatusCode::Failure.into(),
+        };
+        let status = rich_error(Response::new(response), None).unwrap_err();
+        assert_eq!(status.code(), Code::Unauthenticated);
+
+        let details = details(&status);
+        assert_eq!(details.len(), 1);
+        assert_eq!(
+            details[0].type_url,
+            "type.googleapis.com/google.rpc.ErrorInfo"
+        );
+        let error_info = rpc::ErrorInfo::decode(&details[0].value[..]).unwrap();
+        assert_eq!(error_info.reason, "FAILURE");
+        assert_eq!(error_info.domain, ERROR_DOMAIN);
+    }
+
+    #[test]
+    fn should_report_finer_failure_reason() {
+        let mut response = Response::new(SignInResponse {
+            status_code: StatusCode::Failure.into(),
+            ..Default::default()
+        });
+        response.extensions_mut().insert(ErrorReason::WrongPassword);
+        let status = rich_error(response, None).unwrap_err();
+        assert_eq!(status.code(), Code::Unauthenticated);
+        let error_info = rpc::ErrorInfo::decode(&details(&status)[0].value[..]).unwrap();
+        assert_eq!(error_info.reason, "WRONG_PASSWORD");
+
+        let response = SignInResponse {
+            status_code: StatusCode::AccountDisabled.into(),
+            ..Default::default()
+        };
+        let status = rich_error(Response::new(response), None).unwrap_err();
+        let error_info = rpc::ErrorInfo::decode(&details(&status)[0].value[..]).unwrap();
+        assert_eq!(error_info.reason, "ACCOUNT_DISABLED");
+    }
+
+    #[test]
+    fn should_pick_code_of_failure() {
+        let failure = || SignUpResponse {
+            status_code: StatusCode::Failure.into(),
+            ..Default::default()
+        };
+        let code = |response, failure| rich_error(response, failure).unwrap_err().code();
+
+        assert_eq!(
+            code(Response::new(failure()), None),
+            Code::FailedPrecondition
+        );
+        let mut response = Response::new(failure());
+        response.extensions_mut().insert(ErrorReason::UsernameTaken);
+        assert_eq!(code(response, None), Code::AlreadyExists);
+        assert_eq!(
+            code(
+                Response::new(failure()),
+                Some(FailureReason::InvalidRequest)
+            ),
+            Code::InvalidArgument
+        );
+
+        // Whatever else went wrong, the request may have succeeded with the backend up
+        let mut response = Response::new(failure());
+        response.extensions_mut().insert(ErrorReason::UsernameTaken);
+        assert_eq!(
+            code(response, Some(FailureReason::BackendError)),
+            Code::Unavailable
+        );
+    }
+
+    #[test]
+    fn should_report_when_to_retry() {
+        let response = SignInResponse {
+            status_code: StatusCode::AccountLocked.into(),
+            retry_after: 30,
+            ..Default::default()
+        };
+        let status = rich_error(Response::new(response), None).unwrap_err();
+        assert_eq!(status.code(), Code::ResourceExhausted);
+
+        let details = details(&status);
+        assert_eq!(
+            details[1].type_url,
+            "type.googleapis.com/google.rpc.RetryInfo"
+        );
+        let retry_info = rpc::RetryInfo::decode(&details[1].value[..]).unwrap();
+        assert_eq!(retry_info.retry_delay.unwrap().seconds, 30);
+    }
+
+    #[test]
+    fn should_report_password_violations() {
+        let response = ConfirmPasswordResetResponse {
+            status_code: StatusCode::WeakPassword.into(),
+            password_violations: vec![PasswordViolationInfo {
+                message: "Password must be at least 8 characters".to_owned(),
+                ..Default::default()
+            }],
+        };
+        let status = rich_error(Response::new(response), None).unwrap_err();
+        assert_eq!(status.code(), Code::InvalidArgument);
+
+        let details = details(&status);
+        assert_eq!(
+            details[1].type_url,
+            "type.googleapis.com/google.rpc.BadRequest"
+        );
+        let bad_request = rpc::BadRequest::decode(&details[1].value[..]).unwrap();
+        assert_eq!(bad_request.field_violations[0].field, "newPassword");
+        assert_eq!(
+            bad_request.field_violations[0].description,
+            "Password must be at least 8 characters"
+        );
+    }
+}
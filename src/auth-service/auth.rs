@@ -1,273 +1,4065 @@
-use std::sync::Mutex;
+use std::pin::Pin;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
-use crate::{sessions::Sessions, users::Users};
+use crate::{
+    breached_passwords::BreachedPasswords,
+    challenges::SignUpChallenge,
+    clock::{Clock, SystemClock},
+    error::{AuthError, SessionError, StorageError},
+    groups::{Groups, GroupsImpl},
+    idempotency::IdempotencyKeys,
+    lockouts::{Lockouts, LockoutsImpl},
+    login_history::{LoginAttempt, LoginHistory, LoginHistoryImpl},
+    magic_links::{MagicLinks, MagicLinksImpl},
+    mailer::{ConsoleMailer, Mailer},
+    maintenance::Maintenance,
+    metrics::{record_failure, FailureReason},
+    mfa::{provisioning_uri, Mfa, MfaImpl},
+    oidc::{IdTokenClaims, IdTokenVerifier},
+    password_policy::{PasswordPolicy, PasswordViolation},
+    password_resets::{PasswordResets, PasswordResetsImpl},
+    rate_limits::{rate_limited, RateLimiter},
+    secret::Secret,
+    session_auth::AuthenticatedUser,
+    sessions::{
+        token_hash, ClientMetadata, SessionEvent, SessionEventKind, SessionLimit,
+        SessionLimitPolicy, Sessions,
+    },
+    users::{Users, DEFAULT_DELETION_GRACE_PERIOD},
+    validation::validate,
+};
 
+use tokio::sync::{broadcast, Mutex, RwLock};
+use tokio_stream::wrappers::{errors::BroadcastStreamRecvError, BroadcastStream};
+use tokio_stream::{Stream, StreamExt};
 // use tonic::codegen::http::status;
 use tonic::{Request, Response, Status};
 
 use authentication::auth_server::Auth;
+use authentication::v2::{ErrorReason, WatchSessionResponse};
 use authentication::{
-    SignInRequest, SignInResponse, SignOutRequest, SignOutResponse, SignUpRequest, SignUpResponse,
-    StatusCode,
+    ConfirmPasswordResetRequest, ConfirmPasswordResetResponse, ConfirmTotpRequest,
+    ConfirmTotpResponse, DeleteAccountRequest, DeleteAccountResponse, EnrollTotpRequest,
+    EnrollTotpResponse, GetLoginHistoryRequest, GetLoginHistoryResponse, GetProfileRequest,
+    GetProfileResponse, GetSignUpChallengeRequest, GetSignUpChallengeResponse,
+    GetUserAttributesRequest, GetUserAttributesResponse, IntrospectTokenRequest,
+    IntrospectTokenResponse, ListActiveSessionsRequest, ListActiveSessionsResponse,
+    LoginAttemptInfo, PasswordViolationInfo, PasswordViolationType, RedeemMagicLinkRequest,
+    RefreshSessionRequest, RefreshSessionResponse, RenewSessionRequest, RenewSessionResponse,
+    RequestMagicLinkRequest, RequestMagicLinkResponse, RequestPasswordResetRequest,
+    RequestPasswordResetResponse, RevokeTokenRequest, RevokeTokenResponse, SessionEventType,
+    SessionInfo, SetUserAttributeRequest, SetUserAttributeResponse, SignInRequest, SignInResponse,
+    SignInWithIdTokenRequest, SignOutRequest, SignOutResponse, SignUpRequest, SignUpResponse,
+    StatusCode, UpdateProfileRequest, UpdateProfileResponse, ValidateSessionRequest,
+    ValidateSessionResponse,
 };
 
 pub mod authentication {
-    tonic::include_proto!("authentication");
+    pub mod v1 {
+        tonic::include_proto!("authentication.v1");
+    }
+
+    pub mod v2 {
+        tonic::include_proto!("authentication.v2");
+    }
+    pub mod admin {
+        pub mod v1 {
+            tonic::include_proto!("authentication.admin.v1");
+        }
+    }
+
+    // v1 is implemented here, v2 translates its failures to gRPC errors
+    pub use v1::*;
+
+    /// Descriptors of the auth and health protos, served over gRPC reflection.
+    pub const FILE_DESCRIPTOR_SET: &[u8] =
+        tonic::include_file_descriptor_set!("authentication_descriptor");
 }
 
 // Re-exporting
 pub use authentication::auth_server::AuthServer;
 pub use tonic::transport::Server;
 
+/// Login attempts returned by `GetLoginHistory` when the request doesn't set a page size.
+pub const DEFAULT_LOGIN_HISTORY_PAGE_SIZE: usize = 20;
+/// Upper bound on the page size of `GetLoginHistory`.
+pub const MAX_LOGIN_HISTORY_PAGE_SIZE: usize = 100;
+
+/// Reported by `IntrospectToken` for session tokens, the only kind it recognizes.
+pub const SESSION_TOKEN_TYPE: &str = "session";
+/// How often `check_backends` is called to report the auth service's health.
+pub const HEALTH_CHECK_INTERVAL: Duration = Duration::from_secs(10);
+
+/// A `SignUp` reply, why it failed and how the failure is counted, if it did.
+type SignUpOutcome = (SignUpResponse, Option<ErrorReason>, Option<FailureReason>);
+
+/// Backends are behind async locks, as most of them await their database while locked. The users
+/// and sessions are behind read/write locks, so sign ins, validations and other reads only wait
+/// for writes. The in-memory bookkeeping of the service and its layers uses `parking_lot` locks
+/// instead, held only for synchronous updates and never poisoned, so a panicking request doesn't
+/// fail the ones after it.
+///
+/// A backend call that fails leaves its backend unchanged, and steps spanning backends are
+/// ordered so a failure leaves nothing half done: e.g. a reset token is only used up once the
+/// new password is stored, so the user can try again with it.
 pub struct AuthService {
-    users_service: Box<Mutex<dyn Users + Send + Sync>>,
-    sessions_service: Box<Mutex<dyn Sessions + Send + Sync>>,
+    users_service: Box<RwLock<dyn Users + Send + Sync>>,
+    sessions_service: Box<RwLock<dyn Sessions + Send + Sync>>,
+    password_resets_service: Box<Mutex<dyn PasswordResets + Send + Sync>>,
+    magic_links_service: Box<Mutex<dyn MagicLinks + Send + Sync>>,
+    mfa_service: Box<Mutex<dyn Mfa + Send + Sync>>,
+    lockouts_service: Box<Mutex<dyn Lockouts + Send + Sync>>,
+    sign_in_rate_limiter: Option<Box<Mutex<dyn RateLimiter + Send + Sync>>>,
+    login_history_service: Box<Mutex<dyn LoginHistory + Send + Sync>>,
+    groups_service: Box<Mutex<dyn Groups + Send + Sync>>,
+    mailer: Box<dyn Mailer + Send + Sync>,
+    id_token_verifier: Option<Box<dyn IdTokenVerifier + Send + Sync>>,
+    sign_up_challenge: Option<Box<dyn SignUpChallenge + Send + Sync>>,
+    session_limit: Option<SessionLimit>,
+    session_events: Option<broadcast::Sender<SessionEvent>>,
+    deletion_grace_period: Duration,
+    password_policy: Option<PasswordPolicy>,
+    breached_passwords: Option<Box<dyn BreachedPasswords + Send + Sync>>,
+    anti_enumeration: bool,
+    maintenance: parking_lot::Mutex<Maintenance>,
+    sign_up_results: IdempotencyKeys<(String, String), SignUpOutcome>,
+    clock: Box<dyn Clock + Send + Sync>,
 }
 
 impl AuthService {
     pub fn new(
-        users_service: Box<Mutex<dyn Users + Send + Sync>>,
-        sessions_service: Box<Mutex<dyn Sessions + Send + Sync>>,
+        users_service: Box<RwLock<dyn Users + Send + Sync>>,
+        sessions_service: Box<RwLock<dyn Sessions + Send + Sync>>,
     ) -> Self {
         Self {
             users_service,
             sessions_service,
+            password_resets_service: Box::new(Mutex::new(PasswordResetsImpl::default())),
+            magic_links_service: Box::new(Mutex::new(MagicLinksImpl::default())),
+            mfa_service: Box::new(Mutex::new(MfaImpl::default())),
+            lockouts_service: Box::new(Mutex::new(LockoutsImpl::default())),
+            sign_in_rate_limiter: None,
+            login_history_service: Box::new(Mutex::new(LoginHistoryImpl::default())),
+            groups_service: Box::new(Mutex::new(GroupsImpl::default())),
+            mailer: Box::new(ConsoleMailer),
+            id_token_verifier: None,
+            sign_up_challenge: None,
+            session_limit: None,
+            session_events: None,
+            deletion_grace_period: DEFAULT_DELETION_GRACE_PERIOD,
+            password_policy: None,
+            breached_passwords: None,
+            anti_enumeration: false,
+            maintenance: parking_lot::Mutex::default(),
+            sign_up_results: IdempotencyKeys::default(),
+            clock: Box::new(SystemClock),
         }
     }
-}
 
-#[tonic::async_trait]
-impl Auth for AuthService {
-    async fn sign_in(
+    /// Replaces the default `ConsoleMailer` used to deliver reset tokens and magic links.
+    pub fn with_mailer(mut self, mailer: Box<dyn Mailer + Send + Sync>) -> Self {
+        self.mailer = mailer;
+        self
+    }
+
+    /// Replaces the default lockout policy applied to failed sign-ins.
+    pub fn with_lockouts(
+        mut self,
+        lockouts_service: Box<Mutex<dyn Lockouts + Send + Sync>>,
+    ) -> Self {
+        self.lockouts_service = lockouts_service;
+        self
+    }
+
+    /// Throttles `SignIn` attempts for each username, whatever address they come from. Attempts
+    /// aren't limited by default.
+    pub fn with_sign_in_rate_limiter(
+        mut self,
+        sign_in_rate_limiter: Box<Mutex<dyn RateLimiter + Send + Sync>>,
+    ) -> Self {
+        self.sign_in_rate_limiter = Some(sign_in_rate_limiter);
+        self
+    }
+
+    /// Enables `SignInWithIdToken`, which fails for every request without a verifier.
+    #[cfg_attr(not(feature = "oidc"), allow(dead_code))]
+    pub fn with_id_token_verifier(
+        mut self,
+        id_token_verifier: Box<dyn IdTokenVerifier + Send + Sync>,
+    ) -> Self {
+        self.id_token_verifier = Some(id_token_verifier);
+        self
+    }
+
+    /// Requires `SignUp` requests to pass `sign_up_challenge`. Sign up is open without one.
+    pub fn with_sign_up_challenge(
+        mut self,
+        sign_up_challenge: Box<dyn SignUpChallenge + Send + Sync>,
+    ) -> Self {
+        self.sign_up_challenge = Some(sign_up_challenge);
+        self
+    }
+
+    /// The users backend, for the admin API to manage users directly.
+    pub fn users(&self) -> &RwLock<dyn Users + Send + Sync> {
+        &self.users_service
+    }
+
+    /// The sessions backend, for the admin API to revoke sessions.
+    pub fn sessions(&self) -> &RwLock<dyn Sessions + Send + Sync> {
+        &self.sessions_service
+    }
+
+    /// The failed sign-in counts, for the admin API to lift lockouts.
+    pub fn lockouts(&self) -> &Mutex<dyn Lockouts + Send + Sync> {
+        &self.lockouts_service
+    }
+
+    /// Subscribes to the sessions backend's events, for the admin API to stream them. `None` if
+    /// the backend doesn't publish any.
+    pub fn subscribe_session_events(&self) -> Option<broadcast::Receiver<SessionEvent>> {
+        self.session_events
+            .as_ref()
+            .map(|session_events| session_events.subscribe())
+    }
+
+    /// The group memberships, for the admin API to show a user's groups.
+    pub fn groups(&self) -> &Mutex<dyn Groups + Send + Sync> {
+        &self.groups_service
+    }
+
+    /// How long deleted accounts can be restored, for the admin API to restore them.
+    pub fn deletion_grace_period(&self) -> Duration {
+        self.deletion_grace_period
+    }
+
+    /// The clock telling when deleted accounts are due to be purged.
+    pub fn clock(&self) -> &dyn Clock {
+        self.clock.as_ref()
+    }
+
+    /// Which calls are refused for maintenance, for the admin API to show.
+    pub fn maintenance(&self) -> Maintenance {
+        *self.maintenance.lock()
+    }
+
+    /// Switches the maintenance mode for the admin API, returning the previous one. Calls already
+    /// past the check finish as before.
+    pub fn set_maintenance(&self, maintenance: Maintenance) -> Maintenance {
+        std::mem::replace(&mut *self.maintenance.lock(), maintenance)
+    }
+
+    /// Evicts expired sessions, meant to be called periodically.
+    pub async fn remove_expired_sessions(&self) -> usize {
+        self.sessions_service.write().await.remove_expired().await
+    }
+
+    /// Fails if the users or sessions backend can't be reached, for health checks.
+    pub async fn check_backends(&self) -> Result<(), AuthError> {
+        self.users_service.read().await.ping().await?;
+        self.sessions_service.read().await.ping().await?;
+        Ok(())
+    }
+
+    /// Counts the users that aren't deleted and the sessions that haven't expired, if the
+    /// sessions backend stores them.
+    pub async fn count_users_and_sessions(&self) -> Result<(usize, Option<usize>), AuthError> {
+        let users = self.users_service.read().await.count_users().await?;
+        let sessions = self.sessions_service.read().await.count_sessions().await?;
+        Ok((users, sessions))
+    }
+
+    /// Saves state held in memory by the users and sessions backends and closes their
+    /// connections, meant to be called once the server has stopped.
+    pub async fn flush(&self) {
+        self.sessions_service.write().await.flush().await;
+        self.users_service.write().await.flush().await;
+    }
+
+    /// Purges accounts deleted longer than the deletion grace period ago, meant to be called
+    /// periodically.
+    pub async fn purge_deleted_users(&self) -> usize {
+        let deleted_before = self
+            .clock
+            .now()
+            .checked_sub(self.deletion_grace_period)
+            .unwrap_or(UNIX_EPOCH);
+
+        let purged = self
+            .users_service
+            .write()
+            .await
+            .purge_deleted_users(deleted_before)
+            .await;
+
+        // Forget the purged users' sign in attempts and group memberships.
+        let mut login_history_service = self.login_history_service.lock().await;
+        let mut groups_service = self.groups_service.lock().await;
+        for user_uuid in &purged {
+            login_history_service.delete_user_history(user_uuid);
+            groups_service.delete_user_memberships(user_uuid);
+        }
+
+        purged.len()
+    }
+
+    /// Changes how long deleted accounts can be restored before they're purged.
+    pub fn with_deletion_grace_period(mut self, deletion_grace_period: Duration) -> Self {
+        self.deletion_grace_period = deletion_grace_period;
+        self
+    }
+
+    /// Tells the time with `clock` instead of the system clock, so tests can outlast the deletion
+    /// grace period without waiting.
+    #[cfg(test)]
+    pub fn with_clock(mut self, clock: Box<dyn Clock + Send + Sync>) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    /// Requires new passwords chosen at sign up or reset to follow `password_policy`. Any password
+    /// is accepted without one.
+    pub fn with_password_policy(mut self, password_policy: PasswordPolicy) -> Self {
+        self.password_policy = Some(password_policy);
+        self
+    }
+
+    /// Also rejects new passwords `breached_passwords` finds in a data breach. Passwords are
+    /// accepted if the check fails, so an unreachable service doesn't block sign ups.
+    #[cfg_attr(not(feature = "breached-passwords"), allow(dead_code))]
+    pub fn with_breached_passwords(
+        mut self,
+        breached_passwords: Box<dyn BreachedPasswords + Send + Sync>,
+    ) -> Self {
+        self.breached_passwords = Some(breached_passwords);
+        self
+    }
+
+    /// Caps how many sessions each user can have at once. Unlimited by default.
+    pub fn with_session_limit(mut self, session_limit: SessionLimit) -> Self {
+        self.session_limit = Some(session_limit);
+        self
+    }
+
+    /// Answers `SignIn` and `SignUp` the same whether or not the username exists, so callers
+    /// can't use them to find out who has an account. Failed sign ins don't say whether the user
+    /// wasn't found or the password was wrong, and signing up with a taken username or email
+    /// seems to succeed, mailing the account's owner instead.
+    pub fn with_anti_enumeration(mut self) -> Self {
+        self.anti_enumeration = true;
+        self
+    }
+
+    /// Starts in `maintenance` mode rather than with every call accepted.
+    pub fn with_maintenance(self, maintenance: Maintenance) -> Self {
+        *self.maintenance.lock() = maintenance;
+        self
+    }
+
+    /// Replays `SignUp` results to retries with the same idempotency key and username for
+    /// `window`, 10 minutes by default.
+    pub fn with_idempotency_window(mut self, window: Duration) -> Self {
+        self.sign_up_results = IdempotencyKeys::new(window);
+        self
+    }
+
+    /// Serves `WatchSession` and the admin `WatchSessionEvents` from `session_events`, which the
+    /// sessions backend publishes to. The RPCs are unavailable otherwise.
+    pub fn with_session_events(mut self, session_events: broadcast::Sender<SessionEvent>) -> Self {
+        self.session_events = Some(session_events);
+        self
+    }
+
+    /// Creates a new session and refresh token for a signed in user, applying the session limit.
+    async fn start_session(
         &self,
-        request: Request<SignInRequest>,
-    ) -> Result<Response<SignInResponse>, Status> {
-        println!("Got a request: {:?}", request);
+        user_uuid: String,
+        client: ClientMetadata,
+    ) -> Result<SignInResponse, Status> {
+        // Checked here so disabled users can't sign in any way, only once they've proven who
+        // they are.
+        let disabled = self
+            .users_service
+            .read()
+            .await
+            .get_user(user_uuid.clone())
+            .await
+            .is_some_and(|user| user.disabled);
 
-        let req = request.into_inner();
+        if disabled {
+            return Ok(SignInResponse {
+                status_code: StatusCode::AccountDisabled.into(),
+                ..Default::default()
+            });
+        }
 
-        // Get user's uuid from `users_service`. Panic if the lock is poisoned.
-        let user_uuid: Option<String> = match self.users_service.lock() {
-            Ok(users_service) => users_service,
-            Err(_) => panic!("Poisoned lock"),
+        let mut sessions_service = self.sessions_service.write().await;
+
+        if let Some(session_limit) = self.session_limit {
+            // Oldest first.
+            let sessions = sessions_service.list_user_sessions(&user_uuid).await?;
+
+            if sessions.len() >= session_limit.max_sessions {
+                match session_limit.policy {
+                    SessionLimitPolicy::Reject => {
+                        return Ok(SignInResponse {
+                            status_code: StatusCode::SessionLimitReached.into(),
+                            ..Default::default()
+                        });
+                    }
+                    SessionLimitPolicy::EvictOldest => {
+                        // Leave room for the new session.
+                        let excess = sessions.len() + 1 - session_limit.max_sessions.max(1);
+                        for session in &sessions[..excess] {
+                            sessions_service
+                                .delete_user_session(&user_uuid, session.session_id)
+                                .await;
+                        }
+                    }
+                }
+            }
         }
-        .get_user_uuid(req.username.clone(), req.password);
+        // A session or refresh token the backend couldn't store would be handed out without
+        // validating.
+        let session_token = sessions_service.create_session(&user_uuid, client).await?;
+        let refresh_token = sessions_service
+            .create_refresh_token(&user_uuid, &session_token)
+            .await?;
 
-        // Match on `result`. If `result` is `None` return a SignInResponse with a the `status_code` set to `Failure`
-        let mut sigin = SignInResponse {
+        Ok(SignInResponse {
             status_code: StatusCode::Success.into(),
-            session_token: "".to_owned(),
-            user_uuid: "".to_owned(),
-        };
+            user_uuid,
+            session_token,
+            refresh_token,
+            retry_after: 0,
+        })
+    }
 
-        let user_uuid = match user_uuid {
-            None => {
-                let reply = SignInResponse {
-                    status_code: StatusCode::Failure.into(),
-                    session_token: "".to_owned(),
-                    user_uuid: "".to_owned(),
-                };
-                return Ok(Response::new(reply));
+    /// Checks the username, password and TOTP code of a sign in request and starts a session
+    /// if they are correct.
+    async fn password_sign_in(
+        &self,
+        req: &SignInRequest,
+        client: ClientMetadata,
+    ) -> Result<SignInResponse, Status> {
+        // Locked accounts are rejected before checking the password.
+        let locked_for = self.lockouts_service.lock().await.locked_for(&req.username);
+
+        if let Some(locked_for) = locked_for {
+            return Ok(account_locked(locked_for));
+        }
+
+        // Get user's uuid from `users_service`. Concurrent sign ins only share the read lock.
+        let verified = self
+            .users_service
+            .read()
+            .await
+            .verify_password(req.username.clone(), req.password.clone())
+            .await;
+
+        // Match on `result`. If `result` is `None` count the failure and return a SignInResponse
+        // with the `status_code` set to `Failure`, or `AccountLocked` once the threshold is reached.
+        let user_uuid = match verified {
+            None => return Ok(self.failed_sign_in(&req.username).await),
+            Some(verified) => {
+                if verified.needs_rehash {
+                    self.upgrade_password_hash(&verified.user_uuid, req.password.clone())
+                        .await;
+                }
+                verified.user_uuid
             }
-            Some(uuid) => uuid,
         };
 
-        // and `user_uuid`/`session_token` set to empty strings.
+        // Users with TOTP enabled also need a valid code.
+        let mut mfa_service = self.mfa_service.lock().await;
 
-        // Create new session using `sessions_service`. Panic if the lock is poisoned.
-        let session_token = match self.sessions_service.lock() {
-            Ok(sessions_service) => sessions_service,
-            Err(_) => panic!("Poisoned lock"),
-        }
-        .create_session(&user_uuid);
+        if mfa_service.is_totp_enabled(&user_uuid)
+            && !mfa_service.verify_totp(&user_uuid, &req.totp_code)
+        {
+            drop(mfa_service);
 
-        sigin.session_token = session_token;
-        sigin.user_uuid = user_uuid;
-        sigin.status_code = StatusCode::Success.into();
+            // Let the client know to prompt for a code rather than reporting bad credentials.
+            // Only a wrong code counts towards the lockout.
+            return Ok(match req.totp_code.is_empty() {
+                true => SignInResponse {
+                    status_code: StatusCode::MfaRequired.into(),
+                    ..Default::default()
+                },
+                false => self.failed_sign_in(&req.username).await,
+            });
+        }
+        drop(mfa_service);
 
-        println!("USER signin: {:?}", sigin);
+        self.lockouts_service
+            .lock()
+            .await
+            .record_success(&req.username);
 
-        Ok(Response::new(sigin))
+        // Create new session and its refresh token using `sessions_service`.
+        self.start_session(user_uuid, client).await
     }
 
-    async fn sign_up(
-        &self,
-        request: Request<SignUpRequest>,
-    ) -> Result<Response<SignUpResponse>, Status> {
-        println!("Got a request: {:?}", request);
-
-        let req = request.into_inner();
+    /// Handles a `SignUp` request, returning the reply along with why it failed, if it did. The
+    /// failure is returned rather than recorded so it's recorded again for replayed results.
+    async fn create_account(&self, req: SignUpRequest) -> SignUpOutcome {
+        // Reject requests without a valid solution when a challenge is configured.
+        if let Some(sign_up_challenge) = &self.sign_up_challenge {
+            if !sign_up_challenge
+                .verify(&req.challenge_id, &req.challenge_solution)
+                .await
+            {
+                let result = SignUpResponse {
+                    status_code: StatusCode::Failure.into(),
+                    ..Default::default()
+                };
+                return (result, None, Some(FailureReason::InvalidRequest));
+            }
+        }
 
-        // Create a new user through `users_service`. Panic if the lock is poisoned.
-        let result: Result<(), String> = match self.users_service.is_poisoned() {
-            true => panic!("Poisoned lock"),
-            false => self.users_service.lock().unwrap(),
+        if let Err(password_violations) = self.check_password(&req.password).await {
+            let result = SignUpResponse {
+                status_code: StatusCode::WeakPassword.into(),
+                password_violations,
+            };
+            return (result, None, None);
         }
-        .create_user(req.username.clone(), req.password);
 
-        // TODO: Return a `SignUpResponse` with the appropriate `status_code` based on `result`.
+        // Create a new user through `users_service`.
+        let result: Result<(), StorageError> = match self.hash_password(&req.password).await {
+            Ok(hashed_password) => {
+                self.users_service
+                    .write()
+                    .await
+                    .create_user_hashed(req.username.clone(), hashed_password, req.email)
+                    .await
+            }
+            Err(e) => Err(e),
+        };
+
         match result {
             Ok(_) => {
                 let result = SignUpResponse {
                     status_code: StatusCode::Success.into(),
+                    ..Default::default()
                 };
-                return Ok(Response::new(result));
+                (result, None, None)
             }
-            Err(_) => {
+            // Users are only looked up by username, so the owner of a taken email isn't told
+            Err(StorageError::UsernameTaken | StorageError::EmailTaken)
+                if self.anti_enumeration =>
+            {
+                let owner = self
+                    .users_service
+                    .read()
+                    .await
+                    .lookup_user_uuid(req.username)
+                    .await;
+                if let Some(owner) = owner {
+                    self.mailer.send_sign_up_attempt(&owner);
+                }
+
+                let result = SignUpResponse {
+                    status_code: StatusCode::Success.into(),
+                    ..Default::default()
+                };
+                (result, None, None)
+            }
+            Err(e) => {
                 let result = SignUpResponse {
                     status_code: StatusCode::Failure.into(),
+                    ..Default::default()
+                };
+                let error_reason = match e {
+                    StorageError::UsernameTaken => Some(ErrorReason::UsernameTaken),
+                    _ => None,
                 };
-                return Ok(Response::new(result));
+                (result, error_reason, None)
             }
         }
     }
 
-    async fn sign_out(
-        &self,
-        request: Request<SignOutRequest>,
-    ) -> Result<Response<SignOutResponse>, Status> {
-        println!("Got a request: {:?}", request);
+    /// Adds a sign in attempt to the user's login history.
+    async fn record_sign_in(&self, user_uuid: &str, client: ClientMetadata, success: bool) {
+        let attempt = LoginAttempt {
+            timestamp: SystemTime::now(),
+            success,
+            client,
+        };
 
-        let req = request.into_inner();
+        self.login_history_service
+            .lock()
+            .await
+            .record_attempt(user_uuid, attempt);
+    }
 
-        // TODO: Delete session using `sessions_service`.
-        match self.sessions_service.is_poisoned() {
-            true => panic!("Poisoned lock"),
-            false => self.sessions_service.lock(),
-        }
-        .expect("Unable to lock")
-        .delete_session(&req.session_token);
-        
-	// Create `SignOutResponse` with `status_code` set to `Success`
-        let reply: SignOutResponse = SignOutResponse {
-            status_code: StatusCode::Success.into(),
+    /// Re-hashes the password of a user signing in whose hash uses an older algorithm or cost.
+    /// Failing to isn't a reason to fail the sign in, the next one tries again.
+    async fn upgrade_password_hash(&self, user_uuid: &str, password: String) {
+        let result = match self.hash_password(&password).await {
+            Ok(hashed_password) => {
+                self.users_service
+                    .write()
+                    .await
+                    .update_password_hashed(user_uuid.to_owned(), hashed_password)
+                    .await
+            }
+            Err(e) => Err(e),
         };
-        Ok(Response::new(reply))
+        if let Err(e) = result {
+            tracing::warn!(error = %e, "Failed to upgrade password hash");
+        }
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use crate::{sessions::SessionsImpl, users::UsersImpl};
+    /// Hashes `password` for `users_service` without holding its lock, as hashing is slow on
+    /// purpose and every sign in needs the lock.
+    pub async fn hash_password(&self, password: &str) -> Result<String, StorageError> {
+        let hashing = self.users_service.read().await.hashing().await;
+        hashing
+            .hash_password(password)
+            .await
+            .map_err(StorageError::Hashing)
+    }
 
-    use super::*;
+    /// Counts a failed sign-in for `username`, locking the account once the threshold is reached.
+    async fn failed_sign_in(&self, username: &str) -> SignInResponse {
+        let locked_for = self.lockouts_service.lock().await.record_failure(username);
 
-    #[tokio::test]
-    async fn sign_in_should_fail_if_user_not_found() {
-        let users_service = Box::new(Mutex::new(UsersImpl::default()));
-        let sessions_service = Box::new(Mutex::new(SessionsImpl::default()));
+        match locked_for {
+            Some(locked_for) => account_locked(locked_for),
+            None => SignInResponse {
+                status_code: StatusCode::Failure.into(),
+                ..Default::default()
+            },
+        }
+    }
 
-        let auth_service = AuthService::new(users_service, sessions_service);
+    /// Checks a new password against the password policy, describing every rule it breaks.
+    /// Passwords breaking the policy aren't sent to the breach check.
+    pub async fn check_password(&self, password: &str) -> Result<(), Vec<PasswordViolationInfo>> {
+        if let Some(password_policy) = &self.password_policy {
+            password_policy.check(password).map_err(|violations| {
+                violations
+                    .into_iter()
+                    .map(password_violation_info)
+                    .collect::<Vec<_>>()
+            })?;
+        }
 
-        let request = tonic::Request::new(SignInRequest {
-            username: "123456".to_owned(),
-            password: "654321".to_owned(),
-        });
+        if let Some(breached_passwords) = &self.breached_passwords {
+            match breached_passwords.is_breached(password).await {
+                Ok(true) => return Err(vec![password_violation_info(PasswordViolation::Breached)]),
+                Ok(false) => (),
+                Err(e) => tracing::warn!(error = %e, "Skipped breached password check"),
+            }
+        }
 
-        let result = auth_service.sign_in(request).await.unwrap().into_inner();
+        Ok(())
+    }
 
-        assert_eq!(result.status_code, StatusCode::Failure.into());
-        assert_eq!(result.user_uuid.is_empty(), true);
-        assert_eq!(result.session_token.is_empty(), true);
+    /// Resolves a session token to the uuid of the signed in user.
+    pub async fn session_user_uuid(&self, session_token: &str) -> Option<String> {
+        self.sessions_service
+            .read()
+            .await
+            .get_session(session_token)
+            .await
+            .map(|session| session.user_uuid.into())
     }
 
-    #[tokio::test]
-    async fn sign_in_should_fail_if_incorrect_password() {
-        let mut users_service = UsersImpl::default();
+    /// Serves `WatchSession`, streaming once the session ends. Subscribes to session events
+    /// before checking the session, so it can't end unnoticed in between.
+    pub async fn watch_session(
+        &self,
+        session_token: &str,
+    ) -> Result<WatchSessionStream, SessionError> {
+        let receiver = match &self.session_events {
+            Some(session_events) => session_events.subscribe(),
+            None => return Err(SessionError::EventsUnsupported),
+        };
+        if self.session_user_uuid(session_token).await.is_none() {
+            return Err(SessionError::NotFound);
+        }
 
-        let _ = users_service.create_user("123456".to_owned(), "654321".to_owned());
+        let watched_hash = token_hash(session_token);
+        let stream = BroadcastStream::new(receiver)
+            .filter_map(move |event| match event {
+                Ok(event) if event.token_hash != watched_hash => None,
+                Ok(event) => {
+                    let event_type = match event.kind {
+                        SessionEventKind::Created => return None,
+                        SessionEventKind::Deleted => SessionEventType::Deleted,
+                        SessionEventKind::Expired => SessionEventType::Expired,
+                    };
+                    Some(Ok(WatchSessionResponse {
+                        event_type: event_type.into(),
+                        timestamp: unix_timestamp(event.timestamp),
+                    }))
+                }
+                // The session may have ended among the missed events.
+                Err(BroadcastStreamRecvError::Lagged(missed)) => {
+                    Some(Err(Status::data_loss(format!(
+                        "Missed {} session events, validate the session and watch again",
+                        missed
+                    ))))
+                }
+            })
+            .take(1);
 
-        let users_service = Box::new(Mutex::new(users_service));
-        let sessions_service = Box::new(Mutex::new(SessionsImpl::default()));
+        Ok(Box::pin(stream))
+    }
+}
 
-        let auth_service = AuthService::new(users_service, sessions_service);
+/// Stream of `WatchSession`.
+pub type WatchSessionStream =
+    Pin<Box<dyn Stream<Item = Result<WatchSessionResponse, Status>> + Send>>;
 
-        let request = tonic::Request::new(SignInRequest {
-            username: "123456".to_owned(),
-            password: "wrong password".to_owned(),
-        });
+/// The user `SessionAuthLayer` authenticated the request as, if it had a bearer token.
+fn authenticated_user<T>(request: &Request<T>) -> Option<AuthenticatedUser> {
+    request.extensions().get::<AuthenticatedUser>().cloned()
+}
 
-        let result = auth_service.sign_in(request).await.unwrap().into_inner();
+/// Like `authenticated_user`, for RPCs acting on the signed in user. They never trust a session
+/// token or uuid from the message, so requests without a bearer token are unauthenticated.
+// Handlers have to return `Result<_, Status>`, however large `Status` is.
+#[allow(clippy::result_large_err)]
+fn caller<T>(request: &Request<T>) -> Result<AuthenticatedUser, Status> {
+    authenticated_user(request).ok_or_else(|| {
+        record_failure(FailureReason::BadCredentials);
+        Status::unauthenticated("Error, send the session token as `authorization: Bearer` metadata")
+    })
+}
 
-        assert_eq!(result.status_code, StatusCode::Failure.into());
-        assert_eq!(result.user_uuid.is_empty(), true);
-        assert_eq!(result.session_token.is_empty(), true);
-    }
+/// The name a caller was verified by through its TLS client certificate.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ClientIdentity(pub String);
 
-    #[tokio::test]
-    async fn sign_in_should_succeed() {
-        let mut users_service = UsersImpl::default();
+/// The caller's verified certificate name, when the server requires client certificates.
+fn client_identity<T>(request: &Request<T>) -> Option<&str> {
+    request
+        .extensions()
+        .get::<ClientIdentity>()
+        .map(|identity| identity.0.as_str())
+}
 
-        let _ = users_service.create_user("123456".to_owned(), "654321".to_owned());
+/// Logs that a request arrived, along with who made it when the caller is known. The message
+/// itself isn't logged, since many carry passwords or tokens.
+fn log_request<T>(request: &Request<T>) {
+    tracing::info!(
+        peer = request.remote_addr().map(tracing::field::display),
+        client = client_identity(request),
+        "Got a request"
+    );
+}
 
-        let users_service = Box::new(Mutex::new(users_service));
-        let sessions_service = Box::new(Mutex::new(SessionsImpl::default()));
+/// Describes the calling client using the peer address and gRPC request metadata.
+fn client_metadata<T>(request: &Request<T>) -> ClientMetadata {
+    ClientMetadata {
+        ip_address: request
+            .remote_addr()
+            .map(|addr| addr.ip().to_string())
+            .unwrap_or_default(),
+        user_agent: request
+            .metadata()
+            .get("user-agent")
+            .and_then(|user_agent| user_agent.to_str().ok())
+            .unwrap_or_default()
+            .to_owned(),
+    }
+}
 
-        let auth_service = AuthService::new(users_service, sessions_service);
+/// Tells the client the account is locked and when to try again, rounded up to whole seconds.
+fn account_locked(locked_for: Duration) -> SignInResponse {
+    SignInResponse {
+        status_code: StatusCode::AccountLocked.into(),
+        retry_after: locked_for.as_secs() + u64::from(locked_for.subsec_nanos() > 0),
+        ..Default::default()
+    }
+}
 
-        let request = tonic::Request::new(SignInRequest {
-            username: "123456".to_owned(),
-            password: "654321".to_owned(),
-        });
+/// Wraps a failed v1 reply, noting why it failed for v2 to report. The reason isn't sent to v1
+/// clients, whose replies only have a status code.
+fn failed_because<T>(reply: T, error_reason: ErrorReason) -> Response<T> {
+    let mut response = Response::new(reply);
+    response.extensions_mut().insert(error_reason);
+    response
+}
 
-        let result = auth_service.sign_in(request).await.unwrap().into_inner();
+/// The reply to sign ins refused by the maintenance mode.
+fn under_maintenance() -> Response<SignInResponse> {
+    let reply = SignInResponse {
+        status_code: StatusCode::Maintenance.into(),
+        ..Default::default()
+    };
+    failed_because(reply, ErrorReason::Maintenance)
+}
 
-        assert_eq!(result.status_code, StatusCode::Success.into());
-        assert_eq!(result.user_uuid.is_empty(), false);
-        assert_eq!(result.session_token.is_empty(), false);
+fn too_many_sign_ins(retry_after: Duration) -> Status {
+    rate_limited("Too many sign in attempts, try again later", retry_after)
+}
+
+fn password_violation_info(violation: PasswordViolation) -> PasswordViolationInfo {
+    let violation_type = match violation {
+        PasswordViolation::TooShort { .. } => PasswordViolationType::TooShort,
+        PasswordViolation::TooLong { .. } => PasswordViolationType::TooLong,
+        PasswordViolation::MissingLowercase => PasswordViolationType::MissingLowercase,
+        PasswordViolation::MissingUppercase => PasswordViolationType::MissingUppercase,
+        PasswordViolation::MissingDigit => PasswordViolationType::MissingDigit,
+        PasswordViolation::MissingSymbol => PasswordViolationType::MissingSymbol,
+        PasswordViolation::Common => PasswordViolationType::CommonPassword,
+        PasswordViolation::Breached => PasswordViolationType::BreachedPassword,
+    };
+
+    PasswordViolationInfo {
+        r#type: violation_type.into(),
+        message: violation.to_string(),
     }
+}
 
-    #[tokio::test]
-    async fn sign_up_should_fail_if_username_exists() {
-        let mut users_service = UsersImpl::default();
+/// Converts `time` to seconds since the Unix epoch, as used throughout the proto.
+pub fn unix_timestamp(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or_default()
+}
 
-        let _ = users_service.create_user("123456".to_owned(), "654321".to_owned());
+#[tonic::async_trait]
+impl Auth for AuthService {
+    async fn sign_in(
+        &self,
+        request: Request<SignInRequest>,
+    ) -> Result<Response<SignInResponse>, Status> {
+        log_request(&request);
+        validate(&request)?;
 
-        let users_service = Box::new(Mutex::new(users_service));
-        let sessions_service = Box::new(Mutex::new(SessionsImpl::default()));
+        if self.maintenance().refuses_sign_ins() {
+            return Ok(under_maintenance());
+        }
 
-        let auth_service = AuthService::new(users_service, sessions_service);
+        let client = client_metadata(&request);
+        let req = request.into_inner();
 
-        let request = tonic::Request::new(SignUpRequest {
-            username: "123456".to_owned(),
-            password: "654321".to_owned(),
-        });
+        // Usernames are throttled before anything else so guessing is slow even for accounts
+        // that can't be locked, e.g. unknown ones. Emails are matched ignoring case, so they
+        // share a bucket too.
+        if let Some(sign_in_rate_limiter) = &self.sign_in_rate_limiter {
+            let checked = sign_in_rate_limiter
+                .lock()
+                .await
+                .check(&req.username.to_lowercase());
 
-        let result = auth_service.sign_up(request).await.unwrap();
+            if let Err(retry_after) = checked {
+                return Err(too_many_sign_ins(retry_after));
+            }
+        }
+
+        let sigin = self.password_sign_in(&req, client.clone()).await?;
+
+        // Record the attempt in the user's login history. Unknown usernames aren't recorded.
+        let user_uuid = match sigin.user_uuid.is_empty() {
+            true => {
+                self.users_service
+                    .read()
+                    .await
+                    .lookup_user_uuid(req.username.clone())
+                    .await
+            }
+            false => Some(sigin.user_uuid.clone()),
+        };
+
+        // Users are only looked up by username, so a failed sign-in with an unknown email can't
+        // be told apart from a wrong password
+        let error_reason = match sigin.status_code() {
+            _ if self.anti_enumeration => None,
+            StatusCode::Failure if user_uuid.is_some() => Some(ErrorReason::WrongPassword),
+            StatusCode::Failure if !req.username.contains('@') => Some(ErrorReason::UserNotFound),
+            _ => None,
+        };
+
+        if let Some(user_uuid) = user_uuid {
+            let success = sigin.status_code == i32::from(StatusCode::Success);
+            self.record_sign_in(&user_uuid, client, success).await;
+        }
 
-        assert_eq!(result.into_inner().status_code, StatusCode::Failure.into());
+        // Backend errors behind a failure have been recorded already, so they take precedence
+        match sigin.status_code() {
+            StatusCode::Failure => record_failure(FailureReason::BadCredentials),
+            StatusCode::AccountLocked => record_failure(FailureReason::RateLimited),
+            _ => (),
+        }
+
+        tracing::info!(
+            user_uuid = sigin.user_uuid,
+            status = sigin.status_code().as_str_name(),
+            "Handled sign in"
+        );
+
+        Ok(match error_reason {
+            Some(error_reason) => failed_because(sigin, error_reason),
+            None => Response::new(sigin),
+        })
     }
 
-    #[tokio::test]
-    async fn sign_up_should_succeed() {
-        let users_service = Box::new(Mutex::new(UsersImpl::default()));
-        let sessions_service = Box::new(Mutex::new(SessionsImpl::default()));
+    async fn sign_up(
+        &self,
+        request: Request<SignUpRequest>,
+    ) -> Result<Response<SignUpResponse>, Status> {
+        log_request(&request);
+        validate(&request)?;
 
-        let auth_service = AuthService::new(users_service, sessions_service);
+        if self.maintenance().refuses_sign_ups() {
+            let reply = SignUpResponse {
+                status_code: StatusCode::Maintenance.into(),
+                ..Default::default()
+            };
+            return Ok(failed_because(reply, ErrorReason::Maintenance));
+        }
 
-        let request = tonic::Request::new(SignUpRequest {
-            username: "123456".to_owned(),
-            password: "654321".to_owned(),
-        });
+        let req = request.into_inner();
 
-        let result = auth_service.sign_up(request).await.unwrap();
+        // Retries with the idempotency key of a request get its result instead of being handled
+        // again, which would fail as the username is taken by then.
+        let (reply, error_reason, failure) = match req.idempotency_key.is_empty() {
+            true => self.create_account(req).await,
+            false => {
+                let key = (req.username.clone(), req.idempotency_key.clone());
+                self.sign_up_results
+                    .run(key, self.create_account(req))
+                    .await
+            }
+        };
 
-        assert_eq!(result.into_inner().status_code, StatusCode::Success.into());
+        if let Some(failure) = failure {
+            record_failure(failure);
+        }
+        Ok(match error_reason {
+            Some(error_reason) => failed_because(reply, error_reason),
+            None => Response::new(reply),
+        })
     }
 
-    #[tokio::test]
-    async fn sign_out_should_succeed() {
-        let users_service = Box::new(Mutex::new(UsersImpl::default()));
-        let sessions_service = Box::new(Mutex::new(SessionsImpl::default()));
+    async fn sign_out(
+        &self,
+        request: Request<SignOutRequest>,
+    ) -> Result<Response<SignOutResponse>, Status> {
+        log_request(&request);
+        validate(&request)?;
 
-        let auth_service = AuthService::new(users_service, sessions_service);
+        let req = request.into_inner();
 
-        let request = tonic::Request::new(SignOutRequest {
-            session_token: "".to_owned(),
-        });
+        self.sessions_service
+            .write()
+            .await
+            .delete_session(&req.session_token)
+            .await;
 
-        let result = auth_service.sign_out(request).await.unwrap();
+        // Create `SignOutResponse` with `status_code` set to `Success`
+        let reply: SignOutResponse = SignOutResponse {
+            status_code: StatusCode::Success.into(),
+        };
+        Ok(Response::new(reply))
+    }
+
+    async fn validate_session(
+        &self,
+        request: Request<ValidateSessionRequest>,
+    ) -> Result<Response<ValidateSessionResponse>, Status> {
+        log_request(&request);
+        validate(&request)?;
+
+        let req = request.into_inner();
+
+        // Look up the session using `sessions_service`.
+        let session = self
+            .sessions_service
+            .read()
+            .await
+            .get_session(&req.session_token)
+            .await;
+
+        let reply = match session {
+            Some(session) => ValidateSessionResponse {
+                status_code: StatusCode::Success.into(),
+                user_uuid: session.user_uuid.into(),
+                expires_at: unix_timestamp(session.expires_at),
+            },
+            None => {
+                record_failure(FailureReason::BadCredentials);
+                let reply = ValidateSessionResponse {
+                    status_code: StatusCode::Failure.into(),
+                    user_uuid: "".to_owned(),
+                    expires_at: 0,
+                };
+                return Ok(failed_because(reply, ErrorReason::SessionExpired));
+            }
+        };
+
+        Ok(Response::new(reply))
+    }
+
+    async fn refresh_session(
+        &self,
+        request: Request<RefreshSessionRequest>,
+    ) -> Result<Response<RefreshSessionResponse>, Status> {
+        log_request(&request);
+        validate(&request)?;
+
+        let req = request.into_inner();
+
+        // Exchange the refresh token using `sessions_service`.
+        let session_token = self
+            .sessions_service
+            .write()
+            .await
+            .refresh_session(&req.refresh_token)
+            .await;
+
+        let reply = match session_token {
+            Some(session_token) => RefreshSessionResponse {
+                status_code: StatusCode::Success.into(),
+                session_token,
+            },
+            None => {
+                record_failure(FailureReason::BadCredentials);
+                let reply = RefreshSessionResponse {
+                    status_code: StatusCode::Failure.into(),
+                    session_token: "".to_owned(),
+                };
+                return Ok(failed_because(reply, ErrorReason::SessionExpired));
+            }
+        };
+
+        Ok(Response::new(reply))
+    }
+
+    async fn request_password_reset(
+        &self,
+        request: Request<RequestPasswordResetRequest>,
+    ) -> Result<Response<RequestPasswordResetResponse>, Status> {
+        log_request(&request);
+        validate(&request)?;
+
+        let req = request.into_inner();
+
+        // Get user's uuid from `users_service`.
+        let user_uuid = self
+            .users_service
+            .read()
+            .await
+            .lookup_user_uuid(req.username)
+            .await;
+
+        // Unknown usernames get the same response so the RPC can't be used to probe for accounts.
+        if let Some(user_uuid) = user_uuid {
+            let reset_token = self
+                .password_resets_service
+                .lock()
+                .await
+                .create_reset_token(&user_uuid);
+
+            self.mailer
+                .send_password_reset(&user_uuid, &Secret::from(reset_token));
+        }
+
+        let reply = RequestPasswordResetResponse {
+            status_code: StatusCode::Success.into(),
+        };
+        Ok(Response::new(reply))
+    }
+
+    async fn confirm_password_reset(
+        &self,
+        request: Request<ConfirmPasswordResetRequest>,
+    ) -> Result<Response<ConfirmPasswordResetResponse>, Status> {
+        log_request(&request);
+        validate(&request)?;
+
+        let req = request.into_inner();
+
+        // Check the password before redeeming the token, so the user can retry with a better one.
+        if let Err(password_violations) = self.check_password(&req.new_password).await {
+            let reply = ConfirmPasswordResetResponse {
+                status_code: StatusCode::WeakPassword.into(),
+                password_violations,
+            };
+            return Ok(Response::new(reply));
+        }
+
+        let hashed_password = self.hash_password(&req.new_password).await;
+
+        // The token stays locked until the password is stored, so it can't be used twice, and is
+        // only redeemed once it is, so it can be used again if storing fails.
+        let mut password_resets_service = self.password_resets_service.lock().await;
+
+        let reset_token_user = password_resets_service.reset_token_user(&req.reset_token);
+        let updated = match (reset_token_user, hashed_password) {
+            (Some(user_uuid), Ok(hashed_password)) => self
+                .users_service
+                .write()
+                .await
+                .update_password_hashed(user_uuid, hashed_password)
+                .await
+                .is_ok(),
+            _ => false,
+        };
+        if updated {
+            password_resets_service.redeem_reset_token(&req.reset_token);
+        }
+        drop(password_resets_service);
+
+        let status_code = match updated {
+            true => StatusCode::Success,
+            false => StatusCode::Failure,
+        };
+
+        let reply = ConfirmPasswordResetResponse {
+            status_code: status_code.into(),
+            ..Default::default()
+        };
+        Ok(Response::new(reply))
+    }
+
+    async fn delete_account(
+        &self,
+        request: Request<DeleteAccountRequest>,
+    ) -> Result<Response<DeleteAccountResponse>, Status> {
+        log_request(&request);
+        validate(&request)?;
+
+        let user_uuid = caller(&request)?.user_uuid;
+
+        // Sign the user out everywhere before removing the account.
+        self.sessions_service
+            .write()
+            .await
+            .delete_user_sessions(&user_uuid)
+            .await;
+
+        // Keep the account restorable until `purge_deleted_users` removes it for good.
+        let status_code = match self
+            .users_service
+            .write()
+            .await
+            .soft_delete_user(user_uuid)
+            .await
+        {
+            Ok(_) => StatusCode::Success,
+            Err(_) => StatusCode::Failure,
+        };
+
+        let reply = DeleteAccountResponse {
+            status_code: status_code.into(),
+        };
+        Ok(Response::new(reply))
+    }
+
+    async fn get_profile(
+        &self,
+        request: Request<GetProfileRequest>,
+    ) -> Result<Response<GetProfileResponse>, Status> {
+        log_request(&request);
+        validate(&request)?;
+
+        let user_uuid = caller(&request)?.user_uuid;
+
+        // Get the signed in user from `users_service`.
+        let user = self.users_service.read().await.get_user(user_uuid).await;
+
+        let reply = match user {
+            Some(user) => GetProfileResponse {
+                status_code: StatusCode::Success.into(),
+                user_uuid: user.user_uuid.clone(),
+                username: user.username.clone(),
+                display_name: user.display_name.clone(),
+                email: user.email.clone(),
+                created_at: unix_timestamp(user.created_at),
+            },
+            None => GetProfileResponse {
+                status_code: StatusCode::Failure.into(),
+                ..Default::default()
+            },
+        };
+
+        Ok(Response::new(reply))
+    }
+
+    async fn update_profile(
+        &self,
+        request: Request<UpdateProfileRequest>,
+    ) -> Result<Response<UpdateProfileResponse>, Status> {
+        log_request(&request);
+        validate(&request)?;
+
+        let user_uuid = caller(&request)?.user_uuid;
+        let req = request.into_inner();
+
+        // Update the signed in user through `users_service`.
+        let result = self
+            .users_service
+            .write()
+            .await
+            .update_user(user_uuid, req.display_name, req.email)
+            .await;
+
+        let status_code = match result {
+            Ok(_) => StatusCode::Success,
+            // Backend errors were recorded already, otherwise the email is taken
+            Err(_) => {
+                record_failure(FailureReason::InvalidRequest);
+                StatusCode::Failure
+            }
+        };
+
+        let reply = UpdateProfileResponse {
+            status_code: status_code.into(),
+        };
+        Ok(Response::new(reply))
+    }
+
+    async fn list_active_sessions(
+        &self,
+        request: Request<ListActiveSessionsRequest>,
+    ) -> Result<Response<ListActiveSessionsResponse>, Status> {
+        log_request(&request);
+        validate(&request)?;
+
+        let session_token = caller(&request)?.session_token;
+
+        // List the signed in user's sessions using `sessions_service`.
+        let sessions_service = self.sessions_service.read().await;
+
+        let current = match sessions_service.get_session(session_token.expose()).await {
+            Some(session) => session,
+            None => {
+                record_failure(FailureReason::BadCredentials);
+                let reply = ListActiveSessionsResponse {
+                    status_code: StatusCode::Failure.into(),
+                    sessions: vec![],
+                };
+                return Ok(Response::new(reply));
+            }
+        };
+
+        let sessions = sessions_service
+            .list_user_sessions(&current.user_uuid)
+            .await?
+            .into_iter()
+            .map(|session| SessionInfo {
+                current: session.session_id == current.session_id,
+                session_id: session.session_id.to_string(),
+                created_at: unix_timestamp(session.created_at),
+                last_seen: unix_timestamp(session.last_seen),
+                expires_at: unix_timestamp(session.expires_at),
+                ip_address: session.client.ip_address,
+                user_agent: session.client.user_agent,
+            })
+            .collect();
+
+        let reply = ListActiveSessionsResponse {
+            status_code: StatusCode::Success.into(),
+            sessions,
+        };
+        Ok(Response::new(reply))
+    }
+
+    async fn enroll_totp(
+        &self,
+        request: Request<EnrollTotpRequest>,
+    ) -> Result<Response<EnrollTotpResponse>, Status> {
+        log_request(&request);
+        validate(&request)?;
+
+        let user_uuid = caller(&request)?.user_uuid;
+
+        // Get the signed in user from `users_service`.
+        let user = self.users_service.read().await.get_user(user_uuid).await;
+
+        let user = match user {
+            Some(user) => user,
+            None => {
+                let reply = EnrollTotpResponse {
+                    status_code: StatusCode::Failure.into(),
+                    ..Default::default()
+                };
+                return Ok(Response::new(reply));
+            }
+        };
+
+        // Generate a new secret using `mfa_service`.
+        let secret = self.mfa_service.lock().await.enroll_totp(&user.user_uuid);
+
+        let reply = EnrollTotpResponse {
+            status_code: StatusCode::Success.into(),
+            provisioning_uri: provisioning_uri(&user.username, &secret),
+            secret,
+        };
+        Ok(Response::new(reply))
+    }
+
+    async fn confirm_totp(
+        &self,
+        request: Request<ConfirmTotpRequest>,
+    ) -> Result<Response<ConfirmTotpResponse>, Status> {
+        log_request(&request);
+        validate(&request)?;
+
+        let user_uuid = caller(&request)?.user_uuid;
+        let req = request.into_inner();
+
+        // Enable the pending secret using `mfa_service`.
+        let confirmed = self
+            .mfa_service
+            .lock()
+            .await
+            .confirm_totp(&user_uuid, &req.code);
+
+        let status_code = match confirmed {
+            true => StatusCode::Success,
+            false => StatusCode::Failure,
+        };
+
+        let reply = ConfirmTotpResponse {
+            status_code: status_code.into(),
+        };
+        Ok(Response::new(reply))
+    }
+
+    async fn sign_in_with_id_token(
+        &self,
+        request: Request<SignInWithIdTokenRequest>,
+    ) -> Result<Response<SignInResponse>, Status> {
+        log_request(&request);
+        validate(&request)?;
+
+        let maintenance = self.maintenance();
+        if maintenance.refuses_sign_ins() {
+            return Ok(under_maintenance());
+        }
+
+        let client = client_metadata(&request);
+        let req = request.into_inner();
+
+        let failure = SignInResponse {
+            status_code: StatusCode::Failure.into(),
+            ..Default::default()
+        };
+
+        let id_token_verifier = match &self.id_token_verifier {
+            Some(id_token_verifier) => id_token_verifier,
+            None => return Ok(Response::new(failure)),
+        };
+
+        let claims = match id_token_verifier.verify(&req.provider, &req.id_token).await {
+            Ok(claims) => claims,
+            Err(e) => {
+                tracing::warn!(provider = req.provider, error = %e, "Rejected ID token");
+                record_failure(FailureReason::BadCredentials);
+                return Ok(Response::new(failure));
+            }
+        };
+
+        // Find the linked local user, creating one on first sign in.
+        let mut users_service = self.users_service.write().await;
+
+        let user_uuid = match users_service
+            .get_federated_user_uuid(&claims.issuer, &claims.subject)
+            .await
+        {
+            Some(user_uuid) => Ok(user_uuid),
+            // Provisioning a user is a sign up.
+            None if maintenance.refuses_sign_ups() => return Ok(under_maintenance()),
+            None => {
+                let username = federated_username(&*users_service, &req.provider, &claims).await;
+                users_service
+                    .create_federated_user(&claims.issuer, &claims.subject, username)
+                    .await
+            }
+        };
+        drop(users_service);
+
+        let user_uuid = match user_uuid {
+            Ok(user_uuid) => user_uuid,
+            Err(_) => return Ok(Response::new(failure)),
+        };
+
+        self.record_sign_in(&user_uuid, client.clone(), true).await;
+
+        Ok(Response::new(self.start_session(user_uuid, client).await?))
+    }
+
+    async fn get_sign_up_challenge(
+        &self,
+        request: Request<GetSignUpChallengeRequest>,
+    ) -> Result<Response<GetSignUpChallengeResponse>, Status> {
+        log_request(&request);
+        validate(&request)?;
+
+        // Without a configured challenge there is nothing to solve.
+        let sign_up_challenge = match &self.sign_up_challenge {
+            Some(sign_up_challenge) => sign_up_challenge,
+            None => {
+                let reply = GetSignUpChallengeResponse {
+                    status_code: StatusCode::Failure.into(),
+                    ..Default::default()
+                };
+                return Ok(Response::new(reply));
+            }
+        };
+
+        // Verifiers whose challenges are issued elsewhere have none to hand out.
+        let challenge = sign_up_challenge.issue().await.ok_or_else(|| {
+            Status::unavailable("Error, the sign up challenge is issued by another service")
+        })?;
+
+        let reply = GetSignUpChallengeResponse {
+            status_code: StatusCode::Success.into(),
+            challenge_id: challenge.challenge_id,
+            difficulty: challenge.difficulty,
+        };
+
+        Ok(Response::new(reply))
+    }
+
+    async fn get_login_history(
+        &self,
+        request: Request<GetLoginHistoryRequest>,
+    ) -> Result<Response<GetLoginHistoryResponse>, Status> {
+        log_request(&request);
+        validate(&request)?;
+
+        let user_uuid = caller(&request)?.user_uuid;
+        let req = request.into_inner();
+
+        let failure = GetLoginHistoryResponse {
+            status_code: StatusCode::Failure.into(),
+            ..Default::default()
+        };
+
+        // The page token is the number of attempts already returned.
+        let offset: usize = match req.page_token.as_str() {
+            "" => 0,
+            page_token => match page_token.parse() {
+                Ok(offset) => offset,
+                Err(_) => {
+                    record_failure(FailureReason::InvalidRequest);
+                    return Ok(Response::new(failure));
+                }
+            },
+        };
+
+        let page_size = match req.page_size {
+            0 => DEFAULT_LOGIN_HISTORY_PAGE_SIZE,
+            page_size => (page_size as usize).min(MAX_LOGIN_HISTORY_PAGE_SIZE),
+        };
+
+        // Ask for one extra attempt to find out whether there is another page.
+        let mut attempts = self.login_history_service.lock().await.list_attempts(
+            &user_uuid,
+            offset,
+            page_size + 1,
+        );
+
+        let next_page_token = match attempts.len() > page_size {
+            true => {
+                attempts.truncate(page_size);
+                (offset + page_size).to_string()
+            }
+            false => "".to_owned(),
+        };
+
+        let attempts = attempts
+            .into_iter()
+            .map(|attempt| LoginAttemptInfo {
+                timestamp: unix_timestamp(attempt.timestamp),
+                success: attempt.success,
+                ip_address: attempt.client.ip_address,
+                user_agent: attempt.client.user_agent,
+            })
+            .collect();
+
+        let reply = GetLoginHistoryResponse {
+            status_code: StatusCode::Success.into(),
+            attempts,
+            next_page_token,
+        };
+
+        Ok(Response::new(reply))
+    }
+
+    async fn request_magic_link(
+        &self,
+        request: Request<RequestMagicLinkRequest>,
+    ) -> Result<Response<RequestMagicLinkResponse>, Status> {
+        log_request(&request);
+        validate(&request)?;
+
+        let req = request.into_inner();
+
+        // Get user's uuid from `users_service`.
+        let user_uuid = self
+            .users_service
+            .read()
+            .await
+            .lookup_user_uuid(req.username)
+            .await;
+
+        // Unknown usernames get the same response so the RPC can't be used to probe for accounts.
+        if let Some(user_uuid) = user_uuid {
+            let token = self
+                .magic_links_service
+                .lock()
+                .await
+                .create_magic_link(&user_uuid);
+
+            self.mailer
+                .send_magic_link(&user_uuid, &Secret::from(token));
+        }
+
+        let reply = RequestMagicLinkResponse {
+            status_code: StatusCode::Success.into(),
+        };
+        Ok(Response::new(reply))
+    }
+
+    async fn redeem_magic_link(
+        &self,
+        request: Request<RedeemMagicLinkRequest>,
+    ) -> Result<Response<SignInResponse>, Status> {
+        log_request(&request);
+        validate(&request)?;
+
+        // The link isn't used up, so it works once maintenance is over.
+        if self.maintenance().refuses_sign_ins() {
+            return Ok(under_maintenance());
+        }
+
+        let client = client_metadata(&request);
+        let req = request.into_inner();
+
+        let mut magic_links_service = self.magic_links_service.lock().await;
+
+        let user_uuid = match magic_links_service.magic_link_user(&req.token) {
+            Some(user_uuid) => user_uuid,
+            None => {
+                record_failure(FailureReason::BadCredentials);
+                let reply = SignInResponse {
+                    status_code: StatusCode::Failure.into(),
+                    ..Default::default()
+                };
+                return Ok(Response::new(reply));
+            }
+        };
+
+        // Users with TOTP enabled also need a valid code.
+        let mut mfa_service = self.mfa_service.lock().await;
+
+        // Keep the link valid while the client prompts for a code.
+        if mfa_service.is_totp_enabled(&user_uuid) && req.totp_code.is_empty() {
+            let reply = SignInResponse {
+                status_code: StatusCode::MfaRequired.into(),
+                ..Default::default()
+            };
+            return Ok(Response::new(reply));
+        }
+
+        // Any other attempt uses up the link, so codes can't be guessed with it.
+        magic_links_service.redeem_magic_link(&req.token);
+        drop(magic_links_service);
+
+        let verified = !mfa_service.is_totp_enabled(&user_uuid)
+            || mfa_service.verify_totp(&user_uuid, &req.totp_code);
+        drop(mfa_service);
+
+        self.record_sign_in(&user_uuid, client.clone(), verified)
+            .await;
+
+        let reply = match verified {
+            true => self.start_session(user_uuid, client).await?,
+            false => {
+                record_failure(FailureReason::BadCredentials);
+                SignInResponse {
+                    status_code: StatusCode::Failure.into(),
+                    ..Default::default()
+                }
+            }
+        };
+
+        Ok(Response::new(reply))
+    }
+
+    async fn renew_session(
+        &self,
+        request: Request<RenewSessionRequest>,
+    ) -> Result<Response<RenewSessionResponse>, Status> {
+        log_request(&request);
+        validate(&request)?;
+
+        let req = request.into_inner();
+
+        // Renew the session using `sessions_service`.
+        let mut sessions_service = self.sessions_service.write().await;
+
+        let session = match sessions_service.renew_session(&req.session_token).await {
+            Some(session_token) => sessions_service
+                .get_session(&session_token)
+                .await
+                .map(|session| (session_token, session)),
+            None => None,
+        };
+
+        let reply = match session {
+            Some((session_token, session)) => RenewSessionResponse {
+                status_code: StatusCode::Success.into(),
+                session_token,
+                expires_at: unix_timestamp(session.expires_at),
+            },
+            None => {
+                let reply = RenewSessionResponse {
+                    status_code: StatusCode::Failure.into(),
+                    ..Default::default()
+                };
+                return Ok(failed_because(reply, ErrorReason::SessionExpired));
+            }
+        };
+
+        Ok(Response::new(reply))
+    }
+
+    async fn introspect_token(
+        &self,
+        request: Request<IntrospectTokenRequest>,
+    ) -> Result<Response<IntrospectTokenResponse>, Status> {
+        log_request(&request);
+        validate(&request)?;
+
+        let req = request.into_inner();
+
+        // Look up the session without touching it.
+        let session = self
+            .sessions_service
+            .read()
+            .await
+            .peek_session(&req.token)
+            .await;
+
+        let reply = match session {
+            Some(session) => IntrospectTokenResponse {
+                status_code: StatusCode::Success.into(),
+                active: true,
+                groups: self
+                    .groups_service
+                    .lock()
+                    .await
+                    .user_groups(&session.user_uuid),
+                user_uuid: session.user_uuid.into(),
+                scopes: Vec::new(),
+                expires_at: unix_timestamp(session.expires_at),
+                issued_at: unix_timestamp(session.created_at),
+                token_type: SESSION_TOKEN_TYPE.to_owned(),
+            },
+            // Inactive tokens aren't an error, there is just nothing to report about them.
+            None => IntrospectTokenResponse {
+                status_code: StatusCode::Success.into(),
+                active: false,
+                ..Default::default()
+            },
+        };
+
+        Ok(Response::new(reply))
+    }
+
+    async fn revoke_token(
+        &self,
+        request: Request<RevokeTokenRequest>,
+    ) -> Result<Response<RevokeTokenResponse>, Status> {
+        log_request(&request);
+        validate(&request)?;
+
+        let req = request.into_inner();
+
+        // Revoke the token using `sessions_service`.
+        self.sessions_service
+            .write()
+            .await
+            .delete_session(&req.token)
+            .await;
+
+        // Unknown tokens succeed too, so callers can't probe which tokens exist.
+        let reply = RevokeTokenResponse {
+            status_code: StatusCode::Success.into(),
+        };
+
+        Ok(Response::new(reply))
+    }
+
+    async fn set_user_attribute(
+        &self,
+        request: Request<SetUserAttributeRequest>,
+    ) -> Result<Response<SetUserAttributeResponse>, Status> {
+        log_request(&request);
+        validate(&request)?;
+
+        let user_uuid = caller(&request)?.user_uuid;
+        let req = request.into_inner();
+
+        // Set the attribute on the signed in user through `users_service`.
+        let result = self
+            .users_service
+            .write()
+            .await
+            .set_user_attribute(user_uuid, req.key, req.value)
+            .await;
+
+        let status_code = match result {
+            Ok(_) => StatusCode::Success,
+            // Backend errors were recorded already, otherwise the attribute limits would be exceeded
+            Err(_) => {
+                record_failure(FailureReason::InvalidRequest);
+                StatusCode::Failure
+            }
+        };
+
+        let reply = SetUserAttributeResponse {
+            status_code: status_code.into(),
+        };
+        Ok(Response::new(reply))
+    }
+
+    async fn get_user_attributes(
+        &self,
+        request: Request<GetUserAttributesRequest>,
+    ) -> Result<Response<GetUserAttributesResponse>, Status> {
+        log_request(&request);
+        validate(&request)?;
+
+        let user_uuid = caller(&request)?.user_uuid;
+
+        // Get the signed in user from `users_service`.
+        let user = self.users_service.read().await.get_user(user_uuid).await;
+
+        let reply = match user {
+            Some(user) => GetUserAttributesResponse {
+                status_code: StatusCode::Success.into(),
+                attributes: user.attributes.clone().into_iter().collect(),
+            },
+            None => GetUserAttributesResponse {
+                status_code: StatusCode::Failure.into(),
+                ..Default::default()
+            },
+        };
+
+        Ok(Response::new(reply))
+    }
+}
+
+/// Picks a username for a user provisioned from an ID token, preferring the one the provider
+/// suggests and falling back to one derived from the token's subject.
+async fn federated_username(
+    users_service: &(dyn Users + Send + Sync),
+    provider: &str,
+    claims: &IdTokenClaims,
+) -> String {
+    for username in [&claims.preferred_username, &claims.email]
+        .into_iter()
+        .flatten()
+    {
+        if users_service
+            .lookup_user_uuid(username.to_string())
+            .await
+            .is_none()
+        {
+            return username.clone();
+        }
+    }
+
+    format!("{}:{}", provider, claims.subject)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::sync::Arc;
+
+    use crate::{
+        challenges::{solve, Challenge, ProofOfWork},
+        metrics::track_failure,
+        rate_limits::{RateLimit, TokenBucketLimiter},
+        sessions::{SessionsImpl, SESSION_EVENTS_CAPACITY},
+        users::{UsersImpl, MAX_ATTRIBUTE_KEY_LENGTH},
+    };
+
+    use super::*;
+
+    /// Accepts the ID token "valid" as belonging to `subject` of `issuer`.
+    struct TestIdTokenVerifier;
+
+    #[tonic::async_trait]
+    impl IdTokenVerifier for TestIdTokenVerifier {
+        async fn verify(&self, provider: &str, id_token: &str) -> Result<IdTokenClaims, String> {
+            match (provider, id_token) {
+                ("example", "valid") => Ok(IdTokenClaims {
+                    issuer: "issuer".to_owned(),
+                    subject: "subject".to_owned(),
+                    preferred_username: Some("123456".to_owned()),
+                    ..Default::default()
+                }),
+                _ => Err("Error, invalid ID token".to_owned()),
+            }
+        }
+    }
+
+    /// Verifies challenges issued elsewhere, like a CAPTCHA widget.
+    struct TestCaptcha;
+
+    #[tonic::async_trait]
+    impl SignUpChallenge for TestCaptcha {
+        async fn issue(&self) -> Option<Challenge> {
+            None
+        }
+
+        async fn verify(&self, _challenge_id: &str, solution: &str) -> bool {
+            solution == "valid"
+        }
+    }
+
+    /// Keeps delivered reset tokens and magic links so tests can redeem them.
+    #[derive(Clone, Default)]
+    struct TestMailer {
+        reset_tokens: Arc<parking_lot::Mutex<Vec<String>>>,
+        magic_link_tokens: Arc<parking_lot::Mutex<Vec<String>>>,
+        sign_up_attempts: Arc<parking_lot::Mutex<Vec<String>>>,
+    }
+
+    impl Mailer for TestMailer {
+        fn send_password_reset(&self, _user_uuid: &str, reset_token: &Secret) {
+            self.reset_tokens
+                .lock()
+                .push(reset_token.expose().to_owned());
+        }
+
+        fn send_magic_link(&self, _user_uuid: &str, token: &Secret) {
+            self.magic_link_tokens
+                .lock()
+                .push(token.expose().to_owned());
+        }
+
+        fn send_sign_up_attempt(&self, user_uuid: &str) {
+            self.sign_up_attempts.lock().push(user_uuid.to_owned());
+        }
+    }
+
+    /// Wraps `message` in a request authenticated with `session_token` like `SessionAuthLayer`
+    /// does, or unauthenticated if the session isn't valid.
+    async fn authenticated<T>(
+        auth_service: &AuthService,
+        session_token: &str,
+        message: T,
+    ) -> tonic::Request<T> {
+        let mut request = tonic::Request::new(message);
+        if let Some(user_uuid) = auth_service.session_user_uuid(session_token).await {
+            request.extensions_mut().insert(AuthenticatedUser {
+                user_uuid,
+                session_token: session_token.into(),
+            });
+        }
+        request
+    }
+
+    #[tokio::test]
+    async fn sign_in_should_fail_if_user_not_found() {
+        let users_service = Box::new(RwLock::new(UsersImpl::default()));
+        let sessions_service = Box::new(RwLock::new(SessionsImpl::default()));
+
+        let auth_service = AuthService::new(users_service, sessions_service);
+
+        let request = tonic::Request::new(SignInRequest {
+            username: "123456".to_owned(),
+            password: "654321".to_owned(),
+            ..Default::default()
+        });
+
+        let result = auth_service.sign_in(request).await.unwrap();
+        assert_eq!(
+            result.extensions().get::<ErrorReason>(),
+            Some(&ErrorReason::UserNotFound)
+        );
+        let result = result.into_inner();
+
+        assert_eq!(result.status_code, StatusCode::Failure as i32);
+        assert!(result.user_uuid.is_empty());
+        assert!(result.session_token.is_empty());
+    }
+
+    #[tokio::test]
+    async fn sign_in_should_fail_if_incorrect_password() {
+        let mut users_service = UsersImpl::default();
+
+        let _ = users_service
+            .create_user("123456".to_owned(), "654321".to_owned(), String::new())
+            .await;
+
+        let users_service = Box::new(RwLock::new(users_service));
+        let sessions_service = Box::new(RwLock::new(SessionsImpl::default()));
+
+        let auth_service = AuthService::new(users_service, sessions_service);
+
+        let request = tonic::Request::new(SignInRequest {
+            username: "123456".to_owned(),
+            password: "wrong password".to_owned(),
+            ..Default::default()
+        });
+
+        let (result, failure) = track_failure(auth_service.sign_in(request)).await;
+        let result = result.unwrap();
+        assert_eq!(
+            result.extensions().get::<ErrorReason>(),
+            Some(&ErrorReason::WrongPassword)
+        );
+        let result = result.into_inner();
+
+        assert_eq!(result.status_code, StatusCode::Failure as i32);
+        assert!(result.user_uuid.is_empty());
+        assert!(result.session_token.is_empty());
+        // Users' typos aren't counted as backend errors.
+        assert_eq!(failure, Some(FailureReason::BadCredentials));
+    }
+
+    #[tokio::test]
+    async fn sign_in_should_not_tell_unknown_users_apart_with_anti_enumeration() {
+        let mut users_service = UsersImpl::default();
+
+        let _ = users_service
+            .create_user("123456".to_owned(), "654321".to_owned(), String::new())
+            .await;
+
+        let users_service = Box::new(RwLock::new(users_service));
+        let sessions_service = Box::new(RwLock::new(SessionsImpl::default()));
+
+        let auth_service =
+            AuthService::new(users_service, sessions_service).with_anti_enumeration();
+
+        let mut replies = Vec::new();
+        for username in ["123456", "unknown"] {
+            let request = tonic::Request::new(SignInRequest {
+                username: username.to_owned(),
+                password: "wrong password".to_owned(),
+                ..Default::default()
+            });
+
+            let result = auth_service.sign_in(request).await.unwrap();
+            assert_eq!(result.extensions().get::<ErrorReason>(), None);
+            replies.push(result.into_inner());
+        }
+
+        assert_eq!(replies[0], replies[1]);
+        assert_eq!(replies[0].status_code, StatusCode::Failure as i32);
+    }
+
+    #[tokio::test]
+    async fn sign_in_should_succeed() {
+        let mut users_service = UsersImpl::default();
+
+        let _ = users_service
+            .create_user("123456".to_owned(), "654321".to_owned(), String::new())
+            .await;
+
+        let users_service = Box::new(RwLock::new(users_service));
+        let sessions_service = Box::new(RwLock::new(SessionsImpl::default()));
+
+        let auth_service = AuthService::new(users_service, sessions_service);
+
+        let request = tonic::Request::new(SignInRequest {
+            username: "123456".to_owned(),
+            password: "654321".to_owned(),
+            ..Default::default()
+        });
+
+        let result = auth_service.sign_in(request).await.unwrap().into_inner();
+
+        assert_eq!(result.status_code, StatusCode::Success as i32);
+        assert!(!result.user_uuid.is_empty());
+        assert!(!result.session_token.is_empty());
+        assert!(!result.refresh_token.is_empty());
+    }
+
+    #[tokio::test]
+    async fn maintenance_should_refuse_sign_ups_and_sign_ins_but_not_validation() {
+        let users_service = Box::new(RwLock::new(UsersImpl::default()));
+        let sessions_service = Box::new(RwLock::new(SessionsImpl::default()));
+
+        let auth_service = AuthService::new(users_service, sessions_service);
+
+        let sign_up = |username: &str| {
+            tonic::Request::new(SignUpRequest {
+                username: username.to_owned(),
+                password: "654321".to_owned(),
+                ..Default::default()
+            })
+        };
+        let sign_in = || {
+            tonic::Request::new(SignInRequest {
+                username: "123456".to_owned(),
+                password: "654321".to_owned(),
+                ..Default::default()
+            })
+        };
+        auth_service.sign_up(sign_up("123456")).await.unwrap();
+        let session_token = auth_service
+            .sign_in(sign_in())
+            .await
+            .unwrap()
+            .into_inner()
+            .session_token;
+
+        assert_eq!(
+            auth_service.set_maintenance(Maintenance::SignUp),
+            Maintenance::Off
+        );
+        let result = auth_service.sign_up(sign_up("234567")).await.unwrap();
+        assert_eq!(result.get_ref().status_code(), StatusCode::Maintenance);
+        assert_eq!(
+            result.extensions().get::<ErrorReason>(),
+            Some(&ErrorReason::Maintenance)
+        );
+        // Malformed requests are still rejected as such
+        let status = auth_service.sign_up(sign_up("")).await.unwrap_err();
+        assert_eq!(status.code(), tonic::Code::InvalidArgument);
+        let result = auth_service.sign_in(sign_in()).await.unwrap();
+        assert_eq!(result.get_ref().status_code(), StatusCode::Success);
+
+        auth_service.set_maintenance(Maintenance::SignIn);
+        let result = auth_service.sign_in(sign_in()).await.unwrap();
+        assert_eq!(result.get_ref().status_code(), StatusCode::Maintenance);
+        let request = tonic::Request::new(ValidateSessionRequest { session_token });
+        let result = auth_service.validate_session(request).await.unwrap();
+        assert_eq!(result.get_ref().status_code(), StatusCode::Success);
+
+        auth_service.set_maintenance(Maintenance::Off);
+        let result = auth_service.sign_up(sign_up("234567")).await.unwrap();
+        assert_eq!(result.get_ref().status_code(), StatusCode::Success);
+    }
+
+    #[tokio::test]
+    async fn sign_in_should_upgrade_outdated_password_hash() {
+        use crate::password_hashing::Pbkdf2Hasher;
+
+        let mut users_service =
+            UsersImpl::default().with_password_hasher(Box::new(Pbkdf2Hasher::new(1000)));
+
+        let _ = users_service
+            .create_user("123456".to_owned(), "654321".to_owned(), String::new())
+            .await;
+
+        let users_service = users_service.with_password_hasher(Box::new(Pbkdf2Hasher::new(2000)));
+        let users_service = Box::new(RwLock::new(users_service));
+        let sessions_service = Box::new(RwLock::new(SessionsImpl::default()));
+
+        let auth_service = AuthService::new(users_service, sessions_service);
+
+        let request = tonic::Request::new(SignInRequest {
+            username: "123456".to_owned(),
+            password: "654321".to_owned(),
+            ..Default::default()
+        });
+
+        let result = auth_service.sign_in(request).await.unwrap().into_inner();
+
+        assert_eq!(result.status_code, StatusCode::Success as i32);
+
+        let user = auth_service
+            .users_service
+            .read()
+            .await
+            .get_user(result.user_uuid)
+            .await
+            .unwrap();
+        assert!(user.password_hash().starts_with("$pbkdf2-sha256$i=2000,"));
+    }
+
+    #[tokio::test]
+    async fn sign_in_should_accept_email() {
+        let users_service = Box::new(RwLock::new(UsersImpl::default()));
+        let sessions_service = Box::new(RwLock::new(SessionsImpl::default()));
+
+        let auth_service = AuthService::new(users_service, sessions_service);
+
+        let request = tonic::Request::new(SignUpRequest {
+            username: "123456".to_owned(),
+            password: "654321".to_owned(),
+            email: "user@example.com".to_owned(),
+            ..Default::default()
+        });
+
+        let result = auth_service.sign_up(request).await.unwrap();
+
+        assert_eq!(result.into_inner().status_code, StatusCode::Success as i32);
+
+        let request = tonic::Request::new(SignInRequest {
+            username: "user@example.com".to_owned(),
+            password: "654321".to_owned(),
+            ..Default::default()
+        });
+
+        let result = auth_service.sign_in(request).await.unwrap().into_inner();
+
+        assert_eq!(result.status_code, StatusCode::Success as i32);
+        assert!(!result.session_token.is_empty());
+    }
+
+    #[tokio::test]
+    async fn sign_in_should_lock_account_after_repeated_failures() {
+        let mut users_service = UsersImpl::default();
+
+        let _ = users_service
+            .create_user("123456".to_owned(), "654321".to_owned(), String::new())
+            .await;
+
+        let users_service = Box::new(RwLock::new(users_service));
+        let sessions_service = Box::new(RwLock::new(SessionsImpl::default()));
+        let lockouts_service = Box::new(Mutex::new(LockoutsImpl::new(2, Duration::from_secs(60))));
+
+        let auth_service =
+            AuthService::new(users_service, sessions_service).with_lockouts(lockouts_service);
+
+        let sign_in = |password: &str| {
+            tonic::Request::new(SignInRequest {
+                username: "123456".to_owned(),
+                password: password.to_owned(),
+                ..Default::default()
+            })
+        };
+
+        let result = auth_service
+            .sign_in(sign_in("wrong password"))
+            .await
+            .unwrap()
+            .into_inner();
+        assert_eq!(result.status_code, StatusCode::Failure as i32);
+
+        let result = auth_service
+            .sign_in(sign_in("wrong password"))
+            .await
+            .unwrap()
+            .into_inner();
+        assert_eq!(result.status_code, StatusCode::AccountLocked as i32);
+        assert_eq!(result.retry_after, 60);
+
+        // The correct password is rejected while the account is locked.
+        let result = auth_service
+            .sign_in(sign_in("654321"))
+            .await
+            .unwrap()
+            .into_inner();
+        assert_eq!(result.status_code, StatusCode::AccountLocked as i32);
+        assert!(result.retry_after > 0);
+        assert!(result.session_token.is_empty());
+    }
+
+    #[tokio::test]
+    async fn sign_up_should_fail_if_username_exists() {
+        let mut users_service = UsersImpl::default();
+
+        let _ = users_service
+            .create_user("123456".to_owned(), "654321".to_owned(), String::new())
+            .await;
+
+        let users_service = Box::new(RwLock::new(users_service));
+        let sessions_service = Box::new(RwLock::new(SessionsImpl::default()));
+
+        let auth_service = AuthService::new(users_service, sessions_service);
+
+        let request = tonic::Request::new(SignUpRequest {
+            username: "123456".to_owned(),
+            password: "654321".to_owned(),
+            ..Default::default()
+        });
+
+        let result = auth_service.sign_up(request).await.unwrap();
+
+        assert_eq!(
+            result.extensions().get::<ErrorReason>(),
+            Some(&ErrorReason::UsernameTaken)
+        );
+        assert_eq!(result.into_inner().status_code, StatusCode::Failure as i32);
+    }
+
+    #[tokio::test]
+    async fn sign_up_should_hide_taken_username_with_anti_enumeration() {
+        let mut users_service = UsersImpl::default();
+
+        let _ = users_service
+            .create_user("123456".to_owned(), "654321".to_owned(), String::new())
+            .await;
+        let user_uuid = users_service
+            .lookup_user_uuid("123456".to_owned())
+            .await
+            .unwrap();
+
+        let users_service = Box::new(RwLock::new(users_service));
+        let sessions_service = Box::new(RwLock::new(SessionsImpl::default()));
+
+        let mailer = TestMailer::default();
+        let auth_service = AuthService::new(users_service, sessions_service)
+            .with_mailer(Box::new(mailer.clone()))
+            .with_anti_enumeration();
+
+        let request = tonic::Request::new(SignUpRequest {
+            username: "123456".to_owned(),
+            password: "other password".to_owned(),
+            ..Default::default()
+        });
+
+        let result = auth_service.sign_up(request).await.unwrap();
+
+        assert_eq!(result.extensions().get::<ErrorReason>(), None);
+        assert_eq!(result.into_inner().status_code, StatusCode::Success as i32);
+        // The owner is told instead, and their password is unchanged
+        assert_eq!(*mailer.sign_up_attempts.lock(), [user_uuid]);
+        let request = tonic::Request::new(SignInRequest {
+            username: "123456".to_owned(),
+            password: "654321".to_owned(),
+            ..Default::default()
+        });
+        let result = auth_service.sign_in(request).await.unwrap().into_inner();
+        assert_eq!(result.status_code, StatusCode::Success as i32);
+    }
+
+    #[tokio::test]
+    async fn sign_up_should_fail_if_email_exists() {
+        let mut users_service = UsersImpl::default();
+
+        let _ = users_service
+            .create_user(
+                "123456".to_owned(),
+                "654321".to_owned(),
+                "user@example.com".to_owned(),
+            )
+            .await;
+
+        let users_service = Box::new(RwLock::new(users_service));
+        let sessions_service = Box::new(RwLock::new(SessionsImpl::default()));
+
+        let auth_service = AuthService::new(users_service, sessions_service);
+
+        let request = tonic::Request::new(SignUpRequest {
+            username: "654321".to_owned(),
+            password: "654321".to_owned(),
+            email: "user@example.com".to_owned(),
+            ..Default::default()
+        });
+
+        let result = auth_service.sign_up(request).await.unwrap();
+
+        // Only taken usernames have a reason
+        assert_eq!(result.extensions().get::<ErrorReason>(), None);
+        assert_eq!(result.into_inner().status_code, StatusCode::Failure as i32);
+    }
+
+    #[tokio::test]
+    async fn sign_up_should_succeed() {
+        let users_service = Box::new(RwLock::new(UsersImpl::default()));
+        let sessions_service = Box::new(RwLock::new(SessionsImpl::default()));
+
+        let auth_service = AuthService::new(users_service, sessions_service);
+
+        let request = tonic::Request::new(SignUpRequest {
+            username: "123456".to_owned(),
+            password: "654321".to_owned(),
+            ..Default::default()
+        });
+
+        let result = auth_service.sign_up(request).await.unwrap();
+
+        assert_eq!(result.into_inner().status_code, StatusCode::Success as i32);
+    }
+
+    #[tokio::test]
+    async fn sign_up_should_reject_invalid_username() {
+        let users_service = Box::new(RwLock::new(UsersImpl::default()));
+        let sessions_service = Box::new(RwLock::new(SessionsImpl::default()));
+
+        let auth_service = AuthService::new(users_service, sessions_service);
+
+        let request = tonic::Request::new(SignUpRequest {
+            username: "user name".to_owned(),
+            password: "654321".to_owned(),
+            ..Default::default()
+        });
+
+        let (result, failure) = track_failure(auth_service.sign_up(request)).await;
+
+        assert_eq!(result.unwrap_err().code(), tonic::Code::InvalidArgument);
+        assert_eq!(failure, Some(FailureReason::InvalidRequest));
+        let users_service = auth_service.users_service.read().await;
+        assert!(users_service
+            .lookup_user_uuid("user name".to_owned())
+            .await
+            .is_none());
+    }
+
+    #[tokio::test]
+    async fn sign_up_should_replay_result_for_idempotency_key() {
+        let users_service = Box::new(RwLock::new(UsersImpl::default()));
+        let sessions_service = Box::new(RwLock::new(SessionsImpl::default()));
+
+        let auth_service = AuthService::new(users_service, sessions_service);
+
+        let sign_up = |idempotency_key: &str| {
+            tonic::Request::new(SignUpRequest {
+                username: "123456".to_owned(),
+                password: "654321".to_owned(),
+                idempotency_key: idempotency_key.to_owned(),
+                ..Default::default()
+            })
+        };
+
+        let result = auth_service.sign_up(sign_up("key")).await.unwrap();
+        assert_eq!(result.into_inner().status_code, StatusCode::Success as i32);
+
+        // A retry gets the first result rather than finding the username taken
+        let result = auth_service.sign_up(sign_up("key")).await.unwrap();
+        assert_eq!(result.extensions().get::<ErrorReason>(), None);
+        assert_eq!(result.into_inner().status_code, StatusCode::Success as i32);
+
+        let result = auth_service.sign_up(sign_up("other key")).await.unwrap();
+        assert_eq!(
+            result.extensions().get::<ErrorReason>(),
+            Some(&ErrorReason::UsernameTaken)
+        );
+        assert_eq!(result.into_inner().status_code, StatusCode::Failure as i32);
+    }
+
+    #[tokio::test]
+    async fn sign_up_should_require_challenge_solution_when_configured() {
+        let users_service = Box::new(RwLock::new(UsersImpl::default()));
+        let sessions_service = Box::new(RwLock::new(SessionsImpl::default()));
+
+        let auth_service = AuthService::new(users_service, sessions_service)
+            .with_sign_up_challenge(Box::new(ProofOfWork::new(8)));
+
+        let request = tonic::Request::new(SignUpRequest {
+            username: "123456".to_owned(),
+            password: "654321".to_owned(),
+            ..Default::default()
+        });
+
+        let result = auth_service.sign_up(request).await.unwrap();
+
+        assert_eq!(result.into_inner().status_code, StatusCode::Failure as i32);
+
+        let challenge = auth_service
+            .get_sign_up_challenge(tonic::Request::new(GetSignUpChallengeRequest {}))
+            .await
+            .unwrap()
+            .into_inner();
+
+        assert_eq!(challenge.status_code, StatusCode::Success as i32);
+        assert_eq!(challenge.difficulty, 8);
+
+        let challenge_solution = solve(&Challenge {
+            challenge_id: challenge.challenge_id.clone(),
+            difficulty: challenge.difficulty,
+        });
+
+        let request = tonic::Request::new(SignUpRequest {
+            username: "123456".to_owned(),
+            password: "654321".to_owned(),
+            challenge_id: challenge.challenge_id,
+            challenge_solution,
+            ..Default::default()
+        });
+
+        let result = auth_service.sign_up(request).await.unwrap();
+
+        assert_eq!(result.into_inner().status_code, StatusCode::Success as i32);
+    }
+
+    #[tokio::test]
+    async fn get_sign_up_challenge_should_fail_without_challenge() {
+        let users_service = Box::new(RwLock::new(UsersImpl::default()));
+        let sessions_service = Box::new(RwLock::new(SessionsImpl::default()));
+
+        let auth_service = AuthService::new(users_service, sessions_service);
+
+        let result = auth_service
+            .get_sign_up_challenge(tonic::Request::new(GetSignUpChallengeRequest {}))
+            .await
+            .unwrap()
+            .into_inner();
+
+        assert_eq!(result.status_code, StatusCode::Failure as i32);
+    }
+
+    #[tokio::test]
+    async fn get_sign_up_challenge_should_fail_if_issued_elsewhere() {
+        let users_service = Box::new(RwLock::new(UsersImpl::default()));
+        let sessions_service = Box::new(RwLock::new(SessionsImpl::default()));
+
+        let auth_service = AuthService::new(users_service, sessions_service)
+            .with_sign_up_challenge(Box::new(TestCaptcha));
+
+        let status = auth_service
+            .get_sign_up_challenge(tonic::Request::new(GetSignUpChallengeRequest {}))
+            .await
+            .unwrap_err();
+
+        assert_eq!(status.code(), tonic::Code::Unavailable);
+    }
+
+    #[tokio::test]
+    async fn sign_out_should_succeed() {
+        let users_service = Box::new(RwLock::new(UsersImpl::default()));
+        let sessions_service = Box::new(RwLock::new(SessionsImpl::default()));
+
+        let auth_service = AuthService::new(users_service, sessions_service);
+
+        let request = tonic::Request::new(SignOutRequest {
+            session_token: "".to_owned(),
+        });
+
+        let result = auth_service.sign_out(request).await.unwrap();
+
+        assert_eq!(result.into_inner().status_code, StatusCode::Success as i32);
+    }
+    #[tokio::test]
+    async fn validate_session_should_fail_if_session_not_found() {
+        let users_service = Box::new(RwLock::new(UsersImpl::default()));
+        let sessions_service = Box::new(RwLock::new(SessionsImpl::default()));
+
+        let auth_service = AuthService::new(users_service, sessions_service);
+
+        let request = tonic::Request::new(ValidateSessionRequest {
+            session_token: "unknown".to_owned(),
+        });
+
+        let result = auth_service.validate_session(request).await.unwrap();
+        assert_eq!(
+            result.extensions().get::<ErrorReason>(),
+            Some(&ErrorReason::SessionExpired)
+        );
+        let result = result.into_inner();
+
+        assert_eq!(result.status_code, StatusCode::Failure as i32);
+        assert!(result.user_uuid.is_empty());
+        assert_eq!(result.expires_at, 0);
+    }
+
+    #[tokio::test]
+    async fn validate_session_should_succeed() {
+        let mut sessions_service = SessionsImpl::default();
+
+        let session_token = sessions_service
+            .create_session("123456", ClientMetadata::default())
+            .await
+            .unwrap();
+
+        let users_service = Box::new(RwLock::new(UsersImpl::default()));
+        let sessions_service = Box::new(RwLock::new(sessions_service));
+
+        let auth_service = AuthService::new(users_service, sessions_service);
+
+        let request = tonic::Request::new(ValidateSessionRequest { session_token });
+
+        let result = auth_service
+            .validate_session(request)
+            .await
+            .unwrap()
+            .into_inner();
+
+        assert_eq!(result.status_code, StatusCode::Success as i32);
+        assert_eq!(result.user_uuid, "123456");
+        assert!(result.expires_at > 0);
+    }
+    #[tokio::test]
+    async fn refresh_session_should_fail_if_refresh_token_not_found() {
+        let users_service = Box::new(RwLock::new(UsersImpl::default()));
+        let sessions_service = Box::new(RwLock::new(SessionsImpl::default()));
+
+        let auth_service = AuthService::new(users_service, sessions_service);
+
+        let request = tonic::Request::new(RefreshSessionRequest {
+            refresh_token: "unknown".to_owned(),
+        });
+
+        let result = auth_service
+            .refresh_session(request)
+            .await
+            .unwrap()
+            .into_inner();
+
+        assert_eq!(result.status_code, StatusCode::Failure as i32);
+        assert!(result.session_token.is_empty());
+    }
+
+    #[tokio::test]
+    async fn refresh_session_should_succeed() {
+        let mut sessions_service = SessionsImpl::default();
+
+        let session_token = sessions_service
+            .create_session("123456", ClientMetadata::default())
+            .await
+            .unwrap();
+        let refresh_token = sessions_service
+            .create_refresh_token("123456", &session_token)
+            .await
+            .unwrap();
+
+        let users_service = Box::new(RwLock::new(UsersImpl::default()));
+        let sessions_service = Box::new(RwLock::new(sessions_service));
+
+        let auth_service = AuthService::new(users_service, sessions_service);
+
+        let request = tonic::Request::new(RefreshSessionRequest { refresh_token });
+
+        let result = auth_service
+            .refresh_session(request)
+            .await
+            .unwrap()
+            .into_inner();
+
+        assert_eq!(result.status_code, StatusCode::Success as i32);
+        assert!(!result.session_token.is_empty());
+        assert_ne!(result.session_token, session_token);
+    }
+    #[tokio::test]
+    async fn request_password_reset_should_not_send_token_if_user_not_found() {
+        let users_service = Box::new(RwLock::new(UsersImpl::default()));
+        let sessions_service = Box::new(RwLock::new(SessionsImpl::default()));
+        let mailer = TestMailer::default();
+
+        let auth_service =
+            AuthService::new(users_service, sessions_service).with_mailer(Box::new(mailer.clone()));
+
+        let request = tonic::Request::new(RequestPasswordResetRequest {
+            username: "123456".to_owned(),
+        });
+
+        let result = auth_service.request_password_reset(request).await.unwrap();
+
+        assert_eq!(result.into_inner().status_code, StatusCode::Success as i32);
+        assert!(mailer.reset_tokens.lock().is_empty());
+    }
+
+    #[tokio::test]
+    async fn confirm_password_reset_should_fail_if_token_invalid() {
+        let users_service = Box::new(RwLock::new(UsersImpl::default()));
+        let sessions_service = Box::new(RwLock::new(SessionsImpl::default()));
+
+        let auth_service = AuthService::new(users_service, sessions_service);
+
+        let request = tonic::Request::new(ConfirmPasswordResetRequest {
+            reset_token: "unknown".to_owned(),
+            new_password: "new password".to_owned(),
+        });
+
+        let result = auth_service.confirm_password_reset(request).await.unwrap();
+
+        assert_eq!(result.into_inner().status_code, StatusCode::Failure as i32);
+    }
+
+    #[tokio::test]
+    async fn password_reset_should_succeed() {
+        let mut users_service = UsersImpl::default();
+
+        let _ = users_service
+            .create_user("123456".to_owned(), "654321".to_owned(), String::new())
+            .await;
+
+        let users_service = Box::new(RwLock::new(users_service));
+        let sessions_service = Box::new(RwLock::new(SessionsImpl::default()));
+        let mailer = TestMailer::default();
+
+        let auth_service =
+            AuthService::new(users_service, sessions_service).with_mailer(Box::new(mailer.clone()));
+
+        let request = tonic::Request::new(RequestPasswordResetRequest {
+            username: "123456".to_owned(),
+        });
+
+        auth_service.request_password_reset(request).await.unwrap();
+
+        let reset_token = mailer.reset_tokens.lock().pop().unwrap();
+
+        let request = tonic::Request::new(ConfirmPasswordResetRequest {
+            reset_token,
+            new_password: "new password".to_owned(),
+        });
+
+        let result = auth_service.confirm_password_reset(request).await.unwrap();
+
+        assert_eq!(result.into_inner().status_code, StatusCode::Success as i32);
+
+        let request = tonic::Request::new(SignInRequest {
+            username: "123456".to_owned(),
+            password: "new password".to_owned(),
+            ..Default::default()
+        });
+
+        let result = auth_service.sign_in(request).await.unwrap().into_inner();
+
+        assert_eq!(result.status_code, StatusCode::Success as i32);
+    }
+
+    #[tokio::test]
+    async fn confirm_password_reset_should_keep_token_if_update_fails() {
+        let mut users_service = UsersImpl::default();
+
+        let _ = users_service
+            .create_user("123456".to_owned(), "654321".to_owned(), String::new())
+            .await;
+
+        let users_service = Box::new(RwLock::new(users_service));
+        let sessions_service = Box::new(RwLock::new(SessionsImpl::default()));
+        let mailer = TestMailer::default();
+
+        let auth_service =
+            AuthService::new(users_service, sessions_service).with_mailer(Box::new(mailer.clone()));
+
+        let request = tonic::Request::new(RequestPasswordResetRequest {
+            username: "123456".to_owned(),
+        });
+
+        auth_service.request_password_reset(request).await.unwrap();
+
+        let reset_token = mailer.reset_tokens.lock().pop().unwrap();
+
+        // Removed behind the service's back, so storing the new password fails.
+        let mut users_service = auth_service.users_service.write().await;
+        let user_uuid = users_service
+            .get_user_uuid("123456".to_owned(), "654321".to_owned())
+            .await
+            .unwrap();
+        users_service.delete_user(user_uuid.clone()).await;
+        drop(users_service);
+
+        let request = tonic::Request::new(ConfirmPasswordResetRequest {
+            reset_token: reset_token.clone(),
+            new_password: "new password".to_owned(),
+        });
+
+        let result = auth_service.confirm_password_reset(request).await.unwrap();
+
+        assert_eq!(result.into_inner().status_code, StatusCode::Failure as i32);
+        assert_eq!(
+            auth_service
+                .password_resets_service
+                .lock()
+                .await
+                .reset_token_user(&reset_token),
+            Some(user_uuid)
+        );
+    }
+
+    #[tokio::test]
+    async fn delete_account_should_require_authenticated_user() {
+        let users_service = Box::new(RwLock::new(UsersImpl::default()));
+        let sessions_service = Box::new(RwLock::new(SessionsImpl::default()));
+
+        let auth_service = AuthService::new(users_service, sessions_service);
+
+        let request = tonic::Request::new(DeleteAccountRequest {
+            session_token: "unknown".to_owned(),
+        });
+
+        let status = auth_service.delete_account(request).await.unwrap_err();
+
+        assert_eq!(status.code(), tonic::Code::Unauthenticated);
+    }
+
+    #[tokio::test]
+    async fn delete_account_should_succeed() {
+        let mut users_service = UsersImpl::default();
+
+        let _ = users_service
+            .create_user("123456".to_owned(), "654321".to_owned(), String::new())
+            .await;
+
+        let users_service = Box::new(RwLock::new(users_service));
+        let sessions_service = Box::new(RwLock::new(SessionsImpl::default()));
+
+        let auth_service = AuthService::new(users_service, sessions_service);
+
+        let request = tonic::Request::new(SignInRequest {
+            username: "123456".to_owned(),
+            password: "654321".to_owned(),
+            ..Default::default()
+        });
+
+        let session_token = auth_service
+            .sign_in(request)
+            .await
+            .unwrap()
+            .into_inner()
+            .session_token;
+
+        let request = authenticated(
+            &auth_service,
+            &session_token,
+            DeleteAccountRequest {
+                session_token: session_token.clone(),
+            },
+        )
+        .await;
+
+        let result = auth_service.delete_account(request).await.unwrap();
+
+        assert_eq!(result.into_inner().status_code, StatusCode::Success as i32);
+
+        let request = tonic::Request::new(ValidateSessionRequest { session_token });
+
+        let result = auth_service
+            .validate_session(request)
+            .await
+            .unwrap()
+            .into_inner();
+
+        assert_eq!(result.status_code, StatusCode::Failure as i32);
+
+        let request = tonic::Request::new(SignInRequest {
+            username: "123456".to_owned(),
+            password: "654321".to_owned(),
+            ..Default::default()
+        });
+
+        let result = auth_service.sign_in(request).await.unwrap().into_inner();
+
+        assert_eq!(result.status_code, StatusCode::Failure as i32);
+    }
+    #[tokio::test]
+    async fn get_profile_should_require_authenticated_user() {
+        let users_service = Box::new(RwLock::new(UsersImpl::default()));
+        let mut sessions_service = SessionsImpl::default();
+        let session_token = sessions_service
+            .create_session("123456", ClientMetadata::default())
+            .await
+            .unwrap();
+        let sessions_service = Box::new(RwLock::new(sessions_service));
+
+        let auth_service = AuthService::new(users_service, sessions_service);
+
+        // A session token in the message isn't enough, only `SessionAuthLayer` authenticates.
+        let request = tonic::Request::new(GetProfileRequest { session_token });
+
+        let (result, failure) = track_failure(auth_service.get_profile(request)).await;
+
+        assert_eq!(result.unwrap_err().code(), tonic::Code::Unauthenticated);
+        assert_eq!(failure, Some(FailureReason::BadCredentials));
+    }
+
+    #[tokio::test]
+    async fn get_profile_should_accept_authenticated_user() {
+        let mut users_service = UsersImpl::default();
+        let _ = users_service
+            .create_user("123456".to_owned(), "654321".to_owned(), String::new())
+            .await;
+        let user_uuid = users_service
+            .lookup_user_uuid("123456".to_owned())
+            .await
+            .unwrap();
+
+        let users_service = Box::new(RwLock::new(users_service));
+        let sessions_service = Box::new(RwLock::new(SessionsImpl::default()));
+
+        let auth_service = AuthService::new(users_service, sessions_service);
+
+        // Authenticated by `SessionAuthLayer`, so the message doesn't carry the token.
+        let mut request = tonic::Request::new(GetProfileRequest {
+            session_token: String::new(),
+        });
+        request.extensions_mut().insert(AuthenticatedUser {
+            user_uuid: user_uuid.clone(),
+            session_token: "bearer".into(),
+        });
+
+        let result = auth_service
+            .get_profile(request)
+            .await
+            .unwrap()
+            .into_inner();
+
+        assert_eq!(result.status_code, StatusCode::Success as i32);
+        assert_eq!(result.user_uuid, user_uuid);
+        assert_eq!(result.username, "123456");
+    }
+
+    #[tokio::test]
+    async fn update_profile_should_require_authenticated_user() {
+        let users_service = Box::new(RwLock::new(UsersImpl::default()));
+        let sessions_service = Box::new(RwLock::new(SessionsImpl::default()));
+
+        let auth_service = AuthService::new(users_service, sessions_service);
+
+        let request = tonic::Request::new(UpdateProfileRequest {
+            session_token: "unknown".to_owned(),
+            display_name: Some("Name".to_owned()),
+            email: None,
+        });
+
+        let status = auth_service.update_profile(request).await.unwrap_err();
+
+        assert_eq!(status.code(), tonic::Code::Unauthenticated);
+    }
+
+    #[tokio::test]
+    async fn update_profile_should_succeed() {
+        let mut users_service = UsersImpl::default();
+
+        let _ = users_service
+            .create_user("123456".to_owned(), "654321".to_owned(), String::new())
+            .await;
+
+        let users_service = Box::new(RwLock::new(users_service));
+        let sessions_service = Box::new(RwLock::new(SessionsImpl::default()));
+
+        let auth_service = AuthService::new(users_service, sessions_service);
+
+        let request = tonic::Request::new(SignInRequest {
+            username: "123456".to_owned(),
+            password: "654321".to_owned(),
+            ..Default::default()
+        });
+
+        let session_token = auth_service
+            .sign_in(request)
+            .await
+            .unwrap()
+            .into_inner()
+            .session_token;
+
+        let request = authenticated(
+            &auth_service,
+            &session_token,
+            UpdateProfileRequest {
+                session_token: session_token.clone(),
+                display_name: Some("Name".to_owned()),
+                email: Some("user@example.com".to_owned()),
+            },
+        )
+        .await;
+
+        let result = auth_service.update_profile(request).await.unwrap();
+
+        assert_eq!(result.into_inner().status_code, StatusCode::Success as i32);
+
+        let request = authenticated(
+            &auth_service,
+            &session_token,
+            GetProfileRequest {
+                session_token: session_token.clone(),
+            },
+        )
+        .await;
+
+        let result = auth_service
+            .get_profile(request)
+            .await
+            .unwrap()
+            .into_inner();
+
+        assert_eq!(result.status_code, StatusCode::Success as i32);
+        assert_eq!(result.username, "123456");
+        assert_eq!(result.display_name, "Name");
+        assert_eq!(result.email, "user@example.com");
+        assert!(result.created_at > 0);
+    }
+    #[tokio::test]
+    async fn list_active_sessions_should_require_authenticated_user() {
+        let users_service = Box::new(RwLock::new(UsersImpl::default()));
+        let sessions_service = Box::new(RwLock::new(SessionsImpl::default()));
+
+        let auth_service = AuthService::new(users_service, sessions_service);
+
+        let request = tonic::Request::new(ListActiveSessionsRequest {
+            session_token: "unknown".to_owned(),
+        });
+
+        let status = auth_service
+            .list_active_sessions(request)
+            .await
+            .unwrap_err();
+
+        assert_eq!(status.code(), tonic::Code::Unauthenticated);
+    }
+
+    #[tokio::test]
+    async fn list_active_sessions_should_succeed() {
+        let mut sessions_service = SessionsImpl::default();
+
+        let session_token = sessions_service
+            .create_session("123456", ClientMetadata::default())
+            .await
+            .unwrap();
+        sessions_service
+            .create_session("123456", ClientMetadata::default())
+            .await
+            .unwrap();
+        sessions_service
+            .create_session("654321", ClientMetadata::default())
+            .await
+            .unwrap();
+
+        let users_service = Box::new(RwLock::new(UsersImpl::default()));
+        let sessions_service = Box::new(RwLock::new(sessions_service));
+
+        let auth_service = AuthService::new(users_service, sessions_service);
+
+        let request = authenticated(
+            &auth_service,
+            &session_token,
+            ListActiveSessionsRequest {
+                session_token: session_token.clone(),
+            },
+        )
+        .await;
+
+        let result = auth_service
+            .list_active_sessions(request)
+            .await
+            .unwrap()
+            .into_inner();
+
+        assert_eq!(result.status_code, StatusCode::Success as i32);
+        assert_eq!(result.sessions.len(), 2);
+        assert_eq!(
+            result
+                .sessions
+                .iter()
+                .filter(|session| session.current)
+                .count(),
+            1
+        );
+        assert!(result
+            .sessions
+            .iter()
+            .all(|session| session.session_id != session_token));
+    }
+
+    #[tokio::test]
+    async fn sign_in_should_store_client_metadata() {
+        let mut users_service = UsersImpl::default();
+
+        let _ = users_service
+            .create_user("123456".to_owned(), "654321".to_owned(), String::new())
+            .await;
+
+        let users_service = Box::new(RwLock::new(users_service));
+        let sessions_service = Box::new(RwLock::new(SessionsImpl::default()));
+
+        let auth_service = AuthService::new(users_service, sessions_service);
+
+        let mut request = tonic::Request::new(SignInRequest {
+            username: "123456".to_owned(),
+            password: "654321".to_owned(),
+            ..Default::default()
+        });
+        request
+            .metadata_mut()
+            .insert("user-agent", "test-agent".parse().unwrap());
+
+        let session_token = auth_service
+            .sign_in(request)
+            .await
+            .unwrap()
+            .into_inner()
+            .session_token;
+
+        let request = authenticated(
+            &auth_service,
+            &session_token,
+            ListActiveSessionsRequest {
+                session_token: session_token.clone(),
+            },
+        )
+        .await;
+
+        let result = auth_service
+            .list_active_sessions(request)
+            .await
+            .unwrap()
+            .into_inner();
+
+        let session = &result.sessions[0];
+        assert_eq!(session.user_agent, "test-agent");
+        assert!(session.created_at > 0);
+        assert!(session.last_seen >= session.created_at);
+        assert!(session.expires_at > session.created_at);
+    }
+
+    #[tokio::test]
+    async fn sign_in_should_require_totp_once_enabled() {
+        let mut users_service = UsersImpl::default();
+
+        let _ = users_service
+            .create_user("123456".to_owned(), "654321".to_owned(), String::new())
+            .await;
+
+        let users_service = Box::new(RwLock::new(users_service));
+        let sessions_service = Box::new(RwLock::new(SessionsImpl::default()));
+
+        let auth_service = AuthService::new(users_service, sessions_service);
+
+        let sign_in = |totp_code: &str| {
+            tonic::Request::new(SignInRequest {
+                username: "123456".to_owned(),
+                password: "654321".to_owned(),
+                totp_code: totp_code.to_owned(),
+            })
+        };
+
+        let session_token = auth_service
+            .sign_in(sign_in(""))
+            .await
+            .unwrap()
+            .into_inner()
+            .session_token;
+
+        let request = authenticated(
+            &auth_service,
+            &session_token,
+            EnrollTotpRequest {
+                session_token: session_token.clone(),
+            },
+        )
+        .await;
+
+        let enrollment = auth_service
+            .enroll_totp(request)
+            .await
+            .unwrap()
+            .into_inner();
+
+        assert_eq!(enrollment.status_code, StatusCode::Success as i32);
+        assert!(enrollment.provisioning_uri.starts_with("otpauth://totp/"));
+
+        // Not enforced until confirmed.
+        let result = auth_service
+            .sign_in(sign_in(""))
+            .await
+            .unwrap()
+            .into_inner();
+
+        assert_eq!(result.status_code, StatusCode::Success as i32);
+
+        let secret = crate::mfa::base32_decode(&enrollment.secret).unwrap();
+        let code = crate::mfa::totp_code(&secret, SystemTime::now());
+
+        let request = authenticated(
+            &auth_service,
+            &session_token,
+            ConfirmTotpRequest {
+                session_token: session_token.clone(),
+                code: code.clone(),
+            },
+        )
+        .await;
+
+        let result = auth_service.confirm_totp(request).await.unwrap();
+
+        assert_eq!(result.into_inner().status_code, StatusCode::Success as i32);
+
+        let result = auth_service
+            .sign_in(sign_in(""))
+            .await
+            .unwrap()
+            .into_inner();
+
+        assert_eq!(result.status_code, StatusCode::MfaRequired as i32);
+        assert!(result.session_token.is_empty());
+
+        // The code used for confirmation can't be replayed.
+        let result = auth_service
+            .sign_in(sign_in(&code))
+            .await
+            .unwrap()
+            .into_inner();
+
+        assert_eq!(result.status_code, StatusCode::Failure as i32);
+        assert!(result.session_token.is_empty());
+    }
+
+    #[tokio::test]
+    async fn enroll_totp_should_require_authenticated_user() {
+        let users_service = Box::new(RwLock::new(UsersImpl::default()));
+        let sessions_service = Box::new(RwLock::new(SessionsImpl::default()));
+
+        let auth_service = AuthService::new(users_service, sessions_service);
+
+        let request = tonic::Request::new(EnrollTotpRequest {
+            session_token: "unknown".to_owned(),
+        });
+
+        let status = auth_service.enroll_totp(request).await.unwrap_err();
+
+        assert_eq!(status.code(), tonic::Code::Unauthenticated);
+    }
+    #[tokio::test]
+    async fn sign_in_with_id_token_should_fail_without_verifier() {
+        let users_service = Box::new(RwLock::new(UsersImpl::default()));
+        let sessions_service = Box::new(RwLock::new(SessionsImpl::default()));
+
+        let auth_service = AuthService::new(users_service, sessions_service);
+
+        let request = tonic::Request::new(SignInWithIdTokenRequest {
+            provider: "example".to_owned(),
+            id_token: "valid".to_owned(),
+        });
+
+        let result = auth_service
+            .sign_in_with_id_token(request)
+            .await
+            .unwrap()
+            .into_inner();
+
+        assert_eq!(result.status_code, StatusCode::Failure as i32);
+        assert!(result.session_token.is_empty());
+    }
+
+    #[tokio::test]
+    async fn sign_in_with_id_token_should_fail_if_token_invalid() {
+        let users_service = Box::new(RwLock::new(UsersImpl::default()));
+        let sessions_service = Box::new(RwLock::new(SessionsImpl::default()));
+
+        let auth_service = AuthService::new(users_service, sessions_service)
+            .with_id_token_verifier(Box::new(TestIdTokenVerifier));
+
+        let request = tonic::Request::new(SignInWithIdTokenRequest {
+            provider: "example".to_owned(),
+            id_token: "forged".to_owned(),
+        });
+
+        let result = auth_service
+            .sign_in_with_id_token(request)
+            .await
+            .unwrap()
+            .into_inner();
+
+        assert_eq!(result.status_code, StatusCode::Failure as i32);
+        assert!(result.session_token.is_empty());
+    }
+
+    #[tokio::test]
+    async fn sign_in_with_id_token_should_provision_and_reuse_user() {
+        let mut users_service = UsersImpl::default();
+
+        // Takes the provider's preferred username.
+        let _ = users_service
+            .create_user("123456".to_owned(), "654321".to_owned(), String::new())
+            .await;
+
+        let users_service = Box::new(RwLock::new(users_service));
+        let sessions_service = Box::new(RwLock::new(SessionsImpl::default()));
+
+        let auth_service = AuthService::new(users_service, sessions_service)
+            .with_id_token_verifier(Box::new(TestIdTokenVerifier));
+
+        let sign_in = || {
+            tonic::Request::new(SignInWithIdTokenRequest {
+                provider: "example".to_owned(),
+                id_token: "valid".to_owned(),
+            })
+        };
+
+        let first = auth_service
+            .sign_in_with_id_token(sign_in())
+            .await
+            .unwrap()
+            .into_inner();
+
+        assert_eq!(first.status_code, StatusCode::Success as i32);
+        assert!(!first.session_token.is_empty());
+
+        let request = authenticated(
+            &auth_service,
+            &first.session_token,
+            GetProfileRequest {
+                session_token: first.session_token.clone(),
+            },
+        )
+        .await;
+
+        let profile = auth_service
+            .get_profile(request)
+            .await
+            .unwrap()
+            .into_inner();
+
+        assert_eq!(profile.username, "example:subject");
+
+        let second = auth_service
+            .sign_in_with_id_token(sign_in())
+            .await
+            .unwrap()
+            .into_inner();
+
+        assert_eq!(second.status_code, StatusCode::Success as i32);
+        assert_eq!(second.user_uuid, first.user_uuid);
+        assert_ne!(second.session_token, first.session_token);
+    }
+    #[tokio::test]
+    async fn get_login_history_should_list_attempts() {
+        let mut users_service = UsersImpl::default();
+
+        let _ = users_service
+            .create_user("123456".to_owned(), "654321".to_owned(), String::new())
+            .await;
+
+        let users_service = Box::new(RwLock::new(users_service));
+        let sessions_service = Box::new(RwLock::new(SessionsImpl::default()));
+
+        let auth_service = AuthService::new(users_service, sessions_service);
+
+        let sign_in = |password: &str| {
+            tonic::Request::new(SignInRequest {
+                username: "123456".to_owned(),
+                password: password.to_owned(),
+                ..Default::default()
+            })
+        };
+
+        auth_service
+            .sign_in(sign_in("wrong password"))
+            .await
+            .unwrap();
+        let session_token = auth_service
+            .sign_in(sign_in("654321"))
+            .await
+            .unwrap()
+            .into_inner()
+            .session_token;
+
+        let request = authenticated(
+            &auth_service,
+            &session_token,
+            GetLoginHistoryRequest {
+                session_token: session_token.clone(),
+                page_size: 1,
+                page_token: "".to_owned(),
+            },
+        )
+        .await;
+
+        let first_page = auth_service
+            .get_login_history(request)
+            .await
+            .unwrap()
+            .into_inner();
+
+        assert_eq!(first_page.status_code, StatusCode::Success as i32);
+        assert_eq!(first_page.attempts.len(), 1);
+        assert!(first_page.attempts[0].success);
+        assert_eq!(first_page.next_page_token, "1");
+
+        let request = authenticated(
+            &auth_service,
+            &session_token,
+            GetLoginHistoryRequest {
+                session_token: session_token.clone(),
+                page_size: 1,
+                page_token: first_page.next_page_token,
+            },
+        )
+        .await;
+
+        let second_page = auth_service
+            .get_login_history(request)
+            .await
+            .unwrap()
+            .into_inner();
+
+        assert_eq!(second_page.attempts.len(), 1);
+        assert!(!second_page.attempts[0].success);
+        assert!(second_page.next_page_token.is_empty());
+    }
+
+    #[tokio::test]
+    async fn get_login_history_should_require_authenticated_user() {
+        let users_service = Box::new(RwLock::new(UsersImpl::default()));
+        let sessions_service = Box::new(RwLock::new(SessionsImpl::default()));
+
+        let auth_service = AuthService::new(users_service, sessions_service);
+
+        let request = tonic::Request::new(GetLoginHistoryRequest {
+            session_token: "invalid".to_owned(),
+            ..Default::default()
+        });
+
+        let status = auth_service.get_login_history(request).await.unwrap_err();
+
+        assert_eq!(status.code(), tonic::Code::Unauthenticated);
+    }
+    #[tokio::test]
+    async fn request_magic_link_should_not_send_token_if_user_not_found() {
+        let users_service = Box::new(RwLock::new(UsersImpl::default()));
+        let sessions_service = Box::new(RwLock::new(SessionsImpl::default()));
+        let mailer = TestMailer::default();
+
+        let auth_service =
+            AuthService::new(users_service, sessions_service).with_mailer(Box::new(mailer.clone()));
+
+        let request = tonic::Request::new(RequestMagicLinkRequest {
+            username: "123456".to_owned(),
+        });
+
+        let result = auth_service.request_magic_link(request).await.unwrap();
+
+        assert_eq!(result.into_inner().status_code, StatusCode::Success as i32);
+        assert!(mailer.magic_link_tokens.lock().is_empty());
+    }
+
+    #[tokio::test]
+    async fn magic_link_should_sign_in_once() {
+        let mut users_service = UsersImpl::default();
+
+        let _ = users_service
+            .create_user("123456".to_owned(), "654321".to_owned(), String::new())
+            .await;
+
+        let users_service = Box::new(RwLock::new(users_service));
+        let sessions_service = Box::new(RwLock::new(SessionsImpl::default()));
+        let mailer = TestMailer::default();
+
+        let auth_service =
+            AuthService::new(users_service, sessions_service).with_mailer(Box::new(mailer.clone()));
+
+        let request = tonic::Request::new(RequestMagicLinkRequest {
+            username: "123456".to_owned(),
+        });
+
+        auth_service.request_magic_link(request).await.unwrap();
+
+        let token = mailer.magic_link_tokens.lock().pop().unwrap();
+
+        let redeem = || {
+            tonic::Request::new(RedeemMagicLinkRequest {
+                token: token.clone(),
+                totp_code: "".to_owned(),
+            })
+        };
+
+        let result = auth_service
+            .redeem_magic_link(redeem())
+            .await
+            .unwrap()
+            .into_inner();
+
+        assert_eq!(result.status_code, StatusCode::Success as i32);
+        assert!(!result.session_token.is_empty());
+
+        let result = auth_service
+            .redeem_magic_link(redeem())
+            .await
+            .unwrap()
+            .into_inner();
+
+        assert_eq!(result.status_code, StatusCode::Failure as i32);
+    }
+
+    #[tokio::test]
+    async fn magic_link_should_require_totp_once_enabled() {
+        let mut users_service = UsersImpl::default();
+
+        let _ = users_service
+            .create_user("123456".to_owned(), "654321".to_owned(), String::new())
+            .await;
+        let user_uuid = users_service
+            .lookup_user_uuid("123456".to_owned())
+            .await
+            .unwrap();
+
+        let mut mfa_service = MfaImpl::default();
+        let secret = crate::mfa::base32_decode(&mfa_service.enroll_totp(&user_uuid)).unwrap();
+        mfa_service.confirm_totp(
+            &user_uuid,
+            &crate::mfa::totp_code(
+                &secret,
+                SystemTime::now() - Duration::from_secs(crate::mfa::TOTP_STEP),
+            ),
+        );
+
+        let users_service = Box::new(RwLock::new(users_service));
+        let sessions_service = Box::new(RwLock::new(SessionsImpl::default()));
+        let mailer = TestMailer::default();
+
+        let mut auth_service =
+            AuthService::new(users_service, sessions_service).with_mailer(Box::new(mailer.clone()));
+        auth_service.mfa_service = Box::new(Mutex::new(mfa_service));
+
+        let request = tonic::Request::new(RequestMagicLinkRequest {
+            username: "123456".to_owned(),
+        });
+
+        auth_service.request_magic_link(request).await.unwrap();
+
+        let token = mailer.magic_link_tokens.lock().pop().unwrap();
+
+        let redeem = |totp_code: String| {
+            tonic::Request::new(RedeemMagicLinkRequest {
+                token: token.clone(),
+                totp_code,
+            })
+        };
+
+        let result = auth_service
+            .redeem_magic_link(redeem("".to_owned()))
+            .await
+            .unwrap()
+            .into_inner();
+
+        assert_eq!(result.status_code, StatusCode::MfaRequired as i32);
+        assert!(result.session_token.is_empty());
+
+        let result = auth_service
+            .redeem_magic_link(redeem(crate::mfa::totp_code(&secret, SystemTime::now())))
+            .await
+            .unwrap()
+            .into_inner();
+
+        assert_eq!(result.status_code, StatusCode::Success as i32);
+        assert!(!result.session_token.is_empty());
+    }
+    #[tokio::test]
+    async fn renew_session_should_extend_session() {
+        let mut sessions_service = SessionsImpl::default();
+
+        let session_token = sessions_service
+            .create_session("123456", ClientMetadata::default())
+            .await
+            .unwrap();
+
+        let users_service = Box::new(RwLock::new(UsersImpl::default()));
+        let sessions_service = Box::new(RwLock::new(sessions_service));
+
+        let auth_service = AuthService::new(users_service, sessions_service);
+
+        let request = tonic::Request::new(RenewSessionRequest {
+            session_token: session_token.clone(),
+        });
+
+        let result = auth_service
+            .renew_session(request)
+            .await
+            .unwrap()
+            .into_inner();
+
+        assert_eq!(result.status_code, StatusCode::Success as i32);
+        assert_eq!(result.session_token, session_token);
+        assert!(result.expires_at > unix_timestamp(SystemTime::now()));
+
+        let request = tonic::Request::new(RenewSessionRequest {
+            session_token: "unknown".to_owned(),
+        });
+
+        let result = auth_service
+            .renew_session(request)
+            .await
+            .unwrap()
+            .into_inner();
+
+        assert_eq!(result.status_code, StatusCode::Failure as i32);
+    }
+    #[tokio::test]
+    async fn sign_in_should_evict_oldest_session_at_limit() {
+        let mut users_service = UsersImpl::default();
+
+        let _ = users_service
+            .create_user("123456".to_owned(), "654321".to_owned(), String::new())
+            .await;
+
+        let users_service = Box::new(RwLock::new(users_service));
+        let sessions_service = Box::new(RwLock::new(SessionsImpl::default()));
+
+        let auth_service =
+            AuthService::new(users_service, sessions_service).with_session_limit(SessionLimit {
+                max_sessions: 2,
+                policy: SessionLimitPolicy::EvictOldest,
+            });
+
+        let mut session_tokens = Vec::new();
+        for _ in 0..3 {
+            let request = tonic::Request::new(SignInRequest {
+                username: "123456".to_owned(),
+                password: "654321".to_owned(),
+                ..Default::default()
+            });
+
+            let result = auth_service.sign_in(request).await.unwrap().into_inner();
+
+            assert_eq!(result.status_code, StatusCode::Success as i32);
+            session_tokens.push(result.session_token);
+        }
+
+        assert!(auth_service
+            .session_user_uuid(&session_tokens[0])
+            .await
+            .is_none());
+        assert!(auth_service
+            .session_user_uuid(&session_tokens[1])
+            .await
+            .is_some());
+        assert!(auth_service
+            .session_user_uuid(&session_tokens[2])
+            .await
+            .is_some());
+    }
+
+    #[tokio::test]
+    async fn sign_in_should_reject_at_session_limit() {
+        let mut users_service = UsersImpl::default();
+
+        let _ = users_service
+            .create_user("123456".to_owned(), "654321".to_owned(), String::new())
+            .await;
+
+        let users_service = Box::new(RwLock::new(users_service));
+        let sessions_service = Box::new(RwLock::new(SessionsImpl::default()));
+
+        let auth_service =
+            AuthService::new(users_service, sessions_service).with_session_limit(SessionLimit {
+                max_sessions: 1,
+                policy: SessionLimitPolicy::Reject,
+            });
+
+        let sign_in = || {
+            tonic::Request::new(SignInRequest {
+                username: "123456".to_owned(),
+                password: "654321".to_owned(),
+                ..Default::default()
+            })
+        };
+
+        let first = auth_service.sign_in(sign_in()).await.unwrap().into_inner();
+        let second = auth_service.sign_in(sign_in()).await.unwrap().into_inner();
+
+        assert_eq!(first.status_code, StatusCode::Success as i32);
+        assert_eq!(second.status_code, StatusCode::SessionLimitReached as i32);
+        assert!(second.session_token.is_empty());
+        assert!(auth_service
+            .session_user_uuid(&first.session_token)
+            .await
+            .is_some());
+    }
+    #[tokio::test]
+    async fn introspect_token_should_report_active_session() {
+        let mut sessions_service = SessionsImpl::default();
+
+        let session_token = sessions_service
+            .create_session("123456", ClientMetadata::default())
+            .await
+            .unwrap();
+
+        let users_service = Box::new(RwLock::new(UsersImpl::default()));
+        let sessions_service = Box::new(RwLock::new(sessions_service));
+
+        let auth_service = AuthService::new(users_service, sessions_service);
+
+        let request = tonic::Request::new(IntrospectTokenRequest {
+            token: session_token,
+        });
+
+        let result = auth_service
+            .introspect_token(request)
+            .await
+            .unwrap()
+            .into_inner();
+
+        assert_eq!(result.status_code, StatusCode::Success as i32);
+        assert!(result.active);
+        assert_eq!(result.user_uuid, "123456");
+        assert!(result.scopes.is_empty());
+        assert!(result.expires_at > result.issued_at);
+        assert_eq!(result.token_type, "session");
+    }
+    #[tokio::test]
+    async fn introspect_token_should_report_revoked_token_inactive() {
+        let mut sessions_service = SessionsImpl::default();
+
+        let session_token = sessions_service
+            .create_session("123456", ClientMetadata::default())
+            .await
+            .unwrap();
+        let refresh_token = sessions_service
+            .create_refresh_token("123456", &session_token)
+            .await
+            .unwrap();
+
+        let users_service = Box::new(RwLock::new(UsersImpl::default()));
+        let sessions_service = Box::new(RwLock::new(sessions_service));
+
+        let auth_service = AuthService::new(users_service, sessions_service);
+
+        let request = tonic::Request::new(RevokeTokenRequest {
+            token: refresh_token,
+        });
+
+        let result = auth_service
+            .revoke_token(request)
+            .await
+            .unwrap()
+            .into_inner();
+
+        assert_eq!(result.status_code, StatusCode::Success as i32);
+
+        let request = tonic::Request::new(IntrospectTokenRequest {
+            token: session_token,
+        });
+
+        let result = auth_service
+            .introspect_token(request)
+            .await
+            .unwrap()
+            .into_inner();
+
+        assert_eq!(result.status_code, StatusCode::Success as i32);
+        assert!(!result.active);
+        assert!(result.user_uuid.is_empty());
+        assert_eq!(result.expires_at, 0);
+    }
+    #[tokio::test]
+    async fn revoke_token_should_succeed_for_unknown_token() {
+        let users_service = Box::new(RwLock::new(UsersImpl::default()));
+        let sessions_service = Box::new(RwLock::new(SessionsImpl::default()));
+
+        let auth_service = AuthService::new(users_service, sessions_service);
+
+        let request = tonic::Request::new(RevokeTokenRequest {
+            token: "unknown".to_owned(),
+        });
+
+        let result = auth_service
+            .revoke_token(request)
+            .await
+            .unwrap()
+            .into_inner();
+
+        assert_eq!(result.status_code, StatusCode::Success as i32);
+    }
+    #[tokio::test]
+    async fn watch_session_should_stream_when_the_session_ends() {
+        let (sender, _) = broadcast::channel(SESSION_EVENTS_CAPACITY);
+
+        let users_service = Box::new(RwLock::new(UsersImpl::default()));
+        let sessions_service = Box::new(RwLock::new(
+            SessionsImpl::default().with_events(sender.clone()),
+        ));
+
+        let auth_service =
+            AuthService::new(users_service, sessions_service).with_session_events(sender);
+
+        let session_token = auth_service
+            .start_session("123456".to_owned(), ClientMetadata::default())
+            .await
+            .unwrap()
+            .session_token;
+        let other_session_token = auth_service
+            .start_session("123456".to_owned(), ClientMetadata::default())
+            .await
+            .unwrap()
+            .session_token;
+
+        let mut stream = auth_service.watch_session(&session_token).await.unwrap();
+
+        // Other sessions ending isn't reported.
+        for session_token in [other_session_token, session_token] {
+            let request = tonic::Request::new(SignOutRequest { session_token });
+            auth_service.sign_out(request).await.unwrap();
+        }
+
+        let ended = stream.next().await.unwrap().unwrap();
+        assert_eq!(ended.event_type, SessionEventType::Deleted as i32);
+        assert!(stream.next().await.is_none());
+    }
+
+    #[tokio::test]
+    async fn watch_session_should_fail_if_session_not_found() {
+        let (sender, _) = broadcast::channel(SESSION_EVENTS_CAPACITY);
+
+        let users_service = Box::new(RwLock::new(UsersImpl::default()));
+        let sessions_service = Box::new(RwLock::new(
+            SessionsImpl::default().with_events(sender.clone()),
+        ));
+
+        let auth_service =
+            AuthService::new(users_service, sessions_service).with_session_events(sender);
+
+        let result = auth_service.watch_session("unknown").await;
+
+        assert_eq!(result.err(), Some(SessionError::NotFound));
+    }
+    #[tokio::test]
+    async fn get_user_attributes_should_require_authenticated_user() {
+        let users_service = Box::new(RwLock::new(UsersImpl::default()));
+        let sessions_service = Box::new(RwLock::new(SessionsImpl::default()));
+
+        let auth_service = AuthService::new(users_service, sessions_service);
+
+        let request = tonic::Request::new(GetUserAttributesRequest {
+            session_token: "unknown".to_owned(),
+        });
+
+        let status = auth_service.get_user_attributes(request).await.unwrap_err();
+
+        assert_eq!(status.code(), tonic::Code::Unauthenticated);
+    }
+
+    #[tokio::test]
+    async fn set_user_attribute_should_succeed() {
+        let mut users_service = UsersImpl::default();
+
+        let _ = users_service
+            .create_user("123456".to_owned(), "654321".to_owned(), String::new())
+            .await;
+
+        let users_service = Box::new(RwLock::new(users_service));
+        let sessions_service = Box::new(RwLock::new(SessionsImpl::default()));
+
+        let auth_service = AuthService::new(users_service, sessions_service);
+
+        let request = tonic::Request::new(SignInRequest {
+            username: "123456".to_owned(),
+            password: "654321".to_owned(),
+            ..Default::default()
+        });
+
+        let session_token = auth_service
+            .sign_in(request)
+            .await
+            .unwrap()
+            .into_inner()
+            .session_token;
+
+        for (key, value) in [("locale", "en-US"), ("plan", "pro"), ("plan", "")] {
+            let request = authenticated(
+                &auth_service,
+                &session_token,
+                SetUserAttributeRequest {
+                    session_token: session_token.clone(),
+                    key: key.to_owned(),
+                    value: value.to_owned(),
+                },
+            )
+            .await;
+
+            let result = auth_service.set_user_attribute(request).await.unwrap();
+
+            assert_eq!(result.into_inner().status_code, StatusCode::Success as i32);
+        }
+
+        // Keys over the limit are rejected.
+        let request = authenticated(
+            &auth_service,
+            &session_token,
+            SetUserAttributeRequest {
+                session_token: session_token.clone(),
+                key: "k".repeat(MAX_ATTRIBUTE_KEY_LENGTH + 1),
+                value: "value".to_owned(),
+            },
+        )
+        .await;
+
+        let result = auth_service.set_user_attribute(request).await.unwrap();
+
+        assert_eq!(result.into_inner().status_code, StatusCode::Failure as i32);
+
+        let request = authenticated(
+            &auth_service,
+            &session_token,
+            GetUserAttributesRequest {
+                session_token: session_token.clone(),
+            },
+        )
+        .await;
+
+        let result = auth_service
+            .get_user_attributes(request)
+            .await
+            .unwrap()
+            .into_inner();
+
+        assert_eq!(result.status_code, StatusCode::Success as i32);
+        assert_eq!(
+            result.attributes,
+            HashMap::from([("locale".to_owned(), "en-US".to_owned())])
+        );
+    }
+    /// Signs `username` up and deletes their account, returning their uuid.
+    async fn sign_up_and_delete_account(auth_service: &AuthService, username: &str) -> String {
+        let request = tonic::Request::new(SignUpRequest {
+            username: username.to_owned(),
+            password: "password".to_owned(),
+            ..Default::default()
+        });
+        auth_service.sign_up(request).await.unwrap();
+
+        let request = tonic::Request::new(SignInRequest {
+            username: username.to_owned(),
+            password: "password".to_owned(),
+            ..Default::default()
+        });
+        let signed_in = auth_service.sign_in(request).await.unwrap().into_inner();
+
+        let request = authenticated(
+            auth_service,
+            &signed_in.session_token,
+            DeleteAccountRequest {
+                session_token: signed_in.session_token.clone(),
+            },
+        )
+        .await;
+        let result = auth_service.delete_account(request).await.unwrap();
+        assert_eq!(
+            result.into_inner().status_code,
+            i32::from(StatusCode::Success)
+        );
+
+        signed_in.user_uuid
+    }
+
+    #[tokio::test]
+    async fn sign_in_should_fail_after_delete_account() {
+        let users_service = Box::new(RwLock::new(UsersImpl::default()));
+        let sessions_service = Box::new(RwLock::new(SessionsImpl::default()));
+
+        let auth_service = AuthService::new(users_service, sessions_service);
+
+        sign_up_and_delete_account(&auth_service, "123456").await;
+
+        let request = tonic::Request::new(SignInRequest {
+            username: "123456".to_owned(),
+            password: "password".to_owned(),
+            ..Default::default()
+        });
+
+        let result = auth_service.sign_in(request).await.unwrap().into_inner();
+
+        assert_eq!(result.status_code, StatusCode::Failure as i32);
+    }
+
+    #[tokio::test]
+    async fn purge_deleted_users_should_only_remove_users_past_grace_period() {
+        let users_service = Box::new(RwLock::new(UsersImpl::default()));
+        let sessions_service = Box::new(RwLock::new(SessionsImpl::default()));
+
+        let auth_service = AuthService::new(users_service, sessions_service);
+
+        sign_up_and_delete_account(&auth_service, "123456").await;
+
+        assert_eq!(auth_service.purge_deleted_users().await, 0);
+
+        let auth_service = auth_service.with_deletion_grace_period(Duration::ZERO);
+
+        assert_eq!(auth_service.purge_deleted_users().await, 1);
+
+        // The username is free again once the account is purged.
+        let request = tonic::Request::new(SignUpRequest {
+            username: "123456".to_owned(),
+            password: "password".to_owned(),
+            ..Default::default()
+        });
+
+        let result = auth_service.sign_up(request).await.unwrap();
+
+        assert_eq!(result.into_inner().status_code, StatusCode::Success as i32);
+    }
+    #[tokio::test]
+    async fn sign_up_should_report_password_policy_violations() {
+        let users_service = Box::new(RwLock::new(UsersImpl::default()));
+        let sessions_service = Box::new(RwLock::new(SessionsImpl::default()));
+
+        let mut password_policy = PasswordPolicy::default();
+        password_policy.require_digit = true;
+
+        let auth_service =
+            AuthService::new(users_service, sessions_service).with_password_policy(password_policy);
+
+        let request = tonic::Request::new(SignUpRequest {
+            username: "123456".to_owned(),
+            password: "short".to_owned(),
+            ..Default::default()
+        });
+
+        let result = auth_service.sign_up(request).await.unwrap().into_inner();
+
+        assert_eq!(result.status_code, StatusCode::WeakPassword as i32);
+        assert_eq!(
+            result
+                .password_violations
+                .iter()
+                .map(|violation| violation.r#type())
+                .collect::<Vec<_>>(),
+            vec![
+                PasswordViolationType::TooShort,
+                PasswordViolationType::MissingDigit
+            ]
+        );
+        assert_eq!(
+            result.password_violations[0].message,
+            "Password must be at least 8 characters"
+        );
+
+        let request = tonic::Request::new(SignUpRequest {
+            username: "123456".to_owned(),
+            password: "long enough 1".to_owned(),
+            ..Default::default()
+        });
+
+        let result = auth_service.sign_up(request).await.unwrap().into_inner();
+
+        assert_eq!(result.status_code, StatusCode::Success as i32);
+        assert!(result.password_violations.is_empty());
+    }
+
+    #[tokio::test]
+    async fn confirm_password_reset_should_keep_token_if_password_weak() {
+        let mut users_service = UsersImpl::default();
+
+        let _ = users_service
+            .create_user("123456".to_owned(), "654321".to_owned(), String::new())
+            .await;
+
+        let users_service = Box::new(RwLock::new(users_service));
+        let sessions_service = Box::new(RwLock::new(SessionsImpl::default()));
+        let mailer = TestMailer::default();
+
+        let auth_service = AuthService::new(users_service, sessions_service)
+            .with_mailer(Box::new(mailer.clone()))
+            .with_password_policy(PasswordPolicy::default());
+
+        let request = tonic::Request::new(RequestPasswordResetRequest {
+            username: "123456".to_owned(),
+        });
+
+        auth_service.request_password_reset(request).await.unwrap();
+
+        let reset_token = mailer.reset_tokens.lock().pop().unwrap();
+
+        let request = tonic::Request::new(ConfirmPasswordResetRequest {
+            reset_token: reset_token.clone(),
+            new_password: "qwerty123".to_owned(),
+        });
+
+        let result = auth_service
+            .confirm_password_reset(request)
+            .await
+            .unwrap()
+            .into_inner();
+
+        assert_eq!(result.status_code, StatusCode::WeakPassword as i32);
+        assert_eq!(
+            result.password_violations[0].r#type(),
+            PasswordViolationType::CommonPassword
+        );
+
+        let request = tonic::Request::new(ConfirmPasswordResetRequest {
+            reset_token,
+            new_password: "new password".to_owned(),
+        });
+
+        let result = auth_service.confirm_password_reset(request).await.unwrap();
+
+        assert_eq!(result.into_inner().status_code, StatusCode::Success as i32);
+    }
+    /// Reports passwords as breached if they're in the list, or fails if there is none.
+    struct TestBreachedPasswords(Option<Vec<&'static str>>);
+
+    #[tonic::async_trait]
+    impl BreachedPasswords for TestBreachedPasswords {
+        async fn is_breached(&self, password: &str) -> Result<bool, String> {
+            match &self.0 {
+                Some(breached) => Ok(breached.contains(&password)),
+                None => Err("Error, service unavailable".to_string()),
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn sign_up_should_reject_breached_password() {
+        let users_service = Box::new(RwLock::new(UsersImpl::default()));
+        let sessions_service = Box::new(RwLock::new(SessionsImpl::default()));
+
+        let auth_service = AuthService::new(users_service, sessions_service)
+            .with_breached_passwords(Box::new(TestBreachedPasswords(Some(vec!["breached"]))));
+
+        let request = tonic::Request::new(SignUpRequest {
+            username: "123456".to_owned(),
+            password: "breached".to_owned(),
+            ..Default::default()
+        });
+
+        let result = auth_service.sign_up(request).await.unwrap().into_inner();
+
+        assert_eq!(result.status_code, StatusCode::WeakPassword as i32);
+        assert_eq!(
+            result.password_violations[0].r#type(),
+            PasswordViolationType::BreachedPassword
+        );
+
+        let request = tonic::Request::new(SignUpRequest {
+            username: "123456".to_owned(),
+            password: "not breached".to_owned(),
+            ..Default::default()
+        });
+
+        let result = auth_service.sign_up(request).await.unwrap().into_inner();
+
+        assert_eq!(result.status_code, StatusCode::Success as i32);
+    }
+
+    #[tokio::test]
+    async fn sign_up_should_succeed_if_breach_check_fails() {
+        let users_service = Box::new(RwLock::new(UsersImpl::default()));
+        let sessions_service = Box::new(RwLock::new(SessionsImpl::default()));
+
+        let auth_service = AuthService::new(users_service, sessions_service)
+            .with_breached_passwords(Box::new(TestBreachedPasswords(None)));
+
+        let request = tonic::Request::new(SignUpRequest {
+            username: "123456".to_owned(),
+            password: "654321".to_owned(),
+            ..Default::default()
+        });
+
+        let result = auth_service.sign_up(request).await.unwrap().into_inner();
+
+        assert_eq!(result.status_code, StatusCode::Success as i32);
+    }
+    #[tokio::test]
+    async fn sign_in_should_be_rate_limited_per_username() {
+        let users_service = Box::new(RwLock::new(UsersImpl::default()));
+        let sessions_service = Box::new(RwLock::new(SessionsImpl::default()));
+
+        let rate_limiter = TokenBucketLimiter::new(RateLimit {
+            burst: 2,
+            per_minute: 1,
+        });
+        let auth_service = AuthService::new(users_service, sessions_service)
+            .with_sign_in_rate_limiter(Box::new(Mutex::new(rate_limiter)));
+
+        let sign_in = |username: &str| {
+            tonic::Request::new(SignInRequest {
+                username: username.to_owned(),
+                password: "654321".to_owned(),
+                ..Default::default()
+            })
+        };
+
+        assert!(auth_service.sign_in(sign_in("123456")).await.is_ok());
+        assert!(auth_service.sign_in(sign_in("123456")).await.is_ok());
+
+        let status = auth_service.sign_in(sign_in("123456")).await.unwrap_err();
+
+        assert_eq!(status.code(), tonic::Code::ResourceExhausted);
+        assert_eq!(status.metadata().get("retry-after").unwrap(), "60");
+
+        // Other usernames aren't affected.
+        assert!(auth_service.sign_in(sign_in("654321")).await.is_ok());
+    }
+    #[test]
+    fn client_identity_should_come_from_request_extensions() {
+        let mut request = tonic::Request::new(());
+        assert_eq!(client_identity(&request), None);
 
-        assert_eq!(result.into_inner().status_code, StatusCode::Success.into());
+        request
+            .extensions_mut()
+            .insert(ClientIdentity("users.internal".to_owned()));
+        assert_eq!(client_identity(&request), Some("users.internal"));
     }
 }
@@ -0,0 +1,380 @@
+use std::collections::BTreeMap;
+
+use crate::auth::AuthService;
+use crate::error::{AuthError, StorageError};
+use crate::secret::Secret;
+use crate::sessions::{decode, encode, from_millis, to_millis, SessionsImpl};
+use crate::users::{ImportedPassword, ImportedUser, User, UserCursor, UserFilter};
+
+/// Version of the backup format, written on a backup's first line. Backups of other versions are
+/// refused rather than misread.
+pub const BACKUP_FORMAT_VERSION: u32 = 1;
+
+/// Starts the first line of a backup, followed by the format version.
+const BACKUP_HEADER: &str = "auth-backup";
+
+/// Users `write_backup` reads from the backend at a time.
+const BACKUP_USERS_PAGE_SIZE: usize = 500;
+
+/// A backup written by `write_backup`.
+pub struct Backup {
+    pub contents: String,
+    pub users: usize,
+    pub sessions: usize,
+}
+
+/// Writes every user, deleted ones included, and the sessions and refresh tokens if the sessions
+/// backend can be backed up. Each is a tab separated line after a header line with the format
+/// version. Users keep their password hash, so they sign in as before once restored.
+pub async fn write_backup(auth_service: &AuthService) -> Result<Backup, AuthError> {
+    let mut contents = format!("{BACKUP_HEADER}\t{BACKUP_FORMAT_VERSION}\n");
+    let mut users = 0;
+
+    // Page through the users so SQL backends never load more than a page at once.
+    let users_service = auth_service.users().read().await;
+    let filter = UserFilter::default();
+    let mut after: Option<UserCursor> = None;
+    loop {
+        let page = users_service
+            .search_users(&filter, after.as_ref(), BACKUP_USERS_PAGE_SIZE)
+            .await;
+        after = page.last().map(UserCursor::from);
+        let last_page = page.len() < BACKUP_USERS_PAGE_SIZE;
+
+        for user in page {
+            // Searches don't load attributes.
+            let user = users_service
+                .get_user(user.user_uuid)
+                .await
+                .ok_or(StorageError::UserNotFound)?;
+            contents.push_str(&user_line(&user));
+            users += 1;
+        }
+
+        if last_page {
+            break;
+        }
+    }
+    drop(users_service);
+
+    let sessions_backup = auth_service.sessions().read().await.backup().await;
+    let sessions = match sessions_backup {
+        Some(sessions_backup) => {
+            contents.push_str(&sessions_backup);
+            sessions_backup
+                .lines()
+                .filter(|line| line.starts_with("session\t"))
+                .count()
+        }
+        None => 0,
+    };
+
+    Ok(Backup {
+        contents,
+        users,
+        sessions,
+    })
+}
+
+fn user_line(user: &User) -> String {
+    let attributes: Vec<String> = user
+        .attributes
+        .iter()
+        .map(|(key, value)| format!("{}={}", encode(key), encode(value)))
+        .collect();
+    let line = [
+        "user",
+        &encode(&user.user_uuid),
+        &encode(&user.username),
+        &encode(&user.email),
+        &encode(&user.display_name),
+        &to_millis(user.created_at),
+        &encode(user.password_hash()),
+        &user.deleted_at.map(to_millis).unwrap_or_default(),
+        if user.disabled { "1" } else { "0" },
+        &attributes.join(","),
+    ]
+    .join("\t");
+    line + "\n"
+}
+
+struct BackedUpUser {
+    user: ImportedUser,
+    attributes: BTreeMap<String, String>,
+    deleted: bool,
+    disabled: bool,
+}
+
+/// A backup read by `read_backup`, checked in full before anything is restored.
+pub struct ParsedBackup {
+    users: Vec<BackedUpUser>,
+    /// The session and refresh token lines, as the sessions backend wrote them.
+    sessions: String,
+    session_count: usize,
+}
+
+/// What `ParsedBackup::restore` restored.
+#[derive(Debug, PartialEq, Eq)]
+pub struct Restored {
+    pub users: usize,
+    /// Users whose uuid, username or email was taken, e.g. because they weren't lost.
+    pub skipped_users: usize,
+    /// Sessions in the backup. Those that have expired since aren't restored.
+    pub sessions: usize,
+}
+
+/// Reads a backup written by `write_backup`, failing on the first invalid line.
+pub fn read_backup(backup: &str) -> Result<ParsedBackup, String> {
+    let mut lines = backup.lines().enumerate();
+    let header: Vec<&str> = match lines.next() {
+        Some((_, line)) => line.split('\t').collect(),
+        None => Vec::new(),
+    };
+    match header.as_slice() {
+        [BACKUP_HEADER, version] if *version == BACKUP_FORMAT_VERSION.to_string() => (),
+        [BACKUP_HEADER, version] => {
+            return Err(format!(
+                "Error, unsupported backup format version {version}"
+            ))
+        }
+        _ => return Err("Error, not a backup".to_owned()),
+    }
+
+    let mut parsed = ParsedBackup {
+        users: Vec::new(),
+        sessions: String::new(),
+        session_count: 0,
+    };
+    // Session lines are checked by loading them into a throwaway store.
+    let mut sessions_check = SessionsImpl::default();
+
+    for (number, line) in lines {
+        let invalid = || format!("Error, invalid backup line {}", number + 1);
+        let fields: Vec<&str> = line.split('\t').collect();
+
+        match fields.as_slice() {
+            [] | [""] => (),
+            ["user", user_uuid, username, email, display_name, created_at, password_hash, deleted_at, disabled, attributes] =>
+            {
+                let attributes = match *attributes {
+                    "" => BTreeMap::new(),
+                    attributes => attributes
+                        .split(',')
+                        .map(|attribute| {
+                            let (key, value) = attribute.split_once('=')?;
+                            Some((decode(key)?, decode(value)?))
+                        })
+                        .collect::<Option<_>>()
+                        .ok_or_else(invalid)?,
+                };
+                // Only read to check it, restored users are deleted anew
+                let deleted = match *deleted_at {
+                    "" => false,
+                    deleted_at => {
+                        from_millis(deleted_at).ok_or_else(invalid)?;
+                        true
+                    }
+                };
+                parsed.users.push(BackedUpUser {
+                    user: ImportedUser {
+                        user_uuid: Some(decode(user_uuid).ok_or_else(invalid)?),
+                        username: decode(username).ok_or_else(invalid)?,
+                        email: decode(email).ok_or_else(invalid)?,
+                        display_name: Some(decode(display_name).ok_or_else(invalid)?),
+                        created_at: Some(from_millis(created_at).ok_or_else(invalid)?),
+                        password: ImportedPassword::Hashed(Secret::new(
+                            decode(password_hash).ok_or_else(invalid)?,
+                        )),
+                    },
+                    attributes,
+                    deleted,
+                    disabled: match *disabled {
+                        "0" => false,
+                        "1" => true,
+                        _ => return Err(invalid()),
+                    },
+                });
+            }
+            [kind @ ("session" | "refresh"), ..] => {
+                sessions_check.restore(line).map_err(|_| invalid())?;
+                parsed.sessions.push_str(line);
+                parsed.sessions.push('\n');
+                if *kind == "session" {
+                    parsed.session_count += 1;
+                }
+            }
+            _ => return Err(invalid()),
+        }
+    }
+
+    Ok(parsed)
+}
+
+impl ParsedBackup {
+    /// Restores the sessions, then the users. Users already stored are skipped rather than
+    /// overwritten. Deleted users are deleted again once restored, so they can be restored for the
+    /// whole deletion grace period from then on.
+    pub async fn restore(self, auth_service: &AuthService) -> Result<Restored, AuthError> {
+        // First, as the sessions backend may not support it, so nothing is restored then.
+        if !self.sessions.is_empty() {
+            auth_service
+                .sessions()
+                .write()
+                .await
+                .restore_backup(&self.sessions)
+                .await?;
+        }
+
+        let mut users_service = auth_service.users().write().await;
+        let imported = self.users.iter().map(|user| user.user.clone()).collect();
+        let results = users_service.import_users(imported).await;
+
+        let mut restored = Restored {
+            users: 0,
+            skipped_users: 0,
+            sessions: self.session_count,
+        };
+        for (user, result) in self.users.into_iter().zip(results) {
+            let user_uuid = user.user.user_uuid.unwrap_or_default();
+            match result {
+                Ok(()) => (),
+                Err(e @ StorageError::Backend(_)) => return Err(e.into()),
+                Err(e) => {
+                    tracing::warn!(user_uuid, error = %e, "Skipped restoring user");
+                    restored.skipped_users += 1;
+                    continue;
+                }
+            }
+
+            for (key, value) in user.attributes {
+                users_service
+                    .set_user_attribute(user_uuid.clone(), key, value)
+                    .await?;
+            }
+            if user.disabled {
+                users_service
+                    .set_user_disabled(user_uuid.clone(), true)
+                    .await?;
+            }
+            if user.deleted {
+                users_service.soft_delete_user(user_uuid).await?;
+            }
+            restored.users += 1;
+        }
+
+        Ok(restored)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::sync::RwLock;
+
+    use super::*;
+    use crate::sessions::ClientMetadata;
+    use crate::token_signing::TokenSigner;
+    use crate::users::UsersImpl;
+
+    fn auth_service() -> AuthService {
+        // Restored session tokens only verify with the keys they were signed with
+        let signer = TokenSigner::new(vec![b"0123456789abcdef".to_vec()]).unwrap();
+        AuthService::new(
+            Box::new(RwLock::new(UsersImpl::default())),
+            Box::new(RwLock::new(
+                SessionsImpl::default().with_token_signer(signer),
+            )),
+        )
+    }
+
+    #[tokio::test]
+    async fn should_restore_backup() {
+        let backed_up = auth_service();
+        let user_uuid = {
+            let mut users_service = backed_up.users().write().await;
+            for username in ["alice", "bob", "carol"] {
+                users_service
+                    .create_user(
+                        username.to_owned(),
+                        "password".to_owned(),
+                        format!("{username}@example.com"),
+                    )
+                    .await
+                    .unwrap();
+            }
+            let alice = users_service
+                .lookup_user_uuid("alice".to_owned())
+                .await
+                .unwrap();
+            let bob = users_service
+                .lookup_user_uuid("bob".to_owned())
+                .await
+                .unwrap();
+            users_service
+                .set_user_attribute(alice.clone(), "locale".to_owned(), "en\tGB,=".to_owned())
+                .await
+                .unwrap();
+            users_service
+                .set_user_disabled(alice.clone(), true)
+                .await
+                .unwrap();
+            users_service.soft_delete_user(bob).await.unwrap();
+            alice
+        };
+        let session_token = backed_up
+            .sessions()
+            .write()
+            .await
+            .create_session(&user_uuid, ClientMetadata::default())
+            .await
+            .unwrap();
+
+        let backup = write_backup(&backed_up).await.unwrap();
+        assert_eq!((backup.users, backup.sessions), (3, 1));
+
+        let restored = auth_service();
+        let parsed = read_backup(&backup.contents).unwrap();
+        assert_eq!(
+            parsed.restore(&restored).await.unwrap(),
+            Restored {
+                users: 3,
+                skipped_users: 0,
+                sessions: 1,
+            }
+        );
+
+        let users_service = restored.users().read().await;
+        let alice = users_service.get_user(user_uuid.clone()).await.unwrap();
+        assert_eq!(alice.attributes["locale"], "en\tGB,=");
+        assert!(alice.disabled);
+        assert!(users_service
+            .verify_password("carol".to_owned(), "password".to_owned())
+            .await
+            .is_some());
+        assert_eq!(users_service.count_users().await.unwrap(), 2);
+        let session = restored
+            .sessions()
+            .read()
+            .await
+            .peek_session(&session_token)
+            .await
+            .unwrap();
+        assert_eq!(session.user_uuid, user_uuid.as_str());
+
+        // Restoring again skips the users it already has
+        let parsed = read_backup(&backup.contents).unwrap();
+        drop(users_service);
+        let again = parsed.restore(&restored).await.unwrap();
+        assert_eq!((again.users, again.skipped_users), (0, 3));
+    }
+
+    #[test]
+    fn should_reject_invalid_backups() {
+        assert!(read_backup("").is_err());
+        assert!(read_backup("auth-backup\t2\n").is_err());
+        assert!(read_backup("auth-backup\t1\nuser\tonly\n").is_err());
+        assert!(read_backup("auth-backup\t1\nsession\tonly\n").is_err());
+
+        let parsed = read_backup("auth-backup\t1\n\n").unwrap();
+        assert!(parsed.users.is_empty());
+    }
+}
@@ -0,0 +1,116 @@
+// Only `HibpChecker` (behind the `breached-passwords` feature) parses range responses outside of
+// tests.
+#![cfg_attr(not(feature = "breached-passwords"), allow(dead_code))]
+
+use std::time::Duration;
+
+#[cfg(feature = "breached-passwords")]
+pub use hibp::HibpChecker;
+
+/// How long sign up and password resets wait for the breach check by default before giving up
+/// on it.
+pub const DEFAULT_BREACH_CHECK_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Checks whether a password has appeared in a known data breach.
+#[tonic::async_trait]
+pub trait BreachedPasswords {
+    /// Fails if the check couldn't be made, e.g. because the service is unreachable.
+    async fn is_breached(&self, password: &str) -> Result<bool, String>;
+}
+
+/// Whether a range response, one `SUFFIX:COUNT` line per hash starting with the requested
+/// prefix, lists `suffix`. Padding entries have a count of zero and never match.
+pub fn range_contains(body: &str, suffix: &str) -> bool {
+    body.lines().any(|line| match line.trim().split_once(':') {
+        Some((line_suffix, count)) => {
+            line_suffix.eq_ignore_ascii_case(suffix)
+                && count.trim().parse::<u64>().is_ok_and(|count| count > 0)
+        }
+        None => false,
+    })
+}
+
+#[cfg(feature = "breached-passwords")]
+mod hibp {
+    use std::time::Duration;
+
+    use sha1::{Digest, Sha1};
+
+    use super::{range_contains, BreachedPasswords};
+
+    /// Queries the HaveIBeenPwned range API.
+    ///
+    /// Only the first 5 hex characters of the password's SHA-1 hash are sent, and every hash
+    /// sharing them comes back, so the service never learns which password was checked
+    /// (k-anonymity).
+    pub struct HibpChecker {
+        http: reqwest::Client,
+        timeout: Duration,
+    }
+
+    impl HibpChecker {
+        const RANGE_URL: &'static str = "https://api.pwnedpasswords.com/range/";
+
+        /// Gives up on requests taking longer than `timeout`.
+        pub fn new(timeout: Duration) -> Self {
+            Self {
+                http: reqwest::Client::new(),
+                timeout,
+            }
+        }
+    }
+
+    #[tonic::async_trait]
+    impl BreachedPasswords for HibpChecker {
+        async fn is_breached(&self, password: &str) -> Result<bool, String> {
+            let hash: String = Sha1::digest(password.as_bytes())
+                .iter()
+                .map(|byte| format!("{byte:02X}"))
+                .collect();
+            let (prefix, suffix) = hash.split_at(5);
+
+            // Padding hides the response size, which would otherwise narrow down the prefix.
+            let request = async {
+                self.http
+                    .get(format!("{}{prefix}", Self::RANGE_URL))
+                    .header("Add-Padding", "true")
+                    .header("User-Agent", "microservices_rs-auth")
+                    .send()
+                    .await
+                    .and_then(|response| response.error_for_status())?
+                    .text()
+                    .await
+            };
+
+            let body = tokio::time::timeout(self.timeout, request)
+                .await
+                .map_err(|_| "Error, breached password check timed out".to_string())?
+                .map_err(|e| format!("Failed to check for breached password.\n{e:?}"))?;
+
+            Ok(range_contains(&body, suffix))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn should_find_suffix_in_range() {
+        let body = "0018A45C4D1DEF81644B54AB7F969B88D65:1\r\n\
+                    00D4F6E8FA6EECAD2A3AA415EEC418D38EC:2\r\n\
+                    011053FD0102E94D6AE2F8B83D76FAF94F6:0";
+
+        assert!(range_contains(body, "00d4f6e8fa6eecad2a3aa415eec418d38ec"));
+        assert!(!range_contains(body, "1E4C9B93F3F0682250B6CF8331B7EE68FD8"));
+    }
+
+    #[test]
+    fn should_ignore_padding_entries() {
+        let body = "011053FD0102E94D6AE2F8B83D76FAF94F6:0";
+
+        assert!(!range_contains(body, "011053FD0102E94D6AE2F8B83D76FAF94F6"));
+        assert!(!range_contains("not a range", "NOT A RANGE"));
+    }
+}
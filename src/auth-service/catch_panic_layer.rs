@@ -0,0 +1,136 @@
+use std::any::Any;
+use std::future::Future;
+use std::panic::{self, AssertUnwindSafe};
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+use tonic::body::BoxBody;
+use tonic::Status;
+use tower::{Layer, Service};
+
+/// Fails requests whose handler panicked as `INTERNAL`, logging the panic, rather than dropping
+/// the client's connection with every other request on it. Services locked with tokio mutexes
+/// stay usable, as those aren't poisoned by the panic.
+#[derive(Clone, Copy)]
+pub struct CatchPanicLayer;
+
+impl<S> Layer<S> for CatchPanicLayer {
+    type Service = CatchPanic<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        CatchPanic { inner }
+    }
+}
+
+#[derive(Clone)]
+pub struct CatchPanic<S> {
+    inner: S,
+}
+
+impl<S, B> Service<http::Request<B>> for CatchPanic<S>
+where
+    S: Service<http::Request<B>, Response = http::Response<BoxBody>> + Clone + Send + 'static,
+    S::Future: Send + 'static,
+    B: Send + 'static,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, request: http::Request<B>) -> Self::Future {
+        // The clone may not be ready, so call the instance `poll_ready` was called on.
+        let clone = self.inner.clone();
+        let mut inner = std::mem::replace(&mut self.inner, clone);
+
+        let mut response = match panic::catch_unwind(AssertUnwindSafe(|| inner.call(request))) {
+            Ok(response) => Box::pin(response),
+            Err(panic) => {
+                let response = internal_error(panic);
+                return Box::pin(async move { Ok(response) });
+            }
+        };
+        Box::pin(std::future::poll_fn(move |cx| {
+            match panic::catch_unwind(AssertUnwindSafe(|| response.as_mut().poll(cx))) {
+                Ok(poll) => poll,
+                Err(panic) => Poll::Ready(Ok(internal_error(panic))),
+            }
+        }))
+    }
+}
+
+fn internal_error(panic: Box<dyn Any + Send>) -> http::Response<BoxBody> {
+    let message = match panic.downcast::<String>() {
+        Ok(message) => *message,
+        Err(panic) => match panic.downcast::<&str>() {
+            Ok(message) => message.to_string(),
+            Err(_) => "unknown panic".to_owned(),
+        },
+    };
+    tracing::error!(panic = message, "Request handler panicked");
+    Status::internal("Internal error").to_http()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Panics while handling requests to `/panic`, before returning the response future for
+    /// requests to `/panic-early`.
+    #[derive(Clone)]
+    struct Fragile;
+
+    impl Service<http::Request<()>> for Fragile {
+        type Response = http::Response<BoxBody>;
+        type Error = std::convert::Infallible;
+        type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send>>;
+
+        fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+            Poll::Ready(Ok(()))
+        }
+
+        fn call(&mut self, request: http::Request<()>) -> Self::Future {
+            let path = request.uri().path().to_owned();
+            if path == "/panic-early" {
+                panic!("Panicked early");
+            }
+            Box::pin(async move {
+                tokio::task::yield_now().await;
+                if path == "/panic" {
+                    panic!("Panicked");
+                }
+                Ok(http::Response::new(tonic::body::empty_body()))
+            })
+        }
+    }
+
+    fn grpc_status(response: &http::Response<BoxBody>) -> Option<&str> {
+        response
+            .headers()
+            .get("grpc-status")
+            .map(|status| status.to_str().unwrap())
+    }
+
+    async fn call(service: &mut CatchPanic<Fragile>, path: &str) -> http::Response<BoxBody> {
+        let request = http::Request::builder().uri(path).body(()).unwrap();
+        service.call(request).await.unwrap()
+    }
+
+    #[tokio::test]
+    async fn should_fail_panicking_requests_as_internal() {
+        let mut service = CatchPanicLayer.layer(Fragile);
+        let internal = (tonic::Code::Internal as i32).to_string();
+
+        let response = call(&mut service, "/panic").await;
+        assert_eq!(grpc_status(&response), Some(internal.as_str()));
+        let response = call(&mut service, "/panic-early").await;
+        assert_eq!(grpc_status(&response), Some(internal.as_str()));
+
+        // Later requests are still served
+        let response = call(&mut service, "/ok").await;
+        assert_eq!(grpc_status(&response), None);
+    }
+}
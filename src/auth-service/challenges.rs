@@ -0,0 +1,164 @@
+use std::collections::HashMap;
+use std::time::{Duration, SystemTime};
+
+use parking_lot::Mutex;
+use sha2::{Digest, Sha256};
+use uuid::Uuid;
+
+/// Leading zero bits required by default, roughly a million hashes to solve.
+pub const DEFAULT_CHALLENGE_DIFFICULTY: u32 = 20;
+/// How long an issued challenge can be solved for.
+pub const CHALLENGE_LIFETIME: Duration = Duration::from_secs(60 * 5);
+
+/// A challenge handed out by `GetSignUpChallenge`.
+#[derive(Clone, Debug, Default)]
+pub struct Challenge {
+    pub challenge_id: String,
+    /// Leading zero bits required of `sha256("<challenge_id>:<solution>")`.
+    pub difficulty: u32,
+}
+
+/// Anti-bot check `SignUp` requests must pass when one is configured.
+#[tonic::async_trait]
+pub trait SignUpChallenge {
+    /// Issues a challenge for the client to solve. Verifiers whose challenges are issued
+    /// elsewhere, such as a CAPTCHA widget, return `None`.
+    async fn issue(&self) -> Option<Challenge>;
+    /// Checks the client's solution. For CAPTCHA verifiers `solution` is the widget's token.
+    async fn verify(&self, challenge_id: &str, solution: &str) -> bool;
+}
+
+/// Hashcash style proof of work. Each challenge can only be redeemed once.
+pub struct ProofOfWork {
+    difficulty: u32,
+    /// Not poisoned by panics, so sign ups keep working after one.
+    id_to_expiry: Mutex<HashMap<String, SystemTime>>,
+}
+
+impl ProofOfWork {
+    pub fn new(difficulty: u32) -> Self {
+        Self {
+            difficulty,
+            id_to_expiry: Mutex::new(HashMap::new()),
+        }
+    }
+}
+
+impl Default for ProofOfWork {
+    fn default() -> Self {
+        Self::new(DEFAULT_CHALLENGE_DIFFICULTY)
+    }
+}
+
+#[tonic::async_trait]
+impl SignUpChallenge for ProofOfWork {
+    async fn issue(&self) -> Option<Challenge> {
+        let challenge_id = Uuid::new_v4().to_string();
+        let now = SystemTime::now();
+
+        let mut id_to_expiry = self.id_to_expiry.lock();
+
+        // Drop challenges that were never solved so they don't pile up.
+        id_to_expiry.retain(|_, expires_at| *expires_at > now);
+        id_to_expiry.insert(challenge_id.clone(), now + CHALLENGE_LIFETIME);
+
+        Some(Challenge {
+            challenge_id,
+            difficulty: self.difficulty,
+        })
+    }
+
+    async fn verify(&self, challenge_id: &str, solution: &str) -> bool {
+        let expires_at = self.id_to_expiry.lock().remove(challenge_id);
+
+        match expires_at {
+            Some(expires_at) if expires_at > SystemTime::now() => {
+                leading_zero_bits(challenge_id, solution) >= self.difficulty
+            }
+            _ => false,
+        }
+    }
+}
+
+/// Counts the leading zero bits of `sha256("<challenge_id>:<solution>")`.
+fn leading_zero_bits(challenge_id: &str, solution: &str) -> u32 {
+    let hash = Sha256::digest(format!("{}:{}", challenge_id, solution));
+
+    let mut bits = 0;
+    for byte in hash {
+        bits += byte.leading_zeros();
+        if byte != 0 {
+            break;
+        }
+    }
+
+    bits
+}
+
+/// Brute forces a solution the way clients do.
+#[cfg(test)]
+pub fn solve(challenge: &Challenge) -> String {
+    (0u64..)
+        .map(|nonce| nonce.to_string())
+        .find(|nonce| leading_zero_bits(&challenge.challenge_id, nonce) >= challenge.difficulty)
+        .unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn should_verify_solution_once() {
+        let proof_of_work = ProofOfWork::new(8);
+        let challenge = proof_of_work.issue().await.unwrap();
+        let solution = solve(&challenge);
+
+        assert!(
+            proof_of_work
+                .verify(&challenge.challenge_id, &solution)
+                .await
+        );
+        assert!(
+            !proof_of_work
+                .verify(&challenge.challenge_id, &solution)
+                .await
+        );
+    }
+
+    #[tokio::test]
+    async fn should_reject_wrong_solution() {
+        let proof_of_work = ProofOfWork::new(32);
+        let challenge = proof_of_work.issue().await.unwrap();
+
+        assert!(!proof_of_work.verify(&challenge.challenge_id, "0").await);
+        assert!(!proof_of_work.verify("unknown", "0").await);
+    }
+
+    #[tokio::test]
+    async fn should_keep_working_after_panic_holding_lock() {
+        let proof_of_work = std::sync::Arc::new(ProofOfWork::new(0));
+        let panicking = proof_of_work.clone();
+        std::thread::spawn(move || {
+            let _id_to_expiry = panicking.id_to_expiry.lock();
+            panic!("Panicked while holding the lock");
+        })
+        .join()
+        .unwrap_err();
+
+        let challenge = proof_of_work.issue().await.unwrap();
+        assert!(proof_of_work.verify(&challenge.challenge_id, "0").await);
+    }
+
+    #[tokio::test]
+    async fn should_reject_expired_challenge() {
+        let proof_of_work = ProofOfWork::new(0);
+        let challenge = proof_of_work.issue().await.unwrap();
+        proof_of_work
+            .id_to_expiry
+            .lock()
+            .insert(challenge.challenge_id.clone(), SystemTime::now());
+
+        assert!(!proof_of_work.verify(&challenge.challenge_id, "0").await);
+    }
+}
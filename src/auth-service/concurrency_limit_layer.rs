@@ -0,0 +1,162 @@
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+
+use tokio::sync::Semaphore;
+use tonic::body::BoxBody;
+use tonic::Status;
+use tower::{Layer, Service};
+
+/// Caps how many requests are handled at once across all connections. Requests over the cap are
+/// rejected as `UNAVAILABLE` straight away rather than queued, so clients can back off or try
+/// another instance. Without a cap, requests are passed straight on. Health checks are never
+/// rejected, so a busy server isn't taken for a dead one.
+#[derive(Clone)]
+pub struct ConcurrencyLimitLayer {
+    permits: Option<Arc<Semaphore>>,
+}
+
+impl ConcurrencyLimitLayer {
+    pub fn new(max_concurrent_requests: Option<usize>) -> Self {
+        Self {
+            permits: max_concurrent_requests.map(|max| Arc::new(Semaphore::new(max))),
+        }
+    }
+}
+
+impl<S> Layer<S> for ConcurrencyLimitLayer {
+    type Service = ConcurrencyLimit<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        ConcurrencyLimit {
+            inner,
+            permits: self.permits.clone(),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct ConcurrencyLimit<S> {
+    inner: S,
+    permits: Option<Arc<Semaphore>>,
+}
+
+impl<S, B> Service<http::Request<B>> for ConcurrencyLimit<S>
+where
+    S: Service<http::Request<B>, Response = http::Response<BoxBody>> + Clone + Send + 'static,
+    S::Future: Send + 'static,
+    B: Send + 'static,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, request: http::Request<B>) -> Self::Future {
+        let permits = self
+            .permits
+            .clone()
+            .filter(|_| !request.uri().path().starts_with("/grpc.health.v1.Health/"));
+        let permit = match permits.map(Semaphore::try_acquire_owned).transpose() {
+            Ok(permit) => permit,
+            Err(_) => {
+                let status = Status::unavailable("Server is busy, try again later");
+                return Box::pin(async move { Ok(status.to_http()) });
+            }
+        };
+
+        // The clone may not be ready, so call the instance `poll_ready` was called on.
+        let clone = self.inner.clone();
+        let mut inner = std::mem::replace(&mut self.inner, clone);
+        let response = inner.call(request);
+        Box::pin(async move {
+            let response = response.await;
+            drop(permit);
+            response
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::sync::oneshot;
+
+    use super::*;
+
+    /// Answers once the test lets it.
+    #[derive(Clone)]
+    struct Blocked {
+        release: Arc<std::sync::Mutex<Vec<oneshot::Receiver<()>>>>,
+    }
+
+    impl Service<http::Request<()>> for Blocked {
+        type Response = http::Response<BoxBody>;
+        type Error = std::convert::Infallible;
+        type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send>>;
+
+        fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+            Poll::Ready(Ok(()))
+        }
+
+        fn call(&mut self, _request: http::Request<()>) -> Self::Future {
+            let release = self.release.lock().unwrap().pop();
+            Box::pin(async move {
+                if let Some(release) = release {
+                    let _ = release.await;
+                }
+                Ok(http::Response::new(tonic::body::empty_body()))
+            })
+        }
+    }
+
+    fn request(path: &str) -> http::Request<()> {
+        http::Request::builder().uri(path).body(()).unwrap()
+    }
+
+    fn grpc_status(response: &http::Response<BoxBody>) -> Option<&str> {
+        response
+            .headers()
+            .get("grpc-status")
+            .map(|status| status.to_str().unwrap())
+    }
+
+    #[tokio::test]
+    async fn should_reject_requests_over_limit() {
+        let (release, blocked) = oneshot::channel();
+        let mut service = ConcurrencyLimitLayer::new(Some(1)).layer(Blocked {
+            release: Arc::new(std::sync::Mutex::new(vec![blocked])),
+        });
+
+        let in_flight = service.call(request("/authentication.Auth/SignIn"));
+
+        let response = service
+            .call(request("/authentication.Auth/SignIn"))
+            .await
+            .unwrap();
+        assert_eq!(
+            grpc_status(&response),
+            Some((tonic::Code::Unavailable as i32).to_string().as_str())
+        );
+
+        // Health checks get through regardless.
+        let response = service
+            .call(request("/grpc.health.v1.Health/Check"))
+            .await
+            .unwrap();
+        assert_eq!(grpc_status(&response), None);
+
+        // Finished requests free their slot.
+        release.send(()).unwrap();
+        let response = in_flight.await.unwrap();
+        assert_eq!(grpc_status(&response), None);
+        let response = service
+            .call(request("/authentication.Auth/SignIn"))
+            .await
+            .unwrap();
+        assert_eq!(grpc_status(&response), None);
+    }
+}
@@ -0,0 +1,2012 @@
+use std::net::{Ipv6Addr, SocketAddr};
+use std::num::NonZeroUsize;
+use std::time::Duration;
+
+use tonic::codec::CompressionEncoding;
+use tracing_subscriber::EnvFilter;
+
+use crate::access_log_layer::AccessLogFormat;
+use crate::breached_passwords::DEFAULT_BREACH_CHECK_TIMEOUT;
+use crate::challenges::DEFAULT_CHALLENGE_DIFFICULTY;
+use crate::idempotency::DEFAULT_IDEMPOTENCY_WINDOW;
+use crate::lockouts::{DEFAULT_LOCKOUT_DURATION, DEFAULT_LOCKOUT_THRESHOLD};
+use crate::logging::{LogFormat, DEFAULT_LOG_LEVEL};
+use crate::maintenance::Maintenance;
+use crate::password_hashing::{
+    default_hashing_concurrency, DEFAULT_ARGON2_ITERATIONS, DEFAULT_ARGON2_MEMORY_KIB,
+    DEFAULT_ARGON2_PARALLELISM, DEFAULT_PBKDF2_ROUNDS,
+};
+use crate::password_policy::PasswordPolicy;
+use crate::rate_limits::RateLimit;
+use crate::secret::Secret;
+use crate::sessions::{SessionLimit, SessionLimitPolicy, SessionPolicy, SESSION_CLEANUP_INTERVAL};
+use crate::settings::{Settings, DEFAULT_AUTH_SERVICE_PORT};
+use crate::storage_retry::DEFAULT_STORAGE_RETRY_BUDGET;
+use crate::user_ids::DEFAULT_NANOID_LENGTH;
+use crate::users::DEFAULT_DELETION_GRACE_PERIOD;
+
+/// An OpenID Connect provider whose ID tokens can be exchanged for sessions.
+#[derive(Clone, Debug, PartialEq)]
+pub struct OidcProvider {
+    /// Name clients refer to the provider by, e.g. `google`.
+    pub name: String,
+    /// Expected `iss` claim.
+    pub issuer: String,
+    /// Expected `aud` claim.
+    pub client_id: String,
+    /// Where the provider publishes its signing keys.
+    pub jwks_uri: String,
+}
+
+/// Keys used to sign stateless session tokens.
+#[derive(Clone, Debug, PartialEq)]
+pub enum JwtKeys {
+    Hs256 {
+        secret: Secret,
+    },
+    /// PEM encoded RSA keys.
+    Rs256 {
+        private_key_path: String,
+        public_key_path: String,
+    },
+}
+
+/// An LDAP or Active Directory server checking users' passwords.
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(not(feature = "ldap"), allow(dead_code))]
+pub struct LdapConfig {
+    /// e.g. `ldaps://directory.example.com`.
+    pub url: String,
+    /// Service account users are searched as. Searches are anonymous without one.
+    pub bind_dn: Option<String>,
+    pub bind_password: Secret,
+    /// Where user entries are searched for, e.g. `ou=people,dc=example,dc=com`.
+    pub base_dn: String,
+    /// Finds a user's entry, with `{username}` replaced by the escaped username.
+    pub user_filter: String,
+    pub username_attribute: String,
+    pub display_name_attribute: String,
+    pub email_attribute: String,
+    /// How long to wait to connect to the server.
+    pub timeout: Duration,
+}
+
+/// Caches validated sessions in front of the session store.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct SessionCacheConfig {
+    /// Sessions cached at most, the least recently validated ones being dropped first.
+    pub capacity: NonZeroUsize,
+    /// How long a validation is reused before the session store is asked again.
+    pub ttl: Duration,
+}
+
+/// Transport settings of the gRPC server. Unset ones keep the tonic and h2 defaults.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct TransportConfig {
+    /// Idle time before TCP keepalive probes are sent on accepted connections.
+    pub tcp_keepalive: Option<Duration>,
+    /// How often HTTP/2 pings are sent to check idle connections are still alive.
+    pub http2_keepalive_interval: Option<Duration>,
+    /// How long to wait for a ping to be acknowledged before closing the connection.
+    pub http2_keepalive_timeout: Option<Duration>,
+    /// HTTP/2 flow control window of each stream, in bytes.
+    pub initial_stream_window_size: Option<u32>,
+    /// HTTP/2 flow control window of each connection, in bytes.
+    pub initial_connection_window_size: Option<u32>,
+    /// How many requests each connection can have in flight at once.
+    pub max_concurrent_streams: Option<u32>,
+}
+
+/// PEM files the gRPC server terminates TLS with.
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(not(feature = "tls"), allow(dead_code))]
+pub struct TlsConfig {
+    /// Certificate chain, leaf certificate first.
+    pub cert_path: String,
+    /// PKCS#8, PKCS#1 (RSA) or SEC1 (EC) private key.
+    pub key_path: String,
+    /// How often the files are checked for changes. They're only loaded at startup without one.
+    pub reload_interval: Option<Duration>,
+    /// CA bundle client certificates must chain to. Clients don't need certificates without one.
+    pub client_ca_path: Option<String>,
+    /// DNS or URI subject alternative names client certificates may carry. Any name is accepted
+    /// when empty.
+    pub allowed_client_names: Vec<String>,
+}
+
+/// Where users are stored when they aren't kept in memory.
+#[derive(Clone, Debug, PartialEq)]
+pub enum DatabaseConfig {
+    Postgres {
+        url: String,
+        max_connections: u32,
+    },
+    /// An embedded database file, created on first run.
+    Sqlite {
+        path: String,
+    },
+}
+
+/// When the SQL databases' pending migrations are applied.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Migrations {
+    /// On startup, before serving.
+    Startup,
+    /// Only with `MIGRATE`, refusing to serve until then.
+    Manual,
+}
+
+/// An OpenTelemetry collector traces are exported to.
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(not(feature = "otel"), allow(dead_code))]
+pub struct OtlpConfig {
+    /// OTLP/gRPC endpoint, e.g. `http://collector:4317`.
+    pub endpoint: String,
+    /// Name the service's spans are reported under.
+    pub service_name: String,
+}
+
+/// A Sentry project panics and internal errors are reported to.
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(not(feature = "sentry"), allow(dead_code))]
+pub struct SentryConfig {
+    /// The project's client key, e.g. `https://<key>@o0.ingest.sentry.io/<project>`.
+    pub dsn: Secret,
+    /// Deployment reports are tagged with, e.g. `production`.
+    pub environment: Option<String>,
+}
+
+/// Algorithm and cost used to hash new passwords.
+#[derive(Clone, Debug, PartialEq)]
+pub enum PasswordHashing {
+    Pbkdf2 {
+        rounds: u32,
+    },
+    Argon2id {
+        memory_kib: u32,
+        iterations: u32,
+        parallelism: u32,
+    },
+}
+
+/// How the ids of new users are generated.
+#[derive(Clone, Debug, PartialEq)]
+pub enum UserIdFormat {
+    UuidV4,
+    UuidV7,
+    NanoId { length: usize },
+}
+
+/// Name spans are reported under by default.
+pub const DEFAULT_OTEL_SERVICE_NAME: &str = "auth";
+/// Connections pooled to the users database by default.
+pub const DEFAULT_DATABASE_MAX_CONNECTIONS: u32 = 10;
+
+/// Embedded database file used by the SQLite backends by default.
+pub const DEFAULT_SQLITE_PATH: &str = "auth.db";
+/// Matches OpenLDAP style entries, Active Directory needs `(sAMAccountName={username})`.
+pub const DEFAULT_LDAP_USER_FILTER: &str = "(uid={username})";
+pub const DEFAULT_LDAP_USERNAME_ATTRIBUTE: &str = "uid";
+pub const DEFAULT_LDAP_DISPLAY_NAME_ATTRIBUTE: &str = "cn";
+pub const DEFAULT_LDAP_EMAIL_ATTRIBUTE: &str = "mail";
+/// How long in-flight requests get to finish once shutdown starts by default.
+pub const DEFAULT_SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(30);
+/// How long handlers get to answer a request by default.
+pub const DEFAULT_REQUEST_TIMEOUT: Duration = Duration::from_secs(30);
+/// How many requests are handled at once by default.
+pub const DEFAULT_MAX_CONCURRENT_REQUESTS: usize = 256;
+/// How often TLS certificate and key files are checked for changes by default.
+pub const DEFAULT_TLS_RELOAD_INTERVAL: Duration = Duration::from_secs(60);
+/// How long to wait to connect to the LDAP server by default.
+pub const DEFAULT_LDAP_TIMEOUT: Duration = Duration::from_secs(5);
+/// How long cached session validations are reused by default.
+pub const DEFAULT_SESSION_CACHE_TTL: Duration = Duration::from_secs(5);
+
+/// Runtime configuration for the auth service.
+#[derive(Clone, Debug)]
+pub struct Config {
+    /// Which events are logged, as `EnvFilter` directives.
+    pub log_level: String,
+    /// Whether logs are human readable text or JSON lines.
+    pub log_format: LogFormat,
+    /// Export spans to this collector, continuing traces callers started.
+    pub otlp: Option<OtlpConfig>,
+    /// Report panics and internal errors to Sentry.
+    pub sentry: Option<SentryConfig>,
+    /// Where RPC metrics are served for Prometheus to scrape.
+    pub metrics_address: Option<SocketAddr>,
+    /// How RPCs are logged to stdout once answered, if at all.
+    pub access_log: Option<AccessLogFormat>,
+    /// Where the gRPC server listens for TCP connections. Only the Unix domain socket is served
+    /// without one.
+    pub bind_address: Option<SocketAddr>,
+    /// Unix domain socket the gRPC server also listens on, for clients on the same host.
+    pub unix_socket_path: Option<String>,
+    /// Where the admin API is served, on a listener of its own.
+    pub admin_bind_address: Option<SocketAddr>,
+    /// How long open connections are drained for on SIGTERM or SIGINT before they're closed.
+    pub shutdown_timeout: Duration,
+    /// Keepalive and HTTP/2 flow control settings of the gRPC server.
+    pub transport: TransportConfig,
+    /// How long handlers get to answer a request before it fails.
+    pub request_timeout: Option<Duration>,
+    /// Timeouts for methods overriding `request_timeout`, by method name, e.g. `SignIn`.
+    pub method_timeouts: Vec<(String, Duration)>,
+    /// Caps how many requests are handled at once, rejecting any more.
+    pub max_concurrent_requests: Option<usize>,
+    /// Compression accepted on requests and used on responses to clients that accept it.
+    pub grpc_compression: Option<CompressionEncoding>,
+    /// Whether the gRPC reflection service describes the served APIs to tools like `grpcurl`.
+    pub grpc_reflection: bool,
+    /// Serve gRPC over TLS instead of plaintext.
+    pub tls: Option<TlsConfig>,
+    pub oidc_providers: Vec<OidcProvider>,
+    /// Consecutive failed sign-ins after which an account is locked.
+    pub lockout_threshold: u32,
+    /// How long a locked account stays locked.
+    pub lockout_duration: Duration,
+    /// Throttles sign-in attempts for each username.
+    pub sign_in_rate_limit: Option<RateLimit>,
+    /// Throttles each client's requests across all methods.
+    pub request_rate_limit: Option<RateLimit>,
+    /// Throttles each client's requests to a method, by method name, e.g. `SignIn`.
+    pub method_rate_limits: Vec<(String, RateLimit)>,
+    /// Whether `SignUp` requires a solved proof of work challenge.
+    pub sign_up_challenge: bool,
+    /// Leading zero bits required of sign up challenge solutions.
+    pub sign_up_challenge_difficulty: u32,
+    /// Whether `SignIn` and `SignUp` answer the same whether or not a username exists.
+    pub anti_enumeration: bool,
+    /// Which calls are refused until the admin API switches maintenance off.
+    pub maintenance: Maintenance,
+    /// How long `SignUp` results are replayed to retries with the same idempotency key.
+    pub sign_up_idempotency_window: Duration,
+    /// How long sessions stay valid and whether they're renewed on use.
+    pub session_policy: SessionPolicy,
+    /// How often expired sessions are evicted.
+    pub session_cleanup_interval: Duration,
+    /// Caps how many sessions each user can have at once.
+    pub session_limit: Option<SessionLimit>,
+    /// Keys signing session tokens, current key first. A key is generated at startup if empty.
+    pub session_token_keys: Vec<Secret>,
+    /// Issue stateless JWT sessions signed with these keys instead of keeping sessions in memory.
+    pub jwt_sessions: Option<JwtKeys>,
+    /// File JWT revocations are written through to, so signed out sessions stay revoked across
+    /// restarts.
+    #[cfg_attr(not(feature = "jwt-sessions"), allow(dead_code))]
+    pub jwt_revocations_path: Option<String>,
+    /// File in-memory sessions are written through to, so they survive restarts.
+    pub session_store_path: Option<String>,
+    /// Reuse session validations for a short while instead of asking the session store each time.
+    pub session_cache: Option<SessionCacheConfig>,
+    /// Store users in this database instead of in memory.
+    pub users_database: Option<DatabaseConfig>,
+    /// How long database queries failing transiently are retried before giving up.
+    pub storage_retry_budget: Duration,
+    /// When pending migrations of the users and sessions databases are applied.
+    #[cfg_attr(not(any(feature = "postgres", feature = "sqlite")), allow(dead_code))]
+    pub database_migrations: Migrations,
+    /// Apply pending database migrations, then exit without serving.
+    pub migrate: bool,
+    /// TOML or JSON file of accounts and groups created on startup if missing.
+    pub seed_path: Option<String>,
+    /// Check passwords against this directory, provisioning its users in the users backend.
+    pub ldap: Option<LdapConfig>,
+    /// Store sessions in the SQLite database at this path instead of in memory.
+    pub sqlite_sessions_path: Option<String>,
+    /// How new passwords are hashed. Existing hashes are verified with whatever they were made with.
+    pub password_hashing: PasswordHashing,
+    /// How many passwords are hashed or checked at once, on threads apart from the RPC handlers.
+    pub password_hashing_concurrency: usize,
+    /// Suggest a hashing cost whose hashes take this long to verify, then exit without serving.
+    pub password_hash_benchmark: Option<Duration>,
+    /// How the ids of new users are generated.
+    pub user_id_format: UserIdFormat,
+    /// How long deleted accounts can be restored before they're purged.
+    pub deletion_grace_period: Duration,
+    /// Rules new passwords must follow.
+    pub password_policy: PasswordPolicy,
+    /// File listing more passwords to reject, one per line.
+    pub password_denylist_path: Option<String>,
+    /// Whether new passwords are checked against the HaveIBeenPwned breach corpus.
+    pub breached_password_check: bool,
+    /// How long to wait for the breach check before accepting the password anyway.
+    #[cfg_attr(not(feature = "breached-passwords"), allow(dead_code))]
+    pub breached_password_check_timeout: Duration,
+}
+
+impl Config {
+    /// Reads the configuration from flags, environment variables and the config file.
+    pub fn load(settings: &Settings) -> Result<Self, String> {
+        Self::from_vars(|key| settings.var(key))
+    }
+
+    /// Reads the configuration using `var` to look up each variable.
+    ///
+    /// Events at `LOG_LEVEL` (`info` by default) or above are logged to stdout, as text or with
+    /// `LOG_FORMAT=json` as JSON lines. It also takes per module levels, e.g.
+    /// `info,auth::sessions=debug`.
+    ///
+    /// Setting `OTEL_EXPORTER_OTLP_ENDPOINT` exports a span for each RPC and database operation
+    /// over OTLP/gRPC, named after `OTEL_SERVICE_NAME`. Callers' `traceparent` metadata is
+    /// honored, so the spans join their traces.
+    ///
+    /// Setting `SENTRY_DSN` reports panics and events logged at the error level to that Sentry
+    /// project, tagged with `SENTRY_ENVIRONMENT` if set.
+    ///
+    /// Setting `METRICS_ADDRESS`, e.g. `[::]:9090`, serves request counts by outcome and latencies
+    /// of each RPC at `/metrics` for Prometheus. Outcomes tell bad credentials, rate limits and
+    /// invalid requests from backend errors, so alerts can ignore users' mistakes.
+    ///
+    /// `ACCESS_LOG=common` writes a line for each RPC to stdout like web servers' Common Log
+    /// Format, `ACCESS_LOG=json` a JSON object, with the method, peer address, gRPC status code,
+    /// duration and the signed in user's uuid. It's off by default.
+    ///
+    /// The gRPC server listens on `BIND_ADDRESS`, all interfaces on port 50051 by default. Setting
+    /// `UNIX_SOCKET_PATH` also serves plaintext gRPC on a Unix domain socket, e.g. for a sidecar
+    /// gateway, and only there if `BIND_ADDRESS` is empty. On SIGTERM or SIGINT it stops
+    /// accepting connections and gives open ones `SHUTDOWN_TIMEOUT_SECS` to finish.
+    ///
+    /// The admin API, which manages users, sessions and lockouts, is only served on
+    /// `ADMIN_BIND_ADDRESS`, e.g. `127.0.0.1:50052`, and not at all without it. It must differ
+    /// from `BIND_ADDRESS` and be a loopback address, unless TLS is configured with
+    /// `TLS_CLIENT_CA_PATH` so operators connecting from other hosts need client certificates.
+    ///
+    /// Requests fail if they aren't answered in `REQUEST_TIMEOUT_SECS`, or per method in the comma
+    /// separated `METHOD_TIMEOUTS`, e.g. `SignIn:5,SignUp:10`. At most `MAX_CONCURRENT_REQUESTS`
+    /// are handled at once, any more are rejected. Setting either to 0 turns it off.
+    ///
+    /// Connections get TCP keepalive probes after `TCP_KEEPALIVE_SECS` idle, and HTTP/2 pings
+    /// every `HTTP2_KEEPALIVE_INTERVAL_SECS`, closing them if a ping isn't answered within
+    /// `HTTP2_KEEPALIVE_TIMEOUT_SECS`. `HTTP2_INITIAL_STREAM_WINDOW_SIZE` and
+    /// `HTTP2_INITIAL_CONNECTION_WINDOW_SIZE` set the flow control windows in bytes and
+    /// `HTTP2_MAX_CONCURRENT_STREAMS` how many requests each connection can have in flight. Unset,
+    /// they keep the library defaults.
+    ///
+    /// `GRPC_COMPRESSION=gzip` accepts gzip compressed requests and compresses responses to
+    /// clients that accept it. Messages aren't compressed by default (`none`).
+    ///
+    /// Setting `GRPC_REFLECTION=true` also serves the gRPC reflection service, so tools like
+    /// `grpcurl` can call the API without its proto files.
+    ///
+    /// Setting `TLS_CERT_PATH` and `TLS_KEY_PATH` to PEM files serves gRPC over TLS. The files are
+    /// loaded again when they change, checked every `TLS_RELOAD_INTERVAL_SECS` (0 to never).
+    /// Setting `TLS_CLIENT_CA_PATH` too requires callers to present a certificate issued by one of
+    /// its CAs, optionally limited to the comma separated `TLS_ALLOWED_CLIENT_NAMES`.
+    ///
+    /// OIDC providers are listed by name in `OIDC_PROVIDERS` (comma separated). Each provider is
+    /// then configured through `OIDC_<NAME>_ISSUER`, `OIDC_<NAME>_CLIENT_ID` and `OIDC_<NAME>_JWKS_URI`.
+    ///
+    /// Account lockout is configured with `LOCKOUT_THRESHOLD` and `LOCKOUT_DURATION_SECS`.
+    ///
+    /// Each username can attempt `SIGN_IN_RATE_LIMIT_BURST` sign-ins at once, regaining
+    /// `SIGN_IN_RATE_LIMIT_PER_MINUTE` attempts per minute. Setting either to 0 turns the limit off.
+    ///
+    /// Each client IP address can make `REQUEST_RATE_LIMIT_BURST` requests at once, regaining
+    /// `REQUEST_RATE_LIMIT_PER_MINUTE` per minute, when both are set. `METHOD_RATE_LIMITS` adds
+    /// limits for single methods as comma separated `<method>:<burst>:<per minute>` entries, e.g.
+    /// `SignIn:10:5,SignOut:100:60`.
+    ///
+    /// Setting `SIGN_UP_CHALLENGE=true` requires a proof of work on sign up, with
+    /// `SIGN_UP_CHALLENGE_DIFFICULTY` leading zero bits. Sign ups retried with the same idempotency
+    /// key within `SIGN_UP_IDEMPOTENCY_WINDOW_SECS` get the first attempt's result.
+    ///
+    /// Setting `ANTI_ENUMERATION=true` stops `SignIn` and `SignUp` from revealing which usernames
+    /// exist: failed sign ins don't say why, and signing up with a taken username or email seems
+    /// to succeed while the account's owner is mailed.
+    ///
+    /// `MAINTENANCE_MODE=sign-up` starts the service refusing sign ups, and `sign-in` refusing sign
+    /// ins too, with the `MAINTENANCE` status code, e.g. to restart replicas onto a backend being
+    /// migrated. Sessions keep being validated. `off` is the default, and the admin API switches
+    /// the mode at runtime.
+    ///
+    /// Sessions last `SESSION_LIFETIME_SECS` and can be renewed up to `SESSION_MAX_LIFETIME_SECS`
+    /// after creation, on every validation if `SESSION_SLIDING_EXPIRATION=true`. Expired ones are
+    /// evicted every `SESSION_CLEANUP_INTERVAL_SECS`.
+    ///
+    /// `MAX_SESSIONS_PER_USER` caps each user's sessions, rejecting further sign ins when
+    /// `SESSION_LIMIT_POLICY=reject` or signing out of the oldest ones with `evict-oldest` (the
+    /// default). JWT sessions can't be listed, so they can't be capped.
+    ///
+    /// `SESSION_TOKEN_KEYS` lists the keys signing session tokens (comma separated). The first one
+    /// signs new tokens and the rest are still accepted, so keys can be rotated.
+    ///
+    /// `SESSION_BACKEND=jwt` issues stateless sessions signed according to `JWT_ALGORITHM`: `HS256`
+    /// (the default) with `JWT_SECRET`, or `RS256` with `JWT_PRIVATE_KEY_PATH` and `JWT_PUBLIC_KEY_PATH`.
+    /// Signed out sessions and users are only revoked in memory unless `JWT_REVOCATIONS_PATH` names
+    /// a file to keep them in.
+    ///
+    /// `SESSION_STORE_PATH` saves in-memory sessions to a file and loads them back on startup. It
+    /// requires `SESSION_TOKEN_KEYS`, since tokens signed with a generated key can't be verified
+    /// after a restart.
+    ///
+    /// Setting `SESSION_CACHE_SIZE` keeps up to that many validated sessions in memory for
+    /// `SESSION_CACHE_TTL_MILLIS` (5 seconds by default), so validations don't all reach the
+    /// session store. Sessions signed out on other replicas stay valid here until then.
+    ///
+    /// `USERS_BACKEND=postgres` stores users in the Postgres database at `DATABASE_URL`, pooling
+    /// up to `DATABASE_MAX_CONNECTIONS` connections.
+    ///
+    /// `USERS_BACKEND=sqlite` and `SESSION_BACKEND=sqlite` store users and sessions in the SQLite
+    /// database at `SQLITE_PATH`. SQLite sessions require `SESSION_TOKEN_KEYS` for the same reason
+    /// as `SESSION_STORE_PATH`.
+    ///
+    /// Database queries failing transiently, e.g. because the database is busy, are retried with
+    /// backoff for up to `STORAGE_RETRY_BUDGET_MILLIS` before failing as `UNAVAILABLE`.
+    ///
+    /// The SQL databases' schemas are versioned by the migrations embedded in the binary.
+    /// `DATABASE_MIGRATIONS=startup` (the default) applies pending ones on startup, `manual`
+    /// refuses to serve until they're applied by running with `MIGRATE=true` (e.g. `auth --migrate
+    /// true`), which exits once they are. Databases migrated by a newer release are never served.
+    ///
+    /// `SEED` (e.g. `auth --seed seed.toml`) names a TOML file, or JSON if it ends in `.json`,
+    /// of users and groups to start with, for local development and demos. Users whose username
+    /// is taken are kept as they are, but still added to their groups.
+    ///
+    /// Setting `LDAP_URL` checks passwords against an LDAP or Active Directory server. Entries
+    /// under `LDAP_BASE_DN` matching `LDAP_USER_FILTER` are searched for, as `LDAP_BIND_DN` with
+    /// `LDAP_BIND_PASSWORD` if set, then bound as. Their `LDAP_USERNAME_ATTRIBUTE`,
+    /// `LDAP_DISPLAY_NAME_ATTRIBUTE` and `LDAP_EMAIL_ATTRIBUTE` are copied to users provisioned
+    /// in the users backend. Connecting gives up after `LDAP_TIMEOUT_SECS`.
+    ///
+    /// `PASSWORD_HASH_ALGORITHM` picks how new passwords are hashed: `pbkdf2` (the default) with
+    /// `PBKDF2_ROUNDS`, or `argon2id` with `ARGON2_MEMORY_KIB`, `ARGON2_ITERATIONS` and
+    /// `ARGON2_PARALLELISM`. At most `PASSWORD_HASHING_CONCURRENCY` passwords (one per core by
+    /// default) are hashed or checked at once, further sign ins waiting for their turn.
+    ///
+    /// Setting `PASSWORD_HASH_BENCHMARK_MILLIS` times the configured algorithm on this machine
+    /// instead of serving, logging the `PBKDF2_ROUNDS` or `ARGON2_ITERATIONS` whose hashes take
+    /// about that long to verify. Hashes record the cost they were made with, so changing it
+    /// keeps existing passwords valid.
+    ///
+    /// `USER_ID_FORMAT` picks how new users' ids are generated: `uuid-v4` (the default), the time
+    /// ordered `uuid-v7`, or `nanoid` with `NANOID_LENGTH` characters.
+    ///
+    /// Deleted accounts can be restored for `ACCOUNT_DELETION_GRACE_PERIOD_SECS`, after which
+    /// they're purged.
+    ///
+    /// New passwords must be `PASSWORD_MIN_LENGTH` to `PASSWORD_MAX_LENGTH` characters long and
+    /// contain each of the character classes listed in `PASSWORD_REQUIRED_CLASSES` (comma
+    /// separated `lowercase`, `uppercase`, `digit` and `symbol`). Common passwords are rejected,
+    /// along with those listed in the file at `PASSWORD_DENYLIST_PATH`.
+    ///
+    /// Setting `BREACHED_PASSWORD_CHECK=true` also rejects new passwords found in a data breach,
+    /// giving up on the check after `BREACHED_PASSWORD_CHECK_TIMEOUT_MILLIS`.
+    pub fn from_vars(var: impl Fn(&str) -> Option<String>) -> Result<Self, String> {
+        let log_level = var("LOG_LEVEL")
+            .filter(|level| !level.is_empty())
+            .unwrap_or_else(|| DEFAULT_LOG_LEVEL.to_owned());
+        if EnvFilter::try_new(&log_level).is_err() {
+            return Err(format!(
+                "Error, LOG_LEVEL has an invalid value: {}",
+                log_level
+            ));
+        }
+        let log_format = parse(&var, "LOG_FORMAT")?.unwrap_or_default();
+        let otlp = var("OTEL_EXPORTER_OTLP_ENDPOINT")
+            .filter(|endpoint| !endpoint.is_empty())
+            .map(|endpoint| OtlpConfig {
+                endpoint,
+                service_name: var("OTEL_SERVICE_NAME")
+                    .unwrap_or_else(|| DEFAULT_OTEL_SERVICE_NAME.to_owned()),
+            });
+
+        let sentry = var("SENTRY_DSN")
+            .filter(|dsn| !dsn.is_empty())
+            .map(|dsn| SentryConfig {
+                dsn: Secret::new(dsn),
+                environment: var("SENTRY_ENVIRONMENT")
+                    .filter(|environment| !environment.is_empty()),
+            });
+
+        let metrics_address = match var("METRICS_ADDRESS") {
+            Some(address) if address.is_empty() => None,
+            _ => parse(&var, "METRICS_ADDRESS")?,
+        };
+        let access_log = match var("ACCESS_LOG").as_deref() {
+            None | Some("") | Some("off") => None,
+            _ => parse(&var, "ACCESS_LOG")?,
+        };
+
+        let bind_address = match var("BIND_ADDRESS") {
+            Some(address) if address.is_empty() => None,
+            _ => Some(parse(&var, "BIND_ADDRESS")?.unwrap_or(SocketAddr::from((
+                Ipv6Addr::UNSPECIFIED,
+                DEFAULT_AUTH_SERVICE_PORT,
+            )))),
+        };
+        let unix_socket_path = var("UNIX_SOCKET_PATH").filter(|path| !path.is_empty());
+        if bind_address.is_none() && unix_socket_path.is_none() {
+            return Err(
+                "Error, BIND_ADDRESS can only be empty when UNIX_SOCKET_PATH is set".into(),
+            );
+        }
+        let admin_bind_address = match var("ADMIN_BIND_ADDRESS") {
+            Some(address) if address.is_empty() => None,
+            _ => parse(&var, "ADMIN_BIND_ADDRESS")?,
+        };
+        if admin_bind_address.is_some() && admin_bind_address == bind_address {
+            return Err("Error, ADMIN_BIND_ADDRESS must differ from BIND_ADDRESS".into());
+        }
+        let shutdown_timeout = parse(&var, "SHUTDOWN_TIMEOUT_SECS")?
+            .map(Duration::from_secs)
+            .unwrap_or(DEFAULT_SHUTDOWN_TIMEOUT);
+
+        let secs = |key| Ok::<_, String>(parse(&var, key)?.map(Duration::from_secs));
+        let transport = TransportConfig {
+            tcp_keepalive: secs("TCP_KEEPALIVE_SECS")?,
+            http2_keepalive_interval: secs("HTTP2_KEEPALIVE_INTERVAL_SECS")?,
+            http2_keepalive_timeout: secs("HTTP2_KEEPALIVE_TIMEOUT_SECS")?,
+            initial_stream_window_size: parse(&var, "HTTP2_INITIAL_STREAM_WINDOW_SIZE")?,
+            initial_connection_window_size: parse(&var, "HTTP2_INITIAL_CONNECTION_WINDOW_SIZE")?,
+            max_concurrent_streams: parse(&var, "HTTP2_MAX_CONCURRENT_STREAMS")?,
+        };
+
+        let request_timeout = match parse(&var, "REQUEST_TIMEOUT_SECS")? {
+            Some(0) => None,
+            secs => Some(secs.map_or(DEFAULT_REQUEST_TIMEOUT, Duration::from_secs)),
+        };
+        let method_timeouts = var("METHOD_TIMEOUTS")
+            .unwrap_or_default()
+            .split(',')
+            .map(str::trim)
+            .filter(|entry| !entry.is_empty())
+            .map(|entry| {
+                let invalid = || format!("Error, invalid METHOD_TIMEOUTS entry: {}", entry);
+                match entry.split_once(':') {
+                    Some((method, secs)) if !method.is_empty() => Ok((
+                        method.to_owned(),
+                        Duration::from_secs(secs.parse().map_err(|_| invalid())?),
+                    )),
+                    _ => Err(invalid()),
+                }
+            })
+            .collect::<Result<Vec<(String, Duration)>, String>>()?;
+        let max_concurrent_requests = match parse(&var, "MAX_CONCURRENT_REQUESTS")? {
+            Some(0) => None,
+            max => Some(max.unwrap_or(DEFAULT_MAX_CONCURRENT_REQUESTS)),
+        };
+
+        let grpc_compression = match var("GRPC_COMPRESSION").as_deref() {
+            None | Some("none") => None,
+            Some("gzip") => Some(CompressionEncoding::Gzip),
+            Some(encoding) => return Err(format!("Error, unknown GRPC_COMPRESSION: {}", encoding)),
+        };
+
+        let grpc_reflection = parse(&var, "GRPC_REFLECTION")?.unwrap_or(false);
+
+        let tls = match (
+            var("TLS_CERT_PATH").filter(|path| !path.is_empty()),
+            var("TLS_KEY_PATH").filter(|path| !path.is_empty()),
+        ) {
+            (None, None) => None,
+            (Some(cert_path), Some(key_path)) => Some(TlsConfig {
+                cert_path,
+                key_path,
+                reload_interval: match parse(&var, "TLS_RELOAD_INTERVAL_SECS")? {
+                    None => Some(DEFAULT_TLS_RELOAD_INTERVAL),
+                    Some(0) => None,
+                    Some(secs) => Some(Duration::from_secs(secs)),
+                },
+                client_ca_path: var("TLS_CLIENT_CA_PATH").filter(|path| !path.is_empty()),
+                allowed_client_names: var("TLS_ALLOWED_CLIENT_NAMES")
+                    .unwrap_or_default()
+                    .split(',')
+                    .map(str::trim)
+                    .filter(|name| !name.is_empty())
+                    .map(str::to_owned)
+                    .collect(),
+            }),
+            _ => {
+                return Err("Error, TLS_CERT_PATH and TLS_KEY_PATH must be set together".to_string())
+            }
+        };
+
+        if tls.is_some() && bind_address.is_none() && admin_bind_address.is_none() {
+            return Err(
+                "Error, TLS is only served on BIND_ADDRESS and ADMIN_BIND_ADDRESS, which are empty"
+                    .to_string(),
+            );
+        }
+        if tls
+            .as_ref()
+            .is_some_and(|tls| tls.client_ca_path.is_none() && !tls.allowed_client_names.is_empty())
+        {
+            return Err(
+                "Error, TLS_ALLOWED_CLIENT_NAMES needs TLS_CLIENT_CA_PATH to be set".to_string(),
+            );
+        }
+        // The admin API manages every account, so other hosts may only reach it with a client
+        // certificate.
+        if admin_bind_address.is_some_and(|address| !address.ip().is_loopback())
+            && tls.as_ref().is_none_or(|tls| tls.client_ca_path.is_none())
+        {
+            return Err(
+                "Error, ADMIN_BIND_ADDRESS must be a loopback address unless TLS_CLIENT_CA_PATH is set"
+                    .to_string(),
+            );
+        }
+
+        let names = var("OIDC_PROVIDERS").unwrap_or_default();
+
+        let oidc_providers = names
+            .split(',')
+            .map(str::trim)
+            .filter(|name| !name.is_empty())
+            .map(|name| {
+                let setting = |setting: &str| {
+                    let key = format!("OIDC_{}_{}", name.to_uppercase(), setting);
+                    var(&key).ok_or(format!("Error, {} is not set", key))
+                };
+
+                Ok(OidcProvider {
+                    name: name.to_owned(),
+                    issuer: setting("ISSUER")?,
+                    client_id: setting("CLIENT_ID")?,
+                    jwks_uri: setting("JWKS_URI")?,
+                })
+            })
+            .collect::<Result<Vec<OidcProvider>, String>>()?;
+
+        let lockout_threshold =
+            parse(&var, "LOCKOUT_THRESHOLD")?.unwrap_or(DEFAULT_LOCKOUT_THRESHOLD);
+        let lockout_duration = parse(&var, "LOCKOUT_DURATION_SECS")?
+            .map(Duration::from_secs)
+            .unwrap_or(DEFAULT_LOCKOUT_DURATION);
+
+        let default_rate_limit = RateLimit::default();
+        let sign_in_rate_limit = match (
+            parse(&var, "SIGN_IN_RATE_LIMIT_BURST")?.unwrap_or(default_rate_limit.burst),
+            parse(&var, "SIGN_IN_RATE_LIMIT_PER_MINUTE")?.unwrap_or(default_rate_limit.per_minute),
+        ) {
+            (0, _) | (_, 0) => None,
+            (burst, per_minute) => Some(RateLimit { burst, per_minute }),
+        };
+
+        let request_rate_limit = match (
+            parse(&var, "REQUEST_RATE_LIMIT_BURST")?.unwrap_or(0),
+            parse(&var, "REQUEST_RATE_LIMIT_PER_MINUTE")?.unwrap_or(0),
+        ) {
+            (0, _) | (_, 0) => None,
+            (burst, per_minute) => Some(RateLimit { burst, per_minute }),
+        };
+        let method_rate_limits = var("METHOD_RATE_LIMITS")
+            .unwrap_or_default()
+            .split(',')
+            .map(str::trim)
+            .filter(|entry| !entry.is_empty())
+            .map(|entry| {
+                let invalid = || format!("Error, invalid METHOD_RATE_LIMITS entry: {}", entry);
+                match entry.split(':').collect::<Vec<&str>>()[..] {
+                    [method, burst, per_minute] if !method.is_empty() => Ok((
+                        method.to_owned(),
+                        RateLimit {
+                            burst: burst.parse().map_err(|_| invalid())?,
+                            per_minute: per_minute.parse().map_err(|_| invalid())?,
+                        },
+                    )),
+                    _ => Err(invalid()),
+                }
+            })
+            .collect::<Result<Vec<(String, RateLimit)>, String>>()?;
+
+        let sign_up_challenge = parse(&var, "SIGN_UP_CHALLENGE")?.unwrap_or(false);
+        let sign_up_challenge_difficulty =
+            parse(&var, "SIGN_UP_CHALLENGE_DIFFICULTY")?.unwrap_or(DEFAULT_CHALLENGE_DIFFICULTY);
+        let sign_up_idempotency_window = parse(&var, "SIGN_UP_IDEMPOTENCY_WINDOW_SECS")?
+            .map(Duration::from_secs)
+            .unwrap_or(DEFAULT_IDEMPOTENCY_WINDOW);
+        let anti_enumeration = parse(&var, "ANTI_ENUMERATION")?.unwrap_or(false);
+        let maintenance = match var("MAINTENANCE_MODE") {
+            Some(mode) => Maintenance::parse(&mode)?,
+            None => Maintenance::Off,
+        };
+
+        let default_policy = SessionPolicy::default();
+        let session_policy = SessionPolicy {
+            lifetime: parse(&var, "SESSION_LIFETIME_SECS")?
+                .map(Duration::from_secs)
+                .unwrap_or(default_policy.lifetime),
+            max_lifetime: parse(&var, "SESSION_MAX_LIFETIME_SECS")?
+                .map(Duration::from_secs)
+                .unwrap_or(default_policy.max_lifetime),
+            sliding: parse(&var, "SESSION_SLIDING_EXPIRATION")?.unwrap_or(default_policy.sliding),
+        };
+        let session_cleanup_interval = parse(&var, "SESSION_CLEANUP_INTERVAL_SECS")?
+            .map(Duration::from_secs)
+            .unwrap_or(SESSION_CLEANUP_INTERVAL);
+
+        // `tokio::time::interval` panics on a zero period.
+        if session_cleanup_interval.is_zero() {
+            return Err("Error, SESSION_CLEANUP_INTERVAL_SECS must be positive".to_string());
+        }
+
+        let session_limit = match parse(&var, "MAX_SESSIONS_PER_USER")? {
+            None | Some(0) => None,
+            Some(max_sessions) => Some(SessionLimit {
+                max_sessions,
+                policy: match var("SESSION_LIMIT_POLICY").as_deref() {
+                    None | Some("evict-oldest") => SessionLimitPolicy::EvictOldest,
+                    Some("reject") => SessionLimitPolicy::Reject,
+                    Some(policy) => {
+                        return Err(format!("Error, unknown SESSION_LIMIT_POLICY: {}", policy))
+                    }
+                },
+            }),
+        };
+
+        let session_cache = match parse(&var, "SESSION_CACHE_SIZE")?.and_then(NonZeroUsize::new) {
+            None => None,
+            Some(capacity) => Some(SessionCacheConfig {
+                capacity,
+                ttl: match parse(&var, "SESSION_CACHE_TTL_MILLIS")? {
+                    Some(0) => {
+                        return Err("Error, SESSION_CACHE_TTL_MILLIS must be positive".to_string())
+                    }
+                    Some(ttl) => Duration::from_millis(ttl),
+                    None => DEFAULT_SESSION_CACHE_TTL,
+                },
+            }),
+        };
+
+        let session_token_keys: Vec<Secret> = var("SESSION_TOKEN_KEYS")
+            .unwrap_or_default()
+            .split(',')
+            .map(str::trim)
+            .filter(|key| !key.is_empty())
+            .map(Secret::from)
+            .collect();
+
+        let sqlite_path = var("SQLITE_PATH").unwrap_or_else(|| DEFAULT_SQLITE_PATH.to_string());
+
+        let jwt_sessions = match var("SESSION_BACKEND").as_deref() {
+            None | Some("memory") | Some("sqlite") => None,
+            Some("jwt") => {
+                let setting = |key: &str| var(key).ok_or(format!("Error, {} is not set", key));
+
+                match var("JWT_ALGORITHM").as_deref() {
+                    None | Some("HS256") => Some(JwtKeys::Hs256 {
+                        secret: setting("JWT_SECRET")?.into(),
+                    }),
+                    Some("RS256") => Some(JwtKeys::Rs256 {
+                        private_key_path: setting("JWT_PRIVATE_KEY_PATH")?,
+                        public_key_path: setting("JWT_PUBLIC_KEY_PATH")?,
+                    }),
+                    Some(algorithm) => {
+                        return Err(format!("Error, unsupported JWT_ALGORITHM: {}", algorithm))
+                    }
+                }
+            }
+            Some(backend) => return Err(format!("Error, unknown SESSION_BACKEND: {}", backend)),
+        };
+
+        let jwt_revocations_path = var("JWT_REVOCATIONS_PATH").filter(|path| !path.is_empty());
+
+        if jwt_revocations_path.is_some() && jwt_sessions.is_none() {
+            return Err("Error, JWT_REVOCATIONS_PATH requires SESSION_BACKEND=jwt".to_string());
+        }
+
+        if jwt_sessions.is_some() && session_limit.is_some() {
+            return Err(
+                "Error, MAX_SESSIONS_PER_USER can't be used with SESSION_BACKEND=jwt".to_string(),
+            );
+        }
+
+        let sqlite_sessions_path = match var("SESSION_BACKEND").as_deref() {
+            Some("sqlite") if session_token_keys.is_empty() => {
+                return Err("Error, SESSION_BACKEND=sqlite requires SESSION_TOKEN_KEYS".to_string())
+            }
+            Some("sqlite") => Some(sqlite_path.clone()),
+            _ => None,
+        };
+
+        let session_store_path = var("SESSION_STORE_PATH").filter(|path| !path.is_empty());
+
+        if session_store_path.is_some() {
+            if jwt_sessions.is_some() || sqlite_sessions_path.is_some() {
+                return Err(
+                    "Error, SESSION_STORE_PATH only applies to in-memory sessions".to_string(),
+                );
+            }
+            if session_token_keys.is_empty() {
+                return Err("Error, SESSION_STORE_PATH requires SESSION_TOKEN_KEYS".to_string());
+            }
+        }
+
+        let users_database = match var("USERS_BACKEND").as_deref() {
+            None | Some("memory") => None,
+            Some("postgres") => Some(DatabaseConfig::Postgres {
+                url: var("DATABASE_URL").ok_or("Error, DATABASE_URL is not set")?,
+                max_connections: parse(&var, "DATABASE_MAX_CONNECTIONS")?
+                    .unwrap_or(DEFAULT_DATABASE_MAX_CONNECTIONS),
+            }),
+            Some("sqlite") => Some(DatabaseConfig::Sqlite { path: sqlite_path }),
+            Some(backend) => return Err(format!("Error, unknown USERS_BACKEND: {}", backend)),
+        };
+        let storage_retry_budget = parse(&var, "STORAGE_RETRY_BUDGET_MILLIS")?
+            .map(Duration::from_millis)
+            .unwrap_or(DEFAULT_STORAGE_RETRY_BUDGET);
+
+        let database_migrations = match var("DATABASE_MIGRATIONS").as_deref() {
+            None | Some("startup") => Migrations::Startup,
+            Some("manual") => Migrations::Manual,
+            Some(migrations) => {
+                return Err(format!(
+                    "Error, unknown DATABASE_MIGRATIONS: {}",
+                    migrations
+                ))
+            }
+        };
+        let migrate = parse(&var, "MIGRATE")?.unwrap_or(false);
+        let seed_path = var("SEED").filter(|path| !path.is_empty());
+        if migrate && users_database.is_none() && sqlite_sessions_path.is_none() {
+            return Err(
+                "Error, MIGRATE requires a postgres or sqlite USERS_BACKEND or SESSION_BACKEND"
+                    .to_string(),
+            );
+        }
+
+        let setting = |key: &str, default: &str| var(key).unwrap_or_else(|| default.to_owned());
+        let ldap = match var("LDAP_URL").filter(|url| !url.is_empty()) {
+            None => None,
+            Some(url) => Some(LdapConfig {
+                url,
+                bind_dn: var("LDAP_BIND_DN").filter(|bind_dn| !bind_dn.is_empty()),
+                bind_password: var("LDAP_BIND_PASSWORD").unwrap_or_default().into(),
+                base_dn: var("LDAP_BASE_DN").ok_or("Error, LDAP_BASE_DN is not set")?,
+                user_filter: setting("LDAP_USER_FILTER", DEFAULT_LDAP_USER_FILTER),
+                username_attribute: setting(
+                    "LDAP_USERNAME_ATTRIBUTE",
+                    DEFAULT_LDAP_USERNAME_ATTRIBUTE,
+                ),
+                display_name_attribute: setting(
+                    "LDAP_DISPLAY_NAME_ATTRIBUTE",
+                    DEFAULT_LDAP_DISPLAY_NAME_ATTRIBUTE,
+                ),
+                email_attribute: setting("LDAP_EMAIL_ATTRIBUTE", DEFAULT_LDAP_EMAIL_ATTRIBUTE),
+                timeout: parse(&var, "LDAP_TIMEOUT_SECS")?
+                    .map(Duration::from_secs)
+                    .unwrap_or(DEFAULT_LDAP_TIMEOUT),
+            }),
+        };
+
+        if ldap
+            .as_ref()
+            .is_some_and(|ldap| !ldap.user_filter.contains("{username}"))
+        {
+            return Err("Error, LDAP_USER_FILTER must contain {username}".to_string());
+        }
+
+        let password_hashing = match var("PASSWORD_HASH_ALGORITHM").as_deref() {
+            None | Some("pbkdf2") => PasswordHashing::Pbkdf2 {
+                rounds: parse(&var, "PBKDF2_ROUNDS")?.unwrap_or(DEFAULT_PBKDF2_ROUNDS),
+            },
+            Some("argon2id") => PasswordHashing::Argon2id {
+                memory_kib: parse(&var, "ARGON2_MEMORY_KIB")?.unwrap_or(DEFAULT_ARGON2_MEMORY_KIB),
+                iterations: parse(&var, "ARGON2_ITERATIONS")?.unwrap_or(DEFAULT_ARGON2_ITERATIONS),
+                parallelism: parse(&var, "ARGON2_PARALLELISM")?
+                    .unwrap_or(DEFAULT_ARGON2_PARALLELISM),
+            },
+            Some(algorithm) => {
+                return Err(format!(
+                    "Error, unknown PASSWORD_HASH_ALGORITHM: {}",
+                    algorithm
+                ))
+            }
+        };
+
+        let password_hashing_concurrency = match parse(&var, "PASSWORD_HASHING_CONCURRENCY")? {
+            Some(0) => {
+                return Err("Error, PASSWORD_HASHING_CONCURRENCY must be positive".to_string())
+            }
+            concurrency => concurrency.unwrap_or_else(default_hashing_concurrency),
+        };
+
+        let password_hash_benchmark = match parse(&var, "PASSWORD_HASH_BENCHMARK_MILLIS")? {
+            Some(0) => {
+                return Err("Error, PASSWORD_HASH_BENCHMARK_MILLIS must be positive".to_string())
+            }
+            millis => millis.map(Duration::from_millis),
+        };
+
+        let user_id_format = match var("USER_ID_FORMAT").as_deref() {
+            None | Some("uuid-v4") => UserIdFormat::UuidV4,
+            Some("uuid-v7") => UserIdFormat::UuidV7,
+            Some("nanoid") => UserIdFormat::NanoId {
+                length: parse(&var, "NANOID_LENGTH")?.unwrap_or(DEFAULT_NANOID_LENGTH),
+            },
+            Some(format) => return Err(format!("Error, unknown USER_ID_FORMAT: {}", format)),
+        };
+
+        let deletion_grace_period = parse(&var, "ACCOUNT_DELETION_GRACE_PERIOD_SECS")?
+            .map(Duration::from_secs)
+            .unwrap_or(DEFAULT_DELETION_GRACE_PERIOD);
+
+        let mut password_policy = PasswordPolicy::default();
+        if let Some(min_length) = parse(&var, "PASSWORD_MIN_LENGTH")? {
+            password_policy.min_length = min_length;
+        }
+        if let Some(max_length) = parse(&var, "PASSWORD_MAX_LENGTH")? {
+            password_policy.max_length = max_length;
+        }
+        if password_policy.min_length > password_policy.max_length {
+            return Err(
+                "Error, PASSWORD_MIN_LENGTH must not exceed PASSWORD_MAX_LENGTH".to_string(),
+            );
+        }
+        for class in var("PASSWORD_REQUIRED_CLASSES")
+            .unwrap_or_default()
+            .split(',')
+            .map(str::trim)
+            .filter(|class| !class.is_empty())
+        {
+            match class {
+                "lowercase" => password_policy.require_lowercase = true,
+                "uppercase" => password_policy.require_uppercase = true,
+                "digit" => password_policy.require_digit = true,
+                "symbol" => password_policy.require_symbol = true,
+                class => {
+                    return Err(format!(
+                        "Error, unknown password character class: {}",
+                        class
+                    ))
+                }
+            }
+        }
+
+        let password_denylist_path = var("PASSWORD_DENYLIST_PATH").filter(|path| !path.is_empty());
+
+        let breached_password_check = parse(&var, "BREACHED_PASSWORD_CHECK")?.unwrap_or(false);
+        let breached_password_check_timeout =
+            parse(&var, "BREACHED_PASSWORD_CHECK_TIMEOUT_MILLIS")?
+                .map(Duration::from_millis)
+                .unwrap_or(DEFAULT_BREACH_CHECK_TIMEOUT);
+
+        Ok(Self {
+            log_level,
+            log_format,
+            otlp,
+            sentry,
+            metrics_address,
+            access_log,
+            bind_address,
+            unix_socket_path,
+            admin_bind_address,
+            shutdown_timeout,
+            transport,
+            request_timeout,
+            method_timeouts,
+            max_concurrent_requests,
+            grpc_compression,
+            grpc_reflection,
+            tls,
+            oidc_providers,
+            lockout_threshold,
+            lockout_duration,
+            sign_in_rate_limit,
+            request_rate_limit,
+            method_rate_limits,
+            sign_up_challenge,
+            sign_up_challenge_difficulty,
+            anti_enumeration,
+            maintenance,
+            sign_up_idempotency_window,
+            session_policy,
+            session_cleanup_interval,
+            session_limit,
+            session_token_keys,
+            jwt_sessions,
+            jwt_revocations_path,
+            session_store_path,
+            session_cache,
+            users_database,
+            storage_retry_budget,
+            database_migrations,
+            migrate,
+            seed_path,
+            ldap,
+            sqlite_sessions_path,
+            password_hashing,
+            password_hashing_concurrency,
+            password_hash_benchmark,
+            user_id_format,
+            deletion_grace_period,
+            password_policy,
+            password_denylist_path,
+            breached_password_check,
+            breached_password_check_timeout,
+        })
+    }
+}
+
+/// Parses the variable `key` if it is set.
+fn parse<T: std::str::FromStr>(
+    var: &impl Fn(&str) -> Option<String>,
+    key: &str,
+) -> Result<Option<T>, String> {
+    var(key)
+        .map(|value| {
+            value
+                .parse()
+                .map_err(|_| format!("Error, {} has an invalid value: {}", key, value))
+        })
+        .transpose()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::*;
+
+    fn vars(pairs: &[(&str, &str)]) -> impl Fn(&str) -> Option<String> {
+        let vars: HashMap<String, String> = pairs
+            .iter()
+            .map(|(key, value)| (key.to_string(), value.to_string()))
+            .collect();
+        move |key| vars.get(key).cloned()
+    }
+
+    #[test]
+    fn should_default_to_no_oidc_providers() {
+        let config = Config::from_vars(vars(&[])).unwrap();
+        assert!(config.oidc_providers.is_empty());
+    }
+
+    #[test]
+    fn should_read_oidc_providers() {
+        let config = Config::from_vars(vars(&[
+            ("OIDC_PROVIDERS", "google"),
+            ("OIDC_GOOGLE_ISSUER", "https://accounts.google.com"),
+            ("OIDC_GOOGLE_CLIENT_ID", "client"),
+            (
+                "OIDC_GOOGLE_JWKS_URI",
+                "https://www.googleapis.com/oauth2/v3/certs",
+            ),
+        ]))
+        .unwrap();
+
+        assert_eq!(
+            config.oidc_providers,
+            vec![OidcProvider {
+                name: "google".to_owned(),
+                issuer: "https://accounts.google.com".to_owned(),
+                client_id: "client".to_owned(),
+                jwks_uri: "https://www.googleapis.com/oauth2/v3/certs".to_owned(),
+            }]
+        );
+    }
+
+    #[test]
+    fn should_read_bind_address() {
+        let config = Config::from_vars(vars(&[])).unwrap();
+        assert_eq!(config.bind_address, Some("[::0]:50051".parse().unwrap()));
+        assert_eq!(config.unix_socket_path, None);
+
+        let config = Config::from_vars(vars(&[("BIND_ADDRESS", "127.0.0.1:8443")])).unwrap();
+        assert_eq!(config.bind_address, Some("127.0.0.1:8443".parse().unwrap()));
+
+        assert!(Config::from_vars(vars(&[("BIND_ADDRESS", "localhost")])).is_err());
+    }
+
+    #[test]
+    fn should_read_admin_bind_address() {
+        let config = Config::from_vars(vars(&[])).unwrap();
+        assert_eq!(config.admin_bind_address, None);
+
+        let config = Config::from_vars(vars(&[("ADMIN_BIND_ADDRESS", "127.0.0.1:50052")])).unwrap();
+        assert_eq!(
+            config.admin_bind_address,
+            Some("127.0.0.1:50052".parse().unwrap())
+        );
+
+        assert!(Config::from_vars(vars(&[("ADMIN_BIND_ADDRESS", "50052")])).is_err());
+        assert!(Config::from_vars(vars(&[("ADMIN_BIND_ADDRESS", "[::0]:50051")])).is_err());
+    }
+
+    #[test]
+    fn should_require_client_certificates_for_remote_admin_bind_address() {
+        let tls = [
+            ("TLS_CERT_PATH", "/etc/auth/cert.pem"),
+            ("TLS_KEY_PATH", "/etc/auth/key.pem"),
+        ];
+        let client_ca = ("TLS_CLIENT_CA_PATH", "/etc/auth/clients.pem");
+        let admin = ("ADMIN_BIND_ADDRESS", "0.0.0.0:50052");
+
+        assert!(Config::from_vars(vars(&[("ADMIN_BIND_ADDRESS", "[::1]:50052")])).is_ok());
+        assert!(Config::from_vars(vars(&[admin])).is_err());
+        assert!(Config::from_vars(vars(&[tls[0], tls[1], admin])).is_err());
+        assert!(Config::from_vars(vars(&[tls[0], tls[1], client_ca, admin])).is_ok());
+    }
+
+    #[test]
+    fn should_read_unix_socket_path() {
+        let config =
+            Config::from_vars(vars(&[("UNIX_SOCKET_PATH", "/run/auth/auth.sock")])).unwrap();
+        assert_eq!(config.bind_address, Some("[::0]:50051".parse().unwrap()));
+        assert_eq!(
+            config.unix_socket_path.as_deref(),
+            Some("/run/auth/auth.sock")
+        );
+
+        // An empty address only serves on the socket.
+        let config = Config::from_vars(vars(&[
+            ("BIND_ADDRESS", ""),
+            ("UNIX_SOCKET_PATH", "/run/auth/auth.sock"),
+        ]))
+        .unwrap();
+        assert_eq!(config.bind_address, None);
+
+        assert!(Config::from_vars(vars(&[("BIND_ADDRESS", "")])).is_err());
+        assert!(Config::from_vars(vars(&[
+            ("BIND_ADDRESS", ""),
+            ("UNIX_SOCKET_PATH", "/run/auth/auth.sock"),
+            ("TLS_CERT_PATH", "/etc/auth/cert.pem"),
+            ("TLS_KEY_PATH", "/etc/auth/key.pem"),
+        ]))
+        .is_err());
+    }
+
+    #[test]
+    fn should_read_shutdown_timeout() {
+        let config = Config::from_vars(vars(&[])).unwrap();
+        assert_eq!(config.shutdown_timeout, DEFAULT_SHUTDOWN_TIMEOUT);
+
+        let config = Config::from_vars(vars(&[("SHUTDOWN_TIMEOUT_SECS", "5")])).unwrap();
+        assert_eq!(config.shutdown_timeout, Duration::from_secs(5));
+    }
+
+    #[test]
+    fn should_read_tls_settings() {
+        let config = Config::from_vars(vars(&[])).unwrap();
+        assert!(config.tls.is_none());
+
+        let config = Config::from_vars(vars(&[
+            ("TLS_CERT_PATH", "/etc/auth/cert.pem"),
+            ("TLS_KEY_PATH", "/etc/auth/key.pem"),
+        ]))
+        .unwrap();
+        assert_eq!(
+            config.tls,
+            Some(TlsConfig {
+                cert_path: "/etc/auth/cert.pem".to_owned(),
+                key_path: "/etc/auth/key.pem".to_owned(),
+                reload_interval: Some(DEFAULT_TLS_RELOAD_INTERVAL),
+                client_ca_path: None,
+                allowed_client_names: vec![],
+            })
+        );
+
+        let config = Config::from_vars(vars(&[
+            ("TLS_CERT_PATH", "/etc/auth/cert.pem"),
+            ("TLS_KEY_PATH", "/etc/auth/key.pem"),
+            ("TLS_RELOAD_INTERVAL_SECS", "0"),
+        ]))
+        .unwrap();
+        assert_eq!(config.tls.unwrap().reload_interval, None);
+
+        assert!(Config::from_vars(vars(&[("TLS_CERT_PATH", "/etc/auth/cert.pem")])).is_err());
+    }
+
+    #[test]
+    fn should_read_client_certificate_settings() {
+        let config = Config::from_vars(vars(&[
+            ("TLS_CERT_PATH", "/etc/auth/cert.pem"),
+            ("TLS_KEY_PATH", "/etc/auth/key.pem"),
+            ("TLS_CLIENT_CA_PATH", "/etc/auth/clients.pem"),
+            (
+                "TLS_ALLOWED_CLIENT_NAMES",
+                "users.internal, spiffe://example.com/health",
+            ),
+        ]))
+        .unwrap()
+        .tls
+        .unwrap();
+        assert_eq!(
+            config.client_ca_path,
+            Some("/etc/auth/clients.pem".to_owned())
+        );
+        assert_eq!(
+            config.allowed_client_names,
+            vec!["users.internal", "spiffe://example.com/health"]
+        );
+
+        // Names can only be checked on certificates that are required.
+        assert!(Config::from_vars(vars(&[
+            ("TLS_CERT_PATH", "/etc/auth/cert.pem"),
+            ("TLS_KEY_PATH", "/etc/auth/key.pem"),
+            ("TLS_ALLOWED_CLIENT_NAMES", "users.internal"),
+        ]))
+        .is_err());
+    }
+
+    #[test]
+    fn should_read_lockout_settings() {
+        let config = Config::from_vars(vars(&[])).unwrap();
+        assert_eq!(config.lockout_threshold, DEFAULT_LOCKOUT_THRESHOLD);
+        assert_eq!(config.lockout_duration, DEFAULT_LOCKOUT_DURATION);
+
+        let config = Config::from_vars(vars(&[
+            ("LOCKOUT_THRESHOLD", "3"),
+            ("LOCKOUT_DURATION_SECS", "60"),
+        ]))
+        .unwrap();
+        assert_eq!(config.lockout_threshold, 3);
+        assert_eq!(config.lockout_duration, Duration::from_secs(60));
+
+        assert!(Config::from_vars(vars(&[("LOCKOUT_THRESHOLD", "many")])).is_err());
+    }
+
+    #[test]
+    fn should_read_sign_in_rate_limit() {
+        let config = Config::from_vars(vars(&[])).unwrap();
+        assert_eq!(config.sign_in_rate_limit, Some(RateLimit::default()));
+
+        let config = Config::from_vars(vars(&[
+            ("SIGN_IN_RATE_LIMIT_BURST", "3"),
+            ("SIGN_IN_RATE_LIMIT_PER_MINUTE", "1"),
+        ]))
+        .unwrap();
+        assert_eq!(
+            config.sign_in_rate_limit,
+            Some(RateLimit {
+                burst: 3,
+                per_minute: 1,
+            })
+        );
+
+        let config = Config::from_vars(vars(&[("SIGN_IN_RATE_LIMIT_BURST", "0")])).unwrap();
+        assert_eq!(config.sign_in_rate_limit, None);
+
+        assert!(Config::from_vars(vars(&[("SIGN_IN_RATE_LIMIT_PER_MINUTE", "-1")])).is_err());
+    }
+
+    #[test]
+    fn should_read_request_limits() {
+        let config = Config::from_vars(vars(&[])).unwrap();
+        assert_eq!(config.request_timeout, Some(DEFAULT_REQUEST_TIMEOUT));
+        assert!(config.method_timeouts.is_empty());
+        assert_eq!(
+            config.max_concurrent_requests,
+            Some(DEFAULT_MAX_CONCURRENT_REQUESTS)
+        );
+
+        let config = Config::from_vars(vars(&[
+            ("REQUEST_TIMEOUT_SECS", "0"),
+            ("METHOD_TIMEOUTS", "SignIn:5, SignUp:10"),
+            ("MAX_CONCURRENT_REQUESTS", "0"),
+        ]))
+        .unwrap();
+        assert_eq!(config.request_timeout, None);
+        assert_eq!(
+            config.method_timeouts,
+            vec![
+                ("SignIn".to_owned(), Duration::from_secs(5)),
+                ("SignUp".to_owned(), Duration::from_secs(10)),
+            ]
+        );
+        assert_eq!(config.max_concurrent_requests, None);
+
+        let config = Config::from_vars(vars(&[("MAX_CONCURRENT_REQUESTS", "64")])).unwrap();
+        assert_eq!(config.max_concurrent_requests, Some(64));
+
+        assert!(Config::from_vars(vars(&[("METHOD_TIMEOUTS", "SignIn")])).is_err());
+        assert!(Config::from_vars(vars(&[("METHOD_TIMEOUTS", "SignIn:5s")])).is_err());
+    }
+
+    #[test]
+    fn should_read_transport_settings() {
+        let config = Config::from_vars(vars(&[])).unwrap();
+        assert_eq!(config.transport, TransportConfig::default());
+
+        let config = Config::from_vars(vars(&[
+            ("TCP_KEEPALIVE_SECS", "60"),
+            ("HTTP2_KEEPALIVE_INTERVAL_SECS", "30"),
+            ("HTTP2_KEEPALIVE_TIMEOUT_SECS", "10"),
+            ("HTTP2_INITIAL_STREAM_WINDOW_SIZE", "1048576"),
+            ("HTTP2_INITIAL_CONNECTION_WINDOW_SIZE", "4194304"),
+            ("HTTP2_MAX_CONCURRENT_STREAMS", "100"),
+        ]))
+        .unwrap();
+        assert_eq!(
+            config.transport,
+            TransportConfig {
+                tcp_keepalive: Some(Duration::from_secs(60)),
+                http2_keepalive_interval: Some(Duration::from_secs(30)),
+                http2_keepalive_timeout: Some(Duration::from_secs(10)),
+                initial_stream_window_size: Some(1048576),
+                initial_connection_window_size: Some(4194304),
+                max_concurrent_streams: Some(100),
+            }
+        );
+
+        assert!(Config::from_vars(vars(&[("HTTP2_MAX_CONCURRENT_STREAMS", "-1")])).is_err());
+    }
+
+    #[test]
+    fn should_read_grpc_compression() {
+        let config = Config::from_vars(vars(&[])).unwrap();
+        assert_eq!(config.grpc_compression, None);
+
+        let config = Config::from_vars(vars(&[("GRPC_COMPRESSION", "gzip")])).unwrap();
+        assert_eq!(config.grpc_compression, Some(CompressionEncoding::Gzip));
+
+        assert!(Config::from_vars(vars(&[("GRPC_COMPRESSION", "brotli")])).is_err());
+    }
+
+    #[test]
+    fn should_read_log_settings() {
+        let config = Config::from_vars(vars(&[])).unwrap();
+        assert_eq!(config.log_level, "info");
+        assert_eq!(config.log_format, LogFormat::Text);
+
+        let config = Config::from_vars(vars(&[
+            ("LOG_LEVEL", "warn,auth::sessions=debug"),
+            ("LOG_FORMAT", "json"),
+        ]))
+        .unwrap();
+        assert_eq!(config.log_level, "warn,auth::sessions=debug");
+        assert_eq!(config.log_format, LogFormat::Json);
+
+        assert!(Config::from_vars(vars(&[("LOG_LEVEL", "auth=loud")])).is_err());
+        assert!(Config::from_vars(vars(&[("LOG_FORMAT", "xml")])).is_err());
+    }
+
+    #[test]
+    fn should_read_otlp_settings() {
+        let config = Config::from_vars(vars(&[])).unwrap();
+        assert_eq!(config.otlp, None);
+
+        let config = Config::from_vars(vars(&[(
+            "OTEL_EXPORTER_OTLP_ENDPOINT",
+            "http://collector:4317",
+        )]))
+        .unwrap();
+        assert_eq!(
+            config.otlp,
+            Some(OtlpConfig {
+                endpoint: "http://collector:4317".to_string(),
+                service_name: "auth".to_string(),
+            })
+        );
+
+        let config = Config::from_vars(vars(&[
+            ("OTEL_EXPORTER_OTLP_ENDPOINT", "http://collector:4317"),
+            ("OTEL_SERVICE_NAME", "auth-eu"),
+        ]))
+        .unwrap();
+        assert_eq!(config.otlp.unwrap().service_name, "auth-eu");
+    }
+
+    #[test]
+    fn should_read_sentry_settings() {
+        let config = Config::from_vars(vars(&[])).unwrap();
+        assert_eq!(config.sentry, None);
+
+        let config = Config::from_vars(vars(&[
+            ("SENTRY_DSN", "https://key@o0.ingest.sentry.io/42"),
+            ("SENTRY_ENVIRONMENT", "production"),
+        ]))
+        .unwrap();
+        assert_eq!(
+            config.sentry,
+            Some(SentryConfig {
+                dsn: Secret::new("https://key@o0.ingest.sentry.io/42"),
+                environment: Some("production".to_owned()),
+            })
+        );
+    }
+
+    #[test]
+    fn should_read_metrics_address() {
+        let config = Config::from_vars(vars(&[])).unwrap();
+        assert_eq!(config.metrics_address, None);
+
+        let config = Config::from_vars(vars(&[("METRICS_ADDRESS", "[::]:9090")])).unwrap();
+        assert_eq!(config.metrics_address, Some("[::]:9090".parse().unwrap()));
+
+        assert!(Config::from_vars(vars(&[("METRICS_ADDRESS", "9090")])).is_err());
+    }
+
+    #[test]
+    fn should_read_access_log() {
+        let config = Config::from_vars(vars(&[])).unwrap();
+        assert_eq!(config.access_log, None);
+
+        let config = Config::from_vars(vars(&[("ACCESS_LOG", "off")])).unwrap();
+        assert_eq!(config.access_log, None);
+
+        let config = Config::from_vars(vars(&[("ACCESS_LOG", "common")])).unwrap();
+        assert_eq!(config.access_log, Some(AccessLogFormat::Common));
+
+        let config = Config::from_vars(vars(&[("ACCESS_LOG", "json")])).unwrap();
+        assert_eq!(config.access_log, Some(AccessLogFormat::Json));
+
+        assert!(Config::from_vars(vars(&[("ACCESS_LOG", "apache")])).is_err());
+    }
+
+    #[test]
+    fn should_read_grpc_reflection() {
+        let config = Config::from_vars(vars(&[])).unwrap();
+        assert!(!config.grpc_reflection);
+
+        let config = Config::from_vars(vars(&[("GRPC_REFLECTION", "true")])).unwrap();
+        assert!(config.grpc_reflection);
+
+        assert!(Config::from_vars(vars(&[("GRPC_REFLECTION", "yes")])).is_err());
+    }
+
+    #[test]
+    fn should_read_request_rate_limits() {
+        let config = Config::from_vars(vars(&[])).unwrap();
+        assert_eq!(config.request_rate_limit, None);
+        assert!(config.method_rate_limits.is_empty());
+
+        let config = Config::from_vars(vars(&[
+            ("REQUEST_RATE_LIMIT_BURST", "100"),
+            ("REQUEST_RATE_LIMIT_PER_MINUTE", "60"),
+            ("METHOD_RATE_LIMITS", "SignIn:10:5, SignOut:100:60"),
+        ]))
+        .unwrap();
+        assert_eq!(
+            config.request_rate_limit,
+            Some(RateLimit {
+                burst: 100,
+                per_minute: 60,
+            })
+        );
+        assert_eq!(
+            config.method_rate_limits,
+            vec![
+                (
+                    "SignIn".to_owned(),
+                    RateLimit {
+                        burst: 10,
+                        per_minute: 5,
+                    }
+                ),
+                (
+                    "SignOut".to_owned(),
+                    RateLimit {
+                        burst: 100,
+                        per_minute: 60,
+                    }
+                ),
+            ]
+        );
+
+        assert!(Config::from_vars(vars(&[("METHOD_RATE_LIMITS", "SignIn:10")])).is_err());
+        assert!(Config::from_vars(vars(&[("METHOD_RATE_LIMITS", "SignIn:ten:5")])).is_err());
+    }
+
+    #[test]
+    fn should_read_sign_up_challenge_settings() {
+        let config = Config::from_vars(vars(&[])).unwrap();
+        assert!(!config.sign_up_challenge);
+
+        let config = Config::from_vars(vars(&[
+            ("SIGN_UP_CHALLENGE", "true"),
+            ("SIGN_UP_CHALLENGE_DIFFICULTY", "16"),
+        ]))
+        .unwrap();
+        assert!(config.sign_up_challenge);
+        assert_eq!(config.sign_up_challenge_difficulty, 16);
+    }
+
+    #[test]
+    fn should_read_sign_up_idempotency_window() {
+        let config = Config::from_vars(vars(&[])).unwrap();
+        assert_eq!(
+            config.sign_up_idempotency_window,
+            DEFAULT_IDEMPOTENCY_WINDOW
+        );
+
+        let config = Config::from_vars(vars(&[("SIGN_UP_IDEMPOTENCY_WINDOW_SECS", "60")])).unwrap();
+        assert_eq!(config.sign_up_idempotency_window, Duration::from_secs(60));
+    }
+
+    #[test]
+    fn should_read_anti_enumeration() {
+        let config = Config::from_vars(vars(&[])).unwrap();
+        assert!(!config.anti_enumeration);
+
+        let config = Config::from_vars(vars(&[("ANTI_ENUMERATION", "true")])).unwrap();
+        assert!(config.anti_enumeration);
+        assert!(Config::from_vars(vars(&[("ANTI_ENUMERATION", "yes")])).is_err());
+    }
+
+    #[test]
+    fn should_read_maintenance_mode() {
+        let config = Config::from_vars(vars(&[])).unwrap();
+        assert_eq!(config.maintenance, Maintenance::Off);
+
+        let config = Config::from_vars(vars(&[("MAINTENANCE_MODE", "sign-in")])).unwrap();
+        assert_eq!(config.maintenance, Maintenance::SignIn);
+        assert!(Config::from_vars(vars(&[("MAINTENANCE_MODE", "on")])).is_err());
+    }
+
+    #[test]
+    fn should_read_session_settings() {
+        let config = Config::from_vars(vars(&[])).unwrap();
+        assert_eq!(config.session_policy, SessionPolicy::default());
+        assert_eq!(config.session_cleanup_interval, SESSION_CLEANUP_INTERVAL);
+
+        let config = Config::from_vars(vars(&[
+            ("SESSION_LIFETIME_SECS", "3600"),
+            ("SESSION_MAX_LIFETIME_SECS", "7200"),
+            ("SESSION_SLIDING_EXPIRATION", "true"),
+            ("SESSION_CLEANUP_INTERVAL_SECS", "10"),
+        ]))
+        .unwrap();
+        assert_eq!(
+            config.session_policy,
+            SessionPolicy {
+                lifetime: Duration::from_secs(3600),
+                max_lifetime: Duration::from_secs(7200),
+                sliding: true,
+            }
+        );
+        assert_eq!(config.session_cleanup_interval, Duration::from_secs(10));
+
+        assert!(Config::from_vars(vars(&[("SESSION_CLEANUP_INTERVAL_SECS", "0")])).is_err());
+    }
+
+    #[test]
+    fn should_read_session_limit() {
+        let config = Config::from_vars(vars(&[])).unwrap();
+        assert!(config.session_limit.is_none());
+
+        let config = Config::from_vars(vars(&[("MAX_SESSIONS_PER_USER", "3")])).unwrap();
+        assert_eq!(
+            config.session_limit,
+            Some(SessionLimit {
+                max_sessions: 3,
+                policy: SessionLimitPolicy::EvictOldest,
+            })
+        );
+
+        let config = Config::from_vars(vars(&[
+            ("MAX_SESSIONS_PER_USER", "3"),
+            ("SESSION_LIMIT_POLICY", "reject"),
+        ]))
+        .unwrap();
+        assert_eq!(
+            config.session_limit.unwrap().policy,
+            SessionLimitPolicy::Reject
+        );
+
+        assert!(Config::from_vars(vars(&[
+            ("MAX_SESSIONS_PER_USER", "3"),
+            ("SESSION_LIMIT_POLICY", "ignore"),
+        ]))
+        .is_err());
+
+        assert!(Config::from_vars(vars(&[
+            ("MAX_SESSIONS_PER_USER", "3"),
+            ("SESSION_BACKEND", "jwt"),
+            ("JWT_SECRET", "secret"),
+        ]))
+        .is_err());
+    }
+
+    #[test]
+    fn should_read_session_cache() {
+        let config = Config::from_vars(vars(&[])).unwrap();
+        assert!(config.session_cache.is_none());
+
+        let config = Config::from_vars(vars(&[("SESSION_CACHE_SIZE", "0")])).unwrap();
+        assert!(config.session_cache.is_none());
+
+        let config = Config::from_vars(vars(&[("SESSION_CACHE_SIZE", "10000")])).unwrap();
+        assert_eq!(
+            config.session_cache,
+            Some(SessionCacheConfig {
+                capacity: NonZeroUsize::new(10_000).unwrap(),
+                ttl: DEFAULT_SESSION_CACHE_TTL,
+            })
+        );
+
+        let config = Config::from_vars(vars(&[
+            ("SESSION_CACHE_SIZE", "10000"),
+            ("SESSION_CACHE_TTL_MILLIS", "500"),
+        ]))
+        .unwrap();
+        assert_eq!(
+            config.session_cache.unwrap().ttl,
+            Duration::from_millis(500)
+        );
+
+        assert!(Config::from_vars(vars(&[
+            ("SESSION_CACHE_SIZE", "10000"),
+            ("SESSION_CACHE_TTL_MILLIS", "0"),
+        ]))
+        .is_err());
+    }
+
+    #[test]
+    fn should_read_session_token_keys() {
+        let config = Config::from_vars(vars(&[])).unwrap();
+        assert!(config.session_token_keys.is_empty());
+
+        let config = Config::from_vars(vars(&[("SESSION_TOKEN_KEYS", "new, old")])).unwrap();
+        assert_eq!(
+            config.session_token_keys,
+            vec![Secret::from("new"), Secret::from("old")]
+        );
+    }
+
+    #[test]
+    fn should_not_format_secrets() {
+        let config = Config::from_vars(vars(&[
+            ("SESSION_TOKEN_KEYS", "signing-key"),
+            ("SESSION_BACKEND", "jwt"),
+            ("JWT_SECRET", "jwt-secret"),
+            ("LDAP_URL", "ldaps://directory.example.com"),
+            ("LDAP_BIND_PASSWORD", "bind-password"),
+            ("LDAP_BASE_DN", "dc=example,dc=com"),
+        ]))
+        .unwrap();
+
+        let formatted = format!("{:?}", config);
+        assert!(!formatted.contains("signing-key"));
+        assert!(!formatted.contains("jwt-secret"));
+        assert!(!formatted.contains("bind-password"));
+    }
+
+    #[test]
+    fn should_read_jwt_session_settings() {
+        let config = Config::from_vars(vars(&[])).unwrap();
+        assert!(config.jwt_sessions.is_none());
+
+        let config = Config::from_vars(vars(&[
+            ("SESSION_BACKEND", "jwt"),
+            ("JWT_SECRET", "secret"),
+        ]))
+        .unwrap();
+        assert_eq!(
+            config.jwt_sessions,
+            Some(JwtKeys::Hs256 {
+                secret: "secret".into()
+            })
+        );
+
+        let config = Config::from_vars(vars(&[
+            ("SESSION_BACKEND", "jwt"),
+            ("JWT_ALGORITHM", "RS256"),
+            ("JWT_PRIVATE_KEY_PATH", "private.pem"),
+            ("JWT_PUBLIC_KEY_PATH", "public.pem"),
+        ]))
+        .unwrap();
+        assert_eq!(
+            config.jwt_sessions,
+            Some(JwtKeys::Rs256 {
+                private_key_path: "private.pem".to_owned(),
+                public_key_path: "public.pem".to_owned(),
+            })
+        );
+
+        assert!(Config::from_vars(vars(&[("SESSION_BACKEND", "jwt")])).is_err());
+        assert!(Config::from_vars(vars(&[("SESSION_BACKEND", "redis")])).is_err());
+    }
+
+    #[test]
+    fn should_read_jwt_revocations_path() {
+        let config = Config::from_vars(vars(&[])).unwrap();
+        assert!(config.jwt_revocations_path.is_none());
+
+        let config = Config::from_vars(vars(&[
+            ("SESSION_BACKEND", "jwt"),
+            ("JWT_SECRET", "secret"),
+            ("JWT_REVOCATIONS_PATH", "revocations.db"),
+        ]))
+        .unwrap();
+        assert_eq!(
+            config.jwt_revocations_path.as_deref(),
+            Some("revocations.db")
+        );
+
+        assert!(Config::from_vars(vars(&[("JWT_REVOCATIONS_PATH", "revocations.db")])).is_err());
+    }
+
+    #[test]
+    fn should_read_session_store_path() {
+        let config = Config::from_vars(vars(&[])).unwrap();
+        assert!(config.session_store_path.is_none());
+
+        let config = Config::from_vars(vars(&[
+            ("SESSION_STORE_PATH", "sessions.db"),
+            ("SESSION_TOKEN_KEYS", "key"),
+        ]))
+        .unwrap();
+        assert_eq!(config.session_store_path.as_deref(), Some("sessions.db"));
+
+        assert!(Config::from_vars(vars(&[("SESSION_STORE_PATH", "sessions.db")])).is_err());
+        assert!(Config::from_vars(vars(&[
+            ("SESSION_STORE_PATH", "sessions.db"),
+            ("SESSION_TOKEN_KEYS", "key"),
+            ("SESSION_BACKEND", "jwt"),
+            ("JWT_SECRET", "secret"),
+        ]))
+        .is_err());
+    }
+
+    #[test]
+    fn should_read_users_backend() {
+        let config = Config::from_vars(vars(&[])).unwrap();
+        assert!(config.users_database.is_none());
+
+        let config = Config::from_vars(vars(&[
+            ("USERS_BACKEND", "postgres"),
+            ("DATABASE_URL", "postgres://localhost/auth"),
+        ]))
+        .unwrap();
+        assert_eq!(
+            config.users_database,
+            Some(DatabaseConfig::Postgres {
+                url: "postgres://localhost/auth".to_owned(),
+                max_connections: DEFAULT_DATABASE_MAX_CONNECTIONS,
+            })
+        );
+
+        let config = Config::from_vars(vars(&[("USERS_BACKEND", "sqlite")])).unwrap();
+        assert_eq!(
+            config.users_database,
+            Some(DatabaseConfig::Sqlite {
+                path: DEFAULT_SQLITE_PATH.to_owned(),
+            })
+        );
+
+        assert!(Config::from_vars(vars(&[("USERS_BACKEND", "postgres")])).is_err());
+        assert!(Config::from_vars(vars(&[("USERS_BACKEND", "mysql")])).is_err());
+    }
+
+    #[test]
+    fn should_read_storage_retry_budget() {
+        let config = Config::from_vars(vars(&[])).unwrap();
+        assert_eq!(config.storage_retry_budget, DEFAULT_STORAGE_RETRY_BUDGET);
+
+        let config = Config::from_vars(vars(&[("STORAGE_RETRY_BUDGET_MILLIS", "2000")])).unwrap();
+        assert_eq!(config.storage_retry_budget, Duration::from_secs(2));
+    }
+
+    #[test]
+    fn should_read_database_migrations() {
+        let config = Config::from_vars(vars(&[])).unwrap();
+        assert_eq!(config.database_migrations, Migrations::Startup);
+        assert!(!config.migrate);
+
+        let config = Config::from_vars(vars(&[
+            ("USERS_BACKEND", "sqlite"),
+            ("DATABASE_MIGRATIONS", "manual"),
+            ("MIGRATE", "true"),
+        ]))
+        .unwrap();
+        assert_eq!(config.database_migrations, Migrations::Manual);
+        assert!(config.migrate);
+
+        // Nothing to migrate in memory
+        assert!(Config::from_vars(vars(&[("MIGRATE", "true")])).is_err());
+        assert!(Config::from_vars(vars(&[("DATABASE_MIGRATIONS", "never")])).is_err());
+    }
+
+    #[test]
+    fn should_read_seed_path() {
+        let config = Config::from_vars(vars(&[])).unwrap();
+        assert!(config.seed_path.is_none());
+
+        let config = Config::from_vars(vars(&[("SEED", "seed.toml")])).unwrap();
+        assert_eq!(config.seed_path.as_deref(), Some("seed.toml"));
+
+        let config = Config::from_vars(vars(&[("SEED", "")])).unwrap();
+        assert!(config.seed_path.is_none());
+    }
+
+    #[test]
+    fn should_read_sqlite_sessions_path() {
+        let config = Config::from_vars(vars(&[])).unwrap();
+        assert!(config.sqlite_sessions_path.is_none());
+
+        let config = Config::from_vars(vars(&[
+            ("SESSION_BACKEND", "sqlite"),
+            ("SESSION_TOKEN_KEYS", "key"),
+            ("SQLITE_PATH", "/var/lib/auth/auth.db"),
+        ]))
+        .unwrap();
+        assert_eq!(
+            config.sqlite_sessions_path.as_deref(),
+            Some("/var/lib/auth/auth.db")
+        );
+        assert!(config.jwt_sessions.is_none());
+
+        assert!(Config::from_vars(vars(&[("SESSION_BACKEND", "sqlite")])).is_err());
+    }
+
+    #[test]
+    fn should_read_password_hashing() {
+        let config = Config::from_vars(vars(&[])).unwrap();
+        assert_eq!(
+            config.password_hashing,
+            PasswordHashing::Pbkdf2 {
+                rounds: DEFAULT_PBKDF2_ROUNDS
+            }
+        );
+
+        let config = Config::from_vars(vars(&[("PBKDF2_ROUNDS", "1000")])).unwrap();
+        assert_eq!(
+            config.password_hashing,
+            PasswordHashing::Pbkdf2 { rounds: 1000 }
+        );
+
+        let config = Config::from_vars(vars(&[
+            ("PASSWORD_HASH_ALGORITHM", "argon2id"),
+            ("ARGON2_MEMORY_KIB", "65536"),
+            ("ARGON2_ITERATIONS", "3"),
+        ]))
+        .unwrap();
+        assert_eq!(
+            config.password_hashing,
+            PasswordHashing::Argon2id {
+                memory_kib: 65536,
+                iterations: 3,
+                parallelism: DEFAULT_ARGON2_PARALLELISM,
+            }
+        );
+
+        assert!(Config::from_vars(vars(&[("PASSWORD_HASH_ALGORITHM", "md5")])).is_err());
+    }
+
+    #[test]
+    fn should_read_password_hashing_concurrency() {
+        let config = Config::from_vars(vars(&[])).unwrap();
+        assert_eq!(
+            config.password_hashing_concurrency,
+            default_hashing_concurrency()
+        );
+
+        let config = Config::from_vars(vars(&[("PASSWORD_HASHING_CONCURRENCY", "4")])).unwrap();
+        assert_eq!(config.password_hashing_concurrency, 4);
+
+        assert!(Config::from_vars(vars(&[("PASSWORD_HASHING_CONCURRENCY", "0")])).is_err());
+    }
+
+    #[test]
+    fn should_read_password_hash_benchmark() {
+        let config = Config::from_vars(vars(&[])).unwrap();
+        assert_eq!(config.password_hash_benchmark, None);
+
+        let config = Config::from_vars(vars(&[("PASSWORD_HASH_BENCHMARK_MILLIS", "250")])).unwrap();
+        assert_eq!(
+            config.password_hash_benchmark,
+            Some(Duration::from_millis(250))
+        );
+
+        assert!(Config::from_vars(vars(&[("PASSWORD_HASH_BENCHMARK_MILLIS", "0")])).is_err());
+    }
+
+    #[test]
+    fn should_read_user_id_format() {
+        let config = Config::from_vars(vars(&[])).unwrap();
+        assert_eq!(config.user_id_format, UserIdFormat::UuidV4);
+
+        let config = Config::from_vars(vars(&[("USER_ID_FORMAT", "uuid-v7")])).unwrap();
+        assert_eq!(config.user_id_format, UserIdFormat::UuidV7);
+
+        let config = Config::from_vars(vars(&[
+            ("USER_ID_FORMAT", "nanoid"),
+            ("NANOID_LENGTH", "12"),
+        ]))
+        .unwrap();
+        assert_eq!(config.user_id_format, UserIdFormat::NanoId { length: 12 });
+
+        assert!(Config::from_vars(vars(&[("USER_ID_FORMAT", "serial")])).is_err());
+    }
+
+    #[test]
+    fn should_read_password_policy() {
+        let config = Config::from_vars(vars(&[])).unwrap();
+        assert_eq!(config.password_policy, PasswordPolicy::default());
+        assert!(config.password_denylist_path.is_none());
+
+        let config = Config::from_vars(vars(&[
+            ("PASSWORD_MIN_LENGTH", "12"),
+            ("PASSWORD_MAX_LENGTH", "64"),
+            ("PASSWORD_REQUIRED_CLASSES", "uppercase, digit"),
+            ("PASSWORD_DENYLIST_PATH", "/etc/auth/denylist.txt"),
+        ]))
+        .unwrap();
+        assert_eq!(config.password_policy.min_length, 12);
+        assert_eq!(config.password_policy.max_length, 64);
+        assert!(!config.password_policy.require_lowercase);
+        assert!(config.password_policy.require_uppercase);
+        assert!(config.password_policy.require_digit);
+        assert!(!config.password_policy.require_symbol);
+        assert_eq!(
+            config.password_denylist_path.as_deref(),
+            Some("/etc/auth/denylist.txt")
+        );
+
+        assert!(Config::from_vars(vars(&[("PASSWORD_REQUIRED_CLASSES", "emoji")])).is_err());
+        assert!(Config::from_vars(vars(&[
+            ("PASSWORD_MIN_LENGTH", "20"),
+            ("PASSWORD_MAX_LENGTH", "10"),
+        ]))
+        .is_err());
+    }
+
+    #[test]
+    fn should_read_ldap_settings() {
+        let config = Config::from_vars(vars(&[])).unwrap();
+        assert!(config.ldap.is_none());
+
+        let config = Config::from_vars(vars(&[
+            ("LDAP_URL", "ldaps://directory.example.com"),
+            ("LDAP_BASE_DN", "dc=example,dc=com"),
+            ("LDAP_USER_FILTER", "(sAMAccountName={username})"),
+            ("LDAP_USERNAME_ATTRIBUTE", "sAMAccountName"),
+        ]))
+        .unwrap();
+        assert_eq!(
+            config.ldap,
+            Some(LdapConfig {
+                url: "ldaps://directory.example.com".to_owned(),
+                bind_dn: None,
+                bind_password: Secret::default(),
+                base_dn: "dc=example,dc=com".to_owned(),
+                user_filter: "(sAMAccountName={username})".to_owned(),
+                username_attribute: "sAMAccountName".to_owned(),
+                display_name_attribute: DEFAULT_LDAP_DISPLAY_NAME_ATTRIBUTE.to_owned(),
+                email_attribute: DEFAULT_LDAP_EMAIL_ATTRIBUTE.to_owned(),
+                timeout: DEFAULT_LDAP_TIMEOUT,
+            })
+        );
+
+        assert!(Config::from_vars(vars(&[("LDAP_URL", "ldap://localhost")])).is_err());
+        assert!(Config::from_vars(vars(&[
+            ("LDAP_URL", "ldap://localhost"),
+            ("LDAP_BASE_DN", "dc=example,dc=com"),
+            ("LDAP_USER_FILTER", "(uid=alice)"),
+        ]))
+        .is_err());
+    }
+
+    #[test]
+    fn should_read_breached_password_check() {
+        let config = Config::from_vars(vars(&[])).unwrap();
+        assert!(!config.breached_password_check);
+        assert_eq!(
+            config.breached_password_check_timeout,
+            DEFAULT_BREACH_CHECK_TIMEOUT
+        );
+
+        let config = Config::from_vars(vars(&[
+            ("BREACHED_PASSWORD_CHECK", "true"),
+            ("BREACHED_PASSWORD_CHECK_TIMEOUT_MILLIS", "500"),
+        ]))
+        .unwrap();
+        assert!(config.breached_password_check);
+        assert_eq!(
+            config.breached_password_check_timeout,
+            Duration::from_millis(500)
+        );
+    }
+
+    #[test]
+    fn should_read_deletion_grace_period() {
+        let config = Config::from_vars(vars(&[])).unwrap();
+        assert_eq!(config.deletion_grace_period, DEFAULT_DELETION_GRACE_PERIOD);
+
+        let config =
+            Config::from_vars(vars(&[("ACCOUNT_DELETION_GRACE_PERIOD_SECS", "3600")])).unwrap();
+        assert_eq!(config.deletion_grace_period, Duration::from_secs(3600));
+    }
+
+    #[test]
+    fn should_fail_on_incomplete_oidc_provider() {
+        let result = Config::from_vars(vars(&[
+            ("OIDC_PROVIDERS", "google"),
+            ("OIDC_GOOGLE_ISSUER", "https://accounts.google.com"),
+        ]));
+
+        assert!(result.is_err());
+    }
+}
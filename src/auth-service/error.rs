@@ -0,0 +1,153 @@
+use tonic::Status;
+
+use crate::metrics::{record_failure, FailureReason};
+
+/// Why the auth service failed to do something, whichever backend it was up to.
+#[derive(Clone, Debug, PartialEq, Eq, thiserror::Error)]
+pub enum AuthError {
+    #[error(transparent)]
+    Storage(#[from] StorageError),
+    #[error(transparent)]
+    Session(#[from] SessionError),
+}
+
+/// Why a users backend refused or failed a change.
+#[derive(Clone, Debug, PartialEq, Eq, thiserror::Error)]
+pub enum StorageError {
+    #[error("Error, username not unique")]
+    UsernameTaken,
+    #[error("Error, email not unique")]
+    EmailTaken,
+    #[error("Error, user uuid not unique")]
+    UserUuidTaken,
+    #[error("Error, identity already linked")]
+    IdentityLinked,
+    #[error("Error, duplicate user in import")]
+    DuplicateImport,
+    #[error("Error, user uuid not found")]
+    UserNotFound,
+    #[error("Error, user already deleted")]
+    AlreadyDeleted,
+    #[error("Error, user not deleted")]
+    NotDeleted,
+    /// The key or value of an attribute breaks the attribute limits.
+    #[error("Error, {0}")]
+    InvalidAttribute(&'static str),
+    #[error("Error, unsupported password hash")]
+    UnsupportedPasswordHash,
+    #[error("Error, password managed by the directory")]
+    PasswordManagedByDirectory,
+    #[error("Failed to hash the password.\n{0}")]
+    Hashing(String),
+    /// The database or directory failed, e.g. because it's unreachable.
+    #[error("{0}")]
+    Backend(String),
+}
+
+impl StorageError {
+    /// Records a backend failure of the RPC being handled, see `metrics::record_failure`.
+    #[cfg_attr(
+        not(any(feature = "postgres", feature = "sqlite", feature = "ldap")),
+        allow(dead_code)
+    )]
+    pub fn backend(message: String) -> Self {
+        record_failure(FailureReason::BackendError);
+        Self::Backend(message)
+    }
+}
+
+/// Why a sessions backend couldn't serve a session.
+#[derive(Clone, Debug, PartialEq, Eq, thiserror::Error)]
+pub enum SessionError {
+    #[error("Session not found")]
+    NotFound,
+    #[error("Session events aren't published by this sessions backend")]
+    EventsUnsupported,
+    #[error("Sessions can't be restored into this sessions backend")]
+    BackupUnsupported,
+    #[error("Sessions can't be listed with this sessions backend")]
+    ListingUnsupported,
+    #[error("{0}")]
+    InvalidBackup(String),
+    /// The database failed, e.g. because it's unreachable.
+    #[error("{0}")]
+    Backend(String),
+}
+
+impl SessionError {
+    /// Records a backend failure of the RPC being handled, see `metrics::record_failure`.
+    #[cfg_attr(not(feature = "sqlite"), allow(dead_code))]
+    pub fn backend(message: String) -> Self {
+        record_failure(FailureReason::BackendError);
+        Self::Backend(message)
+    }
+}
+
+impl From<AuthError> for Status {
+    fn from(error: AuthError) -> Self {
+        match error {
+            AuthError::Storage(e) => e.into(),
+            AuthError::Session(e) => e.into(),
+        }
+    }
+}
+
+impl From<StorageError> for Status {
+    fn from(error: StorageError) -> Self {
+        use StorageError::*;
+
+        match error {
+            UsernameTaken | EmailTaken | UserUuidTaken | IdentityLinked | DuplicateImport => {
+                Status::already_exists(error.to_string())
+            }
+            UserNotFound => Status::not_found(error.to_string()),
+            AlreadyDeleted | NotDeleted | PasswordManagedByDirectory => {
+                Status::failed_precondition(error.to_string())
+            }
+            InvalidAttribute(_) | UnsupportedPasswordHash => {
+                Status::invalid_argument(error.to_string())
+            }
+            // The details are logged where they happen, they may describe the infrastructure
+            Hashing(_) => Status::internal("Failed to hash the password"),
+            Backend(_) => Status::unavailable("The users backend is unavailable"),
+        }
+    }
+}
+
+impl From<SessionError> for Status {
+    fn from(error: SessionError) -> Self {
+        match error {
+            SessionError::NotFound => Status::unauthenticated(error.to_string()),
+            SessionError::EventsUnsupported
+            | SessionError::BackupUnsupported
+            | SessionError::ListingUnsupported => Status::unimplemented(error.to_string()),
+            SessionError::InvalidBackup(_) => Status::invalid_argument(error.to_string()),
+            SessionError::Backend(_) => Status::unavailable("The sessions backend is unavailable"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tonic::Code;
+
+    use super::*;
+
+    #[test]
+    fn should_convert_to_status() {
+        let status = Status::from(AuthError::from(StorageError::UsernameTaken));
+        assert_eq!(status.code(), Code::AlreadyExists);
+        assert_eq!(status.message(), "Error, username not unique");
+
+        let status = Status::from(StorageError::InvalidAttribute("too many attributes"));
+        assert_eq!(status.code(), Code::InvalidArgument);
+        assert_eq!(status.message(), "Error, too many attributes");
+
+        // Backend details aren't sent to clients
+        let status = Status::from(AuthError::from(SessionError::Backend(
+            "Failed to reach db.internal:5432".to_owned(),
+        )));
+        assert_eq!(status.code(), Code::Unavailable);
+        assert!(!status.message().contains("db.internal"));
+    }
+}
@@ -0,0 +1,229 @@
+use std::fmt::{self, Write as _};
+use std::sync::Arc;
+use std::time::Duration;
+
+use tracing::field::{Field, Visit};
+use tracing::{Event, Level, Subscriber};
+use tracing_subscriber::layer::Context;
+use tracing_subscriber::Layer;
+
+#[cfg(feature = "sentry")]
+pub use sentry_reporter::SentryReporter;
+
+/// How long reports still being sent get at shutdown.
+pub const FLUSH_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// What went wrong.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ErrorKind {
+    /// A thread panicked, e.g. the task handling an RPC.
+    Panic,
+    /// An error was logged, e.g. a database failing.
+    Error,
+}
+
+/// A panic or internal error for an error tracker.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ErrorReport {
+    pub kind: ErrorKind,
+    /// The panic message, or the logged message followed by the event's other fields.
+    pub message: String,
+    /// The module that logged the error, or the file and line that panicked.
+    pub location: Option<String>,
+}
+
+/// Where panics and internal errors are sent so incidents surface in an error tracker.
+pub trait ErrorReporter: Send + Sync {
+    fn report(&self, report: ErrorReport);
+
+    /// Waits up to `timeout` for reports still being sent.
+    fn flush(&self, _timeout: Duration) {}
+}
+
+/// Reports every panic to `reporter`, then hands it to the hook that was set before.
+pub fn report_panics(reporter: Arc<dyn ErrorReporter>) {
+    let previous_hook = std::panic::take_hook();
+    std::panic::set_hook(Box::new(move |info| {
+        let payload = info.payload();
+        let message = match (
+            payload.downcast_ref::<&str>(),
+            payload.downcast_ref::<String>(),
+        ) {
+            (Some(message), _) => message.to_string(),
+            (None, Some(message)) => message.clone(),
+            (None, None) => "Box<dyn Any>".to_owned(),
+        };
+        reporter.report(ErrorReport {
+            kind: ErrorKind::Panic,
+            message,
+            location: info
+                .location()
+                .map(|location| format!("{}:{}", location.file(), location.line())),
+        });
+        previous_hook(info);
+    }));
+}
+
+/// Reports events logged at the error level to its reporter.
+pub struct ErrorReportingLayer {
+    reporter: Arc<dyn ErrorReporter>,
+}
+
+impl ErrorReportingLayer {
+    pub fn new(reporter: Arc<dyn ErrorReporter>) -> Self {
+        Self { reporter }
+    }
+}
+
+impl<S: Subscriber> Layer<S> for ErrorReportingLayer {
+    fn on_event(&self, event: &Event<'_>, _ctx: Context<'_, S>) {
+        if *event.metadata().level() != Level::ERROR {
+            return;
+        }
+
+        let mut fields = EventFields::default();
+        event.record(&mut fields);
+        self.reporter.report(ErrorReport {
+            kind: ErrorKind::Error,
+            message: fields.message + &fields.rest,
+            location: Some(event.metadata().target().to_owned()),
+        });
+    }
+}
+
+/// An event's message, and its other fields as ` key=value` pairs.
+#[derive(Default)]
+struct EventFields {
+    message: String,
+    rest: String,
+}
+
+impl Visit for EventFields {
+    fn record_str(&mut self, field: &Field, value: &str) {
+        match field.name() {
+            "message" => self.message.push_str(value),
+            name => {
+                let _ = write!(self.rest, " {}={:?}", name, value);
+            }
+        }
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        let _ = match field.name() {
+            "message" => write!(self.message, "{:?}", value),
+            name => write!(self.rest, " {}={:?}", name, value),
+        };
+    }
+}
+
+#[cfg(feature = "sentry")]
+mod sentry_reporter {
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    use sentry::protocol::{Event, Exception, Level, Mechanism};
+
+    use super::{ErrorKind, ErrorReport, ErrorReporter};
+    use crate::config::SentryConfig;
+
+    /// Sends reports to Sentry, or any service taking its protocol, in the background.
+    pub struct SentryReporter {
+        client: Arc<sentry::Client>,
+    }
+
+    impl SentryReporter {
+        pub fn new(config: &SentryConfig) -> Result<Self, String> {
+            let dsn = config
+                .dsn
+                .expose()
+                .parse()
+                .map_err(|e| format!("Error, SENTRY_DSN is invalid.\n{e:?}"))?;
+            let options = sentry::apply_defaults(sentry::ClientOptions {
+                dsn: Some(dsn),
+                release: sentry::release_name!(),
+                environment: config.environment.clone().map(Into::into),
+                ..Default::default()
+            });
+
+            Ok(Self {
+                client: Arc::new(sentry::Client::from(options)),
+            })
+        }
+    }
+
+    impl ErrorReporter for SentryReporter {
+        fn report(&self, report: ErrorReport) {
+            let event = match report.kind {
+                ErrorKind::Panic => Event {
+                    level: Level::Fatal,
+                    exception: vec![Exception {
+                        ty: "panic".to_owned(),
+                        value: Some(report.message),
+                        stacktrace: sentry::integrations::backtrace::current_stacktrace(),
+                        mechanism: Some(Mechanism {
+                            ty: "panic".to_owned(),
+                            handled: Some(false),
+                            ..Default::default()
+                        }),
+                        ..Default::default()
+                    }]
+                    .into(),
+                    culprit: report.location,
+                    ..Default::default()
+                },
+                ErrorKind::Error => Event {
+                    level: Level::Error,
+                    message: Some(report.message),
+                    logger: report.location,
+                    ..Default::default()
+                },
+            };
+            self.client.capture_event(event, None);
+        }
+
+        fn flush(&self, timeout: Duration) {
+            self.client.flush(Some(timeout));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Mutex;
+
+    use tracing_subscriber::layer::SubscriberExt;
+
+    use super::*;
+
+    #[derive(Default)]
+    struct TestReporter {
+        reports: Mutex<Vec<ErrorReport>>,
+    }
+
+    impl ErrorReporter for TestReporter {
+        fn report(&self, report: ErrorReport) {
+            self.reports.lock().unwrap().push(report);
+        }
+    }
+
+    #[test]
+    fn should_report_logged_errors() {
+        let reporter = Arc::new(TestReporter::default());
+        let subscriber =
+            tracing_subscriber::registry().with(ErrorReportingLayer::new(reporter.clone()));
+
+        tracing::subscriber::with_default(subscriber, || {
+            tracing::warn!("Retrying");
+            tracing::error!(error = "connection refused", "Failed to query users");
+        });
+
+        assert_eq!(
+            *reporter.reports.lock().unwrap(),
+            [ErrorReport {
+                kind: ErrorKind::Error,
+                message: "Failed to query users error=\"connection refused\"".to_owned(),
+                location: Some(module_path!().to_owned()),
+            }]
+        );
+    }
+}
@@ -0,0 +1,224 @@
+use std::mem;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Each level has 64 slots, each covering 64 slots of the level below.
+const SLOT_BITS: u32 = 6;
+const SLOTS: usize = 1 << SLOT_BITS;
+/// With one second ticks the levels reach 64 seconds, 68 minutes, 3 days and 194 days ahead.
+const LEVELS: usize = 4;
+
+struct Entry<K> {
+    /// The tick the key is due at.
+    deadline: u64,
+    key: K,
+}
+
+struct Level<K> {
+    slots: Vec<Vec<Entry<K>>>,
+    /// Entries across all slots, so empty levels are skipped without looking at their slots.
+    len: usize,
+}
+
+/// Hierarchical timer wheel filing keys by the second they're due at, so taking the keys due
+/// costs time in proportion to them rather than to every key filed.
+///
+/// Keys due within 64 seconds sit in the slot of their second. Keys due later sit in coarser
+/// slots, and are spread over the finer ones as their slot comes up. Keys are never taken before
+/// their deadline, and are taken by the first `advance` at least two seconds after it.
+pub struct ExpiryWheel<K> {
+    /// The next tick to turn the wheel to. Earlier ticks have been taken.
+    elapsed: u64,
+    levels: Vec<Level<K>>,
+    /// Keys due further ahead than the levels reach, filed when the top level comes round.
+    overflow: Vec<Entry<K>>,
+    /// Keys filed with a deadline that had already passed, taken on the next `advance`.
+    due: Vec<K>,
+}
+
+impl<K> ExpiryWheel<K> {
+    /// Starts turning the wheel at `now`.
+    pub fn new(now: SystemTime) -> Self {
+        Self {
+            elapsed: seconds(now),
+            levels: (0..LEVELS)
+                .map(|_| Level {
+                    slots: (0..SLOTS).map(|_| Vec::new()).collect(),
+                    len: 0,
+                })
+                .collect(),
+            overflow: Vec::new(),
+            due: Vec::new(),
+        }
+    }
+
+    /// Files `key` to be taken once `deadline` has passed.
+    pub fn insert(&mut self, deadline: SystemTime, key: K) {
+        // Rounded up, so keys aren't taken in the second before their deadline.
+        let deadline = deadline
+            .duration_since(UNIX_EPOCH)
+            .map(|since_epoch| since_epoch.as_secs() + u64::from(since_epoch.subsec_nanos() > 0))
+            .unwrap_or_default();
+        self.file(Entry { deadline, key });
+    }
+
+    /// Turns the wheel to `now`, taking the keys whose deadline has passed.
+    pub fn advance(&mut self, now: SystemTime) -> Vec<K> {
+        let now = seconds(now);
+        let mut due = mem::take(&mut self.due);
+
+        while self.elapsed <= now {
+            // Skips ahead to the next tick with keys to take or spread, as empty levels have
+            // nothing to do before the level above them moves to its next slot.
+            let mut tick = Some(self.elapsed);
+            for level in 0..LEVELS {
+                if self.levels[level].len > 0 {
+                    break;
+                }
+                let span = 1 << (SLOT_BITS * (level as u32 + 1));
+                tick = match level == LEVELS - 1 && self.overflow.is_empty() {
+                    true => None,
+                    false => Some(self.elapsed.div_ceil(span) * span),
+                };
+            }
+
+            match tick {
+                Some(tick) if tick <= now => {
+                    self.elapsed = tick;
+                    self.turn(tick, &mut due);
+                    self.elapsed = tick + 1;
+                }
+                _ => self.elapsed = now + 1,
+            }
+        }
+
+        due
+    }
+
+    /// Spreads the slots starting at `tick` over the levels below, then takes the keys due at it.
+    fn turn(&mut self, tick: u64, due: &mut Vec<K>) {
+        if tick.is_multiple_of(1 << (SLOT_BITS * LEVELS as u32)) {
+            for entry in mem::take(&mut self.overflow) {
+                self.file(entry);
+            }
+        }
+        for level in (1..LEVELS).rev() {
+            if tick.is_multiple_of(1 << (SLOT_BITS * level as u32)) {
+                for entry in self.take_slot(level, tick) {
+                    self.file(entry);
+                }
+            }
+        }
+        due.extend(self.take_slot(0, tick).map(|entry| entry.key));
+    }
+
+    fn take_slot(&mut self, level: usize, tick: u64) -> impl Iterator<Item = Entry<K>> {
+        let slot = slot(level, tick);
+        let level = &mut self.levels[level];
+        let entries = mem::take(&mut level.slots[slot]);
+        level.len -= entries.len();
+        entries.into_iter()
+    }
+
+    fn file(&mut self, entry: Entry<K>) {
+        if entry.deadline < self.elapsed {
+            self.due.push(entry.key);
+            return;
+        }
+
+        // The highest group of bits the deadline differs from the current tick in picks the
+        // level, so the entry's slot comes up before its deadline and no later than it.
+        let differing = (self.elapsed ^ entry.deadline) | (SLOTS as u64 - 1);
+        let level = ((u64::BITS - 1 - differing.leading_zeros()) / SLOT_BITS) as usize;
+        if level >= LEVELS {
+            self.overflow.push(entry);
+            return;
+        }
+
+        let slot = slot(level, entry.deadline);
+        let level = &mut self.levels[level];
+        level.slots[slot].push(entry);
+        level.len += 1;
+    }
+}
+
+/// The slot `tick` falls in on `level`.
+fn slot(level: usize, tick: u64) -> usize {
+    (tick >> (SLOT_BITS * level as u32)) as usize & (SLOTS - 1)
+}
+
+fn seconds(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .map(|since_epoch| since_epoch.as_secs())
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+
+    fn at(seconds: u64) -> SystemTime {
+        UNIX_EPOCH + Duration::from_secs(1_700_000_000 + seconds)
+    }
+
+    #[test]
+    fn should_take_keys_once_due() {
+        let mut wheel = ExpiryWheel::new(at(0));
+        wheel.insert(at(10), "soon");
+        wheel.insert(at(10) + Duration::from_millis(500), "rounded up");
+        wheel.insert(at(60 * 60 * 24), "tomorrow");
+
+        assert!(wheel.advance(at(9)).is_empty());
+        assert_eq!(wheel.advance(at(10)), vec!["soon"]);
+        assert_eq!(wheel.advance(at(11)), vec!["rounded up"]);
+        assert!(wheel.advance(at(60 * 60 * 24 - 1)).is_empty());
+        assert_eq!(wheel.advance(at(60 * 60 * 24)), vec!["tomorrow"]);
+    }
+
+    #[test]
+    fn should_take_keys_beyond_levels() {
+        let mut wheel = ExpiryWheel::new(at(0));
+        let next_year = 60 * 60 * 24 * 365;
+        wheel.insert(at(next_year), "next year");
+
+        assert!(wheel.advance(at(next_year - 1)).is_empty());
+        assert_eq!(wheel.advance(at(next_year)), vec!["next year"]);
+    }
+
+    #[test]
+    fn should_take_keys_filed_past_deadline() {
+        let mut wheel = ExpiryWheel::new(at(100));
+        wheel.insert(at(50), "late");
+
+        assert_eq!(wheel.advance(at(100)), vec!["late"]);
+        assert!(wheel.advance(at(101)).is_empty());
+    }
+
+    #[test]
+    fn should_take_every_key_at_its_deadline() {
+        let mut wheel = ExpiryWheel::new(at(0));
+        // Spread over every level, filed as the wheel turns
+        let deadlines: Vec<u64> = (0..2_000u64).map(|i| i * 7919 % 300_000 + i).collect();
+
+        let mut taken = Vec::new();
+        for (now, &deadline) in (0..).step_by(150).zip(&deadlines) {
+            wheel.insert(at(now + deadline), now + deadline);
+            for key in wheel.advance(at(now)) {
+                taken.push((key, now));
+            }
+        }
+        for now in
+            (deadlines.len() as u64 * 150..=deadlines.len() as u64 * 150 + 300_000).step_by(150)
+        {
+            for key in wheel.advance(at(now)) {
+                taken.push((key, now));
+            }
+        }
+
+        assert_eq!(taken.len(), deadlines.len());
+        assert!(taken
+            .iter()
+            .all(|&(deadline, now)| deadline <= now && now < deadline + 150));
+    }
+}
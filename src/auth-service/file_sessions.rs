@@ -0,0 +1,248 @@
+use std::fs;
+use std::io::ErrorKind;
+use std::path::PathBuf;
+
+use crate::error::SessionError;
+use crate::sessions::{ClientMetadata, Session, SessionId, Sessions, SessionsImpl};
+
+/// Keeps sessions in memory like `SessionsImpl`, writing them through to a file so a single node
+/// deployment doesn't sign everyone out on restart.
+///
+/// Only changes that create, replace or delete tokens are written. `last_seen` and sliding
+/// renewals from `get_session` are saved along with the next write.
+pub struct FileSessions {
+    sessions: SessionsImpl,
+    path: PathBuf,
+}
+
+impl FileSessions {
+    /// Loads the sessions saved at `path` into `sessions`. A missing file starts out empty.
+    pub fn open(path: impl Into<PathBuf>, mut sessions: SessionsImpl) -> Result<Self, String> {
+        let path = path.into();
+
+        match fs::read_to_string(&path) {
+            Ok(snapshot) => sessions.restore(&snapshot)?,
+            Err(err) if err.kind() == ErrorKind::NotFound => (),
+            Err(err) => {
+                return Err(format!(
+                    "Error reading sessions from {}: {}",
+                    path.display(),
+                    err
+                ))
+            }
+        }
+
+        Ok(Self { sessions, path })
+    }
+
+    /// Replaces the file with the current sessions. The snapshot is written next to it first so a
+    /// crash mid-write can't leave a truncated file behind.
+    ///
+    /// The file is written on the blocking pool so a slow disk doesn't stall the other requests on
+    /// the worker. Writes still happen in order, as callers hold the sessions lock until it's done.
+    async fn persist(&self) {
+        let snapshot = self.sessions.snapshot();
+        let path = self.path.clone();
+
+        let result = tokio::task::spawn_blocking(move || {
+            let temp_path = path.with_extension("tmp");
+            fs::write(&temp_path, snapshot).and_then(|_| fs::rename(&temp_path, &path))
+        })
+        .await
+        .unwrap_or_else(|err| Err(err.into()));
+
+        if let Err(err) = result {
+            tracing::error!(path = %self.path.display(), error = %err, "Failed to write sessions");
+        }
+    }
+}
+
+#[tonic::async_trait]
+impl Sessions for FileSessions {
+    async fn create_session(
+        &mut self,
+        user_uuid: &str,
+        client: ClientMetadata,
+    ) -> Result<String, SessionError> {
+        let session_token = self.sessions.create_session(user_uuid, client).await?;
+        self.persist().await;
+        Ok(session_token)
+    }
+
+    async fn get_session(&self, session_token: &str) -> Option<Session> {
+        self.sessions.get_session(session_token).await
+    }
+
+    async fn peek_session(&self, session_token: &str) -> Option<Session> {
+        self.sessions.peek_session(session_token).await
+    }
+
+    async fn list_user_sessions(&self, user_uuid: &str) -> Result<Vec<Session>, SessionError> {
+        self.sessions.list_user_sessions(user_uuid).await
+    }
+
+    async fn count_sessions(&self) -> Result<Option<usize>, SessionError> {
+        self.sessions.count_sessions().await
+    }
+
+    async fn delete_session(&mut self, session_token: &str) -> Option<SessionId> {
+        let session_id = self.sessions.delete_session(session_token).await;
+        self.persist().await;
+        session_id
+    }
+
+    async fn delete_user_session(&mut self, user_uuid: &str, session_id: SessionId) {
+        self.sessions
+            .delete_user_session(user_uuid, session_id)
+            .await;
+        self.persist().await;
+    }
+
+    async fn delete_user_sessions(&mut self, user_uuid: &str) {
+        self.sessions.delete_user_sessions(user_uuid).await;
+        self.persist().await;
+    }
+
+    async fn create_refresh_token(
+        &mut self,
+        user_uuid: &str,
+        session_token: &str,
+    ) -> Result<String, SessionError> {
+        let refresh_token = self
+            .sessions
+            .create_refresh_token(user_uuid, session_token)
+            .await?;
+        self.persist().await;
+        Ok(refresh_token)
+    }
+
+    async fn refresh_session(&mut self, refresh_token: &str) -> Option<String> {
+        let session_token = self.sessions.refresh_session(refresh_token).await?;
+        self.persist().await;
+        Some(session_token)
+    }
+
+    async fn renew_session(&mut self, session_token: &str) -> Option<String> {
+        let session_token = self.sessions.renew_session(session_token).await?;
+        self.persist().await;
+        Some(session_token)
+    }
+
+    async fn remove_expired(&mut self) -> usize {
+        let removed = self.sessions.remove_expired().await;
+        if removed > 0 {
+            self.persist().await;
+        }
+        removed
+    }
+
+    async fn flush(&mut self) {
+        // Saves `last_seen` and renewals from `get_session`, which aren't written as they happen.
+        self.persist().await;
+    }
+
+    async fn backup(&self) -> Option<String> {
+        self.sessions.backup().await
+    }
+
+    async fn restore_backup(&mut self, backup: &str) -> Result<(), SessionError> {
+        let restored = self.sessions.restore_backup(backup).await;
+        // Lines before an invalid one were restored
+        self.persist().await;
+        restored
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+
+    use uuid::Uuid;
+
+    use super::*;
+    use crate::token_signing::TokenSigner;
+
+    fn sessions_impl() -> SessionsImpl {
+        SessionsImpl::default()
+            .with_token_signer(TokenSigner::new(vec![b"0123456789abcdef".to_vec()]).unwrap())
+    }
+
+    fn temp_path() -> PathBuf {
+        env::temp_dir().join(format!("sessions-{}", Uuid::new_v4()))
+    }
+
+    #[tokio::test]
+    async fn should_start_empty_without_file() {
+        let path = temp_path();
+
+        let sessions_service = FileSessions::open(&path, sessions_impl()).unwrap();
+
+        assert!(sessions_service
+            .list_user_sessions("123456")
+            .await
+            .unwrap()
+            .is_empty());
+        assert!(!path.exists());
+    }
+
+    #[tokio::test]
+    async fn should_keep_sessions_across_restarts() {
+        let path = temp_path();
+
+        let mut sessions_service = FileSessions::open(&path, sessions_impl()).unwrap();
+        let session = sessions_service
+            .create_session("123456", ClientMetadata::default())
+            .await
+            .unwrap();
+        let signed_out = sessions_service
+            .create_session("123456", ClientMetadata::default())
+            .await
+            .unwrap();
+        sessions_service.delete_session(&signed_out).await;
+
+        let sessions_service = FileSessions::open(&path, sessions_impl()).unwrap();
+
+        assert_eq!(
+            sessions_service
+                .get_session(&session)
+                .await
+                .unwrap()
+                .user_uuid,
+            "123456"
+        );
+        assert!(sessions_service.get_session(&signed_out).await.is_none());
+
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn flush_should_save_last_seen() {
+        let path = temp_path();
+
+        let mut sessions_service = FileSessions::open(&path, sessions_impl()).unwrap();
+        let session_token = sessions_service
+            .create_session("123456", ClientMetadata::default())
+            .await
+            .unwrap();
+        tokio::time::sleep(std::time::Duration::from_millis(5)).await;
+        sessions_service.get_session(&session_token).await.unwrap();
+        sessions_service.flush().await;
+
+        let sessions_service = FileSessions::open(&path, sessions_impl()).unwrap();
+        let session = sessions_service.peek_session(&session_token).await.unwrap();
+
+        assert!(session.last_seen > session.created_at);
+
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn should_fail_to_open_invalid_file() {
+        let path = temp_path();
+        fs::write(&path, "invalid\n").unwrap();
+
+        assert!(FileSessions::open(&path, sessions_impl()).is_err());
+
+        fs::remove_file(&path).unwrap();
+    }
+}
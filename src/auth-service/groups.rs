@@ -0,0 +1,187 @@
+use std::collections::{BTreeSet, HashMap};
+
+/// Longest group name, in bytes.
+pub const MAX_GROUP_NAME_LENGTH: usize = 64;
+
+/// Named sets of users, e.g. teams, for downstream services to authorize by.
+pub trait Groups {
+    /// Creates an empty group. Fails if the name is invalid or taken.
+    fn create_group(&mut self, name: &str) -> Result<(), String>;
+    /// Deletes the group along with its memberships.
+    fn delete_group(&mut self, name: &str) -> Result<(), String>;
+    /// Adding an existing member succeeds without changing anything.
+    fn add_member(&mut self, name: &str, user_uuid: &str) -> Result<(), String>;
+    fn remove_member(&mut self, name: &str, user_uuid: &str) -> Result<(), String>;
+    /// Names of the groups the user is a member of, sorted.
+    fn user_groups(&self, user_uuid: &str) -> Vec<String>;
+    /// Removes the user from every group, e.g. once their account is purged.
+    fn delete_user_memberships(&mut self, user_uuid: &str);
+}
+
+#[derive(Default)]
+pub struct GroupsImpl {
+    group_to_members: HashMap<String, BTreeSet<String>>,
+    /// The inverse of `group_to_members`, so introspection doesn't scan every group.
+    uuid_to_groups: HashMap<String, BTreeSet<String>>,
+}
+
+impl Groups for GroupsImpl {
+    fn create_group(&mut self, name: &str) -> Result<(), String> {
+        if !is_valid_group_name(name) {
+            return Err("Error, invalid group name".to_string());
+        }
+        if self.group_to_members.contains_key(name) {
+            return Err("Error, group name not unique".to_string());
+        }
+
+        self.group_to_members
+            .insert(name.to_string(), BTreeSet::new());
+        Ok(())
+    }
+
+    fn delete_group(&mut self, name: &str) -> Result<(), String> {
+        let members = self
+            .group_to_members
+            .remove(name)
+            .ok_or("Error, group not found")?;
+
+        for user_uuid in members {
+            self.forget_membership(&user_uuid, name);
+        }
+        Ok(())
+    }
+
+    fn add_member(&mut self, name: &str, user_uuid: &str) -> Result<(), String> {
+        self.group_to_members
+            .get_mut(name)
+            .ok_or("Error, group not found")?
+            .insert(user_uuid.to_string());
+
+        self.uuid_to_groups
+            .entry(user_uuid.to_string())
+            .or_default()
+            .insert(name.to_string());
+        Ok(())
+    }
+
+    fn remove_member(&mut self, name: &str, user_uuid: &str) -> Result<(), String> {
+        let removed = self
+            .group_to_members
+            .get_mut(name)
+            .ok_or("Error, group not found")?
+            .remove(user_uuid);
+
+        if !removed {
+            return Err("Error, user not a member".to_string());
+        }
+
+        self.forget_membership(user_uuid, name);
+        Ok(())
+    }
+
+    fn user_groups(&self, user_uuid: &str) -> Vec<String> {
+        self.uuid_to_groups
+            .get(user_uuid)
+            .map(|groups| groups.iter().cloned().collect())
+            .unwrap_or_default()
+    }
+
+    fn delete_user_memberships(&mut self, user_uuid: &str) {
+        for name in self.uuid_to_groups.remove(user_uuid).unwrap_or_default() {
+            if let Some(members) = self.group_to_members.get_mut(&name) {
+                members.remove(user_uuid);
+            }
+        }
+    }
+}
+
+impl GroupsImpl {
+    /// Removes `name` from the user's groups, dropping the entry once it's empty.
+    fn forget_membership(&mut self, user_uuid: &str, name: &str) {
+        if let Some(groups) = self.uuid_to_groups.get_mut(user_uuid) {
+            groups.remove(name);
+            if groups.is_empty() {
+                self.uuid_to_groups.remove(user_uuid);
+            }
+        }
+    }
+}
+
+/// Group names end up in tokens and logs, so only allow short names without whitespace.
+fn is_valid_group_name(name: &str) -> bool {
+    !name.is_empty()
+        && name.len() <= MAX_GROUP_NAME_LENGTH
+        && !name.chars().any(|c| c.is_whitespace() || c.is_control())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn should_list_user_groups_sorted() {
+        let mut groups_service = GroupsImpl::default();
+        groups_service.create_group("engineering").unwrap();
+        groups_service.create_group("admins").unwrap();
+        groups_service.add_member("engineering", "123456").unwrap();
+        groups_service.add_member("admins", "123456").unwrap();
+        groups_service.add_member("admins", "123456").unwrap();
+        groups_service.add_member("admins", "654321").unwrap();
+
+        assert_eq!(
+            groups_service.user_groups("123456"),
+            vec!["admins", "engineering"]
+        );
+        assert_eq!(groups_service.user_groups("654321"), vec!["admins"]);
+        assert!(groups_service.user_groups("unknown").is_empty());
+    }
+
+    #[test]
+    fn should_validate_group_names() {
+        let mut groups_service = GroupsImpl::default();
+
+        assert!(groups_service.create_group("").is_err());
+        assert!(groups_service.create_group("two words").is_err());
+        assert!(groups_service
+            .create_group(&"a".repeat(MAX_GROUP_NAME_LENGTH + 1))
+            .is_err());
+
+        groups_service.create_group("team-a").unwrap();
+        assert_eq!(
+            groups_service.create_group("team-a"),
+            Err("Error, group name not unique".to_string())
+        );
+    }
+
+    #[test]
+    fn should_remove_memberships() {
+        let mut groups_service = GroupsImpl::default();
+        groups_service.create_group("admins").unwrap();
+        groups_service.create_group("engineering").unwrap();
+        groups_service.add_member("admins", "123456").unwrap();
+        groups_service.add_member("engineering", "123456").unwrap();
+        groups_service.add_member("engineering", "654321").unwrap();
+
+        groups_service.remove_member("admins", "123456").unwrap();
+        assert!(groups_service.remove_member("admins", "123456").is_err());
+        assert!(groups_service.add_member("unknown", "123456").is_err());
+        assert_eq!(groups_service.user_groups("123456"), vec!["engineering"]);
+
+        groups_service.delete_group("engineering").unwrap();
+        assert!(groups_service.user_groups("123456").is_empty());
+        assert!(groups_service.user_groups("654321").is_empty());
+        assert!(groups_service.delete_group("engineering").is_err());
+    }
+
+    #[test]
+    fn should_delete_user_memberships() {
+        let mut groups_service = GroupsImpl::default();
+        groups_service.create_group("admins").unwrap();
+        groups_service.add_member("admins", "123456").unwrap();
+
+        groups_service.delete_user_memberships("123456");
+
+        assert!(groups_service.user_groups("123456").is_empty());
+        assert!(groups_service.remove_member("admins", "123456").is_err());
+    }
+}
@@ -0,0 +1,135 @@
+use std::collections::HashMap;
+use std::future::Future;
+use std::hash::Hash;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use parking_lot::Mutex;
+
+/// How long a result is replayed to retries with the same idempotency key by default.
+pub const DEFAULT_IDEMPOTENCY_WINDOW: Duration = Duration::from_secs(60 * 10);
+
+/// Results kept before expired ones are dropped.
+const PRUNE_THRESHOLD: usize = 10_000;
+
+struct Slot<T> {
+    /// Locked while the first request with the key is handled, so retries wait for its result.
+    result: Arc<tokio::sync::Mutex<Option<T>>>,
+    expires_at: Instant,
+}
+
+/// Remembers the results of requests by idempotency key, so a retried request gets the result
+/// of the first one instead of being handled again. Results are only kept in memory, so retries
+/// reaching another replica are handled again.
+pub struct IdempotencyKeys<K, T> {
+    window: Duration,
+    key_to_slot: Mutex<HashMap<K, Slot<T>>>,
+}
+
+impl<K: Eq + Hash, T: Clone> IdempotencyKeys<K, T> {
+    /// Replays results for `window` after the first request with their key arrived.
+    pub fn new(window: Duration) -> Self {
+        Self {
+            window,
+            key_to_slot: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Returns the result of `handle`, or of the request that came with `key` before. Requests
+    /// arriving while the first one is handled wait for its result. If the first request is
+    /// cancelled before it's answered, the next one is handled instead.
+    pub async fn run(&self, key: K, handle: impl Future<Output = T>) -> T {
+        let result = {
+            let now = Instant::now();
+            let mut key_to_slot = self.key_to_slot.lock();
+
+            if key_to_slot.len() >= PRUNE_THRESHOLD {
+                key_to_slot.retain(|_, slot| slot.expires_at > now);
+            }
+
+            let slot = key_to_slot.entry(key).or_insert_with(|| Slot {
+                result: Arc::default(),
+                expires_at: now + self.window,
+            });
+            if slot.expires_at <= now {
+                *slot = Slot {
+                    result: Arc::default(),
+                    expires_at: now + self.window,
+                };
+            }
+            slot.result.clone()
+        };
+
+        let mut result = result.lock().await;
+        if let Some(result) = &*result {
+            return result.clone();
+        }
+
+        let handled = handle.await;
+        *result = Some(handled.clone());
+        handled
+    }
+}
+
+impl<K: Eq + Hash, T: Clone> Default for IdempotencyKeys<K, T> {
+    fn default() -> Self {
+        Self::new(DEFAULT_IDEMPOTENCY_WINDOW)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use super::*;
+
+    #[tokio::test]
+    async fn should_replay_result_for_same_key() {
+        let idempotency_keys = IdempotencyKeys::default();
+        let calls = AtomicUsize::new(0);
+        let handle = || async { calls.fetch_add(1, Ordering::SeqCst) };
+
+        assert_eq!(idempotency_keys.run("a", handle()).await, 0);
+        assert_eq!(idempotency_keys.run("a", handle()).await, 0);
+        assert_eq!(idempotency_keys.run("b", handle()).await, 1);
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn should_wait_for_request_in_flight() {
+        let idempotency_keys = IdempotencyKeys::default();
+        let calls = AtomicUsize::new(0);
+        let handle = || async {
+            tokio::task::yield_now().await;
+            calls.fetch_add(1, Ordering::SeqCst)
+        };
+
+        let (first, retry) = tokio::join!(
+            idempotency_keys.run("a", handle()),
+            idempotency_keys.run("a", handle()),
+        );
+
+        assert_eq!((first, retry), (0, 0));
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn should_handle_again_after_window() {
+        let idempotency_keys = IdempotencyKeys::new(Duration::ZERO);
+
+        assert_eq!(idempotency_keys.run("a", async { 1 }).await, 1);
+        assert_eq!(idempotency_keys.run("a", async { 2 }).await, 2);
+    }
+
+    #[tokio::test]
+    async fn should_handle_again_after_cancelled_request() {
+        let idempotency_keys = IdempotencyKeys::default();
+
+        let cancelled = idempotency_keys.run("a", std::future::pending());
+        assert!(tokio::time::timeout(Duration::from_millis(10), cancelled)
+            .await
+            .is_err());
+
+        assert_eq!(idempotency_keys.run("a", async { 1 }).await, 1);
+    }
+}
@@ -0,0 +1,672 @@
+// Only `JwtCodec` (behind the `jwt-sessions` feature) constructs `JwtSessions` outside of tests.
+#![cfg_attr(not(feature = "jwt-sessions"), allow(dead_code))]
+
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use sha2::{Digest, Sha256};
+
+use crate::clock::{Clock, SystemClock};
+use crate::error::SessionError;
+use crate::random::{OsRandom, Random};
+use crate::revocations::{Revocations, RevocationsImpl};
+use crate::sessions::{
+    ClientMetadata, Session, SessionId, SessionPolicy, Sessions, REFRESH_TOKEN_LIFETIME,
+};
+
+#[cfg(feature = "jwt-sessions")]
+pub use jwt::JwtCodec;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum TokenKind {
+    Session,
+    Refresh,
+}
+
+/// Everything a stateless session or refresh token carries.
+#[derive(Clone, Debug)]
+pub struct TokenClaims {
+    pub kind: TokenKind,
+    pub user_uuid: String,
+    /// Shared by a session, its refresh token and the sessions that replace it on refresh.
+    pub session_id: SessionId,
+    /// Unique per token.
+    pub token_id: String,
+    /// Bumped whenever all of the user's sessions are revoked.
+    pub generation: u32,
+    /// When the session was first created, kept across renewals. Unix timestamp (seconds).
+    pub created_at: u64,
+    /// Unix timestamp (seconds).
+    pub issued_at: u64,
+    /// Unix timestamp (seconds).
+    pub expires_at: u64,
+    /// See `client_hash`. Tokens can be read by anyone holding them, so the client's IP address
+    /// and user agent aren't put in them.
+    pub client_hash: String,
+}
+
+/// Signs and verifies tokens.
+pub trait TokenCodec {
+    fn encode(&self, claims: &TokenClaims) -> String;
+    /// Returns the claims if the signature is valid. Expiry is checked by `JwtSessions`.
+    fn decode(&self, token: &str) -> Option<TokenClaims>;
+}
+
+/// Sessions stored in signed tokens rather than in memory, so any instance holding the keys can
+/// validate them. Only revocations are kept, in a `Revocations` store, until the tokens they cover
+/// expire. Sessions can't be listed, as nothing else is.
+///
+/// Tokens can't be changed once issued, so sliding expiration isn't supported. Sessions are
+/// extended through `renew_session` instead, which issues a new token.
+pub struct JwtSessions {
+    codec: Box<dyn TokenCodec + Send + Sync>,
+    policy: SessionPolicy,
+    /// Signed out session ids and users, and the only valid token of refreshed or renewed sessions.
+    revocations: Box<dyn Revocations + Send + Sync>,
+    /// Tells when tokens are issued and expire.
+    clock: Box<dyn Clock + Send + Sync>,
+    /// Draws session and token ids.
+    random: Box<dyn Random + Send + Sync>,
+}
+
+impl JwtSessions {
+    pub fn new(codec: Box<dyn TokenCodec + Send + Sync>, policy: SessionPolicy) -> Self {
+        Self {
+            codec,
+            policy,
+            revocations: Box::new(RevocationsImpl::default()),
+            clock: Box::new(SystemClock),
+            random: Box::new(OsRandom),
+        }
+    }
+
+    /// Tells the time with `clock` instead of the system clock, so tests can expire tokens
+    /// without waiting.
+    #[cfg(test)]
+    pub fn with_clock(mut self, clock: Box<dyn Clock + Send + Sync>) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    /// Draws ids from `random` instead of the OS, so tests get the same ids each run.
+    #[cfg(test)]
+    pub fn with_random(mut self, random: Box<dyn Random + Send + Sync>) -> Self {
+        self.random = random;
+        self
+    }
+
+    /// Replaces the default in-memory revocation list, e.g. with one shared between instances.
+    pub fn with_revocations(mut self, revocations: Box<dyn Revocations + Send + Sync>) -> Self {
+        self.revocations = revocations;
+        self
+    }
+
+    /// Revokes `session_id` along with its refresh token, until the refresh token expires.
+    async fn revoke(&mut self, session_id: SessionId) {
+        self.revocations
+            .revoke(session_id, self.clock.now() + REFRESH_TOKEN_LIFETIME)
+            .await;
+    }
+
+    /// Issues a session token and returns it along with its token id.
+    async fn issue_session(
+        &self,
+        user_uuid: &str,
+        session_id: SessionId,
+        created_at: u64,
+        client_hash: String,
+    ) -> (String, String) {
+        let expires_at = self
+            .policy
+            .expires_at(from_unix(created_at), self.clock.now());
+
+        let claims = TokenClaims {
+            kind: TokenKind::Session,
+            user_uuid: user_uuid.to_string(),
+            session_id,
+            token_id: self.random.uuid().to_string(),
+            generation: self.revocations.generation(user_uuid).await,
+            created_at,
+            issued_at: self.unix_now(),
+            expires_at: unix_timestamp(expires_at),
+            client_hash,
+        };
+
+        (self.codec.encode(&claims), claims.token_id)
+    }
+
+    /// Issues a session token that replaces the other tokens of `claims.session_id`.
+    async fn replace_session(&mut self, claims: TokenClaims, forget_at: u64) -> String {
+        let (session_token, token_id) = self
+            .issue_session(
+                &claims.user_uuid,
+                claims.session_id,
+                claims.created_at,
+                claims.client_hash,
+            )
+            .await;
+
+        self.revocations
+            .replace_tokens(claims.session_id, token_id, from_unix(forget_at))
+            .await;
+
+        session_token
+    }
+
+    fn unix_now(&self) -> u64 {
+        unix_timestamp(self.clock.now())
+    }
+
+    /// Decodes `token` and checks it hasn't expired or been revoked.
+    async fn validate(&self, token: &str, kind: TokenKind) -> Option<TokenClaims> {
+        let claims = self
+            .codec
+            .decode(token)
+            .filter(|claims| claims.kind == kind)
+            .filter(|claims| claims.expires_at > self.unix_now())?;
+
+        if claims.generation != self.revocations.generation(&claims.user_uuid).await
+            || self.revocations.is_revoked(claims.session_id).await
+        {
+            return None;
+        }
+
+        // A refreshed session replaces the previous one.
+        if kind == TokenKind::Session {
+            if let Some(token_id) = self.revocations.replacement(claims.session_id).await {
+                if token_id != claims.token_id {
+                    return None;
+                }
+            }
+        }
+
+        Some(claims)
+    }
+}
+
+#[tonic::async_trait]
+impl Sessions for JwtSessions {
+    async fn create_session(
+        &mut self,
+        user_uuid: &str,
+        client: ClientMetadata,
+    ) -> Result<String, SessionError> {
+        let session_id = SessionId::new(self.random.uuid());
+
+        let (session_token, _) = self
+            .issue_session(user_uuid, session_id, self.unix_now(), client_hash(&client))
+            .await;
+        Ok(session_token)
+    }
+
+    async fn get_session(&self, session_token: &str) -> Option<Session> {
+        let claims = self.validate(session_token, TokenKind::Session).await?;
+
+        // Tokens can't be updated once issued, so `last_seen` is only known for this request.
+        // The client metadata isn't known either, only its hash.
+        Some(Session {
+            session_id: claims.session_id,
+            user_uuid: claims.user_uuid.into(),
+            created_at: from_unix(claims.created_at),
+            last_seen: self.clock.now(),
+            expires_at: from_unix(claims.expires_at),
+            client: ClientMetadata::default(),
+        })
+    }
+
+    async fn peek_session(&self, session_token: &str) -> Option<Session> {
+        let claims = self.validate(session_token, TokenKind::Session).await?;
+
+        Some(Session {
+            session_id: claims.session_id,
+            user_uuid: claims.user_uuid.into(),
+            created_at: from_unix(claims.created_at),
+            last_seen: from_unix(claims.issued_at),
+            expires_at: from_unix(claims.expires_at),
+            client: ClientMetadata::default(),
+        })
+    }
+
+    /// Stateless sessions can't be enumerated.
+    async fn list_user_sessions(&self, _user_uuid: &str) -> Result<Vec<Session>, SessionError> {
+        Err(SessionError::ListingUnsupported)
+    }
+
+    async fn delete_session(&mut self, session_token: &str) -> Option<SessionId> {
+        // Expired tokens are decoded too, their refresh token may still be valid.
+        match self.codec.decode(session_token) {
+            // Also revokes the refresh token, which shares the session id.
+            Some(claims) => {
+                self.revoke(claims.session_id).await;
+                Some(claims.session_id)
+            }
+            None => {
+                tracing::debug!("No session found");
+                None
+            }
+        }
+    }
+
+    async fn delete_user_session(&mut self, _user_uuid: &str, session_id: SessionId) {
+        self.revoke(session_id).await;
+    }
+
+    async fn delete_user_sessions(&mut self, user_uuid: &str) {
+        self.revocations.revoke_user(user_uuid).await;
+    }
+
+    async fn create_refresh_token(
+        &mut self,
+        user_uuid: &str,
+        session_token: &str,
+    ) -> Result<String, SessionError> {
+        let now = self.unix_now();
+
+        // Bind the refresh token to the session, carrying over its client hash.
+        let (session_id, client_hash) = match self.codec.decode(session_token) {
+            Some(claims) => (claims.session_id, claims.client_hash),
+            None => (SessionId::new(self.random.uuid()), String::new()),
+        };
+
+        let claims = TokenClaims {
+            kind: TokenKind::Refresh,
+            user_uuid: user_uuid.to_string(),
+            session_id,
+            token_id: self.random.uuid().to_string(),
+            generation: self.revocations.generation(user_uuid).await,
+            created_at: now,
+            issued_at: now,
+            expires_at: now + REFRESH_TOKEN_LIFETIME.as_secs(),
+            client_hash,
+        };
+
+        Ok(self.codec.encode(&claims))
+    }
+
+    async fn refresh_session(&mut self, refresh_token: &str) -> Option<String> {
+        let mut claims = self.validate(refresh_token, TokenKind::Refresh).await?;
+        let forget_at = claims.expires_at;
+
+        // Refreshing starts a new session, unlike renewing.
+        claims.created_at = self.unix_now();
+
+        Some(self.replace_session(claims, forget_at).await)
+    }
+
+    async fn renew_session(&mut self, session_token: &str) -> Option<String> {
+        let claims = self.validate(session_token, TokenKind::Session).await?;
+
+        // The session id may still have a refresh token that outlives the renewed session.
+        let forget_at = self.unix_now() + REFRESH_TOKEN_LIFETIME.as_secs();
+
+        Some(self.replace_session(claims, forget_at).await)
+    }
+
+    async fn remove_expired(&mut self) -> usize {
+        self.revocations.remove_expired().await;
+
+        // No sessions are stored.
+        0
+    }
+}
+
+/// Hex encoded SHA-256 of the client's IP address and user agent, so a token can be checked
+/// against the client presenting it without revealing either.
+fn client_hash(client: &ClientMetadata) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(client.ip_address.as_bytes());
+    hasher.update([0]);
+    hasher.update(client.user_agent.as_bytes());
+    format!("{:x}", hasher.finalize())
+}
+
+fn unix_timestamp(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or_default()
+}
+
+fn from_unix(secs: u64) -> SystemTime {
+    UNIX_EPOCH + Duration::from_secs(secs)
+}
+
+#[cfg(feature = "jwt-sessions")]
+mod jwt {
+    use jsonwebtoken::{decode, encode, Algorithm, DecodingKey, EncodingKey, Header, Validation};
+    use serde_json::{json, Value};
+
+    use super::{TokenClaims, TokenCodec, TokenKind};
+    use crate::config::JwtKeys;
+
+    /// Signs tokens as JWTs with HS256 or RS256.
+    pub struct JwtCodec {
+        header: Header,
+        encoding_key: EncodingKey,
+        decoding_key: DecodingKey,
+        validation: Validation,
+    }
+
+    impl JwtCodec {
+        pub fn from_keys(keys: &JwtKeys) -> Result<Self, String> {
+            let (algorithm, encoding_key, decoding_key) = match keys {
+                JwtKeys::Hs256 { secret } => (
+                    Algorithm::HS256,
+                    EncodingKey::from_secret(secret.expose().as_bytes()),
+                    DecodingKey::from_secret(secret.expose().as_bytes()),
+                ),
+                JwtKeys::Rs256 {
+                    private_key_path,
+                    public_key_path,
+                } => {
+                    let read = |path: &str| {
+                        std::fs::read(path).map_err(|e| format!("Failed to read {path}.\n{e:?}"))
+                    };
+
+                    (
+                        Algorithm::RS256,
+                        EncodingKey::from_rsa_pem(&read(private_key_path)?)
+                            .map_err(|e| format!("Invalid private key.\n{e:?}"))?,
+                        DecodingKey::from_rsa_pem(&read(public_key_path)?)
+                            .map_err(|e| format!("Invalid public key.\n{e:?}"))?,
+                    )
+                }
+            };
+
+            // Expiry is checked by `JwtSessions`, signing out needs to decode expired tokens.
+            let mut validation = Validation::new(algorithm);
+            validation.validate_exp = false;
+
+            Ok(Self {
+                header: Header::new(algorithm),
+                encoding_key,
+                decoding_key,
+                validation,
+            })
+        }
+    }
+
+    impl TokenCodec for JwtCodec {
+        fn encode(&self, claims: &TokenClaims) -> String {
+            let kind = match claims.kind {
+                TokenKind::Session => "session",
+                TokenKind::Refresh => "refresh",
+            };
+
+            let claims = json!({
+                "typ": kind,
+                "sub": claims.user_uuid,
+                "sid": claims.session_id.to_string(),
+                "jti": claims.token_id,
+                "gen": claims.generation,
+                "cat": claims.created_at,
+                "iat": claims.issued_at,
+                "exp": claims.expires_at,
+                "cli": claims.client_hash,
+            });
+
+            // Signing only fails on invalid keys, which `from_keys` rejects.
+            encode(&self.header, &claims, &self.encoding_key).unwrap_or_default()
+        }
+
+        fn decode(&self, token: &str) -> Option<TokenClaims> {
+            let claims = decode::<Value>(token, &self.decoding_key, &self.validation)
+                .ok()?
+                .claims;
+
+            let string = |claim: &str| claims[claim].as_str().map(str::to_string);
+
+            Some(TokenClaims {
+                kind: match claims["typ"].as_str()? {
+                    "session" => TokenKind::Session,
+                    "refresh" => TokenKind::Refresh,
+                    _ => return None,
+                },
+                user_uuid: string("sub")?,
+                session_id: claims["sid"].as_str()?.parse().ok()?,
+                token_id: string("jti")?,
+                generation: u32::try_from(claims["gen"].as_u64()?).ok()?,
+                created_at: claims["cat"].as_u64()?,
+                issued_at: claims["iat"].as_u64()?,
+                expires_at: claims["exp"].as_u64()?,
+                client_hash: string("cli").unwrap_or_default(),
+            })
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::sync::Mutex;
+
+    use uuid::Uuid;
+
+    use super::*;
+    use crate::clock::ManualClock;
+    use crate::random::SeededRandom;
+
+    /// Keeps claims in memory instead of signing them.
+    #[derive(Default)]
+    struct TestCodec {
+        token_to_claims: Mutex<HashMap<String, TokenClaims>>,
+    }
+
+    impl TokenCodec for TestCodec {
+        fn encode(&self, claims: &TokenClaims) -> String {
+            let token = Uuid::new_v4().to_string();
+            self.token_to_claims
+                .lock()
+                .unwrap()
+                .insert(token.clone(), claims.clone());
+            token
+        }
+
+        fn decode(&self, token: &str) -> Option<TokenClaims> {
+            self.token_to_claims.lock().unwrap().get(token).cloned()
+        }
+    }
+
+    fn jwt_sessions() -> JwtSessions {
+        JwtSessions::new(
+            Box::new(TestCodec::default()),
+            SessionPolicy {
+                lifetime: Duration::from_secs(60),
+                ..Default::default()
+            },
+        )
+        .with_random(Box::new(SeededRandom::new(7)))
+    }
+
+    #[tokio::test]
+    async fn should_get_session() {
+        let mut sessions_service = jwt_sessions();
+        let client = ClientMetadata {
+            ip_address: "127.0.0.1".to_owned(),
+            user_agent: "test".to_owned(),
+        };
+        let session = sessions_service
+            .create_session("123456", client.clone())
+            .await
+            .unwrap();
+
+        let result = sessions_service.get_session(&session).await.unwrap();
+
+        assert_eq!(result.user_uuid, "123456");
+        assert!(result.client.user_agent.is_empty());
+        // Only a hash of the client metadata is put in the token.
+        let claims = sessions_service.codec.decode(&session).unwrap();
+        assert_eq!(claims.client_hash, client_hash(&client));
+        assert_ne!(claims.client_hash, client_hash(&ClientMetadata::default()));
+        assert!(result.expires_at > SystemTime::now());
+        assert!(sessions_service.get_session("unknown").await.is_none());
+    }
+
+    #[tokio::test]
+    async fn should_expire_sessions() {
+        let clock = ManualClock::new(SystemTime::now());
+        let mut sessions_service = jwt_sessions().with_clock(Box::new(clock.clone()));
+        let session = sessions_service
+            .create_session("123456", ClientMetadata::default())
+            .await
+            .unwrap();
+
+        clock.advance(Duration::from_secs(59));
+        assert!(sessions_service.get_session(&session).await.is_some());
+
+        clock.advance(Duration::from_secs(1));
+        assert!(sessions_service.get_session(&session).await.is_none());
+    }
+
+    #[tokio::test]
+    async fn should_not_accept_refresh_token_as_session() {
+        let mut sessions_service = jwt_sessions();
+        let session = sessions_service
+            .create_session("123456", ClientMetadata::default())
+            .await
+            .unwrap();
+        let refresh_token = sessions_service
+            .create_refresh_token("123456", &session)
+            .await
+            .unwrap();
+
+        assert!(sessions_service.get_session(&refresh_token).await.is_none());
+        assert!(sessions_service.refresh_session(&session).await.is_none());
+    }
+
+    #[tokio::test]
+    async fn should_revoke_session_and_refresh_token_on_delete() {
+        let mut sessions_service = jwt_sessions();
+        let session = sessions_service
+            .create_session("123456", ClientMetadata::default())
+            .await
+            .unwrap();
+        let refresh_token = sessions_service
+            .create_refresh_token("123456", &session)
+            .await
+            .unwrap();
+        let other_session = sessions_service
+            .create_session("123456", ClientMetadata::default())
+            .await
+            .unwrap();
+
+        sessions_service.delete_session(&session).await;
+
+        assert!(sessions_service.get_session(&session).await.is_none());
+        assert!(sessions_service
+            .refresh_session(&refresh_token)
+            .await
+            .is_none());
+        assert!(sessions_service.get_session(&other_session).await.is_some());
+    }
+
+    #[tokio::test]
+    async fn should_revoke_all_user_sessions() {
+        let mut sessions_service = jwt_sessions();
+        let session = sessions_service
+            .create_session("123456", ClientMetadata::default())
+            .await
+            .unwrap();
+        let other_session = sessions_service
+            .create_session("654321", ClientMetadata::default())
+            .await
+            .unwrap();
+
+        sessions_service.delete_user_sessions("123456").await;
+
+        assert!(sessions_service.get_session(&session).await.is_none());
+        assert!(sessions_service.get_session(&other_session).await.is_some());
+
+        // Sessions created afterwards are valid again.
+        let session = sessions_service
+            .create_session("123456", ClientMetadata::default())
+            .await
+            .unwrap();
+        assert!(sessions_service.get_session(&session).await.is_some());
+    }
+
+    #[tokio::test]
+    async fn should_replace_session_on_refresh() {
+        let mut sessions_service = jwt_sessions();
+        let session = sessions_service
+            .create_session("123456", ClientMetadata::default())
+            .await
+            .unwrap();
+        let refresh_token = sessions_service
+            .create_refresh_token("123456", &session)
+            .await
+            .unwrap();
+
+        let new_session = sessions_service
+            .refresh_session(&refresh_token)
+            .await
+            .unwrap();
+
+        assert!(sessions_service.get_session(&session).await.is_none());
+        assert!(sessions_service.get_session(&new_session).await.is_some());
+
+        // Signing out of the new session revokes the refresh token too.
+        sessions_service.delete_session(&new_session).await;
+        assert!(sessions_service
+            .refresh_session(&refresh_token)
+            .await
+            .is_none());
+    }
+
+    #[tokio::test]
+    async fn should_replace_session_on_renew() {
+        let mut sessions_service = jwt_sessions();
+        let session = sessions_service
+            .create_session("123456", ClientMetadata::default())
+            .await
+            .unwrap();
+        let created_at = sessions_service
+            .get_session(&session)
+            .await
+            .unwrap()
+            .created_at;
+
+        let renewed = sessions_service.renew_session(&session).await.unwrap();
+
+        assert!(sessions_service.get_session(&session).await.is_none());
+        assert_eq!(
+            sessions_service
+                .get_session(&renewed)
+                .await
+                .unwrap()
+                .created_at,
+            created_at
+        );
+    }
+
+    #[tokio::test]
+    async fn should_add_signed_out_sessions_to_revocation_list() {
+        let mut sessions_service = jwt_sessions();
+        let session = sessions_service
+            .create_session("123456", ClientMetadata::default())
+            .await
+            .unwrap();
+        let session_id = sessions_service
+            .get_session(&session)
+            .await
+            .unwrap()
+            .session_id;
+
+        sessions_service.delete_session(&session).await;
+        sessions_service.remove_expired().await;
+
+        assert!(sessions_service.revocations.is_revoked(session_id).await);
+    }
+
+    #[tokio::test]
+    async fn should_not_list_sessions() {
+        let mut sessions_service = jwt_sessions();
+        sessions_service
+            .create_session("123456", ClientMetadata::default())
+            .await
+            .unwrap();
+
+        assert!(matches!(
+            sessions_service.list_user_sessions("123456").await,
+            Err(SessionError::ListingUnsupported)
+        ));
+    }
+}
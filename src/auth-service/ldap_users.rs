@@ -0,0 +1,634 @@
+// Only `LdapDirectory` (behind the `ldap` feature) puts `LdapUsers` to use outside of tests.
+#![cfg_attr(not(feature = "ldap"), allow(dead_code))]
+
+use std::sync::Arc;
+use std::time::SystemTime;
+
+use tokio::sync::RwLock;
+
+use crate::error::StorageError;
+use crate::password_hashing::HashingPool;
+use crate::users::{ImportedUser, User, UserCursor, UserFilter, Users, VerifiedUser};
+
+#[cfg(feature = "ldap")]
+pub use ldap::LdapDirectory;
+
+/// The attributes of a directory entry copied to its local user.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct DirectoryEntry {
+    /// The entry's login name, e.g. its `uid` or `sAMAccountName`.
+    pub username: String,
+    pub display_name: Option<String>,
+    pub email: Option<String>,
+}
+
+/// The outcome of checking a password against a directory.
+#[derive(Clone, Debug, PartialEq)]
+pub enum DirectoryLogin {
+    Authenticated(DirectoryEntry),
+    InvalidPassword,
+    /// The directory has no entry for the username, so it may be a local user.
+    NotFound,
+}
+
+/// Checks passwords against an external directory, e.g. LDAP or Active Directory.
+#[tonic::async_trait]
+pub trait Directory {
+    /// Fails if the directory couldn't be queried, e.g. because it's unreachable.
+    async fn authenticate(
+        &self,
+        username: &str,
+        password: &str,
+    ) -> Result<DirectoryLogin, StorageError>;
+}
+
+/// Users whose passwords are checked by a directory. Each directory entry gets a local user on
+/// first sign in, linked like an external identity and kept in `local` along with every other
+/// record. Its profile is refreshed from the directory on each sign in.
+///
+/// Usernames missing from the directory, or every username while it's unreachable, fall back to
+/// the local users so local accounts (e.g. administrators) keep working. Directory users can't
+/// sign in with a local password, and their passwords can only be changed in the directory.
+pub struct LdapUsers {
+    directory: Box<dyn Directory + Send + Sync>,
+    /// Identifies the directory as the issuer of the linked identities, e.g. its URL.
+    issuer: String,
+    local: Box<RwLock<dyn Users + Send + Sync>>,
+}
+
+impl LdapUsers {
+    pub fn new(
+        directory: Box<dyn Directory + Send + Sync>,
+        issuer: String,
+        local: Box<RwLock<dyn Users + Send + Sync>>,
+    ) -> Self {
+        Self {
+            directory,
+            issuer,
+            local,
+        }
+    }
+
+    /// Checks the password with the directory. `None` means the local users should decide.
+    async fn directory_login(&self, username: &str, password: &str) -> Option<DirectoryLogin> {
+        // Many servers treat a bind with an empty password as an anonymous bind and accept it.
+        if password.is_empty() {
+            return Some(DirectoryLogin::InvalidPassword);
+        }
+
+        match self.directory.authenticate(username, password).await {
+            Ok(DirectoryLogin::NotFound) => None,
+            Ok(login) => Some(login),
+            Err(e) => {
+                tracing::warn!(error = %e, "Failed to query the directory, falling back to local users");
+                None
+            }
+        }
+    }
+
+    /// Whether the user was provisioned from the directory.
+    async fn is_directory_user(&self, user_uuid: &str) -> bool {
+        let Some(user) = self.local.read().await.get_user(user_uuid.to_owned()).await else {
+            return false;
+        };
+
+        self.local
+            .read()
+            .await
+            .get_federated_user_uuid(&self.issuer, &user.username)
+            .await
+            .is_some_and(|linked_uuid| linked_uuid == user_uuid)
+    }
+
+    /// Finds or creates the local user for `entry`, copying its profile.
+    async fn provision(&self, entry: DirectoryEntry) -> Option<String> {
+        let mut local = self.local.write().await;
+
+        let linked_uuid = local
+            .get_federated_user_uuid(&self.issuer, &entry.username)
+            .await;
+        let user_uuid = match linked_uuid {
+            Some(user_uuid) => user_uuid,
+            None => local
+                .create_federated_user(&self.issuer, &entry.username, entry.username.clone())
+                .await
+                .map_err(|e| tracing::error!(error = %e, "Failed to provision directory user"))
+                .ok()?,
+        };
+
+        // A stale profile shouldn't block signing in, e.g. if the email is taken locally.
+        if let Err(e) = local
+            .update_user(user_uuid.clone(), entry.display_name, entry.email)
+            .await
+        {
+            tracing::warn!(error = %e, "Failed to update directory user profile");
+        }
+
+        Some(user_uuid)
+    }
+
+    /// Checks the password of a user missing from the directory.
+    async fn local_login(&self, username: String, password: String) -> Option<VerifiedUser> {
+        let verified = self
+            .local
+            .read()
+            .await
+            .verify_password(username, password)
+            .await?;
+
+        match self.is_directory_user(&verified.user_uuid).await {
+            true => None,
+            false => Some(verified),
+        }
+    }
+}
+
+#[tonic::async_trait]
+impl Users for LdapUsers {
+    async fn create_user_hashed(
+        &mut self,
+        username: String,
+        hashed_password: String,
+        email: String,
+    ) -> Result<(), StorageError> {
+        self.local
+            .write()
+            .await
+            .create_user_hashed(username, hashed_password, email)
+            .await
+    }
+
+    async fn hashing(&self) -> HashingPool {
+        self.local.read().await.hashing().await
+    }
+
+    async fn create_users(
+        &mut self,
+        users: Vec<(String, String)>,
+    ) -> Vec<Result<(), StorageError>> {
+        self.local.write().await.create_users(users).await
+    }
+
+    async fn get_user_uuid(&self, username: String, password: String) -> Option<String> {
+        match self.directory_login(&username, &password).await {
+            Some(DirectoryLogin::Authenticated(entry)) => {
+                self.local
+                    .read()
+                    .await
+                    .get_federated_user_uuid(&self.issuer, &entry.username)
+                    .await
+            }
+            Some(_) => None,
+            None => self
+                .local_login(username, password)
+                .await
+                .map(|verified| verified.user_uuid),
+        }
+    }
+
+    async fn verify_password(&self, username: String, password: String) -> Option<VerifiedUser> {
+        match self.directory_login(&username, &password).await {
+            // Directory passwords aren't hashed locally, so there's nothing to upgrade.
+            Some(DirectoryLogin::Authenticated(entry)) => {
+                self.provision(entry).await.map(|user_uuid| VerifiedUser {
+                    user_uuid,
+                    needs_rehash: false,
+                })
+            }
+            Some(_) => None,
+            None => self.local_login(username, password).await,
+        }
+    }
+
+    async fn lookup_user_uuid(&self, username: String) -> Option<String> {
+        self.local.read().await.lookup_user_uuid(username).await
+    }
+
+    async fn update_password_hashed(
+        &mut self,
+        user_uuid: String,
+        hashed_password: String,
+    ) -> Result<(), StorageError> {
+        if self.is_directory_user(&user_uuid).await {
+            return Err(StorageError::PasswordManagedByDirectory);
+        }
+
+        self.local
+            .write()
+            .await
+            .update_password_hashed(user_uuid, hashed_password)
+            .await
+    }
+
+    async fn get_user(&self, user_uuid: String) -> Option<Arc<User>> {
+        self.local.read().await.get_user(user_uuid).await
+    }
+
+    async fn update_user(
+        &mut self,
+        user_uuid: String,
+        display_name: Option<String>,
+        email: Option<String>,
+    ) -> Result<(), StorageError> {
+        self.local
+            .write()
+            .await
+            .update_user(user_uuid, display_name, email)
+            .await
+    }
+
+    async fn set_user_attribute(
+        &mut self,
+        user_uuid: String,
+        key: String,
+        value: String,
+    ) -> Result<(), StorageError> {
+        self.local
+            .write()
+            .await
+            .set_user_attribute(user_uuid, key, value)
+            .await
+    }
+
+    async fn delete_user(&mut self, user_uuid: String) {
+        self.local.write().await.delete_user(user_uuid).await
+    }
+
+    async fn soft_delete_user(&mut self, user_uuid: String) -> Result<(), StorageError> {
+        self.local.write().await.soft_delete_user(user_uuid).await
+    }
+
+    async fn restore_user(&mut self, user_uuid: String) -> Result<(), StorageError> {
+        self.local.write().await.restore_user(user_uuid).await
+    }
+
+    async fn set_user_disabled(
+        &mut self,
+        user_uuid: String,
+        disabled: bool,
+    ) -> Result<(), StorageError> {
+        self.local
+            .write()
+            .await
+            .set_user_disabled(user_uuid, disabled)
+            .await
+    }
+
+    async fn purge_deleted_users(&mut self, deleted_before: SystemTime) -> Vec<String> {
+        self.local
+            .write()
+            .await
+            .purge_deleted_users(deleted_before)
+            .await
+    }
+
+    async fn import_users(&mut self, users: Vec<ImportedUser>) -> Vec<Result<(), StorageError>> {
+        self.local.write().await.import_users(users).await
+    }
+
+    async fn search_users(
+        &self,
+        filter: &UserFilter,
+        after: Option<&UserCursor>,
+        limit: usize,
+    ) -> Vec<User> {
+        self.local
+            .read()
+            .await
+            .search_users(filter, after, limit)
+            .await
+    }
+
+    /// Directory users are only counted once they've signed in.
+    async fn count_users(&self) -> Result<usize, StorageError> {
+        self.local.read().await.count_users().await
+    }
+
+    async fn get_federated_user_uuid(&self, issuer: &str, subject: &str) -> Option<String> {
+        self.local
+            .read()
+            .await
+            .get_federated_user_uuid(issuer, subject)
+            .await
+    }
+
+    async fn create_federated_user(
+        &mut self,
+        issuer: &str,
+        subject: &str,
+        username: String,
+    ) -> Result<String, StorageError> {
+        self.local
+            .write()
+            .await
+            .create_federated_user(issuer, subject, username)
+            .await
+    }
+
+    async fn flush(&mut self) {
+        self.local.write().await.flush().await;
+    }
+
+    async fn ping(&self) -> Result<(), StorageError> {
+        // Sign ins fall back to local users while the directory is unreachable, so only the local
+        // backend decides whether the service is healthy.
+        self.local.read().await.ping().await
+    }
+}
+
+#[cfg(feature = "ldap")]
+mod ldap {
+    use ldap3::{ldap_escape, Ldap, LdapConnAsync, LdapConnSettings, Scope, SearchEntry};
+
+    use super::{Directory, DirectoryEntry, DirectoryLogin};
+    use crate::config::LdapConfig;
+    use crate::error::StorageError;
+
+    /// LDAP result code for a bind with the wrong password.
+    const INVALID_CREDENTIALS: u32 = 49;
+
+    /// Authenticates users against an LDAP server or Active Directory.
+    ///
+    /// Each sign in finds the user's entry with a search, bound as the service account if one is
+    /// configured, then binds as that entry with the given password.
+    pub struct LdapDirectory {
+        config: LdapConfig,
+    }
+
+    impl LdapDirectory {
+        pub fn new(config: LdapConfig) -> Self {
+            Self { config }
+        }
+
+        async fn connect(&self) -> Result<Ldap, StorageError> {
+            let settings = LdapConnSettings::new().set_conn_timeout(self.config.timeout);
+            let (conn, ldap) = LdapConnAsync::with_settings(settings, &self.config.url)
+                .await
+                .map_err(|e| {
+                    StorageError::backend(format!("Failed to connect to the directory.\n{e:?}"))
+                })?;
+            ldap3::drive!(conn);
+
+            Ok(ldap)
+        }
+
+        /// Finds the DN and profile of the entry for `username`, if there is exactly one.
+        async fn find_entry(
+            &self,
+            ldap: &mut Ldap,
+            username: &str,
+        ) -> Result<Option<(String, DirectoryEntry)>, StorageError> {
+            if let Some(bind_dn) = &self.config.bind_dn {
+                ldap.simple_bind(bind_dn, self.config.bind_password.expose())
+                    .await
+                    .and_then(|result| result.success())
+                    .map_err(|e| {
+                        StorageError::backend(format!(
+                            "Failed to bind as the service account.\n{e:?}"
+                        ))
+                    })?;
+            }
+
+            let filter = self
+                .config
+                .user_filter
+                .replace("{username}", &ldap_escape(username));
+            let attributes = [
+                &self.config.username_attribute,
+                &self.config.display_name_attribute,
+                &self.config.email_attribute,
+            ];
+
+            let (entries, _) = ldap
+                .search(&self.config.base_dn, Scope::Subtree, &filter, attributes)
+                .await
+                .and_then(|result| result.success())
+                .map_err(|e| {
+                    StorageError::backend(format!("Failed to search the directory.\n{e:?}"))
+                })?;
+
+            // An ambiguous filter shouldn't let one entry sign in as another.
+            let mut entries = entries.into_iter();
+            let entry = match (entries.next(), entries.next()) {
+                (Some(entry), None) => SearchEntry::construct(entry),
+                (None, _) => return Ok(None),
+                (Some(_), Some(_)) => {
+                    return Err(StorageError::backend(format!(
+                        "Error, several directory entries match {filter}"
+                    )))
+                }
+            };
+
+            let attribute = |name: &str| {
+                entry
+                    .attrs
+                    .get(name)
+                    .and_then(|values| values.first())
+                    .filter(|value| !value.is_empty())
+                    .cloned()
+            };
+
+            let directory_entry = DirectoryEntry {
+                username: attribute(&self.config.username_attribute)
+                    .unwrap_or_else(|| username.to_owned()),
+                display_name: attribute(&self.config.display_name_attribute),
+                email: attribute(&self.config.email_attribute),
+            };
+
+            Ok(Some((entry.dn, directory_entry)))
+        }
+    }
+
+    #[tonic::async_trait]
+    impl Directory for LdapDirectory {
+        async fn authenticate(
+            &self,
+            username: &str,
+            password: &str,
+        ) -> Result<DirectoryLogin, StorageError> {
+            let mut ldap = self.connect().await?;
+
+            let login = match self.find_entry(&mut ldap, username).await? {
+                None => DirectoryLogin::NotFound,
+                Some((dn, entry)) => {
+                    let result = ldap.simple_bind(&dn, password).await.map_err(|e| {
+                        StorageError::backend(format!("Failed to bind as the user.\n{e:?}"))
+                    })?;
+
+                    match result.rc {
+                        0 => DirectoryLogin::Authenticated(entry),
+                        INVALID_CREDENTIALS => DirectoryLogin::InvalidPassword,
+                        rc => {
+                            return Err(StorageError::backend(format!(
+                                "Error, user bind failed with code {rc}"
+                            )))
+                        }
+                    }
+                }
+            };
+
+            let _ = ldap.unbind().await;
+
+            Ok(login)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::*;
+    use crate::users::UsersImpl;
+
+    const ISSUER: &str = "ldap://directory.example.com";
+
+    /// A directory with fixed entries and passwords.
+    struct TestDirectory {
+        entries: HashMap<String, (String, DirectoryEntry)>,
+        reachable: bool,
+    }
+
+    #[tonic::async_trait]
+    impl Directory for TestDirectory {
+        async fn authenticate(
+            &self,
+            username: &str,
+            password: &str,
+        ) -> Result<DirectoryLogin, StorageError> {
+            if !self.reachable {
+                return Err(StorageError::Backend(
+                    "Error, directory unreachable".to_string(),
+                ));
+            }
+
+            Ok(match self.entries.get(username) {
+                Some((entry_password, entry)) if entry_password == password => {
+                    DirectoryLogin::Authenticated(entry.clone())
+                }
+                Some(_) => DirectoryLogin::InvalidPassword,
+                None => DirectoryLogin::NotFound,
+            })
+        }
+    }
+
+    fn ldap_users(reachable: bool) -> LdapUsers {
+        let entry = DirectoryEntry {
+            username: "alice".to_owned(),
+            display_name: Some("Alice Liddell".to_owned()),
+            email: Some("alice@example.com".to_owned()),
+        };
+        let directory = TestDirectory {
+            entries: HashMap::from([("alice".to_owned(), ("secret".to_owned(), entry))]),
+            reachable,
+        };
+
+        LdapUsers::new(
+            Box::new(directory),
+            ISSUER.to_owned(),
+            Box::new(RwLock::new(UsersImpl::default())),
+        )
+    }
+
+    #[tokio::test]
+    async fn should_provision_directory_user_on_first_sign_in() {
+        let users_service = ldap_users(true);
+
+        let user_uuid = users_service
+            .verify_password("alice".to_owned(), "secret".to_owned())
+            .await
+            .unwrap()
+            .user_uuid;
+
+        let user = users_service.get_user(user_uuid.clone()).await.unwrap();
+        assert_eq!(user.username, "alice");
+        assert_eq!(user.display_name, "Alice Liddell");
+        assert_eq!(user.email, "alice@example.com");
+
+        // Signing in again finds the same user.
+        assert_eq!(
+            users_service
+                .verify_password("alice".to_owned(), "secret".to_owned())
+                .await
+                .map(|verified| verified.user_uuid),
+            Some(user_uuid.clone())
+        );
+        assert_eq!(
+            users_service
+                .get_user_uuid("alice".to_owned(), "secret".to_owned())
+                .await,
+            Some(user_uuid)
+        );
+        assert!(users_service
+            .verify_password("alice".to_owned(), "wrong".to_owned())
+            .await
+            .is_none());
+        assert!(users_service
+            .verify_password("alice".to_owned(), String::new())
+            .await
+            .is_none());
+    }
+
+    #[tokio::test]
+    async fn should_fall_back_to_local_users() {
+        let mut users_service = ldap_users(true);
+        users_service
+            .create_user("admin".to_owned(), "password".to_owned(), String::new())
+            .await
+            .unwrap();
+
+        assert!(users_service
+            .verify_password("admin".to_owned(), "password".to_owned())
+            .await
+            .is_some());
+        assert!(users_service
+            .verify_password("admin".to_owned(), "wrong".to_owned())
+            .await
+            .is_none());
+    }
+
+    #[tokio::test]
+    async fn should_keep_directory_users_out_of_local_passwords() {
+        let mut users_service = ldap_users(true);
+        let user_uuid = users_service
+            .verify_password("alice".to_owned(), "secret".to_owned())
+            .await
+            .unwrap()
+            .user_uuid;
+
+        assert_eq!(
+            users_service
+                .update_password(user_uuid.clone(), "local password".to_owned())
+                .await,
+            Err(StorageError::PasswordManagedByDirectory)
+        );
+
+        // Even with a local password, directory users only sign in through the directory.
+        users_service
+            .local
+            .write()
+            .await
+            .update_password(user_uuid, "secret".to_owned())
+            .await
+            .unwrap();
+
+        // While the directory is down only local users can sign in.
+        let mut users_service = LdapUsers {
+            directory: ldap_users(false).directory,
+            ..users_service
+        };
+        users_service
+            .create_user("admin".to_owned(), "password".to_owned(), String::new())
+            .await
+            .unwrap();
+
+        assert!(users_service
+            .verify_password("alice".to_owned(), "secret".to_owned())
+            .await
+            .is_none());
+        assert!(users_service
+            .verify_password("admin".to_owned(), "password".to_owned())
+            .await
+            .is_some());
+    }
+}
@@ -0,0 +1,160 @@
+use std::collections::HashMap;
+use std::time::{Duration, SystemTime};
+
+use crate::clock::{Clock, SystemClock};
+
+/// Consecutive failed sign-ins after which an account is locked.
+pub const DEFAULT_LOCKOUT_THRESHOLD: u32 = 5;
+/// How long an account stays locked.
+pub const DEFAULT_LOCKOUT_DURATION: Duration = Duration::from_secs(60 * 15);
+
+pub trait Lockouts {
+    /// Returns how much longer `username` is locked out for, if at all.
+    fn locked_for(&mut self, username: &str) -> Option<Duration>;
+    /// Counts a failed sign-in. Returns the lockout duration if this failure locked the account.
+    fn record_failure(&mut self, username: &str) -> Option<Duration>;
+    /// Resets the failure count after a successful sign-in.
+    fn record_success(&mut self, username: &str);
+    /// Lifts the lockout and forgets the failures, e.g. at an administrator's request.
+    fn unlock(&mut self, username: &str);
+}
+
+#[derive(Clone, Debug, Default)]
+struct FailedSignIns {
+    count: u32,
+    locked_until: Option<SystemTime>,
+}
+
+pub struct LockoutsImpl {
+    threshold: u32,
+    duration: Duration,
+    username_to_failures: HashMap<String, FailedSignIns>,
+    /// Tells when accounts are locked until.
+    clock: Box<dyn Clock + Send + Sync>,
+}
+
+impl LockoutsImpl {
+    pub fn new(threshold: u32, duration: Duration) -> Self {
+        Self {
+            threshold,
+            duration,
+            username_to_failures: HashMap::new(),
+            clock: Box::new(SystemClock),
+        }
+    }
+
+    /// Tells the time with `clock` instead of the system clock, so tests can outlast lockouts
+    /// without waiting.
+    #[cfg(test)]
+    pub fn with_clock(mut self, clock: Box<dyn Clock + Send + Sync>) -> Self {
+        self.clock = clock;
+        self
+    }
+}
+
+impl Default for LockoutsImpl {
+    fn default() -> Self {
+        Self::new(DEFAULT_LOCKOUT_THRESHOLD, DEFAULT_LOCKOUT_DURATION)
+    }
+}
+
+impl Lockouts for LockoutsImpl {
+    fn locked_for(&mut self, username: &str) -> Option<Duration> {
+        let locked_until = self.username_to_failures.get(username)?.locked_until?;
+
+        match locked_until.duration_since(self.clock.now()) {
+            Ok(remaining) if !remaining.is_zero() => Some(remaining),
+            // The lock has expired, start counting failures from scratch.
+            _ => {
+                self.username_to_failures.remove(username);
+                None
+            }
+        }
+    }
+
+    fn record_failure(&mut self, username: &str) -> Option<Duration> {
+        let failures = self
+            .username_to_failures
+            .entry(username.to_string())
+            .or_default();
+
+        failures.count += 1;
+
+        if failures.count < self.threshold {
+            return None;
+        }
+
+        failures.locked_until = Some(self.clock.now() + self.duration);
+
+        Some(self.duration)
+    }
+
+    fn record_success(&mut self, username: &str) {
+        self.unlock(username);
+    }
+
+    fn unlock(&mut self, username: &str) {
+        self.username_to_failures.remove(username);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::ManualClock;
+
+    #[test]
+    fn should_lock_after_threshold() {
+        let mut lockouts_service = LockoutsImpl::new(3, Duration::from_secs(60));
+
+        assert!(lockouts_service.record_failure("username").is_none());
+        assert!(lockouts_service.record_failure("username").is_none());
+        assert!(lockouts_service.locked_for("username").is_none());
+
+        assert_eq!(
+            lockouts_service.record_failure("username"),
+            Some(Duration::from_secs(60))
+        );
+        assert!(lockouts_service.locked_for("username").is_some());
+        assert!(lockouts_service.locked_for("other").is_none());
+    }
+
+    #[test]
+    fn should_reset_failures_on_success() {
+        let mut lockouts_service = LockoutsImpl::new(2, Duration::from_secs(60));
+
+        lockouts_service.record_failure("username");
+        lockouts_service.record_success("username");
+
+        assert!(lockouts_service.record_failure("username").is_none());
+    }
+
+    #[test]
+    fn should_unlock_before_expiry() {
+        let mut lockouts_service = LockoutsImpl::new(1, Duration::from_secs(60));
+
+        lockouts_service.record_failure("username");
+        assert!(lockouts_service.locked_for("username").is_some());
+
+        lockouts_service.unlock("username");
+        assert!(lockouts_service.locked_for("username").is_none());
+    }
+
+    #[test]
+    fn should_unlock_once_expired() {
+        let clock = ManualClock::new(SystemTime::now());
+        let mut lockouts_service =
+            LockoutsImpl::new(1, Duration::from_secs(60)).with_clock(Box::new(clock.clone()));
+
+        lockouts_service.record_failure("username");
+        clock.advance(Duration::from_secs(30));
+        assert_eq!(
+            lockouts_service.locked_for("username"),
+            Some(Duration::from_secs(30))
+        );
+
+        clock.advance(Duration::from_secs(30));
+        assert!(lockouts_service.locked_for("username").is_none());
+        assert!(lockouts_service.username_to_failures.is_empty());
+    }
+}
@@ -0,0 +1,126 @@
+use std::collections::{HashMap, VecDeque};
+use std::time::SystemTime;
+
+use crate::sessions::ClientMetadata;
+
+/// Attempts kept per user, older ones are dropped first.
+pub const MAX_LOGIN_HISTORY: usize = 1000;
+
+pub trait LoginHistory {
+    fn record_attempt(&mut self, user_uuid: &str, attempt: LoginAttempt);
+    /// Lists the user's attempts newest first, skipping the `offset` most recent ones.
+    fn list_attempts(&self, user_uuid: &str, offset: usize, limit: usize) -> Vec<LoginAttempt>;
+    fn delete_user_history(&mut self, user_uuid: &str);
+}
+
+#[derive(Clone, Debug)]
+pub struct LoginAttempt {
+    pub timestamp: SystemTime,
+    pub success: bool,
+    pub client: ClientMetadata,
+}
+
+#[derive(Default)]
+pub struct LoginHistoryImpl {
+    /// Oldest attempt first.
+    uuid_to_attempts: HashMap<String, VecDeque<LoginAttempt>>,
+}
+
+impl LoginHistory for LoginHistoryImpl {
+    fn record_attempt(&mut self, user_uuid: &str, attempt: LoginAttempt) {
+        let attempts = self
+            .uuid_to_attempts
+            .entry(user_uuid.to_string())
+            .or_default();
+
+        if attempts.len() == MAX_LOGIN_HISTORY {
+            attempts.pop_front();
+        }
+
+        attempts.push_back(attempt);
+    }
+
+    fn list_attempts(&self, user_uuid: &str, offset: usize, limit: usize) -> Vec<LoginAttempt> {
+        match self.uuid_to_attempts.get(user_uuid) {
+            Some(attempts) => attempts
+                .iter()
+                .rev()
+                .skip(offset)
+                .take(limit)
+                .cloned()
+                .collect(),
+            None => Vec::new(),
+        }
+    }
+
+    fn delete_user_history(&mut self, user_uuid: &str) {
+        self.uuid_to_attempts.remove(user_uuid);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn attempt(success: bool) -> LoginAttempt {
+        LoginAttempt {
+            timestamp: SystemTime::now(),
+            success,
+            client: ClientMetadata::default(),
+        }
+    }
+
+    #[test]
+    fn should_list_attempts_newest_first() {
+        let mut login_history_service = LoginHistoryImpl::default();
+        login_history_service.record_attempt("123456", attempt(false));
+        login_history_service.record_attempt("123456", attempt(true));
+        login_history_service.record_attempt("654321", attempt(false));
+
+        let attempts = login_history_service.list_attempts("123456", 0, 10);
+
+        assert_eq!(attempts.len(), 2);
+        assert!(attempts[0].success);
+        assert!(!attempts[1].success);
+    }
+
+    #[test]
+    fn should_paginate_attempts() {
+        let mut login_history_service = LoginHistoryImpl::default();
+        for _ in 0..5 {
+            login_history_service.record_attempt("123456", attempt(true));
+        }
+
+        assert_eq!(login_history_service.list_attempts("123456", 0, 2).len(), 2);
+        assert_eq!(login_history_service.list_attempts("123456", 4, 2).len(), 1);
+        assert!(login_history_service
+            .list_attempts("123456", 5, 2)
+            .is_empty());
+    }
+
+    #[test]
+    fn should_drop_oldest_attempts() {
+        let mut login_history_service = LoginHistoryImpl::default();
+        login_history_service.record_attempt("123456", attempt(false));
+        for _ in 0..MAX_LOGIN_HISTORY {
+            login_history_service.record_attempt("123456", attempt(true));
+        }
+
+        let attempts = login_history_service.list_attempts("123456", 0, MAX_LOGIN_HISTORY + 1);
+
+        assert_eq!(attempts.len(), MAX_LOGIN_HISTORY);
+        assert!(attempts.iter().all(|attempt| attempt.success));
+    }
+
+    #[test]
+    fn should_delete_user_history() {
+        let mut login_history_service = LoginHistoryImpl::default();
+        login_history_service.record_attempt("123456", attempt(true));
+
+        login_history_service.delete_user_history("123456");
+
+        assert!(login_history_service
+            .list_attempts("123456", 0, 10)
+            .is_empty());
+    }
+}
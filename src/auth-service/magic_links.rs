@@ -0,0 +1,170 @@
+use std::collections::HashMap;
+use std::time::{Duration, SystemTime};
+
+use crate::clock::{Clock, SystemClock};
+use crate::random::{OsRandom, Random};
+
+/// How long a magic link can be redeemed.
+pub const MAGIC_LINK_LIFETIME: Duration = Duration::from_secs(60 * 10);
+
+pub trait MagicLinks {
+    fn create_magic_link(&mut self, user_uuid: &str) -> String;
+    /// Returns the uuid of the user `token` was issued for without consuming it.
+    fn magic_link_user(&self, token: &str) -> Option<String>;
+    /// Consumes `token` and returns the uuid of the user it was issued for.
+    fn redeem_magic_link(&mut self, token: &str) -> Option<String>;
+}
+
+#[derive(Clone, Debug)]
+struct MagicLink {
+    user_uuid: String,
+    expires_at: SystemTime,
+}
+
+pub struct MagicLinksImpl {
+    token_to_link: HashMap<String, MagicLink>,
+    /// Tells when tokens expire.
+    clock: Box<dyn Clock + Send + Sync>,
+    /// Draws the tokens.
+    random: Box<dyn Random + Send + Sync>,
+}
+
+impl MagicLinksImpl {
+    /// Tells the time with `clock` instead of the system clock, so tests can expire tokens
+    /// without waiting.
+    #[cfg(test)]
+    pub fn with_clock(mut self, clock: Box<dyn Clock + Send + Sync>) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    /// Draws tokens from `random` instead of the OS, so tests get the same tokens each run.
+    #[cfg(test)]
+    pub fn with_random(mut self, random: Box<dyn Random + Send + Sync>) -> Self {
+        self.random = random;
+        self
+    }
+}
+
+impl Default for MagicLinksImpl {
+    fn default() -> Self {
+        Self {
+            token_to_link: HashMap::new(),
+            clock: Box::new(SystemClock),
+            random: Box::new(OsRandom),
+        }
+    }
+}
+
+impl MagicLinks for MagicLinksImpl {
+    fn create_magic_link(&mut self, user_uuid: &str) -> String {
+        let token: String = self.random.uuid().to_string();
+
+        let link = MagicLink {
+            user_uuid: user_uuid.to_string(),
+            expires_at: self.clock.now() + MAGIC_LINK_LIFETIME,
+        };
+
+        // Only the most recently requested link stays valid.
+        self.token_to_link
+            .retain(|_, link| link.user_uuid != user_uuid);
+        self.token_to_link.insert(token.clone(), link);
+
+        token
+    }
+
+    fn magic_link_user(&self, token: &str) -> Option<String> {
+        self.token_to_link
+            .get(token)
+            .filter(|link| link.expires_at > self.clock.now())
+            .map(|link| link.user_uuid.clone())
+    }
+
+    fn redeem_magic_link(&mut self, token: &str) -> Option<String> {
+        // Tokens are removed even when expired so they can't be retried.
+        self.token_to_link
+            .remove(token)
+            .filter(|link| link.expires_at > self.clock.now())
+            .map(|link| link.user_uuid)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::ManualClock;
+    use crate::random::SeededRandom;
+
+    #[test]
+    fn should_create_magic_link() {
+        let mut magic_links_service = MagicLinksImpl::default();
+        let token = magic_links_service.create_magic_link("123456");
+        assert_eq!(magic_links_service.token_to_link.len(), 1);
+        assert_eq!(
+            magic_links_service
+                .token_to_link
+                .get(&token)
+                .unwrap()
+                .user_uuid,
+            "123456"
+        );
+    }
+
+    #[test]
+    fn should_replace_previous_magic_link() {
+        let mut magic_links_service = MagicLinksImpl::default();
+        let first = magic_links_service.create_magic_link("123456");
+        let second = magic_links_service.create_magic_link("123456");
+
+        assert_eq!(magic_links_service.token_to_link.len(), 1);
+        assert!(magic_links_service.redeem_magic_link(&first).is_none());
+        assert!(magic_links_service.redeem_magic_link(&second).is_some());
+    }
+
+    #[test]
+    fn should_redeem_magic_link_once() {
+        let mut magic_links_service = MagicLinksImpl::default();
+        let token = magic_links_service.create_magic_link("123456");
+
+        assert_eq!(
+            magic_links_service.redeem_magic_link(&token),
+            Some("123456".to_owned())
+        );
+        assert!(magic_links_service.redeem_magic_link(&token).is_none());
+    }
+
+    #[test]
+    fn should_not_consume_magic_link_when_looked_up() {
+        let mut magic_links_service = MagicLinksImpl::default();
+        let token = magic_links_service.create_magic_link("123456");
+
+        assert_eq!(
+            magic_links_service.magic_link_user(&token),
+            Some("123456".to_owned())
+        );
+        assert!(magic_links_service.redeem_magic_link(&token).is_some());
+        assert!(magic_links_service.magic_link_user(&token).is_none());
+    }
+
+    #[test]
+    fn should_not_redeem_expired_magic_link() {
+        let clock = ManualClock::new(SystemTime::now());
+        let mut magic_links_service = MagicLinksImpl::default().with_clock(Box::new(clock.clone()));
+        let token = magic_links_service.create_magic_link("123456");
+        clock.advance(MAGIC_LINK_LIFETIME);
+
+        assert!(magic_links_service.redeem_magic_link(&token).is_none());
+        assert_eq!(magic_links_service.token_to_link.len(), 0);
+    }
+
+    #[test]
+    fn should_draw_magic_links_from_random() {
+        let mut first = MagicLinksImpl::default().with_random(Box::new(SeededRandom::new(7)));
+        let mut second = MagicLinksImpl::default().with_random(Box::new(SeededRandom::new(7)));
+
+        assert_eq!(
+            first.create_magic_link("123456"),
+            second.create_magic_link("123456")
+        );
+    }
+}
@@ -0,0 +1,29 @@
+use crate::secret::Secret;
+
+/// Delivers out-of-band messages (such as password reset tokens) to users.
+pub trait Mailer {
+    fn send_password_reset(&self, user_uuid: &str, reset_token: &Secret);
+    fn send_magic_link(&self, user_uuid: &str, token: &Secret);
+    /// Tells the user someone tried to sign up with their username or email, which sign up
+    /// doesn't reveal in anti-enumeration mode.
+    fn send_sign_up_attempt(&self, user_uuid: &str);
+}
+
+/// Logs that messages were sent instead of delivering them. Tokens are left out, as anyone
+/// reading the logs could take over the accounts with them.
+#[derive(Default)]
+pub struct ConsoleMailer;
+
+impl Mailer for ConsoleMailer {
+    fn send_password_reset(&self, user_uuid: &str, _reset_token: &Secret) {
+        tracing::info!(user_uuid, "Sent password reset token");
+    }
+
+    fn send_magic_link(&self, user_uuid: &str, _token: &Secret) {
+        tracing::info!(user_uuid, "Sent magic link");
+    }
+
+    fn send_sign_up_attempt(&self, user_uuid: &str) {
+        tracing::info!(user_uuid, "Sign up attempt with an existing account");
+    }
+}
@@ -1,35 +1,859 @@
-use std::sync::Mutex;
+use std::env;
+use std::future::Future;
+use std::sync::Arc;
+use std::time::Duration;
 
+mod access_log_layer;
+mod admin;
+mod api_versions;
 mod auth;
+mod backups;
+mod breached_passwords;
+mod catch_panic_layer;
+mod challenges;
+mod clock;
+mod concurrency_limit_layer;
+mod config;
+mod error;
+mod error_reporting;
+mod expiry_wheel;
+mod file_sessions;
+mod groups;
+#[path = "../health.rs"]
+mod health;
+mod idempotency;
+mod jwt_sessions;
+mod ldap_users;
+mod lockouts;
+#[path = "../logging.rs"]
+mod logging;
+mod login_history;
+mod magic_links;
+mod mailer;
+mod maintenance;
+mod metrics;
+mod metrics_layer;
+mod mfa;
+#[cfg(any(feature = "postgres", feature = "sqlite"))]
+mod migrations;
+mod oidc;
+mod password_hashing;
+mod password_policy;
+mod password_resets;
+mod random;
+mod rate_limit_layer;
+mod rate_limits;
+#[path = "../request_id.rs"]
+mod request_id;
+mod request_id_layer;
+mod revocations;
+mod runtime_stats;
+mod secret;
+mod seeds;
+mod session_auth;
+mod session_cache;
 mod sessions;
+#[path = "../settings.rs"]
+mod settings;
+mod storage_retry;
+mod telemetry;
+mod timeout_layer;
+#[cfg(feature = "tls")]
+mod tls;
+mod token_signing;
+#[cfg(unix)]
+mod unix_socket;
+mod user_ids;
 mod users;
+mod validation;
 
+use access_log_layer::AccessLogLayer;
+use admin::{AdminServer, AdminService, MAX_ADMIN_MESSAGE_BYTES};
+use api_versions::{AuthV2, AuthV2Server, UNVERSIONED_SERVICE_NAME};
 use auth::*;
-use sessions::{Sessions, SessionsImpl};
+use catch_panic_layer::CatchPanicLayer;
+use challenges::ProofOfWork;
+use concurrency_limit_layer::ConcurrencyLimitLayer;
+use config::{Config, DatabaseConfig, PasswordHashing, UserIdFormat};
+use error_reporting::{ErrorReporter, ErrorReportingLayer};
+use health::{HealthServer, ServingStatus};
+use lockouts::LockoutsImpl;
+use mailer::ConsoleMailer;
+use metrics::RpcMetrics;
+use metrics_layer::MetricsLayer;
+use password_hashing::{PasswordHasher, Pbkdf2Hasher, MIN_PBKDF2_ROUNDS};
+use rate_limit_layer::RateLimitLayer;
+use rate_limits::TokenBucketLimiter;
+use request_id_layer::RequestIdLayer;
+use runtime_stats::RuntimeStats;
+use session_auth::SessionAuthLayer;
+use sessions::{Sessions, SessionsImpl, SESSION_EVENTS_CAPACITY};
+use settings::Settings;
+use storage_retry::{StorageRetry, STORAGE_HEALTH_SERVICE_NAME};
+use timeout_layer::TimeoutLayer;
+use token_signing::TokenSigner;
+use tokio::sync::{broadcast, watch, Mutex, RwLock};
+#[cfg(feature = "tls")]
+use tonic::service::interceptor::InterceptedService;
+use tower::util::MapRequestLayer;
+use user_ids::{IdGenerator, NanoId, UuidV4, UuidV7};
+use users::DELETED_USERS_PURGE_INTERVAL;
 use users::{Users, UsersImpl};
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
-    // Here we are using ip 0.0.0.0 so the service is listening on all the configured network interfaces. This is needed for Docker to work, which we will add later on.
+    // Flags override environment variables, which override the CONFIG_FILE
+    let settings = Settings::load(env::args().skip(1))?;
+    let config = Config::load(&settings)?;
+
+    // Export spans to a collector, if one is configured
+    let mut exporters: Vec<logging::Exporter> = Vec::new();
+    match &config.otlp {
+        #[cfg(feature = "otel")]
+        Some(otlp_config) => exporters.push(telemetry::exporter(otlp_config)?),
+        #[cfg(not(feature = "otel"))]
+        Some(_) => {
+            return Err("An OTLP endpoint is configured but the `otel` feature is disabled".into())
+        }
+        None => (),
+    }
+
+    // Report panics and logged errors to an error tracker, if one is configured
+    let error_reporter: Option<Arc<dyn ErrorReporter>> = match &config.sentry {
+        #[cfg(feature = "sentry")]
+        Some(sentry_config) => Some(Arc::new(error_reporting::SentryReporter::new(
+            sentry_config,
+        )?)),
+        #[cfg(not(feature = "sentry"))]
+        Some(_) => {
+            return Err("A Sentry DSN is configured but the `sentry` feature is disabled".into())
+        }
+        None => None,
+    };
+    if let Some(error_reporter) = &error_reporter {
+        error_reporting::report_panics(error_reporter.clone());
+        exporters.push(Box::new(ErrorReportingLayer::new(error_reporter.clone())));
+    }
+    logging::init(&config.log_level, config.log_format, exporters)?;
+
+    // By default we listen on all the configured network interfaces. This is needed for Docker to work.
     // See: https://stackoverflow.com/questions/39525820/docker-port-forwarding-not-working
-    // Port 50051 is the recommended gRPC port.
-    let addr = "[::0]:50051".parse()?;
+    let addr = config.bind_address;
+
+    // Load the TLS certificate up front so bad files stop the service before anything starts
+    #[cfg(feature = "tls")]
+    let tls = match &config.tls {
+        Some(tls_config) => {
+            let tls = tls::ReloadableTls::load(tls_config)?;
+            if let Some(reload_interval) = tls_config.reload_interval {
+                tls.spawn_reload(reload_interval);
+            }
+            Some(tls)
+        }
+        None => None,
+    };
+    #[cfg(not(feature = "tls"))]
+    if config.tls.is_some() {
+        return Err("TLS is configured but the `tls` feature is disabled".into());
+    }
+
+    // Hash new passwords with the configured algorithm, existing hashes keep verifying. Hashing
+    // runs on the blocking threads, so sign in bursts don't hold up other RPCs
+    let password_hasher: Box<dyn PasswordHasher + Send + Sync> = match config.password_hashing {
+        PasswordHashing::Pbkdf2 { rounds } => Box::new(Pbkdf2Hasher::new(rounds)),
+        #[cfg(feature = "argon2")]
+        PasswordHashing::Argon2id {
+            memory_kib,
+            iterations,
+            parallelism,
+        } => Box::new(password_hashing::Argon2idHasher::new(
+            memory_kib,
+            iterations,
+            parallelism,
+        )?),
+        #[cfg(not(feature = "argon2"))]
+        PasswordHashing::Argon2id { .. } => {
+            return Err(
+                "Argon2id password hashing is configured but the `argon2` feature is disabled"
+                    .into(),
+            )
+        }
+    };
+
+    // Suggest a cost meeting the target verification latency on this machine instead of serving
+    if let Some(target) = config.password_hash_benchmark {
+        let (setting, calibration) =
+            match config.password_hashing {
+                PasswordHashing::Pbkdf2 { .. } => (
+                    "PBKDF2_ROUNDS",
+                    password_hashing::calibrate_cost(MIN_PBKDF2_ROUNDS, target, |rounds| {
+                        Ok(Pbkdf2Hasher::new(rounds))
+                    })?,
+                ),
+                #[cfg(feature = "argon2")]
+                PasswordHashing::Argon2id {
+                    memory_kib,
+                    parallelism,
+                    ..
+                } => (
+                    "ARGON2_ITERATIONS",
+                    password_hashing::calibrate_cost(1, target, |iterations| {
+                        password_hashing::Argon2idHasher::new(memory_kib, iterations, parallelism)
+                    })?,
+                ),
+                #[cfg(not(feature = "argon2"))]
+                PasswordHashing::Argon2id { .. } => return Err(
+                    "Argon2id password hashing is configured but the `argon2` feature is disabled"
+                        .into(),
+                ),
+            };
+        tracing::info!(
+            setting,
+            cost = calibration.cost,
+            verify_time = ?calibration.verify_time,
+            ?target,
+            "Suggested password hashing cost"
+        );
+        return Ok(());
+    }
+
+    // Generate ids for new users in the configured format
+    let id_generator: Box<dyn IdGenerator + Send + Sync> = match config.user_id_format {
+        UserIdFormat::UuidV4 => Box::new(UuidV4::default()),
+        UserIdFormat::UuidV7 => Box::new(UuidV7::default()),
+        UserIdFormat::NanoId { length } => Box::new(NanoId::new(length)),
+    };
+
+    // Retry database queries failing transiently, reporting the storage degraded meanwhile
+    let storage_retry = StorageRetry::new(config.storage_retry_budget);
+
+    // Apply pending database migrations as the stores are opened, unless they're applied manually
+    #[cfg(any(feature = "postgres", feature = "sqlite"))]
+    let migrations = match config.migrate {
+        true => config::Migrations::Startup,
+        false => config.database_migrations,
+    };
+
+    // Create user service instance, either in memory or in a database
+    let users_service: Box<RwLock<dyn Users + Send + Sync + 'static>> =
+        match &config.users_database {
+            None => Box::new(RwLock::new(
+                UsersImpl::default()
+                    .with_password_hasher(password_hasher)
+                    .with_hashing_concurrency(config.password_hashing_concurrency)
+                    .with_id_generator(id_generator),
+            )),
+            #[cfg(feature = "postgres")]
+            Some(DatabaseConfig::Postgres {
+                url,
+                max_connections,
+            }) => Box::new(RwLock::new(
+                users::PostgresUsers::connect(url, *max_connections, migrations)
+                    .await?
+                    .with_password_hasher(password_hasher)
+                    .with_hashing_concurrency(config.password_hashing_concurrency)
+                    .with_id_generator(id_generator)
+                    .with_retry(storage_retry.clone()),
+            )),
+            #[cfg(not(feature = "postgres"))]
+            Some(DatabaseConfig::Postgres { .. }) => return Err(
+                "A Postgres users database is configured but the `postgres` feature is disabled"
+                    .into(),
+            ),
+            #[cfg(feature = "sqlite")]
+            Some(DatabaseConfig::Sqlite { path }) => Box::new(RwLock::new(
+                users::SqliteUsers::open(path, migrations)
+                    .await?
+                    .with_password_hasher(password_hasher)
+                    .with_hashing_concurrency(config.password_hashing_concurrency)
+                    .with_id_generator(id_generator)
+                    .with_retry(storage_retry.clone()),
+            )),
+            #[cfg(not(feature = "sqlite"))]
+            Some(DatabaseConfig::Sqlite { .. }) => {
+                return Err(
+                    "A SQLite users database is configured but the `sqlite` feature is disabled"
+                        .into(),
+                )
+            }
+        };
+
+    // Check passwords against a directory, keeping its users in the users backend
+    #[cfg(feature = "ldap")]
+    let users_service: Box<RwLock<dyn Users + Send + Sync + 'static>> = match config.ldap {
+        Some(ldap_config) => Box::new(RwLock::new(ldap_users::LdapUsers::new(
+            Box::new(ldap_users::LdapDirectory::new(ldap_config.clone())),
+            ldap_config.url,
+            users_service,
+        ))),
+        None => users_service,
+    };
+    #[cfg(not(feature = "ldap"))]
+    if config.ldap.is_some() {
+        return Err("An LDAP directory is configured but the `ldap` feature is disabled".into());
+    }
+
+    // In-memory and SQLite sessions publish their changes for `WatchSessionEvents`
+    let (session_events, _) = broadcast::channel(SESSION_EVENTS_CAPACITY);
+
+    //Create session service instance, either in memory, in SQLite or as stateless JWTs
+    let sessions_service: Box<RwLock<dyn Sessions + Send + Sync + 'static>> = match &config
+        .jwt_sessions
+    {
+        None => {
+            // Sign session tokens with the configured keys so they can be rotated
+            let signer = match config.session_token_keys.is_empty() {
+                true => TokenSigner::generate(),
+                false => TokenSigner::new(
+                    config
+                        .session_token_keys
+                        .iter()
+                        .map(|key| key.expose().as_bytes().to_vec())
+                        .collect(),
+                )?,
+            };
+
+            match &config.sqlite_sessions_path {
+                #[cfg(feature = "sqlite")]
+                Some(path) => Box::new(RwLock::new(
+                    sessions::SqliteSessions::open(path, config.session_policy, migrations)
+                        .await?
+                        .with_token_signer(signer)
+                        .with_events(session_events.clone())
+                        .with_retry(storage_retry.clone()),
+                )),
+                #[cfg(not(feature = "sqlite"))]
+                Some(_) => {
+                    return Err(
+                        "SQLite sessions are configured but the `sqlite` feature is disabled"
+                            .into(),
+                    )
+                }
+                None => {
+                    let sessions = SessionsImpl::new(config.session_policy)
+                        .with_token_signer(signer)
+                        .with_events(session_events.clone());
+
+                    // Keep sessions across restarts if a store is configured
+                    match &config.session_store_path {
+                        Some(path) => Box::new(RwLock::new(file_sessions::FileSessions::open(
+                            path, sessions,
+                        )?)),
+                        None => Box::new(RwLock::new(sessions)),
+                    }
+                }
+            }
+        }
+        #[cfg(feature = "jwt-sessions")]
+        Some(keys) => {
+            let sessions = jwt_sessions::JwtSessions::new(
+                Box::new(jwt_sessions::JwtCodec::from_keys(keys)?),
+                config.session_policy,
+            );
+
+            // Keep revocations across restarts if a file is configured
+            match &config.jwt_revocations_path {
+                Some(path) => Box::new(RwLock::new(
+                    sessions.with_revocations(Box::new(revocations::FileRevocations::open(path)?)),
+                )),
+                None => Box::new(RwLock::new(sessions)),
+            }
+        }
+        #[cfg(not(feature = "jwt-sessions"))]
+        Some(_) => {
+            return Err(
+                "JWT sessions are configured but the `jwt-sessions` feature is disabled".into(),
+            )
+        }
+    };
+
+    // With MIGRATE, stop once the databases are migrated instead of serving
+    if config.migrate {
+        tracing::info!("Database migrations are applied");
+        return Ok(());
+    }
+
+    // Reuse validations for a short while so they don't all reach the session store
+    let sessions_service: Box<RwLock<dyn Sessions + Send + Sync + 'static>> =
+        match config.session_cache {
+            Some(cache) => Box::new(RwLock::new(session_cache::CachedSessions::new(
+                sessions_service,
+                cache.capacity,
+                cache.ttl,
+            ))),
+            None => sessions_service,
+        };
+
+    // Lock accounts after repeated failed sign-ins
+    let lockouts_service = Box::new(Mutex::new(LockoutsImpl::new(
+        config.lockout_threshold,
+        config.lockout_duration,
+    )));
+
+    // Only log that reset tokens and magic links were sent until a real mail delivery backend
+    // exists
+    let auth_service = AuthService::new(users_service, sessions_service)
+        .with_mailer(Box::new(ConsoleMailer))
+        .with_lockouts(lockouts_service)
+        .with_deletion_grace_period(config.deletion_grace_period)
+        .with_idempotency_window(config.sign_up_idempotency_window);
+
+    // Slow down password guessing against any one username
+    let auth_service = match config.sign_in_rate_limit {
+        Some(rate_limit) => auth_service
+            .with_sign_in_rate_limiter(Box::new(Mutex::new(TokenBucketLimiter::new(rate_limit)))),
+        None => auth_service,
+    };
+
+    // Reject weak passwords at sign up and reset, including any listed in the denylist file
+    let password_policy = match &config.password_denylist_path {
+        Some(path) => config.password_policy.with_denylist(
+            std::fs::read_to_string(path)
+                .map_err(|e| format!("Failed to read {path}.\n{e:?}"))?
+                .lines()
+                .map(str::to_owned),
+        ),
+        None => config.password_policy,
+    };
+    let auth_service = auth_service.with_password_policy(password_policy);
+
+    // Also reject passwords known from data breaches, without sending them anywhere
+    #[cfg(feature = "breached-passwords")]
+    let auth_service = match config.breached_password_check {
+        true => auth_service.with_breached_passwords(Box::new(
+            breached_passwords::HibpChecker::new(config.breached_password_check_timeout),
+        )),
+        false => auth_service,
+    };
+    #[cfg(not(feature = "breached-passwords"))]
+    if config.breached_password_check {
+        return Err(
+            "Breached password checks are enabled but the `breached-passwords` feature is disabled"
+                .into(),
+        );
+    }
+
+    // Cap how many sessions each user can have at once
+    let auth_service = match config.session_limit {
+        Some(session_limit) => auth_service.with_session_limit(session_limit),
+        None => auth_service,
+    };
+
+    // JWT sessions are validated without the service, so there are no events to watch
+    let auth_service = match &config.jwt_sessions {
+        None => auth_service.with_session_events(session_events),
+        Some(_) => auth_service,
+    };
+
+    // Require a proof of work on sign up to slow down bots
+    let auth_service = match config.sign_up_challenge {
+        true => auth_service.with_sign_up_challenge(Box::new(ProofOfWork::new(
+            config.sign_up_challenge_difficulty,
+        ))),
+        false => auth_service,
+    };
+
+    // Don't reveal which usernames exist through sign in and sign up
+    let auth_service = match config.anti_enumeration {
+        true => auth_service.with_anti_enumeration(),
+        false => auth_service,
+    };
+
+    // Refuse sign ups, and maybe sign ins, until maintenance is switched off
+    let auth_service = auth_service.with_maintenance(config.maintenance);
+
+    // Allow signing in with ID tokens from the configured OpenID Connect providers
+    #[cfg(feature = "oidc")]
+    let auth_service = match config.oidc_providers.is_empty() {
+        true => auth_service,
+        false => auth_service
+            .with_id_token_verifier(Box::new(oidc::JwksVerifier::new(config.oidc_providers))),
+    };
+    #[cfg(not(feature = "oidc"))]
+    if !config.oidc_providers.is_empty() {
+        return Err("OIDC providers are configured but the `oidc` feature is disabled".into());
+    }
 
-    // Create user service instance
-    let users_service: Box<Mutex<dyn Users + Send + Sync + 'static>> =
-        Box::new(Mutex::new(UsersImpl::default()));
+    // Start with the seed file's accounts and groups, e.g. for local development and demos
+    if let Some(path) = &config.seed_path {
+        let seeded = seeds::read_seed(path)?.load(&auth_service).await?;
+        tracing::info!(
+            path,
+            users = seeded.users,
+            existing_users = seeded.existing_users,
+            groups = seeded.groups,
+            "Loaded seed data"
+        );
+    }
 
-    //Create session service instance
-    let sessions_service: Box<Mutex<dyn Sessions + Send + Sync + 'static>> =
-        Box::new(Mutex::new(SessionsImpl::default()));
+    // Background tasks stop once shutdown starts
+    let (shutdown_sender, shutdown) = watch::channel(false);
+
+    // Periodically evict expired sessions so they don't pile up in memory
+    let auth_service = Arc::new(auth_service);
+    let cleanup_service = auth_service.clone();
+    let mut cleanup_shutdown = shutdown.clone();
+    let cleanup_task = tokio::spawn(async move {
+        let mut interval = tokio::time::interval(config.session_cleanup_interval);
+        loop {
+            tokio::select! {
+                _ = interval.tick() => (),
+                _ = cleanup_shutdown.changed() => break,
+            }
+            let removed = cleanup_service.remove_expired_sessions().await;
+            if removed > 0 {
+                tracing::info!(removed, "Removed expired sessions");
+            }
+        }
+    });
+
+    // Purge deleted accounts once they can no longer be restored
+    let purge_service = auth_service.clone();
+    let mut purge_shutdown = shutdown.clone();
+    let purge_task = tokio::spawn(async move {
+        let mut interval = tokio::time::interval(DELETED_USERS_PURGE_INTERVAL);
+        loop {
+            tokio::select! {
+                _ = interval.tick() => (),
+                _ = purge_shutdown.changed() => break,
+            }
+            let purged = purge_service.purge_deleted_users().await;
+            if purged > 0 {
+                tracing::info!(purged, "Purged deleted users");
+            }
+        }
+    });
+
+    // Report NOT_SERVING over the standard health protocol while the backends are unreachable,
+    // and once shutdown starts. The storage alone is reported NOT_SERVING while queries have
+    // been failing transiently, even if retrying them succeeded.
+    let (health_reporter, health_service) = health::health_service();
+    let health_service = Arc::new(health_service);
+    let health_check_service = auth_service.clone();
+    let mut health_shutdown = shutdown.clone();
+    let health_task = tokio::spawn(async move {
+        let auth_service_names = [
+            <AuthServer<AuthService> as tonic::server::NamedService>::NAME,
+            <AuthV2Server<AuthV2> as tonic::server::NamedService>::NAME,
+            UNVERSIONED_SERVICE_NAME,
+        ];
+        let mut interval = tokio::time::interval(HEALTH_CHECK_INTERVAL);
+        loop {
+            tokio::select! {
+                _ = interval.tick() => (),
+                _ = health_shutdown.changed() => break,
+            }
+            let status = match health_check_service.check_backends().await {
+                Ok(()) => ServingStatus::Serving,
+                Err(e) => {
+                    tracing::error!(error = %e, "Backends unreachable");
+                    ServingStatus::NotServing
+                }
+            };
+            if health_reporter.set_status("", status) {
+                tracing::info!(status = status.as_str_name(), "Health status changed");
+            }
+            for auth_service_name in auth_service_names {
+                health_reporter.set_status(auth_service_name, status);
+            }
+            let storage_status = match storage_retry.is_degraded() {
+                true => ServingStatus::NotServing,
+                false => status,
+            };
+            if health_reporter.set_status(STORAGE_HEALTH_SERVICE_NAME, storage_status) {
+                tracing::info!(
+                    status = storage_status.as_str_name(),
+                    "Storage health status changed"
+                );
+            }
+        }
+        health_reporter.set_status("", ServingStatus::NotServing);
+        health_reporter.set_status(STORAGE_HEALTH_SERVICE_NAME, ServingStatus::NotServing);
+        for auth_service_name in auth_service_names {
+            health_reporter.set_status(auth_service_name, ServingStatus::NotServing);
+        }
+    });
+
+    // Count RPCs by outcome and time them, served for Prometheus if configured
+    let rpc_metrics = Arc::new(RpcMetrics::default());
+    let metrics_task = match config.metrics_address {
+        Some(address) => Some(tokio::spawn(metrics::serve(
+            address,
+            rpc_metrics.clone(),
+            shutting_down(shutdown.clone()),
+        )?)),
+        None => None,
+    };
+
+    // Let operators check on the background tasks with the admin GetStats
+    let runtime_stats = Arc::new(RuntimeStats::new(rpc_metrics.clone()));
+    runtime_stats.watch_task("session-cleanup", &cleanup_task);
+    runtime_stats.watch_task("deleted-user-purge", &purge_task);
+    runtime_stats.watch_task("health-check", &health_task);
+    if let Some(metrics_task) = &metrics_task {
+        runtime_stats.watch_task("metrics-server", metrics_task);
+    }
+
+    // Throttle clients before their requests reach any handler
+    let rate_limit_layer =
+        RateLimitLayer::new(config.request_rate_limit, &config.method_rate_limits);
+
+    // Shed load over the concurrency limit, then give handlers a deadline
+    let concurrency_limit_layer = ConcurrencyLimitLayer::new(config.max_concurrent_requests);
+    let timeout_layer = TimeoutLayer::new(config.request_timeout, &config.method_timeouts);
+
+    // Tell handlers which client certificate the caller was verified with, if any
+    #[cfg(feature = "tls")]
+    let identify_client = tls::IdentifyClient::new(
+        config
+            .tls
+            .as_ref()
+            .map(|tls_config| tls_config.allowed_client_names.clone())
+            .unwrap_or_default(),
+    );
+
+    // Compress bulky responses, e.g. user exports, for clients that accept it
+    let grpc_compression = config.grpc_compression;
+    let auth_server = || {
+        let auth_server = AuthServer::from_arc(auth_service.clone());
+        let auth_server = match grpc_compression {
+            Some(encoding) => auth_server
+                .accept_compressed(encoding)
+                .send_compressed(encoding),
+            None => auth_server,
+        };
+        #[cfg(feature = "tls")]
+        let auth_server = InterceptedService::new(auth_server, identify_client.clone());
+        auth_server
+    };
+    let auth_v2_server = || {
+        let auth_server = AuthV2Server::new(AuthV2::new(auth_service.clone()));
+        let auth_server = match grpc_compression {
+            Some(encoding) => auth_server
+                .accept_compressed(encoding)
+                .send_compressed(encoding),
+            None => auth_server,
+        };
+        #[cfg(feature = "tls")]
+        let auth_server = InterceptedService::new(auth_server, identify_client.clone());
+        auth_server
+    };
+
+    // Let tools like grpcurl discover the API without its proto files
+    let reflection_server = if config.grpc_reflection {
+        let reflection_server = tonic_reflection::server::Builder::configure()
+            .register_encoded_file_descriptor_set(authentication::FILE_DESCRIPTOR_SET)
+            .build()
+            .map_err(|e| format!("Failed to build the reflection service.\n{e:?}"))?;
+        Some(reflection_server)
+    } else {
+        None
+    };
+
+    // Every listener runs requests through the same layers
+    let transport = config.transport;
+    let server = Server::builder()
+        .tcp_keepalive(transport.tcp_keepalive)
+        .http2_keepalive_interval(transport.http2_keepalive_interval)
+        .http2_keepalive_timeout(transport.http2_keepalive_timeout)
+        .initial_stream_window_size(transport.initial_stream_window_size)
+        .initial_connection_window_size(transport.initial_connection_window_size)
+        .max_concurrent_streams(transport.max_concurrent_streams)
+        // Events logged while handling a request are tagged with its method and request ID
+        .trace_fn(telemetry::rpc_span)
+        .layer(RequestIdLayer)
+        .layer(AccessLogLayer::new(config.access_log))
+        .layer(MapRequestLayer::new(api_versions::route_unversioned))
+        .layer(MetricsLayer::new(rpc_metrics))
+        // Inside the metrics and access log, so panicked requests are counted as failed
+        .layer(CatchPanicLayer)
+        .layer(concurrency_limit_layer)
+        .layer(timeout_layer)
+        .layer(rate_limit_layer)
+        .layer(SessionAuthLayer::new(auth_service.clone()));
+
+    // Every listener serves the same services
+    let add_services = |server: &Server<_>| {
+        server
+            .clone()
+            .add_service(HealthServer::from_arc(health_service.clone()))
+            .add_service(auth_server())
+            .add_service(auth_v2_server())
+            .add_optional_service(reflection_server.clone())
+    };
+
+    // Local clients, e.g. a sidecar gateway, can connect over a Unix domain socket in plaintext
+    let unix_incoming = match &config.unix_socket_path {
+        #[cfg(unix)]
+        Some(path) => Some(unix_socket::incoming(path)?),
+        #[cfg(not(unix))]
+        Some(_) => return Err("Unix domain sockets are only supported on Unix".into()),
+        None => None,
+    };
+    let unix_server = add_services(&server);
+    let unix_served = async {
+        match unix_incoming {
+            #[cfg(unix)]
+            Some(incoming) => {
+                unix_server
+                    .serve_with_incoming_shutdown(incoming, shutting_down(shutdown.clone()))
+                    .await
+            }
+            _ => Ok(()),
+        }
+    };
+
+    // Listen for TCP connections, over TLS if it's configured
+    #[cfg(feature = "tls")]
+    let tls_incoming = match (tls.clone(), addr) {
+        (Some(tls), Some(addr)) => Some(tls.incoming(addr, transport.tcp_keepalive).await?),
+        _ => None,
+    };
+    #[cfg(feature = "tls")]
+    let tls_server = tls_incoming.map(|incoming| {
+        add_services(&server)
+            .serve_with_incoming_shutdown(incoming, shutting_down(shutdown.clone()))
+    });
+    #[cfg(not(feature = "tls"))]
+    let tls_server: Option<std::future::Ready<Result<(), tonic::transport::Error>>> = None;
+
+    let tcp_server = add_services(&server);
+    let tcp_served = async {
+        match (tls_server, addr) {
+            (Some(tls_server), _) => tls_server.await,
+            (None, Some(addr)) => {
+                tcp_server
+                    .serve_with_shutdown(addr, shutting_down(shutdown.clone()))
+                    .await
+            }
+            (None, None) => Ok(()),
+        }
+    };
+
+    // Operators manage users, sessions and lockouts on a listener of their own, so none of it is
+    // reachable through the public one. It's served over TLS too if it's configured.
+    let admin_service = Arc::new(AdminService::new(
+        auth_service.clone(),
+        runtime_stats.clone(),
+    ));
+    let add_admin_service = |server: &Server<_>| {
+        // Backups are sent in one message
+        let admin_server = AdminServer::from_arc(admin_service.clone())
+            .max_decoding_message_size(MAX_ADMIN_MESSAGE_BYTES)
+            .max_encoding_message_size(MAX_ADMIN_MESSAGE_BYTES);
+        #[cfg(feature = "tls")]
+        let admin_server = InterceptedService::new(admin_server, identify_client.clone());
+        server.clone().add_service(admin_server)
+    };
+    let admin_addr = config.admin_bind_address;
+    #[cfg(feature = "tls")]
+    let admin_tls_server = match (tls, admin_addr) {
+        (Some(tls), Some(admin_addr)) => {
+            let incoming = tls.incoming(admin_addr, transport.tcp_keepalive).await?;
+            Some(
+                add_admin_service(&server)
+                    .serve_with_incoming_shutdown(incoming, shutting_down(shutdown.clone())),
+            )
+        }
+        _ => None,
+    };
+    #[cfg(not(feature = "tls"))]
+    let admin_tls_server: Option<std::future::Ready<Result<(), tonic::transport::Error>>> = None;
+
+    let admin_server = add_admin_service(&server);
+    let admin_served = async {
+        match (admin_tls_server, admin_addr) {
+            (Some(admin_tls_server), _) => admin_tls_server.await,
+            (None, Some(admin_addr)) => {
+                admin_server
+                    .serve_with_shutdown(admin_addr, shutting_down(shutdown.clone()))
+                    .await
+            }
+            (None, None) => Ok(()),
+        }
+    };
+
+    // Serve until SIGTERM or SIGINT, or until any listener fails
+    let served = serve_until_shutdown(
+        async { tokio::try_join!(tcp_served, unix_served, admin_served).map(|_| ()) },
+        &shutdown_sender,
+        config.shutdown_timeout,
+    )
+    .await;
+
+    #[cfg(unix)]
+    if let Some(path) = &config.unix_socket_path {
+        if let Err(e) = unix_socket::remove(path) {
+            tracing::error!("{e}");
+        }
+    }
+
+    // Let background tasks finish what they're doing, then save what's only held in memory
+    let _ = shutdown_sender.send(true);
+    let _ = tokio::join!(cleanup_task, purge_task, health_task);
+    if let Some(metrics_task) = metrics_task {
+        let _ = metrics_task.await;
+    }
+    auth_service.flush().await;
+    tracing::info!("Shut down");
+    telemetry::flush();
+    if let Some(error_reporter) = error_reporter {
+        error_reporter.flush(error_reporting::FLUSH_TIMEOUT);
+    }
+
+    Ok(served?)
+}
+
+/// Runs `server` until it fails, or until SIGTERM or SIGINT. Shutdown then stops new
+/// connections and gives open ones up to `drain_timeout` to finish before they're closed.
+async fn serve_until_shutdown(
+    server: impl Future<Output = Result<(), tonic::transport::Error>>,
+    shutdown_sender: &watch::Sender<bool>,
+    drain_timeout: Duration,
+) -> Result<(), tonic::transport::Error> {
+    tokio::pin!(server);
+
+    tokio::select! {
+        result = &mut server => return result,
+        _ = shutdown_signal() => tracing::info!("Shutting down, draining connections"),
+    }
+    let _ = shutdown_sender.send(true);
+
+    match tokio::time::timeout(drain_timeout, server).await {
+        Ok(result) => result,
+        Err(_) => {
+            tracing::warn!(?drain_timeout, "Closing connections still open");
+            Ok(())
+        }
+    }
+}
+
+/// Resolves once shutdown starts.
+async fn shutting_down(mut shutdown: watch::Receiver<bool>) {
+    let _ = shutdown.wait_for(|shutting_down| *shutting_down).await;
+}
 
-    let auth_service = AuthService::new(users_service, sessions_service);
+/// Resolves on SIGTERM, which Docker sends to stop containers, or SIGINT (Ctrl+C).
+async fn shutdown_signal() {
+    #[cfg(unix)]
+    let terminate = async {
+        use tokio::signal::unix::{signal, SignalKind};
 
-    // Instantiate gRPC server
-    Server::builder()
-        .add_service(AuthServer::new(auth_service))
-        .serve(addr)
-        .await?;
+        match signal(SignalKind::terminate()) {
+            Ok(mut terminate) => {
+                terminate.recv().await;
+            }
+            Err(e) => {
+                tracing::error!(error = ?e, "Failed to listen for SIGTERM");
+                std::future::pending::<()>().await
+            }
+        }
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
 
-    Ok(())
+    tokio::select! {
+        _ = terminate => (),
+        _ = tokio::signal::ctrl_c() => (),
+    }
 }
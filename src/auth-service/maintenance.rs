@@ -0,0 +1,57 @@
+/// Which requests are refused with `MAINTENANCE` while the backends are worked on, e.g. during a
+/// migration. Session validation and every other call keep working.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Maintenance {
+    #[default]
+    Off,
+    /// Sign ups are refused, so no users are added meanwhile.
+    SignUp,
+    /// Sign ups and sign ins are refused, so no sessions are created either.
+    SignIn,
+}
+
+impl Maintenance {
+    pub fn parse(mode: &str) -> Result<Self, String> {
+        match mode {
+            "off" => Ok(Self::Off),
+            "sign-up" => Ok(Self::SignUp),
+            "sign-in" => Ok(Self::SignIn),
+            mode => Err(format!("Error, unknown maintenance mode: {}", mode)),
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Off => "off",
+            Self::SignUp => "sign-up",
+            Self::SignIn => "sign-in",
+        }
+    }
+
+    pub fn refuses_sign_ups(&self) -> bool {
+        *self != Self::Off
+    }
+
+    pub fn refuses_sign_ins(&self) -> bool {
+        *self == Self::SignIn
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn should_refuse_sign_ins_only_in_sign_in_mode() {
+        assert!(!Maintenance::Off.refuses_sign_ups());
+        assert!(Maintenance::SignUp.refuses_sign_ups());
+        assert!(!Maintenance::SignUp.refuses_sign_ins());
+        assert!(Maintenance::SignIn.refuses_sign_ups());
+        assert!(Maintenance::SignIn.refuses_sign_ins());
+
+        for mode in [Maintenance::Off, Maintenance::SignUp, Maintenance::SignIn] {
+            assert_eq!(Maintenance::parse(mode.as_str()), Ok(mode));
+        }
+        assert!(Maintenance::parse("on").is_err());
+    }
+}
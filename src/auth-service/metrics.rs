@@ -0,0 +1,332 @@
+use std::cell::Cell;
+use std::collections::BTreeMap;
+use std::convert::Infallible;
+use std::fmt::Write;
+use std::future::Future;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{Body, Method, Response, Server};
+use parking_lot::Mutex;
+
+/// Upper bounds of the RPC latency histogram buckets, in seconds.
+const LATENCY_BUCKETS: [f64; 11] = [
+    0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0,
+];
+
+/// Why an RPC failed, so alerts on backend trouble aren't set off by users' typos.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FailureReason {
+    /// Wrong password, unknown user, or an invalid or expired token.
+    BadCredentials,
+    /// Throttled, or the account is locked after repeated failures.
+    RateLimited,
+    /// Malformed or otherwise unacceptable request.
+    InvalidRequest,
+    /// A database, directory or the service itself failed.
+    BackendError,
+}
+
+impl FailureReason {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::BadCredentials => "bad_credentials",
+            Self::RateLimited => "rate_limited",
+            Self::InvalidRequest => "invalid_request",
+            Self::BackendError => "backend_error",
+        }
+    }
+
+    /// Classifies an RPC that failed with `code`.
+    pub fn from_code(code: tonic::Code) -> Option<Self> {
+        use tonic::Code::*;
+
+        match code {
+            Ok => None,
+            Unauthenticated => Some(Self::BadCredentials),
+            ResourceExhausted => Some(Self::RateLimited),
+            Cancelled | InvalidArgument | NotFound | AlreadyExists | PermissionDenied
+            | FailedPrecondition | OutOfRange | Unimplemented => Some(Self::InvalidRequest),
+            Unknown | DeadlineExceeded | Aborted | Internal | Unavailable | DataLoss => {
+                Some(Self::BackendError)
+            }
+        }
+    }
+}
+
+tokio::task_local! {
+    /// Why the RPC handled by the current task failed, as far as it's known yet.
+    static FAILURE: Cell<Option<FailureReason>>;
+}
+
+/// Runs `handler`, returning what it resolves to and the failure it recorded, if any.
+pub async fn track_failure<F: Future>(handler: F) -> (F::Output, Option<FailureReason>) {
+    FAILURE
+        .scope(Cell::new(None), async {
+            let output = handler.await;
+            (output, FAILURE.with(Cell::get))
+        })
+        .await
+}
+
+/// Counts the RPC being handled as failed for `reason`, unless something already did. It's the
+/// first failure that's reported, e.g. a database error rather than the sign in it failed.
+pub fn record_failure(reason: FailureReason) {
+    let _ = FAILURE.try_with(|failure| {
+        if failure.get().is_none() {
+            failure.set(Some(reason));
+        }
+    });
+}
+
+#[derive(Default)]
+struct Histogram {
+    buckets: [u64; LATENCY_BUCKETS.len()],
+    sum: f64,
+    count: u64,
+}
+
+impl Histogram {
+    fn observe(&mut self, seconds: f64) {
+        for (bucket, le) in self.buckets.iter_mut().zip(LATENCY_BUCKETS) {
+            if seconds <= le {
+                *bucket += 1;
+            }
+        }
+        self.sum += seconds;
+        self.count += 1;
+    }
+}
+
+#[derive(Default)]
+struct MethodStats {
+    outcomes: BTreeMap<&'static str, u64>,
+    latency: Histogram,
+}
+
+/// Request counts by outcome and latencies of each RPC, rendered for Prometheus.
+#[derive(Default)]
+pub struct RpcMetrics {
+    methods: Mutex<BTreeMap<(String, String), MethodStats>>,
+    in_flight: AtomicUsize,
+}
+
+/// Counts an RPC as in flight until it's dropped, however the RPC ends.
+pub struct InFlight(Arc<RpcMetrics>);
+
+impl Drop for InFlight {
+    fn drop(&mut self) {
+        self.0.in_flight.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+impl RpcMetrics {
+    /// Counts an RPC as in flight while the returned guard lives.
+    pub fn start(self: &Arc<Self>) -> InFlight {
+        self.in_flight.fetch_add(1, Ordering::Relaxed);
+        InFlight(self.clone())
+    }
+
+    /// RPCs being handled.
+    pub fn in_flight(&self) -> usize {
+        self.in_flight.load(Ordering::Relaxed)
+    }
+
+    /// Records an RPC to `service`/`method` answered after `duration`.
+    pub fn record(
+        &self,
+        service: &str,
+        method: &str,
+        failure: Option<FailureReason>,
+        duration: Duration,
+    ) {
+        let outcome = failure.map_or("ok", |failure| failure.as_str());
+
+        let mut methods = self.methods.lock();
+        let stats = methods
+            .entry((service.to_owned(), method.to_owned()))
+            .or_default();
+        *stats.outcomes.entry(outcome).or_default() += 1;
+        stats.latency.observe(duration.as_secs_f64());
+    }
+
+    /// The metrics in the Prometheus text exposition format.
+    pub fn render(&self) -> String {
+        let methods = self.methods.lock();
+        let mut text = String::new();
+
+        text.push_str("# HELP auth_rpc_in_flight RPCs being handled.\n");
+        text.push_str("# TYPE auth_rpc_in_flight gauge\n");
+        let _ = writeln!(text, "auth_rpc_in_flight {}", self.in_flight());
+
+        text.push_str("# HELP auth_rpc_requests_total RPCs handled, by outcome.\n");
+        text.push_str("# TYPE auth_rpc_requests_total counter\n");
+        for ((service, method), stats) in methods.iter() {
+            for (outcome, count) in &stats.outcomes {
+                let _ = writeln!(
+                    text,
+                    "auth_rpc_requests_total{{service=\"{service}\",method=\"{method}\",outcome=\"{outcome}\"}} {count}"
+                );
+            }
+        }
+
+        text.push_str("# HELP auth_rpc_duration_seconds Time taken to answer RPCs.\n");
+        text.push_str("# TYPE auth_rpc_duration_seconds histogram\n");
+        for ((service, method), stats) in methods.iter() {
+            let labels = format!("service=\"{service}\",method=\"{method}\"");
+            let latency = &stats.latency;
+            for (count, le) in latency.buckets.iter().zip(LATENCY_BUCKETS) {
+                let _ = writeln!(
+                    text,
+                    "auth_rpc_duration_seconds_bucket{{{labels},le=\"{le}\"}} {count}"
+                );
+            }
+            let _ = writeln!(
+                text,
+                "auth_rpc_duration_seconds_bucket{{{labels},le=\"+Inf\"}} {}",
+                latency.count
+            );
+            let _ = writeln!(
+                text,
+                "auth_rpc_duration_seconds_sum{{{labels}}} {}",
+                latency.sum
+            );
+            let _ = writeln!(
+                text,
+                "auth_rpc_duration_seconds_count{{{labels}}} {}",
+                latency.count
+            );
+        }
+
+        text
+    }
+}
+
+/// Serves `metrics` at `GET /metrics` on `address` over plain HTTP until `shutdown` resolves.
+/// Fails right away if the address can't be bound.
+pub fn serve(
+    address: SocketAddr,
+    metrics: Arc<RpcMetrics>,
+    shutdown: impl Future<Output = ()> + Send + 'static,
+) -> Result<impl Future<Output = ()>, String> {
+    let make_service = make_service_fn(move |_| {
+        let metrics = metrics.clone();
+        async move {
+            Ok::<_, Infallible>(service_fn(move |request| {
+                let response = match (request.method(), request.uri().path()) {
+                    (&Method::GET, "/metrics") => Response::builder()
+                        .header("content-type", "text/plain; version=0.0.4")
+                        .body(Body::from(metrics.render())),
+                    _ => Response::builder().status(404).body(Body::empty()),
+                };
+                std::future::ready(response)
+            }))
+        }
+    });
+
+    let server = Server::try_bind(&address)
+        .map_err(|e| format!("Failed to listen on METRICS_ADDRESS {address}.\n{e:?}"))?
+        .serve(make_service)
+        .with_graceful_shutdown(shutdown);
+
+    Ok(async move {
+        if let Err(e) = server.await {
+            tracing::error!(error = ?e, "Metrics server failed");
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn should_keep_first_failure() {
+        let ((), failure) = track_failure(async {
+            record_failure(FailureReason::BackendError);
+            record_failure(FailureReason::BadCredentials);
+        })
+        .await;
+        assert_eq!(failure, Some(FailureReason::BackendError));
+
+        let ((), failure) = track_failure(async {}).await;
+        assert_eq!(failure, None);
+
+        // Outside of an RPC there's nothing to record.
+        record_failure(FailureReason::BackendError);
+    }
+
+    #[test]
+    fn should_count_rpcs_in_flight() {
+        let metrics = Arc::new(RpcMetrics::default());
+
+        let first = metrics.start();
+        let second = metrics.start();
+        assert_eq!(metrics.in_flight(), 2);
+        assert!(metrics
+            .render()
+            .lines()
+            .any(|l| l == "auth_rpc_in_flight 2"));
+
+        drop(first);
+        drop(second);
+        assert_eq!(metrics.in_flight(), 0);
+    }
+
+    #[test]
+    fn should_render_metrics() {
+        let metrics = RpcMetrics::default();
+        let service = "authentication.v1.Auth";
+        metrics.record(service, "SignIn", None, Duration::from_millis(20));
+        metrics.record(
+            service,
+            "SignIn",
+            Some(FailureReason::BadCredentials),
+            Duration::from_millis(200),
+        );
+        metrics.record(
+            service,
+            "SignIn",
+            Some(FailureReason::BadCredentials),
+            Duration::from_secs(20),
+        );
+
+        let text = metrics.render();
+        let labels = "service=\"authentication.v1.Auth\",method=\"SignIn\"";
+        for line in [
+            format!("auth_rpc_requests_total{{{labels},outcome=\"ok\"}} 1"),
+            format!("auth_rpc_requests_total{{{labels},outcome=\"bad_credentials\"}} 2"),
+            format!("auth_rpc_duration_seconds_bucket{{{labels},le=\"0.025\"}} 1"),
+            format!("auth_rpc_duration_seconds_bucket{{{labels},le=\"0.25\"}} 2"),
+            format!("auth_rpc_duration_seconds_bucket{{{labels},le=\"10\"}} 2"),
+            format!("auth_rpc_duration_seconds_bucket{{{labels},le=\"+Inf\"}} 3"),
+            format!("auth_rpc_duration_seconds_count{{{labels}}} 3"),
+        ] {
+            assert!(text.lines().any(|l| l == line), "missing {line} in\n{text}");
+        }
+    }
+
+    #[test]
+    fn should_classify_status_codes() {
+        assert_eq!(FailureReason::from_code(tonic::Code::Ok), None);
+        assert_eq!(
+            FailureReason::from_code(tonic::Code::Unauthenticated),
+            Some(FailureReason::BadCredentials)
+        );
+        assert_eq!(
+            FailureReason::from_code(tonic::Code::ResourceExhausted),
+            Some(FailureReason::RateLimited)
+        );
+        assert_eq!(
+            FailureReason::from_code(tonic::Code::InvalidArgument),
+            Some(FailureReason::InvalidRequest)
+        );
+        assert_eq!(
+            FailureReason::from_code(tonic::Code::Unavailable),
+            Some(FailureReason::BackendError)
+        );
+    }
+}
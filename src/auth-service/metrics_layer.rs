@@ -0,0 +1,152 @@
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::time::Instant;
+
+use tower::{Layer, Service};
+
+use crate::metrics::{track_failure, FailureReason, RpcMetrics};
+
+/// Times each RPC, counts it by outcome, and counts RPCs in flight. Handlers and backends report why an RPC failed with
+/// `metrics::record_failure`, otherwise its gRPC status decides.
+#[derive(Clone)]
+pub struct MetricsLayer {
+    metrics: Arc<RpcMetrics>,
+}
+
+impl MetricsLayer {
+    pub fn new(metrics: Arc<RpcMetrics>) -> Self {
+        Self { metrics }
+    }
+}
+
+impl<S> Layer<S> for MetricsLayer {
+    type Service = Metrics<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        Metrics {
+            inner,
+            metrics: self.metrics.clone(),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct Metrics<S> {
+    inner: S,
+    metrics: Arc<RpcMetrics>,
+}
+
+impl<S, B, ResBody> Service<http::Request<B>> for Metrics<S>
+where
+    S: Service<http::Request<B>, Response = http::Response<ResBody>>,
+    S::Future: Send + 'static,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, request: http::Request<B>) -> Self::Future {
+        let started = Instant::now();
+        let in_flight = self.metrics.start();
+        // gRPC paths are `/<package>.<service>/<method>`.
+        let path = request.uri().path().trim_start_matches('/').to_owned();
+        let response = self.inner.call(request);
+        let metrics = self.metrics.clone();
+
+        Box::pin(async move {
+            let (response, failure) = track_failure(response).await;
+            drop(in_flight);
+            let response = response?;
+
+            // Errors are sent as trailers-only responses, with the status in the headers.
+            let code = response
+                .headers()
+                .get("grpc-status")
+                .and_then(|code| code.to_str().ok()?.parse().ok())
+                .map_or(tonic::Code::Ok, tonic::Code::from_i32);
+            let failure = failure.or_else(|| FailureReason::from_code(code));
+
+            // Made up methods would add a series each, so they're counted together.
+            let (service, method) = match code {
+                tonic::Code::Unimplemented => ("unknown", "unknown"),
+                _ => path.split_once('/').unwrap_or(("unknown", "unknown")),
+            };
+            metrics.record(service, method, failure, started.elapsed());
+
+            Ok(response)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::convert::Infallible;
+
+    use super::*;
+    use crate::metrics::record_failure;
+
+    /// Fails requests to `BadCredentials` by recording the failure, and to `Unavailable` with
+    /// that gRPC status.
+    #[derive(Clone)]
+    struct Handler;
+
+    impl Service<http::Request<()>> for Handler {
+        type Response = http::Response<()>;
+        type Error = Infallible;
+        type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send>>;
+
+        fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+            Poll::Ready(Ok(()))
+        }
+
+        fn call(&mut self, request: http::Request<()>) -> Self::Future {
+            let path = request.uri().path().to_owned();
+            Box::pin(async move {
+                let mut response = http::Response::new(());
+                match path.rsplit('/').next() {
+                    Some("BadCredentials") => record_failure(FailureReason::BadCredentials),
+                    Some("Unavailable") => {
+                        response
+                            .headers_mut()
+                            .insert("grpc-status", (tonic::Code::Unavailable as i32).into());
+                    }
+                    _ => (),
+                }
+                Ok(response)
+            })
+        }
+    }
+
+    fn request(path: &str) -> http::Request<()> {
+        http::Request::builder().uri(path).body(()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn should_count_rpcs_by_outcome() {
+        let metrics = Arc::new(RpcMetrics::default());
+        let mut service = MetricsLayer::new(metrics.clone()).layer(Handler);
+
+        for path in [
+            "/authentication.v1.Auth/Ok",
+            "/authentication.v1.Auth/BadCredentials",
+            "/authentication.v1.Auth/Unavailable",
+        ] {
+            service.call(request(path)).await.unwrap();
+        }
+
+        let text = metrics.render();
+        for line in [
+            "auth_rpc_requests_total{service=\"authentication.v1.Auth\",method=\"Ok\",outcome=\"ok\"} 1",
+            "auth_rpc_requests_total{service=\"authentication.v1.Auth\",method=\"BadCredentials\",outcome=\"bad_credentials\"} 1",
+            "auth_rpc_requests_total{service=\"authentication.v1.Auth\",method=\"Unavailable\",outcome=\"backend_error\"} 1",
+        ] {
+            assert!(text.lines().any(|l| l == line), "missing {line} in\n{text}");
+        }
+    }
+}
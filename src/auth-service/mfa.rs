@@ -0,0 +1,301 @@
+use std::collections::HashMap;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use hmac::{Hmac, Mac};
+use percent_encoding::{utf8_percent_encode, NON_ALPHANUMERIC};
+use rand_core::{OsRng, RngCore};
+use sha2::Sha256;
+
+/// Seconds each TOTP code is valid for.
+pub const TOTP_STEP: u64 = 30;
+/// Number of digits in a TOTP code.
+pub const TOTP_DIGITS: u32 = 6;
+/// How many steps before/after the current one are accepted to tolerate clock drift.
+pub const TOTP_DRIFT_STEPS: u64 = 1;
+/// Shown by authenticator apps next to the account name.
+pub const TOTP_ISSUER: &str = "microservice-project";
+
+const SECRET_LENGTH: usize = 20;
+
+pub trait Mfa {
+    /// Generates a new base32 TOTP secret for the user. It isn't enforced until confirmed.
+    fn enroll_totp(&mut self, user_uuid: &str) -> String;
+    /// Enables the pending secret if `code` is valid for it.
+    fn confirm_totp(&mut self, user_uuid: &str, code: &str) -> bool;
+    fn is_totp_enabled(&self, user_uuid: &str) -> bool;
+    /// Checks `code` against the user's enabled secret. Each code can only be used once.
+    fn verify_totp(&mut self, user_uuid: &str, code: &str) -> bool;
+}
+
+#[derive(Clone, Debug)]
+struct TotpSecret {
+    secret: Vec<u8>,
+    /// Last step a code was accepted for, to reject replays.
+    last_used_step: Option<u64>,
+}
+
+#[derive(Default)]
+pub struct MfaImpl {
+    /// Secrets that have been enrolled but not confirmed yet.
+    uuid_to_pending_secret: HashMap<String, Vec<u8>>,
+    uuid_to_secret: HashMap<String, TotpSecret>,
+}
+
+impl Mfa for MfaImpl {
+    fn enroll_totp(&mut self, user_uuid: &str) -> String {
+        let mut secret = vec![0u8; SECRET_LENGTH];
+        OsRng.fill_bytes(&mut secret);
+
+        let encoded = base32_encode(&secret);
+
+        // An already enabled secret stays in use until the new one is confirmed.
+        self.uuid_to_pending_secret
+            .insert(user_uuid.to_string(), secret);
+
+        encoded
+    }
+
+    fn confirm_totp(&mut self, user_uuid: &str, code: &str) -> bool {
+        let step = match self.uuid_to_pending_secret.get(user_uuid) {
+            Some(secret) => matching_step(secret, code, None),
+            None => None,
+        };
+
+        match (step, self.uuid_to_pending_secret.remove(user_uuid)) {
+            (Some(step), Some(secret)) => {
+                self.uuid_to_secret.insert(
+                    user_uuid.to_string(),
+                    TotpSecret {
+                        secret,
+                        last_used_step: Some(step),
+                    },
+                );
+                true
+            }
+            (_, Some(secret)) => {
+                // Wrong code, keep the secret pending so the user can try again.
+                self.uuid_to_pending_secret
+                    .insert(user_uuid.to_string(), secret);
+                false
+            }
+            _ => false,
+        }
+    }
+
+    fn is_totp_enabled(&self, user_uuid: &str) -> bool {
+        self.uuid_to_secret.contains_key(user_uuid)
+    }
+
+    fn verify_totp(&mut self, user_uuid: &str, code: &str) -> bool {
+        let secret = match self.uuid_to_secret.get_mut(user_uuid) {
+            Some(secret) => secret,
+            None => return false,
+        };
+
+        match matching_step(&secret.secret, code, secret.last_used_step) {
+            Some(step) => {
+                secret.last_used_step = Some(step);
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+/// Builds the `otpauth://` URI authenticator apps use to import a secret, usually via QR code.
+pub fn provisioning_uri(username: &str, secret: &str) -> String {
+    let issuer = utf8_percent_encode(TOTP_ISSUER, NON_ALPHANUMERIC);
+
+    format!(
+        "otpauth://totp/{}:{}?secret={}&issuer={}&algorithm=SHA256&digits={}&period={}",
+        issuer,
+        utf8_percent_encode(username, NON_ALPHANUMERIC),
+        secret,
+        issuer,
+        TOTP_DIGITS,
+        TOTP_STEP
+    )
+}
+
+/// Computes the TOTP code for `secret` at `time` (RFC 6238, HMAC-SHA256).
+#[cfg(test)]
+pub fn totp_code(secret: &[u8], time: SystemTime) -> String {
+    let step = time
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs() / TOTP_STEP)
+        .unwrap_or_default();
+
+    hotp(secret, step)
+}
+
+/// Returns the step `code` is valid for within the drift window, ignoring steps up to `last_used_step`.
+fn matching_step(secret: &[u8], code: &str, last_used_step: Option<u64>) -> Option<u64> {
+    let current = SystemTime::now().duration_since(UNIX_EPOCH).ok()?.as_secs() / TOTP_STEP;
+
+    (current.saturating_sub(TOTP_DRIFT_STEPS)..=current + TOTP_DRIFT_STEPS)
+        .filter(|step| last_used_step.is_none_or(|last| *step > last))
+        .find(|step| hotp(secret, *step) == code)
+}
+
+/// HOTP (RFC 4226) using HMAC-SHA256.
+fn hotp(secret: &[u8], counter: u64) -> String {
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret).expect("HMAC accepts keys of any length");
+    mac.update(&counter.to_be_bytes());
+    let hash = mac.finalize().into_bytes();
+
+    // Dynamic truncation.
+    let offset = (hash[hash.len() - 1] & 0x0f) as usize;
+    let binary = u32::from_be_bytes([
+        hash[offset] & 0x7f,
+        hash[offset + 1],
+        hash[offset + 2],
+        hash[offset + 3],
+    ]);
+
+    format!(
+        "{:0width$}",
+        binary % 10u32.pow(TOTP_DIGITS),
+        width = TOTP_DIGITS as usize
+    )
+}
+
+/// Unpadded RFC 4648 base32, the encoding authenticator apps expect for secrets.
+fn base32_encode(data: &[u8]) -> String {
+    const ALPHABET: &[u8; 32] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZ234567";
+
+    let mut encoded = String::with_capacity((data.len() * 8).div_ceil(5));
+    let mut buffer: u16 = 0;
+    let mut bits = 0;
+
+    for byte in data {
+        buffer = (buffer << 8) | *byte as u16;
+        bits += 8;
+        while bits >= 5 {
+            bits -= 5;
+            encoded.push(ALPHABET[((buffer >> bits) & 0x1f) as usize] as char);
+        }
+    }
+
+    if bits > 0 {
+        encoded.push(ALPHABET[((buffer << (5 - bits)) & 0x1f) as usize] as char);
+    }
+
+    encoded
+}
+
+/// Inverse of `base32_encode`, for tests that need to compute codes from an enrolled secret.
+#[cfg(test)]
+pub fn base32_decode(encoded: &str) -> Option<Vec<u8>> {
+    let mut decoded = Vec::with_capacity(encoded.len() * 5 / 8);
+    let mut buffer: u16 = 0;
+    let mut bits = 0;
+
+    for c in encoded.bytes() {
+        let value = match c {
+            b'A'..=b'Z' => c - b'A',
+            b'2'..=b'7' => c - b'2' + 26,
+            _ => return None,
+        };
+        buffer = (buffer << 5) | value as u16;
+        bits += 5;
+        if bits >= 8 {
+            bits -= 8;
+            decoded.push((buffer >> bits) as u8);
+        }
+    }
+
+    Some(decoded)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+
+    fn current_code(secret: &[u8]) -> String {
+        totp_code(secret, SystemTime::now())
+    }
+
+    #[test]
+    fn should_base32_encode() {
+        assert_eq!(
+            base32_encode(b"12345678901234567890"),
+            "GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQ"
+        );
+        assert_eq!(base32_encode(b"f"), "MY");
+        assert_eq!(base32_decode("MY").unwrap(), b"f");
+    }
+
+    #[test]
+    fn should_match_rfc_6238_sha256_vector() {
+        // Test vector from RFC 6238 appendix B, truncated to 6 digits.
+        let secret = b"12345678901234567890123456789012";
+        let time = UNIX_EPOCH + Duration::from_secs(59);
+        assert_eq!(totp_code(secret, time), "119246");
+    }
+
+    #[test]
+    fn should_not_enable_totp_until_confirmed() {
+        let mut mfa_service = MfaImpl::default();
+        mfa_service.enroll_totp("123456");
+
+        assert!(!mfa_service.is_totp_enabled("123456"));
+        assert!(!mfa_service.confirm_totp("123456", "invalid"));
+        assert!(!mfa_service.is_totp_enabled("123456"));
+
+        let code = current_code(mfa_service.uuid_to_pending_secret.get("123456").unwrap());
+        assert!(mfa_service.confirm_totp("123456", &code));
+        assert!(mfa_service.is_totp_enabled("123456"));
+    }
+
+    #[test]
+    fn should_verify_totp_once() {
+        let mut mfa_service = MfaImpl::default();
+        mfa_service.enroll_totp("123456");
+        let secret = mfa_service.uuid_to_pending_secret.remove("123456").unwrap();
+        mfa_service.uuid_to_secret.insert(
+            "123456".to_owned(),
+            TotpSecret {
+                secret: secret.clone(),
+                last_used_step: None,
+            },
+        );
+
+        let code = current_code(&secret);
+
+        assert!(mfa_service.verify_totp("123456", &code));
+        assert!(!mfa_service.verify_totp("123456", &code));
+    }
+
+    #[test]
+    fn should_accept_code_within_drift_window() {
+        let mut mfa_service = MfaImpl::default();
+        mfa_service.enroll_totp("123456");
+        let secret = mfa_service.uuid_to_pending_secret.remove("123456").unwrap();
+        mfa_service.uuid_to_secret.insert(
+            "123456".to_owned(),
+            TotpSecret {
+                secret: secret.clone(),
+                last_used_step: None,
+            },
+        );
+
+        let previous = totp_code(&secret, SystemTime::now() - Duration::from_secs(TOTP_STEP));
+
+        assert!(mfa_service.verify_totp("123456", &previous));
+    }
+
+    #[test]
+    fn should_keep_enabled_secret_while_reenrolling() {
+        let mut mfa_service = MfaImpl::default();
+        mfa_service.enroll_totp("123456");
+        let code = current_code(mfa_service.uuid_to_pending_secret.get("123456").unwrap());
+        mfa_service.confirm_totp("123456", &code);
+
+        mfa_service.enroll_totp("123456");
+
+        assert!(mfa_service.is_totp_enabled("123456"));
+        assert_eq!(mfa_service.uuid_to_pending_secret.len(), 1);
+    }
+}
@@ -0,0 +1,148 @@
+use std::collections::HashMap;
+
+use sqlx::migrate::{Migrate, MigrateError, Migrator};
+
+use crate::config::Migrations;
+
+/// Migrations of the Postgres users database, embedded from `migrations/postgres`.
+#[cfg(feature = "postgres")]
+pub static POSTGRES_MIGRATIONS: Migrator = sqlx::migrate!("migrations/postgres");
+
+/// Migrations of the SQLite database, embedded from `migrations/sqlite`. Users and sessions share
+/// the file, so both tables are created whichever of them is stored there.
+#[cfg(feature = "sqlite")]
+pub static SQLITE_MIGRATIONS: Migrator = sqlx::migrate!("migrations/sqlite");
+
+/// Brings the schema of `database` up to date, or with `Migrations::Manual` checks that it
+/// already is. Either way, a schema migrated by a newer release or by migrations changed since
+/// they were applied is refused rather than served.
+pub async fn migrate<C: Migrate>(
+    migrator: &Migrator,
+    conn: &mut C,
+    database: &str,
+    migrations: Migrations,
+) -> Result<(), String> {
+    let failed =
+        |e: MigrateError| format!("Failed to read the {database} database's migrations.\n{e:?}");
+
+    conn.ensure_migrations_table().await.map_err(failed)?;
+    if let Some(version) = conn.dirty_version().await.map_err(failed)? {
+        return Err(format!(
+            "Error, migration {version} of the {database} database failed partway. Repair the \
+            schema, then delete its row from _sqlx_migrations"
+        ));
+    }
+
+    let applied: HashMap<i64, _> = conn
+        .list_applied_migrations()
+        .await
+        .map_err(failed)?
+        .into_iter()
+        .map(|migration| (migration.version, migration.checksum))
+        .collect();
+    for (version, checksum) in &applied {
+        match migrator
+            .iter()
+            .find(|migration| migration.version == *version)
+        {
+            None => {
+                return Err(format!(
+                    "Error, the {database} database has migration {version}, which this \
+                    release doesn't know. It was migrated by a newer release"
+                ))
+            }
+            Some(migration) if migration.checksum != *checksum => {
+                return Err(format!(
+                    "Error, migration {version} of the {database} database was changed after \
+                    it was applied"
+                ))
+            }
+            Some(_) => (),
+        }
+    }
+
+    let pending: Vec<i64> = migrator
+        .iter()
+        .filter(|migration| !migration.migration_type.is_down_migration())
+        .filter(|migration| !applied.contains_key(&migration.version))
+        .map(|migration| migration.version)
+        .collect();
+    if pending.is_empty() {
+        return Ok(());
+    }
+
+    match migrations {
+        Migrations::Manual => Err(format!(
+            "Error, the {database} database is missing migrations {pending:?}. Apply them with \
+            MIGRATE=true first"
+        )),
+        Migrations::Startup => {
+            // Takes a lock, so replicas starting at once apply them only once.
+            migrator
+                .run_direct(conn)
+                .await
+                .map_err(|e| format!("Failed to migrate the {database} database.\n{e:?}"))?;
+            tracing::info!(database, migrations = ?pending, "Applied database migrations");
+            Ok(())
+        }
+    }
+}
+
+#[cfg(all(test, feature = "sqlite"))]
+mod tests {
+    use sqlx::sqlite::SqliteConnection;
+    use sqlx::Connection;
+
+    use super::*;
+
+    async fn connection() -> SqliteConnection {
+        SqliteConnection::connect("sqlite::memory:").await.unwrap()
+    }
+
+    #[tokio::test]
+    async fn should_apply_pending_migrations_on_startup() {
+        let mut conn = connection().await;
+        migrate(&SQLITE_MIGRATIONS, &mut conn, "test", Migrations::Startup)
+            .await
+            .unwrap();
+
+        let applied = conn.list_applied_migrations().await.unwrap();
+        assert_eq!(applied.len(), SQLITE_MIGRATIONS.iter().count());
+        // Nothing left to apply
+        migrate(&SQLITE_MIGRATIONS, &mut conn, "test", Migrations::Manual)
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn should_refuse_pending_migrations_when_manual() {
+        let mut conn = connection().await;
+        assert!(
+            migrate(&SQLITE_MIGRATIONS, &mut conn, "test", Migrations::Manual)
+                .await
+                .is_err()
+        );
+        assert!(conn.list_applied_migrations().await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn should_refuse_schema_of_newer_release() {
+        let mut conn = connection().await;
+        migrate(&SQLITE_MIGRATIONS, &mut conn, "test", Migrations::Startup)
+            .await
+            .unwrap();
+        sqlx::query(
+            "INSERT INTO _sqlx_migrations \
+            (version, description, success, checksum, execution_time) \
+            VALUES (9999, 'from the future', TRUE, x'00', 0)",
+        )
+        .execute(&mut conn)
+        .await
+        .unwrap();
+
+        let e = migrate(&SQLITE_MIGRATIONS, &mut conn, "test", Migrations::Startup)
+            .await
+            .unwrap_err();
+        assert!(e.contains("newer release"));
+    }
+}
@@ -0,0 +1,481 @@
+use std::fmt::Debug;
+use std::num::NonZeroUsize;
+use std::sync::{Arc, OnceLock};
+use std::time::{Duration, Instant};
+
+#[cfg(feature = "argon2")]
+use argon2::{Algorithm, Argon2, Params as Argon2Params, Version};
+use pbkdf2::{
+    password_hash::{PasswordHash, PasswordHasher as _, PasswordVerifier, SaltString},
+    Params as Pbkdf2Params, Pbkdf2,
+};
+use rand_core::OsRng;
+use tokio::sync::Semaphore;
+
+/// PBKDF2-SHA256 rounds used by default.
+pub const DEFAULT_PBKDF2_ROUNDS: u32 = Pbkdf2Params::RECOMMENDED_ROUNDS as u32;
+/// Argon2id memory cost in KiB used by default.
+pub const DEFAULT_ARGON2_MEMORY_KIB: u32 = 19 * 1024;
+/// Argon2id passes over memory used by default.
+pub const DEFAULT_ARGON2_ITERATIONS: u32 = 2;
+/// Argon2id lanes used by default.
+pub const DEFAULT_ARGON2_PARALLELISM: u32 = 1;
+/// PBKDF2-SHA256 rounds `calibrate_cost` starts timing from.
+pub const MIN_PBKDF2_ROUNDS: u32 = 1000;
+
+/// Hashes new passwords into PHC strings, e.g. `$pbkdf2-sha256$i=600000,l=32$...`.
+///
+/// The string names the algorithm and its cost, so `verify_password` checks hashes from any
+/// hasher and switching hashers doesn't invalidate existing passwords.
+pub trait PasswordHasher: Debug {
+    fn hash_password(&self, password: &str) -> Result<String, String>;
+    /// Whether `hashed_password` was made with another algorithm or cost than this hasher uses,
+    /// so it should be replaced the next time the password is known.
+    fn needs_rehash(&self, hashed_password: &str) -> bool;
+}
+
+#[derive(Clone, Debug)]
+pub struct Pbkdf2Hasher {
+    rounds: u32,
+}
+
+impl Pbkdf2Hasher {
+    pub fn new(rounds: u32) -> Self {
+        Self { rounds }
+    }
+
+    fn params(&self) -> Pbkdf2Params {
+        Pbkdf2Params {
+            rounds: self.rounds,
+            ..Default::default()
+        }
+    }
+}
+
+impl Default for Pbkdf2Hasher {
+    fn default() -> Self {
+        Self::new(DEFAULT_PBKDF2_ROUNDS)
+    }
+}
+
+impl PasswordHasher for Pbkdf2Hasher {
+    fn hash_password(&self, password: &str) -> Result<String, String> {
+        let salt = SaltString::generate(&mut OsRng);
+
+        Ok(Pbkdf2
+            .hash_password_customized(password.as_bytes(), None, None, self.params(), &salt)
+            .map_err(|e| format!("Failed to hash password.\n{e:?}"))?
+            .to_string())
+    }
+
+    fn needs_rehash(&self, hashed_password: &str) -> bool {
+        match PasswordHash::new(hashed_password) {
+            Ok(parsed_hash) if parsed_hash.algorithm.as_str() == "pbkdf2-sha256" => {
+                Pbkdf2Params::try_from(&parsed_hash).map_or(true, |params| params != self.params())
+            }
+            _ => true,
+        }
+    }
+}
+
+#[cfg(feature = "argon2")]
+#[derive(Clone, Debug)]
+pub struct Argon2idHasher {
+    params: Argon2Params,
+}
+
+#[cfg(feature = "argon2")]
+impl Argon2idHasher {
+    /// Fails if the costs are out of the ranges Argon2 allows.
+    pub fn new(memory_kib: u32, iterations: u32, parallelism: u32) -> Result<Self, String> {
+        let params = Argon2Params::new(memory_kib, iterations, parallelism, None)
+            .map_err(|e| format!("Invalid Argon2id parameters.\n{e:?}"))?;
+
+        Ok(Self { params })
+    }
+}
+
+#[cfg(feature = "argon2")]
+impl PasswordHasher for Argon2idHasher {
+    fn hash_password(&self, password: &str) -> Result<String, String> {
+        let salt = SaltString::generate(&mut OsRng);
+
+        Ok(
+            Argon2::new(Algorithm::Argon2id, Version::V0x13, self.params.clone())
+                .hash_password(password.as_bytes(), &salt)
+                .map_err(|e| format!("Failed to hash password.\n{e:?}"))?
+                .to_string(),
+        )
+    }
+
+    fn needs_rehash(&self, hashed_password: &str) -> bool {
+        match PasswordHash::new(hashed_password) {
+            Ok(parsed_hash)
+                if parsed_hash.algorithm.as_str() == "argon2id"
+                    && parsed_hash.version == Some(Version::V0x13.into()) =>
+            {
+                Argon2Params::try_from(&parsed_hash).map_or(true, |params| {
+                    params.m_cost() != self.params.m_cost()
+                        || params.t_cost() != self.params.t_cost()
+                        || params.p_cost() != self.params.p_cost()
+                })
+            }
+            _ => true,
+        }
+    }
+}
+
+/// Checks `password` against a hash produced by any `PasswordHasher`, using the algorithm and
+/// cost recorded in the hash.
+pub fn verify_password(hashed_password: &str, password: &str) -> bool {
+    let parsed_hash = match PasswordHash::new(hashed_password) {
+        Ok(parsed_hash) => parsed_hash,
+        Err(_) => return false,
+    };
+
+    match parsed_hash.algorithm.as_str() {
+        "pbkdf2-sha256" | "pbkdf2-sha512" => Pbkdf2
+            .verify_password(password.as_bytes(), &parsed_hash)
+            .is_ok(),
+        #[cfg(feature = "argon2")]
+        "argon2id" => Argon2::default()
+            .verify_password(password.as_bytes(), &parsed_hash)
+            .is_ok(),
+        _ => false,
+    }
+}
+
+/// A hash of a random password, checked against when a user doesn't exist so that failing to sign
+/// in takes as long as with a wrong password and doesn't tell whether the user exists.
+#[derive(Debug, Default)]
+pub struct DummyHash(OnceLock<Option<String>>);
+
+impl DummyHash {
+    /// Checks `password` against the dummy hash, which never matches. The hash is made with
+    /// `hasher` on first use, so it costs as much to check as the hashes of new passwords.
+    pub fn verify(&self, hasher: &dyn PasswordHasher, password: &str) -> bool {
+        let hashed_password = self.0.get_or_init(|| {
+            let random_password = SaltString::generate(&mut OsRng);
+            hasher.hash_password(random_password.as_str()).ok()
+        });
+        if let Some(hashed_password) = hashed_password {
+            verify_password(hashed_password, password);
+        }
+        false
+    }
+}
+
+/// How many passwords are hashed or checked at once by default: one per core.
+pub fn default_hashing_concurrency() -> usize {
+    std::thread::available_parallelism().map_or(1, NonZeroUsize::get)
+}
+
+/// Hashes and checks passwords on tokio's blocking threads, at most `concurrency` at once, so a
+/// burst of sign ins queues for hashing instead of stalling the async workers other RPCs run on.
+#[derive(Clone, Debug)]
+pub struct HashingPool {
+    hasher: Arc<dyn PasswordHasher + Send + Sync>,
+    dummy_hash: Arc<DummyHash>,
+    permits: Arc<Semaphore>,
+}
+
+impl HashingPool {
+    pub fn new(hasher: Box<dyn PasswordHasher + Send + Sync>, concurrency: usize) -> Self {
+        Self {
+            hasher: hasher.into(),
+            dummy_hash: Arc::default(),
+            permits: Arc::new(Semaphore::new(concurrency.max(1))),
+        }
+    }
+
+    /// Hashes new passwords with `hasher`, keeping the concurrency.
+    pub fn with_hasher(self, hasher: Box<dyn PasswordHasher + Send + Sync>) -> Self {
+        Self {
+            hasher: hasher.into(),
+            dummy_hash: Arc::default(),
+            ..self
+        }
+    }
+
+    /// Hashes at most `concurrency` passwords at once, keeping the hasher.
+    pub fn with_concurrency(self, concurrency: usize) -> Self {
+        Self {
+            permits: Arc::new(Semaphore::new(concurrency.max(1))),
+            ..self
+        }
+    }
+
+    pub async fn hash_password(&self, password: &str) -> Result<String, String> {
+        let hasher = self.hasher.clone();
+        let password = password.to_owned();
+        self.run(move || hasher.hash_password(&password)).await
+    }
+
+    /// See `verify_password`.
+    pub async fn verify_password(&self, hashed_password: &str, password: &str) -> bool {
+        let hashed_password = hashed_password.to_owned();
+        let password = password.to_owned();
+        self.run(move || verify_password(&hashed_password, &password))
+            .await
+    }
+
+    /// Checks `password` against the dummy hash for users that don't exist, see `DummyHash`.
+    pub async fn verify_dummy(&self, password: &str) -> bool {
+        let hasher = self.hasher.clone();
+        let dummy_hash = self.dummy_hash.clone();
+        let password = password.to_owned();
+        self.run(move || dummy_hash.verify(hasher.as_ref(), &password))
+            .await
+    }
+
+    /// See `PasswordHasher::needs_rehash`, which is cheap enough to run on the async workers.
+    pub fn needs_rehash(&self, hashed_password: &str) -> bool {
+        self.hasher.needs_rehash(hashed_password)
+    }
+
+    /// Runs `hash` once a permit is free. The permit is held until `hash` returns, even if the
+    /// caller gives up waiting, as blocking threads can't be cancelled.
+    async fn run<T: Send + 'static>(&self, hash: impl FnOnce() -> T + Send + 'static) -> T {
+        let permit = self
+            .permits
+            .clone()
+            .acquire_owned()
+            .await
+            .expect("Hashing permits are never closed");
+
+        let hashed = tokio::task::spawn_blocking(move || {
+            let _permit = permit;
+            hash()
+        });
+        match hashed.await {
+            Ok(hashed) => hashed,
+            Err(e) => std::panic::resume_unwind(e.into_panic()),
+        }
+    }
+}
+
+impl Default for HashingPool {
+    fn default() -> Self {
+        Self::new(
+            Box::new(Pbkdf2Hasher::default()),
+            default_hashing_concurrency(),
+        )
+    }
+}
+
+/// The cost suggested by `calibrate_cost`, and how long verifying a hash made with it took.
+#[derive(Clone, Debug)]
+pub struct Calibration {
+    pub cost: u32,
+    pub verify_time: Duration,
+}
+
+/// Suggests about the highest cost whose hashes take `target` to verify on this machine. Hashes
+/// from `hasher_with_cost` are timed at doubling costs from `min_cost` until one takes a quarter
+/// of `target`, then the cost is scaled up linearly from there and timed once more.
+pub fn calibrate_cost<H: PasswordHasher>(
+    min_cost: u32,
+    target: Duration,
+    hasher_with_cost: impl Fn(u32) -> Result<H, String>,
+) -> Result<Calibration, String> {
+    let time_verify = |cost| -> Result<Duration, String> {
+        let hashed_password = hasher_with_cost(cost)?.hash_password("calibration")?;
+        let started = Instant::now();
+        verify_password(&hashed_password, "calibration");
+        Ok(started.elapsed())
+    };
+
+    let mut cost = min_cost.max(1);
+    let mut verify_time = time_verify(cost)?;
+    while verify_time < target / 4 && cost <= u32::MAX / 2 {
+        cost *= 2;
+        verify_time = time_verify(cost)?;
+    }
+
+    let scale = target.as_secs_f64() / verify_time.as_secs_f64().max(f64::EPSILON);
+    let cost = (f64::from(cost) * scale)
+        .round()
+        .clamp(f64::from(min_cost.max(1)), f64::from(u32::MAX)) as u32;
+
+    Ok(Calibration {
+        cost,
+        verify_time: time_verify(cost)?,
+    })
+}
+
+/// Whether `verify_password` can check passwords against `hashed_password`, e.g. one imported
+/// from another deployment.
+pub fn is_supported_hash(hashed_password: &str) -> bool {
+    match PasswordHash::new(hashed_password) {
+        Ok(parsed_hash) => {
+            let alg = parsed_hash.algorithm.as_str();
+            matches!(alg, "pbkdf2-sha256" | "pbkdf2-sha512")
+                || (alg == "argon2id" && cfg!(feature = "argon2"))
+        }
+        Err(_) => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use super::*;
+
+    #[test]
+    fn should_verify_pbkdf2_hash() {
+        let hashed_password = Pbkdf2Hasher::new(1000).hash_password("password").unwrap();
+
+        assert!(hashed_password.starts_with("$pbkdf2-sha256$i=1000,"));
+        assert!(verify_password(&hashed_password, "password"));
+        assert!(!verify_password(&hashed_password, "wrong"));
+    }
+
+    #[test]
+    fn should_rehash_pbkdf2_hash_with_other_rounds() {
+        let hashed_password = Pbkdf2Hasher::new(1000).hash_password("password").unwrap();
+
+        assert!(!Pbkdf2Hasher::new(1000).needs_rehash(&hashed_password));
+        assert!(Pbkdf2Hasher::new(2000).needs_rehash(&hashed_password));
+        assert!(Pbkdf2Hasher::new(1000).needs_rehash("not a hash"));
+    }
+
+    #[test]
+    fn should_reject_unknown_algorithm() {
+        assert!(!verify_password("$md5$abcdefgh$abcdefgh", "password"));
+        assert!(!verify_password("not a hash", "password"));
+    }
+
+    #[test]
+    fn should_never_match_dummy_hash() {
+        let dummy_hash = DummyHash::default();
+        let hasher = Pbkdf2Hasher::new(1000);
+
+        assert!(!dummy_hash.verify(&hasher, "password"));
+        assert!(!dummy_hash.verify(&hasher, ""));
+        assert!(dummy_hash
+            .0
+            .get()
+            .unwrap()
+            .as_ref()
+            .unwrap()
+            .starts_with("$pbkdf2-sha256$i=1000,"));
+    }
+
+    #[test]
+    fn should_recognize_supported_hashes() {
+        let hashed_password = Pbkdf2Hasher::new(1000).hash_password("password").unwrap();
+
+        assert!(is_supported_hash(&hashed_password));
+        assert!(!is_supported_hash("$md5$abcdefgh$abcdefgh"));
+        assert!(!is_supported_hash("not a hash"));
+    }
+
+    /// Counts how many hashes run at once, taking a while each so they overlap.
+    #[derive(Debug, Default)]
+    struct SlowHasher {
+        running: AtomicUsize,
+        most_running: AtomicUsize,
+    }
+
+    impl PasswordHasher for Arc<SlowHasher> {
+        fn hash_password(&self, password: &str) -> Result<String, String> {
+            let running = self.running.fetch_add(1, Ordering::SeqCst) + 1;
+            self.most_running.fetch_max(running, Ordering::SeqCst);
+            std::thread::sleep(Duration::from_millis(50));
+            self.running.fetch_sub(1, Ordering::SeqCst);
+            Ok(password.to_owned())
+        }
+
+        fn needs_rehash(&self, _hashed_password: &str) -> bool {
+            false
+        }
+    }
+
+    #[tokio::test]
+    async fn should_hash_at_most_concurrency_passwords_at_once() {
+        let hasher = Arc::new(SlowHasher::default());
+        let hashing = HashingPool::new(Box::new(hasher.clone()), 2);
+
+        let hashed = tokio::join!(
+            hashing.hash_password("a"),
+            hashing.hash_password("b"),
+            hashing.hash_password("c"),
+            hashing.hash_password("d"),
+        );
+
+        assert_eq!(
+            hashed,
+            (
+                Ok("a".to_owned()),
+                Ok("b".to_owned()),
+                Ok("c".to_owned()),
+                Ok("d".to_owned())
+            )
+        );
+        assert_eq!(hasher.most_running.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn should_verify_on_hashing_pool() {
+        let hashing = HashingPool::new(Box::new(Pbkdf2Hasher::new(1000)), 1);
+        let hashed_password = hashing.hash_password("password").await.unwrap();
+
+        assert!(hashing.verify_password(&hashed_password, "password").await);
+        assert!(!hashing.verify_password(&hashed_password, "wrong").await);
+        assert!(!hashing.verify_dummy("password").await);
+        assert!(!hashing.needs_rehash(&hashed_password));
+    }
+
+    #[cfg(feature = "argon2")]
+    #[test]
+    fn should_verify_argon2id_hash() {
+        let hasher = Argon2idHasher::new(DEFAULT_ARGON2_MEMORY_KIB, 1, 1).unwrap();
+        let hashed_password = hasher.hash_password("password").unwrap();
+
+        assert!(hashed_password.starts_with("$argon2id$v=19$m=19456,t=1,p=1$"));
+        assert!(verify_password(&hashed_password, "password"));
+        assert!(!verify_password(&hashed_password, "wrong"));
+    }
+
+    #[cfg(feature = "argon2")]
+    #[test]
+    fn should_rehash_across_algorithms() {
+        let argon2id_hasher = Argon2idHasher::new(DEFAULT_ARGON2_MEMORY_KIB, 1, 1).unwrap();
+        let pbkdf2_hash = Pbkdf2Hasher::new(1000).hash_password("password").unwrap();
+        let argon2id_hash = argon2id_hasher.hash_password("password").unwrap();
+
+        assert!(argon2id_hasher.needs_rehash(&pbkdf2_hash));
+        assert!(!argon2id_hasher.needs_rehash(&argon2id_hash));
+        assert!(Pbkdf2Hasher::new(1000).needs_rehash(&argon2id_hash));
+        assert!(Argon2idHasher::new(DEFAULT_ARGON2_MEMORY_KIB, 2, 1)
+            .unwrap()
+            .needs_rehash(&argon2id_hash));
+    }
+
+    #[test]
+    fn should_calibrate_cost_to_target() {
+        let calibration = calibrate_cost(MIN_PBKDF2_ROUNDS, Duration::from_millis(5), |rounds| {
+            Ok(Pbkdf2Hasher::new(rounds))
+        })
+        .unwrap();
+
+        assert!(calibration.cost >= MIN_PBKDF2_ROUNDS);
+        assert!(calibration.verify_time > Duration::ZERO);
+    }
+
+    #[test]
+    fn should_fail_calibration_with_invalid_hasher() {
+        let calibration = calibrate_cost(1, Duration::from_millis(5), |_| {
+            Err::<Pbkdf2Hasher, _>("Invalid parameters".to_owned())
+        });
+
+        assert!(calibration.is_err());
+    }
+
+    #[cfg(feature = "argon2")]
+    #[test]
+    fn should_reject_invalid_argon2id_parameters() {
+        assert!(Argon2idHasher::new(DEFAULT_ARGON2_MEMORY_KIB, 0, 1).is_err());
+    }
+}
@@ -0,0 +1,239 @@
+use std::collections::HashSet;
+use std::fmt;
+
+/// Shortest password accepted by default.
+pub const DEFAULT_MIN_PASSWORD_LENGTH: usize = 8;
+/// Longest password accepted by default. Bounds the work of hashing untrusted input.
+pub const DEFAULT_MAX_PASSWORD_LENGTH: usize = 128;
+
+/// Passwords always rejected, compared case insensitively.
+const COMMON_PASSWORDS: [&str; 24] = [
+    "123456",
+    "123456789",
+    "12345678",
+    "1234567890",
+    "password",
+    "password1",
+    "password123",
+    "qwerty",
+    "qwerty123",
+    "qwertyuiop",
+    "111111",
+    "11111111",
+    "000000",
+    "abc123",
+    "iloveyou",
+    "letmein",
+    "welcome",
+    "monkey",
+    "dragon",
+    "football",
+    "baseball",
+    "sunshine",
+    "princess",
+    "admin123",
+];
+
+/// A rule a password breaks.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PasswordViolation {
+    TooShort {
+        min_length: usize,
+    },
+    TooLong {
+        max_length: usize,
+    },
+    MissingLowercase,
+    MissingUppercase,
+    MissingDigit,
+    MissingSymbol,
+    /// On the denylist of common or leaked passwords.
+    Common,
+    /// Found in a data breach by a `BreachedPasswords` check.
+    Breached,
+}
+
+impl fmt::Display for PasswordViolation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::TooShort { min_length } => {
+                write!(f, "Password must be at least {min_length} characters")
+            }
+            Self::TooLong { max_length } => {
+                write!(f, "Password must be at most {max_length} characters")
+            }
+            Self::MissingLowercase => write!(f, "Password must contain a lowercase letter"),
+            Self::MissingUppercase => write!(f, "Password must contain an uppercase letter"),
+            Self::MissingDigit => write!(f, "Password must contain a digit"),
+            Self::MissingSymbol => write!(f, "Password must contain a symbol"),
+            Self::Common => write!(f, "Password is too common"),
+            Self::Breached => write!(f, "Password has appeared in a data breach"),
+        }
+    }
+}
+
+/// Rules new passwords must follow, checked when they are chosen rather than at sign in.
+#[derive(Clone, Debug, PartialEq)]
+pub struct PasswordPolicy {
+    /// Bounds on the length in characters.
+    pub min_length: usize,
+    pub max_length: usize,
+    pub require_lowercase: bool,
+    pub require_uppercase: bool,
+    pub require_digit: bool,
+    /// Any character that isn't a letter, digit or whitespace counts as a symbol.
+    pub require_symbol: bool,
+    /// Lowercased passwords rejected on top of the built-in common ones.
+    denylist: HashSet<String>,
+}
+
+impl Default for PasswordPolicy {
+    fn default() -> Self {
+        Self {
+            min_length: DEFAULT_MIN_PASSWORD_LENGTH,
+            max_length: DEFAULT_MAX_PASSWORD_LENGTH,
+            require_lowercase: false,
+            require_uppercase: false,
+            require_digit: false,
+            require_symbol: false,
+            denylist: HashSet::new(),
+        }
+    }
+}
+
+impl PasswordPolicy {
+    /// Also rejects `passwords`, e.g. a list of leaked passwords. Blank entries are ignored.
+    pub fn with_denylist(mut self, passwords: impl IntoIterator<Item = String>) -> Self {
+        self.denylist.extend(
+            passwords
+                .into_iter()
+                .map(|password| password.trim().to_lowercase())
+                .filter(|password| !password.is_empty()),
+        );
+        self
+    }
+
+    /// Returns every rule `password` breaks, so users can fix them all at once.
+    pub fn check(&self, password: &str) -> Result<(), Vec<PasswordViolation>> {
+        let mut violations = Vec::new();
+
+        let length = password.chars().count();
+        if length < self.min_length {
+            violations.push(PasswordViolation::TooShort {
+                min_length: self.min_length,
+            });
+        }
+        if length > self.max_length {
+            violations.push(PasswordViolation::TooLong {
+                max_length: self.max_length,
+            });
+        }
+
+        let classes = [
+            (
+                self.require_lowercase,
+                password.chars().any(char::is_lowercase),
+                PasswordViolation::MissingLowercase,
+            ),
+            (
+                self.require_uppercase,
+                password.chars().any(char::is_uppercase),
+                PasswordViolation::MissingUppercase,
+            ),
+            (
+                self.require_digit,
+                password.chars().any(|c| c.is_ascii_digit()),
+                PasswordViolation::MissingDigit,
+            ),
+            (
+                self.require_symbol,
+                password
+                    .chars()
+                    .any(|c| !c.is_alphanumeric() && !c.is_whitespace()),
+                PasswordViolation::MissingSymbol,
+            ),
+        ];
+        for (required, present, violation) in classes {
+            if required && !present {
+                violations.push(violation);
+            }
+        }
+
+        let lowercased = password.to_lowercase();
+        if COMMON_PASSWORDS.contains(&lowercased.as_str()) || self.denylist.contains(&lowercased) {
+            violations.push(PasswordViolation::Common);
+        }
+
+        match violations.is_empty() {
+            true => Ok(()),
+            false => Err(violations),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn should_accept_password_following_default_policy() {
+        assert_eq!(PasswordPolicy::default().check("correct horse"), Ok(()));
+    }
+
+    #[test]
+    fn should_check_length_in_characters() {
+        let policy = PasswordPolicy {
+            min_length: 4,
+            max_length: 6,
+            ..Default::default()
+        };
+
+        assert_eq!(
+            policy.check("abc"),
+            Err(vec![PasswordViolation::TooShort { min_length: 4 }])
+        );
+        assert_eq!(
+            policy.check("abcdefg"),
+            Err(vec![PasswordViolation::TooLong { max_length: 6 }])
+        );
+        // Four characters, but more bytes.
+        assert_eq!(policy.check("éééé"), Ok(()));
+    }
+
+    #[test]
+    fn should_report_every_missing_class() {
+        let policy = PasswordPolicy {
+            require_lowercase: true,
+            require_uppercase: true,
+            require_digit: true,
+            require_symbol: true,
+            ..Default::default()
+        };
+
+        assert_eq!(
+            policy.check("lowercase only"),
+            Err(vec![
+                PasswordViolation::MissingUppercase,
+                PasswordViolation::MissingDigit,
+                PasswordViolation::MissingSymbol,
+            ])
+        );
+        assert_eq!(policy.check("Upper, lower & 1"), Ok(()));
+    }
+
+    #[test]
+    fn should_reject_denylisted_passwords() {
+        let policy = PasswordPolicy::default()
+            .with_denylist(vec!["Tr0ub4dor&3".to_owned(), "  ".to_owned()]);
+
+        assert_eq!(
+            policy.check("PASSWORD123"),
+            Err(vec![PasswordViolation::Common])
+        );
+        assert_eq!(
+            policy.check("tr0ub4dor&3"),
+            Err(vec![PasswordViolation::Common])
+        );
+        assert_eq!(policy.check("correct horse"), Ok(()));
+    }
+}
@@ -0,0 +1,170 @@
+use std::collections::HashMap;
+use std::time::{Duration, SystemTime};
+
+use crate::clock::{Clock, SystemClock};
+use crate::random::{OsRandom, Random};
+
+/// How long a password reset token can be redeemed.
+pub const RESET_TOKEN_LIFETIME: Duration = Duration::from_secs(60 * 15);
+
+pub trait PasswordResets {
+    fn create_reset_token(&mut self, user_uuid: &str) -> String;
+    /// Returns the uuid of the user `reset_token` was issued for without consuming it.
+    fn reset_token_user(&self, reset_token: &str) -> Option<String>;
+    /// Consumes `reset_token` and returns the uuid of the user it was issued for.
+    fn redeem_reset_token(&mut self, reset_token: &str) -> Option<String>;
+}
+
+#[derive(Clone, Debug)]
+struct PasswordReset {
+    user_uuid: String,
+    expires_at: SystemTime,
+}
+
+pub struct PasswordResetsImpl {
+    token_to_reset: HashMap<String, PasswordReset>,
+    /// Tells when tokens expire.
+    clock: Box<dyn Clock + Send + Sync>,
+    /// Draws the tokens.
+    random: Box<dyn Random + Send + Sync>,
+}
+
+impl PasswordResetsImpl {
+    /// Tells the time with `clock` instead of the system clock, so tests can expire tokens
+    /// without waiting.
+    #[cfg(test)]
+    pub fn with_clock(mut self, clock: Box<dyn Clock + Send + Sync>) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    /// Draws tokens from `random` instead of the OS, so tests get the same tokens each run.
+    #[cfg(test)]
+    pub fn with_random(mut self, random: Box<dyn Random + Send + Sync>) -> Self {
+        self.random = random;
+        self
+    }
+}
+
+impl Default for PasswordResetsImpl {
+    fn default() -> Self {
+        Self {
+            token_to_reset: HashMap::new(),
+            clock: Box::new(SystemClock),
+            random: Box::new(OsRandom),
+        }
+    }
+}
+
+impl PasswordResets for PasswordResetsImpl {
+    fn create_reset_token(&mut self, user_uuid: &str) -> String {
+        let reset_token: String = self.random.uuid().to_string();
+
+        let reset = PasswordReset {
+            user_uuid: user_uuid.to_string(),
+            expires_at: self.clock.now() + RESET_TOKEN_LIFETIME,
+        };
+
+        // Only the most recently requested token stays valid.
+        self.token_to_reset
+            .retain(|_, reset| reset.user_uuid != user_uuid);
+        self.token_to_reset.insert(reset_token.clone(), reset);
+
+        reset_token
+    }
+
+    fn reset_token_user(&self, reset_token: &str) -> Option<String> {
+        self.token_to_reset
+            .get(reset_token)
+            .filter(|reset| reset.expires_at > self.clock.now())
+            .map(|reset| reset.user_uuid.clone())
+    }
+
+    fn redeem_reset_token(&mut self, reset_token: &str) -> Option<String> {
+        // Tokens are removed even when expired so they can't be retried.
+        self.token_to_reset
+            .remove(reset_token)
+            .filter(|reset| reset.expires_at > self.clock.now())
+            .map(|reset| reset.user_uuid)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::ManualClock;
+    use crate::random::SeededRandom;
+
+    #[test]
+    fn should_create_reset_token() {
+        let mut resets_service = PasswordResetsImpl::default();
+        let reset_token = resets_service.create_reset_token("123456");
+        assert_eq!(resets_service.token_to_reset.len(), 1);
+        assert_eq!(
+            resets_service
+                .token_to_reset
+                .get(&reset_token)
+                .unwrap()
+                .user_uuid,
+            "123456"
+        );
+    }
+
+    #[test]
+    fn should_replace_previous_reset_token() {
+        let mut resets_service = PasswordResetsImpl::default();
+        let first = resets_service.create_reset_token("123456");
+        let second = resets_service.create_reset_token("123456");
+
+        assert_eq!(resets_service.token_to_reset.len(), 1);
+        assert!(resets_service.redeem_reset_token(&first).is_none());
+        assert!(resets_service.redeem_reset_token(&second).is_some());
+    }
+
+    #[test]
+    fn should_redeem_reset_token_once() {
+        let mut resets_service = PasswordResetsImpl::default();
+        let reset_token = resets_service.create_reset_token("123456");
+
+        assert_eq!(
+            resets_service.redeem_reset_token(&reset_token),
+            Some("123456".to_owned())
+        );
+        assert!(resets_service.redeem_reset_token(&reset_token).is_none());
+    }
+
+    #[test]
+    fn should_look_up_reset_token_without_redeeming() {
+        let mut resets_service = PasswordResetsImpl::default();
+        let reset_token = resets_service.create_reset_token("123456");
+
+        assert_eq!(
+            resets_service.reset_token_user(&reset_token),
+            Some("123456".to_owned())
+        );
+        assert!(resets_service.redeem_reset_token(&reset_token).is_some());
+        assert!(resets_service.reset_token_user(&reset_token).is_none());
+    }
+
+    #[test]
+    fn should_not_redeem_expired_reset_token() {
+        let clock = ManualClock::new(SystemTime::now());
+        let mut resets_service = PasswordResetsImpl::default().with_clock(Box::new(clock.clone()));
+        let reset_token = resets_service.create_reset_token("123456");
+        clock.advance(RESET_TOKEN_LIFETIME);
+
+        assert!(resets_service.redeem_reset_token(&reset_token).is_none());
+        assert_eq!(resets_service.token_to_reset.len(), 0);
+    }
+
+    #[test]
+    fn should_draw_reset_tokens_from_random() {
+        let mut first = PasswordResetsImpl::default().with_random(Box::new(SeededRandom::new(7)));
+        let mut second = PasswordResetsImpl::default().with_random(Box::new(SeededRandom::new(7)));
+
+        assert_eq!(
+            first.create_reset_token("123456"),
+            second.create_reset_token("123456")
+        );
+    }
+}
@@ -0,0 +1,82 @@
+use std::fmt::Debug;
+
+#[cfg(test)]
+use parking_lot::Mutex;
+use rand_core::{OsRng, RngCore};
+use uuid::Uuid;
+
+/// Where tokens and ids get their random bytes from, so tests can issue the same ones each run.
+pub trait Random: Debug {
+    fn fill_bytes(&self, bytes: &mut [u8]);
+
+    /// A random (version 4) UUID.
+    fn uuid(&self) -> Uuid {
+        let mut bytes = [0u8; 16];
+        self.fill_bytes(&mut bytes);
+        uuid::Builder::from_random_bytes(bytes).into_uuid()
+    }
+}
+
+/// The operating system's cryptographically secure generator.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct OsRandom;
+
+impl Random for OsRandom {
+    fn fill_bytes(&self, bytes: &mut [u8]) {
+        OsRng.fill_bytes(bytes);
+    }
+}
+
+/// The same bytes for the same seed, from SplitMix64. Predictable, so only meant for tests.
+#[cfg(test)]
+#[derive(Debug)]
+pub struct SeededRandom {
+    state: Mutex<u64>,
+}
+
+#[cfg(test)]
+impl SeededRandom {
+    pub fn new(seed: u64) -> Self {
+        Self {
+            state: Mutex::new(seed),
+        }
+    }
+}
+
+#[cfg(test)]
+impl Random for SeededRandom {
+    fn fill_bytes(&self, bytes: &mut [u8]) {
+        let mut state = self.state.lock();
+        for chunk in bytes.chunks_mut(8) {
+            *state = state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+            let mut z = *state;
+            z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+            z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+            z ^= z >> 31;
+            chunk.copy_from_slice(&z.to_le_bytes()[..chunk.len()]);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn seeded_random_should_repeat_for_same_seed() {
+        let (first, second) = (SeededRandom::new(7), SeededRandom::new(7));
+        assert_eq!(first.uuid(), second.uuid());
+        assert_eq!(first.uuid(), second.uuid());
+        assert_ne!(first.uuid(), SeededRandom::new(8).uuid());
+
+        let mut bytes = [0u8; 11];
+        first.fill_bytes(&mut bytes);
+        assert_ne!(bytes, [0u8; 11]);
+    }
+
+    #[test]
+    fn uuid_should_be_version_4() {
+        assert_eq!(OsRandom.uuid().get_version_num(), 4);
+        assert_eq!(SeededRandom::new(7).uuid().get_version_num(), 4);
+    }
+}
@@ -0,0 +1,190 @@
+use std::collections::HashMap;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::time::Duration;
+
+use parking_lot::Mutex;
+use tonic::body::BoxBody;
+use tonic::transport::server::TcpConnectInfo;
+use tower::{Layer, Service};
+
+use crate::rate_limits::{rate_limited, RateLimit, RateLimiter, TokenBucketLimiter};
+
+type SharedLimiter = Mutex<Box<dyn RateLimiter + Send>>;
+
+/// Throttles each client, identified by IP address, across all methods and per method. Methods
+/// are named without their service, e.g. `SignIn`. Throttled requests are rejected as
+/// `RESOURCE_EXHAUSTED` without reaching the handler.
+#[derive(Clone)]
+pub struct RateLimitLayer {
+    global: Option<Arc<SharedLimiter>>,
+    methods: Arc<HashMap<String, SharedLimiter>>,
+}
+
+impl RateLimitLayer {
+    pub fn new(global: Option<RateLimit>, methods: &[(String, RateLimit)]) -> Self {
+        let limiter = |limit: RateLimit| -> SharedLimiter {
+            Mutex::new(Box::new(TokenBucketLimiter::new(limit)))
+        };
+
+        Self {
+            global: global.map(|limit| Arc::new(limiter(limit))),
+            methods: Arc::new(
+                methods
+                    .iter()
+                    .map(|(method, limit)| (method.clone(), limiter(*limit)))
+                    .collect(),
+            ),
+        }
+    }
+
+    /// Counts a request to `path` from `client`. Fails with how long until the client can call
+    /// the method again if it's over either limit.
+    fn check(&self, path: &str, client: &str) -> Result<(), Duration> {
+        if let Some(global) = &self.global {
+            global.lock().check(client)?;
+        }
+
+        // gRPC paths are `/<package>.<service>/<method>`.
+        let method = path.rsplit('/').next().unwrap_or_default();
+        match self.methods.get(method) {
+            Some(limiter) => limiter.lock().check(client),
+            None => Ok(()),
+        }
+    }
+}
+
+impl<S> Layer<S> for RateLimitLayer {
+    type Service = RateLimited<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        RateLimited {
+            inner,
+            limits: self.clone(),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct RateLimited<S> {
+    inner: S,
+    limits: RateLimitLayer,
+}
+
+impl<S, B> Service<http::Request<B>> for RateLimited<S>
+where
+    S: Service<http::Request<B>, Response = http::Response<BoxBody>> + Clone + Send + 'static,
+    S::Future: Send + 'static,
+    B: Send + 'static,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, request: http::Request<B>) -> Self::Future {
+        // Clients without a known address share their limits.
+        let client = request
+            .extensions()
+            .get::<TcpConnectInfo>()
+            .and_then(|connect_info| connect_info.remote_addr())
+            .map(|addr| addr.ip().to_string())
+            .unwrap_or_default();
+
+        if let Err(retry_after) = self.limits.check(request.uri().path(), &client) {
+            let status = rate_limited("Too many requests, try again later", retry_after);
+            return Box::pin(async move { Ok(status.to_http()) });
+        }
+
+        // The clone may not be ready, so call the instance `poll_ready` was called on.
+        let clone = self.inner.clone();
+        let mut inner = std::mem::replace(&mut self.inner, clone);
+        Box::pin(async move { inner.call(request).await })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Clone)]
+    struct Ok200;
+
+    impl Service<http::Request<()>> for Ok200 {
+        type Response = http::Response<BoxBody>;
+        type Error = std::convert::Infallible;
+        type Future = std::future::Ready<Result<Self::Response, Self::Error>>;
+
+        fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+            Poll::Ready(Ok(()))
+        }
+
+        fn call(&mut self, _request: http::Request<()>) -> Self::Future {
+            std::future::ready(Ok(http::Response::new(tonic::body::empty_body())))
+        }
+    }
+
+    fn request(method: &str) -> http::Request<()> {
+        http::Request::builder()
+            .uri(format!("/authentication.Auth/{method}"))
+            .body(())
+            .unwrap()
+    }
+
+    fn grpc_status(response: &http::Response<BoxBody>) -> Option<&str> {
+        response
+            .headers()
+            .get("grpc-status")
+            .map(|status| status.to_str().unwrap())
+    }
+
+    #[tokio::test]
+    async fn should_limit_methods_separately() {
+        let sign_in = RateLimit {
+            burst: 2,
+            per_minute: 1,
+        };
+        let mut service = RateLimitLayer::new(None, &[("SignIn".to_owned(), sign_in)]).layer(Ok200);
+
+        for _ in 0..2 {
+            let response = service.call(request("SignIn")).await.unwrap();
+            assert_eq!(grpc_status(&response), None);
+        }
+
+        let response = service.call(request("SignIn")).await.unwrap();
+        assert_eq!(
+            grpc_status(&response),
+            Some((tonic::Code::ResourceExhausted as i32).to_string().as_str())
+        );
+        assert_eq!(response.headers()["retry-after"], "60");
+        assert!(response.headers().contains_key("grpc-status-details-bin"));
+
+        // Methods without a limit aren't throttled.
+        for _ in 0..10 {
+            let response = service.call(request("SignOut")).await.unwrap();
+            assert_eq!(grpc_status(&response), None);
+        }
+    }
+
+    #[tokio::test]
+    async fn should_limit_all_methods_globally() {
+        let global = RateLimit {
+            burst: 3,
+            per_minute: 1,
+        };
+        let mut service = RateLimitLayer::new(Some(global), &[]).layer(Ok200);
+
+        for method in ["SignIn", "SignOut", "GetProfile"] {
+            let response = service.call(request(method)).await.unwrap();
+            assert_eq!(grpc_status(&response), None);
+        }
+
+        let response = service.call(request("ValidateSession")).await.unwrap();
+        assert!(grpc_status(&response).is_some());
+    }
+}
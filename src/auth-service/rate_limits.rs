@@ -0,0 +1,239 @@
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+use prost::Message;
+use tonic::metadata::MetadataValue;
+use tonic::{Code, Status};
+
+/// Sign-in attempts each username can make back to back by default.
+pub const DEFAULT_SIGN_IN_BURST: u32 = 10;
+/// Sign-in attempts each username regains per minute by default.
+pub const DEFAULT_SIGN_IN_RATE_PER_MINUTE: u32 = 5;
+
+/// Buckets kept before full ones are dropped, so trying many usernames doesn't grow memory
+/// without bound.
+const PRUNE_THRESHOLD: usize = 10_000;
+
+/// How many attempts can be made at once and how quickly they are regained.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct RateLimit {
+    pub burst: u32,
+    pub per_minute: u32,
+}
+
+impl Default for RateLimit {
+    fn default() -> Self {
+        Self {
+            burst: DEFAULT_SIGN_IN_BURST,
+            per_minute: DEFAULT_SIGN_IN_RATE_PER_MINUTE,
+        }
+    }
+}
+
+pub trait RateLimiter {
+    /// Counts an attempt for `key`. Fails with how long until the next attempt is allowed if
+    /// `key` is over its limit.
+    fn check(&mut self, key: &str) -> Result<(), Duration>;
+}
+
+#[derive(Clone, Copy, Debug)]
+struct TokenBucket {
+    tokens: f64,
+    updated_at: Instant,
+}
+
+/// Gives each key a bucket of `burst` tokens, refilled continuously at `per_minute`. Every
+/// attempt takes a token.
+pub struct TokenBucketLimiter {
+    limit: RateLimit,
+    key_to_bucket: HashMap<String, TokenBucket>,
+}
+
+impl TokenBucketLimiter {
+    pub fn new(limit: RateLimit) -> Self {
+        Self {
+            limit,
+            key_to_bucket: HashMap::new(),
+        }
+    }
+
+    fn check_at(&mut self, key: &str, now: Instant) -> Result<(), Duration> {
+        if self.key_to_bucket.len() >= PRUNE_THRESHOLD {
+            let limit = self.limit;
+            self.key_to_bucket
+                .retain(|_, bucket| refilled(limit, bucket, now) < f64::from(limit.burst));
+        }
+
+        let limit = self.limit;
+        let bucket = self
+            .key_to_bucket
+            .entry(key.to_string())
+            .or_insert(TokenBucket {
+                tokens: f64::from(limit.burst),
+                updated_at: now,
+            });
+
+        bucket.tokens = refilled(limit, bucket, now);
+        bucket.updated_at = now;
+
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            return Ok(());
+        }
+
+        match limit.per_minute {
+            // Nothing is regained, so the bucket never refills.
+            0 => Err(Duration::MAX),
+            per_minute => Err(Duration::from_secs_f64(
+                (1.0 - bucket.tokens) * 60.0 / f64::from(per_minute),
+            )),
+        }
+    }
+}
+
+impl RateLimiter for TokenBucketLimiter {
+    fn check(&mut self, key: &str) -> Result<(), Duration> {
+        self.check_at(key, Instant::now())
+    }
+}
+
+/// `google.rpc.RetryInfo`, the standard error detail telling clients when to retry.
+#[derive(Clone, PartialEq, Message)]
+struct RetryInfo {
+    #[prost(message, optional, tag = "1")]
+    retry_delay: Option<prost_types::Duration>,
+}
+
+/// `google.rpc.Status`, which carries error details in `grpc-status-details-bin`.
+#[derive(Clone, PartialEq, Message)]
+struct RpcStatus {
+    #[prost(int32, tag = "1")]
+    code: i32,
+    #[prost(string, tag = "2")]
+    message: String,
+    #[prost(message, repeated, tag = "3")]
+    details: Vec<prost_types::Any>,
+}
+
+/// Rejects a throttled request as `RESOURCE_EXHAUSTED`, with a `retry-after` header in whole
+/// seconds, rounded up, and a `google.rpc.RetryInfo` detail for clients that understand it.
+pub fn rate_limited(message: &str, retry_after: Duration) -> Status {
+    let retry_info = RetryInfo {
+        retry_delay: Some(prost_types::Duration {
+            seconds: i64::try_from(retry_after.as_secs()).unwrap_or(i64::MAX),
+            nanos: retry_after.subsec_nanos() as i32,
+        }),
+    };
+    let details = RpcStatus {
+        code: Code::ResourceExhausted as i32,
+        message: message.to_owned(),
+        details: vec![prost_types::Any {
+            type_url: "type.googleapis.com/google.rpc.RetryInfo".to_owned(),
+            value: retry_info.encode_to_vec(),
+        }],
+    };
+
+    let mut status = Status::with_details(
+        Code::ResourceExhausted,
+        message,
+        details.encode_to_vec().into(),
+    );
+    let retry_after = retry_after.as_secs() + u64::from(retry_after.subsec_nanos() > 0);
+    status
+        .metadata_mut()
+        .insert("retry-after", MetadataValue::from(retry_after));
+    status
+}
+
+/// Tokens in `bucket` at `now`, capped at the burst.
+fn refilled(limit: RateLimit, bucket: &TokenBucket, now: Instant) -> f64 {
+    let elapsed = now.saturating_duration_since(bucket.updated_at);
+    let regained = elapsed.as_secs_f64() * f64::from(limit.per_minute) / 60.0;
+
+    (bucket.tokens + regained).min(f64::from(limit.burst))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn limiter(burst: u32, per_minute: u32) -> TokenBucketLimiter {
+        TokenBucketLimiter::new(RateLimit { burst, per_minute })
+    }
+
+    #[test]
+    fn should_allow_burst_then_throttle() {
+        let mut rate_limiter = limiter(3, 6);
+        let now = Instant::now();
+
+        for _ in 0..3 {
+            assert_eq!(rate_limiter.check_at("123456", now), Ok(()));
+        }
+
+        assert_eq!(
+            rate_limiter.check_at("123456", now),
+            Err(Duration::from_secs(10))
+        );
+        // Other keys have their own bucket.
+        assert_eq!(rate_limiter.check_at("654321", now), Ok(()));
+    }
+
+    #[test]
+    fn should_refill_over_time() {
+        let mut rate_limiter = limiter(2, 6);
+        let now = Instant::now();
+        rate_limiter.check_at("123456", now).unwrap();
+        rate_limiter.check_at("123456", now).unwrap();
+
+        let later = now + Duration::from_secs(5);
+        assert_eq!(
+            rate_limiter.check_at("123456", later),
+            Err(Duration::from_secs(5))
+        );
+
+        let later = now + Duration::from_secs(10);
+        assert_eq!(rate_limiter.check_at("123456", later), Ok(()));
+
+        // Refilling stops at the burst.
+        let much_later = later + Duration::from_secs(3600);
+        rate_limiter.check_at("123456", much_later).unwrap();
+        rate_limiter.check_at("123456", much_later).unwrap();
+        assert!(rate_limiter.check_at("123456", much_later).is_err());
+    }
+
+    #[test]
+    fn should_prune_full_buckets() {
+        let mut rate_limiter = limiter(1, 60);
+        let now = Instant::now();
+        for key in 0..PRUNE_THRESHOLD {
+            rate_limiter.check_at(&key.to_string(), now).unwrap();
+        }
+
+        let later = now + Duration::from_secs(1);
+        rate_limiter.check_at("123456", later).unwrap();
+
+        assert_eq!(rate_limiter.key_to_bucket.len(), 1);
+    }
+    #[test]
+    fn rate_limited_should_include_retry_info() {
+        let status = rate_limited("Too many requests", Duration::from_millis(1500));
+
+        assert_eq!(status.code(), Code::ResourceExhausted);
+        assert_eq!(status.metadata().get("retry-after").unwrap(), "2");
+
+        let details = RpcStatus::decode(status.details()).unwrap();
+        assert_eq!(details.code, Code::ResourceExhausted as i32);
+        assert_eq!(
+            details.details[0].type_url,
+            "type.googleapis.com/google.rpc.RetryInfo"
+        );
+        let retry_info = RetryInfo::decode(details.details[0].value.as_slice()).unwrap();
+        assert_eq!(
+            retry_info.retry_delay,
+            Some(prost_types::Duration {
+                seconds: 1,
+                nanos: 500_000_000,
+            })
+        );
+    }
+}
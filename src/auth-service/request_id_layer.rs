@@ -0,0 +1,119 @@
+use std::future::Future;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+use tower::{Layer, Service};
+use tracing::Span;
+
+use crate::request_id::{request_id, REQUEST_ID_HEADER};
+
+/// Identifies each request by the `x-request-id` metadata it came with, or a new ID if it had
+/// none. The ID is recorded on the RPC span, so it's on every log line, and sent back on every
+/// response, errors included. Handlers find it in the request metadata to pass on.
+#[derive(Clone, Default)]
+pub struct RequestIdLayer;
+
+impl<S> Layer<S> for RequestIdLayer {
+    type Service = RequestId<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        RequestId { inner }
+    }
+}
+
+#[derive(Clone)]
+pub struct RequestId<S> {
+    inner: S,
+}
+
+impl<S, B, ResBody> Service<http::Request<B>> for RequestId<S>
+where
+    S: Service<http::Request<B>, Response = http::Response<ResBody>>,
+    S::Future: Send + 'static,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, mut request: http::Request<B>) -> Self::Future {
+        let request_id = request_id(request.headers());
+        request
+            .headers_mut()
+            .insert(REQUEST_ID_HEADER, request_id.clone());
+        let response = self.inner.call(request);
+
+        Box::pin(async move {
+            // Responses are awaited in the RPC span, which only `call` runs outside of
+            if let Ok(request_id) = request_id.to_str() {
+                Span::current().record("request_id", request_id);
+            }
+
+            let mut response = response.await?;
+            response.headers_mut().insert(REQUEST_ID_HEADER, request_id);
+            Ok(response)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::convert::Infallible;
+
+    use super::*;
+
+    /// Answers with the request ID it was called with in the body.
+    #[derive(Clone)]
+    struct EchoRequestId;
+
+    impl Service<http::Request<()>> for EchoRequestId {
+        type Response = http::Response<String>;
+        type Error = Infallible;
+        type Future = std::future::Ready<Result<Self::Response, Self::Error>>;
+
+        fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+            Poll::Ready(Ok(()))
+        }
+
+        fn call(&mut self, request: http::Request<()>) -> Self::Future {
+            let request_id = request.headers()[REQUEST_ID_HEADER].to_str().unwrap();
+            std::future::ready(Ok(http::Response::new(request_id.to_owned())))
+        }
+    }
+
+    fn request(request_id: Option<&str>) -> http::Request<()> {
+        let mut request = http::Request::builder().uri("/authentication.Auth/SignIn");
+        if let Some(request_id) = request_id {
+            request = request.header(REQUEST_ID_HEADER, request_id);
+        }
+        request.body(()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn should_keep_caller_request_id() {
+        let mut service = RequestIdLayer.layer(EchoRequestId);
+
+        let response = service.call(request(Some("checkout-42"))).await.unwrap();
+        assert_eq!(response.body(), "checkout-42");
+        assert_eq!(response.headers()[REQUEST_ID_HEADER], "checkout-42");
+    }
+
+    #[tokio::test]
+    async fn should_generate_missing_request_id() {
+        let mut service = RequestIdLayer.layer(EchoRequestId);
+
+        let response = service.call(request(None)).await.unwrap();
+        assert!(uuid::Uuid::parse_str(response.body()).is_ok());
+        assert_eq!(
+            response.headers()[REQUEST_ID_HEADER],
+            response.body().as_str()
+        );
+
+        let too_long = "a".repeat(129);
+        let response = service.call(request(Some(&too_long))).await.unwrap();
+        assert_ne!(response.body(), &too_long);
+    }
+}
@@ -0,0 +1,380 @@
+use std::collections::HashMap;
+use std::fs;
+use std::io::ErrorKind;
+use std::path::PathBuf;
+use std::time::SystemTime;
+
+use crate::clock::{Clock, SystemClock};
+use crate::sessions::{from_millis, to_millis, SessionId};
+
+/// What invalidates tokens before they expire. Needed by backends whose tokens stay valid on their
+/// own, such as JWT sessions, which keep nothing else between requests.
+#[tonic::async_trait]
+pub trait Revocations {
+    /// Revokes `session_id` until `until`, after which its tokens have expired anyway.
+    async fn revoke(&mut self, session_id: SessionId, until: SystemTime);
+    async fn is_revoked(&self, session_id: SessionId) -> bool;
+    /// How many times all of the user's sessions have been revoked. Only tokens issued since carry
+    /// the current generation.
+    async fn generation(&self, user_uuid: &str) -> u32;
+    /// Revokes every token issued to the user so far by bumping their generation.
+    async fn revoke_user(&mut self, user_uuid: &str);
+    /// Makes `token_id` the only valid session token of `session_id` until `until`, e.g. once the
+    /// session has been refreshed.
+    async fn replace_tokens(&mut self, session_id: SessionId, token_id: String, until: SystemTime);
+    /// The token id that replaced the other session tokens of `session_id`, if any.
+    async fn replacement(&self, session_id: SessionId) -> Option<String>;
+    /// Forgets revocations and replacements that are no longer needed. Generations are kept, as
+    /// tokens carrying an older one would become valid again.
+    async fn remove_expired(&mut self);
+}
+
+pub struct RevocationsImpl {
+    session_id_to_expiry: HashMap<SessionId, SystemTime>,
+    uuid_to_generation: HashMap<String, u32>,
+    session_id_to_replacement: HashMap<SessionId, (String, SystemTime)>,
+    /// Tells which revocations and replacements have expired.
+    clock: Box<dyn Clock + Send + Sync>,
+}
+
+impl Default for RevocationsImpl {
+    fn default() -> Self {
+        Self {
+            session_id_to_expiry: HashMap::new(),
+            uuid_to_generation: HashMap::new(),
+            session_id_to_replacement: HashMap::new(),
+            clock: Box::new(SystemClock),
+        }
+    }
+}
+
+#[cfg_attr(not(feature = "jwt-sessions"), allow(dead_code))]
+impl RevocationsImpl {
+    /// Tells the time with `clock` instead of the system clock, so tests can outlast revocations
+    /// without waiting.
+    #[cfg(test)]
+    pub fn with_clock(mut self, clock: Box<dyn Clock + Send + Sync>) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    /// Serializes the revocations, replacements and generations for `restore`.
+    pub fn snapshot(&self) -> String {
+        let revoked = self
+            .session_id_to_expiry
+            .iter()
+            .map(|(session_id, expires_at)| {
+                ["revoked", &session_id.to_string(), &to_millis(*expires_at)].join("\t")
+            });
+        let replaced =
+            self.session_id_to_replacement
+                .iter()
+                .map(|(session_id, (token_id, expires_at))| {
+                    [
+                        "replaced",
+                        &session_id.to_string(),
+                        token_id,
+                        &to_millis(*expires_at),
+                    ]
+                    .join("\t")
+                });
+        let generations = self
+            .uuid_to_generation
+            .iter()
+            .map(|(user_uuid, generation)| {
+                ["generation", user_uuid, &generation.to_string()].join("\t")
+            });
+
+        revoked
+            .chain(replaced)
+            .chain(generations)
+            .map(|line| line + "\n")
+            .collect()
+    }
+
+    /// Loads the revocations, replacements and generations of a `snapshot`.
+    pub fn restore(&mut self, snapshot: &str) -> Result<(), String> {
+        for (number, line) in snapshot.lines().enumerate() {
+            let invalid = || format!("Error, invalid revocations snapshot line {}", number + 1);
+            let fields: Vec<&str> = line.split('\t').collect();
+
+            match fields.as_slice() {
+                [] | [""] => (),
+                ["revoked", session_id, expires_at] => {
+                    self.session_id_to_expiry.insert(
+                        session_id.parse().map_err(|_| invalid())?,
+                        from_millis(expires_at).ok_or_else(invalid)?,
+                    );
+                }
+                ["replaced", session_id, token_id, expires_at] => {
+                    self.session_id_to_replacement.insert(
+                        session_id.parse().map_err(|_| invalid())?,
+                        (
+                            token_id.to_string(),
+                            from_millis(expires_at).ok_or_else(invalid)?,
+                        ),
+                    );
+                }
+                ["generation", user_uuid, generation] => {
+                    self.uuid_to_generation.insert(
+                        user_uuid.to_string(),
+                        generation.parse().map_err(|_| invalid())?,
+                    );
+                }
+                _ => return Err(invalid()),
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[tonic::async_trait]
+impl Revocations for RevocationsImpl {
+    async fn revoke(&mut self, session_id: SessionId, until: SystemTime) {
+        let expires_at = self.session_id_to_expiry.entry(session_id).or_insert(until);
+
+        // Never shorten an existing revocation.
+        *expires_at = (*expires_at).max(until);
+    }
+
+    async fn is_revoked(&self, session_id: SessionId) -> bool {
+        self.session_id_to_expiry.contains_key(&session_id)
+    }
+
+    async fn generation(&self, user_uuid: &str) -> u32 {
+        self.uuid_to_generation
+            .get(user_uuid)
+            .copied()
+            .unwrap_or_default()
+    }
+
+    async fn revoke_user(&mut self, user_uuid: &str) {
+        *self
+            .uuid_to_generation
+            .entry(user_uuid.to_string())
+            .or_default() += 1;
+    }
+
+    async fn replace_tokens(&mut self, session_id: SessionId, token_id: String, until: SystemTime) {
+        self.session_id_to_replacement
+            .insert(session_id, (token_id, until));
+    }
+
+    async fn replacement(&self, session_id: SessionId) -> Option<String> {
+        self.session_id_to_replacement
+            .get(&session_id)
+            .map(|(token_id, _)| token_id.clone())
+    }
+
+    async fn remove_expired(&mut self) {
+        let now = self.clock.now();
+
+        self.session_id_to_expiry
+            .retain(|_, expires_at| *expires_at > now);
+        self.session_id_to_replacement
+            .retain(|_, (_, expires_at)| *expires_at > now);
+    }
+}
+
+/// Keeps revocations in memory like `RevocationsImpl`, writing them through to a file so signed
+/// out JWT sessions don't become valid again on restart.
+#[cfg_attr(not(feature = "jwt-sessions"), allow(dead_code))]
+pub struct FileRevocations {
+    revocations: RevocationsImpl,
+    path: PathBuf,
+}
+
+#[cfg_attr(not(feature = "jwt-sessions"), allow(dead_code))]
+impl FileRevocations {
+    /// Loads the revocations saved at `path`. A missing file starts out empty.
+    pub fn open(path: impl Into<PathBuf>) -> Result<Self, String> {
+        let path = path.into();
+        let mut revocations = RevocationsImpl::default();
+
+        match fs::read_to_string(&path) {
+            Ok(snapshot) => revocations.restore(&snapshot)?,
+            Err(err) if err.kind() == ErrorKind::NotFound => (),
+            Err(err) => {
+                return Err(format!(
+                    "Error reading revocations from {}: {}",
+                    path.display(),
+                    err
+                ))
+            }
+        }
+
+        Ok(Self { revocations, path })
+    }
+
+    /// Replaces the file with the current revocations, see `FileSessions::persist`.
+    async fn persist(&self) {
+        let snapshot = self.revocations.snapshot();
+        let path = self.path.clone();
+
+        let result = tokio::task::spawn_blocking(move || {
+            let temp_path = path.with_extension("tmp");
+            fs::write(&temp_path, snapshot).and_then(|_| fs::rename(&temp_path, &path))
+        })
+        .await
+        .unwrap_or_else(|err| Err(err.into()));
+
+        if let Err(err) = result {
+            tracing::error!(path = %self.path.display(), error = %err, "Failed to write revocations");
+        }
+    }
+}
+
+#[tonic::async_trait]
+impl Revocations for FileRevocations {
+    async fn revoke(&mut self, session_id: SessionId, until: SystemTime) {
+        self.revocations.revoke(session_id, until).await;
+        self.persist().await;
+    }
+
+    async fn is_revoked(&self, session_id: SessionId) -> bool {
+        self.revocations.is_revoked(session_id).await
+    }
+
+    async fn generation(&self, user_uuid: &str) -> u32 {
+        self.revocations.generation(user_uuid).await
+    }
+
+    async fn revoke_user(&mut self, user_uuid: &str) {
+        self.revocations.revoke_user(user_uuid).await;
+        self.persist().await;
+    }
+
+    async fn replace_tokens(&mut self, session_id: SessionId, token_id: String, until: SystemTime) {
+        self.revocations
+            .replace_tokens(session_id, token_id, until)
+            .await;
+        self.persist().await;
+    }
+
+    async fn replacement(&self, session_id: SessionId) -> Option<String> {
+        self.revocations.replacement(session_id).await
+    }
+
+    async fn remove_expired(&mut self) {
+        self.revocations.remove_expired().await;
+        self.persist().await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use uuid::Uuid;
+
+    use super::*;
+    use crate::clock::ManualClock;
+
+    const FIRST: SessionId = SessionId::new(Uuid::from_u128(1));
+    const SECOND: SessionId = SessionId::new(Uuid::from_u128(2));
+
+    #[tokio::test]
+    async fn should_revoke_session_id() {
+        let mut revocations_service = RevocationsImpl::default();
+        revocations_service
+            .revoke(FIRST, SystemTime::now() + Duration::from_secs(60))
+            .await;
+
+        assert!(revocations_service.is_revoked(FIRST).await);
+        assert!(!revocations_service.is_revoked(SECOND).await);
+    }
+
+    #[tokio::test]
+    async fn should_forget_expired_revocations() {
+        let clock = ManualClock::new(SystemTime::now());
+        let mut revocations_service =
+            RevocationsImpl::default().with_clock(Box::new(clock.clone()));
+        revocations_service
+            .revoke(FIRST, clock.now() + Duration::from_secs(30))
+            .await;
+        revocations_service
+            .revoke(SECOND, clock.now() + Duration::from_secs(60))
+            .await;
+
+        clock.advance(Duration::from_secs(30));
+        revocations_service.remove_expired().await;
+
+        assert!(!revocations_service.is_revoked(FIRST).await);
+        assert!(revocations_service.is_revoked(SECOND).await);
+    }
+
+    #[tokio::test]
+    async fn should_not_shorten_revocation() {
+        let mut revocations_service = RevocationsImpl::default();
+        revocations_service
+            .revoke(FIRST, SystemTime::now() + Duration::from_secs(60))
+            .await;
+        revocations_service
+            .revoke(FIRST, SystemTime::now() - Duration::from_secs(1))
+            .await;
+
+        revocations_service.remove_expired().await;
+
+        assert!(revocations_service.is_revoked(FIRST).await);
+    }
+
+    #[tokio::test]
+    async fn should_keep_generations_and_forget_expired_replacements() {
+        let mut revocations_service = RevocationsImpl::default();
+        revocations_service.revoke_user("123456").await;
+        revocations_service
+            .replace_tokens(
+                FIRST,
+                "first".to_owned(),
+                SystemTime::now() - Duration::from_secs(1),
+            )
+            .await;
+        revocations_service
+            .replace_tokens(
+                SECOND,
+                "second".to_owned(),
+                SystemTime::now() + Duration::from_secs(60),
+            )
+            .await;
+
+        revocations_service.remove_expired().await;
+
+        assert_eq!(revocations_service.generation("123456").await, 1);
+        assert_eq!(revocations_service.generation("654321").await, 0);
+        assert_eq!(revocations_service.replacement(FIRST).await, None);
+        assert_eq!(
+            revocations_service.replacement(SECOND).await.as_deref(),
+            Some("second")
+        );
+    }
+
+    #[tokio::test]
+    async fn should_keep_revocations_across_restarts() {
+        let path = std::env::temp_dir().join(format!("revocations-{}", Uuid::new_v4()));
+
+        let mut revocations_service = FileRevocations::open(&path).unwrap();
+        revocations_service
+            .revoke(FIRST, SystemTime::now() + Duration::from_secs(60))
+            .await;
+        revocations_service.revoke_user("123456").await;
+        revocations_service
+            .replace_tokens(
+                SECOND,
+                "second".to_owned(),
+                SystemTime::now() + Duration::from_secs(60),
+            )
+            .await;
+
+        let revocations_service = FileRevocations::open(&path).unwrap();
+
+        assert!(revocations_service.is_revoked(FIRST).await);
+        assert_eq!(revocations_service.generation("123456").await, 1);
+        assert_eq!(
+            revocations_service.replacement(SECOND).await.as_deref(),
+            Some("second")
+        );
+
+        std::fs::remove_file(path).unwrap();
+    }
+}
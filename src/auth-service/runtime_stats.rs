@@ -0,0 +1,142 @@
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use parking_lot::Mutex;
+use tokio::task::{AbortHandle, JoinHandle};
+
+use crate::auth::authentication::admin::v1::{BackgroundTaskInfo, GetStatsResponse};
+use crate::auth::AuthService;
+use crate::error::AuthError;
+use crate::metrics::RpcMetrics;
+
+/// Cargo features of the auth service and whether it was built with them.
+const FEATURES: [(&str, bool); 11] = [
+    ("argon2", cfg!(feature = "argon2")),
+    ("breached-passwords", cfg!(feature = "breached-passwords")),
+    ("json-logs", cfg!(feature = "json-logs")),
+    ("jwt-sessions", cfg!(feature = "jwt-sessions")),
+    ("ldap", cfg!(feature = "ldap")),
+    ("oidc", cfg!(feature = "oidc")),
+    ("otel", cfg!(feature = "otel")),
+    ("postgres", cfg!(feature = "postgres")),
+    ("sentry", cfg!(feature = "sentry")),
+    ("sqlite", cfg!(feature = "sqlite")),
+    ("tls", cfg!(feature = "tls")),
+];
+
+/// What `GetStats` reports besides the backends' counts: requests in flight, whether the
+/// background tasks are still running, and how the service was built.
+pub struct RuntimeStats {
+    started_at: SystemTime,
+    rpc_metrics: Arc<RpcMetrics>,
+    tasks: Mutex<Vec<(&'static str, AbortHandle)>>,
+}
+
+impl RuntimeStats {
+    pub fn new(rpc_metrics: Arc<RpcMetrics>) -> Self {
+        Self {
+            started_at: SystemTime::now(),
+            rpc_metrics,
+            tasks: Mutex::new(Vec::new()),
+        }
+    }
+
+    /// Reports `task` as `name`, running until it ends.
+    pub fn watch_task(&self, name: &'static str, task: &JoinHandle<()>) {
+        self.tasks.lock().push((name, task.abort_handle()));
+    }
+
+    pub async fn report(&self, auth_service: &AuthService) -> Result<GetStatsResponse, AuthError> {
+        let (user_count, active_sessions) = auth_service.count_users_and_sessions().await?;
+
+        let background_tasks = self
+            .tasks
+            .lock()
+            .iter()
+            .map(|(name, task)| BackgroundTaskInfo {
+                name: name.to_string(),
+                running: !task.is_finished(),
+            })
+            .collect();
+
+        Ok(GetStatsResponse {
+            user_count: user_count as u64,
+            active_sessions: active_sessions.map_or(-1, |sessions| sessions as i64),
+            in_flight_requests: self.rpc_metrics.in_flight() as u64,
+            background_tasks,
+            version: env!("CARGO_PKG_VERSION").to_owned(),
+            features: FEATURES
+                .iter()
+                .filter(|(_, enabled)| *enabled)
+                .map(|(feature, _)| feature.to_string())
+                .collect(),
+            started_at: self
+                .started_at
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::sync::RwLock;
+
+    use super::*;
+    use crate::sessions::{ClientMetadata, Sessions, SessionsImpl};
+    use crate::users::{Users, UsersImpl};
+
+    #[tokio::test]
+    async fn should_report_runtime_stats() {
+        let mut users_service = UsersImpl::default();
+        users_service
+            .create_user(
+                "alice".to_owned(),
+                "password".to_owned(),
+                "alice@example.com".to_owned(),
+            )
+            .await
+            .unwrap();
+        let mut sessions_service = SessionsImpl::default();
+        sessions_service
+            .create_session("123456", ClientMetadata::default())
+            .await
+            .unwrap();
+        let auth_service = AuthService::new(
+            Box::new(RwLock::new(users_service)),
+            Box::new(RwLock::new(sessions_service)),
+        );
+
+        let rpc_metrics = Arc::new(RpcMetrics::default());
+        let runtime_stats = RuntimeStats::new(rpc_metrics.clone());
+        let finished_task = tokio::spawn(async {});
+        while !finished_task.is_finished() {
+            tokio::task::yield_now().await;
+        }
+        let running_task = tokio::spawn(std::future::pending());
+        runtime_stats.watch_task("finished", &finished_task);
+        runtime_stats.watch_task("running", &running_task);
+        let _in_flight = rpc_metrics.start();
+
+        let stats = runtime_stats.report(&auth_service).await.unwrap();
+        assert_eq!(stats.user_count, 1);
+        assert_eq!(stats.active_sessions, 1);
+        assert_eq!(stats.in_flight_requests, 1);
+        assert_eq!(
+            stats.background_tasks,
+            [
+                BackgroundTaskInfo {
+                    name: "finished".to_owned(),
+                    running: false,
+                },
+                BackgroundTaskInfo {
+                    name: "running".to_owned(),
+                    running: true,
+                },
+            ]
+        );
+        assert_eq!(stats.version, env!("CARGO_PKG_VERSION"));
+        running_task.abort();
+    }
+}
@@ -0,0 +1,67 @@
+use std::fmt;
+
+/// Shown in place of a secret's value.
+const REDACTED: &str = "[REDACTED]";
+
+/// A password, token or key that `Debug` and `Display` don't reveal, so it can't end up in logs
+/// or error messages by accident. `expose` gets the value where it's actually needed.
+#[derive(Clone, Default, PartialEq, Eq)]
+pub struct Secret(String);
+
+impl Secret {
+    pub fn new(value: impl Into<String>) -> Self {
+        Self(value.into())
+    }
+
+    pub fn expose(&self) -> &str {
+        &self.0
+    }
+
+    pub fn into_exposed(self) -> String {
+        self.0
+    }
+}
+
+impl From<String> for Secret {
+    fn from(value: String) -> Self {
+        Self(value)
+    }
+}
+
+impl From<&str> for Secret {
+    fn from(value: &str) -> Self {
+        Self(value.to_owned())
+    }
+}
+
+impl fmt::Debug for Secret {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // Unset secrets are shown as such, it doesn't give anything away
+        match self.0.is_empty() {
+            true => f.write_str("\"\""),
+            false => f.write_str(REDACTED),
+        }
+    }
+}
+
+impl fmt::Display for Secret {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(REDACTED)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn should_not_format_value() {
+        let secret = Secret::new("hunter2");
+        assert_eq!(format!("{:?}", secret), "[REDACTED]");
+        assert_eq!(format!("{:#?}", Some(&secret)), "Some(\n    [REDACTED],\n)");
+        assert_eq!(secret.to_string(), "[REDACTED]");
+        assert_eq!(secret.expose(), "hunter2");
+
+        assert_eq!(format!("{:?}", Secret::default()), "\"\"");
+    }
+}
@@ -0,0 +1,328 @@
+use std::collections::BTreeSet;
+
+use crate::auth::AuthService;
+use crate::secret::Secret;
+use crate::settings::{parse_value, strip_comment};
+use crate::users::{ImportedPassword, ImportedUser};
+
+/// Accounts and groups to start with, e.g. for local development and demos, read by `read_seed`.
+#[derive(Debug, Default, PartialEq)]
+pub struct Seed {
+    /// Groups to create even without members.
+    pub groups: Vec<String>,
+    pub users: Vec<SeedUser>,
+}
+
+#[derive(Debug, Default, PartialEq)]
+pub struct SeedUser {
+    pub username: String,
+    pub password: Secret,
+    pub email: String,
+    /// Defaults to the username.
+    pub display_name: Option<String>,
+    /// Created if they don't exist yet.
+    pub groups: Vec<String>,
+}
+
+/// What `Seed::load` created.
+#[derive(Debug, PartialEq, Eq)]
+pub struct Seeded {
+    pub users: usize,
+    /// Users whose username was taken, e.g. by an earlier run with a database backend.
+    pub existing_users: usize,
+    pub groups: usize,
+}
+
+/// Reads the seed file at `path`, as JSON if it ends in `.json` and TOML otherwise.
+pub fn read_seed(path: &str) -> Result<Seed, String> {
+    let contents =
+        std::fs::read_to_string(path).map_err(|e| format!("Failed to read {path}.\n{e:?}"))?;
+    let seed = match path.ends_with(".json") {
+        #[cfg(feature = "json")]
+        true => json::from_json(&contents),
+        #[cfg(not(feature = "json"))]
+        true => return Err("JSON seed files require the `json` feature".into()),
+        false => from_toml(&contents),
+    };
+    seed.map_err(|e| format!("Error, invalid seed file {path}: {e}"))
+}
+
+/// Reads users as `[[users]]` tables with `username`, `password`, `email`, `display_name` and
+/// `groups` keys, and groups to create without members as a top level `groups` array.
+fn from_toml(contents: &str) -> Result<Seed, String> {
+    let mut seed = Seed::default();
+
+    for (number, line) in contents.lines().enumerate() {
+        let line = strip_comment(line).trim();
+        let line_error = |e: &str| format!("line {}: {}", number + 1, e);
+
+        if line.is_empty() {
+            continue;
+        }
+        if line == "[[users]]" {
+            seed.users.push(SeedUser::default());
+            continue;
+        }
+        if line.starts_with('[') {
+            return Err(line_error("expected [[users]]"));
+        }
+
+        let (key, value) = line
+            .split_once('=')
+            .ok_or(line_error("expected key = value"))?;
+        let value = value.trim();
+        let string = || parse_value(value).map_err(|e| line_error(&e));
+        let array = || parse_array(value).map_err(|e| line_error(&e));
+
+        match (seed.users.last_mut(), key.trim()) {
+            (None, "groups") => seed.groups = array()?,
+            (Some(user), "username") => user.username = string()?,
+            (Some(user), "password") => user.password = Secret::new(string()?),
+            (Some(user), "email") => user.email = string()?,
+            (Some(user), "display_name") => user.display_name = Some(string()?),
+            (Some(user), "groups") => user.groups = array()?,
+            (_, key) => return Err(line_error(&format!("unknown key {key}"))),
+        }
+    }
+
+    check(seed)
+}
+
+/// Reads a single line array of strings.
+fn parse_array(value: &str) -> Result<Vec<String>, String> {
+    value
+        .strip_prefix('[')
+        .and_then(|array| array.strip_suffix(']'))
+        .ok_or("expected an array")?
+        .split(',')
+        .map(str::trim)
+        .filter(|item| !item.is_empty())
+        .map(parse_value)
+        .collect()
+}
+
+fn check(seed: Seed) -> Result<Seed, String> {
+    for (index, user) in seed.users.iter().enumerate() {
+        if user.username.is_empty() || user.password.expose().is_empty() {
+            return Err(format!("user {} needs a username and password", index + 1));
+        }
+    }
+    Ok(seed)
+}
+
+impl Seed {
+    /// Creates the groups and the users whose username isn't taken, then adds the users to their
+    /// groups, existing ones included since groups aren't persisted. Passwords are hashed like
+    /// any other but not checked against the password policy, so demo accounts can have simple
+    /// ones.
+    pub async fn load(self, auth_service: &AuthService) -> Result<Seeded, String> {
+        let groups: BTreeSet<&String> = self
+            .groups
+            .iter()
+            .chain(self.users.iter().flat_map(|user| &user.groups))
+            .collect();
+        let mut groups_service = auth_service.groups().lock().await;
+        for name in &groups {
+            groups_service
+                .create_group(name)
+                .map_err(|e| format!("Failed to create group {name}.\n{e}"))?;
+        }
+        drop(groups_service);
+
+        let mut seeded = Seeded {
+            users: 0,
+            existing_users: 0,
+            groups: groups.len(),
+        };
+        let mut users_service = auth_service.users().write().await;
+        for user in self.users {
+            let user_uuid = match users_service.lookup_user_uuid(user.username.clone()).await {
+                Some(user_uuid) => {
+                    seeded.existing_users += 1;
+                    user_uuid
+                }
+                None => {
+                    let imported = ImportedUser {
+                        user_uuid: None,
+                        username: user.username.clone(),
+                        email: user.email,
+                        display_name: user.display_name,
+                        created_at: None,
+                        password: ImportedPassword::Plaintext(user.password),
+                    };
+                    if let Some(Err(e)) = users_service.import_users(vec![imported]).await.pop() {
+                        return Err(format!("Failed to seed user {}.\n{e:?}", user.username));
+                    }
+                    seeded.users += 1;
+                    users_service
+                        .lookup_user_uuid(user.username.clone())
+                        .await
+                        .ok_or(format!("Failed to seed user {}", user.username))?
+                }
+            };
+
+            let mut groups_service = auth_service.groups().lock().await;
+            for name in &user.groups {
+                groups_service
+                    .add_member(name, &user_uuid)
+                    .map_err(|e| format!("Failed to add {} to {name}.\n{e}", user.username))?;
+            }
+        }
+
+        Ok(seeded)
+    }
+}
+
+#[cfg(feature = "json")]
+mod json {
+    use serde_json::{Map, Value};
+
+    use super::{check, Seed, SeedUser};
+    use crate::secret::Secret;
+
+    /// Reads an object with a `users` array of objects keyed like the TOML tables, and a
+    /// `groups` array.
+    pub fn from_json(json: &str) -> Result<Seed, String> {
+        let value: Value =
+            serde_json::from_str(json).map_err(|e| format!("Failed to parse JSON.\n{e:?}"))?;
+        let seed = value.as_object().ok_or("expected an object")?;
+
+        let users = match seed.get("users") {
+            None => Vec::new(),
+            Some(users) => users
+                .as_array()
+                .ok_or("expected an array of users")?
+                .iter()
+                .map(|user| {
+                    let user = user.as_object().ok_or("expected a user object")?;
+                    Ok(SeedUser {
+                        username: string_field(user, "username"),
+                        password: Secret::new(string_field(user, "password")),
+                        email: string_field(user, "email"),
+                        display_name: user
+                            .get("display_name")
+                            .and_then(Value::as_str)
+                            .map(str::to_owned),
+                        groups: string_array(user, "groups")?,
+                    })
+                })
+                .collect::<Result<_, String>>()?,
+        };
+
+        check(Seed {
+            groups: string_array(seed, "groups")?,
+            users,
+        })
+    }
+
+    fn string_field(object: &Map<String, Value>, key: &str) -> String {
+        object
+            .get(key)
+            .and_then(Value::as_str)
+            .unwrap_or_default()
+            .to_owned()
+    }
+
+    fn string_array(object: &Map<String, Value>, key: &str) -> Result<Vec<String>, String> {
+        match object.get(key) {
+            None => Ok(Vec::new()),
+            Some(array) => array
+                .as_array()
+                .and_then(|array| {
+                    array
+                        .iter()
+                        .map(|item| item.as_str().map(str::to_owned))
+                        .collect()
+                })
+                .ok_or(format!("expected {key} to be an array of strings")),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::sync::RwLock;
+
+    use super::*;
+    use crate::sessions::SessionsImpl;
+    use crate::users::UsersImpl;
+
+    const SEED: &str = r#"
+        # Demo accounts
+        groups = ["support"]
+
+        [[users]]
+        username = "alice"
+        password = "alice-password"
+        email = "alice@example.com"
+        display_name = "Alice"
+        groups = ["admins", "support"]
+
+        [[users]]
+        username = "bob"
+        password = 'bob-password'
+    "#;
+
+    #[test]
+    fn should_read_toml_seed() {
+        let seed = from_toml(SEED).unwrap();
+
+        assert_eq!(seed.groups, ["support"]);
+        assert_eq!(seed.users.len(), 2);
+        assert_eq!(seed.users[0].display_name.as_deref(), Some("Alice"));
+        assert_eq!(seed.users[0].groups, ["admins", "support"]);
+        assert_eq!(seed.users[1].password.expose(), "bob-password");
+        assert!(seed.users[1].groups.is_empty());
+
+        assert!(from_toml("[[users]]\nusername = \"carol\"").is_err());
+        assert!(from_toml("[users]").is_err());
+        assert!(from_toml("[[users]]\nrole = \"admin\"").is_err());
+    }
+
+    #[tokio::test]
+    async fn should_load_seed_keeping_existing_users() {
+        let auth_service = AuthService::new(
+            Box::new(RwLock::new(UsersImpl::default())),
+            Box::new(RwLock::new(SessionsImpl::default())),
+        );
+        auth_service
+            .users()
+            .write()
+            .await
+            .create_user(
+                "bob".to_owned(),
+                "existing-password".to_owned(),
+                "".to_owned(),
+            )
+            .await
+            .unwrap();
+
+        let seeded = from_toml(SEED).unwrap().load(&auth_service).await.unwrap();
+        assert_eq!(
+            seeded,
+            Seeded {
+                users: 1,
+                existing_users: 1,
+                groups: 2,
+            }
+        );
+
+        let users_service = auth_service.users().read().await;
+        let alice = users_service
+            .verify_password("alice".to_owned(), "alice-password".to_owned())
+            .await
+            .unwrap();
+        assert!(users_service
+            .verify_password("bob".to_owned(), "existing-password".to_owned())
+            .await
+            .is_some());
+        assert_eq!(
+            auth_service
+                .groups()
+                .lock()
+                .await
+                .user_groups(&alice.user_uuid),
+            ["admins", "support"]
+        );
+    }
+}
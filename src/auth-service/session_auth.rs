@@ -0,0 +1,216 @@
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+
+use http::header::AUTHORIZATION;
+use tonic::body::BoxBody;
+use tonic::Status;
+use tower::{Layer, Service};
+
+use crate::auth::AuthService;
+use crate::secret::Secret;
+
+/// The user signed in with the request's bearer session token, added to the request and response
+/// extensions by `SessionAuthLayer`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct AuthenticatedUser {
+    pub user_uuid: String,
+    pub session_token: Secret,
+}
+
+/// Validates `authorization: Bearer <session token>` request metadata, adding the signed in
+/// user to the request as an `AuthenticatedUser`. Requests with an invalid token are rejected as
+/// unauthenticated, requests without one are passed on for handlers to decide.
+#[derive(Clone)]
+pub struct SessionAuthLayer {
+    auth_service: Arc<AuthService>,
+}
+
+impl SessionAuthLayer {
+    pub fn new(auth_service: Arc<AuthService>) -> Self {
+        Self { auth_service }
+    }
+}
+
+impl<S> Layer<S> for SessionAuthLayer {
+    type Service = SessionAuth<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        SessionAuth {
+            inner,
+            auth_service: self.auth_service.clone(),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct SessionAuth<S> {
+    inner: S,
+    auth_service: Arc<AuthService>,
+}
+
+impl<S, B> Service<http::Request<B>> for SessionAuth<S>
+where
+    S: Service<http::Request<B>, Response = http::Response<BoxBody>> + Clone + Send + 'static,
+    S::Future: Send + 'static,
+    B: Send + 'static,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, mut request: http::Request<B>) -> Self::Future {
+        // The clone may not be ready, so call the instance `poll_ready` was called on.
+        let clone = self.inner.clone();
+        let mut inner = std::mem::replace(&mut self.inner, clone);
+        let auth_service = self.auth_service.clone();
+
+        Box::pin(async move {
+            let session_token = match bearer_token(&request) {
+                Some(session_token) => session_token,
+                None => return inner.call(request).await,
+            };
+
+            match auth_service.session_user_uuid(session_token.expose()).await {
+                Some(user_uuid) => {
+                    let user = AuthenticatedUser {
+                        user_uuid,
+                        session_token,
+                    };
+                    request.extensions_mut().insert(user.clone());
+                    let mut response = inner.call(request).await?;
+                    response.extensions_mut().insert(user);
+                    Ok(response)
+                }
+                None => Ok(Status::unauthenticated("Invalid session token").to_http()),
+            }
+        })
+    }
+}
+
+fn bearer_token<B>(request: &http::Request<B>) -> Option<Secret> {
+    let authorization = request.headers().get(AUTHORIZATION)?.to_str().ok()?;
+    let (scheme, token) = authorization.split_once(' ')?;
+
+    scheme
+        .eq_ignore_ascii_case("bearer")
+        .then(|| Secret::new(token.trim()))
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::sync::RwLock;
+
+    use super::*;
+    use crate::sessions::{ClientMetadata, Sessions, SessionsImpl};
+    use crate::users::UsersImpl;
+
+    /// Answers with the authenticated user's uuid in a header, empty without one.
+    #[derive(Clone)]
+    struct EchoUser;
+
+    impl Service<http::Request<()>> for EchoUser {
+        type Response = http::Response<BoxBody>;
+        type Error = std::convert::Infallible;
+        type Future = std::future::Ready<Result<Self::Response, Self::Error>>;
+
+        fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+            Poll::Ready(Ok(()))
+        }
+
+        fn call(&mut self, request: http::Request<()>) -> Self::Future {
+            let user_uuid = request
+                .extensions()
+                .get::<AuthenticatedUser>()
+                .map(|user| user.user_uuid.clone())
+                .unwrap_or_default();
+
+            let mut response = http::Response::new(tonic::body::empty_body());
+            response
+                .headers_mut()
+                .insert("user-uuid", user_uuid.parse().unwrap());
+            std::future::ready(Ok(response))
+        }
+    }
+
+    async fn auth_service_with_session() -> (Arc<AuthService>, String) {
+        let mut sessions_service = SessionsImpl::default();
+        let session_token = sessions_service
+            .create_session("123456", ClientMetadata::default())
+            .await
+            .unwrap();
+
+        let auth_service = AuthService::new(
+            Box::new(RwLock::new(UsersImpl::default())),
+            Box::new(RwLock::new(sessions_service)),
+        );
+        (Arc::new(auth_service), session_token)
+    }
+
+    fn request(authorization: Option<&str>) -> http::Request<()> {
+        let mut request = http::Request::new(());
+        if let Some(authorization) = authorization {
+            request
+                .headers_mut()
+                .insert(AUTHORIZATION, authorization.parse().unwrap());
+        }
+        request
+    }
+
+    #[tokio::test]
+    async fn should_add_authenticated_user() {
+        let (auth_service, session_token) = auth_service_with_session().await;
+        let mut service = SessionAuthLayer::new(auth_service).layer(EchoUser);
+
+        let response = service
+            .call(request(Some(&format!("Bearer {session_token}"))))
+            .await
+            .unwrap();
+
+        assert_eq!(response.headers()["user-uuid"], "123456");
+        // Outer layers, e.g. the access log, learn who it was from the response.
+        assert_eq!(
+            response
+                .extensions()
+                .get::<AuthenticatedUser>()
+                .unwrap()
+                .user_uuid,
+            "123456"
+        );
+    }
+
+    #[tokio::test]
+    async fn should_pass_on_requests_without_token() {
+        let (auth_service, _) = auth_service_with_session().await;
+        let mut service = SessionAuthLayer::new(auth_service).layer(EchoUser);
+
+        let response = service.call(request(None)).await.unwrap();
+        assert_eq!(response.headers()["user-uuid"], "");
+
+        // Other schemes are left for handlers too.
+        let response = service
+            .call(request(Some("Basic dXNlcjpwYXNz")))
+            .await
+            .unwrap();
+        assert_eq!(response.headers()["user-uuid"], "");
+    }
+
+    #[tokio::test]
+    async fn should_reject_invalid_token() {
+        let (auth_service, _) = auth_service_with_session().await;
+        let mut service = SessionAuthLayer::new(auth_service).layer(EchoUser);
+
+        let response = service.call(request(Some("Bearer invalid"))).await.unwrap();
+
+        assert_eq!(
+            response.headers()["grpc-status"],
+            (tonic::Code::Unauthenticated as i32).to_string()
+        );
+        assert!(response.headers().get("user-uuid").is_none());
+    }
+}
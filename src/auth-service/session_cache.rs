@@ -0,0 +1,363 @@
+use std::num::NonZeroUsize;
+use std::time::{Duration, SystemTime};
+
+use lru::LruCache;
+use parking_lot::Mutex;
+use tokio::sync::RwLock;
+
+use crate::clock::{Clock, SystemClock};
+use crate::error::SessionError;
+use crate::sessions::{ClientMetadata, Session, SessionId, Sessions};
+
+struct CachedSession {
+    session: Session,
+    cached_at: SystemTime,
+}
+
+/// Answers `get_session` from an in-process LRU cache for up to `ttl` after asking `sessions`,
+/// so high-QPS validations don't each make a round-trip to a database backed store.
+///
+/// Sessions are forgotten when they're signed out, deleted or replaced through this store. Other
+/// replicas' caches aren't told, so a session signed out elsewhere stays valid here for up to
+/// `ttl`. Validations answered from the cache don't record `last_seen` or slide the expiry.
+pub struct CachedSessions {
+    sessions: Box<RwLock<dyn Sessions + Send + Sync>>,
+    cache: Mutex<LruCache<String, CachedSession>>,
+    ttl: Duration,
+    clock: Box<dyn Clock + Send + Sync>,
+}
+
+impl CachedSessions {
+    pub fn new(
+        sessions: Box<RwLock<dyn Sessions + Send + Sync>>,
+        capacity: NonZeroUsize,
+        ttl: Duration,
+    ) -> Self {
+        Self {
+            sessions,
+            cache: Mutex::new(LruCache::new(capacity)),
+            ttl,
+            clock: Box::new(SystemClock),
+        }
+    }
+
+    /// Tells when validations were cached from `clock`, so tests can move past the TTL.
+    #[cfg(test)]
+    pub fn with_clock(mut self, clock: Box<dyn Clock + Send + Sync>) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    /// Forgets the sessions matching `predicate`.
+    fn forget(&self, predicate: impl Fn(&Session) -> bool) {
+        let mut cache = self.cache.lock();
+        let session_tokens: Vec<String> = cache
+            .iter()
+            .filter(|(_, cached)| predicate(&cached.session))
+            .map(|(session_token, _)| session_token.clone())
+            .collect();
+        for session_token in session_tokens {
+            cache.pop(&session_token);
+        }
+    }
+}
+
+#[tonic::async_trait]
+impl Sessions for CachedSessions {
+    async fn create_session(
+        &mut self,
+        user_uuid: &str,
+        client: ClientMetadata,
+    ) -> Result<String, SessionError> {
+        self.sessions
+            .write()
+            .await
+            .create_session(user_uuid, client)
+            .await
+    }
+
+    async fn get_session(&self, session_token: &str) -> Option<Session> {
+        let now = self.clock.now();
+        {
+            let mut cache = self.cache.lock();
+            match cache.get(session_token) {
+                Some(cached)
+                    if now < cached.cached_at + self.ttl && !cached.session.is_expired(now) =>
+                {
+                    return Some(cached.session.clone())
+                }
+                Some(_) => {
+                    cache.pop(session_token);
+                }
+                None => (),
+            }
+        }
+
+        // Only valid sessions are cached, so tokens that were never valid always reach the store.
+        let session = self
+            .sessions
+            .read()
+            .await
+            .get_session(session_token)
+            .await?;
+        self.cache.lock().put(
+            session_token.to_owned(),
+            CachedSession {
+                session: session.clone(),
+                cached_at: now,
+            },
+        );
+        Some(session)
+    }
+
+    async fn peek_session(&self, session_token: &str) -> Option<Session> {
+        self.sessions.read().await.peek_session(session_token).await
+    }
+
+    async fn list_user_sessions(&self, user_uuid: &str) -> Result<Vec<Session>, SessionError> {
+        self.sessions
+            .read()
+            .await
+            .list_user_sessions(user_uuid)
+            .await
+    }
+
+    async fn count_sessions(&self) -> Result<Option<usize>, SessionError> {
+        self.sessions.read().await.count_sessions().await
+    }
+
+    async fn delete_session(&mut self, session_token: &str) -> Option<SessionId> {
+        let session_id = self
+            .sessions
+            .write()
+            .await
+            .delete_session(session_token)
+            .await;
+        // A refresh token ends a session cached under another token.
+        match session_id {
+            Some(session_id) => self.forget(|session| session.session_id == session_id),
+            None => {
+                self.cache.lock().pop(session_token);
+            }
+        }
+        session_id
+    }
+
+    async fn delete_user_session(&mut self, user_uuid: &str, session_id: SessionId) {
+        self.sessions
+            .write()
+            .await
+            .delete_user_session(user_uuid, session_id)
+            .await;
+        self.forget(|session| session.session_id == session_id);
+    }
+
+    async fn delete_user_sessions(&mut self, user_uuid: &str) {
+        self.sessions
+            .write()
+            .await
+            .delete_user_sessions(user_uuid)
+            .await;
+        self.forget(|session| session.user_uuid == user_uuid);
+    }
+
+    async fn create_refresh_token(
+        &mut self,
+        user_uuid: &str,
+        session_token: &str,
+    ) -> Result<String, SessionError> {
+        self.sessions
+            .write()
+            .await
+            .create_refresh_token(user_uuid, session_token)
+            .await
+    }
+
+    async fn refresh_session(&mut self, refresh_token: &str) -> Option<String> {
+        let mut sessions = self.sessions.write().await;
+        let session_token = sessions.refresh_session(refresh_token).await?;
+
+        // The replaced session's token isn't known here, so the user's cached sessions are all
+        // forgotten. They're looked up again on their next validation.
+        if let Some(session) = sessions.peek_session(&session_token).await {
+            self.forget(|cached| cached.user_uuid == session.user_uuid);
+        } else {
+            self.cache.lock().clear();
+        }
+        Some(session_token)
+    }
+
+    async fn renew_session(&mut self, session_token: &str) -> Option<String> {
+        let renewed = self
+            .sessions
+            .write()
+            .await
+            .renew_session(session_token)
+            .await;
+        // Forgotten even if renewing failed, as the session may have just expired or been deleted.
+        self.cache.lock().pop(session_token);
+        renewed
+    }
+
+    async fn remove_expired(&mut self) -> usize {
+        let now = self.clock.now();
+        self.forget(|session| session.is_expired(now));
+        self.sessions.write().await.remove_expired().await
+    }
+
+    async fn flush(&mut self) {
+        self.sessions.write().await.flush().await;
+    }
+
+    async fn ping(&self) -> Result<(), SessionError> {
+        self.sessions.read().await.ping().await
+    }
+
+    async fn backup(&self) -> Option<String> {
+        self.sessions.read().await.backup().await
+    }
+
+    async fn restore_backup(&mut self, backup: &str) -> Result<(), SessionError> {
+        let restored = self.sessions.write().await.restore_backup(backup).await;
+        // Sessions restored over cached ones are looked up again.
+        self.cache.lock().clear();
+        restored
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::UNIX_EPOCH;
+
+    use super::*;
+    use crate::clock::ManualClock;
+    use crate::sessions::SessionsImpl;
+
+    const TTL: Duration = Duration::from_secs(5);
+
+    fn cached_sessions(clock: &ManualClock) -> CachedSessions {
+        let sessions = SessionsImpl::default().with_clock(Box::new(clock.clone()));
+        CachedSessions::new(
+            Box::new(RwLock::new(sessions)),
+            NonZeroUsize::new(100).unwrap(),
+            TTL,
+        )
+        .with_clock(Box::new(clock.clone()))
+    }
+
+    #[tokio::test]
+    async fn should_answer_validations_from_cache_until_ttl() {
+        let started = UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        let clock = ManualClock::new(started);
+        let mut sessions_service = cached_sessions(&clock);
+        let session_token = sessions_service
+            .create_session("123456", ClientMetadata::default())
+            .await
+            .unwrap();
+
+        sessions_service.get_session(&session_token).await.unwrap();
+
+        // Answered from the cache, so not recorded as seen
+        clock.advance(Duration::from_secs(1));
+        let session = sessions_service.get_session(&session_token).await.unwrap();
+        assert_eq!(session.last_seen, started);
+
+        clock.advance(TTL);
+        let session = sessions_service.get_session(&session_token).await.unwrap();
+        assert_eq!(session.last_seen, clock.now());
+    }
+
+    #[tokio::test]
+    async fn should_forget_signed_out_sessions() {
+        let clock = ManualClock::new(UNIX_EPOCH + Duration::from_secs(1_700_000_000));
+        let mut sessions_service = cached_sessions(&clock);
+        let signed_out = sessions_service
+            .create_session("123456", ClientMetadata::default())
+            .await
+            .unwrap();
+        let revoked = sessions_service
+            .create_session("123456", ClientMetadata::default())
+            .await
+            .unwrap();
+        let other_device = sessions_service
+            .create_session("123456", ClientMetadata::default())
+            .await
+            .unwrap();
+        let other_user = sessions_service
+            .create_session("654321", ClientMetadata::default())
+            .await
+            .unwrap();
+        for session_token in [&signed_out, &revoked, &other_device, &other_user] {
+            sessions_service.get_session(session_token).await.unwrap();
+        }
+
+        sessions_service.delete_session(&signed_out).await;
+        assert!(sessions_service.get_session(&signed_out).await.is_none());
+
+        let signed_out_elsewhere = sessions_service
+            .create_session("123456", ClientMetadata::default())
+            .await
+            .unwrap();
+        let refresh_token = sessions_service
+            .create_refresh_token("123456", &signed_out_elsewhere)
+            .await
+            .unwrap();
+        sessions_service
+            .get_session(&signed_out_elsewhere)
+            .await
+            .unwrap();
+        sessions_service.delete_session(&refresh_token).await;
+        assert!(sessions_service
+            .get_session(&signed_out_elsewhere)
+            .await
+            .is_none());
+
+        let session_id = sessions_service
+            .get_session(&revoked)
+            .await
+            .unwrap()
+            .session_id;
+        sessions_service
+            .delete_user_session("123456", session_id)
+            .await;
+        assert!(sessions_service.get_session(&revoked).await.is_none());
+        assert!(sessions_service.get_session(&other_device).await.is_some());
+
+        sessions_service.delete_user_sessions("123456").await;
+        assert!(sessions_service.get_session(&other_device).await.is_none());
+        assert!(sessions_service.get_session(&other_user).await.is_some());
+    }
+
+    #[tokio::test]
+    async fn should_forget_replaced_sessions() {
+        let clock = ManualClock::new(UNIX_EPOCH + Duration::from_secs(1_700_000_000));
+        let mut sessions_service = cached_sessions(&clock);
+        let refreshed = sessions_service
+            .create_session("123456", ClientMetadata::default())
+            .await
+            .unwrap();
+        let refresh_token = sessions_service
+            .create_refresh_token("123456", &refreshed)
+            .await
+            .unwrap();
+        let renewed = sessions_service
+            .create_session("123456", ClientMetadata::default())
+            .await
+            .unwrap();
+        sessions_service.get_session(&refreshed).await.unwrap();
+        let cached = sessions_service.get_session(&renewed).await.unwrap();
+
+        let session_token = sessions_service
+            .refresh_session(&refresh_token)
+            .await
+            .unwrap();
+        assert!(sessions_service.get_session(&refreshed).await.is_none());
+        assert!(sessions_service.get_session(&session_token).await.is_some());
+
+        // The renewed expiry is looked up rather than the cached one
+        clock.advance(Duration::from_secs(1));
+        let session_token = sessions_service.renew_session(&renewed).await.unwrap();
+        let session = sessions_service.get_session(&session_token).await.unwrap();
+        assert!(session.expires_at > cached.expires_at);
+    }
+}
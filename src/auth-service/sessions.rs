@@ -1,61 +1,2207 @@
-use std::collections::HashMap;
+use std::borrow::Borrow;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::hash::Hash;
+use std::str::FromStr;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
+use dashmap::DashMap;
+use percent_encoding::{percent_decode_str, utf8_percent_encode, NON_ALPHANUMERIC};
+use sha2::{Digest, Sha256};
+use tokio::sync::broadcast;
 use uuid::Uuid;
 
+use crate::clock::{Clock, SystemClock};
+use crate::error::SessionError;
+use crate::expiry_wheel::ExpiryWheel;
+use crate::random::{OsRandom, Random};
+use crate::secret::Secret;
+use crate::token_signing::TokenSigner;
+use crate::user_ids::UserUuid;
+
+#[cfg(feature = "sqlite")]
+pub use sqlite::SqliteSessions;
+
+/// How long a newly created session stays valid by default.
+pub const SESSION_LIFETIME: Duration = Duration::from_secs(60 * 60 * 24);
+
+/// How long a session can be kept alive by renewing it, by default.
+pub const SESSION_MAX_LIFETIME: Duration = Duration::from_secs(60 * 60 * 24 * 7);
+
+/// How often expired sessions are evicted by default.
+pub const SESSION_CLEANUP_INTERVAL: Duration = Duration::from_secs(60);
+
+/// How many session events are buffered for watchers that fall behind.
+pub const SESSION_EVENTS_CAPACITY: usize = 1024;
+
+/// How long a refresh token can be exchanged for new sessions.
+pub const REFRESH_TOKEN_LIFETIME: Duration = Duration::from_secs(60 * 60 * 24 * 30);
+
+#[tonic::async_trait]
 pub trait Sessions {
-    fn create_session(&mut self, user_uuid: &str) -> String;
-    fn delete_session(&mut self, user_uuid: &str);
+    /// Fails if the backend couldn't store the session, as its token wouldn't validate.
+    async fn create_session(
+        &mut self,
+        user_uuid: &str,
+        client: ClientMetadata,
+    ) -> Result<String, SessionError>;
+    /// Returns the session if it is still valid and records it as seen.
+    async fn get_session(&self, session_token: &str) -> Option<Session>;
+    /// Like `get_session`, but leaves the session untouched, for token introspection.
+    async fn peek_session(&self, session_token: &str) -> Option<Session>;
+    /// Lists the user's sessions that haven't expired, oldest first. Fails with
+    /// `ListingUnsupported` if the backend doesn't store them.
+    async fn list_user_sessions(&self, user_uuid: &str) -> Result<Vec<Session>, SessionError>;
+    /// Counts the sessions that haven't expired, `None` if they aren't stored.
+    async fn count_sessions(&self) -> Result<Option<usize>, SessionError> {
+        Ok(None)
+    }
+    /// Ends the session of a session or refresh token. Returns the id of the session that ended,
+    /// so caches keyed by its token can forget it.
+    async fn delete_session(&mut self, session_token: &str) -> Option<SessionId>;
+    /// Deletes the session identified by `session_id`, as listed by `list_user_sessions`.
+    async fn delete_user_session(&mut self, user_uuid: &str, session_id: SessionId);
+    /// Deletes every session and refresh token belonging to `user_uuid`.
+    async fn delete_user_sessions(&mut self, user_uuid: &str);
+    /// Issues a refresh token tied to `session_token`. Deleting that session revokes it. Fails if
+    /// the backend couldn't store the token, as it wouldn't refresh.
+    async fn create_refresh_token(
+        &mut self,
+        user_uuid: &str,
+        session_token: &str,
+    ) -> Result<String, SessionError>;
+    /// Replaces the session tied to `refresh_token` with a new one and returns its token.
+    async fn refresh_session(&mut self, refresh_token: &str) -> Option<String>;
+    /// Extends the session by its lifetime, capped at its maximum lifetime. Returns the token to
+    /// use from now on, which may differ from `session_token`.
+    async fn renew_session(&mut self, session_token: &str) -> Option<String>;
+    /// Evicts expired sessions and refresh tokens. Returns how many sessions were removed.
+    async fn remove_expired(&mut self) -> usize;
+    /// Saves anything not yet persisted and closes connections, before the service exits.
+    async fn flush(&mut self) {}
+    /// Fails if the backend can't be reached, for health checks.
+    async fn ping(&self) -> Result<(), SessionError> {
+        Ok(())
+    }
+    /// Serializes every session and refresh token for `restore_backup`. `None` if the backend
+    /// isn't backed up through the service, e.g. a database with backups of its own.
+    async fn backup(&self) -> Option<String> {
+        None
+    }
+    /// Adds the sessions and refresh tokens of a `backup` that haven't expired.
+    async fn restore_backup(&mut self, _backup: &str) -> Result<(), SessionError> {
+        Err(SessionError::BackupUnsupported)
+    }
+}
+
+/// How long sessions last.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct SessionPolicy {
+    /// How long a session stays valid after being created or renewed.
+    pub lifetime: Duration,
+    /// Sessions can't be renewed past this long after being created.
+    pub max_lifetime: Duration,
+    /// Whether each successful validation renews the session.
+    pub sliding: bool,
+}
+
+impl SessionPolicy {
+    /// When a session created at `created_at` expires if renewed at `now`.
+    pub fn expires_at(&self, created_at: SystemTime, now: SystemTime) -> SystemTime {
+        (now + self.lifetime).min(created_at + self.max_lifetime)
+    }
+}
+
+impl Default for SessionPolicy {
+    fn default() -> Self {
+        Self {
+            lifetime: SESSION_LIFETIME,
+            max_lifetime: SESSION_MAX_LIFETIME,
+            sliding: false,
+        }
+    }
+}
+
+/// What to do when a user signs in while already at their session limit.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SessionLimitPolicy {
+    /// Reject the new sign in.
+    Reject,
+    /// Sign out of the oldest sessions to make room.
+    EvictOldest,
+}
+
+/// Caps how many sessions a user can have at once.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct SessionLimit {
+    pub max_sessions: usize,
+    pub policy: SessionLimitPolicy,
+}
+
+/// Describes the client a session was created from.
+#[derive(Clone, Debug, Default)]
+pub struct ClientMetadata {
+    pub ip_address: String,
+    pub user_agent: String,
+}
+
+/// Identifies a session without revealing its token. Session ids are random UUIDs, kept as their
+/// 16 bytes so they're copied rather than allocated.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct SessionId(Uuid);
+
+impl SessionId {
+    pub const fn new(uuid: Uuid) -> Self {
+        Self(uuid)
+    }
+}
+
+impl fmt::Display for SessionId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.0.hyphenated().fmt(f)
+    }
+}
+
+impl FromStr for SessionId {
+    type Err = uuid::Error;
+
+    fn from_str(session_id: &str) -> Result<Self, Self::Err> {
+        Uuid::parse_str(session_id).map(Self)
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Session {
+    pub session_id: SessionId,
+    pub user_uuid: UserUuid,
+    pub created_at: SystemTime,
+    pub last_seen: SystemTime,
+    pub expires_at: SystemTime,
+    pub client: ClientMetadata,
+}
+
+impl Session {
+    pub fn is_expired(&self, now: SystemTime) -> bool {
+        self.expires_at <= now
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SessionEventKind {
+    Created,
+    /// Signed out, revoked or replaced by a refreshed session.
+    Deleted,
+    Expired,
+}
+
+/// Published whenever a session starts or stops being valid.
+#[derive(Clone, Debug)]
+pub struct SessionEvent {
+    pub kind: SessionEventKind,
+    pub session_id: SessionId,
+    pub user_uuid: UserUuid,
+    /// Hex encoded SHA-256 of the session token, so caches can be keyed without exposing tokens.
+    pub token_hash: String,
+    pub expires_at: SystemTime,
+    pub timestamp: SystemTime,
+}
+
+/// Hashes a session token the way `SessionEvent::token_hash` does.
+pub fn token_hash(session_token: &str) -> String {
+    format!("{:x}", Sha256::digest(session_token.as_bytes()))
+}
+
+#[derive(Clone, Debug)]
+struct RefreshToken {
+    user_uuid: String,
+    session_token: Secret,
+    expires_at: SystemTime,
 }
 
-#[derive(Default)]
 pub struct SessionsImpl {
-    uuid_to_session: HashMap<String, String>,
+    policy: SessionPolicy,
+    /// Signs session and refresh tokens.
+    signer: TokenSigner,
+    /// Sharded, so validations only contend when their sessions share a shard. `get_session`
+    /// updates sessions through it without exclusive access to the store.
+    token_to_session: DashMap<String, Session>,
+    /// Session tokens by user, so a user's sessions can be found without a full scan.
+    uuid_to_tokens: HashMap<UserUuid, HashSet<String>>,
+    refresh_token_to_session: HashMap<String, RefreshToken>,
+    /// Refresh tokens by the session token they were issued with and by user, so signing out
+    /// revokes them without a full scan.
+    session_to_refresh_tokens: HashMap<String, HashSet<String>>,
+    uuid_to_refresh_tokens: HashMap<UserUuid, HashSet<String>>,
+    /// Session tokens by when they expire, so cleanup only looks at sessions due to expire.
+    /// Renewals and deletions aren't filed, the sessions are looked up again once due.
+    session_expiry: ExpiryWheel<String>,
+    refresh_token_expiry: ExpiryWheel<String>,
+    events: Option<broadcast::Sender<SessionEvent>>,
+    /// Tells when sessions and refresh tokens are created and expire.
+    clock: Box<dyn Clock + Send + Sync>,
+    /// Draws the ids of tokens and sessions.
+    random: Box<dyn Random + Send + Sync>,
 }
 
+impl SessionsImpl {
+    pub fn new(policy: SessionPolicy) -> Self {
+        Self {
+            policy,
+            signer: TokenSigner::generate(),
+            token_to_session: DashMap::new(),
+            uuid_to_tokens: HashMap::new(),
+            refresh_token_to_session: HashMap::new(),
+            session_to_refresh_tokens: HashMap::new(),
+            uuid_to_refresh_tokens: HashMap::new(),
+            session_expiry: ExpiryWheel::new(SystemClock.now()),
+            refresh_token_expiry: ExpiryWheel::new(SystemClock.now()),
+            events: None,
+            clock: Box::new(SystemClock),
+            random: Box::new(OsRandom),
+        }
+    }
+
+    /// Signs tokens with configured keys instead of a key generated at startup.
+    pub fn with_token_signer(mut self, signer: TokenSigner) -> Self {
+        self.signer = signer;
+        self
+    }
+
+    /// Publishes session events to `events`, e.g. for `WatchSessionEvents`.
+    pub fn with_events(mut self, events: broadcast::Sender<SessionEvent>) -> Self {
+        self.events = Some(events);
+        self
+    }
+
+    /// Tells the time with `clock` instead of the system clock, so tests can expire sessions
+    /// without waiting.
+    #[cfg(test)]
+    pub fn with_clock(mut self, clock: Box<dyn Clock + Send + Sync>) -> Self {
+        self.session_expiry = ExpiryWheel::new(clock.now());
+        self.refresh_token_expiry = ExpiryWheel::new(clock.now());
+        self.clock = clock;
+        self
+    }
+
+    /// Draws token and session ids from `random` instead of the OS, so tests get the same
+    /// tokens each run.
+    #[cfg(test)]
+    pub fn with_random(mut self, random: Box<dyn Random + Send + Sync>) -> Self {
+        self.random = random;
+        self
+    }
+
+    fn publish(&self, kind: SessionEventKind, session_token: &str, session: &Session) {
+        publish(
+            &self.events,
+            kind,
+            token_hash(session_token),
+            session,
+            self.clock.now(),
+        );
+    }
+
+    /// Serializes every session and refresh token, one per line, for `restore`.
+    pub fn snapshot(&self) -> String {
+        let sessions = self.token_to_session.iter().map(|entry| {
+            let (session_token, session) = entry.pair();
+            [
+                "session",
+                session_token,
+                &session.session_id.to_string(),
+                &session.user_uuid,
+                &to_millis(session.created_at),
+                &to_millis(session.last_seen),
+                &to_millis(session.expires_at),
+                &encode(&session.client.ip_address),
+                &encode(&session.client.user_agent),
+            ]
+            .join("\t")
+        });
+        let refresh_tokens =
+            self.refresh_token_to_session
+                .iter()
+                .map(|(refresh_token, refresh)| {
+                    [
+                        "refresh",
+                        refresh_token,
+                        &refresh.user_uuid,
+                        refresh.session_token.expose(),
+                        &to_millis(refresh.expires_at),
+                    ]
+                    .join("\t")
+                });
+
+        sessions
+            .chain(refresh_tokens)
+            .map(|line| line + "\n")
+            .collect()
+    }
+
+    /// Loads sessions and refresh tokens from a `snapshot`, skipping any that have expired.
+    pub fn restore(&mut self, snapshot: &str) -> Result<(), String> {
+        let now = self.clock.now();
+
+        for (number, line) in snapshot.lines().enumerate() {
+            let invalid = || format!("Error, invalid session snapshot line {}", number + 1);
+            let fields: Vec<&str> = line.split('\t').collect();
+
+            match fields.as_slice() {
+                [] | [""] => (),
+                ["session", session_token, session_id, user_uuid, created_at, last_seen, expires_at, ip_address, user_agent] =>
+                {
+                    let session = Session {
+                        session_id: session_id.parse().map_err(|_| invalid())?,
+                        user_uuid: UserUuid::from(*user_uuid),
+                        created_at: from_millis(created_at).ok_or_else(invalid)?,
+                        last_seen: from_millis(last_seen).ok_or_else(invalid)?,
+                        expires_at: from_millis(expires_at).ok_or_else(invalid)?,
+                        client: ClientMetadata {
+                            ip_address: decode(ip_address).ok_or_else(invalid)?,
+                            user_agent: decode(user_agent).ok_or_else(invalid)?,
+                        },
+                    };
+
+                    if session.expires_at > now {
+                        self.session_expiry
+                            .insert(session.expires_at, session_token.to_string());
+                        self.uuid_to_tokens
+                            .entry(session.user_uuid.clone())
+                            .or_default()
+                            .insert(session_token.to_string());
+                        self.token_to_session
+                            .insert(session_token.to_string(), session);
+                    }
+                }
+                ["refresh", refresh_token, user_uuid, session_token, expires_at] => {
+                    let refresh = RefreshToken {
+                        user_uuid: user_uuid.to_string(),
+                        session_token: Secret::new(*session_token),
+                        expires_at: from_millis(expires_at).ok_or_else(invalid)?,
+                    };
+
+                    if refresh.expires_at > now {
+                        self.insert_refresh_token(refresh_token.to_string(), refresh);
+                    }
+                }
+                _ => return Err(invalid()),
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Removes a session from both maps.
+    fn remove_session(&mut self, session_token: &str) -> Option<Session> {
+        let (_, session) = self.token_to_session.remove(session_token)?;
+
+        forget(&mut self.uuid_to_tokens, &session.user_uuid, session_token);
+
+        Some(session)
+    }
+
+    /// Adds a refresh token to the map, its indexes and the expiry wheel.
+    fn insert_refresh_token(&mut self, refresh_token: String, refresh: RefreshToken) {
+        self.refresh_token_expiry
+            .insert(refresh.expires_at, refresh_token.clone());
+        self.session_to_refresh_tokens
+            .entry(refresh.session_token.expose().to_string())
+            .or_default()
+            .insert(refresh_token.clone());
+        self.uuid_to_refresh_tokens
+            .entry(UserUuid::from(refresh.user_uuid.as_str()))
+            .or_default()
+            .insert(refresh_token.clone());
+        self.refresh_token_to_session.insert(refresh_token, refresh);
+    }
+
+    /// Removes a refresh token from the map and its indexes.
+    fn remove_refresh_token(&mut self, refresh_token: &str) -> Option<RefreshToken> {
+        let refresh = self.refresh_token_to_session.remove(refresh_token)?;
+
+        forget(
+            &mut self.session_to_refresh_tokens,
+            refresh.session_token.expose(),
+            refresh_token,
+        );
+        forget(
+            &mut self.uuid_to_refresh_tokens,
+            &refresh.user_uuid,
+            refresh_token,
+        );
+
+        Some(refresh)
+    }
+}
+
+/// Removes `token` from the set indexed by `key`, and the set once it's empty.
+fn forget<K>(index: &mut HashMap<K, HashSet<String>>, key: &str, token: &str)
+where
+    K: Borrow<str> + Eq + Hash,
+{
+    if let Some(tokens) = index.get_mut(key) {
+        tokens.remove(token);
+        if tokens.is_empty() {
+            index.remove(key);
+        }
+    }
+}
+
+impl Default for SessionsImpl {
+    fn default() -> Self {
+        Self::new(SessionPolicy::default())
+    }
+}
+
+#[tonic::async_trait]
 impl Sessions for SessionsImpl {
-    fn create_session(&mut self, user_uuid: &str) -> String {
-        let session: String = Uuid::new_v4().to_string(); // Create a new session using Uuid::new_v4().
+    async fn create_session(
+        &mut self,
+        user_uuid: &str,
+        client: ClientMetadata,
+    ) -> Result<String, SessionError> {
+        // Create a new signed session token.
+        let session_token: String = self.signer.issue_with(self.random.as_ref());
+        let now = self.clock.now();
+        let user_uuid = UserUuid::from(user_uuid);
+
+        let session = Session {
+            session_id: SessionId::new(self.random.uuid()),
+            user_uuid: user_uuid.clone(),
+            created_at: now,
+            last_seen: now,
+            expires_at: self.policy.expires_at(now, now),
+            client,
+        };
+
+        self.publish(SessionEventKind::Created, &session_token, &session);
+        self.session_expiry
+            .insert(session.expires_at, session_token.clone());
+        self.token_to_session.insert(session_token.clone(), session);
+        self.uuid_to_tokens
+            .entry(user_uuid)
+            .or_default()
+            .insert(session_token.clone());
+
+        Ok(session_token)
+    }
+
+    async fn get_session(&self, session_token: &str) -> Option<Session> {
+        // Tampered tokens are rejected without a lookup.
+        if !self.signer.verify(session_token) {
+            return None;
+        }
+
+        // Expired sessions are treated as if they don't exist.
+        let now = self.clock.now();
+        let mut session = self
+            .token_to_session
+            .get_mut(session_token)
+            .filter(|session| !session.is_expired(now))?;
+
+        session.last_seen = now;
+
+        if self.policy.sliding {
+            session.expires_at = self.policy.expires_at(session.created_at, now);
+        }
+
+        Some(session.clone())
+    }
 
-        self.uuid_to_session
-            .insert(user_uuid.to_string(), session.clone());
+    async fn peek_session(&self, session_token: &str) -> Option<Session> {
+        if !self.signer.verify(session_token) {
+            return None;
+        }
 
-        session
+        self.token_to_session
+            .get(session_token)
+            .filter(|session| !session.is_expired(self.clock.now()))
+            .map(|session| session.clone())
     }
 
-    fn delete_session(&mut self, user_uuid: &str) {
-        match self.uuid_to_session.get(user_uuid) {
-            Some(_) => {
-                self.uuid_to_session.remove(user_uuid);
+    async fn list_user_sessions(&self, user_uuid: &str) -> Result<Vec<Session>, SessionError> {
+        let now = self.clock.now();
+        let mut sessions: Vec<Session> = self
+            .uuid_to_tokens
+            .get(user_uuid)
+            .into_iter()
+            .flatten()
+            .filter_map(|session_token| self.token_to_session.get(session_token))
+            .filter(|session| !session.is_expired(now))
+            .map(|session| session.clone())
+            .collect();
+
+        sessions.sort_by_key(|session| session.created_at);
+
+        Ok(sessions)
+    }
+
+    async fn count_sessions(&self) -> Result<Option<usize>, SessionError> {
+        let now = self.clock.now();
+        Ok(Some(
+            self.token_to_session
+                .iter()
+                .filter(|session| !session.is_expired(now))
+                .count(),
+        ))
+    }
+
+    async fn delete_session(&mut self, session_token: &str) -> Option<SessionId> {
+        // Revoking a refresh token ends the session it was issued with.
+        let session_token = match self.remove_refresh_token(session_token) {
+            Some(refresh) => refresh.session_token.into_exposed(),
+            None => session_token.to_string(),
+        };
+        let session_token = session_token.as_str();
+
+        let session = self.remove_session(session_token);
+        match &session {
+            Some(session) => self.publish(SessionEventKind::Deleted, session_token, session),
+            None => tracing::debug!("No session found"),
+        };
+
+        // Signing out also revokes the refresh token issued with the session.
+        let refresh_tokens = self
+            .session_to_refresh_tokens
+            .remove(session_token)
+            .unwrap_or_default();
+        for refresh_token in refresh_tokens {
+            self.remove_refresh_token(&refresh_token);
+        }
+
+        session.map(|session| session.session_id)
+    }
+
+    async fn delete_user_session(&mut self, user_uuid: &str, session_id: SessionId) {
+        let session_token = self
+            .uuid_to_tokens
+            .get(user_uuid)
+            .into_iter()
+            .flatten()
+            .find(|session_token| {
+                self.token_to_session
+                    .get(*session_token)
+                    .is_some_and(|session| session.session_id == session_id)
+            })
+            .cloned();
+
+        if let Some(session_token) = session_token {
+            self.delete_session(&session_token).await;
+        }
+    }
+
+    async fn delete_user_sessions(&mut self, user_uuid: &str) {
+        for session_token in self.uuid_to_tokens.remove(user_uuid).unwrap_or_default() {
+            if let Some((_, session)) = self.token_to_session.remove(&session_token) {
+                self.publish(SessionEventKind::Deleted, &session_token, &session);
+            }
+        }
+        let refresh_tokens = self
+            .uuid_to_refresh_tokens
+            .remove(user_uuid)
+            .unwrap_or_default();
+        for refresh_token in refresh_tokens {
+            self.remove_refresh_token(&refresh_token);
+        }
+    }
+
+    async fn create_refresh_token(
+        &mut self,
+        user_uuid: &str,
+        session_token: &str,
+    ) -> Result<String, SessionError> {
+        let refresh_token: String = self.signer.issue_with(self.random.as_ref());
+
+        let refresh = RefreshToken {
+            user_uuid: user_uuid.to_string(),
+            session_token: Secret::new(session_token),
+            expires_at: self.clock.now() + REFRESH_TOKEN_LIFETIME,
+        };
+
+        self.insert_refresh_token(refresh_token.clone(), refresh);
+
+        Ok(refresh_token)
+    }
+
+    async fn refresh_session(&mut self, refresh_token: &str) -> Option<String> {
+        if !self.signer.verify(refresh_token) {
+            return None;
+        }
+
+        let refresh = self
+            .refresh_token_to_session
+            .get(refresh_token)
+            .filter(|refresh| refresh.expires_at > self.clock.now())?
+            .clone();
+
+        // The previous session is replaced rather than left alive alongside the new one.
+        // It was created by the same client, so its metadata carries over.
+        let client = match self.remove_session(refresh.session_token.expose()) {
+            Some(session) => {
+                self.publish(
+                    SessionEventKind::Deleted,
+                    refresh.session_token.expose(),
+                    &session,
+                );
+                session.client
+            }
+            None => ClientMetadata::default(),
+        };
+
+        let session_token = self.create_session(&refresh.user_uuid, client).await.ok()?;
+
+        if let Some(refresh) = self.refresh_token_to_session.get_mut(refresh_token) {
+            refresh.session_token = Secret::new(session_token.as_str());
+        }
+        forget(
+            &mut self.session_to_refresh_tokens,
+            refresh.session_token.expose(),
+            refresh_token,
+        );
+        self.session_to_refresh_tokens
+            .entry(session_token.clone())
+            .or_default()
+            .insert(refresh_token.to_string());
+
+        Some(session_token)
+    }
+
+    async fn renew_session(&mut self, session_token: &str) -> Option<String> {
+        if !self.signer.verify(session_token) {
+            return None;
+        }
+
+        let now = self.clock.now();
+        let mut session = self
+            .token_to_session
+            .get_mut(session_token)
+            .filter(|session| !session.is_expired(now))?;
+
+        session.expires_at = self.policy.expires_at(session.created_at, now);
+
+        Some(session_token.to_string())
+    }
+
+    async fn remove_expired(&mut self) -> usize {
+        let now = self.clock.now();
+
+        let mut removed = 0;
+        for session_token in self.session_expiry.advance(now) {
+            // Sessions renewed since they were filed are filed again for their new expiry.
+            let expires_at = match self.token_to_session.get(&session_token) {
+                Some(session) => session.expires_at,
+                None => continue,
+            };
+            if expires_at > now {
+                self.session_expiry.insert(expires_at, session_token);
+            } else if let Some(session) = self.remove_session(&session_token) {
+                self.publish(SessionEventKind::Expired, &session_token, &session);
+                removed += 1;
+            }
+        }
+        for refresh_token in self.refresh_token_expiry.advance(now) {
+            self.remove_refresh_token(&refresh_token);
+        }
+
+        removed
+    }
+
+    async fn backup(&self) -> Option<String> {
+        Some(self.snapshot())
+    }
+
+    async fn restore_backup(&mut self, backup: &str) -> Result<(), SessionError> {
+        self.restore(backup).map_err(SessionError::InvalidBackup)
+    }
+}
+
+fn publish(
+    events: &Option<broadcast::Sender<SessionEvent>>,
+    kind: SessionEventKind,
+    token_hash: String,
+    session: &Session,
+    timestamp: SystemTime,
+) {
+    if let Some(events) = events {
+        // Sending only fails when nobody is watching.
+        let _ = events.send(SessionEvent {
+            kind,
+            session_id: session.session_id,
+            user_uuid: session.user_uuid.clone(),
+            token_hash,
+            expires_at: session.expires_at,
+            timestamp,
+        });
+    }
+}
+
+pub fn to_millis(time: SystemTime) -> String {
+    time.duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_millis())
+        .unwrap_or_default()
+        .to_string()
+}
+
+pub fn from_millis(millis: &str) -> Option<SystemTime> {
+    Some(UNIX_EPOCH + Duration::from_millis(millis.parse().ok()?))
+}
+
+/// Escapes client supplied metadata so it can't break the snapshot format.
+pub fn encode(value: &str) -> String {
+    utf8_percent_encode(value, NON_ALPHANUMERIC).to_string()
+}
+
+pub fn decode(value: &str) -> Option<String> {
+    percent_decode_str(value)
+        .decode_utf8()
+        .ok()
+        .map(|value| value.into_owned())
+}
+
+#[cfg(feature = "sqlite")]
+mod sqlite {
+    use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+    use sqlx::sqlite::{SqliteConnectOptions, SqlitePool, SqlitePoolOptions};
+    use tokio::sync::broadcast;
+    use uuid::Uuid;
+
+    use super::{
+        publish, token_hash, ClientMetadata, Session, SessionEvent, SessionEventKind, SessionId,
+        SessionPolicy, Sessions, REFRESH_TOKEN_LIFETIME,
+    };
+    use crate::clock::{Clock, SystemClock};
+    use crate::config::Migrations;
+    use crate::error::SessionError;
+    use crate::metrics::{record_failure, FailureReason};
+    use crate::migrations::{migrate, SQLITE_MIGRATIONS};
+    use crate::random::{OsRandom, Random};
+    use crate::storage_retry::StorageRetry;
+    use crate::token_signing::TokenSigner;
+
+    const SESSION_COLUMNS: &str = "token_hash, session_id, user_uuid, created_at, last_seen, \
+                                   expires_at, ip_address, user_agent";
+
+    type SessionRow = (String, String, String, i64, i64, i64, String, String);
+
+    /// Stores sessions in an embedded SQLite database, so single node deployments keep them
+    /// across restarts without running a database server.
+    pub struct SqliteSessions {
+        pool: SqlitePool,
+        policy: SessionPolicy,
+        /// Signs session and refresh tokens. Configure fixed keys, tokens signed with a
+        /// generated key are rejected after a restart.
+        signer: TokenSigner,
+        events: Option<broadcast::Sender<SessionEvent>>,
+        retry: StorageRetry,
+        clock: Box<dyn Clock + Send + Sync>,
+        random: Box<dyn Random + Send + Sync>,
+    }
+
+    impl SqliteSessions {
+        /// Opens the database at `path`, creating it if needed, and migrates its schema according
+        /// to `migrations`. Only token hashes are stored, so a leaked database file can't be used
+        /// to hijack sessions.
+        pub async fn open(
+            path: &str,
+            policy: SessionPolicy,
+            migrations: Migrations,
+        ) -> Result<Self, String> {
+            let options = SqliteConnectOptions::new()
+                .filename(path)
+                .create_if_missing(true);
+
+            let pool = SqlitePoolOptions::new()
+                .connect_with(options)
+                .await
+                .map_err(|e| format!("Failed to open {path}.\n{e:?}"))?;
+
+            let mut conn = pool
+                .acquire()
+                .await
+                .map_err(|e| format!("Failed to open {path}.\n{e:?}"))?;
+            migrate(&SQLITE_MIGRATIONS, &mut *conn, path, migrations).await?;
+            drop(conn);
+
+            Ok(Self {
+                pool,
+                policy,
+                signer: TokenSigner::generate(),
+                events: None,
+                retry: StorageRetry::default(),
+                clock: Box::new(SystemClock),
+                random: Box::new(OsRandom),
+            })
+        }
+
+        pub fn with_token_signer(mut self, signer: TokenSigner) -> Self {
+            self.signer = signer;
+            self
+        }
+
+        /// Publishes session events to `events`, e.g. for `WatchSessionEvents`.
+        pub fn with_events(mut self, events: broadcast::Sender<SessionEvent>) -> Self {
+            self.events = Some(events);
+            self
+        }
+
+        /// Retries queries failing transiently with `retry`, sharing its health with other stores.
+        pub fn with_retry(mut self, retry: StorageRetry) -> Self {
+            self.retry = retry;
+            self
+        }
+
+        /// Tells the time with `clock` instead of the system clock, see `SessionsImpl::with_clock`.
+        #[cfg(test)]
+        pub fn with_clock(mut self, clock: Box<dyn Clock + Send + Sync>) -> Self {
+            self.clock = clock;
+            self
+        }
+
+        /// Draws tokens and session ids from `random` instead of the OS, see
+        /// `SessionsImpl::with_random`.
+        #[cfg(test)]
+        pub fn with_random(mut self, random: Box<dyn Random + Send + Sync>) -> Self {
+            self.random = random;
+            self
+        }
+
+        /// Looks up the unexpired session whose token hashes to `session_hash`.
+        async fn find_session(&self, session_hash: &str) -> Option<Session> {
+            let query = format!(
+                "SELECT {SESSION_COLUMNS} FROM sessions \
+                WHERE token_hash = $1 AND expires_at > $2"
+            );
+            let row: Option<SessionRow> = self
+                .retry
+                .run(|| {
+                    sqlx::query_as(&query)
+                        .bind(session_hash)
+                        .bind(to_millis(self.clock.now()))
+                        .fetch_optional(&self.pool)
+                })
+                .await
+                .unwrap_or_else(|e| {
+                    tracing::error!(error = ?e, "Failed to get session");
+                    record_failure(FailureReason::BackendError);
+                    None
+                });
+
+            row.map(|row| from_row(row).1)
+        }
+
+        /// Deletes the session whose token hashes to `session_hash`, leaving its refresh tokens.
+        async fn remove_session(&self, session_hash: &str) -> Option<Session> {
+            let query =
+                format!("DELETE FROM sessions WHERE token_hash = $1 RETURNING {SESSION_COLUMNS}");
+            let row: Option<SessionRow> = self
+                .retry
+                .run(|| {
+                    sqlx::query_as(&query)
+                        .bind(session_hash)
+                        .fetch_optional(&self.pool)
+                })
+                .await
+                .unwrap_or_else(|e| {
+                    tracing::error!(error = ?e, "Failed to delete session");
+                    record_failure(FailureReason::BackendError);
+                    None
+                });
+
+            let (session_hash, session) = from_row(row?);
+            publish(
+                &self.events,
+                SessionEventKind::Deleted,
+                session_hash,
+                &session,
+                self.clock.now(),
+            );
+
+            Some(session)
+        }
+
+        /// Deletes the session whose token hashes to `session_hash` along with its refresh
+        /// tokens. Returns its id if there was one.
+        async fn end_session(&self, session_hash: &str) -> Option<SessionId> {
+            let session = self.remove_session(session_hash).await;
+
+            // Signing out also revokes the refresh token issued with the session.
+            let result = self
+                .retry
+                .run(|| {
+                    sqlx::query("DELETE FROM refresh_tokens WHERE session_token_hash = $1")
+                        .bind(session_hash)
+                        .execute(&self.pool)
+                })
+                .await;
+
+            if let Err(e) = result {
+                tracing::error!(error = ?e, "Failed to delete refresh tokens");
+                record_failure(FailureReason::BackendError);
+            }
+
+            session.map(|session| session.session_id)
+        }
+    }
+
+    #[tonic::async_trait]
+    impl Sessions for SqliteSessions {
+        #[tracing::instrument(skip_all, fields(db.system = "sqlite", otel.kind = "client"))]
+        async fn create_session(
+            &mut self,
+            user_uuid: &str,
+            client: ClientMetadata,
+        ) -> Result<String, SessionError> {
+            let session_token: String = self.signer.issue_with(self.random.as_ref());
+            let session_hash = token_hash(&session_token);
+            let now = self.clock.now();
+
+            let session = Session {
+                session_id: SessionId::new(self.random.uuid()),
+                user_uuid: user_uuid.into(),
+                created_at: now,
+                last_seen: now,
+                expires_at: self.policy.expires_at(now, now),
+                client,
+            };
+
+            let query = format!(
+                "INSERT INTO sessions ({SESSION_COLUMNS}) VALUES ($1, $2, $3, $4, $5, $6, $7, $8)"
+            );
+            self.retry
+                .run(|| {
+                    sqlx::query(&query)
+                        .bind(&session_hash)
+                        .bind(session.session_id.to_string())
+                        .bind(&*session.user_uuid)
+                        .bind(to_millis(session.created_at))
+                        .bind(to_millis(session.last_seen))
+                        .bind(to_millis(session.expires_at))
+                        .bind(&session.client.ip_address)
+                        .bind(&session.client.user_agent)
+                        .execute(&self.pool)
+                })
+                .await
+                .map_err(|e| SessionError::backend(format!("Failed to create session.\n{e:?}")))?;
+
+            publish(
+                &self.events,
+                SessionEventKind::Created,
+                session_hash,
+                &session,
+                self.clock.now(),
+            );
+            Ok(session_token)
+        }
+
+        #[tracing::instrument(skip_all, fields(db.system = "sqlite", otel.kind = "client"))]
+        async fn get_session(&self, session_token: &str) -> Option<Session> {
+            // Tampered tokens are rejected without a lookup.
+            if !self.signer.verify(session_token) {
+                return None;
+            }
+
+            let session_hash = token_hash(session_token);
+            let mut session = self.find_session(&session_hash).await?;
+
+            session.last_seen = self.clock.now();
+
+            if self.policy.sliding {
+                session.expires_at = self.policy.expires_at(session.created_at, self.clock.now());
+            }
+
+            let result = self
+                .retry
+                .run(|| {
+                    sqlx::query(
+                        "UPDATE sessions SET last_seen = $2, expires_at = $3 WHERE token_hash = $1",
+                    )
+                    .bind(&session_hash)
+                    .bind(to_millis(session.last_seen))
+                    .bind(to_millis(session.expires_at))
+                    .execute(&self.pool)
+                })
+                .await;
+
+            if let Err(e) = result {
+                tracing::error!(error = ?e, "Failed to update session");
+                record_failure(FailureReason::BackendError);
+            }
+
+            Some(session)
+        }
+
+        #[tracing::instrument(skip_all, fields(db.system = "sqlite", otel.kind = "client"))]
+        async fn peek_session(&self, session_token: &str) -> Option<Session> {
+            if !self.signer.verify(session_token) {
+                return None;
             }
 
-            None => {
-                println!("No session found");
+            self.find_session(&token_hash(session_token)).await
+        }
+
+        #[tracing::instrument(skip_all, fields(db.system = "sqlite", otel.kind = "client"))]
+        async fn list_user_sessions(&self, user_uuid: &str) -> Result<Vec<Session>, SessionError> {
+            let query = format!(
+                "SELECT {SESSION_COLUMNS} FROM sessions \
+                WHERE user_uuid = $1 AND expires_at > $2 ORDER BY created_at"
+            );
+            let rows: Vec<SessionRow> = self
+                .retry
+                .run(|| {
+                    sqlx::query_as(&query)
+                        .bind(user_uuid)
+                        .bind(to_millis(self.clock.now()))
+                        .fetch_all(&self.pool)
+                })
+                .await
+                .map_err(|e| SessionError::backend(format!("Failed to list sessions.\n{e:?}")))?;
+
+            Ok(rows.into_iter().map(|row| from_row(row).1).collect())
+        }
+
+        #[tracing::instrument(skip_all, fields(db.system = "sqlite", otel.kind = "client"))]
+        async fn count_sessions(&self) -> Result<Option<usize>, SessionError> {
+            self.retry
+                .run(|| {
+                    sqlx::query_scalar("SELECT COUNT(*) FROM sessions WHERE expires_at > $1")
+                        .bind(to_millis(self.clock.now()))
+                        .fetch_one(&self.pool)
+                })
+                .await
+                .map(|count: i64| Some(count as usize))
+                .map_err(|e| SessionError::backend(format!("Failed to count sessions.\n{e:?}")))
+        }
+
+        #[tracing::instrument(skip_all, fields(db.system = "sqlite", otel.kind = "client"))]
+        async fn delete_session(&mut self, session_token: &str) -> Option<SessionId> {
+            let token_hash = token_hash(session_token);
+
+            // Revoking a refresh token ends the session it was issued with.
+            let session_hash: Option<String> = self
+                .retry
+                .run(|| {
+                    sqlx::query_scalar(
+                        "DELETE FROM refresh_tokens WHERE token_hash = $1 \
+                                 RETURNING session_token_hash",
+                    )
+                    .bind(&token_hash)
+                    .fetch_optional(&self.pool)
+                })
+                .await
+                .unwrap_or_else(|e| {
+                    tracing::error!(error = ?e, "Failed to delete refresh token");
+                    record_failure(FailureReason::BackendError);
+                    None
+                });
+
+            let session_id = self.end_session(&session_hash.unwrap_or(token_hash)).await;
+            if session_id.is_none() {
+                tracing::debug!("No session found");
+            }
+            session_id
+        }
+
+        #[tracing::instrument(skip_all, fields(db.system = "sqlite", otel.kind = "client"))]
+        async fn delete_user_session(&mut self, user_uuid: &str, session_id: SessionId) {
+            let session_hash: Option<String> = self
+                .retry
+                .run(|| {
+                    sqlx::query_scalar(
+                        "SELECT token_hash FROM sessions WHERE user_uuid = $1 AND session_id = $2",
+                    )
+                    .bind(user_uuid)
+                    .bind(session_id.to_string())
+                    .fetch_optional(&self.pool)
+                })
+                .await
+                .unwrap_or_else(|e| {
+                    tracing::error!(error = ?e, "Failed to get session");
+                    record_failure(FailureReason::BackendError);
+                    None
+                });
+
+            if let Some(session_hash) = session_hash {
+                self.end_session(&session_hash).await;
+            }
+        }
+
+        #[tracing::instrument(skip_all, fields(db.system = "sqlite", otel.kind = "client"))]
+        async fn delete_user_sessions(&mut self, user_uuid: &str) {
+            let result = async {
+                let query = format!(
+                    "DELETE FROM sessions WHERE user_uuid = $1 RETURNING {SESSION_COLUMNS}"
+                );
+                let rows: Vec<SessionRow> = self
+                    .retry
+                    .run(|| sqlx::query_as(&query).bind(user_uuid).fetch_all(&self.pool))
+                    .await?;
+
+                self.retry
+                    .run(|| {
+                        sqlx::query("DELETE FROM refresh_tokens WHERE user_uuid = $1")
+                            .bind(user_uuid)
+                            .execute(&self.pool)
+                    })
+                    .await?;
+
+                Ok::<_, sqlx::Error>(rows)
+            }
+            .await;
+
+            match result {
+                Ok(rows) => {
+                    for (session_hash, session) in rows.into_iter().map(from_row) {
+                        publish(
+                            &self.events,
+                            SessionEventKind::Deleted,
+                            session_hash,
+                            &session,
+                            self.clock.now(),
+                        );
+                    }
+                }
+                Err(e) => {
+                    tracing::error!(error = ?e, "Failed to delete sessions");
+                    record_failure(FailureReason::BackendError);
+                }
+            }
+        }
+
+        #[tracing::instrument(skip_all, fields(db.system = "sqlite", otel.kind = "client"))]
+        async fn create_refresh_token(
+            &mut self,
+            user_uuid: &str,
+            session_token: &str,
+        ) -> Result<String, SessionError> {
+            let refresh_token: String = self.signer.issue_with(self.random.as_ref());
+
+            self.retry.run(|| sqlx::query(
+                    "INSERT INTO refresh_tokens (token_hash, user_uuid, session_token_hash, expires_at) \
+                     VALUES ($1, $2, $3, $4)",
+                )
+                .bind(token_hash(&refresh_token))
+                .bind(user_uuid)
+                .bind(token_hash(session_token))
+                .bind(to_millis(self.clock.now() + REFRESH_TOKEN_LIFETIME))
+                .execute(&self.pool))
+                .await
+                .map_err(|e| {
+                    SessionError::backend(format!("Failed to create refresh token.\n{e:?}"))
+                })?;
+
+            Ok(refresh_token)
+        }
+
+        #[tracing::instrument(skip_all, fields(db.system = "sqlite", otel.kind = "client"))]
+        async fn refresh_session(&mut self, refresh_token: &str) -> Option<String> {
+            if !self.signer.verify(refresh_token) {
+                return None;
+            }
+
+            let refresh_hash = token_hash(refresh_token);
+
+            let (user_uuid, session_hash): (String, String) = self
+                .retry
+                .run(|| {
+                    sqlx::query_as(
+                        "SELECT user_uuid, session_token_hash FROM refresh_tokens \
+                                 WHERE token_hash = $1 AND expires_at > $2",
+                    )
+                    .bind(&refresh_hash)
+                    .bind(to_millis(self.clock.now()))
+                    .fetch_optional(&self.pool)
+                })
+                .await
+                .unwrap_or_else(|e| {
+                    tracing::error!(error = ?e, "Failed to get refresh token");
+                    record_failure(FailureReason::BackendError);
+                    None
+                })?;
+
+            // The previous session is replaced rather than left alive alongside the new one.
+            // It was created by the same client, so its metadata carries over.
+            let client = self
+                .remove_session(&session_hash)
+                .await
+                .map(|session| session.client)
+                .unwrap_or_default();
+
+            let session_token = self.create_session(&user_uuid, client).await.ok()?;
+
+            let result = self
+                .retry
+                .run(|| {
+                    sqlx::query(
+                        "UPDATE refresh_tokens SET session_token_hash = $2 WHERE token_hash = $1",
+                    )
+                    .bind(&refresh_hash)
+                    .bind(token_hash(&session_token))
+                    .execute(&self.pool)
+                })
+                .await;
+
+            if let Err(e) = result {
+                tracing::error!(error = ?e, "Failed to update refresh token");
+                record_failure(FailureReason::BackendError);
+            }
+
+            Some(session_token)
+        }
+
+        #[tracing::instrument(skip_all, fields(db.system = "sqlite", otel.kind = "client"))]
+        async fn renew_session(&mut self, session_token: &str) -> Option<String> {
+            if !self.signer.verify(session_token) {
+                return None;
+            }
+
+            let session_hash = token_hash(session_token);
+            let session = self.find_session(&session_hash).await?;
+
+            let result = self
+                .retry
+                .run(|| {
+                    sqlx::query("UPDATE sessions SET expires_at = $2 WHERE token_hash = $1")
+                        .bind(&session_hash)
+                        .bind(to_millis(
+                            self.policy.expires_at(session.created_at, self.clock.now()),
+                        ))
+                        .execute(&self.pool)
+                })
+                .await;
+
+            match result {
+                Ok(_) => Some(session_token.to_string()),
+                Err(e) => {
+                    tracing::error!(error = ?e, "Failed to renew session");
+                    record_failure(FailureReason::BackendError);
+                    None
+                }
+            }
+        }
+
+        #[tracing::instrument(skip_all, fields(db.system = "sqlite", otel.kind = "client"))]
+        async fn remove_expired(&mut self) -> usize {
+            let now = to_millis(self.clock.now());
+
+            let result = async {
+                let query = format!(
+                    "DELETE FROM sessions WHERE expires_at <= $1 RETURNING {SESSION_COLUMNS}"
+                );
+                let rows: Vec<SessionRow> = self
+                    .retry
+                    .run(|| sqlx::query_as(&query).bind(now).fetch_all(&self.pool))
+                    .await?;
+
+                self.retry
+                    .run(|| {
+                        sqlx::query("DELETE FROM refresh_tokens WHERE expires_at <= $1")
+                            .bind(now)
+                            .execute(&self.pool)
+                    })
+                    .await?;
+
+                Ok::<_, sqlx::Error>(rows)
             }
+            .await;
+
+            match result {
+                Ok(rows) => {
+                    let removed = rows.len();
+                    for (session_hash, session) in rows.into_iter().map(from_row) {
+                        publish(
+                            &self.events,
+                            SessionEventKind::Expired,
+                            session_hash,
+                            &session,
+                            self.clock.now(),
+                        );
+                    }
+                    removed
+                }
+                Err(e) => {
+                    tracing::error!(error = ?e, "Failed to remove expired sessions");
+                    record_failure(FailureReason::BackendError);
+                    0
+                }
+            }
+        }
+
+        async fn flush(&mut self) {
+            self.pool.close().await;
+        }
+
+        #[tracing::instrument(skip_all, fields(db.system = "sqlite", otel.kind = "client"))]
+        async fn ping(&self) -> Result<(), SessionError> {
+            self.retry
+                .run(|| sqlx::query("SELECT 1").execute(&self.pool))
+                .await
+                .map(|_| ())
+                .map_err(|e| {
+                    SessionError::backend(format!("Failed to reach the sessions database.\n{e:?}"))
+                })
+        }
+    }
+
+    fn from_row(row: SessionRow) -> (String, Session) {
+        let (
+            session_hash,
+            session_id,
+            user_uuid,
+            created_at,
+            last_seen,
+            expires_at,
+            ip_address,
+            user_agent,
+        ) = row;
+
+        let session = Session {
+            // Only UUIDs are written, so a row can't hold anything else unless edited by hand.
+            session_id: session_id.parse().unwrap_or(SessionId::new(Uuid::nil())),
+            user_uuid: user_uuid.into(),
+            created_at: from_millis(created_at),
+            last_seen: from_millis(last_seen),
+            expires_at: from_millis(expires_at),
+            client: ClientMetadata {
+                ip_address,
+                user_agent,
+            },
         };
+
+        (session_hash, session)
+    }
+
+    fn to_millis(time: SystemTime) -> i64 {
+        time.duration_since(UNIX_EPOCH)
+            .map(|duration| duration.as_millis() as i64)
+            .unwrap_or_default()
+    }
+
+    fn from_millis(millis: i64) -> SystemTime {
+        UNIX_EPOCH + Duration::from_millis(millis as u64)
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use std::env;
+
+        use super::*;
+        use crate::clock::ManualClock;
+        use crate::random::SeededRandom;
+
+        /// Each pooled connection to `:memory:` would get its own database, so use a file.
+        async fn sqlite_sessions() -> SqliteSessions {
+            let path = env::temp_dir().join(format!("sessions-{}.db", Uuid::new_v4()));
+
+            SqliteSessions::open(
+                path.to_str().unwrap(),
+                SessionPolicy::default(),
+                Migrations::Startup,
+            )
+            .await
+            .unwrap()
+        }
+
+        #[tokio::test]
+        async fn should_get_session() {
+            let mut sessions_service = sqlite_sessions().await;
+            let session = sessions_service
+                .create_session("123456", ClientMetadata::default())
+                .await
+                .unwrap();
+
+            let result = sessions_service.get_session(&session).await.unwrap();
+
+            assert_eq!(result.user_uuid, "123456");
+            assert_eq!(
+                sessions_service
+                    .list_user_sessions("123456")
+                    .await
+                    .unwrap()
+                    .len(),
+                1
+            );
+        }
+
+        #[tokio::test]
+        async fn should_delete_session_and_refresh_token() {
+            let mut sessions_service = sqlite_sessions().await;
+            let session = sessions_service
+                .create_session("123456", ClientMetadata::default())
+                .await
+                .unwrap();
+            let refresh_token = sessions_service
+                .create_refresh_token("123456", &session)
+                .await
+                .unwrap();
+
+            sessions_service.delete_session(&session).await;
+
+            assert!(sessions_service.get_session(&session).await.is_none());
+            assert!(sessions_service
+                .refresh_session(&refresh_token)
+                .await
+                .is_none());
+        }
+
+        #[tokio::test]
+        async fn should_refresh_session() {
+            let mut sessions_service = sqlite_sessions().await;
+            let session = sessions_service
+                .create_session("123456", ClientMetadata::default())
+                .await
+                .unwrap();
+            let refresh_token = sessions_service
+                .create_refresh_token("123456", &session)
+                .await
+                .unwrap();
+
+            let refreshed = sessions_service
+                .refresh_session(&refresh_token)
+                .await
+                .unwrap();
+
+            assert!(sessions_service.get_session(&session).await.is_none());
+            assert!(sessions_service.get_session(&refreshed).await.is_some());
+            assert!(sessions_service
+                .refresh_session(&refresh_token)
+                .await
+                .is_some());
+        }
+
+        #[tokio::test]
+        async fn should_expire_sessions_as_clock_advances() {
+            let clock = ManualClock::new(UNIX_EPOCH + Duration::from_secs(1_700_000_000));
+            let mut sessions_service = sqlite_sessions().await.with_clock(Box::new(clock.clone()));
+            let session = sessions_service
+                .create_session("123456", ClientMetadata::default())
+                .await
+                .unwrap();
+
+            clock.advance(SessionPolicy::default().lifetime - Duration::from_secs(1));
+            assert!(sessions_service.get_session(&session).await.is_some());
+            clock.advance(SessionPolicy::default().max_lifetime);
+            assert!(sessions_service.get_session(&session).await.is_none());
+            assert_eq!(sessions_service.remove_expired().await, 1);
+        }
+
+        #[tokio::test]
+        async fn should_issue_same_tokens_with_seeded_random() {
+            let signer = || TokenSigner::new(vec![b"key".to_vec()]).unwrap();
+            let mut first = sqlite_sessions()
+                .await
+                .with_token_signer(signer())
+                .with_random(Box::new(SeededRandom::new(7)));
+            let mut second = sqlite_sessions()
+                .await
+                .with_token_signer(signer())
+                .with_random(Box::new(SeededRandom::new(7)));
+
+            let session = first
+                .create_session("123456", ClientMetadata::default())
+                .await
+                .unwrap();
+            assert_eq!(
+                session,
+                second
+                    .create_session("123456", ClientMetadata::default())
+                    .await
+                    .unwrap()
+            );
+            assert_eq!(
+                first.get_session(&session).await.unwrap().session_id,
+                second.get_session(&session).await.unwrap().session_id
+            );
+        }
+
+        #[tokio::test]
+        async fn should_fail_to_create_session_without_database() {
+            let mut sessions_service = sqlite_sessions().await;
+            sessions_service.flush().await;
+
+            let result = sessions_service
+                .create_session("123456", ClientMetadata::default())
+                .await;
+
+            assert!(matches!(result, Err(SessionError::Backend(_))));
+        }
+
+        #[tokio::test]
+        async fn should_fail_to_create_refresh_token_without_database() {
+            let mut sessions_service = sqlite_sessions().await;
+            let session = sessions_service
+                .create_session("123456", ClientMetadata::default())
+                .await
+                .unwrap();
+            sessions_service.flush().await;
+
+            let result = sessions_service
+                .create_refresh_token("123456", &session)
+                .await;
+
+            assert!(matches!(result, Err(SessionError::Backend(_))));
+        }
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::clock::ManualClock;
+    use crate::random::SeededRandom;
 
-    #[test]
-    fn should_create_session() {
+    #[tokio::test]
+    async fn should_create_session() {
         let mut session_service = SessionsImpl::default();
-        assert_eq!(session_service.uuid_to_session.len(), 0);
-        let session = session_service.create_session("123456");
-        assert_eq!(session_service.uuid_to_session.len(), 1);
+        assert_eq!(session_service.token_to_session.len(), 0);
+        let session = session_service
+            .create_session("123456", ClientMetadata::default())
+            .await
+            .unwrap();
+        assert_eq!(session_service.token_to_session.len(), 1);
         assert_eq!(
-            session_service.uuid_to_session.get("123456").unwrap(),
-            &session
+            session_service
+                .token_to_session
+                .get(&session)
+                .unwrap()
+                .user_uuid,
+            "123456"
         );
     }
 
+    #[tokio::test]
+    async fn should_get_session() {
+        let mut session_service = SessionsImpl::default();
+        let session = session_service
+            .create_session("123456", ClientMetadata::default())
+            .await
+            .unwrap();
+
+        let result = session_service.get_session(&session).await.unwrap();
+
+        assert_eq!(result.user_uuid, "123456");
+        assert!(result.expires_at > SystemTime::now());
+    }
+
+    #[tokio::test]
+    async fn should_update_last_seen_on_get_session() {
+        let mut session_service = SessionsImpl::default();
+        let session = session_service
+            .create_session("123456", ClientMetadata::default())
+            .await
+            .unwrap();
+        let created_at = session_service
+            .token_to_session
+            .get(&session)
+            .unwrap()
+            .created_at;
+        session_service
+            .token_to_session
+            .get_mut(&session)
+            .unwrap()
+            .last_seen = created_at - Duration::from_secs(60);
+
+        let result = session_service.get_session(&session).await.unwrap();
+
+        assert!(result.last_seen >= created_at);
+    }
+
+    #[tokio::test]
+    async fn should_list_user_sessions() {
+        let signer = || TokenSigner::new(vec![b"0123456789abcdef".to_vec()]).unwrap();
+        let mut session_service = SessionsImpl::default().with_token_signer(signer());
+        let client = ClientMetadata {
+            ip_address: "127.0.0.1".to_owned(),
+            user_agent: "test".to_owned(),
+        };
+        session_service
+            .create_session("123456", client)
+            .await
+            .unwrap();
+        session_service
+            .create_session("123456", ClientMetadata::default())
+            .await
+            .unwrap();
+        session_service
+            .create_session("654321", ClientMetadata::default())
+            .await
+            .unwrap();
+
+        let sessions = session_service.list_user_sessions("123456").await.unwrap();
+
+        assert_eq!(sessions.len(), 2);
+        assert!(sessions.iter().all(|session| session.user_uuid == "123456"));
+        assert!(sessions
+            .iter()
+            .any(|session| session.client.user_agent == "test"));
+    }
+
+    #[tokio::test]
+    async fn should_not_list_expired_sessions() {
+        let mut session_service = SessionsImpl::default();
+        let session = session_service
+            .create_session("123456", ClientMetadata::default())
+            .await
+            .unwrap();
+        session_service
+            .token_to_session
+            .get_mut(&session)
+            .unwrap()
+            .expires_at = SystemTime::now() - Duration::from_secs(1);
+
+        assert!(session_service
+            .list_user_sessions("123456")
+            .await
+            .unwrap()
+            .is_empty());
+    }
+
+    #[tokio::test]
+    async fn should_not_get_expired_session() {
+        let mut session_service = SessionsImpl::default();
+        let session = session_service
+            .create_session("123456", ClientMetadata::default())
+            .await
+            .unwrap();
+        session_service
+            .token_to_session
+            .get_mut(&session)
+            .unwrap()
+            .expires_at = SystemTime::now() - Duration::from_secs(1);
+
+        assert!(session_service.get_session(&session).await.is_none());
+    }
+
+    #[tokio::test]
+    async fn should_delete_session() {
+        let mut session_service = SessionsImpl::default();
+        let session = session_service
+            .create_session("123456", ClientMetadata::default())
+            .await
+            .unwrap();
+        session_service.delete_session(&session).await;
+        assert_eq!(session_service.token_to_session.len(), 0);
+    }
+
+    #[tokio::test]
+    async fn should_delete_user_sessions() {
+        let mut session_service = SessionsImpl::default();
+        let session = session_service
+            .create_session("123456", ClientMetadata::default())
+            .await
+            .unwrap();
+        session_service
+            .create_refresh_token("123456", &session)
+            .await
+            .unwrap();
+        session_service
+            .create_session("123456", ClientMetadata::default())
+            .await
+            .unwrap();
+        let other_session = session_service
+            .create_session("654321", ClientMetadata::default())
+            .await
+            .unwrap();
+
+        session_service.delete_user_sessions("123456").await;
+
+        assert_eq!(session_service.token_to_session.len(), 1);
+        assert!(session_service.get_session(&other_session).await.is_some());
+        assert_eq!(session_service.refresh_token_to_session.len(), 0);
+    }
+
+    #[tokio::test]
+    async fn should_refresh_session() {
+        let mut session_service = SessionsImpl::default();
+        let session = session_service
+            .create_session("123456", ClientMetadata::default())
+            .await
+            .unwrap();
+        let refresh_token = session_service
+            .create_refresh_token("123456", &session)
+            .await
+            .unwrap();
+
+        let new_session = session_service
+            .refresh_session(&refresh_token)
+            .await
+            .unwrap();
+
+        assert_ne!(new_session, session);
+        assert!(session_service.get_session(&session).await.is_none());
+        assert_eq!(
+            session_service
+                .get_session(&new_session)
+                .await
+                .unwrap()
+                .user_uuid,
+            "123456"
+        );
+    }
+
+    #[tokio::test]
+    async fn should_not_refresh_with_expired_refresh_token() {
+        let mut session_service = SessionsImpl::default();
+        let session = session_service
+            .create_session("123456", ClientMetadata::default())
+            .await
+            .unwrap();
+        let refresh_token = session_service
+            .create_refresh_token("123456", &session)
+            .await
+            .unwrap();
+        session_service
+            .refresh_token_to_session
+            .get_mut(&refresh_token)
+            .unwrap()
+            .expires_at = SystemTime::now() - Duration::from_secs(1);
+
+        assert!(session_service
+            .refresh_session(&refresh_token)
+            .await
+            .is_none());
+    }
+
+    #[tokio::test]
+    async fn should_revoke_refresh_token_when_session_deleted() {
+        let mut session_service = SessionsImpl::default();
+        let session = session_service
+            .create_session("123456", ClientMetadata::default())
+            .await
+            .unwrap();
+        let refresh_token = session_service
+            .create_refresh_token("123456", &session)
+            .await
+            .unwrap();
+
+        session_service.delete_session(&session).await;
+
+        assert!(session_service
+            .refresh_session(&refresh_token)
+            .await
+            .is_none());
+    }
+    #[tokio::test]
+    async fn should_delete_session_by_refresh_token() {
+        let mut session_service = SessionsImpl::default();
+        let session = session_service
+            .create_session("123456", ClientMetadata::default())
+            .await
+            .unwrap();
+        let refresh_token = session_service
+            .create_refresh_token("123456", &session)
+            .await
+            .unwrap();
+
+        session_service.delete_session(&refresh_token).await;
+
+        assert!(session_service.get_session(&session).await.is_none());
+        assert!(session_service
+            .refresh_session(&refresh_token)
+            .await
+            .is_none());
+    }
+    #[tokio::test]
+    async fn should_revoke_refresh_token_when_refreshed_session_deleted() {
+        let mut session_service = SessionsImpl::default();
+        let session = session_service
+            .create_session("123456", ClientMetadata::default())
+            .await
+            .unwrap();
+        let refresh_token = session_service
+            .create_refresh_token("123456", &session)
+            .await
+            .unwrap();
+        let new_session = session_service
+            .refresh_session(&refresh_token)
+            .await
+            .unwrap();
+
+        session_service.delete_session(&new_session).await;
+
+        assert!(session_service.refresh_token_to_session.is_empty());
+        assert!(session_service.session_to_refresh_tokens.is_empty());
+        assert!(session_service.uuid_to_refresh_tokens.is_empty());
+    }
+    #[tokio::test]
+    async fn should_not_update_last_seen_on_peek_session() {
+        let mut session_service = SessionsImpl::default();
+        let session = session_service
+            .create_session("123456", ClientMetadata::default())
+            .await
+            .unwrap();
+        let last_seen = session_service
+            .token_to_session
+            .get(&session)
+            .unwrap()
+            .last_seen;
+
+        let peeked = session_service.peek_session(&session).await.unwrap();
+
+        assert_eq!(peeked.last_seen, last_seen);
+        assert!(session_service.peek_session("unknown").await.is_none());
+    }
+    #[tokio::test]
+    async fn should_use_configured_session_lifetime() {
+        let mut session_service = SessionsImpl::new(SessionPolicy {
+            lifetime: Duration::from_secs(60),
+            ..Default::default()
+        });
+        let session = session_service
+            .create_session("123456", ClientMetadata::default())
+            .await
+            .unwrap();
+
+        let result = session_service.get_session(&session).await.unwrap();
+
+        assert!(result.expires_at <= SystemTime::now() + Duration::from_secs(60));
+    }
+
+    #[tokio::test]
+    async fn should_remove_expired_sessions() {
+        let clock = ManualClock::new(UNIX_EPOCH + Duration::from_secs(1_700_000_000));
+        let mut session_service = SessionsImpl::default().with_clock(Box::new(clock.clone()));
+        let expired = session_service
+            .create_session("123456", ClientMetadata::default())
+            .await
+            .unwrap();
+        session_service
+            .create_refresh_token("123456", &expired)
+            .await
+            .unwrap();
+        clock.advance(REFRESH_TOKEN_LIFETIME);
+        let session = session_service
+            .create_session("123456", ClientMetadata::default())
+            .await
+            .unwrap();
+
+        assert_eq!(session_service.remove_expired().await, 1);
+        assert_eq!(session_service.token_to_session.len(), 1);
+        assert!(session_service.get_session(&session).await.is_some());
+        assert!(session_service.refresh_token_to_session.is_empty());
+    }
+
+    #[tokio::test]
+    async fn should_keep_renewed_sessions_on_cleanup() {
+        let clock = ManualClock::new(UNIX_EPOCH + Duration::from_secs(1_700_000_000));
+        let mut session_service = SessionsImpl::new(SessionPolicy {
+            lifetime: Duration::from_secs(60),
+            max_lifetime: Duration::from_secs(300),
+            sliding: true,
+        })
+        .with_clock(Box::new(clock.clone()));
+        let session = session_service
+            .create_session("123456", ClientMetadata::default())
+            .await
+            .unwrap();
+
+        // Renewed by the validation, so still valid when its first expiry comes due
+        clock.advance(Duration::from_secs(30));
+        assert!(session_service.get_session(&session).await.is_some());
+        clock.advance(Duration::from_secs(30));
+        assert_eq!(session_service.remove_expired().await, 0);
+        assert!(session_service.get_session(&session).await.is_some());
+
+        clock.advance(Duration::from_secs(60));
+        assert_eq!(session_service.remove_expired().await, 1);
+    }
+
+    #[tokio::test]
+    async fn should_expire_sessions_as_clock_advances() {
+        let clock = ManualClock::new(UNIX_EPOCH + Duration::from_secs(1_700_000_000));
+        let mut session_service = SessionsImpl::new(SessionPolicy {
+            lifetime: Duration::from_secs(60),
+            max_lifetime: Duration::from_secs(90),
+            sliding: true,
+        })
+        .with_clock(Box::new(clock.clone()));
+        let session = session_service
+            .create_session("123456", ClientMetadata::default())
+            .await
+            .unwrap();
+
+        // Each use renews the session, until its max lifetime
+        clock.advance(Duration::from_secs(59));
+        assert!(session_service.get_session(&session).await.is_some());
+        clock.advance(Duration::from_secs(30));
+        assert!(session_service.get_session(&session).await.is_some());
+        clock.advance(Duration::from_secs(1));
+        assert!(session_service.get_session(&session).await.is_none());
+        assert_eq!(session_service.remove_expired().await, 1);
+    }
+
+    #[tokio::test]
+    async fn should_issue_same_tokens_with_seeded_random() {
+        let session_service = || {
+            SessionsImpl::default()
+                .with_token_signer(TokenSigner::new(vec![b"key".to_vec()]).unwrap())
+                .with_random(Box::new(SeededRandom::new(7)))
+        };
+        let (mut first, mut second) = (session_service(), session_service());
+
+        let session = first
+            .create_session("123456", ClientMetadata::default())
+            .await
+            .unwrap();
+        assert_eq!(
+            session,
+            second
+                .create_session("123456", ClientMetadata::default())
+                .await
+                .unwrap()
+        );
+        assert_eq!(
+            first.get_session(&session).await.unwrap().session_id,
+            second.get_session(&session).await.unwrap().session_id
+        );
+        assert_eq!(
+            first
+                .create_refresh_token("123456", &session)
+                .await
+                .unwrap(),
+            second
+                .create_refresh_token("123456", &session)
+                .await
+                .unwrap()
+        );
+    }
+
+    #[tokio::test]
+    async fn should_renew_session_up_to_max_lifetime() {
+        let mut session_service = SessionsImpl::new(SessionPolicy {
+            lifetime: Duration::from_secs(60),
+            max_lifetime: Duration::from_secs(90),
+            sliding: false,
+        });
+        let session = session_service
+            .create_session("123456", ClientMetadata::default())
+            .await
+            .unwrap();
+        let created_at = SystemTime::now() - Duration::from_secs(60);
+        {
+            let mut stored = session_service.token_to_session.get_mut(&session).unwrap();
+            stored.created_at = created_at;
+            stored.expires_at = SystemTime::now() + Duration::from_secs(1);
+        }
+
+        assert_eq!(
+            session_service.renew_session(&session).await,
+            Some(session.clone())
+        );
+        assert_eq!(
+            session_service
+                .get_session(&session)
+                .await
+                .unwrap()
+                .expires_at,
+            created_at + Duration::from_secs(90)
+        );
+        assert!(session_service.renew_session("unknown").await.is_none());
+    }
+
+    #[tokio::test]
+    async fn should_renew_session_on_get_when_sliding() {
+        let mut session_service = SessionsImpl::new(SessionPolicy {
+            sliding: true,
+            ..Default::default()
+        });
+        let session = session_service
+            .create_session("123456", ClientMetadata::default())
+            .await
+            .unwrap();
+        let expires_at = SystemTime::now() + Duration::from_secs(1);
+        session_service
+            .token_to_session
+            .get_mut(&session)
+            .unwrap()
+            .expires_at = expires_at;
+
+        assert!(
+            session_service
+                .get_session(&session)
+                .await
+                .unwrap()
+                .expires_at
+                > expires_at
+        );
+    }
+    #[tokio::test]
+    async fn should_delete_user_session_by_id() {
+        let mut session_service = SessionsImpl::default();
+        let session = session_service
+            .create_session("123456", ClientMetadata::default())
+            .await
+            .unwrap();
+        let refresh_token = session_service
+            .create_refresh_token("123456", &session)
+            .await
+            .unwrap();
+        let other_session = session_service
+            .create_session("123456", ClientMetadata::default())
+            .await
+            .unwrap();
+        let session_id = session_service
+            .get_session(&session)
+            .await
+            .unwrap()
+            .session_id;
+
+        // Only the owner can delete a session.
+        session_service
+            .delete_user_session("654321", session_id)
+            .await;
+        assert!(session_service.get_session(&session).await.is_some());
+
+        session_service
+            .delete_user_session("123456", session_id)
+            .await;
+
+        assert!(session_service.get_session(&session).await.is_none());
+        assert!(session_service
+            .refresh_session(&refresh_token)
+            .await
+            .is_none());
+        assert!(session_service.get_session(&other_session).await.is_some());
+        assert_eq!(
+            session_service
+                .list_user_sessions("123456")
+                .await
+                .unwrap()
+                .len(),
+            1
+        );
+    }
+    #[tokio::test]
+    async fn should_reject_tampered_session_token() {
+        let mut session_service = SessionsImpl::default();
+        let session = session_service
+            .create_session("123456", ClientMetadata::default())
+            .await
+            .unwrap();
+        let (id, _) = session.rsplit_once('.').unwrap();
+
+        assert!(session_service
+            .get_session(&format!("{}.tampered", id))
+            .await
+            .is_none());
+        assert!(session_service.get_session(&session).await.is_some());
+    }
+
+    #[tokio::test]
+    async fn should_accept_tokens_signed_before_key_rotation() {
+        let mut session_service = SessionsImpl::default()
+            .with_token_signer(TokenSigner::new(vec![b"old".to_vec()]).unwrap());
+        let session = session_service
+            .create_session("123456", ClientMetadata::default())
+            .await
+            .unwrap();
+
+        session_service.signer = TokenSigner::new(vec![b"new".to_vec(), b"old".to_vec()]).unwrap();
+
+        assert!(session_service.get_session(&session).await.is_some());
+    }
+    #[tokio::test]
+    async fn should_restore_snapshot() {
+        let signer = || TokenSigner::new(vec![b"0123456789abcdef".to_vec()]).unwrap();
+        let mut session_service = SessionsImpl::default().with_token_signer(signer());
+        let client = ClientMetadata {
+            ip_address: "127.0.0.1".to_owned(),
+            user_agent: "client\t1.0\n".to_owned(),
+        };
+        let session = session_service
+            .create_session("123456", client)
+            .await
+            .unwrap();
+        let refresh_token = session_service
+            .create_refresh_token("123456", &session)
+            .await
+            .unwrap();
+
+        let mut restored = SessionsImpl::default().with_token_signer(signer());
+        restored.restore(&session_service.snapshot()).unwrap();
+
+        let result = restored.get_session(&session).await.unwrap();
+        assert_eq!(result.user_uuid, "123456");
+        assert_eq!(result.client.user_agent, "client\t1.0\n");
+        assert_eq!(
+            restored.list_user_sessions("123456").await.unwrap().len(),
+            1
+        );
+        assert!(restored.refresh_session(&refresh_token).await.is_some());
+    }
+    #[tokio::test]
+    async fn should_not_restore_expired_sessions() {
+        let mut session_service = SessionsImpl::default();
+        let session = session_service
+            .create_session("123456", ClientMetadata::default())
+            .await
+            .unwrap();
+        session_service
+            .token_to_session
+            .get_mut(&session)
+            .unwrap()
+            .expires_at = SystemTime::now() - Duration::from_secs(1);
+
+        let mut restored = SessionsImpl::default();
+        restored.restore(&session_service.snapshot()).unwrap();
+
+        assert!(restored.token_to_session.is_empty());
+        assert!(restored.uuid_to_tokens.is_empty());
+    }
     #[test]
-    fn should_delete_session() {
+    fn should_reject_invalid_snapshot() {
         let mut session_service = SessionsImpl::default();
-        session_service.create_session("123456");
-        session_service.delete_session("123456");
-        assert_eq!(session_service.uuid_to_session.len(), 0);
+
+        assert!(session_service.restore("session\tabc\n").is_err());
+        assert!(session_service
+            .restore("session\tabc.def\tnot-a-uuid\t123456\t0\t0\t0\t\t\n")
+            .is_err());
+    }
+
+    #[test]
+    fn should_print_and_parse_session_id() {
+        let session_id = SessionId::new(Uuid::from_u128(1));
+
+        assert_eq!(
+            session_id.to_string(),
+            "00000000-0000-0000-0000-000000000001"
+        );
+        assert_eq!(session_id.to_string().parse(), Ok(session_id));
+        assert!("123456".parse::<SessionId>().is_err());
+    }
+    #[tokio::test]
+    async fn should_publish_session_events() {
+        let (sender, mut receiver) = broadcast::channel(16);
+        let clock = ManualClock::new(UNIX_EPOCH + Duration::from_secs(1_700_000_000));
+        let mut session_service = SessionsImpl::default()
+            .with_events(sender)
+            .with_clock(Box::new(clock.clone()));
+
+        let session = session_service
+            .create_session("123456", ClientMetadata::default())
+            .await
+            .unwrap();
+        let expired = session_service
+            .create_session("123456", ClientMetadata::default())
+            .await
+            .unwrap();
+        session_service.delete_session(&session).await;
+        clock.advance(SESSION_LIFETIME);
+        session_service.remove_expired().await;
+
+        let events: Vec<(SessionEventKind, String)> =
+            std::iter::from_fn(|| receiver.try_recv().ok())
+                .map(|event| (event.kind, event.token_hash))
+                .collect();
+
+        assert_eq!(
+            events,
+            vec![
+                (SessionEventKind::Created, token_hash(&session)),
+                (SessionEventKind::Created, token_hash(&expired)),
+                (SessionEventKind::Deleted, token_hash(&session)),
+                (SessionEventKind::Expired, token_hash(&expired)),
+            ]
+        );
     }
 }
@@ -0,0 +1,177 @@
+use std::future::Future;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use parking_lot::Mutex;
+
+/// How long storage calls failing transiently are retried for by default.
+pub const DEFAULT_STORAGE_RETRY_BUDGET: Duration = Duration::from_millis(500);
+
+/// Health service name reported `NOT_SERVING` while the storage is degraded, even though the auth
+/// services are still `SERVING`.
+pub const STORAGE_HEALTH_SERVICE_NAME: &str = "authentication.Storage";
+
+/// The wait before the first retry, doubled after each one up to `MAX_BACKOFF`.
+const FIRST_BACKOFF: Duration = Duration::from_millis(10);
+const MAX_BACKOFF: Duration = Duration::from_millis(200);
+
+/// How long the storage is reported degraded after its last transient failure.
+const DEGRADED_FOR: Duration = Duration::from_secs(60);
+
+/// Errors worth retrying, because the call failed without any effect and may well succeed if
+/// made again, e.g. because the database was busy.
+pub trait Transient {
+    fn is_transient(&self) -> bool;
+}
+
+/// Retries storage calls failing transiently with exponential backoff, until `budget` has passed
+/// since the first attempt. Clones share when the storage last failed, so the stores can report
+/// it to the health service.
+#[derive(Clone)]
+pub struct StorageRetry {
+    budget: Duration,
+    last_transient_failure: Arc<Mutex<Option<Instant>>>,
+}
+
+impl StorageRetry {
+    pub fn new(budget: Duration) -> Self {
+        Self {
+            budget,
+            last_transient_failure: Arc::default(),
+        }
+    }
+
+    /// Whether a storage call failed transiently in the last minute, even if a retry succeeded.
+    pub fn is_degraded(&self) -> bool {
+        self.last_transient_failure
+            .lock()
+            .is_some_and(|failed_at| failed_at.elapsed() < DEGRADED_FOR)
+    }
+
+    /// Makes the call with `attempt` until it succeeds, fails for good or the budget runs out.
+    /// Returns the result of the last attempt, so callers report running out like any failure.
+    #[cfg_attr(not(any(feature = "postgres", feature = "sqlite")), allow(dead_code))]
+    pub async fn run<T, E, F>(&self, mut attempt: impl FnMut() -> F) -> Result<T, E>
+    where
+        E: Transient,
+        F: Future<Output = Result<T, E>>,
+    {
+        let started = Instant::now();
+        let mut backoff = FIRST_BACKOFF;
+        loop {
+            let result = attempt().await;
+            match &result {
+                Err(e) if e.is_transient() => (),
+                _ => return result,
+            }
+
+            *self.last_transient_failure.lock() = Some(Instant::now());
+            if started.elapsed() + backoff > self.budget {
+                tracing::warn!(budget = ?self.budget, "Storage retry budget exhausted");
+                return result;
+            }
+            tokio::time::sleep(backoff).await;
+            backoff = (backoff * 2).min(MAX_BACKOFF);
+        }
+    }
+}
+
+impl Default for StorageRetry {
+    fn default() -> Self {
+        Self::new(DEFAULT_STORAGE_RETRY_BUDGET)
+    }
+}
+
+/// Only errors after which the statement surely didn't run are transient, so writes aren't
+/// applied twice: no connection was free, or the database was busy or aborted the transaction.
+/// Dropped connections aren't retried, as the statement may have run before.
+#[cfg(any(feature = "postgres", feature = "sqlite"))]
+impl Transient for sqlx::Error {
+    fn is_transient(&self) -> bool {
+        match self {
+            sqlx::Error::PoolTimedOut => true,
+            sqlx::Error::Database(db_error) => match db_error.code() {
+                // Postgres: serialization failure, deadlock, starting up and too many connections
+                Some(code) if ["40001", "40P01", "57P03", "53300"].contains(&code.as_ref()) => true,
+                // SQLite: SQLITE_BUSY and SQLITE_LOCKED, including their extended codes
+                Some(code) => code
+                    .parse::<i32>()
+                    .is_ok_and(|code| matches!(code & 0xff, 5 | 6)),
+                None => false,
+            },
+            _ => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use super::*;
+
+    #[derive(Debug, PartialEq)]
+    enum TestError {
+        Busy,
+        Broken,
+    }
+
+    impl Transient for TestError {
+        fn is_transient(&self) -> bool {
+            *self == TestError::Busy
+        }
+    }
+
+    #[tokio::test]
+    async fn should_retry_transient_errors() {
+        let retry = StorageRetry::default();
+        let attempts = AtomicUsize::new(0);
+
+        let result = retry
+            .run(|| async {
+                match attempts.fetch_add(1, Ordering::SeqCst) {
+                    0 | 1 => Err(TestError::Busy),
+                    attempt => Ok(attempt),
+                }
+            })
+            .await;
+
+        assert_eq!(result, Ok(2));
+        assert!(retry.is_degraded());
+    }
+
+    #[tokio::test]
+    async fn should_not_retry_other_errors() {
+        let retry = StorageRetry::default();
+        let attempts = AtomicUsize::new(0);
+
+        let result: Result<(), _> = retry
+            .run(|| async {
+                attempts.fetch_add(1, Ordering::SeqCst);
+                Err(TestError::Broken)
+            })
+            .await;
+
+        assert_eq!(result, Err(TestError::Broken));
+        assert_eq!(attempts.load(Ordering::SeqCst), 1);
+        assert!(!retry.is_degraded());
+    }
+
+    #[tokio::test]
+    async fn should_give_up_after_budget() {
+        let retry = StorageRetry::new(Duration::from_millis(25));
+        let attempts = AtomicUsize::new(0);
+
+        let result: Result<(), _> = retry
+            .run(|| async {
+                attempts.fetch_add(1, Ordering::SeqCst);
+                Err(TestError::Busy)
+            })
+            .await;
+
+        // Waits 10ms, then gives up rather than waiting another 20ms
+        assert_eq!(result, Err(TestError::Busy));
+        assert_eq!(attempts.load(Ordering::SeqCst), 2);
+        assert!(retry.is_degraded());
+    }
+}
@@ -0,0 +1,107 @@
+use tracing::Span;
+
+#[cfg(feature = "otel")]
+pub use otlp::exporter;
+
+/// Span each RPC is handled in, named after its method. `RequestIdLayer` records the request
+/// ID on it. With the `otel` feature, it continues the trace the caller sent in its
+/// `traceparent` metadata, if any.
+pub fn rpc_span(request: &http::Request<()>) -> Span {
+    let method = request.uri().path();
+    let span = tracing::info_span!(
+        "rpc",
+        method,
+        request_id = tracing::field::Empty,
+        otel.name = method.trim_start_matches('/'),
+        otel.kind = "server",
+        rpc.system = "grpc",
+    );
+
+    #[cfg(feature = "otel")]
+    otlp::continue_trace(&span, request.headers());
+
+    span
+}
+
+/// Sends the spans the exporter still holds, if there is one.
+pub fn flush() {
+    #[cfg(feature = "otel")]
+    opentelemetry::global::shutdown_tracer_provider();
+}
+
+#[cfg(feature = "otel")]
+mod otlp {
+    use http::HeaderMap;
+    use opentelemetry::propagation::{Extractor, TextMapPropagator};
+    use opentelemetry::sdk::propagation::TraceContextPropagator;
+    use opentelemetry::sdk::{trace, Resource};
+    use opentelemetry::KeyValue;
+    use opentelemetry_otlp::WithExportConfig;
+    use tracing::Span;
+    use tracing_opentelemetry::OpenTelemetrySpanExt;
+
+    use crate::config::OtlpConfig;
+    use crate::logging::Exporter;
+
+    /// Exports spans over OTLP/gRPC, batching them in the background.
+    pub fn exporter(config: &OtlpConfig) -> Result<Exporter, String> {
+        let resource = Resource::new([KeyValue::new("service.name", config.service_name.clone())]);
+        let tracer = opentelemetry_otlp::new_pipeline()
+            .tracing()
+            .with_exporter(
+                opentelemetry_otlp::new_exporter()
+                    .tonic()
+                    .with_endpoint(&config.endpoint),
+            )
+            .with_trace_config(trace::config().with_resource(resource))
+            .install_batch(opentelemetry::runtime::Tokio)
+            .map_err(|e| format!("Failed to set up the OTLP exporter.\n{e:?}"))?;
+
+        Ok(Box::new(tracing_opentelemetry::layer().with_tracer(tracer)))
+    }
+
+    /// Makes `span` a child of the W3C trace context in `headers`. It starts a new trace without
+    /// one.
+    pub fn continue_trace(span: &Span, headers: &HeaderMap) {
+        let parent = TraceContextPropagator::new().extract(&Metadata(headers));
+        span.set_parent(parent);
+    }
+
+    struct Metadata<'a>(&'a HeaderMap);
+
+    impl Extractor for Metadata<'_> {
+        fn get(&self, key: &str) -> Option<&str> {
+            self.0.get(key).and_then(|value| value.to_str().ok())
+        }
+
+        fn keys(&self) -> Vec<&str> {
+            self.0.keys().map(|key| key.as_str()).collect()
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use opentelemetry::trace::TraceContextExt;
+
+        use super::*;
+
+        #[test]
+        fn should_read_trace_context_from_metadata() {
+            let mut headers = HeaderMap::new();
+            headers.insert(
+                "traceparent",
+                "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01"
+                    .parse()
+                    .unwrap(),
+            );
+
+            let parent = TraceContextPropagator::new().extract(&Metadata(&headers));
+            let span_context = parent.span().span_context().clone();
+            assert_eq!(
+                span_context.trace_id().to_string(),
+                "4bf92f3577b34da6a3ce929d0e0e4736"
+            );
+            assert!(span_context.is_remote());
+        }
+    }
+}
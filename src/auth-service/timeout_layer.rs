@@ -0,0 +1,155 @@
+use std::collections::HashMap;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::time::Duration;
+
+use tonic::body::BoxBody;
+use tonic::Status;
+use tower::{Layer, Service};
+
+/// Fails requests their handler hasn't answered in time as `DEADLINE_EXCEEDED`. Methods are
+/// named without their service, e.g. `SignIn`, and fall back to the default timeout. Clients can
+/// still ask for a shorter deadline with `grpc-timeout`.
+#[derive(Clone)]
+pub struct TimeoutLayer {
+    default: Option<Duration>,
+    methods: Arc<HashMap<String, Duration>>,
+}
+
+impl TimeoutLayer {
+    pub fn new(default: Option<Duration>, methods: &[(String, Duration)]) -> Self {
+        Self {
+            default,
+            methods: Arc::new(methods.iter().cloned().collect()),
+        }
+    }
+
+    fn timeout(&self, path: &str) -> Option<Duration> {
+        // gRPC paths are `/<package>.<service>/<method>`.
+        let method = path.rsplit('/').next().unwrap_or_default();
+        self.methods.get(method).copied().or(self.default)
+    }
+}
+
+impl<S> Layer<S> for TimeoutLayer {
+    type Service = Timeout<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        Timeout {
+            inner,
+            timeouts: self.clone(),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct Timeout<S> {
+    inner: S,
+    timeouts: TimeoutLayer,
+}
+
+impl<S, B> Service<http::Request<B>> for Timeout<S>
+where
+    S: Service<http::Request<B>, Response = http::Response<BoxBody>> + Clone + Send + 'static,
+    S::Future: Send + 'static,
+    B: Send + 'static,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, request: http::Request<B>) -> Self::Future {
+        let timeout = self.timeouts.timeout(request.uri().path());
+
+        // The clone may not be ready, so call the instance `poll_ready` was called on.
+        let clone = self.inner.clone();
+        let mut inner = std::mem::replace(&mut self.inner, clone);
+        let response = inner.call(request);
+
+        let Some(timeout) = timeout else {
+            return Box::pin(response);
+        };
+        Box::pin(async move {
+            match tokio::time::timeout(timeout, response).await {
+                Ok(response) => response,
+                Err(_) => Ok(Status::deadline_exceeded("Request timed out").to_http()),
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Answers after sleeping for as many milliseconds as the method name says.
+    #[derive(Clone)]
+    struct Sleepy;
+
+    impl Service<http::Request<()>> for Sleepy {
+        type Response = http::Response<BoxBody>;
+        type Error = std::convert::Infallible;
+        type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send>>;
+
+        fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+            Poll::Ready(Ok(()))
+        }
+
+        fn call(&mut self, request: http::Request<()>) -> Self::Future {
+            let millis = request.uri().path().rsplit('/').next().unwrap().parse();
+            Box::pin(async move {
+                tokio::time::sleep(Duration::from_millis(millis.unwrap())).await;
+                Ok(http::Response::new(tonic::body::empty_body()))
+            })
+        }
+    }
+
+    fn request(method: &str) -> http::Request<()> {
+        http::Request::builder()
+            .uri(format!("/authentication.Auth/{method}"))
+            .body(())
+            .unwrap()
+    }
+
+    fn timed_out(response: &http::Response<BoxBody>) -> bool {
+        response
+            .headers()
+            .get("grpc-status")
+            .map(|status| status.to_str().unwrap())
+            == Some(&(tonic::Code::DeadlineExceeded as i32).to_string())
+    }
+
+    #[tokio::test]
+    async fn should_time_out_slow_requests() {
+        let mut service = TimeoutLayer::new(
+            Some(Duration::from_millis(100)),
+            &[("300".to_owned(), Duration::from_secs(1))],
+        )
+        .layer(Sleepy);
+
+        let response = service.call(request("10")).await.unwrap();
+        assert!(!timed_out(&response));
+
+        let response = service.call(request("200")).await.unwrap();
+        assert!(timed_out(&response));
+
+        // Methods with their own timeout don't use the default.
+        let response = service.call(request("300")).await.unwrap();
+        assert!(!timed_out(&response));
+    }
+
+    #[tokio::test]
+    async fn should_not_time_out_without_timeout() {
+        let mut service = TimeoutLayer::new(None, &[]).layer(Sleepy);
+
+        let response = service.call(request("200")).await.unwrap();
+
+        assert!(!timed_out(&response));
+    }
+}
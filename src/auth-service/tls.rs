@@ -0,0 +1,266 @@
+use std::fs::File;
+use std::io::BufReader;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+
+use parking_lot::RwLock;
+use rustls_pemfile::Item;
+use socket2::{SockRef, TcpKeepalive};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::mpsc;
+use tokio_rustls::rustls::server::AllowAnyAuthenticatedClient;
+use tokio_rustls::rustls::{Certificate, PrivateKey, RootCertStore, ServerConfig};
+use tokio_rustls::server::TlsStream;
+use tokio_rustls::TlsAcceptor;
+use tokio_stream::wrappers::ReceiverStream;
+use tonic::service::Interceptor;
+use tonic::{Request, Status};
+use x509_parser::extensions::GeneralName;
+
+use crate::auth::ClientIdentity;
+use crate::config::TlsConfig;
+
+/// How long clients get to complete the TLS handshake.
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+/// Handshaken connections waiting for the server to pick them up.
+const PENDING_CONNECTIONS: usize = 128;
+
+/// Connections for `Server::serve_with_incoming`.
+pub type TlsIncoming = ReceiverStream<Result<TlsStream<TcpStream>, std::io::Error>>;
+
+/// Terminates TLS with a certificate and key loaded from PEM files, which can be replaced
+/// while the server runs. With a client CA, callers must present a certificate it issued.
+#[derive(Clone)]
+pub struct ReloadableTls {
+    tls_config: Arc<TlsConfig>,
+    config: Arc<RwLock<Arc<ServerConfig>>>,
+}
+
+impl ReloadableTls {
+    /// Fails if the files can't be read or don't hold a matching certificate chain and key.
+    pub fn load(tls_config: &TlsConfig) -> Result<Self, String> {
+        Ok(Self {
+            tls_config: Arc::new(tls_config.clone()),
+            config: Arc::new(RwLock::new(Arc::new(server_config(tls_config)?))),
+        })
+    }
+
+    /// Checks the files for changes every `interval`, loading them again when they do. New
+    /// connections use the new certificate, existing ones keep theirs.
+    pub fn spawn_reload(&self, interval: Duration) {
+        let tls = self.clone();
+
+        tokio::spawn(async move {
+            let mut modified = tls.modified();
+            let mut interval = tokio::time::interval(interval);
+            loop {
+                interval.tick().await;
+
+                let current = tls.modified();
+                if current == modified {
+                    continue;
+                }
+                // The certificate and key are rarely replaced at the same instant, a mismatch is
+                // retried once the other file changes too.
+                modified = current;
+
+                match server_config(&tls.tls_config) {
+                    Ok(config) => {
+                        *tls.config.write() = Arc::new(config);
+                        tracing::info!(path = tls.tls_config.cert_path, "Reloaded TLS certificate");
+                    }
+                    Err(e) => {
+                        tracing::warn!(error = %e, "Failed to reload TLS certificate, keeping the old one")
+                    }
+                }
+            }
+        });
+    }
+
+    /// Listens on `addr`, handing connections to the server once their handshake completes.
+    /// Accepted connections get TCP keepalive probes after `tcp_keepalive` idle.
+    pub async fn incoming(
+        self,
+        addr: SocketAddr,
+        tcp_keepalive: Option<Duration>,
+    ) -> Result<TlsIncoming, String> {
+        let listener = TcpListener::bind(addr)
+            .await
+            .map_err(|e| format!("Failed to listen on {addr}.\n{e:?}"))?;
+        let (sender, receiver) = mpsc::channel(PENDING_CONNECTIONS);
+
+        tokio::spawn(async move {
+            while !sender.is_closed() {
+                let stream = match listener.accept().await {
+                    Ok((stream, _)) => stream,
+                    Err(e) => {
+                        tracing::warn!(error = ?e, "Failed to accept connection");
+                        continue;
+                    }
+                };
+                if let Some(tcp_keepalive) = tcp_keepalive {
+                    let keepalive = TcpKeepalive::new().with_time(tcp_keepalive);
+                    if let Err(e) = SockRef::from(&stream).set_tcp_keepalive(&keepalive) {
+                        tracing::warn!(error = ?e, "Failed to set TCP keepalive");
+                    }
+                }
+
+                let acceptor = TlsAcceptor::from(self.config.read().clone());
+                let tls_config = self.tls_config.clone();
+                let sender = sender.clone();
+
+                // Handshake off the accept loop so slow clients don't hold up others.
+                tokio::spawn(async move {
+                    match tokio::time::timeout(HANDSHAKE_TIMEOUT, acceptor.accept(stream)).await {
+                        Ok(Ok(stream)) => {
+                            if let Err(e) = check_client(&tls_config, &stream) {
+                                tracing::warn!(error = %e, "Rejected client certificate");
+                                return;
+                            }
+                            let _ = sender.send(Ok(stream)).await;
+                        }
+                        Ok(Err(e)) => tracing::warn!(error = ?e, "TLS handshake failed"),
+                        Err(_) => tracing::warn!("TLS handshake timed out"),
+                    }
+                });
+            }
+        });
+
+        Ok(ReceiverStream::new(receiver))
+    }
+
+    fn modified(&self) -> [Option<SystemTime>; 3] {
+        let modified = |path: &str| std::fs::metadata(path).and_then(|m| m.modified()).ok();
+
+        [
+            modified(&self.tls_config.cert_path),
+            modified(&self.tls_config.key_path),
+            self.tls_config.client_ca_path.as_deref().and_then(modified),
+        ]
+    }
+}
+
+/// Identifies a client by its certificate's first DNS or URI subject alternative name in
+/// `allowed_names`, or its first one when `allowed_names` is empty.
+pub fn client_identity(cert: &[u8], allowed_names: &[String]) -> Result<String, String> {
+    let (_, cert) = x509_parser::parse_x509_certificate(cert)
+        .map_err(|e| format!("Error, invalid client certificate.\n{e:?}"))?;
+    let alternative_names = cert
+        .subject_alternative_name()
+        .map_err(|e| format!("Error, invalid client certificate.\n{e:?}"))?
+        .map(|extension| extension.value.general_names.clone())
+        .unwrap_or_default();
+
+    alternative_names
+        .iter()
+        .filter_map(|name| match name {
+            GeneralName::DNSName(name) | GeneralName::URI(name) => Some(name.to_string()),
+            _ => None,
+        })
+        .find(|name| allowed_names.is_empty() || allowed_names.contains(name))
+        .ok_or("Error, client certificate name not allowed".to_string())
+}
+
+/// Tells handlers which client certificate the caller was verified with, if any, as a
+/// `ClientIdentity` request extension. Never rejects a request.
+#[derive(Clone)]
+pub struct IdentifyClient {
+    allowed_client_names: Vec<String>,
+}
+
+impl IdentifyClient {
+    pub fn new(allowed_client_names: Vec<String>) -> Self {
+        Self {
+            allowed_client_names,
+        }
+    }
+}
+
+impl Interceptor for IdentifyClient {
+    fn call(&mut self, mut request: Request<()>) -> Result<Request<()>, Status> {
+        let identity = request.peer_certs().and_then(|certs| {
+            let cert = certs.first()?;
+            client_identity(cert.get_ref(), &self.allowed_client_names).ok()
+        });
+        if let Some(identity) = identity {
+            request.extensions_mut().insert(ClientIdentity(identity));
+        }
+        Ok(request)
+    }
+}
+
+/// Checks the certificate a client authenticated with carries an allowed name.
+fn check_client(tls_config: &TlsConfig, stream: &TlsStream<TcpStream>) -> Result<(), String> {
+    if tls_config.client_ca_path.is_none() {
+        return Ok(());
+    }
+
+    let cert = stream
+        .get_ref()
+        .1
+        .peer_certificates()
+        .and_then(|certs| certs.first())
+        .ok_or("Error, no client certificate")?;
+
+    client_identity(&cert.0, &tls_config.allowed_client_names).map(|_| ())
+}
+
+/// Builds a server config serving HTTP/2 from a PEM certificate chain and private key,
+/// verifying clients against the client CA bundle if there is one.
+fn server_config(tls_config: &TlsConfig) -> Result<ServerConfig, String> {
+    let certs = read_certs(&tls_config.cert_path)?;
+    let key_path = &tls_config.key_path;
+
+    let key = read_pem(key_path)?
+        .into_iter()
+        .find_map(|item| match item {
+            Item::PKCS8Key(key) | Item::RSAKey(key) | Item::ECKey(key) => Some(PrivateKey(key)),
+            _ => None,
+        })
+        .ok_or(format!("Error, no private key found in {key_path}"))?;
+
+    let builder = ServerConfig::builder().with_safe_defaults();
+    let builder = match &tls_config.client_ca_path {
+        Some(client_ca_path) => {
+            let mut roots = RootCertStore::empty();
+            for cert in read_certs(client_ca_path)? {
+                roots
+                    .add(&cert)
+                    .map_err(|e| format!("Error, invalid CA in {client_ca_path}.\n{e:?}"))?;
+            }
+            builder.with_client_cert_verifier(AllowAnyAuthenticatedClient::new(roots).boxed())
+        }
+        None => builder.with_no_client_auth(),
+    };
+
+    let mut config = builder
+        .with_single_cert(certs, key)
+        .map_err(|e| format!("Error, invalid TLS certificate or key.\n{e:?}"))?;
+    config.alpn_protocols = vec![b"h2".to_vec()];
+
+    Ok(config)
+}
+
+fn read_certs(path: &str) -> Result<Vec<Certificate>, String> {
+    let certs: Vec<Certificate> = read_pem(path)?
+        .into_iter()
+        .filter_map(|item| match item {
+            Item::X509Certificate(cert) => Some(Certificate(cert)),
+            _ => None,
+        })
+        .collect();
+
+    if certs.is_empty() {
+        return Err(format!("Error, no certificates found in {path}"));
+    }
+
+    Ok(certs)
+}
+
+fn read_pem(path: &str) -> Result<Vec<Item>, String> {
+    let file = File::open(path).map_err(|e| format!("Failed to open {path}.\n{e:?}"))?;
+
+    rustls_pemfile::read_all(&mut BufReader::new(file))
+        .map_err(|e| format!("Error, {path} isn't valid PEM.\n{e:?}"))
+}
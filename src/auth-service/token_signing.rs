@@ -0,0 +1,130 @@
+use crate::random::Random;
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use hmac::{Hmac, Mac};
+use rand_core::{OsRng, RngCore};
+use sha2::Sha256;
+
+const GENERATED_KEY_LENGTH: usize = 32;
+
+/// Issues opaque tokens of the form `id.signature`, so tampered or made up tokens can be rejected
+/// without a store lookup.
+///
+/// The first key signs new tokens. The others are only used to verify, so a key can be rotated
+/// out by adding its replacement first and removing it once its tokens have expired.
+pub struct TokenSigner {
+    keys: Vec<Vec<u8>>,
+}
+
+impl TokenSigner {
+    pub fn new(keys: Vec<Vec<u8>>) -> Result<Self, String> {
+        if keys.is_empty() || keys.iter().any(|key| key.is_empty()) {
+            return Err("Error, token signing keys can't be empty".to_string());
+        }
+
+        Ok(Self { keys })
+    }
+
+    /// Signs with a key that only lives as long as the process, like in-memory sessions do.
+    pub fn generate() -> Self {
+        let mut key = vec![0u8; GENERATED_KEY_LENGTH];
+        OsRng.fill_bytes(&mut key);
+
+        Self { keys: vec![key] }
+    }
+
+    /// Issues a new random token.
+    #[cfg(test)]
+    pub fn issue(&self) -> String {
+        self.issue_with(&crate::random::OsRandom)
+    }
+
+    /// Issues a new token whose id is drawn from `random`.
+    pub fn issue_with(&self, random: &dyn Random) -> String {
+        let id = random.uuid().to_string();
+        let signature = URL_SAFE_NO_PAD.encode(mac(&self.keys[0], &id).finalize().into_bytes());
+
+        format!("{}.{}", id, signature)
+    }
+
+    /// Checks `token` was signed by one of the keys.
+    pub fn verify(&self, token: &str) -> bool {
+        let (id, signature) = match token.rsplit_once('.') {
+            Some(parts) => parts,
+            None => return false,
+        };
+
+        let signature = match URL_SAFE_NO_PAD.decode(signature) {
+            Ok(signature) => signature,
+            Err(_) => return false,
+        };
+
+        self.keys
+            .iter()
+            .any(|key| mac(key, id).verify_slice(&signature).is_ok())
+    }
+}
+
+impl Default for TokenSigner {
+    fn default() -> Self {
+        Self::generate()
+    }
+}
+
+fn mac(key: &[u8], id: &str) -> Hmac<Sha256> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(key).expect("HMAC accepts keys of any length");
+    mac.update(id.as_bytes());
+    mac
+}
+
+#[cfg(test)]
+mod tests {
+    use uuid::Uuid;
+
+    use super::*;
+    use crate::random::SeededRandom;
+
+    #[test]
+    fn should_verify_issued_token() {
+        let signer = TokenSigner::generate();
+        let token = signer.issue();
+
+        assert!(signer.verify(&token));
+        assert!(!TokenSigner::generate().verify(&token));
+    }
+
+    #[test]
+    fn should_reject_tampered_token() {
+        let signer = TokenSigner::generate();
+        let token = signer.issue();
+        let (id, signature) = token.rsplit_once('.').unwrap();
+
+        assert!(!signer.verify(id));
+        assert!(!signer.verify(&format!("{}.{}", Uuid::new_v4(), signature)));
+        assert!(!signer.verify(&format!("{}.{}", id, "invalid")));
+    }
+
+    #[test]
+    fn should_verify_with_rotated_keys() {
+        let old = TokenSigner::new(vec![b"old".to_vec()]).unwrap();
+        let rotated = TokenSigner::new(vec![b"new".to_vec(), b"old".to_vec()]).unwrap();
+        let token = old.issue();
+
+        assert!(rotated.verify(&token));
+        assert!(!old.verify(&rotated.issue()));
+    }
+
+    #[test]
+    fn should_issue_same_tokens_from_seeded_random() {
+        let signer = TokenSigner::new(vec![b"key".to_vec()]).unwrap();
+        let token = signer.issue_with(&SeededRandom::new(7));
+
+        assert_eq!(token, signer.issue_with(&SeededRandom::new(7)));
+        assert!(signer.verify(&token));
+    }
+
+    #[test]
+    fn should_reject_empty_keys() {
+        assert!(TokenSigner::new(Vec::new()).is_err());
+        assert!(TokenSigner::new(vec![Vec::new()]).is_err());
+    }
+}
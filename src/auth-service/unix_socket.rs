@@ -0,0 +1,61 @@
+use std::io::ErrorKind;
+use std::os::unix::fs::FileTypeExt;
+
+use tokio::net::UnixListener;
+use tokio_stream::wrappers::UnixListenerStream;
+
+/// Listens on the Unix domain socket at `path` for `Server::serve_with_incoming`. A socket left
+/// behind by a previous run is replaced, but any other file there is left alone and fails.
+pub fn incoming(path: &str) -> Result<UnixListenerStream, String> {
+    match std::fs::symlink_metadata(path) {
+        Ok(metadata) if metadata.file_type().is_socket() => remove(path)?,
+        Ok(_) => return Err(format!("Failed to listen on {path}, it isn't a socket")),
+        Err(e) if e.kind() == ErrorKind::NotFound => (),
+        Err(e) => return Err(format!("Failed to listen on {path}.\n{e:?}")),
+    }
+
+    let listener =
+        UnixListener::bind(path).map_err(|e| format!("Failed to listen on {path}.\n{e:?}"))?;
+    Ok(UnixListenerStream::new(listener))
+}
+
+/// Removes the socket at `path`, so clients fail fast instead of connecting to a dead server.
+pub fn remove(path: &str) -> Result<(), String> {
+    match std::fs::remove_file(path) {
+        Ok(()) => Ok(()),
+        Err(e) if e.kind() == ErrorKind::NotFound => Ok(()),
+        Err(e) => Err(format!("Failed to remove {path}.\n{e:?}")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn should_replace_stale_socket() {
+        let path = std::env::temp_dir().join(format!("auth-{}.sock", uuid::Uuid::new_v4()));
+        let path = path.to_str().unwrap();
+
+        let listener = incoming(path).unwrap();
+        drop(listener);
+        // The socket file outlives its listener.
+        assert!(std::path::Path::new(path).exists());
+
+        let _listener = incoming(path).unwrap();
+        tokio::net::UnixStream::connect(path).await.unwrap();
+
+        remove(path).unwrap();
+        assert!(!std::path::Path::new(path).exists());
+    }
+
+    #[tokio::test]
+    async fn should_not_replace_other_files() {
+        let path = std::env::temp_dir().join(format!("auth-{}.sock", uuid::Uuid::new_v4()));
+        std::fs::write(&path, "").unwrap();
+
+        assert!(incoming(path.to_str().unwrap()).is_err());
+
+        std::fs::remove_file(path).unwrap();
+    }
+}
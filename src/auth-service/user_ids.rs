@@ -0,0 +1,270 @@
+use std::borrow::Borrow;
+use std::fmt::{self, Debug, Display};
+use std::ops::Deref;
+use std::sync::Arc;
+use std::time::UNIX_EPOCH;
+
+use uuid::Uuid;
+
+use crate::clock::{Clock, SystemClock};
+use crate::random::{OsRandom, Random};
+
+/// Length of generated nanoids by default, about as collision resistant as a UUIDv4.
+pub const DEFAULT_NANOID_LENGTH: usize = 21;
+
+/// URL safe characters used by nanoids. There are 64 of them, so masking a random byte picks one
+/// uniformly.
+const NANOID_ALPHABET: &[u8; 64] =
+    b"_-0123456789abcdefghijklmnopqrstuvwxyzABCDEFGHIJKLMNOPQRSTUVWXYZ";
+
+/// A user's id as sessions and their events carry it. Clones share the id rather than copying
+/// it, so validating a session doesn't allocate for it.
+#[derive(Clone, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct UserUuid(Arc<str>);
+
+impl Deref for UserUuid {
+    type Target = str;
+
+    fn deref(&self) -> &str {
+        &self.0
+    }
+}
+
+impl Borrow<str> for UserUuid {
+    fn borrow(&self) -> &str {
+        &self.0
+    }
+}
+
+impl Display for UserUuid {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl From<&str> for UserUuid {
+    fn from(user_uuid: &str) -> Self {
+        Self(user_uuid.into())
+    }
+}
+
+impl From<String> for UserUuid {
+    fn from(user_uuid: String) -> Self {
+        Self(user_uuid.into())
+    }
+}
+
+impl From<UserUuid> for String {
+    fn from(user_uuid: UserUuid) -> Self {
+        user_uuid.0.to_string()
+    }
+}
+
+impl PartialEq<str> for UserUuid {
+    fn eq(&self, other: &str) -> bool {
+        &*self.0 == other
+    }
+}
+
+impl PartialEq<&str> for UserUuid {
+    fn eq(&self, other: &&str) -> bool {
+        &*self.0 == *other
+    }
+}
+
+/// Generates the ids of new users.
+pub trait IdGenerator: Debug {
+    fn generate(&self) -> String;
+}
+
+/// Random UUIDs, e.g. `0f8fad5b-d9cb-469f-a165-70867728950e`.
+#[derive(Debug)]
+pub struct UuidV4 {
+    random: Box<dyn Random + Send + Sync>,
+}
+
+impl UuidV4 {
+    /// Takes the random bytes from `random` instead of the OS, e.g. to repeat ids in tests.
+    #[cfg(test)]
+    pub fn with_random(mut self, random: Box<dyn Random + Send + Sync>) -> Self {
+        self.random = random;
+        self
+    }
+}
+
+impl Default for UuidV4 {
+    fn default() -> Self {
+        Self {
+            random: Box::new(OsRandom),
+        }
+    }
+}
+
+impl IdGenerator for UuidV4 {
+    fn generate(&self) -> String {
+        self.random.uuid().to_string()
+    }
+}
+
+/// UUIDs starting with the creation time in milliseconds, so newer users sort after older ones.
+#[derive(Debug)]
+pub struct UuidV7 {
+    clock: Box<dyn Clock + Send + Sync>,
+    random: Box<dyn Random + Send + Sync>,
+}
+
+impl UuidV7 {
+    /// Takes the creation time from `clock` instead of the system clock.
+    #[cfg(test)]
+    pub fn with_clock(mut self, clock: Box<dyn Clock + Send + Sync>) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    /// Takes the random bytes from `random` instead of the OS.
+    #[cfg(test)]
+    pub fn with_random(mut self, random: Box<dyn Random + Send + Sync>) -> Self {
+        self.random = random;
+        self
+    }
+}
+
+impl Default for UuidV7 {
+    fn default() -> Self {
+        Self {
+            clock: Box::new(SystemClock),
+            random: Box::new(OsRandom),
+        }
+    }
+}
+
+impl IdGenerator for UuidV7 {
+    fn generate(&self) -> String {
+        let millis = self
+            .clock
+            .now()
+            .duration_since(UNIX_EPOCH)
+            .map(|duration| duration.as_millis() as u64)
+            .unwrap_or_default();
+
+        let mut bytes = [0u8; 16];
+        self.random.fill_bytes(&mut bytes[6..]);
+        bytes[..6].copy_from_slice(&millis.to_be_bytes()[2..]);
+        // Set the version to 7 and the variant to RFC 4122.
+        bytes[6] = (bytes[6] & 0x0f) | 0x70;
+        bytes[8] = (bytes[8] & 0x3f) | 0x80;
+
+        Uuid::from_bytes(bytes).to_string()
+    }
+}
+
+/// Short random ids made of URL safe characters, e.g. `V1StGXR8_Z5jdHi6B-myT`.
+#[derive(Debug)]
+pub struct NanoId {
+    length: usize,
+    random: Box<dyn Random + Send + Sync>,
+}
+
+impl NanoId {
+    pub fn new(length: usize) -> Self {
+        Self {
+            length,
+            random: Box::new(OsRandom),
+        }
+    }
+
+    /// Takes the random bytes from `random` instead of the OS.
+    #[cfg(test)]
+    pub fn with_random(mut self, random: Box<dyn Random + Send + Sync>) -> Self {
+        self.random = random;
+        self
+    }
+}
+
+impl Default for NanoId {
+    fn default() -> Self {
+        Self::new(DEFAULT_NANOID_LENGTH)
+    }
+}
+
+impl IdGenerator for NanoId {
+    fn generate(&self) -> String {
+        let mut bytes = vec![0u8; self.length];
+        self.random.fill_bytes(&mut bytes);
+
+        bytes
+            .into_iter()
+            .map(|byte| NANOID_ALPHABET[(byte & 63) as usize] as char)
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+
+    use uuid::Version;
+
+    use super::*;
+    use crate::clock::ManualClock;
+    use crate::random::SeededRandom;
+
+    #[test]
+    fn should_share_user_uuid_between_clones() {
+        let user_uuid = UserUuid::from("0f8fad5b-d9cb-469f-a165-70867728950e");
+        let clone = user_uuid.clone();
+
+        assert!(Arc::ptr_eq(&user_uuid.0, &clone.0));
+        assert_eq!(clone, "0f8fad5b-d9cb-469f-a165-70867728950e");
+        assert_eq!(String::from(clone), user_uuid.to_string());
+    }
+
+    #[test]
+    fn should_generate_unique_uuid_v4s() {
+        let ids: HashSet<String> = (0..100).map(|_| UuidV4::default().generate()).collect();
+
+        assert_eq!(ids.len(), 100);
+        let id = ids.iter().next().unwrap();
+        assert_eq!(
+            Uuid::parse_str(id).unwrap().get_version(),
+            Some(Version::Random)
+        );
+    }
+
+    #[test]
+    fn should_generate_time_ordered_uuid_v7s() {
+        let first = UuidV7::default().generate();
+        std::thread::sleep(std::time::Duration::from_millis(2));
+        let second = UuidV7::default().generate();
+
+        assert!(first < second);
+        assert_eq!(Uuid::parse_str(&first).unwrap().get_version_num(), 7);
+        assert_ne!(UuidV7::default().generate(), UuidV7::default().generate());
+    }
+
+    #[test]
+    fn should_generate_nanoids() {
+        let id = NanoId::new(10).generate();
+
+        assert_eq!(id.len(), 10);
+        assert!(id.bytes().all(|byte| NANOID_ALPHABET.contains(&byte)));
+        assert_ne!(NanoId::default().generate(), NanoId::default().generate());
+    }
+
+    #[test]
+    fn should_repeat_ids_with_seeded_random_and_manual_clock() {
+        let now = UNIX_EPOCH + std::time::Duration::from_secs(1_700_000_000);
+        let uuid_v7 = || {
+            UuidV7::default()
+                .with_clock(Box::new(ManualClock::new(now)))
+                .with_random(Box::new(SeededRandom::new(7)))
+        };
+        assert_eq!(uuid_v7().generate(), uuid_v7().generate());
+
+        let uuid_v4 = || UuidV4::default().with_random(Box::new(SeededRandom::new(7)));
+        assert_eq!(uuid_v4().generate(), uuid_v4().generate());
+
+        let nanoid = || NanoId::default().with_random(Box::new(SeededRandom::new(7)));
+        assert_eq!(nanoid().generate(), nanoid().generate());
+    }
+}
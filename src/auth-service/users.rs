@@ -1,177 +1,3528 @@
-use pbkdf2::{
-    password_hash::{PasswordHash, PasswordHasher, PasswordVerifier, SaltString},
-    Pbkdf2,
-};
-use rand_core::OsRng;
-use uuid::Uuid;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::ops::Bound;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
 
-use std::collections::HashMap;
+use crate::clock::{Clock, SystemClock};
+use crate::error::StorageError;
+use crate::password_hashing::{is_supported_hash, HashingPool, PasswordHasher};
+use crate::random::{OsRandom, Random};
+use crate::secret::Secret;
+use crate::user_ids::{IdGenerator, UuidV4};
 
+/// Attributes each user can have at most.
+pub const MAX_USER_ATTRIBUTES: usize = 32;
+/// Longest attribute key, in bytes.
+pub const MAX_ATTRIBUTE_KEY_LENGTH: usize = 64;
+/// Longest attribute value, in bytes.
+pub const MAX_ATTRIBUTE_VALUE_LENGTH: usize = 1024;
+/// How long deleted accounts can be restored before they're purged by default.
+pub const DEFAULT_DELETION_GRACE_PERIOD: Duration = Duration::from_secs(60 * 60 * 24 * 30);
+/// How often accounts past their deletion grace period are purged.
+pub const DELETED_USERS_PURGE_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+#[cfg(feature = "postgres")]
+pub use postgres::PostgresUsers;
+#[cfg(feature = "sqlite")]
+pub use sqlite::SqliteUsers;
+
+#[tonic::async_trait]
 pub trait Users {
-    fn create_user(&mut self, username: String, password: String) -> Result<(), String>;
-    fn get_user_uuid(&self, username: String, password: String) -> Option<String>;
-    fn delete_user(&mut self, user_uuid: String);
+    /// Creates a user. `email` may be empty, otherwise it must not belong to another user. The
+    /// service hashes first and calls `create_user_hashed` instead.
+    #[allow(dead_code)]
+    async fn create_user(
+        &mut self,
+        username: String,
+        password: String,
+        email: String,
+    ) -> Result<(), StorageError> {
+        let hashed_password = self
+            .hashing()
+            .await
+            .hash_password(&password)
+            .await
+            .map_err(StorageError::Hashing)?;
+
+        self.create_user_hashed(username, hashed_password, email)
+            .await
+    }
+    /// Like `create_user`, with a password already hashed by `hashing`, so callers sharing the
+    /// users don't hold write access while hashing.
+    async fn create_user_hashed(
+        &mut self,
+        username: String,
+        hashed_password: String,
+        email: String,
+    ) -> Result<(), StorageError>;
+    /// Hashes new passwords, see `create_user_hashed`.
+    async fn hashing(&self) -> HashingPool;
+    /// Creates each `(username, password)` pair independently, returning one result per entry.
+    async fn create_users(&mut self, users: Vec<(String, String)>)
+        -> Vec<Result<(), StorageError>>;
+    /// Checks the password of the user with `username` as their username or email.
+    #[allow(dead_code)]
+    async fn get_user_uuid(&self, username: String, password: String) -> Option<String> {
+        self.verify_password(username, password)
+            .await
+            .map(|verified| verified.user_uuid)
+    }
+    /// Like `get_user_uuid`, but also tells whether the stored hash uses an older algorithm or
+    /// cost. Sign in then re-hashes the password with `update_password_hashed`, so it only needs write
+    /// access to the users for the rare upgrade.
+    async fn verify_password(&self, username: String, password: String) -> Option<VerifiedUser>;
+    /// Like `get_user_uuid` but without checking the password.
+    async fn lookup_user_uuid(&self, username: String) -> Option<String>;
+    /// Hashes and stores a new password. The service hashes first and calls
+    /// `update_password_hashed` instead.
+    #[allow(dead_code)]
+    async fn update_password(
+        &mut self,
+        user_uuid: String,
+        password: String,
+    ) -> Result<(), StorageError> {
+        let hashed_password = self
+            .hashing()
+            .await
+            .hash_password(&password)
+            .await
+            .map_err(StorageError::Hashing)?;
+
+        self.update_password_hashed(user_uuid, hashed_password)
+            .await
+    }
+    /// Like `update_password`, with a password already hashed by `hashing`.
+    async fn update_password_hashed(
+        &mut self,
+        user_uuid: String,
+        hashed_password: String,
+    ) -> Result<(), StorageError>;
+    /// Shared rather than copied, so in memory lookups don't allocate.
+    async fn get_user(&self, user_uuid: String) -> Option<Arc<User>>;
+    /// Updates the user's profile. Fields left as `None` are unchanged. Fails if `email` belongs to
+    /// another user.
+    async fn update_user(
+        &mut self,
+        user_uuid: String,
+        display_name: Option<String>,
+        email: Option<String>,
+    ) -> Result<(), StorageError>;
+    /// Sets an app-specific attribute on the user, or removes it if `value` is empty. Fails if
+    /// this would exceed the attribute limits.
+    async fn set_user_attribute(
+        &mut self,
+        user_uuid: String,
+        key: String,
+        value: String,
+    ) -> Result<(), StorageError>;
+    async fn delete_user(&mut self, user_uuid: String);
+    /// Marks the user as deleted so they can't sign in or be looked up, keeping their data until
+    /// `purge_deleted_users` removes it.
+    async fn soft_delete_user(&mut self, user_uuid: String) -> Result<(), StorageError>;
+    /// Undoes `soft_delete_user`.
+    async fn restore_user(&mut self, user_uuid: String) -> Result<(), StorageError>;
+    /// Blocks the user from signing in, or unblocks them.
+    async fn set_user_disabled(
+        &mut self,
+        user_uuid: String,
+        disabled: bool,
+    ) -> Result<(), StorageError>;
+    /// Deletes users that were soft deleted before `deleted_before` and returns their uuids.
+    async fn purge_deleted_users(&mut self, deleted_before: SystemTime) -> Vec<String>;
+    /// Creates users exported from another deployment, returning one result per entry. Entries
+    /// are checked like `create_users`, and also fail if their uuid is taken.
+    async fn import_users(&mut self, users: Vec<ImportedUser>) -> Vec<Result<(), StorageError>>;
+    /// Lists up to `limit` users matching `filter`, ordered by creation time then uuid, starting
+    /// after the user at `after`. Deleted users are included unless filtered out. Attributes
+    /// aren't loaded.
+    async fn search_users(
+        &self,
+        filter: &UserFilter,
+        after: Option<&UserCursor>,
+        limit: usize,
+    ) -> Vec<User>;
+    /// Counts the users that aren't deleted.
+    async fn count_users(&self) -> Result<usize, StorageError>;
+    /// Finds the local user linked to an external identity.
+    async fn get_federated_user_uuid(&self, issuer: &str, subject: &str) -> Option<String>;
+    /// Creates a local user linked to an external identity and returns its uuid.
+    ///
+    /// The user gets a random password, so they can only sign in through the provider unless
+    /// they reset it.
+    async fn create_federated_user(
+        &mut self,
+        issuer: &str,
+        subject: &str,
+        username: String,
+    ) -> Result<String, StorageError>;
+    /// Closes connections to the database, before the service exits.
+    async fn flush(&mut self) {}
+    /// Fails if the database can't be reached, for health checks.
+    async fn ping(&self) -> Result<(), StorageError> {
+        Ok(())
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct User {
+    pub user_uuid: String,
+    pub username: String,
+    password: Secret,
+    pub display_name: String,
+    pub email: String,
+    pub created_at: SystemTime,
+    /// App-specific data, e.g. a locale or plan tier.
+    pub attributes: BTreeMap<String, String>,
+    /// When the user deleted their account, if they did.
+    pub deleted_at: Option<SystemTime>,
+    /// Whether an administrator blocked the user from signing in.
+    pub disabled: bool,
+}
+
+impl User {
+    /// The user's password as a PHC string, e.g. to export them to another deployment.
+    pub fn password_hash(&self) -> &str {
+        self.password.expose()
+    }
+}
+
+/// A user whose password was verified, see `Users::verify_password`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct VerifiedUser {
+    pub user_uuid: String,
+    /// The stored hash should be replaced with one from the current hasher.
+    pub needs_rehash: bool,
+}
+
+/// Whether a user has deleted their account.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum UserStatus {
+    Active,
+    Deleted,
+}
+
+/// Criteria for `search_users`. Empty or `None` fields match every user.
+#[derive(Clone, Debug, Default)]
+pub struct UserFilter {
+    pub username_prefix: String,
+    /// Matched case insensitively against the part of the email after the `@`.
+    pub email_domain: String,
+    /// Inclusive.
+    pub created_after: Option<SystemTime>,
+    /// Exclusive.
+    pub created_before: Option<SystemTime>,
+    pub status: Option<UserStatus>,
+}
+
+impl UserFilter {
+    fn matches(&self, user: &User) -> bool {
+        let status = match user.deleted_at {
+            Some(_) => UserStatus::Deleted,
+            None => UserStatus::Active,
+        };
+
+        user.username.starts_with(&self.username_prefix)
+            && (self.email_domain.is_empty()
+                || user
+                    .email
+                    .rsplit_once('@')
+                    .is_some_and(|(_, domain)| domain.eq_ignore_ascii_case(&self.email_domain)))
+            && self
+                .created_after
+                .is_none_or(|after| user.created_at >= after)
+            && self
+                .created_before
+                .is_none_or(|before| user.created_at < before)
+            && self.status.is_none_or(|wanted| wanted == status)
+    }
+}
+
+/// Position of a user in `search_users` order, used to resume a search after them.
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct UserCursor {
+    pub created_at: SystemTime,
+    pub user_uuid: String,
+}
+
+impl From<&User> for UserCursor {
+    fn from(user: &User) -> Self {
+        Self {
+            created_at: user.created_at,
+            user_uuid: user.user_uuid.clone(),
+        }
+    }
+}
+
+/// The password of an imported user.
+#[derive(Clone, Debug)]
+pub enum ImportedPassword {
+    /// Hashed with the current hasher on import.
+    Plaintext(Secret),
+    /// A PHC string from any supported hasher, kept as is.
+    Hashed(Secret),
+}
+
+impl ImportedPassword {
+    async fn into_hash(self, hashing: &HashingPool) -> Result<String, StorageError> {
+        match self {
+            Self::Plaintext(password) => hashing
+                .hash_password(password.expose())
+                .await
+                .map_err(StorageError::Hashing),
+            Self::Hashed(hashed_password) if is_supported_hash(hashed_password.expose()) => {
+                Ok(hashed_password.into_exposed())
+            }
+            Self::Hashed(_) => Err(StorageError::UnsupportedPasswordHash),
+        }
+    }
+}
+
+/// A user exported from another deployment. Unset fields are filled in like for new users.
+#[derive(Clone, Debug)]
+pub struct ImportedUser {
+    pub user_uuid: Option<String>,
+    pub username: String,
+    pub email: String,
+    pub display_name: Option<String>,
+    pub created_at: Option<SystemTime>,
+    pub password: ImportedPassword,
+}
+
+#[derive(Debug)]
+pub struct UsersImpl {
+    /// Shares each user with `username_to_user`, changes replace it in both.
+    uuid_to_user: HashMap<String, Arc<User>>,
+    username_to_user: HashMap<String, Arc<User>>,
+    /// Maps `(issuer, subject)` of external identities to local user uuids.
+    federated_to_uuid: HashMap<(String, String), String>,
+    /// Maps lowercased emails to usernames, so users can sign in with either.
+    email_to_username: HashMap<String, String>,
+    /// Users in `search_users` order.
+    by_creation: BTreeSet<UserCursor>,
+    hashing: HashingPool,
+    id_generator: Box<dyn IdGenerator + Send + Sync>,
+    /// Tells when users are created and deleted.
+    clock: Box<dyn Clock + Send + Sync>,
+    /// Draws the placeholder passwords of federated users.
+    random: Box<dyn Random + Send + Sync>,
+}
+
+impl Default for UsersImpl {
+    fn default() -> Self {
+        Self {
+            uuid_to_user: HashMap::new(),
+            username_to_user: HashMap::new(),
+            federated_to_uuid: HashMap::new(),
+            email_to_username: HashMap::new(),
+            by_creation: BTreeSet::new(),
+            hashing: HashingPool::default(),
+            id_generator: Box::new(UuidV4::default()),
+            clock: Box::new(SystemClock),
+            random: Box::new(OsRandom),
+        }
+    }
+}
+
+#[tonic::async_trait]
+impl Users for UsersImpl {
+    async fn create_user_hashed(
+        &mut self,
+        new_username: String,
+        hashed_password: String,
+        email: String,
+    ) -> Result<(), StorageError> {
+        self.check_available(&new_username, &email)?;
+        let user = self.user_with_hash(new_username, hashed_password, email);
+
+        self.insert_user(user);
+
+        Ok(())
+    }
+
+    async fn hashing(&self) -> HashingPool {
+        self.hashing.clone()
+    }
+
+    async fn create_users(
+        &mut self,
+        new_users: Vec<(String, String)>,
+    ) -> Vec<Result<(), StorageError>> {
+        // Hash and validate every entry before touching the indexes so a failing entry never
+        // leaves another one half-written.
+        let mut pending: Vec<Result<User, StorageError>> = Vec::with_capacity(new_users.len());
+
+        for (new_username, password) in new_users {
+            let duplicate_in_batch = pending
+                .iter()
+                .flatten()
+                .any(|user| user.username == new_username);
+
+            let user = if duplicate_in_batch {
+                Err(StorageError::UsernameTaken)
+            } else {
+                self.new_user(new_username, password, String::new()).await
+            };
+
+            pending.push(user);
+        }
+
+        pending
+            .into_iter()
+            .map(|user| user.map(|user| self.insert_user(user)))
+            .collect()
+    }
+
+    async fn verify_password(&self, username: String, password: String) -> Option<VerifiedUser> {
+        // Retrieve `User`, or return `None` after as much work as a wrong password if the user can't be found.
+        let user: &User = match self.find_user(&username) {
+            Some(user) => user,
+            None => {
+                self.hashing.verify_dummy(&password).await;
+                return None;
+            }
+        };
+
+        self.hashing
+            .verify_password(user.password.expose(), &password)
+            .await
+            .then(|| VerifiedUser {
+                user_uuid: user.user_uuid.clone(),
+                needs_rehash: self.hashing.needs_rehash(user.password.expose()),
+            })
+    }
+
+    async fn lookup_user_uuid(&self, username: String) -> Option<String> {
+        self.username_to_user
+            .get(&username)
+            .filter(|user| user.deleted_at.is_none())
+            .map(|user| user.user_uuid.clone())
+    }
+
+    async fn update_password_hashed(
+        &mut self,
+        user_uuid: String,
+        hashed_password: String,
+    ) -> Result<(), StorageError> {
+        self.modify_user(&user_uuid, |user| user.password = hashed_password.into())
+    }
+
+    async fn get_user(&self, user_uuid: String) -> Option<Arc<User>> {
+        self.uuid_to_user.get(&user_uuid).cloned()
+    }
+
+    async fn update_user(
+        &mut self,
+        user_uuid: String,
+        display_name: Option<String>,
+        email: Option<String>,
+    ) -> Result<(), StorageError> {
+        let user = self
+            .uuid_to_user
+            .get(&user_uuid)
+            .ok_or(StorageError::UserNotFound)?;
+
+        if let Some(email) = &email {
+            if !self.is_email_available(email, &user.username) {
+                return Err(StorageError::EmailTaken);
+            }
+
+            let (old_key, username) = (email_key(&user.email), user.username.clone());
+            self.email_to_username.remove(&old_key);
+            if !email.is_empty() {
+                self.email_to_username.insert(email_key(email), username);
+            }
+        }
+
+        self.modify_user(&user_uuid, |user| {
+            if let Some(display_name) = display_name {
+                user.display_name = display_name;
+            }
+            if let Some(email) = email {
+                user.email = email;
+            }
+        })
+    }
+
+    async fn set_user_attribute(
+        &mut self,
+        user_uuid: String,
+        key: String,
+        value: String,
+    ) -> Result<(), StorageError> {
+        let mut attributes = self
+            .uuid_to_user
+            .get(&user_uuid)
+            .ok_or(StorageError::UserNotFound)?
+            .attributes
+            .clone();
+
+        set_attribute(&mut attributes, key, value)?;
+
+        self.modify_user(&user_uuid, |user| user.attributes = attributes)
+    }
+
+    async fn delete_user(&mut self, user_uuid: String) {
+        let mut user_name: String = String::new();
+        match self.uuid_to_user.get(&user_uuid) {
+            Some(_) => {
+                user_name = self.uuid_to_user.get(&user_uuid).unwrap().username.clone();
+                if let Some(user) = self.uuid_to_user.remove(&user_uuid) {
+                    self.by_creation.remove(&UserCursor::from(user.as_ref()));
+                }
+            }
+            None => tracing::warn!("User uuid not found"),
+        };
+
+        match self.username_to_user.remove(&user_name) {
+            Some(user) => {
+                self.email_to_username.remove(&email_key(&user.email));
+            }
+            None => tracing::warn!("Username not found"),
+        };
+
+        self.federated_to_uuid.retain(|_, uuid| uuid != &user_uuid);
+    }
+
+    async fn soft_delete_user(&mut self, user_uuid: String) -> Result<(), StorageError> {
+        match self.uuid_to_user.get(&user_uuid) {
+            Some(user) if user.deleted_at.is_none() => {}
+            Some(_) => return Err(StorageError::AlreadyDeleted),
+            None => return Err(StorageError::UserNotFound),
+        }
+
+        let deleted_at = self.clock.now();
+        self.modify_user(&user_uuid, |user| user.deleted_at = Some(deleted_at))
+    }
+
+    async fn restore_user(&mut self, user_uuid: String) -> Result<(), StorageError> {
+        match self.uuid_to_user.get(&user_uuid) {
+            Some(user) if user.deleted_at.is_some() => {}
+            Some(_) => return Err(StorageError::NotDeleted),
+            None => return Err(StorageError::UserNotFound),
+        }
+
+        self.modify_user(&user_uuid, |user| user.deleted_at = None)
+    }
+
+    async fn set_user_disabled(
+        &mut self,
+        user_uuid: String,
+        disabled: bool,
+    ) -> Result<(), StorageError> {
+        self.modify_user(&user_uuid, |user| user.disabled = disabled)
+    }
+
+    async fn purge_deleted_users(&mut self, deleted_before: SystemTime) -> Vec<String> {
+        let purged: Vec<String> = self
+            .uuid_to_user
+            .values()
+            .filter(|user| user.deleted_at.is_some_and(|at| at < deleted_before))
+            .map(|user| user.user_uuid.clone())
+            .collect();
+
+        for user_uuid in &purged {
+            self.delete_user(user_uuid.clone()).await;
+        }
+
+        purged
+    }
+
+    async fn import_users(&mut self, users: Vec<ImportedUser>) -> Vec<Result<(), StorageError>> {
+        // Validate every entry before touching the indexes, like `create_users`.
+        let mut pending: Vec<Result<User, StorageError>> = Vec::with_capacity(users.len());
+
+        for imported in users {
+            let user = self.imported_user(imported).await.and_then(|user| {
+                let duplicate_in_batch = pending.iter().flatten().any(|other| {
+                    other.username == user.username
+                        || other.user_uuid == user.user_uuid
+                        || (!user.email.is_empty()
+                            && email_key(&other.email) == email_key(&user.email))
+                });
+
+                match duplicate_in_batch {
+                    true => Err(StorageError::DuplicateImport),
+                    false => Ok(user),
+                }
+            });
+
+            pending.push(user);
+        }
+
+        pending
+            .into_iter()
+            .map(|user| user.map(|user| self.insert_user(user)))
+            .collect()
+    }
+
+    async fn search_users(
+        &self,
+        filter: &UserFilter,
+        after: Option<&UserCursor>,
+        limit: usize,
+    ) -> Vec<User> {
+        let start = match after {
+            Some(after) => Bound::Excluded(after),
+            None => Bound::Unbounded,
+        };
+
+        self.by_creation
+            .range((start, Bound::Unbounded))
+            .filter_map(|cursor| self.uuid_to_user.get(&cursor.user_uuid))
+            .filter(|user| filter.matches(user))
+            .take(limit)
+            .map(|user| User::clone(user))
+            .collect()
+    }
+
+    async fn count_users(&self) -> Result<usize, StorageError> {
+        Ok(self
+            .uuid_to_user
+            .values()
+            .filter(|user| user.deleted_at.is_none())
+            .count())
+    }
+
+    async fn get_federated_user_uuid(&self, issuer: &str, subject: &str) -> Option<String> {
+        self.federated_to_uuid
+            .get(&(issuer.to_string(), subject.to_string()))
+            .filter(|user_uuid| {
+                self.uuid_to_user
+                    .get(*user_uuid)
+                    .is_some_and(|user| user.deleted_at.is_none())
+            })
+            .cloned()
+    }
+
+    async fn create_federated_user(
+        &mut self,
+        issuer: &str,
+        subject: &str,
+        username: String,
+    ) -> Result<String, StorageError> {
+        let key = (issuer.to_string(), subject.to_string());
+
+        if self.federated_to_uuid.contains_key(&key) {
+            return Err(StorageError::IdentityLinked);
+        }
+
+        let user = self
+            .new_user(username, self.random.uuid().to_string(), String::new())
+            .await?;
+        let user_uuid = user.user_uuid.clone();
+
+        self.insert_user(user);
+        self.federated_to_uuid.insert(key, user_uuid.clone());
+
+        Ok(user_uuid)
+    }
+}
+
+impl UsersImpl {
+    /// Hashes new passwords with `hasher` instead of PBKDF2 with the default rounds.
+    pub fn with_password_hasher(mut self, hasher: Box<dyn PasswordHasher + Send + Sync>) -> Self {
+        self.hashing = self.hashing.with_hasher(hasher);
+        self
+    }
+
+    /// Hashes at most `concurrency` passwords at once instead of one per core.
+    pub fn with_hashing_concurrency(mut self, concurrency: usize) -> Self {
+        self.hashing = self.hashing.with_concurrency(concurrency);
+        self
+    }
+
+    /// Generates user ids with `id_generator` instead of random UUIDs.
+    pub fn with_id_generator(mut self, id_generator: Box<dyn IdGenerator + Send + Sync>) -> Self {
+        self.id_generator = id_generator;
+        self
+    }
+
+    /// Tells the time with `clock` instead of the system clock, e.g. to check creation times in
+    /// tests.
+    #[cfg(test)]
+    pub fn with_clock(mut self, clock: Box<dyn Clock + Send + Sync>) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    /// Builds a user with a hashed password, failing if the username or email is already taken.
+    async fn new_user(
+        &self,
+        new_username: String,
+        password: String,
+        email: String,
+    ) -> Result<User, StorageError> {
+        self.check_available(&new_username, &email)?;
+
+        let hashed_password = self
+            .hashing
+            .hash_password(&password)
+            .await
+            .map_err(StorageError::Hashing)?;
+
+        Ok(self.user_with_hash(new_username, hashed_password, email))
+    }
+
+    /// Fails if the username or email is already taken.
+    fn check_available(&self, new_username: &str, email: &str) -> Result<(), StorageError> {
+        if !self.is_username_available(new_username) {
+            return Err(StorageError::UsernameTaken);
+        }
+        if !self.is_email_available(email, new_username) {
+            return Err(StorageError::EmailTaken);
+        }
+        Ok(())
+    }
+
+    /// Builds a new user with a unique uuid and an already hashed password.
+    fn user_with_hash(&self, new_username: String, hashed_password: String, email: String) -> User {
+        User {
+            user_uuid: self.id_generator.generate(),
+            username: new_username.clone(),
+            password: hashed_password.into(),
+            display_name: new_username,
+            email,
+            created_at: self.clock.now(),
+            attributes: BTreeMap::new(),
+            deleted_at: None,
+            disabled: false,
+        }
+    }
+
+    /// Builds an imported user, failing if their username, email or uuid is already taken.
+    async fn imported_user(&self, imported: ImportedUser) -> Result<User, StorageError> {
+        if !self.is_username_available(&imported.username) {
+            return Err(StorageError::UsernameTaken);
+        }
+        if !self.is_email_available(&imported.email, &imported.username) {
+            return Err(StorageError::EmailTaken);
+        }
+
+        let user_uuid = match imported.user_uuid {
+            Some(user_uuid) if self.uuid_to_user.contains_key(&user_uuid) => {
+                return Err(StorageError::UserUuidTaken)
+            }
+            Some(user_uuid) => user_uuid,
+            None => self.id_generator.generate(),
+        };
+
+        Ok(User {
+            user_uuid,
+            password: imported.password.into_hash(&self.hashing).await?.into(),
+            display_name: imported
+                .display_name
+                .unwrap_or_else(|| imported.username.clone()),
+            username: imported.username,
+            email: imported.email,
+            created_at: imported.created_at.unwrap_or_else(|| self.clock.now()),
+            attributes: BTreeMap::new(),
+            deleted_at: None,
+            disabled: false,
+        })
+    }
+
+    /// Adds user to `username_to_user`, `uuid_to_user`, `email_to_username` and `by_creation`.
+    fn insert_user(&mut self, user: User) {
+        self.by_creation.insert(UserCursor::from(&user));
+        if !user.email.is_empty() {
+            self.email_to_username
+                .insert(email_key(&user.email), user.username.clone());
+        }
+        let user = Arc::new(user);
+        self.username_to_user
+            .insert(user.username.clone(), user.clone());
+        self.uuid_to_user.insert(user.user_uuid.clone(), user);
+    }
+
+    /// Finds the user whose username or email is `login`. Usernames win if both match. Deleted
+    /// users are never found.
+    fn find_user(&self, login: &str) -> Option<&Arc<User>> {
+        self.username_to_user
+            .get(login)
+            .or_else(|| {
+                let username = self.email_to_username.get(&email_key(login))?;
+                self.username_to_user.get(username)
+            })
+            .filter(|user| user.deleted_at.is_none())
+    }
+
+    /// Whether `username` is neither taken nor someone's email, since `find_user` would then
+    /// sign in as the new user with that email.
+    fn is_username_available(&self, username: &str) -> bool {
+        !self.username_to_user.contains_key(username)
+            && !self.email_to_username.contains_key(&email_key(username))
+    }
+
+    /// Whether `email` is empty or unused by anyone but `username`, both as an email and as a
+    /// username, since `find_user` lets usernames win.
+    fn is_email_available(&self, email: &str, username: &str) -> bool {
+        if email.is_empty() {
+            return true;
+        }
+
+        let key = email_key(email);
+        self.email_to_username
+            .get(&key)
+            .is_none_or(|owner| owner == username)
+            && [email, key.as_str()].into_iter().all(|login| {
+                self.username_to_user
+                    .get(login)
+                    .is_none_or(|user| user.username == username)
+            })
+    }
+
+    /// Applies `update` to a copy of the user and swaps it into both indexes, so they never
+    /// disagree and users handed out before stay as they were.
+    fn modify_user(
+        &mut self,
+        user_uuid: &str,
+        update: impl FnOnce(&mut User),
+    ) -> Result<(), StorageError> {
+        let mut user = match self.uuid_to_user.get(user_uuid) {
+            Some(user) => User::clone(user),
+            None => return Err(StorageError::UserNotFound),
+        };
+        update(&mut user);
+
+        let user = Arc::new(user);
+        self.username_to_user
+            .insert(user.username.clone(), user.clone());
+        self.uuid_to_user.insert(user_uuid.to_owned(), user);
+
+        Ok(())
+    }
+}
+
+/// Sets or removes an attribute in `attributes`, failing if it would exceed the limits.
+fn set_attribute(
+    attributes: &mut BTreeMap<String, String>,
+    key: String,
+    value: String,
+) -> Result<(), StorageError> {
+    if key.is_empty() || key.len() > MAX_ATTRIBUTE_KEY_LENGTH {
+        return Err(StorageError::InvalidAttribute("invalid attribute key"));
+    }
+    if value.len() > MAX_ATTRIBUTE_VALUE_LENGTH {
+        return Err(StorageError::InvalidAttribute("attribute value too long"));
+    }
+
+    if value.is_empty() {
+        attributes.remove(&key);
+    } else if attributes.contains_key(&key) || attributes.len() < MAX_USER_ATTRIBUTES {
+        attributes.insert(key, value);
+    } else {
+        return Err(StorageError::InvalidAttribute("too many attributes"));
+    }
+
+    Ok(())
+}
+
+/// Emails are compared case insensitively.
+fn email_key(email: &str) -> String {
+    email.to_lowercase()
 }
 
-#[derive(Clone, Debug)]
-pub struct User {
-    user_uuid: String,
-    username: String,
-    password: String,
-}
+#[cfg(feature = "postgres")]
+mod postgres {
+    use std::sync::Arc;
+    use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+    use sqlx::postgres::{PgPool, PgPoolOptions};
+
+    use super::{
+        set_attribute, ImportedUser, User, UserCursor, UserFilter, UserStatus, Users, VerifiedUser,
+    };
+    use crate::clock::{Clock, SystemClock};
+    use crate::config::Migrations;
+    use crate::error::StorageError;
+    use crate::metrics::{record_failure, FailureReason};
+    use crate::migrations::{migrate, POSTGRES_MIGRATIONS};
+    use crate::password_hashing::{HashingPool, PasswordHasher};
+    use crate::random::{OsRandom, Random};
+    use crate::storage_retry::StorageRetry;
+    use crate::user_ids::{IdGenerator, UuidV4};
+
+    type UserRow = (
+        String,
+        String,
+        String,
+        String,
+        String,
+        i64,
+        Option<i64>,
+        bool,
+    );
+
+    /// Stores users in Postgres. Queries are sent as prepared statements, which sqlx caches per
+    /// pooled connection.
+    pub struct PostgresUsers {
+        pool: PgPool,
+        hashing: HashingPool,
+        id_generator: Box<dyn IdGenerator + Send + Sync>,
+        retry: StorageRetry,
+        clock: Box<dyn Clock + Send + Sync>,
+        random: Box<dyn Random + Send + Sync>,
+    }
+
+    impl PostgresUsers {
+        /// Connects to the database at `url` and migrates its schema according to `migrations`.
+        pub async fn connect(
+            url: &str,
+            max_connections: u32,
+            migrations: Migrations,
+        ) -> Result<Self, String> {
+            // Don't include `url` in errors, it usually contains a password.
+            let pool = PgPoolOptions::new()
+                .max_connections(max_connections)
+                .connect(url)
+                .await
+                .map_err(|e| format!("Failed to connect to the users database.\n{e:?}"))?;
+
+            let mut conn = pool
+                .acquire()
+                .await
+                .map_err(|e| format!("Failed to connect to the users database.\n{e:?}"))?;
+            migrate(&POSTGRES_MIGRATIONS, &mut *conn, "users", migrations).await?;
+            drop(conn);
+
+            Ok(Self {
+                pool,
+                hashing: HashingPool::default(),
+                id_generator: Box::new(UuidV4::default()),
+                retry: StorageRetry::default(),
+                clock: Box::new(SystemClock),
+                random: Box::new(OsRandom),
+            })
+        }
+
+        /// Hashes new passwords with `hasher` instead of PBKDF2 with the default rounds.
+        pub fn with_password_hasher(
+            mut self,
+            hasher: Box<dyn PasswordHasher + Send + Sync>,
+        ) -> Self {
+            self.hashing = self.hashing.with_hasher(hasher);
+            self
+        }
+
+        /// Hashes at most `concurrency` passwords at once instead of one per core.
+        pub fn with_hashing_concurrency(mut self, concurrency: usize) -> Self {
+            self.hashing = self.hashing.with_concurrency(concurrency);
+            self
+        }
+
+        /// Generates user ids with `id_generator` instead of random UUIDs.
+        pub fn with_id_generator(
+            mut self,
+            id_generator: Box<dyn IdGenerator + Send + Sync>,
+        ) -> Self {
+            self.id_generator = id_generator;
+            self
+        }
+
+        /// Retries queries failing transiently with `retry`, sharing its health with other stores.
+        pub fn with_retry(mut self, retry: StorageRetry) -> Self {
+            self.retry = retry;
+            self
+        }
+
+        /// Looks up the uuid and password hash of the user with `login` as their username or
+        /// email. Usernames win if both match. Deleted users are never found.
+        async fn find_password_hash(&self, login: &str) -> Option<(String, String)> {
+            self.retry
+                .run(|| {
+                    sqlx::query_as(
+                        "SELECT user_uuid, password FROM users
+                         WHERE deleted_at IS NULL
+                             AND (username = $1 OR (email <> '' AND lower(email) = lower($1)))
+                         ORDER BY username = $1 DESC
+                         LIMIT 1",
+                    )
+                    .bind(login)
+                    .fetch_optional(&self.pool)
+                })
+                .await
+                .unwrap_or_else(|e| {
+                    tracing::error!(error = ?e, "Failed to look up user");
+                    record_failure(FailureReason::BackendError);
+                    None
+                })
+        }
+
+        /// Inserts a new user with an already hashed password, unless the username is taken. The
+        /// display name defaults to the username. Returns whether the user was inserted, see
+        /// `inserted`.
+        async fn insert_user(
+            executor: impl sqlx::PgExecutor<'_>,
+            user_uuid: &str,
+            username: &str,
+            hashed_password: &str,
+            email: &str,
+            display_name: Option<&str>,
+            created_at: SystemTime,
+        ) -> Result<bool, sqlx::Error> {
+            sqlx::query(
+                "INSERT INTO users (user_uuid, username, password, display_name, email, created_at)
+                 VALUES ($1, $2, $3, COALESCE($6, $2), $4, $5)
+                 ON CONFLICT (username) DO NOTHING",
+            )
+            .bind(user_uuid)
+            .bind(username)
+            .bind(hashed_password)
+            .bind(email)
+            .bind(unix_timestamp(created_at))
+            .bind(display_name)
+            .execute(executor)
+            .await
+            .map(|result| result.rows_affected() > 0)
+        }
+
+        /// Whether `username` is the email of a user other than `user_uuid`, and whether `email`
+        /// is their username, see `available_logins`. Empty values never match.
+        async fn login_conflicts(
+            executor: impl sqlx::PgExecutor<'_>,
+            user_uuid: &str,
+            username: &str,
+            email: &str,
+        ) -> Result<(bool, bool), sqlx::Error> {
+            sqlx::query_as(
+                "SELECT
+                     EXISTS (SELECT 1 FROM users WHERE user_uuid <> $1 AND $2 <> ''
+                         AND email <> '' AND lower(email) = lower($2)),
+                     EXISTS (SELECT 1 FROM users WHERE user_uuid <> $1 AND $3 <> ''
+                         AND username IN ($3, lower($3)))",
+            )
+            .bind(user_uuid)
+            .bind(username)
+            .bind(email)
+            .fetch_one(executor)
+            .await
+        }
+
+        /// Inserts an imported user, keeping their uuid, hash and creation time when given.
+        async fn import_user(&self, user: ImportedUser) -> Result<(), StorageError> {
+            let hashed_password = user.password.into_hash(&self.hashing).await?;
+
+            let user_uuid = match user.user_uuid {
+                Some(user_uuid) if self.get_user(user_uuid.clone()).await.is_some() => {
+                    return Err(StorageError::UserUuidTaken)
+                }
+                Some(user_uuid) => user_uuid,
+                None => self.id_generator.generate(),
+            };
+            available_logins(
+                self.retry
+                    .run(|| {
+                        Self::login_conflicts(&self.pool, &user_uuid, &user.username, &user.email)
+                    })
+                    .await,
+            )?;
+
+            let created_at = user.created_at.unwrap_or_else(|| self.clock.now());
+            let result = self
+                .retry
+                .run(|| {
+                    Self::insert_user(
+                        &self.pool,
+                        &user_uuid,
+                        &user.username,
+                        &hashed_password,
+                        &user.email,
+                        user.display_name.as_deref(),
+                        created_at,
+                    )
+                })
+                .await;
+            inserted(result)
+        }
+    }
+
+    #[tonic::async_trait]
+    impl Users for PostgresUsers {
+        #[tracing::instrument(skip_all, fields(db.system = "postgresql", otel.kind = "client"))]
+        async fn create_user_hashed(
+            &mut self,
+            username: String,
+            hashed_password: String,
+            email: String,
+        ) -> Result<(), StorageError> {
+            available_logins(
+                self.retry
+                    .run(|| Self::login_conflicts(&self.pool, "", &username, &email))
+                    .await,
+            )?;
+
+            let user_uuid = self.id_generator.generate();
+            let created_at = self.clock.now();
+            let result = self
+                .retry
+                .run(|| {
+                    Self::insert_user(
+                        &self.pool,
+                        &user_uuid,
+                        &username,
+                        &hashed_password,
+                        &email,
+                        None,
+                        created_at,
+                    )
+                })
+                .await;
+            inserted(result)
+        }
+
+        #[tracing::instrument(skip_all, fields(db.system = "postgresql", otel.kind = "client"))]
+        async fn create_users(
+            &mut self,
+            users: Vec<(String, String)>,
+        ) -> Vec<Result<(), StorageError>> {
+            // Entries are independent, a later duplicate fails on the unique username.
+            let mut results = Vec::with_capacity(users.len());
+            for (username, password) in users {
+                results.push(self.create_user(username, password, String::new()).await);
+            }
+            results
+        }
+
+        async fn hashing(&self) -> HashingPool {
+            self.hashing.clone()
+        }
+
+        #[tracing::instrument(skip_all, fields(db.system = "postgresql", otel.kind = "client"))]
+        async fn verify_password(
+            &self,
+            username: String,
+            password: String,
+        ) -> Option<VerifiedUser> {
+            match self.find_password_hash(&username).await {
+                Some((user_uuid, hashed_password)) => self
+                    .hashing
+                    .verify_password(&hashed_password, &password)
+                    .await
+                    .then(|| VerifiedUser {
+                        user_uuid,
+                        needs_rehash: self.hashing.needs_rehash(&hashed_password),
+                    }),
+                None => {
+                    self.hashing.verify_dummy(&password).await;
+                    None
+                }
+            }
+        }
+
+        #[tracing::instrument(skip_all, fields(db.system = "postgresql", otel.kind = "client"))]
+        async fn lookup_user_uuid(&self, username: String) -> Option<String> {
+            self.retry
+                .run(|| {
+                    sqlx::query_scalar(
+                        "SELECT user_uuid FROM users WHERE username = $1 AND deleted_at IS NULL",
+                    )
+                    .bind(&username)
+                    .fetch_optional(&self.pool)
+                })
+                .await
+                .unwrap_or_else(|e| {
+                    tracing::error!(error = ?e, "Failed to look up user");
+                    record_failure(FailureReason::BackendError);
+                    None
+                })
+        }
+
+        #[tracing::instrument(skip_all, fields(db.system = "postgresql", otel.kind = "client"))]
+        async fn update_password_hashed(
+            &mut self,
+            user_uuid: String,
+            hashed_password: String,
+        ) -> Result<(), StorageError> {
+            let result = self
+                .retry
+                .run(|| {
+                    sqlx::query("UPDATE users SET password = $2 WHERE user_uuid = $1")
+                        .bind(&user_uuid)
+                        .bind(&hashed_password)
+                        .execute(&self.pool)
+                })
+                .await
+                .map_err(|e| StorageError::backend(format!("Failed to update password.\n{e:?}")))?;
+
+            match result.rows_affected() {
+                0 => Err(StorageError::UserNotFound),
+                _ => Ok(()),
+            }
+        }
+
+        #[tracing::instrument(skip_all, fields(db.system = "postgresql", otel.kind = "client"))]
+        async fn get_user(&self, user_uuid: String) -> Option<Arc<User>> {
+            let row: Option<UserRow> = self
+                .retry
+                .run(|| {
+                    sqlx::query_as(
+                "SELECT user_uuid, username, password, display_name, email, created_at, deleted_at,
+                                disabled
+                                 FROM users WHERE user_uuid = $1",
+            )
+            .bind(&user_uuid)
+            .fetch_optional(&self.pool)
+                })
+                .await
+                .unwrap_or_else(|e| {
+                    tracing::error!(error = ?e, "Failed to get user");
+                    record_failure(FailureReason::BackendError);
+                    None
+                });
+
+            let user = user_from_row(row?);
+
+            let attributes: Vec<(String, String)> = self
+                .retry
+                .run(|| {
+                    sqlx::query_as("SELECT name, value FROM user_attributes WHERE user_uuid = $1")
+                        .bind(&user.user_uuid)
+                        .fetch_all(&self.pool)
+                })
+                .await
+                .unwrap_or_else(|e| {
+                    tracing::error!(error = ?e, "Failed to get user attributes");
+                    record_failure(FailureReason::BackendError);
+                    Vec::new()
+                });
+
+            Some(Arc::new(User {
+                attributes: attributes.into_iter().collect(),
+                ..user
+            }))
+        }
+
+        #[tracing::instrument(skip_all, fields(db.system = "postgresql", otel.kind = "client"))]
+        async fn update_user(
+            &mut self,
+            user_uuid: String,
+            display_name: Option<String>,
+            email: Option<String>,
+        ) -> Result<(), StorageError> {
+            if let Some(email) = &email {
+                available_logins(
+                    self.retry
+                        .run(|| Self::login_conflicts(&self.pool, &user_uuid, "", email))
+                        .await,
+                )?;
+            }
+
+            let result = self
+                .retry
+                .run(|| {
+                    sqlx::query(
+                        "UPDATE users
+                                 SET display_name = COALESCE($2, display_name), email = COALESCE($3, email)
+                                 WHERE user_uuid = $1",
+                    )
+                    .bind(&user_uuid)
+                    .bind(&display_name)
+                    .bind(&email)
+                    .execute(&self.pool)
+                })
+                .await
+                .map_err(|e| email_conflict(e, "Failed to update user."))?;
+
+            match result.rows_affected() {
+                0 => Err(StorageError::UserNotFound),
+                _ => Ok(()),
+            }
+        }
+
+        #[tracing::instrument(skip_all, fields(db.system = "postgresql", otel.kind = "client"))]
+        async fn set_user_attribute(
+            &mut self,
+            user_uuid: String,
+            key: String,
+            value: String,
+        ) -> Result<(), StorageError> {
+            // Check the limits against the current attributes before writing.
+            let mut attributes = self
+                .get_user(user_uuid.clone())
+                .await
+                .ok_or(StorageError::UserNotFound)?
+                .attributes
+                .clone();
+
+            set_attribute(&mut attributes, key.clone(), value.clone())?;
+
+            let result = match value.is_empty() {
+                true => {
+                    self.retry
+                        .run(|| {
+                            sqlx::query(
+                                "DELETE FROM user_attributes WHERE user_uuid = $1 AND name = $2",
+                            )
+                            .bind(&user_uuid)
+                            .bind(&key)
+                            .execute(&self.pool)
+                        })
+                        .await
+                }
+                false => {
+                    self.retry
+                        .run(|| {
+                            sqlx::query(
+                        "INSERT INTO user_attributes (user_uuid, name, value) VALUES ($1, $2, $3)
+                                 ON CONFLICT (user_uuid, name) DO UPDATE SET value = excluded.value",
+                    )
+                    .bind(&user_uuid)
+                    .bind(&key)
+                    .bind(&value)
+                    .execute(&self.pool)
+                        })
+                        .await
+                }
+            };
+
+            result
+                .map(|_| ())
+                .map_err(|e| StorageError::backend(format!("Failed to set user attribute.\n{e:?}")))
+        }
+
+        #[tracing::instrument(skip_all, fields(db.system = "postgresql", otel.kind = "client"))]
+        async fn delete_user(&mut self, user_uuid: String) {
+            // Linked identities are removed by the cascade.
+            let result = sqlx::query("DELETE FROM users WHERE user_uuid = $1")
+                .bind(&user_uuid)
+                .execute(&self.pool)
+                .await;
+
+            match result {
+                Ok(result) if result.rows_affected() == 0 => tracing::warn!("User uuid not found"),
+                Ok(_) => (),
+                Err(e) => {
+                    tracing::error!(error = ?e, "Failed to delete user");
+                    record_failure(FailureReason::BackendError);
+                }
+            }
+        }
+
+        #[tracing::instrument(skip_all, fields(db.system = "postgresql", otel.kind = "client"))]
+        async fn soft_delete_user(&mut self, user_uuid: String) -> Result<(), StorageError> {
+            let result = self
+                .retry
+                .run(|| {
+                    sqlx::query(
+                "UPDATE users SET deleted_at = $2 WHERE user_uuid = $1 AND deleted_at IS NULL",
+            )
+            .bind(&user_uuid)
+            .bind(unix_timestamp(self.clock.now()))
+            .execute(&self.pool)
+                })
+                .await
+                .map_err(|e| StorageError::backend(format!("Failed to delete user.\n{e:?}")))?;
+
+            match result.rows_affected() {
+                0 => Err(StorageError::UserNotFound),
+                _ => Ok(()),
+            }
+        }
+
+        #[tracing::instrument(skip_all, fields(db.system = "postgresql", otel.kind = "client"))]
+        async fn restore_user(&mut self, user_uuid: String) -> Result<(), StorageError> {
+            let result = self.retry.run(|| sqlx::query(
+                "UPDATE users SET deleted_at = NULL WHERE user_uuid = $1 AND deleted_at IS NOT NULL",
+            )
+            .bind(&user_uuid)
+            .execute(&self.pool))
+            .await
+            .map_err(|e| StorageError::backend(format!("Failed to restore user.\n{e:?}")))?;
+
+            match result.rows_affected() {
+                0 => Err(StorageError::NotDeleted),
+                _ => Ok(()),
+            }
+        }
+
+        #[tracing::instrument(skip_all, fields(db.system = "postgresql", otel.kind = "client"))]
+        async fn set_user_disabled(
+            &mut self,
+            user_uuid: String,
+            disabled: bool,
+        ) -> Result<(), StorageError> {
+            let result = self
+                .retry
+                .run(|| {
+                    sqlx::query("UPDATE users SET disabled = $2 WHERE user_uuid = $1")
+                        .bind(&user_uuid)
+                        .bind(disabled)
+                        .execute(&self.pool)
+                })
+                .await
+                .map_err(|e| StorageError::backend(format!("Failed to update user.\n{e:?}")))?;
+
+            match result.rows_affected() {
+                0 => Err(StorageError::UserNotFound),
+                _ => Ok(()),
+            }
+        }
+
+        #[tracing::instrument(skip_all, fields(db.system = "postgresql", otel.kind = "client"))]
+        async fn purge_deleted_users(&mut self, deleted_before: SystemTime) -> Vec<String> {
+            // Linked identities and attributes are removed by the cascade.
+            self.retry
+                .run(|| {
+                    sqlx::query_scalar(
+                        "DELETE FROM users WHERE deleted_at < $1 RETURNING user_uuid",
+                    )
+                    .bind(unix_timestamp(deleted_before))
+                    .fetch_all(&self.pool)
+                })
+                .await
+                .unwrap_or_else(|e| {
+                    tracing::error!(error = ?e, "Failed to purge deleted users");
+                    record_failure(FailureReason::BackendError);
+                    Vec::new()
+                })
+        }
+
+        #[tracing::instrument(skip_all, fields(db.system = "postgresql", otel.kind = "client"))]
+        async fn import_users(
+            &mut self,
+            users: Vec<ImportedUser>,
+        ) -> Vec<Result<(), StorageError>> {
+            // Entries are independent, a later duplicate fails on the unique username or email.
+            let mut results = Vec::with_capacity(users.len());
+            for user in users {
+                results.push(self.import_user(user).await);
+            }
+            results
+        }
+
+        #[tracing::instrument(skip_all, fields(db.system = "postgresql", otel.kind = "client"))]
+        async fn search_users(
+            &self,
+            filter: &UserFilter,
+            after: Option<&UserCursor>,
+            limit: usize,
+        ) -> Vec<User> {
+            let rows: Vec<UserRow> = self
+                .retry
+                .run(|| {
+                    sqlx::query_as(
+                "SELECT user_uuid, username, password, display_name, email, created_at, deleted_at,
+                                disabled
+                         FROM users
+                         WHERE substr(username, 1, length($1)) = $1
+                             AND ($2 = '' OR lower(email) LIKE '%@' || $2 ESCAPE '\\')
+                             AND ($3 IS NULL OR created_at >= $3)
+                             AND ($4 IS NULL OR created_at < $4)
+                             AND ($5 IS NULL OR (deleted_at IS NOT NULL) = $5)
+                             AND ($6 IS NULL OR created_at > $6 OR (created_at = $6 AND user_uuid > $7))
+                         ORDER BY created_at, user_uuid
+                         LIMIT $8",
+            )
+            .bind(&filter.username_prefix)
+            .bind(escape_like(&filter.email_domain.to_lowercase()))
+            .bind(filter.created_after.map(unix_timestamp))
+            .bind(filter.created_before.map(unix_timestamp))
+            .bind(filter.status.map(|status| status == UserStatus::Deleted))
+            .bind(after.map(|after| unix_timestamp(after.created_at)))
+            .bind(after.map(|after| after.user_uuid.as_str()))
+            .bind(limit as i64)
+            .fetch_all(&self.pool)
+                })
+                .await
+                .unwrap_or_else(|e| {
+                    tracing::error!(error = ?e, "Failed to search users");
+                    record_failure(FailureReason::BackendError);
+                    Vec::new()
+                });
+
+            rows.into_iter().map(user_from_row).collect()
+        }
+
+        #[tracing::instrument(skip_all, fields(db.system = "postgresql", otel.kind = "client"))]
+        async fn count_users(&self) -> Result<usize, StorageError> {
+            self.retry
+                .run(|| {
+                    sqlx::query_scalar("SELECT COUNT(*) FROM users WHERE deleted_at IS NULL")
+                        .fetch_one(&self.pool)
+                })
+                .await
+                .map(|count: i64| count as usize)
+                .map_err(|e| StorageError::backend(format!("Failed to count users.\n{e:?}")))
+        }
+
+        #[tracing::instrument(skip_all, fields(db.system = "postgresql", otel.kind = "client"))]
+        async fn get_federated_user_uuid(&self, issuer: &str, subject: &str) -> Option<String> {
+            self.retry
+                .run(|| {
+                    sqlx::query_scalar(
+                        "SELECT users.user_uuid FROM federated_identities
+                         JOIN users ON users.user_uuid = federated_identities.user_uuid
+                         WHERE issuer = $1 AND subject = $2 AND deleted_at IS NULL",
+                    )
+                    .bind(issuer)
+                    .bind(subject)
+                    .fetch_optional(&self.pool)
+                })
+                .await
+                .unwrap_or_else(|e| {
+                    tracing::error!(error = ?e, "Failed to look up federated user");
+                    record_failure(FailureReason::BackendError);
+                    None
+                })
+        }
+
+        #[tracing::instrument(skip_all, fields(db.system = "postgresql", otel.kind = "client"))]
+        async fn create_federated_user(
+            &mut self,
+            issuer: &str,
+            subject: &str,
+            username: String,
+        ) -> Result<String, StorageError> {
+            let hashed_password = self
+                .hashing
+                .hash_password(&self.random.uuid().to_string())
+                .await
+                .map_err(StorageError::Hashing)?;
+
+            // Create the user and link it together, or not at all.
+            let mut transaction = self.retry.run(|| self.pool.begin()).await.map_err(|e| {
+                StorageError::backend(format!("Failed to create federated user.\n{e:?}"))
+            })?;
+
+            available_logins(Self::login_conflicts(&mut *transaction, "", &username, "").await)?;
+            let user_uuid = self.id_generator.generate();
+            inserted(
+                Self::insert_user(
+                    &mut *transaction,
+                    &user_uuid,
+                    &username,
+                    &hashed_password,
+                    "",
+                    None,
+                    self.clock.now(),
+                )
+                .await,
+            )?;
+
+            let result = sqlx::query(
+                "INSERT INTO federated_identities (issuer, subject, user_uuid)
+                 VALUES ($1, $2, $3)
+                 ON CONFLICT (issuer, subject) DO NOTHING",
+            )
+            .bind(issuer)
+            .bind(subject)
+            .bind(&user_uuid)
+            .execute(&mut *transaction)
+            .await
+            .map_err(|e| {
+                StorageError::backend(format!("Failed to create federated user.\n{e:?}"))
+            })?;
+
+            // Dropping the transaction rolls the new user back.
+            if result.rows_affected() == 0 {
+                return Err(StorageError::IdentityLinked);
+            }
+
+            transaction.commit().await.map_err(|e| {
+                StorageError::backend(format!("Failed to create federated user.\n{e:?}"))
+            })?;
+
+            Ok(user_uuid)
+        }
+
+        async fn flush(&mut self) {
+            self.pool.close().await;
+        }
+
+        #[tracing::instrument(skip_all, fields(db.system = "postgresql", otel.kind = "client"))]
+        async fn ping(&self) -> Result<(), StorageError> {
+            self.retry
+                .run(|| sqlx::query("SELECT 1").execute(&self.pool))
+                .await
+                .map(|_| ())
+                .map_err(|e| {
+                    StorageError::backend(format!("Failed to reach the users database.\n{e:?}"))
+                })
+        }
+    }
+
+    /// Reports the outcome of `insert_user` like `UsersImpl` does.
+    fn inserted(result: Result<bool, sqlx::Error>) -> Result<(), StorageError> {
+        match result.map_err(|e| email_conflict(e, "Failed to create user."))? {
+            true => Ok(()),
+            false => Err(StorageError::UsernameTaken),
+        }
+    }
+
+    /// Fails like `UsersImpl` does when a username would be another user's email or the other way
+    /// around, since `find_password_hash` would then sign in as the wrong one.
+    fn available_logins(conflicts: Result<(bool, bool), sqlx::Error>) -> Result<(), StorageError> {
+        match conflicts {
+            Ok((true, _)) => Err(StorageError::UsernameTaken),
+            Ok((_, true)) => Err(StorageError::EmailTaken),
+            Ok(_) => Ok(()),
+            Err(e) => Err(StorageError::backend(format!(
+                "Failed to check usernames and emails.\n{e:?}"
+            ))),
+        }
+    }
+
+    /// Reports a violation of the unique email index like `UsersImpl` does.
+    fn email_conflict(e: sqlx::Error, context: &str) -> StorageError {
+        match e.as_database_error() {
+            Some(db_error) if db_error.is_unique_violation() => StorageError::EmailTaken,
+            _ => StorageError::backend(format!("{context}\n{e:?}")),
+        }
+    }
+
+    /// Builds a user from a row of `users`, without attributes.
+    fn user_from_row(row: UserRow) -> User {
+        let (user_uuid, username, password, display_name, email, created_at, deleted_at, disabled) =
+            row;
+
+        User {
+            user_uuid,
+            username,
+            password: password.into(),
+            display_name,
+            email,
+            created_at: UNIX_EPOCH + Duration::from_secs(created_at as u64),
+            attributes: Default::default(),
+            deleted_at: deleted_at
+                .map(|deleted_at| UNIX_EPOCH + Duration::from_secs(deleted_at as u64)),
+            disabled,
+        }
+    }
+
+    /// Escapes `value` to be matched literally by `LIKE ... ESCAPE '\'`.
+    fn escape_like(value: &str) -> String {
+        value
+            .replace('\\', "\\\\")
+            .replace('%', "\\%")
+            .replace('_', "\\_")
+    }
+
+    fn unix_timestamp(time: SystemTime) -> i64 {
+        time.duration_since(UNIX_EPOCH)
+            .map(|duration| duration.as_secs() as i64)
+            .unwrap_or_default()
+    }
+}
+
+#[cfg(feature = "sqlite")]
+mod sqlite {
+    use std::sync::Arc;
+    use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+    use sqlx::sqlite::{SqliteConnectOptions, SqlitePool, SqlitePoolOptions};
+
+    use super::{
+        set_attribute, ImportedUser, User, UserCursor, UserFilter, UserStatus, Users, VerifiedUser,
+    };
+    use crate::clock::{Clock, SystemClock};
+    use crate::config::Migrations;
+    use crate::error::StorageError;
+    use crate::metrics::{record_failure, FailureReason};
+    use crate::migrations::{migrate, SQLITE_MIGRATIONS};
+    use crate::password_hashing::{HashingPool, PasswordHasher};
+    use crate::random::{OsRandom, Random};
+    use crate::storage_retry::StorageRetry;
+    use crate::user_ids::{IdGenerator, UuidV4};
+
+    type UserRow = (
+        String,
+        String,
+        String,
+        String,
+        String,
+        i64,
+        Option<i64>,
+        bool,
+    );
+
+    /// Stores users in an embedded SQLite database, so single node deployments keep them across
+    /// restarts without running a database server.
+    pub struct SqliteUsers {
+        pool: SqlitePool,
+        hashing: HashingPool,
+        id_generator: Box<dyn IdGenerator + Send + Sync>,
+        retry: StorageRetry,
+        clock: Box<dyn Clock + Send + Sync>,
+        random: Box<dyn Random + Send + Sync>,
+    }
+
+    impl SqliteUsers {
+        /// Opens the database at `path`, creating it if needed, and migrates its schema according
+        /// to `migrations`.
+        pub async fn open(path: &str, migrations: Migrations) -> Result<Self, String> {
+            let options = SqliteConnectOptions::new()
+                .filename(path)
+                .create_if_missing(true);
+
+            let pool = SqlitePoolOptions::new()
+                .connect_with(options)
+                .await
+                .map_err(|e| format!("Failed to open {path}.\n{e:?}"))?;
+
+            let mut conn = pool
+                .acquire()
+                .await
+                .map_err(|e| format!("Failed to open {path}.\n{e:?}"))?;
+            migrate(&SQLITE_MIGRATIONS, &mut *conn, path, migrations).await?;
+            drop(conn);
+
+            Ok(Self {
+                pool,
+                hashing: HashingPool::default(),
+                id_generator: Box::new(UuidV4::default()),
+                retry: StorageRetry::default(),
+                clock: Box::new(SystemClock),
+                random: Box::new(OsRandom),
+            })
+        }
+
+        /// Hashes new passwords with `hasher` instead of PBKDF2 with the default rounds.
+        pub fn with_password_hasher(
+            mut self,
+            hasher: Box<dyn PasswordHasher + Send + Sync>,
+        ) -> Self {
+            self.hashing = self.hashing.with_hasher(hasher);
+            self
+        }
+
+        /// Hashes at most `concurrency` passwords at once instead of one per core.
+        pub fn with_hashing_concurrency(mut self, concurrency: usize) -> Self {
+            self.hashing = self.hashing.with_concurrency(concurrency);
+            self
+        }
+
+        /// Generates user ids with `id_generator` instead of random UUIDs.
+        pub fn with_id_generator(
+            mut self,
+            id_generator: Box<dyn IdGenerator + Send + Sync>,
+        ) -> Self {
+            self.id_generator = id_generator;
+            self
+        }
+
+        /// Retries queries failing transiently with `retry`, sharing its health with other stores.
+        pub fn with_retry(mut self, retry: StorageRetry) -> Self {
+            self.retry = retry;
+            self
+        }
+
+        /// Tells the time with `clock` instead of the system clock, see `UsersImpl::with_clock`.
+        #[cfg(test)]
+        pub fn with_clock(mut self, clock: Box<dyn Clock + Send + Sync>) -> Self {
+            self.clock = clock;
+            self
+        }
+
+        /// Looks up the uuid and password hash of the user with `login` as their username or
+        /// email. Usernames win if both match. Deleted users are never found.
+        async fn find_password_hash(&self, login: &str) -> Option<(String, String)> {
+            self.retry
+                .run(|| {
+                    sqlx::query_as(
+                        "SELECT user_uuid, password FROM users
+                         WHERE deleted_at IS NULL
+                             AND (username = $1 OR (email <> '' AND lower(email) = lower($1)))
+                         ORDER BY username = $1 DESC
+                         LIMIT 1",
+                    )
+                    .bind(login)
+                    .fetch_optional(&self.pool)
+                })
+                .await
+                .unwrap_or_else(|e| {
+                    tracing::error!(error = ?e, "Failed to look up user");
+                    record_failure(FailureReason::BackendError);
+                    None
+                })
+        }
+
+        /// Inserts a new user with an already hashed password, unless the username is taken. The
+        /// display name defaults to the username. Returns whether the user was inserted, see
+        /// `inserted`.
+        async fn insert_user(
+            executor: impl sqlx::SqliteExecutor<'_>,
+            user_uuid: &str,
+            username: &str,
+            hashed_password: &str,
+            email: &str,
+            display_name: Option<&str>,
+            created_at: SystemTime,
+        ) -> Result<bool, sqlx::Error> {
+            sqlx::query(
+                "INSERT INTO users (user_uuid, username, password, display_name, email, created_at)
+                 VALUES ($1, $2, $3, COALESCE($6, $2), $4, $5)
+                 ON CONFLICT (username) DO NOTHING",
+            )
+            .bind(user_uuid)
+            .bind(username)
+            .bind(hashed_password)
+            .bind(email)
+            .bind(unix_timestamp(created_at))
+            .bind(display_name)
+            .execute(executor)
+            .await
+            .map(|result| result.rows_affected() > 0)
+        }
+
+        /// Whether `username` is the email of a user other than `user_uuid`, and whether `email`
+        /// is their username, see `available_logins`. Empty values never match.
+        async fn login_conflicts(
+            executor: impl sqlx::SqliteExecutor<'_>,
+            user_uuid: &str,
+            username: &str,
+            email: &str,
+        ) -> Result<(bool, bool), sqlx::Error> {
+            sqlx::query_as(
+                "SELECT
+                     EXISTS (SELECT 1 FROM users WHERE user_uuid <> $1 AND $2 <> ''
+                         AND email <> '' AND lower(email) = lower($2)),
+                     EXISTS (SELECT 1 FROM users WHERE user_uuid <> $1 AND $3 <> ''
+                         AND username IN ($3, lower($3)))",
+            )
+            .bind(user_uuid)
+            .bind(username)
+            .bind(email)
+            .fetch_one(executor)
+            .await
+        }
+
+        /// Inserts an imported user, keeping their uuid, hash and creation time when given.
+        async fn import_user(&self, user: ImportedUser) -> Result<(), StorageError> {
+            let hashed_password = user.password.into_hash(&self.hashing).await?;
+
+            let user_uuid = match user.user_uuid {
+                Some(user_uuid) if self.get_user(user_uuid.clone()).await.is_some() => {
+                    return Err(StorageError::UserUuidTaken)
+                }
+                Some(user_uuid) => user_uuid,
+                None => self.id_generator.generate(),
+            };
+            available_logins(
+                self.retry
+                    .run(|| {
+                        Self::login_conflicts(&self.pool, &user_uuid, &user.username, &user.email)
+                    })
+                    .await,
+            )?;
+
+            let created_at = user.created_at.unwrap_or_else(|| self.clock.now());
+            let result = self
+                .retry
+                .run(|| {
+                    Self::insert_user(
+                        &self.pool,
+                        &user_uuid,
+                        &user.username,
+                        &hashed_password,
+                        &user.email,
+                        user.display_name.as_deref(),
+                        created_at,
+                    )
+                })
+                .await;
+            inserted(result)
+        }
+    }
+
+    #[tonic::async_trait]
+    impl Users for SqliteUsers {
+        #[tracing::instrument(skip_all, fields(db.system = "sqlite", otel.kind = "client"))]
+        async fn create_user_hashed(
+            &mut self,
+            username: String,
+            hashed_password: String,
+            email: String,
+        ) -> Result<(), StorageError> {
+            available_logins(
+                self.retry
+                    .run(|| Self::login_conflicts(&self.pool, "", &username, &email))
+                    .await,
+            )?;
+
+            let user_uuid = self.id_generator.generate();
+            let created_at = self.clock.now();
+            let result = self
+                .retry
+                .run(|| {
+                    Self::insert_user(
+                        &self.pool,
+                        &user_uuid,
+                        &username,
+                        &hashed_password,
+                        &email,
+                        None,
+                        created_at,
+                    )
+                })
+                .await;
+            inserted(result)
+        }
+
+        #[tracing::instrument(skip_all, fields(db.system = "sqlite", otel.kind = "client"))]
+        async fn create_users(
+            &mut self,
+            users: Vec<(String, String)>,
+        ) -> Vec<Result<(), StorageError>> {
+            // Entries are independent, a later duplicate fails on the unique username.
+            let mut results = Vec::with_capacity(users.len());
+            for (username, password) in users {
+                results.push(self.create_user(username, password, String::new()).await);
+            }
+            results
+        }
+
+        async fn hashing(&self) -> HashingPool {
+            self.hashing.clone()
+        }
+
+        #[tracing::instrument(skip_all, fields(db.system = "sqlite", otel.kind = "client"))]
+        async fn verify_password(
+            &self,
+            username: String,
+            password: String,
+        ) -> Option<VerifiedUser> {
+            match self.find_password_hash(&username).await {
+                Some((user_uuid, hashed_password)) => self
+                    .hashing
+                    .verify_password(&hashed_password, &password)
+                    .await
+                    .then(|| VerifiedUser {
+                        user_uuid,
+                        needs_rehash: self.hashing.needs_rehash(&hashed_password),
+                    }),
+                None => {
+                    self.hashing.verify_dummy(&password).await;
+                    None
+                }
+            }
+        }
+
+        #[tracing::instrument(skip_all, fields(db.system = "sqlite", otel.kind = "client"))]
+        async fn lookup_user_uuid(&self, username: String) -> Option<String> {
+            self.retry
+                .run(|| {
+                    sqlx::query_scalar(
+                        "SELECT user_uuid FROM users WHERE username = $1 AND deleted_at IS NULL",
+                    )
+                    .bind(&username)
+                    .fetch_optional(&self.pool)
+                })
+                .await
+                .unwrap_or_else(|e| {
+                    tracing::error!(error = ?e, "Failed to look up user");
+                    record_failure(FailureReason::BackendError);
+                    None
+                })
+        }
+
+        #[tracing::instrument(skip_all, fields(db.system = "sqlite", otel.kind = "client"))]
+        async fn update_password_hashed(
+            &mut self,
+            user_uuid: String,
+            hashed_password: String,
+        ) -> Result<(), StorageError> {
+            let result = self
+                .retry
+                .run(|| {
+                    sqlx::query("UPDATE users SET password = $2 WHERE user_uuid = $1")
+                        .bind(&user_uuid)
+                        .bind(&hashed_password)
+                        .execute(&self.pool)
+                })
+                .await
+                .map_err(|e| StorageError::backend(format!("Failed to update password.\n{e:?}")))?;
+
+            match result.rows_affected() {
+                0 => Err(StorageError::UserNotFound),
+                _ => Ok(()),
+            }
+        }
+
+        #[tracing::instrument(skip_all, fields(db.system = "sqlite", otel.kind = "client"))]
+        async fn get_user(&self, user_uuid: String) -> Option<Arc<User>> {
+            let row: Option<UserRow> = self
+                .retry
+                .run(|| {
+                    sqlx::query_as(
+                "SELECT user_uuid, username, password, display_name, email, created_at, deleted_at,
+                                disabled
+                                 FROM users WHERE user_uuid = $1",
+            )
+            .bind(&user_uuid)
+            .fetch_optional(&self.pool)
+                })
+                .await
+                .unwrap_or_else(|e| {
+                    tracing::error!(error = ?e, "Failed to get user");
+                    record_failure(FailureReason::BackendError);
+                    None
+                });
+
+            let user = user_from_row(row?);
+
+            let attributes: Vec<(String, String)> = self
+                .retry
+                .run(|| {
+                    sqlx::query_as("SELECT name, value FROM user_attributes WHERE user_uuid = $1")
+                        .bind(&user.user_uuid)
+                        .fetch_all(&self.pool)
+                })
+                .await
+                .unwrap_or_else(|e| {
+                    tracing::error!(error = ?e, "Failed to get user attributes");
+                    record_failure(FailureReason::BackendError);
+                    Vec::new()
+                });
+
+            Some(Arc::new(User {
+                attributes: attributes.into_iter().collect(),
+                ..user
+            }))
+        }
+
+        #[tracing::instrument(skip_all, fields(db.system = "sqlite", otel.kind = "client"))]
+        async fn update_user(
+            &mut self,
+            user_uuid: String,
+            display_name: Option<String>,
+            email: Option<String>,
+        ) -> Result<(), StorageError> {
+            if let Some(email) = &email {
+                available_logins(
+                    self.retry
+                        .run(|| Self::login_conflicts(&self.pool, &user_uuid, "", email))
+                        .await,
+                )?;
+            }
+
+            let result = self
+                .retry
+                .run(|| {
+                    sqlx::query(
+                        "UPDATE users
+                                 SET display_name = COALESCE($2, display_name), email = COALESCE($3, email)
+                                 WHERE user_uuid = $1",
+                    )
+                    .bind(&user_uuid)
+                    .bind(&display_name)
+                    .bind(&email)
+                    .execute(&self.pool)
+                })
+                .await
+                .map_err(|e| email_conflict(e, "Failed to update user."))?;
+
+            match result.rows_affected() {
+                0 => Err(StorageError::UserNotFound),
+                _ => Ok(()),
+            }
+        }
+
+        #[tracing::instrument(skip_all, fields(db.system = "sqlite", otel.kind = "client"))]
+        async fn set_user_attribute(
+            &mut self,
+            user_uuid: String,
+            key: String,
+            value: String,
+        ) -> Result<(), StorageError> {
+            // Check the limits against the current attributes before writing.
+            let mut attributes = self
+                .get_user(user_uuid.clone())
+                .await
+                .ok_or(StorageError::UserNotFound)?
+                .attributes
+                .clone();
+
+            set_attribute(&mut attributes, key.clone(), value.clone())?;
+
+            let result = match value.is_empty() {
+                true => {
+                    self.retry
+                        .run(|| {
+                            sqlx::query(
+                                "DELETE FROM user_attributes WHERE user_uuid = $1 AND name = $2",
+                            )
+                            .bind(&user_uuid)
+                            .bind(&key)
+                            .execute(&self.pool)
+                        })
+                        .await
+                }
+                false => {
+                    self.retry
+                        .run(|| {
+                            sqlx::query(
+                        "INSERT INTO user_attributes (user_uuid, name, value) VALUES ($1, $2, $3)
+                                 ON CONFLICT (user_uuid, name) DO UPDATE SET value = excluded.value",
+                    )
+                    .bind(&user_uuid)
+                    .bind(&key)
+                    .bind(&value)
+                    .execute(&self.pool)
+                        })
+                        .await
+                }
+            };
+
+            result
+                .map(|_| ())
+                .map_err(|e| StorageError::backend(format!("Failed to set user attribute.\n{e:?}")))
+        }
+
+        #[tracing::instrument(skip_all, fields(db.system = "sqlite", otel.kind = "client"))]
+        async fn delete_user(&mut self, user_uuid: String) {
+            // Linked identities are removed by the cascade, sqlx enables foreign keys by default.
+            let result = sqlx::query("DELETE FROM users WHERE user_uuid = $1")
+                .bind(&user_uuid)
+                .execute(&self.pool)
+                .await;
+
+            match result {
+                Ok(result) if result.rows_affected() == 0 => tracing::warn!("User uuid not found"),
+                Ok(_) => (),
+                Err(e) => {
+                    tracing::error!(error = ?e, "Failed to delete user");
+                    record_failure(FailureReason::BackendError);
+                }
+            }
+        }
+
+        #[tracing::instrument(skip_all, fields(db.system = "sqlite", otel.kind = "client"))]
+        async fn soft_delete_user(&mut self, user_uuid: String) -> Result<(), StorageError> {
+            let result = self
+                .retry
+                .run(|| {
+                    sqlx::query(
+                "UPDATE users SET deleted_at = $2 WHERE user_uuid = $1 AND deleted_at IS NULL",
+            )
+            .bind(&user_uuid)
+            .bind(unix_timestamp(self.clock.now()))
+            .execute(&self.pool)
+                })
+                .await
+                .map_err(|e| StorageError::backend(format!("Failed to delete user.\n{e:?}")))?;
+
+            match result.rows_affected() {
+                0 => Err(StorageError::UserNotFound),
+                _ => Ok(()),
+            }
+        }
+
+        #[tracing::instrument(skip_all, fields(db.system = "sqlite", otel.kind = "client"))]
+        async fn restore_user(&mut self, user_uuid: String) -> Result<(), StorageError> {
+            let result = self.retry.run(|| sqlx::query(
+                "UPDATE users SET deleted_at = NULL WHERE user_uuid = $1 AND deleted_at IS NOT NULL",
+            )
+            .bind(&user_uuid)
+            .execute(&self.pool))
+            .await
+            .map_err(|e| StorageError::backend(format!("Failed to restore user.\n{e:?}")))?;
+
+            match result.rows_affected() {
+                0 => Err(StorageError::NotDeleted),
+                _ => Ok(()),
+            }
+        }
+
+        #[tracing::instrument(skip_all, fields(db.system = "sqlite", otel.kind = "client"))]
+        async fn set_user_disabled(
+            &mut self,
+            user_uuid: String,
+            disabled: bool,
+        ) -> Result<(), StorageError> {
+            let result = self
+                .retry
+                .run(|| {
+                    sqlx::query("UPDATE users SET disabled = $2 WHERE user_uuid = $1")
+                        .bind(&user_uuid)
+                        .bind(disabled)
+                        .execute(&self.pool)
+                })
+                .await
+                .map_err(|e| StorageError::backend(format!("Failed to update user.\n{e:?}")))?;
+
+            match result.rows_affected() {
+                0 => Err(StorageError::UserNotFound),
+                _ => Ok(()),
+            }
+        }
+
+        #[tracing::instrument(skip_all, fields(db.system = "sqlite", otel.kind = "client"))]
+        async fn purge_deleted_users(&mut self, deleted_before: SystemTime) -> Vec<String> {
+            // Linked identities and attributes are removed by the cascade.
+            self.retry
+                .run(|| {
+                    sqlx::query_scalar(
+                        "DELETE FROM users WHERE deleted_at < $1 RETURNING user_uuid",
+                    )
+                    .bind(unix_timestamp(deleted_before))
+                    .fetch_all(&self.pool)
+                })
+                .await
+                .unwrap_or_else(|e| {
+                    tracing::error!(error = ?e, "Failed to purge deleted users");
+                    record_failure(FailureReason::BackendError);
+                    Vec::new()
+                })
+        }
+
+        #[tracing::instrument(skip_all, fields(db.system = "sqlite", otel.kind = "client"))]
+        async fn import_users(
+            &mut self,
+            users: Vec<ImportedUser>,
+        ) -> Vec<Result<(), StorageError>> {
+            // Entries are independent, a later duplicate fails on the unique username or email.
+            let mut results = Vec::with_capacity(users.len());
+            for user in users {
+                results.push(self.import_user(user).await);
+            }
+            results
+        }
+
+        #[tracing::instrument(skip_all, fields(db.system = "sqlite", otel.kind = "client"))]
+        async fn search_users(
+            &self,
+            filter: &UserFilter,
+            after: Option<&UserCursor>,
+            limit: usize,
+        ) -> Vec<User> {
+            let rows: Vec<UserRow> = self
+                .retry
+                .run(|| {
+                    sqlx::query_as(
+                "SELECT user_uuid, username, password, display_name, email, created_at, deleted_at,
+                                disabled
+                         FROM users
+                         WHERE substr(username, 1, length($1)) = $1
+                             AND ($2 = '' OR lower(email) LIKE '%@' || $2 ESCAPE '\\')
+                             AND ($3 IS NULL OR created_at >= $3)
+                             AND ($4 IS NULL OR created_at < $4)
+                             AND ($5 IS NULL OR (deleted_at IS NOT NULL) = $5)
+                             AND ($6 IS NULL OR created_at > $6 OR (created_at = $6 AND user_uuid > $7))
+                         ORDER BY created_at, user_uuid
+                         LIMIT $8",
+            )
+            .bind(&filter.username_prefix)
+            .bind(escape_like(&filter.email_domain.to_lowercase()))
+            .bind(filter.created_after.map(unix_timestamp))
+            .bind(filter.created_before.map(unix_timestamp))
+            .bind(filter.status.map(|status| status == UserStatus::Deleted))
+            .bind(after.map(|after| unix_timestamp(after.created_at)))
+            .bind(after.map(|after| after.user_uuid.as_str()))
+            .bind(limit as i64)
+            .fetch_all(&self.pool)
+                })
+                .await
+                .unwrap_or_else(|e| {
+                    tracing::error!(error = ?e, "Failed to search users");
+                    record_failure(FailureReason::BackendError);
+                    Vec::new()
+                });
+
+            rows.into_iter().map(user_from_row).collect()
+        }
+
+        #[tracing::instrument(skip_all, fields(db.system = "sqlite", otel.kind = "client"))]
+        async fn count_users(&self) -> Result<usize, StorageError> {
+            self.retry
+                .run(|| {
+                    sqlx::query_scalar("SELECT COUNT(*) FROM users WHERE deleted_at IS NULL")
+                        .fetch_one(&self.pool)
+                })
+                .await
+                .map(|count: i64| count as usize)
+                .map_err(|e| StorageError::backend(format!("Failed to count users.\n{e:?}")))
+        }
+
+        #[tracing::instrument(skip_all, fields(db.system = "sqlite", otel.kind = "client"))]
+        async fn get_federated_user_uuid(&self, issuer: &str, subject: &str) -> Option<String> {
+            self.retry
+                .run(|| {
+                    sqlx::query_scalar(
+                        "SELECT users.user_uuid FROM federated_identities
+                         JOIN users ON users.user_uuid = federated_identities.user_uuid
+                         WHERE issuer = $1 AND subject = $2 AND deleted_at IS NULL",
+                    )
+                    .bind(issuer)
+                    .bind(subject)
+                    .fetch_optional(&self.pool)
+                })
+                .await
+                .unwrap_or_else(|e| {
+                    tracing::error!(error = ?e, "Failed to look up federated user");
+                    record_failure(FailureReason::BackendError);
+                    None
+                })
+        }
+
+        #[tracing::instrument(skip_all, fields(db.system = "sqlite", otel.kind = "client"))]
+        async fn create_federated_user(
+            &mut self,
+            issuer: &str,
+            subject: &str,
+            username: String,
+        ) -> Result<String, StorageError> {
+            let hashed_password = self
+                .hashing
+                .hash_password(&self.random.uuid().to_string())
+                .await
+                .map_err(StorageError::Hashing)?;
+
+            // Create the user and link it together, or not at all.
+            let mut transaction = self.retry.run(|| self.pool.begin()).await.map_err(|e| {
+                StorageError::backend(format!("Failed to create federated user.\n{e:?}"))
+            })?;
+
+            available_logins(Self::login_conflicts(&mut *transaction, "", &username, "").await)?;
+            let user_uuid = self.id_generator.generate();
+            inserted(
+                Self::insert_user(
+                    &mut *transaction,
+                    &user_uuid,
+                    &username,
+                    &hashed_password,
+                    "",
+                    None,
+                    self.clock.now(),
+                )
+                .await,
+            )?;
+
+            let result = sqlx::query(
+                "INSERT INTO federated_identities (issuer, subject, user_uuid)
+                 VALUES ($1, $2, $3)
+                 ON CONFLICT (issuer, subject) DO NOTHING",
+            )
+            .bind(issuer)
+            .bind(subject)
+            .bind(&user_uuid)
+            .execute(&mut *transaction)
+            .await
+            .map_err(|e| {
+                StorageError::backend(format!("Failed to create federated user.\n{e:?}"))
+            })?;
+
+            // Dropping the transaction rolls the new user back.
+            if result.rows_affected() == 0 {
+                return Err(StorageError::IdentityLinked);
+            }
+
+            transaction.commit().await.map_err(|e| {
+                StorageError::backend(format!("Failed to create federated user.\n{e:?}"))
+            })?;
+
+            Ok(user_uuid)
+        }
+
+        async fn flush(&mut self) {
+            self.pool.close().await;
+        }
+
+        #[tracing::instrument(skip_all, fields(db.system = "sqlite", otel.kind = "client"))]
+        async fn ping(&self) -> Result<(), StorageError> {
+            self.retry
+                .run(|| sqlx::query("SELECT 1").execute(&self.pool))
+                .await
+                .map(|_| ())
+                .map_err(|e| {
+                    StorageError::backend(format!("Failed to reach the users database.\n{e:?}"))
+                })
+        }
+    }
+
+    /// Reports the outcome of `insert_user` like `UsersImpl` does.
+    fn inserted(result: Result<bool, sqlx::Error>) -> Result<(), StorageError> {
+        match result.map_err(|e| email_conflict(e, "Failed to create user."))? {
+            true => Ok(()),
+            false => Err(StorageError::UsernameTaken),
+        }
+    }
+
+    /// Fails like `UsersImpl` does when a username would be another user's email or the other way
+    /// around, since `find_password_hash` would then sign in as the wrong one.
+    fn available_logins(conflicts: Result<(bool, bool), sqlx::Error>) -> Result<(), StorageError> {
+        match conflicts {
+            Ok((true, _)) => Err(StorageError::UsernameTaken),
+            Ok((_, true)) => Err(StorageError::EmailTaken),
+            Ok(_) => Ok(()),
+            Err(e) => Err(StorageError::backend(format!(
+                "Failed to check usernames and emails.\n{e:?}"
+            ))),
+        }
+    }
+
+    /// Reports a violation of the unique email index like `UsersImpl` does.
+    fn email_conflict(e: sqlx::Error, context: &str) -> StorageError {
+        match e.as_database_error() {
+            Some(db_error) if db_error.is_unique_violation() => StorageError::EmailTaken,
+            _ => StorageError::backend(format!("{context}\n{e:?}")),
+        }
+    }
+
+    /// Builds a user from a row of `users`, without attributes.
+    fn user_from_row(row: UserRow) -> User {
+        let (user_uuid, username, password, display_name, email, created_at, deleted_at, disabled) =
+            row;
+
+        User {
+            user_uuid,
+            username,
+            password: password.into(),
+            display_name,
+            email,
+            created_at: UNIX_EPOCH + Duration::from_secs(created_at as u64),
+            attributes: Default::default(),
+            deleted_at: deleted_at
+                .map(|deleted_at| UNIX_EPOCH + Duration::from_secs(deleted_at as u64)),
+            disabled,
+        }
+    }
+
+    /// Escapes `value` to be matched literally by `LIKE ... ESCAPE '\'`.
+    fn escape_like(value: &str) -> String {
+        value
+            .replace('\\', "\\\\")
+            .replace('%', "\\%")
+            .replace('_', "\\_")
+    }
+
+    fn unix_timestamp(time: SystemTime) -> i64 {
+        time.duration_since(UNIX_EPOCH)
+            .map(|duration| duration.as_secs() as i64)
+            .unwrap_or_default()
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use std::env;
+
+        use uuid::Uuid;
+
+        use super::*;
+        use crate::clock::ManualClock;
+        use crate::users::ImportedPassword;
+
+        /// Each pooled connection to `:memory:` would get its own database, so use a file.
+        async fn sqlite_users() -> SqliteUsers {
+            let path = env::temp_dir().join(format!("users-{}.db", Uuid::new_v4()));
+
+            SqliteUsers::open(path.to_str().unwrap(), Migrations::Startup)
+                .await
+                .unwrap()
+        }
+
+        #[tokio::test]
+        async fn should_create_and_sign_in_user() {
+            let mut users_service = sqlite_users().await;
+            users_service
+                .create_user("username".to_owned(), "password".to_owned(), String::new())
+                .await
+                .unwrap();
+
+            assert!(users_service
+                .create_user("username".to_owned(), "password".to_owned(), String::new())
+                .await
+                .is_err());
+
+            let user_uuid = users_service
+                .get_user_uuid("username".to_owned(), "password".to_owned())
+                .await
+                .unwrap();
+            assert!(users_service
+                .get_user_uuid("username".to_owned(), "wrong".to_owned())
+                .await
+                .is_none());
+            assert_eq!(
+                users_service
+                    .get_user(user_uuid)
+                    .await
+                    .unwrap()
+                    .display_name,
+                "username"
+            );
+        }
+
+        #[tokio::test]
+        async fn should_delete_federated_user() {
+            let mut users_service = sqlite_users().await;
+            let user_uuid = users_service
+                .create_federated_user("issuer", "subject", "username".to_owned())
+                .await
+                .unwrap();
+
+            assert!(users_service
+                .create_federated_user("issuer", "subject", "other".to_owned())
+                .await
+                .is_err());
+
+            users_service.delete_user(user_uuid).await;
+
+            assert!(users_service
+                .get_federated_user_uuid("issuer", "subject")
+                .await
+                .is_none());
+        }
+
+        #[tokio::test]
+        async fn should_soft_delete_and_purge_user() {
+            let clock = ManualClock::new(UNIX_EPOCH + Duration::from_secs(1_700_000_000));
+            let mut users_service = sqlite_users().await.with_clock(Box::new(clock.clone()));
+            users_service
+                .create_user("username".to_owned(), "password".to_owned(), String::new())
+                .await
+                .unwrap();
+            let user_uuid = users_service
+                .lookup_user_uuid("username".to_owned())
+                .await
+                .unwrap();
+
+            clock.advance(Duration::from_secs(60));
+            users_service
+                .soft_delete_user(user_uuid.clone())
+                .await
+                .unwrap();
+
+            assert!(users_service
+                .get_user_uuid("username".to_owned(), "password".to_owned())
+                .await
+                .is_none());
+            let user = users_service.get_user(user_uuid.clone()).await.unwrap();
+            assert_eq!(
+                user.created_at,
+                UNIX_EPOCH + Duration::from_secs(1_700_000_000)
+            );
+            assert_eq!(user.deleted_at, Some(clock.now()));
+
+            let purged = users_service
+                .purge_deleted_users(clock.now() + Duration::from_secs(1))
+                .await;
+
+            assert_eq!(purged, vec![user_uuid.clone()]);
+            assert!(users_service.get_user(user_uuid).await.is_none());
+        }
+
+        #[tokio::test]
+        async fn should_search_users() {
+            let mut users_service = sqlite_users().await;
+            for (username, email) in [
+                ("alice", "alice@example.com"),
+                ("albert", "albert@Example.com"),
+                ("bob", "bob@other.org"),
+            ] {
+                users_service
+                    .create_user(username.to_owned(), "password".to_owned(), email.to_owned())
+                    .await
+                    .unwrap();
+            }
+
+            let filter = UserFilter {
+                username_prefix: "al".to_owned(),
+                email_domain: "example.com".to_owned(),
+                ..Default::default()
+            };
+            let first = users_service.search_users(&filter, None, 1).await;
+            let second = users_service
+                .search_users(&filter, Some(&UserCursor::from(&first[0])), 10)
+                .await;
+
+            assert_eq!(first.len(), 1);
+            assert_eq!(second.len(), 1);
+            assert_ne!(first[0].username, second[0].username);
+
+            // `_` in the filter is matched literally rather than as a wildcard.
+            let filter = UserFilter {
+                email_domain: "example_com".to_owned(),
+                ..Default::default()
+            };
+            assert!(users_service
+                .search_users(&filter, None, 10)
+                .await
+                .is_empty());
+        }
+
+        #[tokio::test]
+        async fn should_disable_user() {
+            let mut users_service = sqlite_users().await;
+            users_service
+                .create_user("username".to_owned(), "password".to_owned(), String::new())
+                .await
+                .unwrap();
+            let user_uuid = users_service
+                .lookup_user_uuid("username".to_owned())
+                .await
+                .unwrap();
+
+            users_service
+                .set_user_disabled(user_uuid.clone(), true)
+                .await
+                .unwrap();
+
+            assert!(users_service.get_user(user_uuid).await.unwrap().disabled);
+            assert!(users_service
+                .set_user_disabled("unknown".to_owned(), true)
+                .await
+                .is_err());
+        }
+
+        #[tokio::test]
+        async fn should_not_let_usernames_and_emails_collide() {
+            let mut users_service = sqlite_users().await;
+            users_service
+                .create_user(
+                    "victim".to_owned(),
+                    "password".to_owned(),
+                    "victim@example.com".to_owned(),
+                )
+                .await
+                .unwrap();
+            users_service
+                .create_user(
+                    "other@example.com".to_owned(),
+                    "password".to_owned(),
+                    String::new(),
+                )
+                .await
+                .unwrap();
+
+            let result = users_service
+                .create_user(
+                    "Victim@example.com".to_owned(),
+                    "password".to_owned(),
+                    String::new(),
+                )
+                .await;
+            assert_eq!(result.unwrap_err(), StorageError::UsernameTaken);
+
+            let victim_uuid = users_service
+                .lookup_user_uuid("victim".to_owned())
+                .await
+                .unwrap();
+            let result = users_service
+                .update_user(victim_uuid, None, Some("other@example.com".to_owned()))
+                .await;
+            assert_eq!(result.unwrap_err(), StorageError::EmailTaken);
+        }
+
+        #[tokio::test]
+        async fn should_import_users() {
+            let mut users_service = sqlite_users().await;
+            let created_at = UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+
+            let results = users_service
+                .import_users(vec![
+                    ImportedUser {
+                        user_uuid: Some("imported-uuid".to_owned()),
+                        username: "imported".to_owned(),
+                        email: "imported@example.com".to_owned(),
+                        display_name: None,
+                        created_at: Some(created_at),
+                        password: ImportedPassword::Plaintext("password".into()),
+                    },
+                    ImportedUser {
+                        user_uuid: Some("imported-uuid".to_owned()),
+                        username: "other".to_owned(),
+                        email: String::new(),
+                        display_name: None,
+                        created_at: None,
+                        password: ImportedPassword::Plaintext("password".into()),
+                    },
+                ])
+                .await;
+
+            assert!(results[0].is_ok());
+            assert!(results[1].is_err());
+
+            let user = users_service
+                .get_user("imported-uuid".to_owned())
+                .await
+                .unwrap();
+            assert_eq!(user.display_name, "imported");
+            assert_eq!(user.created_at, created_at);
+            assert_eq!(
+                users_service
+                    .get_user_uuid("imported".to_owned(), "password".to_owned())
+                    .await,
+                Some("imported-uuid".to_owned())
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::ManualClock;
+    use crate::password_hashing::Pbkdf2Hasher;
+
+    #[tokio::test]
+    async fn should_create_user() {
+        let mut user_service = UsersImpl::default();
+        user_service
+            .create_user("username".to_owned(), "password".to_owned(), String::new())
+            .await
+            .expect("should create user");
+
+        assert_eq!(user_service.uuid_to_user.len(), 1);
+        assert_eq!(user_service.username_to_user.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn should_fail_creating_user_with_existing_username() {
+        let mut user_service = UsersImpl::default();
+        user_service
+            .create_user("username".to_owned(), "password".to_owned(), String::new())
+            .await
+            .expect("should create user");
+
+        let result = user_service
+            .create_user("username".to_owned(), "password".to_owned(), String::new())
+            .await;
+
+        assert!(result.is_err());
+    }
+
+    #[tokio::test]
+    async fn should_create_users() {
+        let mut user_service = UsersImpl::default();
+        user_service
+            .create_user("existing".to_owned(), "password".to_owned(), String::new())
+            .await
+            .expect("should create user");
+
+        let results = user_service
+            .create_users(vec![
+                ("first".to_owned(), "password".to_owned()),
+                ("existing".to_owned(), "password".to_owned()),
+                ("second".to_owned(), "password".to_owned()),
+                ("first".to_owned(), "password".to_owned()),
+            ])
+            .await;
+
+        assert!(results[0].is_ok());
+        assert!(results[1].is_err());
+        assert!(results[2].is_ok());
+        assert!(results[3].is_err());
+        assert_eq!(user_service.username_to_user.len(), 3);
+    }
+
+    #[tokio::test]
+    async fn should_give_each_user_its_own_id() {
+        let mut user_service = UsersImpl::default();
+        user_service
+            .create_user("first".to_owned(), "password".to_owned(), String::new())
+            .await
+            .expect("should create user");
+        user_service
+            .create_user("second".to_owned(), "password".to_owned(), String::new())
+            .await
+            .expect("should create user");
+
+        let first = user_service.lookup_user_uuid("first".to_owned()).await;
+        let second = user_service.lookup_user_uuid("second".to_owned()).await;
+
+        assert!(first.is_some());
+        assert_ne!(first, second);
+    }
+
+    #[tokio::test]
+    async fn should_generate_ids_with_configured_generator() {
+        let mut user_service =
+            UsersImpl::default().with_id_generator(Box::new(crate::user_ids::NanoId::new(8)));
+        user_service
+            .create_user("username".to_owned(), "password".to_owned(), String::new())
+            .await
+            .expect("should create user");
+
+        let user_uuid = user_service
+            .lookup_user_uuid("username".to_owned())
+            .await
+            .unwrap();
 
-#[derive(Default, Debug)]
-pub struct UsersImpl {
-    uuid_to_user: HashMap<String, User>,
-    username_to_user: HashMap<String, User>,
-}
+        assert_eq!(user_uuid.len(), 8);
+        assert!(user_service.get_user(user_uuid).await.is_some());
+    }
 
-impl Users for UsersImpl {
-    fn create_user(&mut self, new_username: String, password: String) -> Result<(), String> {
-        // TODO: Check if username already exist. If so return an error.
-        if self
-            .username_to_user
-            .values()
-            .map(|user| &user.username)
-            .any(|username| username == &new_username)
-        {
-            return Err("Error, username not unique".to_string());
-        }
+    #[tokio::test]
+    async fn should_retrieve_user_uuid() {
+        let mut user_service = UsersImpl::default();
+        user_service
+            .create_user("username".to_owned(), "password".to_owned(), String::new())
+            .await
+            .expect("should create user");
+
+        assert!(user_service
+            .get_user_uuid("username".to_owned(), "password".to_owned())
+            .await
+            .is_some());
+    }
 
-        let salt = SaltString::generate(&mut OsRng);
+    #[tokio::test]
+    async fn should_fail_to_retrieve_user_uuid_with_incorrect_password() {
+        let mut user_service = UsersImpl::default();
+        user_service
+            .create_user("username".to_owned(), "password".to_owned(), String::new())
+            .await
+            .expect("should create user");
 
-        let hashed_password = Pbkdf2
-            .hash_password(password.as_bytes(), &salt)
-            .map_err(|e| format!("Failed to hash password.\n{e:?}"))?
-            .to_string();
+        assert!(user_service
+            .get_user_uuid("username".to_owned(), "incorrect password".to_owned())
+            .await
+            .is_none());
+    }
 
-        let user: User = User {
-            user_uuid: Uuid::NAMESPACE_X500.to_string(),
-            username: new_username.clone(),
-            password: hashed_password,
-        }; // Create new user with unique uuid and hashed password.
+    #[tokio::test]
+    async fn should_lookup_user_uuid() {
+        let mut user_service = UsersImpl::default();
+        user_service
+            .create_user("username".to_owned(), "password".to_owned(), String::new())
+            .await
+            .expect("should create user");
 
-        // TODO: Add user to `username_to_user` and `uuid_to_user`.
+        assert!(user_service
+            .lookup_user_uuid("username".to_owned())
+            .await
+            .is_some());
+        assert!(user_service
+            .lookup_user_uuid("unknown".to_owned())
+            .await
+            .is_none());
+    }
 
-        let user_stuff = HashMap::from([(new_username, user.clone())]);
-        let uuid_stuff = HashMap::from([(user.clone().user_uuid, user)]);
+    #[tokio::test]
+    async fn should_update_password() {
+        let mut user_service = UsersImpl::default();
+        user_service
+            .create_user("username".to_owned(), "password".to_owned(), String::new())
+            .await
+            .expect("should create user");
 
-        let saving = UsersImpl {
-            username_to_user: user_stuff,
-            uuid_to_user: uuid_stuff,
-        };
+        let user_uuid = user_service
+            .lookup_user_uuid("username".to_owned())
+            .await
+            .unwrap();
 
-        self.username_to_user = saving.username_to_user;
-        self.uuid_to_user = saving.uuid_to_user;
+        user_service
+            .update_password(user_uuid, "new password".to_owned())
+            .await
+            .expect("should update password");
 
-        Ok(())
+        assert!(user_service
+            .get_user_uuid("username".to_owned(), "password".to_owned())
+            .await
+            .is_none());
+        assert!(user_service
+            .get_user_uuid("username".to_owned(), "new password".to_owned())
+            .await
+            .is_some());
     }
 
-    fn get_user_uuid(&self, username: String, password: String) -> Option<String> {
-        let user: &User = match self.username_to_user.get(&username) {
-            Some(user) => user,
-            None => return None,
-        }; // Retrieve `User` or return `None` is user can't be found.
+    #[tokio::test]
+    async fn should_verify_passwords_hashed_with_other_costs() {
+        let mut user_service = UsersImpl::default();
+        user_service
+            .create_user("username".to_owned(), "password".to_owned(), String::new())
+            .await
+            .expect("should create user");
 
-        // Get user's password as `PasswordHash` instance.
-        let hashed_password = user.password.clone();
-        let parsed_hash = PasswordHash::new(&hashed_password).ok()?;
+        // Hashes record their cost, so changing the hasher keeps existing passwords valid.
+        let mut user_service = user_service.with_password_hasher(Box::new(Pbkdf2Hasher::new(1000)));
+        user_service
+            .create_user("other".to_owned(), "password".to_owned(), String::new())
+            .await
+            .expect("should create user");
 
-        // Verify passed in password matches user's password.
-        let result = Pbkdf2.verify_password(password.as_bytes(), &parsed_hash);
+        assert!(user_service
+            .get_user_uuid("username".to_owned(), "password".to_owned())
+            .await
+            .is_some());
+        assert!(user_service
+            .get_user_uuid("other".to_owned(), "password".to_owned())
+            .await
+            .is_some());
+    }
 
-        // TODO: If the username and password passed in matches the user's username and password return the user's uuid.
+    #[tokio::test]
+    async fn should_report_outdated_password_hash() {
+        let mut user_service =
+            UsersImpl::default().with_password_hasher(Box::new(Pbkdf2Hasher::new(1000)));
+        user_service
+            .create_user("username".to_owned(), "password".to_owned(), String::new())
+            .await
+            .expect("should create user");
 
-        if result.is_ok() {
-            Some(user.user_uuid.clone())
-        } else {
-            None
-        }
+        let mut user_service = user_service.with_password_hasher(Box::new(Pbkdf2Hasher::new(2000)));
+
+        assert!(user_service
+            .verify_password("username".to_owned(), "wrong".to_owned())
+            .await
+            .is_none());
+
+        let verified = user_service
+            .verify_password("username".to_owned(), "password".to_owned())
+            .await
+            .unwrap();
+        assert!(verified.needs_rehash);
+
+        user_service
+            .update_password(verified.user_uuid.clone(), "password".to_owned())
+            .await
+            .unwrap();
+
+        let user = user_service.get_user(verified.user_uuid).await.unwrap();
+        assert!(user.password.expose().starts_with("$pbkdf2-sha256$i=2000,"));
+        assert!(
+            !user_service
+                .verify_password("username".to_owned(), "password".to_owned())
+                .await
+                .unwrap()
+                .needs_rehash
+        );
     }
 
-    fn delete_user(&mut self, user_uuid: String) {
-        // TODO: Remove user from `username_to_user` and `uuid_to_user`.
-        let mut user_name: String = String::new();
-        match self.uuid_to_user.get(&user_uuid) {
-            Some(_) => {
-                user_name = self.uuid_to_user.get(&user_uuid).unwrap().username.clone();
-                self.uuid_to_user.remove(&user_uuid);
-            }
-            None => println!("Error, user uuid not found"),
-        };
+    #[tokio::test]
+    async fn should_get_user() {
+        let mut user_service = UsersImpl::default();
+        user_service
+            .create_user("username".to_owned(), "password".to_owned(), String::new())
+            .await
+            .expect("should create user");
 
-        match self.username_to_user.remove(&user_name) {
-            Some(_) => (),
-            None => println!("Error, username not found"),
-        };
+        let user_uuid = user_service
+            .lookup_user_uuid("username".to_owned())
+            .await
+            .unwrap();
+
+        let user = user_service.get_user(user_uuid.clone()).await.unwrap();
+
+        assert_eq!(user.user_uuid, user_uuid);
+        assert_eq!(user.username, "username");
+        assert_eq!(user.display_name, "username");
+        assert!(user.email.is_empty());
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+    #[tokio::test]
+    async fn should_update_user() {
+        let mut user_service = UsersImpl::default();
+        user_service
+            .create_user("username".to_owned(), "password".to_owned(), String::new())
+            .await
+            .expect("should create user");
+
+        let user_uuid = user_service
+            .lookup_user_uuid("username".to_owned())
+            .await
+            .unwrap();
+
+        user_service
+            .update_user(user_uuid.clone(), None, Some("user@example.com".to_owned()))
+            .await
+            .expect("should update user");
 
-    #[test]
-    fn should_create_user() {
+        let user = user_service.get_user(user_uuid).await.unwrap();
+
+        assert_eq!(user.display_name, "username");
+        assert_eq!(user.email, "user@example.com");
+        assert_eq!(
+            user_service.username_to_user.get("username").unwrap().email,
+            "user@example.com"
+        );
+    }
+
+    #[tokio::test]
+    async fn should_share_users_between_indexes() {
         let mut user_service = UsersImpl::default();
         user_service
-            .create_user("username".to_owned(), "password".to_owned())
+            .create_user("username".to_owned(), "password".to_owned(), String::new())
+            .await
             .expect("should create user");
 
-        assert_eq!(user_service.uuid_to_user.len(), 1);
-        assert_eq!(user_service.username_to_user.len(), 1);
+        let user_uuid = user_service
+            .lookup_user_uuid("username".to_owned())
+            .await
+            .unwrap();
+        let user = user_service.get_user(user_uuid.clone()).await.unwrap();
+
+        assert!(Arc::ptr_eq(
+            &user,
+            user_service.username_to_user.get("username").unwrap()
+        ));
+
+        user_service
+            .update_user(user_uuid.clone(), Some("Name".to_owned()), None)
+            .await
+            .expect("should update user");
+
+        // Users handed out before a change keep their old fields.
+        let updated = user_service.get_user(user_uuid).await.unwrap();
+        assert_eq!(user.display_name, "username");
+        assert_eq!(updated.display_name, "Name");
+        assert!(Arc::ptr_eq(
+            &updated,
+            user_service.username_to_user.get("username").unwrap()
+        ));
     }
 
-    #[test]
-    fn should_fail_creating_user_with_existing_username() {
+    #[tokio::test]
+    async fn should_sign_in_with_email() {
         let mut user_service = UsersImpl::default();
         user_service
-            .create_user("username".to_owned(), "password".to_owned())
+            .create_user(
+                "username".to_owned(),
+                "password".to_owned(),
+                "User@Example.com".to_owned(),
+            )
+            .await
             .expect("should create user");
 
-        let result = user_service.create_user("username".to_owned(), "password".to_owned());
+        let user_uuid = user_service.lookup_user_uuid("username".to_owned()).await;
 
-        assert!(result.is_err());
+        assert!(user_uuid.is_some());
+        assert_eq!(
+            user_service
+                .get_user_uuid("user@example.com".to_owned(), "password".to_owned())
+                .await,
+            user_uuid
+        );
+        assert!(user_service
+            .verify_password("user@example.com".to_owned(), "wrong".to_owned())
+            .await
+            .is_none());
+    }
+
+    #[tokio::test]
+    async fn should_fail_creating_user_with_existing_email() {
+        let mut user_service = UsersImpl::default();
+        user_service
+            .create_user(
+                "first".to_owned(),
+                "password".to_owned(),
+                "user@example.com".to_owned(),
+            )
+            .await
+            .expect("should create user");
+
+        let result = user_service
+            .create_user(
+                "second".to_owned(),
+                "password".to_owned(),
+                "USER@example.com".to_owned(),
+            )
+            .await;
+
+        assert_eq!(result.unwrap_err(), StorageError::EmailTaken);
+
+        // Users without an email don't conflict.
+        user_service
+            .create_user("third".to_owned(), "password".to_owned(), String::new())
+            .await
+            .expect("should create user");
+        user_service
+            .create_user("fourth".to_owned(), "password".to_owned(), String::new())
+            .await
+            .expect("should create user");
+    }
+
+    #[tokio::test]
+    async fn should_not_let_usernames_and_emails_collide() {
+        let mut user_service = UsersImpl::default();
+        user_service
+            .create_user(
+                "victim".to_owned(),
+                "password".to_owned(),
+                "victim@example.com".to_owned(),
+            )
+            .await
+            .expect("should create user");
+
+        let result = user_service
+            .create_user(
+                "Victim@example.com".to_owned(),
+                "password".to_owned(),
+                String::new(),
+            )
+            .await;
+        assert_eq!(result.unwrap_err(), StorageError::UsernameTaken);
+
+        let results = user_service
+            .import_users(vec![ImportedUser {
+                user_uuid: None,
+                username: "victim@example.com".to_owned(),
+                email: String::new(),
+                display_name: None,
+                created_at: None,
+                password: ImportedPassword::Plaintext("password".into()),
+            }])
+            .await;
+        assert_eq!(results, vec![Err(StorageError::UsernameTaken)]);
+
+        user_service
+            .create_user(
+                "other@example.com".to_owned(),
+                "password".to_owned(),
+                String::new(),
+            )
+            .await
+            .expect("should create user");
+
+        let result = user_service
+            .create_user(
+                "attacker".to_owned(),
+                "password".to_owned(),
+                "other@example.com".to_owned(),
+            )
+            .await;
+        assert_eq!(result.unwrap_err(), StorageError::EmailTaken);
+
+        let victim_uuid = user_service
+            .lookup_user_uuid("victim".to_owned())
+            .await
+            .unwrap();
+        let result = user_service
+            .update_user(victim_uuid, None, Some("other@example.com".to_owned()))
+            .await;
+        assert_eq!(result.unwrap_err(), StorageError::EmailTaken);
+
+        // A user's own username is still a valid email for them.
+        user_service
+            .create_user(
+                "self@example.com".to_owned(),
+                "password".to_owned(),
+                "self@example.com".to_owned(),
+            )
+            .await
+            .expect("should create user");
     }
 
-    #[test]
-    fn should_retrieve_user_uuid() {
+    #[tokio::test]
+    async fn should_keep_emails_unique_on_update() {
         let mut user_service = UsersImpl::default();
         user_service
-            .create_user("username".to_owned(), "password".to_owned())
+            .create_user(
+                "first".to_owned(),
+                "password".to_owned(),
+                "first@example.com".to_owned(),
+            )
+            .await
+            .expect("should create user");
+        user_service
+            .create_user("second".to_owned(), "password".to_owned(), String::new())
+            .await
             .expect("should create user");
 
+        let first_uuid = user_service
+            .lookup_user_uuid("first".to_owned())
+            .await
+            .unwrap();
+        let second_uuid = user_service
+            .lookup_user_uuid("second".to_owned())
+            .await
+            .unwrap();
+
+        let result = user_service
+            .update_user(
+                second_uuid.clone(),
+                None,
+                Some("first@example.com".to_owned()),
+            )
+            .await;
+        assert_eq!(result.unwrap_err(), StorageError::EmailTaken);
+
+        // Changing an email frees the old one.
+        user_service
+            .update_user(first_uuid, None, Some("new@example.com".to_owned()))
+            .await
+            .expect("should update user");
+        user_service
+            .update_user(second_uuid, None, Some("first@example.com".to_owned()))
+            .await
+            .expect("should update user");
+
         assert!(user_service
-            .get_user_uuid("username".to_owned(), "password".to_owned())
+            .get_user_uuid("new@example.com".to_owned(), "password".to_owned())
+            .await
             .is_some());
+        assert_eq!(
+            user_service
+                .get_user_uuid("first@example.com".to_owned(), "password".to_owned())
+                .await,
+            user_service.lookup_user_uuid("second".to_owned()).await
+        );
     }
 
-    #[test]
-    fn should_fail_to_retrieve_user_uuid_with_incorrect_password() {
+    #[tokio::test]
+    async fn should_limit_user_attributes() {
         let mut user_service = UsersImpl::default();
         user_service
-            .create_user("username".to_owned(), "password".to_owned())
+            .create_user("username".to_owned(), "password".to_owned(), String::new())
+            .await
             .expect("should create user");
 
+        let user_uuid = user_service
+            .lookup_user_uuid("username".to_owned())
+            .await
+            .unwrap();
+
+        for i in 0..MAX_USER_ATTRIBUTES {
+            user_service
+                .set_user_attribute(user_uuid.clone(), format!("key{i}"), "value".to_owned())
+                .await
+                .expect("should set attribute");
+        }
+
+        let result = user_service
+            .set_user_attribute(user_uuid.clone(), "extra".to_owned(), "value".to_owned())
+            .await;
+        assert_eq!(
+            result.unwrap_err(),
+            StorageError::InvalidAttribute("too many attributes")
+        );
+
+        // Existing keys can still be replaced or removed.
+        user_service
+            .set_user_attribute(user_uuid.clone(), "key0".to_owned(), "new".to_owned())
+            .await
+            .expect("should replace attribute");
+        user_service
+            .set_user_attribute(user_uuid.clone(), "key1".to_owned(), String::new())
+            .await
+            .expect("should remove attribute");
+
+        let result = user_service
+            .set_user_attribute(
+                user_uuid.clone(),
+                "key1".to_owned(),
+                "v".repeat(MAX_ATTRIBUTE_VALUE_LENGTH + 1),
+            )
+            .await;
+        assert_eq!(
+            result.unwrap_err(),
+            StorageError::InvalidAttribute("attribute value too long")
+        );
+
+        let user = user_service.get_user(user_uuid).await.unwrap();
+        assert_eq!(user.attributes.len(), MAX_USER_ATTRIBUTES - 1);
+        assert_eq!(user.attributes["key0"], "new");
+    }
+
+    #[tokio::test]
+    async fn should_fail_updating_unknown_user() {
+        let mut user_service = UsersImpl::default();
+
+        let result = user_service
+            .update_user("unknown".to_owned(), None, None)
+            .await;
+
+        assert!(result.is_err());
+    }
+
+    #[tokio::test]
+    async fn should_create_federated_user() {
+        let mut user_service = UsersImpl::default();
+
+        let user_uuid = user_service
+            .create_federated_user("issuer", "subject", "username".to_owned())
+            .await
+            .expect("should create federated user");
+
+        assert_eq!(
+            user_service
+                .get_federated_user_uuid("issuer", "subject")
+                .await,
+            Some(user_uuid)
+        );
         assert!(user_service
-            .get_user_uuid("username".to_owned(), "incorrect password".to_owned())
+            .get_federated_user_uuid("other issuer", "subject")
+            .await
             .is_none());
+        assert!(user_service
+            .create_federated_user("issuer", "subject", "other".to_owned())
+            .await
+            .is_err());
     }
 
-    #[test]
-    fn should_delete_user() {
+    #[tokio::test]
+    async fn should_delete_user() {
         let mut user_service = UsersImpl::default();
         user_service
-            .create_user("username".to_owned(), "password".to_owned())
+            .create_user("username".to_owned(), "password".to_owned(), String::new())
+            .await
             .expect("should create user");
 
         let user_uuid = user_service
             .get_user_uuid("username".to_owned(), "password".to_owned())
+            .await
             .unwrap();
 
-        user_service.delete_user(user_uuid);
+        user_service.delete_user(user_uuid).await;
 
         assert_eq!(user_service.uuid_to_user.len(), 0);
         assert_eq!(user_service.username_to_user.len(), 0);
     }
+    #[tokio::test]
+    async fn should_soft_delete_and_restore_user() {
+        let mut user_service = UsersImpl::default();
+        user_service
+            .create_user(
+                "username".to_owned(),
+                "password".to_owned(),
+                "user@example.com".to_owned(),
+            )
+            .await
+            .expect("should create user");
+        let user_uuid = user_service
+            .lookup_user_uuid("username".to_owned())
+            .await
+            .unwrap();
+
+        user_service
+            .soft_delete_user(user_uuid.clone())
+            .await
+            .expect("should soft delete user");
+
+        assert!(user_service
+            .get_user_uuid("username".to_owned(), "password".to_owned())
+            .await
+            .is_none());
+        assert!(user_service
+            .get_user_uuid("user@example.com".to_owned(), "password".to_owned())
+            .await
+            .is_none());
+        assert!(user_service
+            .lookup_user_uuid("username".to_owned())
+            .await
+            .is_none());
+        assert!(user_service
+            .soft_delete_user(user_uuid.clone())
+            .await
+            .is_err());
+
+        user_service
+            .restore_user(user_uuid.clone())
+            .await
+            .expect("should restore user");
+
+        assert_eq!(
+            user_service
+                .get_user_uuid("username".to_owned(), "password".to_owned())
+                .await,
+            Some(user_uuid.clone())
+        );
+        assert!(user_service.restore_user(user_uuid).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn should_purge_users_deleted_before_cutoff() {
+        let clock = ManualClock::new(SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000));
+        let mut user_service = UsersImpl::default().with_clock(Box::new(clock.clone()));
+        for username in ["deleted", "kept"] {
+            user_service
+                .create_user(username.to_owned(), "password".to_owned(), String::new())
+                .await
+                .expect("should create user");
+        }
+        let deleted_uuid = user_service
+            .lookup_user_uuid("deleted".to_owned())
+            .await
+            .unwrap();
+        clock.advance(Duration::from_secs(60));
+        user_service
+            .soft_delete_user(deleted_uuid.clone())
+            .await
+            .unwrap();
+
+        assert!(user_service
+            .purge_deleted_users(clock.now() - Duration::from_secs(1))
+            .await
+            .is_empty());
+
+        let purged = user_service
+            .purge_deleted_users(clock.now() + Duration::from_secs(1))
+            .await;
+
+        assert_eq!(purged, vec![deleted_uuid.clone()]);
+        assert!(user_service.get_user(deleted_uuid).await.is_none());
+        assert!(user_service
+            .lookup_user_uuid("kept".to_owned())
+            .await
+            .is_some());
+    }
+    #[tokio::test]
+    async fn should_search_users_in_creation_order() {
+        let mut user_service = UsersImpl::default();
+        for (username, email) in [
+            ("alice", "alice@example.com"),
+            ("bob", "bob@other.org"),
+            ("albert", "albert@EXAMPLE.com"),
+            ("alfred", ""),
+        ] {
+            user_service
+                .create_user(username.to_owned(), "password".to_owned(), email.to_owned())
+                .await
+                .expect("should create user");
+        }
+        let alfred_uuid = user_service
+            .lookup_user_uuid("alfred".to_owned())
+            .await
+            .unwrap();
+        user_service.soft_delete_user(alfred_uuid).await.unwrap();
+
+        let usernames = |users: Vec<User>| -> Vec<String> {
+            users.into_iter().map(|user| user.username).collect()
+        };
+
+        let filter = UserFilter {
+            username_prefix: "al".to_owned(),
+            ..Default::default()
+        };
+        assert_eq!(
+            usernames(user_service.search_users(&filter, None, 10).await),
+            vec!["alice", "albert", "alfred"]
+        );
+
+        let filter = UserFilter {
+            email_domain: "example.com".to_owned(),
+            ..Default::default()
+        };
+        assert_eq!(
+            usernames(user_service.search_users(&filter, None, 10).await),
+            vec!["alice", "albert"]
+        );
+
+        let filter = UserFilter {
+            status: Some(UserStatus::Deleted),
+            ..Default::default()
+        };
+        assert_eq!(
+            usernames(user_service.search_users(&filter, None, 10).await),
+            vec!["alfred"]
+        );
+
+        let filter = UserFilter {
+            created_after: Some(SystemTime::now()),
+            ..Default::default()
+        };
+        assert!(user_service
+            .search_users(&filter, None, 10)
+            .await
+            .is_empty());
+    }
+
+    #[tokio::test]
+    async fn should_resume_search_after_cursor() {
+        let mut user_service = UsersImpl::default();
+        for username in ["first", "second", "third"] {
+            user_service
+                .create_user(username.to_owned(), "password".to_owned(), String::new())
+                .await
+                .expect("should create user");
+        }
+
+        let filter = UserFilter::default();
+        let first_page = user_service.search_users(&filter, None, 2).await;
+        let cursor = UserCursor::from(first_page.last().unwrap());
+
+        // Deleting a user on the first page doesn't shift the next one.
+        user_service
+            .delete_user(first_page[0].user_uuid.clone())
+            .await;
+
+        let second_page = user_service.search_users(&filter, Some(&cursor), 2).await;
+
+        assert_eq!(first_page.len(), 2);
+        assert_eq!(second_page.len(), 1);
+        assert_eq!(second_page[0].username, "third");
+    }
+    #[tokio::test]
+    async fn should_import_users_with_hashed_and_plaintext_passwords() {
+        let mut user_service = UsersImpl::default();
+        let hashed_password = Pbkdf2Hasher::new(1000).hash_password("hashed").unwrap();
+        let imported = |username: &str, user_uuid: Option<&str>, password| ImportedUser {
+            user_uuid: user_uuid.map(str::to_owned),
+            username: username.to_owned(),
+            email: String::new(),
+            display_name: None,
+            created_at: None,
+            password,
+        };
+
+        let results = user_service
+            .import_users(vec![
+                imported(
+                    "hashed",
+                    Some("hashed-uuid"),
+                    ImportedPassword::Hashed(hashed_password.into()),
+                ),
+                imported(
+                    "plaintext",
+                    None,
+                    ImportedPassword::Plaintext("plaintext".into()),
+                ),
+                imported(
+                    "duplicate",
+                    Some("hashed-uuid"),
+                    ImportedPassword::Plaintext("password".into()),
+                ),
+                imported(
+                    "unsupported",
+                    None,
+                    ImportedPassword::Hashed("$1$salt$hash".into()),
+                ),
+            ])
+            .await;
+
+        assert!(results[0].is_ok());
+        assert!(results[1].is_ok());
+        assert_eq!(results[2], Err(StorageError::DuplicateImport));
+        assert_eq!(results[3], Err(StorageError::UnsupportedPasswordHash));
+
+        assert_eq!(
+            user_service
+                .get_user_uuid("hashed".to_owned(), "hashed".to_owned())
+                .await,
+            Some("hashed-uuid".to_owned())
+        );
+        assert!(user_service
+            .get_user_uuid("plaintext".to_owned(), "plaintext".to_owned())
+            .await
+            .is_some());
+        assert!(user_service
+            .lookup_user_uuid("unsupported".to_owned())
+            .await
+            .is_none());
+    }
+    #[tokio::test]
+    async fn should_disable_and_enable_user() {
+        let mut user_service = UsersImpl::default();
+        user_service
+            .create_user("username".to_owned(), "password".to_owned(), String::new())
+            .await
+            .expect("should create user");
+        let user_uuid = user_service
+            .lookup_user_uuid("username".to_owned())
+            .await
+            .unwrap();
+
+        user_service
+            .set_user_disabled(user_uuid.clone(), true)
+            .await
+            .unwrap();
+        assert!(
+            user_service
+                .get_user(user_uuid.clone())
+                .await
+                .unwrap()
+                .disabled
+        );
+        assert!(user_service.username_to_user["username"].disabled);
+
+        user_service
+            .set_user_disabled(user_uuid.clone(), false)
+            .await
+            .unwrap();
+        assert!(!user_service.get_user(user_uuid).await.unwrap().disabled);
+
+        assert!(user_service
+            .set_user_disabled("unknown".to_owned(), true)
+            .await
+            .is_err());
+    }
 }
@@ -0,0 +1,442 @@
+use prost::Message;
+use tonic::{Code, Request, Status};
+
+use crate::api_versions::{any, rpc};
+use crate::auth::authentication::admin::v1 as admin;
+use crate::auth::authentication::v1::*;
+use crate::auth::authentication::v2::WatchSessionRequest;
+use crate::groups::MAX_GROUP_NAME_LENGTH;
+use crate::metrics::{record_failure, FailureReason};
+
+/// Longest username new users can pick, in characters.
+pub const MAX_USERNAME_LENGTH: usize = 64;
+/// Longest email, in characters. Also bounds logins, which are usernames or emails.
+pub const MAX_EMAIL_LENGTH: usize = 254;
+/// Longest display name, in characters.
+pub const MAX_DISPLAY_NAME_LENGTH: usize = 128;
+/// Longest password, in bytes, so overlong ones are rejected before they're hashed. The password
+/// policy's maximum is usually lower.
+pub const MAX_PASSWORD_BYTES: usize = 1024;
+/// Longest token, uuid, code or other opaque value, in bytes. ID tokens are the longest.
+pub const MAX_OPAQUE_BYTES: usize = 16 * 1024;
+
+/// Characters new usernames can have besides ASCII letters and digits.
+const USERNAME_PUNCTUATION: &[char] = &['.', '_', '-', '@', '+'];
+
+/// Checks the fields of a request before it's handled, so malformed input never reaches the
+/// backends.
+pub trait Validate {
+    /// Adds a violation to `violations` for each field breaking a rule.
+    fn validate(&self, violations: &mut Violations);
+}
+
+/// Fields of a request that break a rule, reported as `google.rpc.BadRequest` field violations.
+#[derive(Debug, Default)]
+pub struct Violations(Vec<rpc::bad_request::FieldViolation>);
+
+impl Violations {
+    fn add(&mut self, field: &str, description: &str) {
+        self.0.push(rpc::bad_request::FieldViolation {
+            field: field.to_owned(),
+            description: description.to_owned(),
+        });
+    }
+
+    /// Adds a violation if `value` has control characters, which no field needs.
+    fn printable(&mut self, field: &str, value: &str) -> bool {
+        if value.chars().any(char::is_control) {
+            self.add(field, "Must not contain control characters");
+            return false;
+        }
+        true
+    }
+
+    /// A username picked for a new user.
+    fn new_username(&mut self, field: &str, username: &str) {
+        if username.is_empty() {
+            self.add(field, "Must not be empty");
+        } else if username.chars().count() > MAX_USERNAME_LENGTH {
+            self.add(field, "Must be at most 64 characters long");
+        } else if !username
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || USERNAME_PUNCTUATION.contains(&c))
+        {
+            self.add(
+                field,
+                "Must only contain ASCII letters, digits and the characters . _ - @ +",
+            );
+        }
+    }
+
+    /// A username or email to sign in with. Existing usernames may predate the rules for new
+    /// ones, e.g. when imported or provisioned from a directory.
+    fn login(&mut self, field: &str, login: &str) {
+        if self.printable(field, login) && login.chars().count() > MAX_EMAIL_LENGTH {
+            self.add(field, "Must be at most 254 characters long");
+        }
+    }
+
+    fn password(&mut self, field: &str, password: &str) {
+        if password.len() > MAX_PASSWORD_BYTES {
+            self.add(field, "Must be at most 1024 bytes long");
+        } else if password.contains('\0') {
+            self.add(field, "Must not contain NUL characters");
+        }
+    }
+
+    /// An email, which may be empty where it's optional.
+    fn email(&mut self, field: &str, email: &str) {
+        if email.is_empty() || !self.printable(field, email) {
+            return;
+        }
+        let well_formed = email.split_once('@').is_some_and(|(local, domain)| {
+            !local.is_empty() && !domain.is_empty() && !domain.contains('@')
+        });
+        if email.chars().count() > MAX_EMAIL_LENGTH {
+            self.add(field, "Must be at most 254 characters long");
+        } else if !well_formed || email.contains(char::is_whitespace) {
+            self.add(field, "Must be an email address");
+        }
+    }
+
+    fn display_name(&mut self, field: &str, display_name: &str) {
+        if self.printable(field, display_name)
+            && display_name.chars().count() > MAX_DISPLAY_NAME_LENGTH
+        {
+            self.add(field, "Must be at most 128 characters long");
+        }
+    }
+
+    /// A token, uuid, code or name the service looks up as is.
+    /// A new group's name, which ends up in tokens and logs.
+    fn group_name(&mut self, field: &str, name: &str) {
+        if name.is_empty() {
+            self.add(field, "Must not be empty");
+        } else if name.len() > MAX_GROUP_NAME_LENGTH {
+            self.add(field, "Must be at most 64 bytes long");
+        } else if name.chars().any(|c| c.is_whitespace() || c.is_control()) {
+            self.add(field, "Must not contain whitespace");
+        }
+    }
+
+    fn opaque(&mut self, field: &str, value: &str) {
+        if self.printable(field, value) && value.len() > MAX_OPAQUE_BYTES {
+            self.add(field, "Must be at most 16384 bytes long");
+        }
+    }
+}
+
+/// Fails with `INVALID_ARGUMENT` and a `google.rpc.BadRequest` listing every invalid field of
+/// the request, if it has any.
+// Handlers have to return `Result<_, Status>`, however large `Status` is.
+#[allow(clippy::result_large_err)]
+pub fn validate<T: Validate>(request: &Request<T>) -> Result<(), Status> {
+    let mut violations = Violations::default();
+    request.get_ref().validate(&mut violations);
+    if violations.0.is_empty() {
+        return Ok(());
+    }
+
+    record_failure(FailureReason::InvalidRequest);
+    let message = "The request has invalid fields";
+    let status = rpc::Status {
+        code: Code::InvalidArgument as i32,
+        message: message.to_owned(),
+        details: vec![any(
+            "google.rpc.BadRequest",
+            &rpc::BadRequest {
+                field_violations: violations.0,
+            },
+        )],
+    };
+    Err(Status::with_details(
+        Code::InvalidArgument,
+        message,
+        status.encode_to_vec().into(),
+    ))
+}
+
+/// Implements `Validate` for requests whose fields are all looked up as is, like tokens.
+macro_rules! opaque_fields {
+    ($($request:path { $($field:ident: $name:literal),* },)*) => {
+        $(
+            impl Validate for $request {
+                #[allow(unused_variables)]
+                fn validate(&self, violations: &mut Violations) {
+                    $(violations.opaque($name, &self.$field);)*
+                }
+            }
+        )*
+    };
+}
+
+opaque_fields! {
+    SignOutRequest { session_token: "sessionToken" },
+    ValidateSessionRequest { session_token: "sessionToken" },
+    RefreshSessionRequest { refresh_token: "refreshToken" },
+    RenewSessionRequest { session_token: "sessionToken" },
+    DeleteAccountRequest { session_token: "sessionToken" },
+    GetProfileRequest { session_token: "sessionToken" },
+    ListActiveSessionsRequest { session_token: "sessionToken" },
+    EnrollTotpRequest { session_token: "sessionToken" },
+    ConfirmTotpRequest { session_token: "sessionToken", code: "code" },
+    SignInWithIdTokenRequest { provider: "provider", id_token: "idToken" },
+    GetSignUpChallengeRequest {},
+    GetLoginHistoryRequest { session_token: "sessionToken", page_token: "pageToken" },
+    RedeemMagicLinkRequest { token: "token", totp_code: "totpCode" },
+    IntrospectTokenRequest { token: "token" },
+    RevokeTokenRequest { token: "token" },
+    // The attribute limits are enforced by the users backend
+    SetUserAttributeRequest { session_token: "sessionToken" },
+    GetUserAttributesRequest { session_token: "sessionToken" },
+    WatchSessionRequest { session_token: "sessionToken" },
+    admin::GetUserRequest { user_uuid: "userUuid" },
+    admin::DeleteUserRequest { user_uuid: "userUuid" },
+    admin::RestoreUserRequest { user_uuid: "userUuid" },
+    admin::ExportUsersRequest {},
+    admin::ListUserSessionsRequest { user_uuid: "userUuid" },
+    admin::RevokeSessionRequest { user_uuid: "userUuid", session_id: "sessionId" },
+    admin::RevokeUserSessionsRequest { user_uuid: "userUuid" },
+    admin::WatchSessionEventsRequest {},
+    admin::DeleteGroupRequest { name: "name" },
+    admin::AddGroupMemberRequest { group_name: "groupName", user_uuid: "userUuid" },
+    admin::RemoveGroupMemberRequest { group_name: "groupName", user_uuid: "userUuid" },
+    // Backups are checked line by line as they're read
+    admin::BackupRequest {},
+    admin::RestoreRequest {},
+    admin::GetMaintenanceModeRequest {},
+}
+
+impl Validate for SignUpRequest {
+    fn validate(&self, violations: &mut Violations) {
+        violations.new_username("username", &self.username);
+        violations.password("password", &self.password);
+        violations.email("email", &self.email);
+        violations.opaque("challengeId", &self.challenge_id);
+        violations.opaque("challengeSolution", &self.challenge_solution);
+        violations.opaque("idempotencyKey", &self.idempotency_key);
+    }
+}
+
+impl Validate for SignInRequest {
+    fn validate(&self, violations: &mut Violations) {
+        violations.login("username", &self.username);
+        violations.password("password", &self.password);
+        violations.opaque("totpCode", &self.totp_code);
+    }
+}
+
+impl Validate for RequestPasswordResetRequest {
+    fn validate(&self, violations: &mut Violations) {
+        violations.login("username", &self.username);
+    }
+}
+
+impl Validate for RequestMagicLinkRequest {
+    fn validate(&self, violations: &mut Violations) {
+        violations.login("username", &self.username);
+    }
+}
+
+impl Validate for ConfirmPasswordResetRequest {
+    fn validate(&self, violations: &mut Violations) {
+        violations.opaque("resetToken", &self.reset_token);
+        violations.password("newPassword", &self.new_password);
+    }
+}
+
+impl Validate for UpdateProfileRequest {
+    fn validate(&self, violations: &mut Violations) {
+        violations.opaque("sessionToken", &self.session_token);
+        if let Some(display_name) = &self.display_name {
+            violations.display_name("displayName", display_name);
+        }
+        if let Some(email) = &self.email {
+            violations.email("email", email);
+        }
+    }
+}
+
+impl Validate for BatchCreateUsersRequest {
+    fn validate(&self, violations: &mut Violations) {
+        for (i, user) in self.users.iter().enumerate() {
+            violations.new_username(&format!("users[{i}].username"), &user.username);
+            violations.password(&format!("users[{i}].password"), &user.password);
+        }
+    }
+}
+
+impl Validate for admin::ImportUsersRequest {
+    fn validate(&self, violations: &mut Violations) {
+        // Usernames are kept as the other deployment had them
+        for (i, user) in self.users.iter().enumerate() {
+            violations.opaque(&format!("users[{i}].userUuid"), &user.user_uuid);
+            violations.login(&format!("users[{i}].username"), &user.username);
+            violations.email(&format!("users[{i}].email"), &user.email);
+            violations.display_name(&format!("users[{i}].displayName"), &user.display_name);
+            violations.opaque(&format!("users[{i}].passwordHash"), &user.password_hash);
+            violations.password(&format!("users[{i}].password"), &user.password);
+        }
+    }
+}
+
+impl Validate for admin::CreateUserRequest {
+    fn validate(&self, violations: &mut Violations) {
+        violations.new_username("username", &self.username);
+        violations.password("password", &self.password);
+        violations.email("email", &self.email);
+        violations.display_name("displayName", &self.display_name);
+    }
+}
+
+impl Validate for admin::UpdateUserRequest {
+    fn validate(&self, violations: &mut Violations) {
+        violations.opaque("userUuid", &self.user_uuid);
+        if let Some(display_name) = &self.display_name {
+            violations.display_name("displayName", display_name);
+        }
+        if let Some(email) = &self.email {
+            violations.email("email", email);
+        }
+    }
+}
+
+impl Validate for admin::CreateGroupRequest {
+    fn validate(&self, violations: &mut Violations) {
+        violations.group_name("name", &self.name);
+    }
+}
+
+impl Validate for admin::GetLockoutRequest {
+    fn validate(&self, violations: &mut Violations) {
+        violations.login("username", &self.username);
+    }
+}
+
+impl Validate for admin::ClearLockoutRequest {
+    fn validate(&self, violations: &mut Violations) {
+        violations.login("username", &self.username);
+    }
+}
+
+impl Validate for admin::SetMaintenanceModeRequest {
+    fn validate(&self, violations: &mut Violations) {
+        if !admin::MaintenanceMode::is_valid(self.mode) {
+            violations.add("mode", "Must be a known maintenance mode");
+        }
+    }
+}
+
+impl Validate for SearchUsersRequest {
+    fn validate(&self, violations: &mut Violations) {
+        violations.login("usernamePrefix", &self.username_prefix);
+        violations.login("emailDomain", &self.email_domain);
+        violations.opaque("pageToken", &self.page_token);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn field_violations<T: Validate>(request: T) -> Vec<(String, String)> {
+        let mut violations = Violations::default();
+        request.validate(&mut violations);
+        violations
+            .0
+            .into_iter()
+            .map(|violation| (violation.field, violation.description))
+            .collect()
+    }
+
+    #[test]
+    fn should_check_new_usernames() {
+        let sign_up = |username: &str| SignUpRequest {
+            username: username.to_owned(),
+            ..Default::default()
+        };
+
+        assert!(field_violations(sign_up("alice.smith+test@example.com")).is_empty());
+        for username in ["", "alice smith", "ålice", "alice\n", &"a".repeat(65)] {
+            let violations = field_violations(sign_up(username));
+            assert_eq!(violations.len(), 1, "{username:?}");
+            assert_eq!(violations[0].0, "username");
+        }
+    }
+
+    #[test]
+    fn should_let_existing_usernames_sign_in() {
+        let sign_in = |username: &str| SignInRequest {
+            username: username.to_owned(),
+            password: "password".to_owned(),
+            ..Default::default()
+        };
+
+        // Provisioned from an identity provider or imported
+        assert!(field_violations(sign_in("google:1234")).is_empty());
+        assert!(field_violations(sign_in("ålice")).is_empty());
+        assert_eq!(
+            field_violations(sign_in("alice\u{7}")),
+            [(
+                "username".to_owned(),
+                "Must not contain control characters".to_owned()
+            )]
+        );
+    }
+
+    #[test]
+    fn should_check_passwords_and_emails() {
+        let request = SignUpRequest {
+            username: "alice".to_owned(),
+            password: "a".repeat(MAX_PASSWORD_BYTES + 1),
+            email: "alice".to_owned(),
+            ..Default::default()
+        };
+
+        let fields: Vec<String> = field_violations(request)
+            .into_iter()
+            .map(|(field, _)| field)
+            .collect();
+        assert_eq!(fields, ["password", "email"]);
+
+        let request = UpdateProfileRequest {
+            email: Some(String::new()),
+            display_name: Some("Alice Smith".to_owned()),
+            ..Default::default()
+        };
+        assert!(field_violations(request).is_empty());
+    }
+
+    #[test]
+    fn should_name_fields_of_batch_entries() {
+        let request = BatchCreateUsersRequest {
+            users: vec![
+                NewUser {
+                    username: "alice".to_owned(),
+                    password: "password".to_owned(),
+                },
+                NewUser {
+                    username: "bob smith".to_owned(),
+                    password: "password".to_owned(),
+                },
+            ],
+        };
+
+        let violations = field_violations(request);
+        assert_eq!(violations.len(), 1);
+        assert_eq!(violations[0].0, "users[1].username");
+    }
+
+    #[test]
+    fn should_fail_as_invalid_argument_with_details() {
+        let request = Request::new(SignUpRequest::default());
+
+        let status = validate(&request).unwrap_err();
+
+        assert_eq!(status.code(), Code::InvalidArgument);
+        let details = rpc::Status::decode(status.details()).unwrap();
+        let bad_request = rpc::BadRequest::decode(&details.details[0].value[..]).unwrap();
+        assert_eq!(bad_request.field_violations[0].field, "username");
+        assert!(validate(&Request::new(GetSignUpChallengeRequest {})).is_ok());
+    }
+}
@@ -0,0 +1,546 @@
+use std::fs;
+use std::process::ExitCode;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use auth_client::{AuthClient, AuthError, RetryPolicy};
+use clap::{Parser, Subcommand};
+use tonic::{Code, Request, Status};
+
+use crate::authentication::admin::v1::admin_client::AdminClient;
+use crate::authentication::admin::v1::{
+    AddGroupMemberRequest, BackupRequest, CreateGroupRequest, DeleteGroupRequest,
+    ExportUsersRequest, GetMaintenanceModeRequest, ImportUsersRequest, MaintenanceMode,
+    RemoveGroupMemberRequest, RestoreRequest, RestoreUserRequest, SetMaintenanceModeRequest,
+    UpdateUserRequest, WatchSessionEventsRequest,
+};
+use crate::authentication::v1::{SearchUsersRequest, StatusCode, UserStatus};
+use crate::settings::{Settings, DEFAULT_AUTH_SERVICE_PORT};
+
+pub mod authentication {
+    pub mod v1 {
+        tonic::include_proto!("authentication.v1");
+    }
+
+    pub mod v2 {
+        tonic::include_proto!("authentication.v2");
+    }
+
+    pub mod admin {
+        pub mod v1 {
+            tonic::include_proto!("authentication.admin.v1");
+        }
+    }
+}
+
+#[path = "../settings.rs"]
+mod settings;
+mod user_records;
+
+/// Exit code when the auth service couldn't be reached, so scripts can tell it apart from a
+/// rejected request and try again later. `EX_UNAVAILABLE` from sysexits.h.
+const EXIT_UNAVAILABLE: u8 = 69;
+
+/// Largest backup `backup` and `restore` send or accept, as large as the admin API allows.
+const MAX_BACKUP_BYTES: usize = 256 * 1024 * 1024;
+
+/// Users sent per `ImportUsers` call, the most the admin API accepts.
+const IMPORT_USERS_BATCH_SIZE: usize = 1000;
+
+/// Operates the auth service from scripts and the command line. Results are printed as
+/// `key=value` lines or tab separated rows, errors to stderr. Exits with 1 when the request is
+/// rejected and 69 when the auth service can't be reached.
+#[derive(Parser)]
+#[command(version, about, long_about = None)]
+struct Cli {
+    /// TOML file with settings, overridden by environment variables
+    #[arg(long, global = true)]
+    config_file: Option<String>,
+    /// Host of the auth service, overrides AUTH_SERVICE_IP
+    #[arg(long, global = true)]
+    auth_service_ip: Option<String>,
+    /// Port of the auth service, overrides AUTH_SERVICE_PORT
+    #[arg(long, global = true)]
+    auth_service_port: Option<u16>,
+    /// Address of the auth service's admin API, e.g. 127.0.0.1:50052, overrides
+    /// ADMIN_SERVICE_ADDRESS. Defaults to the ADMIN_BIND_ADDRESS the auth service is configured with
+    #[arg(long, global = true)]
+    admin_service_address: Option<String>,
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    SignUp {
+        #[command(flatten)]
+        credentials: Credentials,
+        #[arg(short, long, default_value = "")]
+        email: String,
+    },
+    /// Prints the user's UUID, session token and refresh token
+    SignIn {
+        #[command(flatten)]
+        credentials: Credentials,
+        #[arg(short, long, default_value = "")]
+        totp_code: String,
+    },
+    SignOut {
+        #[command(flatten)]
+        session: SessionToken,
+    },
+    /// Prints whose session it is and when it expires, failing if it isn't active
+    Validate {
+        #[command(flatten)]
+        session: SessionToken,
+    },
+    /// Manages users, for administrators
+    #[command(subcommand)]
+    Users(UsersCommand),
+    /// Manages sessions, for administrators
+    #[command(subcommand)]
+    Sessions(SessionsCommand),
+    /// Manages groups through the admin API
+    #[command(subcommand)]
+    Groups(GroupsCommand),
+    /// Writes every user and, unless they're stored in a database, session to FILE through the
+    /// admin API, for disaster recovery
+    Backup { file: String },
+    /// Restores a backup written by `backup` through the admin API, keeping the users already
+    /// stored. Prints how many users and sessions were restored
+    Restore { file: String },
+    /// Prints the maintenance mode, or switches to MODE through the admin API: `sign-up` refuses
+    /// sign ups, `sign-in` sign ins as well
+    Maintenance {
+        #[arg(value_parser = ["off", "sign-up", "sign-in"])]
+        mode: Option<String>,
+    },
+}
+
+#[derive(Subcommand)]
+enum UsersCommand {
+    /// Prints the users matching every given filter through the admin API, oldest first: UUID,
+    /// username, email, creation time and status
+    List {
+        #[arg(long, default_value = "")]
+        username_prefix: String,
+        #[arg(long, default_value = "")]
+        email_domain: String,
+        #[arg(long, default_value = "any", value_parser = ["any", "active", "deleted"])]
+        status: String,
+    },
+    /// Signs the user out everywhere and blocks them from signing in through the admin API
+    Disable { user_uuid: String },
+    /// Lets a disabled user sign in again through the admin API
+    Enable { user_uuid: String },
+    /// Restores a deleted account within the deletion grace period through the admin API
+    Restore { user_uuid: String },
+    /// Writes every account that isn't deleted to FILE through the admin API, password hashes
+    /// included, e.g. to import them elsewhere. Prints how many users were exported
+    Export {
+        file: String,
+        #[arg(long, default_value = "csv", value_parser = ["csv", "json"])]
+        format: String,
+    },
+    /// Creates the accounts in a FILE written by `users export` through the admin API. Prints the
+    /// users that couldn't be imported and their errors, then how many were imported
+    Import {
+        file: String,
+        #[arg(long, default_value = "csv", value_parser = ["csv", "json"])]
+        format: String,
+    },
+}
+
+#[derive(Subcommand)]
+enum SessionsCommand {
+    /// Revokes session or refresh tokens along with their sessions
+    Revoke {
+        #[arg(required = true)]
+        tokens: Vec<String>,
+    },
+    /// Prints session events as they happen until interrupted
+    Watch,
+}
+
+#[derive(Subcommand)]
+enum GroupsCommand {
+    /// Creates an empty group, e.g. `admins`
+    Create {
+        name: String,
+    },
+    /// Deletes a group along with its memberships
+    Delete {
+        name: String,
+    },
+    AddMember {
+        name: String,
+        user_uuid: String,
+    },
+    RemoveMember {
+        name: String,
+        user_uuid: String,
+    },
+}
+
+#[derive(clap::Args)]
+struct Credentials {
+    /// Username or email, overrides AUTH_USERNAME
+    #[arg(short, long)]
+    username: Option<String>,
+    /// Overrides AUTH_PASSWORD, which is safer as flags end up in the shell history
+    #[arg(short, long)]
+    password: Option<String>,
+}
+
+#[derive(clap::Args)]
+struct SessionToken {
+    /// Overrides AUTH_SESSION_TOKEN
+    #[arg(short, long)]
+    session_token: Option<String>,
+}
+
+#[tokio::main]
+async fn main() -> ExitCode {
+    match run(Cli::parse()).await {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("{e}");
+            let unavailable = match e.downcast_ref::<AuthError>() {
+                Some(e) => e.is_transient(),
+                None => {
+                    e.is::<tonic::transport::Error>()
+                        || e.downcast_ref::<Status>()
+                            .is_some_and(|status| status.code() == Code::Unavailable)
+                }
+            };
+            match unavailable {
+                true => ExitCode::from(EXIT_UNAVAILABLE),
+                false => ExitCode::FAILURE,
+            }
+        }
+    }
+}
+
+async fn run(cli: Cli) -> Result<(), Box<dyn std::error::Error>> {
+    let (credentials, session) = match &cli.command {
+        Command::SignUp { credentials, .. } | Command::SignIn { credentials, .. } => {
+            (Some(credentials), None)
+        }
+        Command::SignOut { session } | Command::Validate { session } => (None, Some(session)),
+        _ => (None, None),
+    };
+
+    // Flags override environment variables, which override the CONFIG_FILE
+    let flags = [
+        ("--config-file", cli.config_file.clone()),
+        ("--auth-service-ip", cli.auth_service_ip.clone()),
+        (
+            "--auth-service-port",
+            cli.auth_service_port.map(|port| port.to_string()),
+        ),
+        ("--admin-service-address", cli.admin_service_address.clone()),
+        (
+            "--auth-username",
+            credentials.and_then(|credentials| credentials.username.clone()),
+        ),
+        (
+            "--auth-password",
+            credentials.and_then(|credentials| credentials.password.clone()),
+        ),
+        (
+            "--auth-session-token",
+            session.and_then(|session| session.session_token.clone()),
+        ),
+    ]
+    .into_iter()
+    .filter_map(|(flag, value)| Some([flag.to_owned(), value?]))
+    .flatten();
+    let settings = Settings::load(flags)?;
+    let required = |key: &str| {
+        settings
+            .var(key)
+            .filter(|value| !value.is_empty())
+            .ok_or(format!("Error, {key} is required"))
+    };
+
+    let auth_ip = settings
+        .var("AUTH_SERVICE_IP")
+        .unwrap_or("[::0]".to_owned());
+    let auth_port: u16 = match settings.var("AUTH_SERVICE_PORT") {
+        Some(port) => port.parse()?,
+        None => DEFAULT_AUTH_SERVICE_PORT,
+    };
+    let client = AuthClient::connect_lazy(format!("http://{}:{}", auth_ip, auth_port))?
+        .with_retry_policy(RetryPolicy::default());
+
+    match cli.command {
+        Command::SignUp { email, .. } => {
+            client
+                .sign_up(
+                    &required("AUTH_USERNAME")?,
+                    &required("AUTH_PASSWORD")?,
+                    &email,
+                )
+                .await?;
+        }
+        Command::SignIn { totp_code, .. } => {
+            let session = client
+                .sign_in_with_totp(
+                    &required("AUTH_USERNAME")?,
+                    &required("AUTH_PASSWORD")?,
+                    &totp_code,
+                )
+                .await?;
+            println!("user_uuid={}", session.user_uuid);
+            println!("session_token={}", session.session_token);
+            println!("refresh_token={}", session.refresh_token);
+        }
+        Command::SignOut { .. } => {
+            client.sign_out(&required("AUTH_SESSION_TOKEN")?).await?;
+        }
+        Command::Validate { .. } => {
+            let session = client
+                .validate_session(&required("AUTH_SESSION_TOKEN")?)
+                .await?;
+            println!("user_uuid={}", session.user_uuid);
+            println!("expires_at={}", unix_time(session.expires_at));
+        }
+        Command::Users(UsersCommand::List {
+            username_prefix,
+            email_domain,
+            status,
+        }) => {
+            let status = match status.as_str() {
+                "active" => UserStatus::Active,
+                "deleted" => UserStatus::Deleted,
+                _ => UserStatus::Any,
+            };
+
+            let mut admin_client = admin_client(&settings).await?;
+            let mut page_token = String::new();
+            loop {
+                let page = admin_client
+                    .list_users(Request::new(SearchUsersRequest {
+                        username_prefix: username_prefix.clone(),
+                        email_domain: email_domain.clone(),
+                        status: status.into(),
+                        page_token,
+                        ..Default::default()
+                    }))
+                    .await?
+                    .into_inner();
+                for user in page.users {
+                    let status = match (user.deleted_at, user.disabled) {
+                        (0, true) => "disabled",
+                        (0, false) => "active",
+                        _ => "deleted",
+                    };
+                    println!(
+                        "{}\t{}\t{}\t{}\t{}",
+                        user.user_uuid, user.username, user.email, user.created_at, status
+                    );
+                }
+                match page.next_page_token.is_empty() {
+                    true => break,
+                    false => page_token = page.next_page_token,
+                }
+            }
+        }
+        Command::Users(UsersCommand::Disable { user_uuid }) => {
+            admin_client(&settings)
+                .await?
+                .update_user(Request::new(UpdateUserRequest {
+                    user_uuid,
+                    disabled: Some(true),
+                    ..Default::default()
+                }))
+                .await?;
+        }
+        Command::Users(UsersCommand::Enable { user_uuid }) => {
+            admin_client(&settings)
+                .await?
+                .update_user(Request::new(UpdateUserRequest {
+                    user_uuid,
+                    disabled: Some(false),
+                    ..Default::default()
+                }))
+                .await?;
+        }
+        Command::Users(UsersCommand::Restore { user_uuid }) => {
+            admin_client(&settings)
+                .await?
+                .restore_user(Request::new(RestoreUserRequest { user_uuid }))
+                .await?;
+        }
+        Command::Users(UsersCommand::Export { file, format }) => {
+            let format = user_records::Format::parse(&format)?;
+            let users = admin_client(&settings)
+                .await?
+                .export_users(Request::new(ExportUsersRequest {}))
+                .await?
+                .into_inner()
+                .users;
+            fs::write(&file, format.write(&users)?)
+                .map_err(|e| format!("Failed to write the users to {file}.\n{e:?}"))?;
+            println!("users={}", users.len());
+        }
+        Command::Users(UsersCommand::Import { file, format }) => {
+            let format = user_records::Format::parse(&format)?;
+            let contents =
+                fs::read_to_string(&file).map_err(|e| format!("Failed to read {file}.\n{e:?}"))?;
+            let users = format.read(&contents)?;
+
+            let mut admin_client = admin_client(&settings).await?;
+            let mut imported = 0;
+            for batch in users.chunks(IMPORT_USERS_BATCH_SIZE) {
+                let results = admin_client
+                    .import_users(Request::new(ImportUsersRequest {
+                        users: batch.to_vec(),
+                    }))
+                    .await?
+                    .into_inner()
+                    .results;
+                for result in results {
+                    match result.status_code() {
+                        StatusCode::Success => imported += 1,
+                        _ => println!("{}\t{}", result.username, result.error),
+                    }
+                }
+            }
+            println!("users={}", users.len());
+            println!("imported={imported}");
+        }
+        Command::Sessions(SessionsCommand::Revoke { tokens }) => {
+            for token in tokens {
+                client.revoke_token(&token).await?;
+            }
+        }
+        Command::Sessions(SessionsCommand::Watch) => {
+            let mut events = admin_client(&settings)
+                .await?
+                .watch_session_events(Request::new(WatchSessionEventsRequest {}))
+                .await?
+                .into_inner();
+            while let Some(event) = events.message().await? {
+                println!(
+                    "{} session_id={} user_uuid={}",
+                    event.event_type().as_str_name(),
+                    event.session_id,
+                    event.user_uuid
+                );
+            }
+        }
+        Command::Groups(GroupsCommand::Create { name }) => {
+            admin_client(&settings)
+                .await?
+                .create_group(Request::new(CreateGroupRequest { name }))
+                .await?;
+        }
+        Command::Groups(GroupsCommand::Delete { name }) => {
+            admin_client(&settings)
+                .await?
+                .delete_group(Request::new(DeleteGroupRequest { name }))
+                .await?;
+        }
+        Command::Groups(GroupsCommand::AddMember { name, user_uuid }) => {
+            admin_client(&settings)
+                .await?
+                .add_group_member(Request::new(AddGroupMemberRequest {
+                    group_name: name,
+                    user_uuid,
+                }))
+                .await?;
+        }
+        Command::Groups(GroupsCommand::RemoveMember { name, user_uuid }) => {
+            admin_client(&settings)
+                .await?
+                .remove_group_member(Request::new(RemoveGroupMemberRequest {
+                    group_name: name,
+                    user_uuid,
+                }))
+                .await?;
+        }
+        Command::Backup { file } => {
+            let backup = admin_client(&settings)
+                .await?
+                .backup(Request::new(BackupRequest {}))
+                .await?
+                .into_inner();
+            fs::write(&file, backup.backup)
+                .map_err(|e| format!("Failed to write the backup to {file}.\n{e:?}"))?;
+            println!("users={}", backup.users);
+            println!("sessions={}", backup.sessions);
+        }
+        Command::Restore { file } => {
+            let backup = fs::read(&file).map_err(|e| format!("Failed to read {file}.\n{e:?}"))?;
+            let restored = admin_client(&settings)
+                .await?
+                .restore(Request::new(RestoreRequest { backup }))
+                .await?
+                .into_inner();
+            println!("users={}", restored.users);
+            println!("skipped_users={}", restored.skipped_users);
+            println!("sessions={}", restored.sessions);
+        }
+        Command::Maintenance { mode: None } => {
+            let response = admin_client(&settings)
+                .await?
+                .get_maintenance_mode(Request::new(GetMaintenanceModeRequest {}))
+                .await?
+                .into_inner();
+            println!("mode={}", maintenance_mode_name(response.mode()));
+        }
+        Command::Maintenance { mode: Some(mode) } => {
+            let mode = match mode.as_str() {
+                "sign-up" => MaintenanceMode::SignUp,
+                "sign-in" => MaintenanceMode::SignIn,
+                _ => MaintenanceMode::Off,
+            };
+            let response = admin_client(&settings)
+                .await?
+                .set_maintenance_mode(Request::new(SetMaintenanceModeRequest {
+                    mode: mode.into(),
+                }))
+                .await?
+                .into_inner();
+            println!("mode={}", maintenance_mode_name(mode));
+            println!(
+                "previous_mode={}",
+                maintenance_mode_name(response.previous_mode())
+            );
+        }
+    }
+
+    Ok(())
+}
+
+/// Names maintenance modes as `MAINTENANCE_MODE` does.
+fn maintenance_mode_name(mode: MaintenanceMode) -> &'static str {
+    match mode {
+        MaintenanceMode::Off => "off",
+        MaintenanceMode::SignUp => "sign-up",
+        MaintenanceMode::SignIn => "sign-in",
+    }
+}
+
+/// Connects to the admin API, which the auth service serves on a listener of its own.
+async fn admin_client(
+    settings: &Settings,
+) -> Result<AdminClient<tonic::transport::Channel>, Box<dyn std::error::Error>> {
+    let address = settings
+        .var("ADMIN_SERVICE_ADDRESS")
+        .or_else(|| settings.var("ADMIN_BIND_ADDRESS"))
+        .filter(|address| !address.is_empty())
+        .ok_or("Error, ADMIN_SERVICE_ADDRESS is required")?;
+    let client = AdminClient::connect(format!("http://{address}"))
+        .await?
+        .max_decoding_message_size(MAX_BACKUP_BYTES)
+        .max_encoding_message_size(MAX_BACKUP_BYTES);
+    Ok(client)
+}
+
+/// Seconds since the Unix epoch, as the auth API reports times.
+fn unix_time(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
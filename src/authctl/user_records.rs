@@ -0,0 +1,283 @@
+use crate::authentication::admin::v1::UserRecord;
+
+/// Columns written to CSV exports. Imports also accept a `password` column for plaintext
+/// passwords, and any subset of these as long as `username` is present.
+const CSV_COLUMNS: [&str; 6] = [
+    "user_uuid",
+    "username",
+    "email",
+    "display_name",
+    "created_at",
+    "password_hash",
+];
+
+/// File formats users can be exported to and imported from.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Format {
+    Csv,
+    Json,
+}
+
+impl Format {
+    pub fn parse(format: &str) -> Result<Self, String> {
+        match format {
+            "csv" => Ok(Self::Csv),
+            "json" => Ok(Self::Json),
+            format => Err(format!("Error, unknown format: {}", format)),
+        }
+    }
+
+    pub fn write(self, users: &[UserRecord]) -> Result<String, String> {
+        match self {
+            Self::Csv => Ok(to_csv(users)),
+            #[cfg(feature = "json")]
+            Self::Json => Ok(json::to_json(users)),
+            #[cfg(not(feature = "json"))]
+            Self::Json => Err("JSON exports require the `json` feature".to_string()),
+        }
+    }
+
+    pub fn read(self, contents: &str) -> Result<Vec<UserRecord>, String> {
+        match self {
+            Self::Csv => from_csv(contents),
+            #[cfg(feature = "json")]
+            Self::Json => json::from_json(contents),
+            #[cfg(not(feature = "json"))]
+            Self::Json => Err("JSON imports require the `json` feature".to_string()),
+        }
+    }
+}
+
+fn to_csv(users: &[UserRecord]) -> String {
+    let mut csv = CSV_COLUMNS.join(",") + "\n";
+
+    for user in users {
+        let fields = [
+            user.user_uuid.clone(),
+            user.username.clone(),
+            user.email.clone(),
+            user.display_name.clone(),
+            user.created_at.to_string(),
+            user.password_hash.clone(),
+        ];
+        let fields: Vec<String> = fields.iter().map(|field| csv_field(field)).collect();
+
+        csv += &fields.join(",");
+        csv += "\n";
+    }
+
+    csv
+}
+
+fn from_csv(csv: &str) -> Result<Vec<UserRecord>, String> {
+    let mut rows = parse_csv(csv)?.into_iter();
+    let header = rows.next().ok_or("Error, missing CSV header")?;
+
+    if !header.iter().any(|column| column == "username") {
+        return Err("Error, missing username column".to_string());
+    }
+
+    rows.enumerate()
+        .map(|(index, row)| {
+            if row.len() != header.len() {
+                return Err(format!(
+                    "Error, wrong number of fields on row {}",
+                    index + 1
+                ));
+            }
+
+            let mut user = UserRecord::default();
+            for (column, value) in header.iter().zip(row) {
+                match column.as_str() {
+                    "user_uuid" => user.user_uuid = value,
+                    "username" => user.username = value,
+                    "email" => user.email = value,
+                    "display_name" => user.display_name = value,
+                    "created_at" if value.is_empty() => (),
+                    "created_at" => {
+                        user.created_at = value.parse().map_err(|_| {
+                            format!("Error, invalid created_at on row {}", index + 1)
+                        })?
+                    }
+                    "password_hash" => user.password_hash = value,
+                    "password" => user.password = value,
+                    column => return Err(format!("Error, unknown column: {}", column)),
+                }
+            }
+
+            Ok(user)
+        })
+        .collect()
+}
+
+/// Quotes `value` if it contains a separator, quote or line break.
+fn csv_field(value: &str) -> String {
+    match value.contains([',', '"', '\n', '\r']) {
+        true => format!("\"{}\"", value.replace('"', "\"\"")),
+        false => value.to_owned(),
+    }
+}
+
+/// Splits RFC 4180 CSV into rows of fields. Blank lines are skipped.
+fn parse_csv(csv: &str) -> Result<Vec<Vec<String>>, String> {
+    let mut rows = Vec::new();
+    let mut row = Vec::new();
+    let mut field = String::new();
+    let mut quoted = false;
+    let mut chars = csv.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match (quoted, c) {
+            (true, '"') if chars.peek() == Some(&'"') => {
+                chars.next();
+                field.push('"');
+            }
+            (true, '"') => quoted = false,
+            (true, c) => field.push(c),
+            (false, '"') if field.is_empty() => quoted = true,
+            (false, ',') => row.push(std::mem::take(&mut field)),
+            (false, '\r') if chars.peek() == Some(&'\n') => (),
+            (false, '\n') => {
+                row.push(std::mem::take(&mut field));
+                if row != [""] {
+                    rows.push(std::mem::take(&mut row));
+                }
+                row.clear();
+            }
+            (false, c) => field.push(c),
+        }
+    }
+
+    if quoted {
+        return Err("Error, unterminated quoted field".to_string());
+    }
+
+    row.push(field);
+    if row != [""] {
+        rows.push(row);
+    }
+
+    Ok(rows)
+}
+
+#[cfg(feature = "json")]
+mod json {
+    use serde_json::{json, Map, Value};
+
+    use crate::authentication::admin::v1::UserRecord;
+
+    /// Writes users as an array of objects keyed like the CSV columns.
+    pub fn to_json(users: &[UserRecord]) -> String {
+        let users: Vec<Value> = users
+            .iter()
+            .map(|user| {
+                json!({
+                    "user_uuid": user.user_uuid,
+                    "username": user.username,
+                    "email": user.email,
+                    "display_name": user.display_name,
+                    "created_at": user.created_at,
+                    "password_hash": user.password_hash,
+                })
+            })
+            .collect();
+
+        Value::Array(users).to_string()
+    }
+
+    pub fn from_json(json: &str) -> Result<Vec<UserRecord>, String> {
+        let value: Value =
+            serde_json::from_str(json).map_err(|e| format!("Failed to parse JSON.\n{e:?}"))?;
+
+        value
+            .as_array()
+            .ok_or("Error, expected an array of users")?
+            .iter()
+            .map(|user| {
+                let user = user.as_object().ok_or("Error, expected a user object")?;
+
+                Ok(UserRecord {
+                    user_uuid: string_field(user, "user_uuid"),
+                    username: string_field(user, "username"),
+                    email: string_field(user, "email"),
+                    display_name: string_field(user, "display_name"),
+                    created_at: user
+                        .get("created_at")
+                        .and_then(Value::as_u64)
+                        .unwrap_or_default(),
+                    password_hash: string_field(user, "password_hash"),
+                    password: string_field(user, "password"),
+                })
+            })
+            .collect()
+    }
+
+    fn string_field(user: &Map<String, Value>, key: &str) -> String {
+        user.get(key)
+            .and_then(Value::as_str)
+            .unwrap_or_default()
+            .to_owned()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn user(username: &str, display_name: &str) -> UserRecord {
+        UserRecord {
+            user_uuid: format!("{username}-uuid"),
+            username: username.to_owned(),
+            email: format!("{username}@example.com"),
+            display_name: display_name.to_owned(),
+            created_at: 1_700_000_000,
+            password_hash: "$pbkdf2-sha256$i=1000,l=32$c2FsdA$aGFzaA".to_owned(),
+            password: "".to_owned(),
+        }
+    }
+
+    #[test]
+    fn should_round_trip_csv() {
+        let users = vec![
+            user("alice", "Alice"),
+            user("bob", "Bob \"the builder\", Jr.\nSecond line"),
+        ];
+
+        let csv = Format::Csv.write(&users).unwrap();
+
+        assert!(csv.starts_with("user_uuid,username,email,display_name,created_at,password_hash\n"));
+        assert_eq!(Format::Csv.read(&csv).unwrap(), users);
+    }
+
+    #[test]
+    fn should_read_csv_with_plaintext_passwords() {
+        let csv = "username,password\r\nalice,secret\r\n\r\nbob,\"pass,word\"";
+
+        let users = Format::Csv.read(csv).unwrap();
+
+        assert_eq!(users.len(), 2);
+        assert_eq!(users[0].username, "alice");
+        assert_eq!(users[0].password, "secret");
+        assert_eq!(users[1].password, "pass,word");
+        assert_eq!(users[1].created_at, 0);
+    }
+
+    #[test]
+    fn should_reject_invalid_csv() {
+        assert!(Format::Csv.read("").is_err());
+        assert!(Format::Csv.read("email\nalice@example.com").is_err());
+        assert!(Format::Csv.read("username,shoe_size\nalice,9").is_err());
+        assert!(Format::Csv.read("username,email\nalice").is_err());
+        assert!(Format::Csv
+            .read("username,created_at\nalice,yesterday")
+            .is_err());
+        assert!(Format::Csv.read("username\n\"alice").is_err());
+    }
+
+    #[test]
+    fn should_parse_format() {
+        assert_eq!(Format::parse("csv"), Ok(Format::Csv));
+        assert_eq!(Format::parse("json"), Ok(Format::Json));
+        assert!(Format::parse("xml").is_err());
+    }
+}
@@ -1,20 +1,55 @@
 use clap::{Parser, Subcommand};
-use std::env;
+use sha2::{Digest, Sha256};
 
 use authentication::auth_client::AuthClient;
-use authentication::{SignInRequest, SignOutRequest, SignUpRequest};
+use authentication::{
+    ConfirmPasswordResetRequest, ConfirmTotpRequest, DeleteAccountRequest, EnrollTotpRequest,
+    GetLoginHistoryRequest, GetProfileRequest, GetSignUpChallengeRequest, GetUserAttributesRequest,
+    IntrospectTokenRequest, ListActiveSessionsRequest, RedeemMagicLinkRequest,
+    RefreshSessionRequest, RenewSessionRequest, RequestMagicLinkRequest,
+    RequestPasswordResetRequest, RevokeTokenRequest, SetUserAttributeRequest, SignInRequest,
+    SignOutRequest, SignUpRequest, UpdateProfileRequest, ValidateSessionRequest,
+};
+use tonic::codec::CompressionEncoding;
 use tonic::transport::Channel;
 use tonic::{Request, Response};
 
-use crate::authentication::{SignInResponse, SignOutResponse, SignUpResponse};
+use crate::authentication::{
+    ConfirmPasswordResetResponse, ConfirmTotpResponse, DeleteAccountResponse, EnrollTotpResponse,
+    GetLoginHistoryResponse, GetProfileResponse, GetSignUpChallengeResponse,
+    GetUserAttributesResponse, IntrospectTokenResponse, ListActiveSessionsResponse,
+    RefreshSessionResponse, RenewSessionResponse, RequestMagicLinkResponse,
+    RequestPasswordResetResponse, RevokeTokenResponse, SetUserAttributeResponse, SignInResponse,
+    SignOutResponse, SignUpResponse, UpdateProfileResponse, ValidateSessionResponse,
+};
+use crate::settings::{Settings, DEFAULT_AUTH_SERVICE_PORT};
 
 pub mod authentication {
-    tonic::include_proto!("authentication");
+    tonic::include_proto!("authentication.v1");
 }
 
+#[path = "../settings.rs"]
+mod settings;
+
 #[derive(Parser)]
 #[command(version, about, long_about = None)]
 struct Cli {
+    /// TOML file with settings, overridden by environment variables
+    #[arg(long, global = true)]
+    config_file: Option<String>,
+    /// Host of the auth service, overrides AUTH_SERVICE_IP
+    #[arg(long, global = true)]
+    auth_service_ip: Option<String>,
+    /// Port of the auth service, overrides AUTH_SERVICE_PORT
+    #[arg(long, global = true)]
+    auth_service_port: Option<u16>,
+    /// Unix domain socket of the auth service, used instead of its host and port, overrides
+    /// AUTH_SERVICE_SOCKET
+    #[arg(long, global = true)]
+    auth_service_socket: Option<String>,
+    /// Compress requests and accept compressed responses, overrides GRPC_COMPRESSION
+    #[arg(long, global = true, value_parser = ["gzip", "none"])]
+    grpc_compression: Option<String>,
     #[command(subcommand)]
     command: Option<Commands>,
 }
@@ -22,39 +57,179 @@ struct Cli {
 #[derive(Subcommand)]
 enum Commands {
     SignIn {
+        /// Username or email
         #[arg(short, long)]
         username: String,
         #[arg(short, long)]
         password: String,
+        #[arg(short, long)]
+        totp_code: Option<String>,
     },
     SignUp {
         #[arg(short, long)]
         username: String,
         #[arg(short, long)]
         password: String,
+        #[arg(short, long)]
+        email: Option<String>,
+        /// CAPTCHA token, for deployments that verify one instead of a proof of work
+        #[arg(short, long)]
+        captcha_token: Option<String>,
+    },
+    GetSignUpChallenge,
+    RequestMagicLink {
+        #[arg(short, long)]
+        username: String,
+    },
+    RedeemMagicLink {
+        #[arg(short, long)]
+        token: String,
+        #[arg(long)]
+        totp_code: Option<String>,
+    },
+    GetLoginHistory {
+        #[arg(short, long)]
+        session_token: String,
+        #[arg(long)]
+        page_size: Option<u32>,
+        #[arg(long)]
+        page_token: Option<String>,
     },
     SignOut {
         #[arg(short, long)]
         session_token: String,
     },
+    ValidateSession {
+        #[arg(short, long)]
+        session_token: String,
+    },
+    RefreshSession {
+        #[arg(short, long)]
+        refresh_token: String,
+    },
+    RenewSession {
+        #[arg(short, long)]
+        session_token: String,
+    },
+    IntrospectToken {
+        #[arg(short, long)]
+        token: String,
+    },
+    RevokeToken {
+        #[arg(short, long)]
+        token: String,
+    },
+    RequestPasswordReset {
+        #[arg(short, long)]
+        username: String,
+    },
+    ConfirmPasswordReset {
+        #[arg(short, long)]
+        reset_token: String,
+        #[arg(short, long)]
+        new_password: String,
+    },
+    DeleteAccount {
+        #[arg(short, long)]
+        session_token: String,
+    },
+    GetProfile {
+        #[arg(short, long)]
+        session_token: String,
+    },
+    UpdateProfile {
+        #[arg(short, long)]
+        session_token: String,
+        #[arg(short, long)]
+        display_name: Option<String>,
+        #[arg(short, long)]
+        email: Option<String>,
+    },
+    /// Sets an app-specific attribute, or removes it when the value is empty
+    SetUserAttribute {
+        #[arg(short, long)]
+        session_token: String,
+        #[arg(short, long)]
+        key: String,
+        #[arg(short, long, default_value = "")]
+        value: String,
+    },
+    GetUserAttributes {
+        #[arg(short, long)]
+        session_token: String,
+    },
+    ListActiveSessions {
+        #[arg(short, long)]
+        session_token: String,
+    },
+    EnrollTotp {
+        #[arg(short, long)]
+        session_token: String,
+    },
+    ConfirmTotp {
+        #[arg(short, long)]
+        session_token: String,
+        #[arg(short, long)]
+        code: String,
+    },
 }
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
-    // AUTH_SERVICE_IP can be set to your droplet's ip address once your app is deployed
-    let auth_ip = env::var("AUTH_SERVICE_IP").unwrap_or("[::0]".to_owned());
-    // Create new `AuthClient` instance. Propagate any errors.
-    let mut client: AuthClient<Channel> =
-        AuthClient::connect(format!("http://{}:50051", auth_ip)).await?;
-
     let cli = Cli::parse();
 
+    // Flags override environment variables, which override the CONFIG_FILE
+    let flags = [
+        ("--config-file", cli.config_file.clone()),
+        ("--auth-service-ip", cli.auth_service_ip.clone()),
+        (
+            "--auth-service-port",
+            cli.auth_service_port.map(|port| port.to_string()),
+        ),
+        ("--auth-service-socket", cli.auth_service_socket.clone()),
+        ("--grpc-compression", cli.grpc_compression.clone()),
+    ]
+    .into_iter()
+    .filter_map(|(flag, value)| Some([flag.to_owned(), value?]))
+    .flatten();
+    let settings = Settings::load(flags)?;
+
+    // AUTH_SERVICE_IP can be set to your droplet's ip address once your app is deployed
+    let auth_ip = settings
+        .var("AUTH_SERVICE_IP")
+        .unwrap_or("[::0]".to_owned());
+    let auth_port: u16 = match settings.var("AUTH_SERVICE_PORT") {
+        Some(port) => port.parse()?,
+        None => DEFAULT_AUTH_SERVICE_PORT,
+    };
+    // Create new `AuthClient` instance, over the Unix domain socket if set. Propagate any errors.
+    let client: AuthClient<Channel> = match settings
+        .var("AUTH_SERVICE_SOCKET")
+        .filter(|path| !path.is_empty())
+    {
+        Some(path) => AuthClient::new(connect_unix_socket(path).await?),
+        None => AuthClient::connect(format!("http://{}:{}", auth_ip, auth_port)).await?,
+    };
+    // Only compress requests if the auth service is set up to accept them
+    let mut client = match settings.var("GRPC_COMPRESSION").as_deref() {
+        None | Some("none") => client,
+        Some("gzip") => client
+            .send_compressed(CompressionEncoding::Gzip)
+            .accept_compressed(CompressionEncoding::Gzip),
+        Some(encoding) => return Err(format!("Unknown GRPC_COMPRESSION: {encoding}").into()),
+    };
+
     match &cli.command {
-        Some(Commands::SignIn { username, password }) => {
+        Some(Commands::SignIn {
+            username,
+            password,
+            totp_code,
+        }) => {
             // Create a new `SignInRequest`.
             let request: Request<SignInRequest> = Request::new(SignInRequest {
                 username: username.clone(),
                 password: password.clone(),
+                totp_code: totp_code.clone().unwrap_or_default(),
             });
 
             // Make a sign in request. Propagate any errors. Convert Response<SignInResponse> into SignInResponse.
@@ -62,11 +237,26 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
 
             println!("{:?}", response);
         }
-        Some(Commands::SignUp { username, password }) => {
+        Some(Commands::SignUp {
+            username,
+            password,
+            email,
+            captcha_token,
+        }) => {
+            // Solve the sign up challenge, if the service issues one.
+            let (challenge_id, challenge_solution) = match captcha_token {
+                Some(captcha_token) => (String::new(), captcha_token.clone()),
+                None => solve_sign_up_challenge(&mut client).await?,
+            };
+
             // Create a new `SignUpRequest`.
             let request: Request<SignUpRequest> = Request::new(SignUpRequest {
                 username: username.clone(),
                 password: password.clone(),
+                challenge_id,
+                challenge_solution,
+                email: email.clone().unwrap_or_default(),
+                idempotency_key: String::new(),
             });
 
             // Make a sign up request. Propagate any errors.
@@ -84,8 +274,322 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
 
             println!("{:?}", response.into_inner());
         }
+        Some(Commands::ValidateSession { session_token }) => {
+            // Create a new `ValidateSessionRequest`.
+            let request: Request<ValidateSessionRequest> = Request::new(ValidateSessionRequest {
+                session_token: session_token.clone(),
+            });
+
+            let response: Response<ValidateSessionResponse> =
+                client.validate_session(request).await?;
+
+            println!("{:?}", response.into_inner());
+        }
+        Some(Commands::RefreshSession { refresh_token }) => {
+            // Create a new `RefreshSessionRequest`.
+            let request: Request<RefreshSessionRequest> = Request::new(RefreshSessionRequest {
+                refresh_token: refresh_token.clone(),
+            });
+
+            let response: Response<RefreshSessionResponse> =
+                client.refresh_session(request).await?;
+
+            println!("{:?}", response.into_inner());
+        }
+        Some(Commands::RenewSession { session_token }) => {
+            // Create a new `RenewSessionRequest`.
+            let request: Request<RenewSessionRequest> = Request::new(RenewSessionRequest {
+                session_token: session_token.clone(),
+            });
+
+            let response: Response<RenewSessionResponse> = client.renew_session(request).await?;
+
+            println!("{:?}", response.into_inner());
+        }
+        Some(Commands::IntrospectToken { token }) => {
+            // Create a new `IntrospectTokenRequest`.
+            let request: Request<IntrospectTokenRequest> = Request::new(IntrospectTokenRequest {
+                token: token.clone(),
+            });
+
+            let response: Response<IntrospectTokenResponse> =
+                client.introspect_token(request).await?;
+
+            println!("{:?}", response.into_inner());
+        }
+        Some(Commands::RevokeToken { token }) => {
+            // Create a new `RevokeTokenRequest`.
+            let request: Request<RevokeTokenRequest> = Request::new(RevokeTokenRequest {
+                token: token.clone(),
+            });
+
+            let response: Response<RevokeTokenResponse> = client.revoke_token(request).await?;
+
+            println!("{:?}", response.into_inner());
+        }
+        Some(Commands::RequestPasswordReset { username }) => {
+            // Create a new `RequestPasswordResetRequest`.
+            let request: Request<RequestPasswordResetRequest> =
+                Request::new(RequestPasswordResetRequest {
+                    username: username.clone(),
+                });
+
+            let response: Response<RequestPasswordResetResponse> =
+                client.request_password_reset(request).await?;
+
+            println!("{:?}", response.into_inner());
+        }
+        Some(Commands::ConfirmPasswordReset {
+            reset_token,
+            new_password,
+        }) => {
+            // Create a new `ConfirmPasswordResetRequest`.
+            let request: Request<ConfirmPasswordResetRequest> =
+                Request::new(ConfirmPasswordResetRequest {
+                    reset_token: reset_token.clone(),
+                    new_password: new_password.clone(),
+                });
+
+            let response: Response<ConfirmPasswordResetResponse> =
+                client.confirm_password_reset(request).await?;
+
+            println!("{:?}", response.into_inner());
+        }
+        Some(Commands::DeleteAccount { session_token }) => {
+            // Create a new `DeleteAccountRequest`.
+            let request: Request<DeleteAccountRequest> = signed_in(
+                session_token,
+                DeleteAccountRequest {
+                    session_token: session_token.clone(),
+                },
+            )?;
+
+            let response: Response<DeleteAccountResponse> = client.delete_account(request).await?;
+
+            println!("{:?}", response.into_inner());
+        }
+        Some(Commands::GetProfile { session_token }) => {
+            // Create a new `GetProfileRequest`.
+            let request: Request<GetProfileRequest> = signed_in(
+                session_token,
+                GetProfileRequest {
+                    session_token: session_token.clone(),
+                },
+            )?;
+
+            let response: Response<GetProfileResponse> = client.get_profile(request).await?;
+
+            println!("{:?}", response.into_inner());
+        }
+        Some(Commands::UpdateProfile {
+            session_token,
+            display_name,
+            email,
+        }) => {
+            // Create a new `UpdateProfileRequest`.
+            let request: Request<UpdateProfileRequest> = signed_in(
+                session_token,
+                UpdateProfileRequest {
+                    session_token: session_token.clone(),
+                    display_name: display_name.clone(),
+                    email: email.clone(),
+                },
+            )?;
+
+            let response: Response<UpdateProfileResponse> = client.update_profile(request).await?;
+
+            println!("{:?}", response.into_inner());
+        }
+        Some(Commands::SetUserAttribute {
+            session_token,
+            key,
+            value,
+        }) => {
+            // Create a new `SetUserAttributeRequest`.
+            let request: Request<SetUserAttributeRequest> = signed_in(
+                session_token,
+                SetUserAttributeRequest {
+                    session_token: session_token.clone(),
+                    key: key.clone(),
+                    value: value.clone(),
+                },
+            )?;
+
+            let response: Response<SetUserAttributeResponse> =
+                client.set_user_attribute(request).await?;
+
+            println!("{:?}", response.into_inner());
+        }
+        Some(Commands::GetUserAttributes { session_token }) => {
+            // Create a new `GetUserAttributesRequest`.
+            let request: Request<GetUserAttributesRequest> = signed_in(
+                session_token,
+                GetUserAttributesRequest {
+                    session_token: session_token.clone(),
+                },
+            )?;
+
+            let response: Response<GetUserAttributesResponse> =
+                client.get_user_attributes(request).await?;
+
+            println!("{:?}", response.into_inner());
+        }
+        Some(Commands::ListActiveSessions { session_token }) => {
+            // Create a new `ListActiveSessionsRequest`.
+            let request: Request<ListActiveSessionsRequest> = signed_in(
+                session_token,
+                ListActiveSessionsRequest {
+                    session_token: session_token.clone(),
+                },
+            )?;
+
+            let response: Response<ListActiveSessionsResponse> =
+                client.list_active_sessions(request).await?;
+
+            println!("{:?}", response.into_inner());
+        }
+        Some(Commands::EnrollTotp { session_token }) => {
+            // Create a new `EnrollTotpRequest`.
+            let request: Request<EnrollTotpRequest> = signed_in(
+                session_token,
+                EnrollTotpRequest {
+                    session_token: session_token.clone(),
+                },
+            )?;
+
+            let response: Response<EnrollTotpResponse> = client.enroll_totp(request).await?;
+
+            println!("{:?}", response.into_inner());
+        }
+        Some(Commands::ConfirmTotp {
+            session_token,
+            code,
+        }) => {
+            // Create a new `ConfirmTotpRequest`.
+            let request: Request<ConfirmTotpRequest> = signed_in(
+                session_token,
+                ConfirmTotpRequest {
+                    session_token: session_token.clone(),
+                    code: code.clone(),
+                },
+            )?;
+
+            let response: Response<ConfirmTotpResponse> = client.confirm_totp(request).await?;
+
+            println!("{:?}", response.into_inner());
+        }
+        Some(Commands::GetSignUpChallenge) => {
+            let request: Request<GetSignUpChallengeRequest> =
+                Request::new(GetSignUpChallengeRequest {});
+
+            let response: Response<GetSignUpChallengeResponse> =
+                client.get_sign_up_challenge(request).await?;
+
+            println!("{:?}", response.into_inner());
+        }
+        Some(Commands::GetLoginHistory {
+            session_token,
+            page_size,
+            page_token,
+        }) => {
+            // Create a new `GetLoginHistoryRequest`.
+            let request: Request<GetLoginHistoryRequest> = signed_in(
+                session_token,
+                GetLoginHistoryRequest {
+                    session_token: session_token.clone(),
+                    page_size: page_size.unwrap_or_default(),
+                    page_token: page_token.clone().unwrap_or_default(),
+                },
+            )?;
+
+            let response: Response<GetLoginHistoryResponse> =
+                client.get_login_history(request).await?;
+
+            println!("{:?}", response.into_inner());
+        }
+        Some(Commands::RequestMagicLink { username }) => {
+            // Create a new `RequestMagicLinkRequest`.
+            let request: Request<RequestMagicLinkRequest> = Request::new(RequestMagicLinkRequest {
+                username: username.clone(),
+            });
+
+            let response: Response<RequestMagicLinkResponse> =
+                client.request_magic_link(request).await?;
+
+            println!("{:?}", response.into_inner());
+        }
+        Some(Commands::RedeemMagicLink { token, totp_code }) => {
+            // Create a new `RedeemMagicLinkRequest`.
+            let request: Request<RedeemMagicLinkRequest> = Request::new(RedeemMagicLinkRequest {
+                token: token.clone(),
+                totp_code: totp_code.clone().unwrap_or_default(),
+            });
+
+            let response: SignInResponse = client.redeem_magic_link(request).await?.into_inner();
+
+            println!("{:?}", response);
+        }
         None => {}
     }
 
     Ok(())
 }
+
+/// Connects to the auth service over the Unix domain socket at `path`. The endpoint's URI is
+/// only a placeholder, the connector decides where to connect.
+#[cfg(unix)]
+async fn connect_unix_socket(path: String) -> Result<Channel, tonic::transport::Error> {
+    tonic::transport::Endpoint::from_static("http://[::]:50051")
+        .connect_with_connector(tower::service_fn(move |_: tonic::transport::Uri| {
+            tokio::net::UnixStream::connect(path.clone())
+        }))
+        .await
+}
+
+#[cfg(not(unix))]
+async fn connect_unix_socket(_path: String) -> Result<Channel, Box<dyn std::error::Error>> {
+    Err("Unix domain sockets are only supported on Unix".into())
+}
+
+/// Wraps `message` for an RPC acting on the signed in user, who the auth service only takes from
+/// `authorization: Bearer` metadata.
+fn signed_in<T>(session_token: &str, message: T) -> Result<Request<T>, Box<dyn std::error::Error>> {
+    let mut request = Request::new(message);
+    request
+        .metadata_mut()
+        .insert("authorization", format!("Bearer {session_token}").parse()?);
+    Ok(request)
+}
+
+/// Requests a sign up challenge and brute forces its proof of work. Returns empty strings when
+/// the auth service doesn't issue one.
+async fn solve_sign_up_challenge(
+    client: &mut AuthClient<Channel>,
+) -> Result<(String, String), Box<dyn std::error::Error>> {
+    let challenge: GetSignUpChallengeResponse = client
+        .get_sign_up_challenge(Request::new(GetSignUpChallengeRequest {}))
+        .await?
+        .into_inner();
+
+    if challenge.challenge_id.is_empty() {
+        return Ok((String::new(), String::new()));
+    }
+
+    // Find a nonce whose hash starts with `difficulty` zero bits.
+    let solution = (0u64..)
+        .map(|nonce| nonce.to_string())
+        .find(|nonce| {
+            let hash = Sha256::digest(format!("{}:{}", challenge.challenge_id, nonce));
+            let mut bits = 0;
+            for byte in hash {
+                bits += byte.leading_zeros();
+                if byte != 0 {
+                    break;
+                }
+            }
+            bits >= challenge.difficulty
+        })
+        .unwrap_or_default();
+
+    Ok((challenge.challenge_id, solution))
+}
@@ -0,0 +1,257 @@
+use async_graphql::{
+    Context, EmptySubscription, ErrorExtensions, Object, Result, Schema, SimpleObject,
+};
+use axum::extract::State;
+use axum::http::{HeaderMap, HeaderName, HeaderValue};
+use axum::response::{IntoResponse, Response};
+use axum::routing::post;
+use axum::Router;
+
+use crate::authentication::{
+    SignInRequest, SignOutRequest, SignUpRequest, StatusCode, ValidateSessionRequest,
+};
+use crate::{Gateway, Json};
+
+type AuthSchema = Schema<Query, Mutation, EmptySubscription>;
+
+/// Serves the auth API as GraphQL at `/graphql`. Like the REST routes, it signs browsers in with
+/// the session cookie and reads the session token from it or from `Authorization: Bearer`.
+pub fn router(gateway: Gateway) -> Router {
+    let schema = Schema::build(Query, Mutation, EmptySubscription)
+        .data(gateway)
+        .finish();
+
+    Router::new()
+        .route("/graphql", post(execute))
+        .with_state(schema)
+}
+
+/// The session token the request was sent with, if any.
+struct SessionToken(Option<String>);
+
+async fn execute(
+    State(schema): State<AuthSchema>,
+    headers: HeaderMap,
+    Json(request): Json<async_graphql::Request>,
+) -> Response {
+    let session_token = schema
+        .data::<Gateway>()
+        .and_then(|gateway| gateway.cookie.session_token(&headers));
+    let response = schema
+        .execute(request.data(SessionToken(session_token)))
+        .await;
+
+    // Resolvers set cookies through headers on the GraphQL response
+    let mut headers = HeaderMap::new();
+    for (name, value) in &response.http_headers {
+        if let (Ok(name), Ok(value)) = (
+            HeaderName::from_bytes(name.as_str().as_bytes()),
+            HeaderValue::from_bytes(value.as_bytes()),
+        ) {
+            headers.append(name, value);
+        }
+    }
+
+    (headers, Json(response)).into_response()
+}
+
+pub struct Query;
+
+#[Object]
+impl Query {
+    /// The signed in user, or null without a valid session.
+    async fn viewer(&self, ctx: &Context<'_>) -> Result<Option<Viewer>> {
+        let Some(session_token) = ctx.data_unchecked::<SessionToken>().0.clone() else {
+            return Ok(None);
+        };
+
+        let response = client(ctx)
+            .validate_session(ValidateSessionRequest { session_token })
+            .await
+            .map_err(grpc_error)?
+            .into_inner();
+
+        Ok(match response.status_code() {
+            StatusCode::Success => Some(Viewer {
+                user_uuid: response.user_uuid,
+                expires_at: response.expires_at,
+            }),
+            _ => None,
+        })
+    }
+}
+
+#[derive(SimpleObject)]
+struct Viewer {
+    user_uuid: String,
+    /// Unix timestamp (seconds) the session expires at.
+    expires_at: u64,
+}
+
+pub struct Mutation;
+
+#[Object]
+impl Mutation {
+    /// Creates a user. `challengeId` and `challengeSolution` are from `GetSignUpChallenge`, when
+    /// the deployment requires one. Retries with the same `idempotencyKey` and username get the
+    /// first request's result.
+    // Each argument is a GraphQL argument
+    #[allow(clippy::too_many_arguments)]
+    async fn sign_up(
+        &self,
+        ctx: &Context<'_>,
+        username: String,
+        password: String,
+        #[graphql(default)] email: String,
+        #[graphql(default)] challenge_id: String,
+        #[graphql(default)] challenge_solution: String,
+        #[graphql(default)] idempotency_key: String,
+    ) -> Result<SignUpResult> {
+        let request = SignUpRequest {
+            username,
+            password,
+            challenge_id,
+            challenge_solution,
+            email,
+            idempotency_key,
+        };
+        let response = client(ctx)
+            .sign_up(request)
+            .await
+            .map_err(grpc_error)?
+            .into_inner();
+
+        Ok(SignUpResult {
+            status: response.status_code().as_str_name(),
+            password_violations: response
+                .password_violations
+                .into_iter()
+                .map(|violation| violation.message)
+                .collect(),
+        })
+    }
+
+    /// Signs in with a username or email, handing the session token to the browser in the
+    /// session cookie.
+    async fn sign_in(
+        &self,
+        ctx: &Context<'_>,
+        username: String,
+        password: String,
+        #[graphql(default)] totp_code: String,
+    ) -> Result<SignInResult> {
+        let request = SignInRequest {
+            username,
+            password,
+            totp_code,
+        };
+        let response = client(ctx)
+            .sign_in(request)
+            .await
+            .map_err(grpc_error)?
+            .into_inner();
+
+        let status_code = response.status_code();
+        if status_code == StatusCode::Success {
+            let cookie = ctx
+                .data_unchecked::<Gateway>()
+                .cookie
+                .set(&response.session_token);
+            ctx.append_http_header("set-cookie", cookie.as_bytes());
+        }
+
+        Ok(SignInResult {
+            status: status_code.as_str_name(),
+            user_uuid: response.user_uuid,
+            retry_after: response.retry_after,
+        })
+    }
+
+    /// Ends the session the request was sent with and clears the session cookie.
+    async fn sign_out(&self, ctx: &Context<'_>) -> Result<SignOutResult> {
+        // The browser's session is over either way
+        let cookie = ctx.data_unchecked::<Gateway>().cookie.clear();
+        ctx.append_http_header("set-cookie", cookie.as_bytes());
+
+        let Some(session_token) = ctx.data_unchecked::<SessionToken>().0.clone() else {
+            return Ok(SignOutResult {
+                status: StatusCode::Failure.as_str_name(),
+            });
+        };
+
+        let response = client(ctx)
+            .sign_out(SignOutRequest { session_token })
+            .await
+            .map_err(grpc_error)?
+            .into_inner();
+
+        Ok(SignOutResult {
+            status: response.status_code().as_str_name(),
+        })
+    }
+}
+
+#[derive(SimpleObject)]
+struct SignUpResult {
+    status: &'static str,
+    /// Why the password was rejected, when the status is `WEAK_PASSWORD`.
+    password_violations: Vec<String>,
+}
+
+#[derive(SimpleObject)]
+struct SignInResult {
+    status: &'static str,
+    user_uuid: String,
+    /// Seconds until the account unlocks, when the status is `ACCOUNT_LOCKED`.
+    retry_after: u64,
+}
+
+#[derive(SimpleObject)]
+struct SignOutResult {
+    status: &'static str,
+}
+
+fn client(ctx: &Context<'_>) -> crate::AuthClient<crate::AuthChannel> {
+    ctx.data_unchecked::<Gateway>().client.clone()
+}
+
+/// Reports a failed gRPC call as a GraphQL error, with its gRPC code and when to retry.
+fn grpc_error(status: tonic::Status) -> async_graphql::Error {
+    let retry_after = status
+        .metadata()
+        .get("retry-after")
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.parse::<u64>().ok());
+
+    async_graphql::Error::new(status.message()).extend_with(|_, extensions| {
+        extensions.set("code", format!("{:?}", status.code()));
+        if let Some(retry_after) = retry_after {
+            extensions.set("retryAfter", retry_after);
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn should_report_grpc_errors() {
+        let mut status = tonic::Status::resource_exhausted("slow down");
+        status
+            .metadata_mut()
+            .insert("retry-after", "30".parse().unwrap());
+
+        let error = grpc_error(status).into_server_error(Default::default());
+        assert_eq!(error.message, "slow down");
+        let extensions = error.extensions.unwrap();
+        assert_eq!(
+            extensions.get("code"),
+            Some(&async_graphql::Value::from("ResourceExhausted"))
+        );
+        assert_eq!(
+            extensions.get("retryAfter"),
+            Some(&async_graphql::Value::from(30u64))
+        );
+    }
+}
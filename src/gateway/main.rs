@@ -0,0 +1,793 @@
+use std::env;
+use std::net::{Ipv6Addr, SocketAddr};
+
+use authentication::auth_client::AuthClient;
+use authentication::v2::auth_client::AuthClient as AuthV2Client;
+use authentication::v2::{WatchSessionRequest, WatchSessionResponse};
+use authentication::{
+    SignInRequest, SignOutRequest, SignUpRequest, StatusCode, ValidateSessionRequest,
+};
+use axum::body::{Body, Bytes};
+use axum::extract::ws::{Message, WebSocket, WebSocketUpgrade};
+use axum::extract::{FromRequest, State};
+use axum::http::header::{AUTHORIZATION, CONTENT_TYPE, COOKIE, RETRY_AFTER, SET_COOKIE};
+use axum::http::{HeaderMap, HeaderValue, Request, StatusCode as HttpStatus};
+use axum::middleware::{self, Next};
+use axum::response::{IntoResponse, Response};
+use axum::routing::{get, post};
+use axum::Router;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use tonic::body::BoxBody;
+use tonic::transport::{Channel, Endpoint};
+use tonic::{Code, Streaming};
+use tower::util::MapRequest;
+use tracing::Instrument;
+use utoipa::openapi::security::{ApiKey, ApiKeyValue, HttpAuthScheme, HttpBuilder, SecurityScheme};
+use utoipa::{OpenApi, ToSchema};
+
+use crate::logging::LogFormat;
+use crate::request_id::{request_id, REQUEST_ID_HEADER};
+use crate::settings::{Settings, DEFAULT_AUTH_SERVICE_PORT};
+
+pub mod authentication {
+    pub mod v1 {
+        tonic::include_proto!("authentication.v1");
+    }
+
+    pub mod v2 {
+        tonic::include_proto!("authentication.v2");
+    }
+
+    pub use v1::*;
+}
+
+#[cfg(feature = "graphql")]
+mod graphql;
+#[path = "../logging.rs"]
+mod logging;
+#[path = "../request_id.rs"]
+mod request_id;
+#[path = "../settings.rs"]
+mod settings;
+mod transcoding;
+
+/// Port the gateway listens on by default.
+const DEFAULT_GATEWAY_PORT: u16 = 8080;
+/// Cookie the session token is kept in by default.
+const DEFAULT_SESSION_COOKIE_NAME: &str = "session";
+/// Header clients retrying `/signup` set to the same value on every attempt.
+const IDEMPOTENCY_KEY_HEADER: &str = "idempotency-key";
+
+tokio::task_local! {
+    /// ID of the HTTP request being handled, passed on to the auth service with its calls.
+    static REQUEST_ID: HeaderValue;
+}
+
+/// Channel to the auth service sending the request ID along with every call.
+type AuthChannel = MapRequest<Channel, fn(Request<BoxBody>) -> Request<BoxBody>>;
+
+/// Translates REST/JSON requests to the auth service's gRPC API, for clients that can't speak
+/// gRPC. Browsers get their session token in an `HttpOnly` cookie, other clients can send it as
+/// `Authorization: Bearer <session token>` instead. The v2 API is also served under `/v2` as
+/// its proto's `google.api.http` rules describe.
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    // Flags override environment variables, which override the CONFIG_FILE
+    let settings = Settings::load(env::args().skip(1))?;
+    logging::init(
+        &settings
+            .var("LOG_LEVEL")
+            .unwrap_or(logging::DEFAULT_LOG_LEVEL.to_owned()),
+        match settings.var("LOG_FORMAT") {
+            Some(format) => format.parse()?,
+            None => LogFormat::Text,
+        },
+        Vec::new(),
+    )?;
+
+    let addr = match settings.var("GATEWAY_BIND_ADDRESS") {
+        Some(addr) => addr.parse()?,
+        None => SocketAddr::from((Ipv6Addr::UNSPECIFIED, DEFAULT_GATEWAY_PORT)),
+    };
+    let cookie = SessionCookie {
+        name: settings
+            .var("SESSION_COOKIE_NAME")
+            .unwrap_or(DEFAULT_SESSION_COOKIE_NAME.to_owned()),
+        // Browsers only send secure cookies over HTTPS, turn it off to try the gateway over HTTP
+        secure: match settings.var("SESSION_COOKIE_SECURE") {
+            Some(secure) => secure.parse()?,
+            None => true,
+        },
+    };
+
+    // Connect lazily so the gateway can start before the auth service does
+    let channel = match settings
+        .var("AUTH_SERVICE_SOCKET")
+        .filter(|path| !path.is_empty())
+    {
+        Some(path) => unix_socket_channel(path)?,
+        None => {
+            let auth_hostname = settings
+                .var("AUTH_SERVICE_HOST_NAME")
+                .unwrap_or("[::0]".to_owned());
+            let auth_port: u16 = match settings.var("AUTH_SERVICE_PORT") {
+                Some(port) => port.parse()?,
+                None => DEFAULT_AUTH_SERVICE_PORT,
+            };
+            Endpoint::from_shared(format!("http://{}:{}", auth_hostname, auth_port))?.connect_lazy()
+        }
+    };
+
+    let channel: AuthChannel = MapRequest::new(channel, with_request_id);
+
+    // Serve the v2 API's HTTP rules next to the routes with cookie sessions
+    let app = router(Gateway {
+        client: AuthClient::new(channel.clone()),
+        client_v2: AuthV2Client::new(channel.clone()),
+        cookie,
+    })
+    .merge(transcoding::router(channel)?)
+    .layer(middleware::from_fn(identify_request));
+
+    tracing::info!(%addr, "Gateway listening");
+    axum::Server::bind(&addr)
+        .serve(app.into_make_service())
+        .await?;
+
+    Ok(())
+}
+
+/// Connects to the auth service over the Unix domain socket at `path` once it's first called.
+#[cfg(unix)]
+fn unix_socket_channel(path: String) -> Result<Channel, tonic::transport::Error> {
+    Ok(
+        Endpoint::from_static("http://[::]:50051").connect_with_connector_lazy(tower::service_fn(
+            move |_: tonic::transport::Uri| tokio::net::UnixStream::connect(path.clone()),
+        )),
+    )
+}
+
+#[cfg(not(unix))]
+fn unix_socket_channel(_path: String) -> Result<Channel, Box<dyn std::error::Error>> {
+    Err("Unix domain sockets are only supported on Unix".into())
+}
+
+/// Tags the request with the `x-request-id` it came with, or a new one, so the auth service's
+/// logs and the gateway's can be correlated. The ID is sent back on every response.
+async fn identify_request<B>(request: Request<B>, next: Next<B>) -> Response {
+    let request_id = request_id(request.headers());
+    let span = tracing::info_span!(
+        "request",
+        method = %request.method(),
+        path = request.uri().path(),
+        request_id = request_id.to_str().unwrap_or_default(),
+    );
+
+    let mut response = REQUEST_ID
+        .scope(request_id.clone(), next.run(request))
+        .instrument(span)
+        .await;
+    response.headers_mut().insert(REQUEST_ID_HEADER, request_id);
+    response
+}
+
+/// Adds the ID of the request being handled, if any, to a call to the auth service.
+fn with_request_id(mut request: Request<BoxBody>) -> Request<BoxBody> {
+    if let Ok(request_id) = REQUEST_ID.try_with(HeaderValue::clone) {
+        request.headers_mut().insert(REQUEST_ID_HEADER, request_id);
+    }
+    request
+}
+
+#[derive(Clone)]
+struct Gateway {
+    client: AuthClient<AuthChannel>,
+    client_v2: AuthV2Client<AuthChannel>,
+    cookie: SessionCookie,
+}
+
+fn router(gateway: Gateway) -> Router {
+    let router = Router::new()
+        .route("/signup", post(sign_up))
+        .route("/signin", post(sign_in))
+        .route("/signout", post(sign_out))
+        .route("/validate", post(validate))
+        .route("/watch", get(watch_session))
+        .route("/openapi.json", get(openapi))
+        .with_state(gateway.clone());
+
+    #[cfg(feature = "graphql")]
+    let router = router.merge(graphql::router(gateway));
+
+    router
+}
+
+/// The cookie browsers keep their session token in.
+#[derive(Clone)]
+struct SessionCookie {
+    name: String,
+    secure: bool,
+}
+
+impl SessionCookie {
+    /// `Set-Cookie` value handing `session_token` to the browser. Scripts can't read it and
+    /// other sites can't make the browser send it.
+    fn set(&self, session_token: &str) -> HeaderValue {
+        self.header(&format!("{}={}", self.name, session_token))
+    }
+
+    /// `Set-Cookie` value making the browser forget the session token.
+    fn clear(&self) -> HeaderValue {
+        self.header(&format!("{}=; Max-Age=0", self.name))
+    }
+
+    fn header(&self, cookie: &str) -> HeaderValue {
+        let secure = if self.secure { "; Secure" } else { "" };
+        HeaderValue::from_str(&format!(
+            "{cookie}; Path=/; HttpOnly; SameSite=Strict{secure}"
+        ))
+        .unwrap_or(HeaderValue::from_static(""))
+    }
+
+    /// The session token from `Authorization: Bearer`, or else from the session cookie.
+    fn session_token(&self, headers: &HeaderMap) -> Option<String> {
+        let bearer = headers
+            .get(AUTHORIZATION)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.strip_prefix("Bearer "))
+            .map(str::trim);
+        let cookie = || {
+            headers
+                .get_all(COOKIE)
+                .iter()
+                .filter_map(|value| value.to_str().ok())
+                .flat_map(|value| value.split(';'))
+                .filter_map(|cookie| cookie.trim().split_once('='))
+                .find(|(name, _)| *name == self.name)
+                .map(|(_, value)| value)
+        };
+
+        bearer
+            .or_else(cookie)
+            .filter(|token| !token.is_empty())
+            .map(str::to_owned)
+    }
+}
+
+/// Describes the REST routes, so client SDKs can be generated for them.
+#[derive(OpenApi)]
+#[openapi(
+    info(title = "Auth gateway"),
+    paths(sign_up, sign_in, sign_out, validate),
+    modifiers(&SessionSecurity)
+)]
+struct ApiDoc;
+
+/// Routes taking a session accept it in the session cookie or as a bearer token.
+struct SessionSecurity;
+
+impl utoipa::Modify for SessionSecurity {
+    fn modify(&self, openapi: &mut utoipa::openapi::OpenApi) {
+        let components = openapi.components.get_or_insert_with(Default::default);
+        components.add_security_scheme(
+            "cookie",
+            SecurityScheme::ApiKey(ApiKey::Cookie(ApiKeyValue::new(
+                DEFAULT_SESSION_COOKIE_NAME,
+            ))),
+        );
+        components.add_security_scheme(
+            "bearer",
+            SecurityScheme::Http(HttpBuilder::new().scheme(HttpAuthScheme::Bearer).build()),
+        );
+    }
+}
+
+async fn openapi() -> Response {
+    Json(ApiDoc::openapi()).into_response()
+}
+
+#[derive(Deserialize, ToSchema)]
+struct SignUpBody {
+    username: String,
+    password: String,
+    #[serde(default)]
+    email: String,
+    /// From `GetSignUpChallenge`, when the deployment requires one.
+    #[serde(default)]
+    challenge_id: String,
+    #[serde(default)]
+    challenge_solution: String,
+}
+
+#[derive(Serialize, ToSchema)]
+struct SignUpReply {
+    #[schema(example = "SUCCESS")]
+    status: &'static str,
+    /// Why the password was rejected, when the status is `WEAK_PASSWORD`.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    password_violations: Vec<String>,
+}
+
+/// Creates a user.
+#[utoipa::path(
+    post,
+    path = "/signup",
+    request_body = SignUpBody,
+    params(("Idempotency-Key" = Option<String>, Header,
+        description = "Retries with the same key and username get the first request's reply")),
+    responses(
+        (status = 201, description = "The user was created", body = SignUpReply),
+        (status = 422, description = "The password is too weak", body = SignUpReply),
+        (status = 400, description = "The user wasn't created", body = SignUpReply),
+        (status = 503, description = "Sign ups are paused for maintenance", body = SignUpReply),
+    )
+)]
+async fn sign_up(
+    State(mut gateway): State<Gateway>,
+    headers: HeaderMap,
+    Json(body): Json<SignUpBody>,
+) -> Response {
+    let idempotency_key = headers
+        .get(IDEMPOTENCY_KEY_HEADER)
+        .and_then(|key| key.to_str().ok())
+        .unwrap_or_default();
+    let request = SignUpRequest {
+        username: body.username,
+        password: body.password,
+        challenge_id: body.challenge_id,
+        challenge_solution: body.challenge_solution,
+        email: body.email,
+        idempotency_key: idempotency_key.to_owned(),
+    };
+    let response = match gateway.client.sign_up(request).await {
+        Ok(response) => response.into_inner(),
+        Err(status) => return grpc_error(status),
+    };
+
+    let status_code = response.status_code();
+    let reply = SignUpReply {
+        status: status_code.as_str_name(),
+        password_violations: response
+            .password_violations
+            .into_iter()
+            .map(|violation| violation.message)
+            .collect(),
+    };
+    let http_status = match status_code {
+        StatusCode::Success => HttpStatus::CREATED,
+        StatusCode::WeakPassword => HttpStatus::UNPROCESSABLE_ENTITY,
+        StatusCode::Maintenance => HttpStatus::SERVICE_UNAVAILABLE,
+        _ => HttpStatus::BAD_REQUEST,
+    };
+
+    (http_status, Json(reply)).into_response()
+}
+
+#[derive(Deserialize, ToSchema)]
+struct SignInBody {
+    /// Username or email.
+    username: String,
+    password: String,
+    /// Required once TOTP is enabled for the user.
+    #[serde(default)]
+    totp_code: String,
+}
+
+#[derive(Serialize, ToSchema)]
+struct SignInReply {
+    #[schema(example = "SUCCESS")]
+    status: &'static str,
+    #[serde(skip_serializing_if = "String::is_empty")]
+    user_uuid: String,
+}
+
+/// Signs in with a username or email, handing the session token to the browser in the session
+/// cookie.
+#[utoipa::path(
+    post,
+    path = "/signin",
+    request_body = SignInBody,
+    responses(
+        (status = 200, description = "Signed in", body = SignInReply,
+            headers(("Set-Cookie" = String, description = "The session cookie"))),
+        (status = 401, description = "The credentials were wrong", body = SignInReply),
+        (status = 403, description = "The account is locked or disabled", body = SignInReply,
+            headers(("Retry-After" = u64, description = "Seconds until a locked account unlocks"))),
+        (status = 409, description = "The user has too many sessions", body = SignInReply),
+        (status = 503, description = "Sign ins are paused for maintenance", body = SignInReply),
+    )
+)]
+async fn sign_in(State(mut gateway): State<Gateway>, Json(body): Json<SignInBody>) -> Response {
+    let request = SignInRequest {
+        username: body.username,
+        password: body.password,
+        totp_code: body.totp_code,
+    };
+    let response = match gateway.client.sign_in(request).await {
+        Ok(response) => response.into_inner(),
+        Err(status) => return grpc_error(status),
+    };
+
+    let status_code = response.status_code();
+    let reply = Json(SignInReply {
+        status: status_code.as_str_name(),
+        user_uuid: response.user_uuid,
+    });
+    match status_code {
+        StatusCode::Success => (
+            [(SET_COOKIE, gateway.cookie.set(&response.session_token))],
+            reply,
+        )
+            .into_response(),
+        StatusCode::AccountLocked => (
+            HttpStatus::FORBIDDEN,
+            [(RETRY_AFTER, HeaderValue::from(response.retry_after))],
+            reply,
+        )
+            .into_response(),
+        StatusCode::AccountDisabled => (HttpStatus::FORBIDDEN, reply).into_response(),
+        StatusCode::SessionLimitReached => (HttpStatus::CONFLICT, reply).into_response(),
+        StatusCode::Maintenance => (HttpStatus::SERVICE_UNAVAILABLE, reply).into_response(),
+        _ => (HttpStatus::UNAUTHORIZED, reply).into_response(),
+    }
+}
+
+#[derive(Serialize, ToSchema)]
+struct SignOutReply {
+    #[schema(example = "SUCCESS")]
+    status: &'static str,
+}
+
+/// Ends the session and clears the session cookie.
+#[utoipa::path(
+    post,
+    path = "/signout",
+    security(("cookie" = []), ("bearer" = [])),
+    responses(
+        (status = 200, description = "Signed out", body = SignOutReply),
+        (status = 401, description = "There was no valid session", body = SignOutReply),
+    )
+)]
+async fn sign_out(State(mut gateway): State<Gateway>, headers: HeaderMap) -> Response {
+    let clear_cookie = [(SET_COOKIE, gateway.cookie.clear())];
+    let Some(session_token) = gateway.cookie.session_token(&headers) else {
+        return (HttpStatus::UNAUTHORIZED, clear_cookie, no_session()).into_response();
+    };
+
+    let response = match gateway
+        .client
+        .sign_out(SignOutRequest { session_token })
+        .await
+    {
+        Ok(response) => response.into_inner(),
+        Err(status) => return grpc_error(status),
+    };
+
+    let status_code = response.status_code();
+    let reply = Json(SignOutReply {
+        status: status_code.as_str_name(),
+    });
+    let http_status = match status_code {
+        StatusCode::Success => HttpStatus::OK,
+        _ => HttpStatus::UNAUTHORIZED,
+    };
+
+    // The browser's session is over either way.
+    (http_status, clear_cookie, reply).into_response()
+}
+
+#[derive(Serialize, ToSchema)]
+struct ValidateReply {
+    #[schema(example = "SUCCESS")]
+    status: &'static str,
+    #[serde(skip_serializing_if = "String::is_empty")]
+    user_uuid: String,
+    /// Unix timestamp (seconds).
+    #[serde(skip_serializing_if = "is_zero")]
+    expires_at: u64,
+}
+
+/// Checks the session, returning whose it is and when it expires.
+#[utoipa::path(
+    post,
+    path = "/validate",
+    security(("cookie" = []), ("bearer" = [])),
+    responses(
+        (status = 200, description = "The session is valid", body = ValidateReply),
+        (status = 401, description = "There was no valid session", body = ValidateReply),
+    )
+)]
+async fn validate(State(mut gateway): State<Gateway>, headers: HeaderMap) -> Response {
+    let Some(session_token) = gateway.cookie.session_token(&headers) else {
+        return (HttpStatus::UNAUTHORIZED, no_session()).into_response();
+    };
+
+    let response = match gateway
+        .client
+        .validate_session(ValidateSessionRequest { session_token })
+        .await
+    {
+        Ok(response) => response.into_inner(),
+        Err(status) => return grpc_error(status),
+    };
+
+    let status_code = response.status_code();
+    let reply = Json(ValidateReply {
+        status: status_code.as_str_name(),
+        user_uuid: response.user_uuid,
+        expires_at: response.expires_at,
+    });
+    match status_code {
+        StatusCode::Success => reply.into_response(),
+        _ => (HttpStatus::UNAUTHORIZED, reply).into_response(),
+    }
+}
+
+#[derive(Serialize)]
+struct SessionEndedReply {
+    /// `DELETED` or `EXPIRED`.
+    event_type: &'static str,
+    /// Unix timestamp (seconds).
+    timestamp: u64,
+}
+
+/// Tells the browser over a WebSocket once its session ends, e.g. because it was signed out
+/// elsewhere, so the UI can sign out at once. A single JSON message is sent before the socket is
+/// closed, and the socket is closed without one if the session can no longer be watched.
+async fn watch_session(
+    State(mut gateway): State<Gateway>,
+    headers: HeaderMap,
+    upgrade: WebSocketUpgrade,
+) -> Response {
+    let Some(session_token) = gateway.cookie.session_token(&headers) else {
+        return (HttpStatus::UNAUTHORIZED, no_session()).into_response();
+    };
+
+    // Watch before upgrading, so invalid sessions are rejected with an HTTP status
+    let ended = match gateway
+        .client_v2
+        .watch_session(WatchSessionRequest { session_token })
+        .await
+    {
+        Ok(response) => response.into_inner(),
+        Err(status) => return grpc_error(status),
+    };
+
+    upgrade.on_upgrade(|socket| notify_session_end(socket, ended))
+}
+
+async fn notify_session_end(mut socket: WebSocket, mut ended: Streaming<WatchSessionResponse>) {
+    let ended = loop {
+        tokio::select! {
+            ended = ended.message() => break ended,
+            // Stop watching once the browser goes away
+            message = socket.recv() => match message {
+                Some(Ok(_)) => continue,
+                _ => return,
+            },
+        }
+    };
+
+    if let Ok(Some(ended)) = ended {
+        let _ = socket.send(session_ended_message(&ended)).await;
+    }
+    let _ = socket.close().await;
+}
+
+fn session_ended_message(ended: &WatchSessionResponse) -> Message {
+    let reply = SessionEndedReply {
+        event_type: ended.event_type().as_str_name(),
+        timestamp: ended.timestamp,
+    };
+    Message::Text(serde_json::to_string(&reply).unwrap_or_default())
+}
+
+/// A JSON request or response body.
+struct Json<T>(T);
+
+impl<T: Serialize> IntoResponse for Json<T> {
+    fn into_response(self) -> Response {
+        match serde_json::to_vec(&self.0) {
+            Ok(body) => (
+                [(CONTENT_TYPE, HeaderValue::from_static("application/json"))],
+                body,
+            )
+                .into_response(),
+            Err(e) => (HttpStatus::INTERNAL_SERVER_ERROR, e.to_string()).into_response(),
+        }
+    }
+}
+
+/// Reads JSON request bodies, rejecting malformed ones.
+#[axum::async_trait]
+impl<T: DeserializeOwned, S: Send + Sync> FromRequest<S, Body> for Json<T> {
+    type Rejection = Response;
+
+    async fn from_request(request: Request<Body>, state: &S) -> Result<Self, Self::Rejection> {
+        let body = Bytes::from_request(request, state)
+            .await
+            .map_err(IntoResponse::into_response)?;
+
+        serde_json::from_slice(&body).map(Json).map_err(|e| {
+            let reply = Json(ErrorReply {
+                status: "ERROR",
+                message: e.to_string(),
+            });
+            (HttpStatus::BAD_REQUEST, reply).into_response()
+        })
+    }
+}
+
+fn is_zero(value: &u64) -> bool {
+    *value == 0
+}
+
+/// Reply to requests without a session token.
+fn no_session() -> Json<SignOutReply> {
+    Json(SignOutReply {
+        status: StatusCode::Failure.as_str_name(),
+    })
+}
+
+#[derive(Serialize)]
+struct ErrorReply {
+    status: &'static str,
+    message: String,
+}
+
+/// Translates a failed gRPC call to the closest HTTP status, passing on when to retry.
+fn grpc_error(status: tonic::Status) -> Response {
+    let reply = Json(ErrorReply {
+        status: "ERROR",
+        message: status.message().to_owned(),
+    });
+
+    let http_status = http_status(status.code());
+    match retry_after(&status) {
+        Some(retry_after) => (http_status, [(RETRY_AFTER, retry_after)], reply).into_response(),
+        None => (http_status, reply).into_response(),
+    }
+}
+
+/// The HTTP status closest to a gRPC status code.
+fn http_status(code: Code) -> HttpStatus {
+    match code {
+        Code::InvalidArgument | Code::OutOfRange => HttpStatus::BAD_REQUEST,
+        Code::Unauthenticated => HttpStatus::UNAUTHORIZED,
+        Code::PermissionDenied => HttpStatus::FORBIDDEN,
+        Code::NotFound => HttpStatus::NOT_FOUND,
+        Code::AlreadyExists | Code::Aborted => HttpStatus::CONFLICT,
+        Code::FailedPrecondition => HttpStatus::PRECONDITION_FAILED,
+        Code::ResourceExhausted => HttpStatus::TOO_MANY_REQUESTS,
+        Code::Unimplemented => HttpStatus::NOT_IMPLEMENTED,
+        Code::Unavailable => HttpStatus::SERVICE_UNAVAILABLE,
+        Code::DeadlineExceeded => HttpStatus::GATEWAY_TIMEOUT,
+        _ => HttpStatus::BAD_GATEWAY,
+    }
+}
+
+/// When the auth service said to retry a failed call, for a `Retry-After` header.
+fn retry_after(status: &tonic::Status) -> Option<HeaderValue> {
+    status
+        .metadata()
+        .get("retry-after")
+        .and_then(|value| HeaderValue::from_bytes(value.as_bytes()).ok())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cookie() -> SessionCookie {
+        SessionCookie {
+            name: "session".to_owned(),
+            secure: true,
+        }
+    }
+
+    fn headers(entries: &[(&'static str, &'static str)]) -> HeaderMap {
+        entries
+            .iter()
+            .map(|(name, value)| (name.parse().unwrap(), HeaderValue::from_static(value)))
+            .collect()
+    }
+
+    #[test]
+    fn should_read_session_token_from_bearer_or_cookie() {
+        let cookie = cookie();
+
+        let from_bearer = headers(&[
+            ("authorization", "Bearer bearer-token"),
+            ("cookie", "session=cookie-token"),
+        ]);
+        assert_eq!(
+            cookie.session_token(&from_bearer).as_deref(),
+            Some("bearer-token")
+        );
+
+        let from_cookie = headers(&[("cookie", "theme=dark; session=cookie-token")]);
+        assert_eq!(
+            cookie.session_token(&from_cookie).as_deref(),
+            Some("cookie-token")
+        );
+
+        let without_token = headers(&[("cookie", "theme=dark; session=")]);
+        assert_eq!(cookie.session_token(&without_token), None);
+    }
+
+    #[test]
+    fn should_format_session_cookie() {
+        assert_eq!(
+            cookie().set("token"),
+            "session=token; Path=/; HttpOnly; SameSite=Strict; Secure"
+        );
+
+        let insecure = SessionCookie {
+            secure: false,
+            ..cookie()
+        };
+        assert_eq!(
+            insecure.clear(),
+            "session=; Max-Age=0; Path=/; HttpOnly; SameSite=Strict"
+        );
+    }
+
+    #[tokio::test]
+    async fn should_reject_malformed_json() {
+        let request = |body: &'static str| Request::new(Body::from(body));
+
+        let Json(body) = Json::<SignInBody>::from_request(
+            request(r#"{"username": "alice", "password": "secret"}"#),
+            &(),
+        )
+        .await
+        .map_err(|_| "rejected")
+        .unwrap();
+        assert_eq!(body.username, "alice");
+        assert_eq!(body.totp_code, "");
+
+        let rejected = Json::<SignInBody>::from_request(request(r#"{"username": "alice"}"#), &())
+            .await
+            .map(|_| ())
+            .unwrap_err();
+        assert_eq!(rejected.status(), HttpStatus::BAD_REQUEST);
+    }
+
+    #[test]
+    fn should_describe_every_route() {
+        let openapi = ApiDoc::openapi();
+        let paths: Vec<_> = openapi.paths.paths.keys().map(String::as_str).collect();
+        assert_eq!(paths, ["/signin", "/signout", "/signup", "/validate"]);
+
+        let schemas = openapi.components.unwrap().schemas;
+        assert!(schemas.contains_key("SignInBody"));
+        assert!(schemas.contains_key("ValidateReply"));
+    }
+
+    #[test]
+    fn should_describe_session_end() {
+        let ended = WatchSessionResponse {
+            event_type: authentication::SessionEventType::Deleted.into(),
+            timestamp: 1700000000,
+        };
+        let Message::Text(text) = session_ended_message(&ended) else {
+            panic!("Expected a text message");
+        };
+        assert_eq!(text, r#"{"event_type":"DELETED","timestamp":1700000000}"#);
+    }
+
+    #[test]
+    fn should_translate_grpc_errors() {
+        let response = grpc_error(tonic::Status::unavailable("down"));
+        assert_eq!(response.status(), HttpStatus::SERVICE_UNAVAILABLE);
+
+        let mut status = tonic::Status::resource_exhausted("slow down");
+        status
+            .metadata_mut()
+            .insert("retry-after", "30".parse().unwrap());
+        let response = grpc_error(status);
+        assert_eq!(response.status(), HttpStatus::TOO_MANY_REQUESTS);
+        assert_eq!(response.headers()[RETRY_AFTER], "30");
+    }
+}
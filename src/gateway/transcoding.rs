@@ -0,0 +1,418 @@
+use std::collections::HashMap;
+
+use axum::body::Bytes;
+use axum::extract::{Path, RawQuery};
+use axum::http::header::{AUTHORIZATION, CONTENT_TYPE, RETRY_AFTER};
+use axum::http::{HeaderMap, HeaderValue, StatusCode as HttpStatus};
+use axum::response::{IntoResponse, Response};
+use axum::routing::{on, MethodFilter, MethodRouter};
+use axum::Router;
+use percent_encoding::percent_decode_str;
+use prost::bytes::{Buf, BufMut};
+use prost_reflect::prost::Message;
+use prost_reflect::{
+    DescriptorPool, DynamicMessage, Kind, MethodDescriptor, ReflectMessage, Value,
+};
+use tonic::codec::{Codec, DecodeBuf, Decoder, EncodeBuf, Encoder};
+use tonic::Status;
+
+use crate::{http_status, retry_after, AuthChannel, ErrorReply, Json};
+
+/// Service whose `google.api.http` rules are served.
+const SERVICE_NAME: &str = "authentication.v2.Auth";
+
+/// Serves every unary RPC of the v2 auth API that has a `google.api.http` rule as REST/JSON,
+/// so the proto is the one definition of both the gRPC and the REST API.
+pub fn router(channel: AuthChannel) -> Result<Router, String> {
+    let mut routes: HashMap<String, MethodRouter> = HashMap::new();
+    for rule in rules()? {
+        let path = rule.route();
+        let filter = rule.method_filter;
+        let channel = channel.clone();
+        let handler = move |Path(params): Path<HashMap<String, String>>,
+                            RawQuery(query): RawQuery,
+                            headers: HeaderMap,
+                            body: Bytes| async move {
+            rule.call(channel, params, query, headers, body).await
+        };
+
+        let method_router = match routes.remove(&path) {
+            Some(method_router) => method_router.on(filter, handler),
+            None => on(filter, handler),
+        };
+        routes.insert(path, method_router);
+    }
+
+    Ok(routes
+        .into_iter()
+        .fold(Router::new(), |router, (path, method_router)| {
+            router.route(&path, method_router)
+        }))
+}
+
+/// How an RPC is served over HTTP, from its `google.api.http` rule.
+#[derive(Clone)]
+struct Rule {
+    rpc: MethodDescriptor,
+    method_filter: MethodFilter,
+    /// Path template, e.g. `/v2/attributes/{key}`.
+    template: String,
+    /// Whether the request fields not bound by the path are read from a JSON body, otherwise
+    /// they're read from the query string.
+    body: bool,
+}
+
+/// The rules of the RPCs served over HTTP. Only the `*` body is supported, and server streaming
+/// RPCs aren't served.
+fn rules() -> Result<Vec<Rule>, String> {
+    let pool = DescriptorPool::decode(FILE_DESCRIPTOR_SET)
+        .map_err(|e| format!("Error, invalid descriptor set.\n{e:?}"))?;
+    let http = pool
+        .get_extension_by_name("google.api.http")
+        .ok_or("Error, google.api.http isn't in the descriptor set")?;
+    let service = pool
+        .get_service_by_name(SERVICE_NAME)
+        .ok_or(format!("Error, {SERVICE_NAME} isn't in the descriptor set"))?;
+
+    let mut rules = Vec::new();
+    for rpc in service.methods() {
+        let options = rpc.options();
+        if rpc.is_client_streaming() || rpc.is_server_streaming() || !options.has_extension(&http) {
+            continue;
+        }
+        let extension = options.get_extension(&http);
+        let Some(http_rule) = extension.as_message() else {
+            continue;
+        };
+
+        let field = |name: &str| {
+            http_rule
+                .get_field_by_name(name)
+                .and_then(|value| value.as_str().map(str::to_owned))
+                .unwrap_or_default()
+        };
+        let (method_filter, template) = [
+            (MethodFilter::GET, "get"),
+            (MethodFilter::PUT, "put"),
+            (MethodFilter::POST, "post"),
+            (MethodFilter::DELETE, "delete"),
+            (MethodFilter::PATCH, "patch"),
+        ]
+        .into_iter()
+        .map(|(method_filter, name)| (method_filter, field(name)))
+        .find(|(_, template)| !template.is_empty())
+        .ok_or(format!(
+            "Error, {} has no supported HTTP method",
+            rpc.name()
+        ))?;
+        let body = match field("body").as_str() {
+            "*" => true,
+            "" => false,
+            body => return Err(format!("Error, {} has unsupported body {body}", rpc.name())),
+        };
+
+        rules.push(Rule {
+            rpc,
+            method_filter,
+            template,
+            body,
+        });
+    }
+    Ok(rules)
+}
+
+impl Rule {
+    /// The template as an axum route, e.g. `/v2/attributes/:key`.
+    fn route(&self) -> String {
+        self.template
+            .split('/')
+            .map(|segment| match segment.strip_prefix('{') {
+                Some(variable) => format!(":{}", variable.trim_end_matches('}')),
+                None => segment.to_owned(),
+            })
+            .collect::<Vec<_>>()
+            .join("/")
+    }
+
+    /// Builds the request message from the JSON body, then the query string and path variables.
+    fn request(
+        &self,
+        params: &HashMap<String, String>,
+        query: Option<&str>,
+        body: &[u8],
+    ) -> Result<DynamicMessage, String> {
+        let mut message = if self.body && !body.is_empty() {
+            let mut deserializer = serde_json::Deserializer::from_slice(body);
+            DynamicMessage::deserialize(self.rpc.input(), &mut deserializer)
+                .map_err(|e| e.to_string())?
+        } else {
+            DynamicMessage::new(self.rpc.input())
+        };
+
+        if !self.body {
+            let query_params = query
+                .unwrap_or_default()
+                .split('&')
+                .filter_map(|pair| pair.split_once('='));
+            for (name, value) in query_params {
+                let value = value.replace('+', " ");
+                set_field(
+                    &mut message,
+                    name,
+                    &percent_decode_str(&value).decode_utf8_lossy(),
+                )?;
+            }
+        }
+        for (name, value) in params {
+            set_field(&mut message, name, value)?;
+        }
+        Ok(message)
+    }
+
+    async fn call(
+        self,
+        channel: AuthChannel,
+        params: HashMap<String, String>,
+        query: Option<String>,
+        headers: HeaderMap,
+        body: Bytes,
+    ) -> Response {
+        let message = match self.request(&params, query.as_deref(), &body) {
+            Ok(message) => message,
+            Err(message) => {
+                let reply = Json(ErrorReply {
+                    status: "ERROR",
+                    message,
+                });
+                return (HttpStatus::BAD_REQUEST, reply).into_response();
+            }
+        };
+
+        let mut request = tonic::Request::new(Bytes::from(message.encode_to_vec()));
+        if let Some(authorization) = headers.get(AUTHORIZATION) {
+            if let Ok(authorization) = authorization.as_bytes().try_into() {
+                request
+                    .metadata_mut()
+                    .insert("authorization", authorization);
+            }
+        }
+
+        let path = format!("/{}/{}", SERVICE_NAME, self.rpc.name());
+        let mut grpc = tonic::client::Grpc::new(channel);
+        let response = match grpc.ready().await {
+            Ok(()) => match path.parse() {
+                Ok(path) => grpc.unary(request, path, RawCodec).await,
+                Err(e) => Err(Status::internal(format!("Invalid path {path}.\n{e:?}"))),
+            },
+            Err(e) => Err(Status::unavailable(e.to_string())),
+        };
+
+        let response = match response {
+            Ok(response) => response.into_inner(),
+            Err(status) => return self.error(status),
+        };
+        match DynamicMessage::decode(self.rpc.output(), response) {
+            Ok(response) => json(&response).into_response(),
+            Err(e) => self.error(Status::internal(e.to_string())),
+        }
+    }
+
+    /// Reports a failed call as its `google.rpc.Status` in JSON, with the closest HTTP status.
+    fn error(&self, status: Status) -> Response {
+        let details = self
+            .rpc
+            .parent_pool()
+            .get_message_by_name("google.rpc.Status")
+            .and_then(|desc| DynamicMessage::decode(desc, status.details()).ok())
+            .filter(|_| !status.details().is_empty());
+        let body = match details {
+            Some(details) => json(&details),
+            None => {
+                let reply = serde_json::json!({
+                    "code": status.code() as i32,
+                    "message": status.message(),
+                });
+                serde_json::to_vec(&reply).unwrap_or_default()
+            }
+        };
+        let body = (
+            [(CONTENT_TYPE, HeaderValue::from_static("application/json"))],
+            body,
+        );
+
+        let http_status = http_status(status.code());
+        match retry_after(&status) {
+            Some(retry_after) => (http_status, [(RETRY_AFTER, retry_after)], body).into_response(),
+            None => (http_status, body).into_response(),
+        }
+    }
+}
+
+/// Sets the field `name` from a path variable or query parameter.
+fn set_field(message: &mut DynamicMessage, name: &str, value: &str) -> Result<(), String> {
+    let invalid = || format!("Invalid value for {name}");
+    let field = message
+        .descriptor()
+        .get_field_by_name(name)
+        .or_else(|| message.descriptor().get_field_by_json_name(name))
+        .ok_or(format!("Unknown field {name}"))?;
+    if field.is_list() || field.is_map() {
+        return Err(format!("{name} can't be set from the URL"));
+    }
+
+    let value = match field.kind() {
+        Kind::String => Value::String(value.to_owned()),
+        Kind::Bool => Value::Bool(value.parse().map_err(|_| invalid())?),
+        Kind::Int32 | Kind::Sint32 | Kind::Sfixed32 => {
+            Value::I32(value.parse().map_err(|_| invalid())?)
+        }
+        Kind::Int64 | Kind::Sint64 | Kind::Sfixed64 => {
+            Value::I64(value.parse().map_err(|_| invalid())?)
+        }
+        Kind::Uint32 | Kind::Fixed32 => Value::U32(value.parse().map_err(|_| invalid())?),
+        Kind::Uint64 | Kind::Fixed64 => Value::U64(value.parse().map_err(|_| invalid())?),
+        Kind::Enum(enum_desc) => {
+            let number = match enum_desc.get_value_by_name(value) {
+                Some(enum_value) => enum_value.number(),
+                None => value.parse().map_err(|_| invalid())?,
+            };
+            Value::EnumNumber(number)
+        }
+        _ => return Err(format!("{name} can't be set from the URL")),
+    };
+    message.set_field(&field, value);
+    Ok(())
+}
+
+fn json(message: &DynamicMessage) -> Vec<u8> {
+    let mut serializer = serde_json::Serializer::new(Vec::new());
+    match serde::Serialize::serialize(message, &mut serializer) {
+        Ok(()) => serializer.into_inner(),
+        Err(_) => Vec::new(),
+    }
+}
+
+/// Passes encoded messages through as they are.
+struct RawCodec;
+
+impl Codec for RawCodec {
+    type Encode = Bytes;
+    type Decode = Bytes;
+    type Encoder = RawCodec;
+    type Decoder = RawCodec;
+
+    fn encoder(&mut self) -> Self::Encoder {
+        RawCodec
+    }
+
+    fn decoder(&mut self) -> Self::Decoder {
+        RawCodec
+    }
+}
+
+impl Encoder for RawCodec {
+    type Item = Bytes;
+    type Error = Status;
+
+    fn encode(&mut self, item: Bytes, dst: &mut EncodeBuf<'_>) -> Result<(), Status> {
+        dst.put(item);
+        Ok(())
+    }
+}
+
+impl Decoder for RawCodec {
+    type Item = Bytes;
+    type Error = Status;
+
+    fn decode(&mut self, src: &mut DecodeBuf<'_>) -> Result<Option<Bytes>, Status> {
+        Ok(Some(src.copy_to_bytes(src.remaining())))
+    }
+}
+
+/// Descriptors of the auth protos, including their HTTP rules.
+const FILE_DESCRIPTOR_SET: &[u8] = tonic::include_file_descriptor_set!("authentication_descriptor");
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rule(rpc: &str) -> Rule {
+        rules()
+            .unwrap()
+            .into_iter()
+            .find(|rule| rule.rpc.name() == rpc)
+            .unwrap()
+    }
+
+    #[test]
+    fn should_serve_every_unary_rpc() {
+        let rules = rules().unwrap();
+        let pool = DescriptorPool::decode(FILE_DESCRIPTOR_SET).unwrap();
+        let service = pool.get_service_by_name(SERVICE_NAME).unwrap();
+        let unary = service
+            .methods()
+            .filter(|rpc| !rpc.is_server_streaming())
+            .count();
+        assert_eq!(rules.len(), unary);
+
+        let mut routes: Vec<_> = rules
+            .iter()
+            .map(|rule| (rule.route(), format!("{:?}", rule.method_filter)))
+            .collect();
+        routes.sort();
+        routes.dedup();
+        assert_eq!(routes.len(), rules.len());
+    }
+
+    #[test]
+    fn should_read_path_variables() {
+        let rule = rule("SetUserAttribute");
+        assert_eq!(rule.route(), "/v2/attributes/:key");
+
+        let params = HashMap::from([("key".to_owned(), "theme".to_owned())]);
+        let body = br#"{"sessionToken": "1234", "value": "dark"}"#;
+        let message = rule.request(&params, None, body).unwrap();
+        assert_eq!(
+            message.get_field_by_name("key").unwrap().as_str(),
+            Some("theme")
+        );
+        assert_eq!(
+            message.get_field_by_name("sessionToken").unwrap().as_str(),
+            Some("1234")
+        );
+    }
+
+    #[test]
+    fn should_read_query_parameters() {
+        let rule = rule("GetLoginHistory");
+        let query = "sessionToken=ab%20cd&pageSize=10";
+        let message = rule.request(&HashMap::new(), Some(query), b"").unwrap();
+        assert_eq!(
+            message.get_field_by_name("sessionToken").unwrap().as_str(),
+            Some("ab cd")
+        );
+        assert_eq!(
+            message.get_field_by_name("pageSize").unwrap().as_u32(),
+            Some(10)
+        );
+
+        assert!(rule
+            .request(&HashMap::new(), Some("pageSize=ten"), b"")
+            .is_err());
+    }
+
+    #[test]
+    fn should_read_json_body() {
+        let rule = rule("SignIn");
+        let body = br#"{"username": "alice", "password": "secret"}"#;
+        let message = rule.request(&HashMap::new(), None, body).unwrap();
+        assert_eq!(
+            message.get_field_by_name("username").unwrap().as_str(),
+            Some("alice")
+        );
+
+        assert!(rule
+            .request(&HashMap::new(), None, br#"{"user": "alice"}"#)
+            .is_err());
+    }
+}
@@ -1,80 +1,133 @@
 use std::env;
+use std::net::SocketAddr;
+use std::sync::Arc;
 
-use authentication::auth_client::AuthClient;
-use authentication::{SignInRequest, SignOutRequest, SignUpRequest};
-use tokio::time::{sleep, Duration};
-use tonic::{Request, Response};
-use uuid::Uuid;
+use auth_client::AuthClient;
+use tokio::time::{interval, timeout, Duration, MissedTickBehavior};
+use tonic::transport::{Endpoint, Server};
 
-use crate::authentication::{SignInResponse, SignOutResponse, SignUpResponse, StatusCode};
+use crate::health::HealthServer;
+use crate::logging::LogFormat;
+use crate::monitor::Monitor;
+use crate::probes::{AuthProbe, GrpcHealthProbe, Probe};
+use crate::settings::{Settings, DEFAULT_AUTH_SERVICE_PORT};
 
-pub mod authentication {
-    tonic::include_proto!("authentication");
-}
-
-#[tokio::main]
-async fn main() -> Result<(), Box<dyn std::error::Error>> {
-    // AUTH_SERVICE_HOST_NAME will be set to 'auth' when running the health check service in Docker
-    // ::0 is required for Docker to work: https://stackoverflow.com/questions/59179831/docker-app-server-ip-address-127-0-0-1-difference-of-0-0-0-0-ip
-    let auth_hostname = env::var("AUTH_SERVICE_HOST_NAME").unwrap_or("[::0]".to_owned());
+#[path = "../health.rs"]
+mod health;
+#[path = "../logging.rs"]
+mod logging;
+mod monitor;
+mod probes;
+#[path = "../settings.rs"]
+mod settings;
 
-    // Establish connection when auth service
-    let mut client = AuthClient::connect(format!("http://{}:50051", auth_hostname)).await?;
+/// How long to wait between checks by default.
+const DEFAULT_CHECK_INTERVAL_SECS: u64 = 3;
 
-    loop {
-        let username: String = Uuid::new_v4().to_string(); // Create random username using new_v4()
-        let password: String = Uuid::new_v4().to_string(); // Create random password using new_v4()
+/// How many checks in a row a dependency has to fail to be reported down by default.
+const DEFAULT_FAILURE_THRESHOLD: u32 = 3;
 
-        // Create a new `SignUpRequest`.
-        let request: Request<SignUpRequest> = Request::new(SignUpRequest {
-            username: username.clone(),
-            password: password.clone(),
-        });
-
-        // Make a sign up request. Propagate any errors.
-        let response: Response<SignUpResponse> =
-            Response::new(client.sign_up(request).await?.into_inner());
-
-        // Log the response
-        println!(
-            "SIGN UP RESPONSE STATUS: {:?}",
-            StatusCode::from_i32(response.into_inner().status_code)
-        );
-
-        // ---------------------------------------------
-
-        // Create a new `SignInRequest`.
-        let request: Request<SignInRequest> = Request::new(SignInRequest {
-            username: username.clone(),
-            password: password.clone(),
-        });
+/// How long a single check may take before it counts as failed.
+const PROBE_TIMEOUT: Duration = Duration::from_secs(10);
 
-        // Make a sign in request. Propagate any errors. Convert Response<SignInResponse> into SignInResponse.
-        let response: SignInResponse = client.sign_in(request).await?.into_inner();
-
-        println!(
-            "SIGN IN RESPONSE STATUS: {:?}",
-            // Log response status_code
-            StatusCode::from_i32(response.status_code)
-        );
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    // Flags override environment variables, which override the CONFIG_FILE
+    let settings = Settings::load(env::args().skip(1))?;
+    logging::init(
+        &settings
+            .var("LOG_LEVEL")
+            .unwrap_or(logging::DEFAULT_LOG_LEVEL.to_owned()),
+        match settings.var("LOG_FORMAT") {
+            Some(format) => format.parse()?,
+            None => LogFormat::Text,
+        },
+        Vec::new(),
+    )?;
 
-        // ---------------------------------------------
+    // AUTH_SERVICE_HOST_NAME will be set to 'auth' when running the health check service in Docker
+    // ::0 is required for Docker to work: https://stackoverflow.com/questions/59179831/docker-app-server-ip-address-127-0-0-1-difference-of-0-0-0-0-ip
+    let auth_hostname = settings
+        .var("AUTH_SERVICE_HOST_NAME")
+        .unwrap_or("[::0]".to_owned());
+    let auth_port: u16 = match settings.var("AUTH_SERVICE_PORT") {
+        Some(port) => port.parse()?,
+        None => DEFAULT_AUTH_SERVICE_PORT,
+    };
+    let check_interval = match settings.var("CHECK_INTERVAL_SECS") {
+        Some(secs) => Duration::from_secs(secs.parse()?),
+        None => Duration::from_secs(DEFAULT_CHECK_INTERVAL_SECS),
+    };
+    let failure_threshold: u32 = match settings.var("FAILURE_THRESHOLD") {
+        Some(threshold) => threshold.parse()?,
+        None => DEFAULT_FAILURE_THRESHOLD,
+    };
+    // Other services implementing the standard health protocol, e.g. `billing=http://billing:50051`
+    let targets = probes::parse_targets(&settings.var("GRPC_HEALTH_TARGETS").unwrap_or_default())?;
+    let grpc_address: SocketAddr = settings
+        .var("HEALTH_GRPC_ADDRESS")
+        .unwrap_or("[::0]:50052".to_owned())
+        .parse()?;
+    let http_address: SocketAddr = settings
+        .var("HEALTH_HTTP_ADDRESS")
+        .unwrap_or("[::0]:8081".to_owned())
+        .parse()?;
+
+    // Connect lazily, so dependencies that aren't up yet are reported down rather than stopping
+    // the service.
+    let auth_client = AuthClient::connect_lazy(format!("http://{}:{}", auth_hostname, auth_port))?;
+    let mut probes: Vec<Box<dyn Probe>> = vec![Box::new(AuthProbe::new(auth_client))];
+    for (name, url) in targets {
+        let channel = Endpoint::from_shared(url)?.connect_lazy();
+        probes.push(Box::new(GrpcHealthProbe::new(name, channel)));
+    }
 
-        // Create a new `SignOutRequest`.
-        let request: Request<SignOutRequest> = Request::new(SignOutRequest {
-            session_token: response.session_token,
+    let (health_reporter, health_service) = health::health_service();
+    let monitor = Arc::new(Monitor::new(
+        probes.iter().map(|probe| probe.name()),
+        failure_threshold,
+        health_reporter,
+    ));
+
+    // Probe each dependency on its own, so a slow one doesn't delay the others.
+    for mut probe in probes {
+        let monitor = monitor.clone();
+        tokio::spawn(async move {
+            let mut ticks = interval(check_interval);
+            ticks.set_missed_tick_behavior(MissedTickBehavior::Delay);
+            loop {
+                ticks.tick().await;
+                let result = match timeout(PROBE_TIMEOUT, probe.probe()).await {
+                    Ok(result) => result,
+                    Err(_) => Err(format!("Timed out after {:?}", PROBE_TIMEOUT)),
+                };
+                monitor.record(probe.name(), result);
+            }
         });
-
-        let response: Response<SignOutResponse> = client.sign_out(request).await?;
-
-        println!(
-            "SIGN OUT RESPONSE STATUS: {:?}",
-            // Log response status_code
-            StatusCode::from_i32(response.into_inner().status_code)
-        );
-
-        println!("--------------------------------------",);
-
-        sleep(Duration::from_secs(3)).await;
     }
+
+    tracing::info!(
+        grpc_address = %grpc_address,
+        http_address = %http_address,
+        "Serving dependency health"
+    );
+
+    let http_server = monitor::serve_http(http_address, monitor.clone())?;
+    let grpc_server = Server::builder()
+        .add_service(HealthServer::new(health_service))
+        .serve(grpc_address);
+    tokio::try_join!(
+        async {
+            http_server
+                .await
+                .map_err(Box::<dyn std::error::Error>::from)
+        },
+        async {
+            grpc_server
+                .await
+                .map_err(Box::<dyn std::error::Error>::from)
+        },
+    )?;
+
+    Ok(())
 }
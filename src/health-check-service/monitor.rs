@@ -0,0 +1,243 @@
+use std::collections::BTreeMap;
+use std::convert::Infallible;
+use std::fmt::Write;
+use std::future::Future;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{Body, Method, Response, Server};
+
+use crate::health::{HealthReporter, ServingStatus};
+
+/// What's known about a dependency from its probes so far.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct DependencyHealth {
+    /// Probes that failed in a row, reset by a successful one.
+    pub consecutive_failures: u32,
+    pub last_error: Option<String>,
+    pub last_checked: Option<SystemTime>,
+}
+
+/// Tracks probe results of each dependency, and reports them and the aggregate status over the
+/// gRPC health protocol. The empty service name stands for all dependencies together.
+pub struct Monitor {
+    failure_threshold: u32,
+    dependencies: Mutex<BTreeMap<String, DependencyHealth>>,
+    health_reporter: HealthReporter,
+}
+
+impl Monitor {
+    /// Monitors the dependencies called `names`, each down after `failure_threshold` failed
+    /// probes in a row.
+    pub fn new<'a>(
+        names: impl IntoIterator<Item = &'a str>,
+        failure_threshold: u32,
+        health_reporter: HealthReporter,
+    ) -> Self {
+        let monitor = Self {
+            failure_threshold,
+            dependencies: Mutex::new(
+                names
+                    .into_iter()
+                    .map(|name| (name.to_owned(), DependencyHealth::default()))
+                    .collect(),
+            ),
+            health_reporter,
+        };
+        monitor.report_statuses(&monitor.dependencies.lock().unwrap());
+        monitor
+    }
+
+    /// Records the result of probing the dependency called `name`.
+    pub fn record(&self, name: &str, result: Result<(), String>) {
+        let mut dependencies = self.dependencies.lock().unwrap();
+        let dependency = dependencies.entry(name.to_owned()).or_default();
+        dependency.last_checked = Some(SystemTime::now());
+        match result {
+            Ok(()) => {
+                dependency.consecutive_failures = 0;
+                dependency.last_error = None;
+            }
+            Err(e) => {
+                dependency.consecutive_failures += 1;
+                tracing::warn!(
+                    dependency = name,
+                    consecutive_failures = dependency.consecutive_failures,
+                    error = e,
+                    "Probe failed"
+                );
+                dependency.last_error = Some(e);
+            }
+        }
+        self.report_statuses(&dependencies);
+    }
+
+    /// Unknown until probed, then down once it failed `failure_threshold` probes in a row.
+    fn status(&self, dependency: &DependencyHealth) -> ServingStatus {
+        match dependency.last_checked {
+            None => ServingStatus::Unknown,
+            Some(_) if dependency.consecutive_failures >= self.failure_threshold => {
+                ServingStatus::NotServing
+            }
+            Some(_) => ServingStatus::Serving,
+        }
+    }
+
+    /// Not serving if any dependency is down, unknown until each was probed.
+    fn aggregate_status(&self, dependencies: &BTreeMap<String, DependencyHealth>) -> ServingStatus {
+        let statuses: Vec<ServingStatus> = dependencies
+            .values()
+            .map(|dependency| self.status(dependency))
+            .collect();
+
+        if statuses.contains(&ServingStatus::NotServing) {
+            ServingStatus::NotServing
+        } else if statuses.contains(&ServingStatus::Unknown) {
+            ServingStatus::Unknown
+        } else {
+            ServingStatus::Serving
+        }
+    }
+
+    fn report_statuses(&self, dependencies: &BTreeMap<String, DependencyHealth>) {
+        for (name, dependency) in dependencies {
+            if self
+                .health_reporter
+                .set_status(name, self.status(dependency))
+            {
+                tracing::info!(
+                    dependency = name,
+                    status = self.status(dependency).as_str_name(),
+                    "Dependency status changed"
+                );
+            }
+        }
+        self.health_reporter
+            .set_status("", self.aggregate_status(dependencies));
+    }
+
+    /// Whether all dependencies are up, and the aggregate and each dependency's status as JSON.
+    pub fn report(&self) -> (bool, String) {
+        let dependencies = self.dependencies.lock().unwrap();
+        let aggregate_status = self.aggregate_status(&dependencies);
+
+        let mut json = format!(
+            "{{\"status\":\"{}\",\"dependencies\":[",
+            aggregate_status.as_str_name()
+        );
+        for (i, (name, dependency)) in dependencies.iter().enumerate() {
+            if i > 0 {
+                json.push(',');
+            }
+            let _ = write!(
+                json,
+                "{{\"name\":{},\"status\":\"{}\",\"consecutiveFailures\":{}",
+                json_string(name),
+                self.status(dependency).as_str_name(),
+                dependency.consecutive_failures
+            );
+            if let Some(last_checked) = dependency.last_checked {
+                let last_checked = last_checked
+                    .duration_since(UNIX_EPOCH)
+                    .unwrap_or_default()
+                    .as_secs();
+                let _ = write!(json, ",\"lastChecked\":{}", last_checked);
+            }
+            if let Some(last_error) = &dependency.last_error {
+                let _ = write!(json, ",\"lastError\":{}", json_string(last_error));
+            }
+            json.push('}');
+        }
+        json.push_str("]}");
+
+        (aggregate_status == ServingStatus::Serving, json)
+    }
+}
+
+/// Quotes `value` as a JSON string.
+fn json_string(value: &str) -> String {
+    let mut quoted = String::with_capacity(value.len() + 2);
+    quoted.push('"');
+    for c in value.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            c if c.is_control() => {
+                let _ = write!(quoted, "\\u{:04x}", c as u32);
+            }
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+/// Serves the monitor's report at `GET /health` on `address` over plain HTTP, with status 200
+/// while all dependencies are up and 503 otherwise. Fails right away if the address can't be
+/// bound.
+pub fn serve_http(
+    address: SocketAddr,
+    monitor: Arc<Monitor>,
+) -> Result<impl Future<Output = Result<(), hyper::Error>>, String> {
+    let make_service = make_service_fn(move |_| {
+        let monitor = monitor.clone();
+        async move {
+            Ok::<_, Infallible>(service_fn(move |request| {
+                let response = match (request.method(), request.uri().path()) {
+                    (&Method::GET, "/health") => {
+                        let (healthy, json) = monitor.report();
+                        Response::builder()
+                            .status(match healthy {
+                                true => 200,
+                                false => 503,
+                            })
+                            .header("content-type", "application/json")
+                            .body(Body::from(json))
+                    }
+                    _ => Response::builder().status(404).body(Body::empty()),
+                };
+                std::future::ready(response)
+            }))
+        }
+    });
+
+    Ok(Server::try_bind(&address)
+        .map_err(|e| format!("Failed to listen on HEALTH_HTTP_ADDRESS {address}.\n{e:?}"))?
+        .serve(make_service))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::health::health_service;
+
+    #[test]
+    fn should_report_dependency_down_after_consecutive_failures() {
+        let (health_reporter, _health_service) = health_service();
+        let monitor = Monitor::new(["auth", "billing"], 2, health_reporter);
+        assert!(monitor.report().1.starts_with("{\"status\":\"UNKNOWN\""));
+
+        monitor.record("auth", Ok(()));
+        monitor.record("billing", Err("Check returned NOT_SERVING".to_owned()));
+        assert!(monitor.report().0);
+
+        monitor.record("billing", Err("Check returned NOT_SERVING".to_owned()));
+        let (healthy, json) = monitor.report();
+        assert!(!healthy);
+        assert!(json.starts_with("{\"status\":\"NOT_SERVING\""));
+        assert!(json.contains(
+            "{\"name\":\"billing\",\"status\":\"NOT_SERVING\",\"consecutiveFailures\":2,"
+        ));
+        assert!(json.contains("\"lastError\":\"Check returned NOT_SERVING\"}"));
+
+        monitor.record("billing", Ok(()));
+        let (healthy, json) = monitor.report();
+        assert!(healthy);
+        assert!(json
+            .contains("{\"name\":\"billing\",\"status\":\"SERVING\",\"consecutiveFailures\":0,"));
+        assert!(!json.contains("lastError"));
+    }
+}
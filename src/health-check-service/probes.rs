@@ -0,0 +1,145 @@
+use auth_client::AuthClient;
+use tonic::transport::Channel;
+use tonic::{Request, Status};
+use uuid::Uuid;
+
+use crate::health::grpc_health::health_client::HealthClient;
+use crate::health::grpc_health::HealthCheckRequest;
+use crate::health::ServingStatus;
+
+/// Checks whether a dependency works, once per call.
+#[tonic::async_trait]
+pub trait Probe: Send {
+    /// Name the dependency is reported under, e.g. `auth`.
+    fn name(&self) -> &str;
+    async fn probe(&mut self) -> Result<(), String>;
+}
+
+/// Signs up a random user, then signs in, validates the session and signs out, so the whole
+/// auth flow is checked rather than just the server being up.
+pub struct AuthProbe {
+    client: AuthClient,
+}
+
+impl AuthProbe {
+    pub fn new(client: AuthClient) -> Self {
+        Self { client }
+    }
+}
+
+#[tonic::async_trait]
+impl Probe for AuthProbe {
+    fn name(&self) -> &str {
+        "auth"
+    }
+
+    async fn probe(&mut self) -> Result<(), String> {
+        let username = Uuid::new_v4().to_string();
+        let password = Uuid::new_v4().to_string();
+
+        self.client
+            .sign_up(&username, &password, "")
+            .await
+            .map_err(|e| format!("SignUp failed: {}", e))?;
+        let session = self
+            .client
+            .sign_in(&username, &password)
+            .await
+            .map_err(|e| format!("SignIn failed: {}", e))?;
+        self.client
+            .validate_session(&session.session_token)
+            .await
+            .map_err(|e| format!("ValidateSession failed: {}", e))?;
+        self.client
+            .sign_out(&session.session_token)
+            .await
+            .map_err(|e| format!("SignOut failed: {}", e))
+    }
+}
+
+/// Asks a server implementing the standard `grpc.health.v1.Health` protocol whether it's serving
+/// as a whole.
+pub struct GrpcHealthProbe {
+    name: String,
+    client: HealthClient<Channel>,
+}
+
+impl GrpcHealthProbe {
+    pub fn new(name: String, channel: Channel) -> Self {
+        Self {
+            name,
+            client: HealthClient::new(channel),
+        }
+    }
+}
+
+#[tonic::async_trait]
+impl Probe for GrpcHealthProbe {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    async fn probe(&mut self) -> Result<(), String> {
+        let response = self
+            .client
+            .check(Request::new(HealthCheckRequest {
+                service: String::new(),
+            }))
+            .await
+            .map_err(|e| failed("Check", e))?
+            .into_inner();
+
+        match response.status() {
+            ServingStatus::Serving => Ok(()),
+            status => Err(format!("Check returned {}", status.as_str_name())),
+        }
+    }
+}
+
+/// Parses `GRPC_HEALTH_TARGETS`, comma separated `name=url` pairs, e.g.
+/// `billing=http://billing:50051`.
+pub fn parse_targets(targets: &str) -> Result<Vec<(String, String)>, String> {
+    targets
+        .split(',')
+        .map(str::trim)
+        .filter(|target| !target.is_empty())
+        .map(|target| match target.split_once('=') {
+            Some((name, url)) if !name.trim().is_empty() && !url.trim().is_empty() => {
+                Ok((name.trim().to_owned(), url.trim().to_owned()))
+            }
+            _ => Err(format!(
+                "Error, GRPC_HEALTH_TARGETS has an invalid target: {}",
+                target
+            )),
+        })
+        .collect()
+}
+
+fn failed(rpc: &str, status: Status) -> String {
+    format!(
+        "{} failed with {:?}: {}",
+        rpc,
+        status.code(),
+        status.message()
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn should_parse_targets() {
+        assert_eq!(
+            parse_targets("billing=http://billing:50051, search = http://search:50051").unwrap(),
+            [
+                ("billing".to_owned(), "http://billing:50051".to_owned()),
+                ("search".to_owned(), "http://search:50051".to_owned()),
+            ]
+        );
+        assert!(parse_targets("").unwrap().is_empty());
+
+        assert!(parse_targets("http://billing:50051").is_err());
+        assert!(parse_targets("billing=").is_err());
+    }
+}
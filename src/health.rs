@@ -0,0 +1,163 @@
+use std::collections::HashMap;
+use std::pin::Pin;
+use std::sync::Arc;
+
+use tokio::sync::watch;
+use tokio_stream::wrappers::WatchStream;
+use tokio_stream::{Stream, StreamExt};
+use tonic::{Request, Response, Status};
+
+use grpc_health::health_server::Health;
+use grpc_health::{HealthCheckRequest, HealthCheckResponse};
+
+pub use grpc_health::health_check_response::ServingStatus;
+pub use grpc_health::health_server::HealthServer;
+
+pub mod grpc_health {
+    tonic::include_proto!("grpc.health.v1");
+}
+
+/// Sets the statuses reported by the `HealthService` it was created with. The empty service name
+/// stands for the server as a whole.
+#[derive(Clone)]
+pub struct HealthReporter {
+    statuses: Arc<watch::Sender<HashMap<String, ServingStatus>>>,
+}
+
+impl HealthReporter {
+    /// Returns whether the status changed.
+    pub fn set_status(&self, service: &str, status: ServingStatus) -> bool {
+        self.statuses.send_if_modified(|statuses| {
+            statuses.insert(service.to_owned(), status) != Some(status)
+        })
+    }
+}
+
+/// Serves the standard `grpc.health.v1.Health` protocol, so Kubernetes and load balancers can
+/// probe the server natively.
+pub struct HealthService {
+    statuses: watch::Receiver<HashMap<String, ServingStatus>>,
+}
+
+/// Creates a health service along with the reporter setting its statuses. Services are unknown
+/// until a status is set for them.
+pub fn health_service() -> (HealthReporter, HealthService) {
+    let (sender, receiver) = watch::channel(HashMap::new());
+
+    (
+        HealthReporter {
+            statuses: Arc::new(sender),
+        },
+        HealthService { statuses: receiver },
+    )
+}
+
+#[tonic::async_trait]
+impl Health for HealthService {
+    async fn check(
+        &self,
+        request: Request<HealthCheckRequest>,
+    ) -> Result<Response<HealthCheckResponse>, Status> {
+        let service = request.into_inner().service;
+
+        let status = self
+            .statuses
+            .borrow()
+            .get(&service)
+            .copied()
+            .ok_or_else(|| Status::not_found(format!("Unknown service: {}", service)))?;
+
+        Ok(Response::new(HealthCheckResponse {
+            status: status.into(),
+        }))
+    }
+
+    type WatchStream = Pin<Box<dyn Stream<Item = Result<HealthCheckResponse, Status>> + Send>>;
+
+    async fn watch(
+        &self,
+        request: Request<HealthCheckRequest>,
+    ) -> Result<Response<Self::WatchStream>, Status> {
+        let service = request.into_inner().service;
+
+        // Starts with the current status, then sends it again whenever it changes.
+        let mut last_status = None;
+        let stream = WatchStream::new(self.statuses.clone()).filter_map(move |statuses| {
+            let status = statuses
+                .get(&service)
+                .copied()
+                .unwrap_or(ServingStatus::ServiceUnknown);
+
+            (last_status.replace(status) != Some(status)).then_some(Ok(HealthCheckResponse {
+                status: status.into(),
+            }))
+        });
+
+        Ok(Response::new(Box::pin(stream) as Self::WatchStream))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request(service: &str) -> Request<HealthCheckRequest> {
+        Request::new(HealthCheckRequest {
+            service: service.to_owned(),
+        })
+    }
+
+    #[tokio::test]
+    async fn check_should_report_status() {
+        let (health_reporter, health_service) = health_service();
+        health_reporter.set_status("authentication.Auth", ServingStatus::NotServing);
+
+        let response = health_service
+            .check(request("authentication.Auth"))
+            .await
+            .unwrap()
+            .into_inner();
+        assert_eq!(response.status(), ServingStatus::NotServing);
+
+        health_reporter.set_status("authentication.Auth", ServingStatus::Serving);
+        let response = health_service
+            .check(request("authentication.Auth"))
+            .await
+            .unwrap()
+            .into_inner();
+        assert_eq!(response.status(), ServingStatus::Serving);
+    }
+
+    #[tokio::test]
+    async fn check_should_fail_for_unknown_service() {
+        let (_health_reporter, health_service) = health_service();
+
+        let status = health_service.check(request("unknown")).await.unwrap_err();
+
+        assert_eq!(status.code(), tonic::Code::NotFound);
+    }
+
+    #[tokio::test]
+    async fn watch_should_send_status_changes() {
+        let (health_reporter, health_service) = health_service();
+
+        let mut stream = health_service
+            .watch(request("authentication.Auth"))
+            .await
+            .unwrap()
+            .into_inner();
+        let status = stream.next().await.unwrap().unwrap().status();
+        assert_eq!(status, ServingStatus::ServiceUnknown);
+
+        health_reporter.set_status("authentication.Auth", ServingStatus::Serving);
+        let status = stream.next().await.unwrap().unwrap().status();
+        assert_eq!(status, ServingStatus::Serving);
+
+        // Unchanged statuses and other services aren't sent.
+        assert!(!health_reporter.set_status("authentication.Auth", ServingStatus::Serving));
+        health_reporter.set_status("", ServingStatus::Serving);
+        health_reporter.set_status("authentication.Auth", ServingStatus::NotServing);
+        let status = stream.next().await.unwrap().unwrap().status();
+        assert_eq!(status, ServingStatus::NotServing);
+    }
+}
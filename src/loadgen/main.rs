@@ -0,0 +1,206 @@
+use std::time::{Duration, Instant};
+
+use auth_client::{AuthClient, Session};
+use clap::Parser;
+use rand_core::{OsRng, RngCore};
+use tokio::task::JoinSet;
+
+use crate::mix::{Mix, Operation};
+use crate::settings::{Settings, DEFAULT_AUTH_SERVICE_PORT};
+use crate::stats::Stats;
+
+mod mix;
+#[path = "../settings.rs"]
+mod settings;
+mod stats;
+
+/// Drives a mix of sign-in, sign-up and validate calls against the auth service and reports each
+/// operation's throughput and latency percentiles, to size hashing costs and instance counts.
+/// Signs up its own users to sign in as, which are left behind.
+#[derive(Parser)]
+#[command(version, about, long_about = None)]
+struct Cli {
+    /// TOML file with settings, overridden by environment variables
+    #[arg(long)]
+    config_file: Option<String>,
+    /// Host of the auth service, overrides AUTH_SERVICE_IP
+    #[arg(long)]
+    auth_service_ip: Option<String>,
+    /// Port of the auth service, overrides AUTH_SERVICE_PORT
+    #[arg(long)]
+    auth_service_port: Option<u16>,
+    /// Relative weights of the operations, as comma separated operation=weight pairs
+    #[arg(short, long, default_value = "sign-in=70,validate=25,sign-up=5", value_parser = Mix::parse)]
+    mix: Mix,
+    /// Calls in flight at once
+    #[arg(short, long, default_value_t = 16)]
+    concurrency: usize,
+    /// Seconds to generate load for
+    #[arg(short, long, default_value_t = 30)]
+    duration: u64,
+    /// Connections to spread the calls over
+    #[arg(long, default_value_t = 4)]
+    connections: usize,
+    /// Users signed up beforehand to sign in as and validate sessions of. The auth service rate
+    /// limits sign-ins per username, raise this or SIGN_IN_RATE_LIMIT_* when sign-ins fail
+    #[arg(long, default_value_t = 20)]
+    users: usize,
+}
+
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let cli = Cli::parse();
+
+    // Flags override environment variables, which override the CONFIG_FILE
+    let flags = [
+        ("--config-file", cli.config_file.clone()),
+        ("--auth-service-ip", cli.auth_service_ip.clone()),
+        (
+            "--auth-service-port",
+            cli.auth_service_port.map(|port| port.to_string()),
+        ),
+    ]
+    .into_iter()
+    .filter_map(|(flag, value)| Some([flag.to_owned(), value?]))
+    .flatten();
+    let settings = Settings::load(flags)?;
+
+    let auth_ip = settings
+        .var("AUTH_SERVICE_IP")
+        .unwrap_or("[::0]".to_owned());
+    let auth_port: u16 = match settings.var("AUTH_SERVICE_PORT") {
+        Some(port) => port.parse()?,
+        None => DEFAULT_AUTH_SERVICE_PORT,
+    };
+    let endpoint = format!("http://{}:{}", auth_ip, auth_port);
+
+    let mut clients = Vec::new();
+    for _ in 0..cli.connections.max(1) {
+        clients.push(
+            AuthClient::connect(endpoint.clone())
+                .await
+                .map_err(|e| format!("Failed to connect to {endpoint}.\n{e}"))?,
+        );
+    }
+
+    // Each run signs up its own users so they don't collide with earlier runs'
+    let run_id = uuid::Uuid::new_v4().simple().to_string()[..8].to_owned();
+    let users = if cli.mix.includes(Operation::SignIn) || cli.mix.includes(Operation::Validate) {
+        cli.users.max(1)
+    } else {
+        0
+    };
+    let mut pool = Vec::new();
+    for index in 0..users {
+        let user = LoadUser::new(&run_id, &format!("user{index}"));
+        clients[0]
+            .sign_up(&user.username, &user.password, "")
+            .await
+            .map_err(|e| format!("Failed to sign up {}.\n{e}", user.username))?;
+        let session = clients[0]
+            .sign_in(&user.username, &user.password)
+            .await
+            .map_err(|e| format!("Failed to sign in {}.\n{e}", user.username))?;
+        pool.push((user, session));
+    }
+    eprintln!(
+        "Generating load against {endpoint} for {}s with {} calls in flight",
+        cli.duration, cli.concurrency
+    );
+
+    let started = Instant::now();
+    let deadline = started + Duration::from_secs(cli.duration);
+    let mut workers = JoinSet::new();
+    for worker in 0..cli.concurrency.max(1) {
+        let generator = Generator {
+            client: clients[worker % clients.len()].clone(),
+            mix: cli.mix.clone(),
+            pool: pool.clone(),
+            run_id: run_id.clone(),
+            worker,
+        };
+        workers.spawn(generator.run(deadline));
+    }
+
+    let mut stats = Stats::default();
+    while let Some(worker_stats) = workers.join_next().await {
+        stats.merge(worker_stats?);
+    }
+    print!("{}", stats.report(started.elapsed()));
+
+    // Leave the pool's sessions to expire on their own if this fails
+    for (_, session) in &pool {
+        let _ = clients[0].sign_out(&session.session_token).await;
+    }
+    Ok(())
+}
+
+#[derive(Clone)]
+struct LoadUser {
+    username: String,
+    password: String,
+}
+
+impl LoadUser {
+    fn new(run_id: &str, name: &str) -> Self {
+        Self {
+            username: format!("loadgen-{run_id}-{name}"),
+            password: format!("Loadgen-{}!", uuid::Uuid::new_v4().simple()),
+        }
+    }
+}
+
+/// One of the calls in flight, making them one after another until the deadline.
+struct Generator {
+    client: AuthClient,
+    mix: Mix,
+    pool: Vec<(LoadUser, Session)>,
+    run_id: String,
+    worker: usize,
+}
+
+impl Generator {
+    async fn run(self, deadline: Instant) -> Stats {
+        let mut stats = Stats::default();
+        let mut sign_ups = 0;
+        while Instant::now() < deadline {
+            let operation = self.mix.pick(OsRng.next_u32());
+            let started = Instant::now();
+            let result = match operation {
+                Operation::SignIn => {
+                    let (user, _) = self.pooled();
+                    match self.client.sign_in(&user.username, &user.password).await {
+                        // Signing out again keeps users under the session limit, and isn't timed
+                        Ok(session) => {
+                            let latency = started.elapsed();
+                            let _ = self.client.sign_out(&session.session_token).await;
+                            Ok(latency)
+                        }
+                        Err(_) => Err(()),
+                    }
+                }
+                Operation::SignUp => {
+                    sign_ups += 1;
+                    let user = LoadUser::new(&self.run_id, &format!("w{}-{sign_ups}", self.worker));
+                    let result = self
+                        .client
+                        .sign_up(&user.username, &user.password, "")
+                        .await;
+                    result.map(|()| started.elapsed()).map_err(|_| ())
+                }
+                Operation::Validate => {
+                    let (_, session) = self.pooled();
+                    let result = self.client.validate_session(&session.session_token).await;
+                    result.map(|_| started.elapsed()).map_err(|_| ())
+                }
+            };
+            stats.record(operation, result);
+        }
+        stats
+    }
+
+    /// A random user of the pool, which isn't empty when the mix signs in or validates.
+    fn pooled(&self) -> &(LoadUser, Session) {
+        &self.pool[OsRng.next_u32() as usize % self.pool.len()]
+    }
+}
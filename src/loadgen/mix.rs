@@ -0,0 +1,116 @@
+use std::fmt;
+
+/// A call the load generator makes.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Operation {
+    SignIn,
+    SignUp,
+    Validate,
+}
+
+impl Operation {
+    const ALL: [Operation; 3] = [Operation::SignIn, Operation::SignUp, Operation::Validate];
+
+    fn name(self) -> &'static str {
+        match self {
+            Operation::SignIn => "sign-in",
+            Operation::SignUp => "sign-up",
+            Operation::Validate => "validate",
+        }
+    }
+}
+
+impl fmt::Display for Operation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+/// How often each operation is picked, relative to the others.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Mix {
+    weights: Vec<(Operation, u32)>,
+    total: u32,
+}
+
+impl Mix {
+    /// Parses comma separated `operation=weight` pairs, e.g. `sign-in=70,validate=25,sign-up=5`.
+    pub fn parse(mix: &str) -> Result<Self, String> {
+        let mut weights = Vec::new();
+        for pair in mix
+            .split(',')
+            .map(str::trim)
+            .filter(|pair| !pair.is_empty())
+        {
+            let (name, weight) = pair
+                .split_once('=')
+                .ok_or(format!("Error, invalid mix entry: {pair}"))?;
+            let operation = Operation::ALL
+                .into_iter()
+                .find(|operation| operation.name() == name.trim())
+                .ok_or(format!("Error, unknown operation in mix: {name}"))?;
+            let weight: u32 = weight
+                .trim()
+                .parse()
+                .map_err(|_| format!("Error, invalid weight in mix: {pair}"))?;
+            if weight > 0 {
+                weights.push((operation, weight));
+            }
+        }
+
+        let total = weights.iter().map(|(_, weight)| weight).sum();
+        if total == 0 {
+            return Err("Error, the mix needs an operation with a positive weight".to_owned());
+        }
+        Ok(Self { weights, total })
+    }
+
+    /// The operation for a uniformly random `roll`.
+    pub fn pick(&self, roll: u32) -> Operation {
+        let mut roll = roll % self.total;
+        for (operation, weight) in &self.weights {
+            if roll < *weight {
+                return *operation;
+            }
+            roll -= weight;
+        }
+        unreachable!("rolls are below the total weight")
+    }
+
+    pub fn includes(&self, operation: Operation) -> bool {
+        self.weights
+            .iter()
+            .any(|(included, _)| *included == operation)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn should_pick_operations_by_weight() {
+        let mix = Mix::parse("sign-in=3, validate=1, sign-up=0").unwrap();
+
+        let picks: Vec<Operation> = (0..4).map(|roll| mix.pick(roll)).collect();
+        assert_eq!(
+            picks,
+            [
+                Operation::SignIn,
+                Operation::SignIn,
+                Operation::SignIn,
+                Operation::Validate
+            ]
+        );
+        assert_eq!(mix.pick(4), Operation::SignIn);
+        assert!(!mix.includes(Operation::SignUp));
+    }
+
+    #[test]
+    fn should_reject_invalid_mix() {
+        assert!(Mix::parse("sign-in").is_err());
+        assert!(Mix::parse("sign-out=1").is_err());
+        assert!(Mix::parse("sign-in=-1").is_err());
+        assert!(Mix::parse("sign-in=0").is_err());
+    }
+}
@@ -0,0 +1,132 @@
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::time::Duration;
+
+use crate::mix::Operation;
+
+/// Latencies of an operation's successful calls, and how many failed.
+#[derive(Clone, Debug, Default)]
+pub struct OperationStats {
+    latencies: Vec<Duration>,
+    errors: u64,
+}
+
+impl OperationStats {
+    pub fn record(&mut self, result: Result<Duration, ()>) {
+        match result {
+            Ok(latency) => self.latencies.push(latency),
+            Err(()) => self.errors += 1,
+        }
+    }
+
+    fn merge(&mut self, other: OperationStats) {
+        self.latencies.extend(other.latencies);
+        self.errors += other.errors;
+    }
+
+    /// The latency `percentile` percent of calls were at least as fast as, by the nearest rank
+    /// method. Expects the latencies sorted.
+    fn percentile(&self, percentile: f64) -> Duration {
+        if self.latencies.is_empty() {
+            return Duration::ZERO;
+        }
+        let rank = (percentile / 100.0 * self.latencies.len() as f64).ceil() as usize;
+        self.latencies[rank.clamp(1, self.latencies.len()) - 1]
+    }
+}
+
+/// Calls made per operation during a run.
+#[derive(Debug, Default)]
+pub struct Stats {
+    operations: BTreeMap<Operation, OperationStats>,
+}
+
+impl Stats {
+    pub fn record(&mut self, operation: Operation, result: Result<Duration, ()>) {
+        self.operations.entry(operation).or_default().record(result);
+    }
+
+    /// Adds the calls recorded by another worker.
+    pub fn merge(&mut self, other: Stats) {
+        for (operation, stats) in other.operations {
+            self.operations.entry(operation).or_default().merge(stats);
+        }
+    }
+
+    /// A table of each operation's throughput over `elapsed` and latency percentiles.
+    pub fn report(mut self, elapsed: Duration) -> String {
+        let mut report = format!(
+            "{:<10} {:>9} {:>7} {:>9} {:>9} {:>9} {:>9} {:>9}\n",
+            "operation", "requests", "errors", "req/s", "p50", "p90", "p99", "max"
+        );
+        let mut total = OperationStats::default();
+        for (operation, stats) in &mut self.operations {
+            stats.latencies.sort();
+            write_row(&mut report, &operation.to_string(), stats, elapsed);
+            total.merge(stats.clone());
+        }
+        total.latencies.sort();
+        write_row(&mut report, "total", &total, elapsed);
+        report
+    }
+}
+
+fn write_row(report: &mut String, name: &str, stats: &OperationStats, elapsed: Duration) {
+    let requests = stats.latencies.len() as u64 + stats.errors;
+    let _ = writeln!(
+        report,
+        "{:<10} {:>9} {:>7} {:>9.1} {:>9} {:>9} {:>9} {:>9}",
+        name,
+        requests,
+        stats.errors,
+        requests as f64 / elapsed.as_secs_f64().max(f64::EPSILON),
+        millis(stats.percentile(50.0)),
+        millis(stats.percentile(90.0)),
+        millis(stats.percentile(99.0)),
+        millis(stats.percentile(100.0)),
+    );
+}
+
+fn millis(latency: Duration) -> String {
+    format!("{:.1}ms", latency.as_secs_f64() * 1000.0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn should_compute_percentiles() {
+        let mut stats = OperationStats::default();
+        for millis in 1..=100 {
+            stats.record(Ok(Duration::from_millis(millis)));
+        }
+
+        assert_eq!(stats.percentile(50.0), Duration::from_millis(50));
+        assert_eq!(stats.percentile(99.0), Duration::from_millis(99));
+        assert_eq!(stats.percentile(100.0), Duration::from_millis(100));
+        assert_eq!(OperationStats::default().percentile(50.0), Duration::ZERO);
+    }
+
+    #[test]
+    fn should_report_throughput() {
+        let mut stats = Stats::default();
+        stats.record(Operation::SignIn, Ok(Duration::from_millis(20)));
+        let mut other = Stats::default();
+        other.record(Operation::SignIn, Ok(Duration::from_millis(10)));
+        other.record(Operation::Validate, Err(()));
+        stats.merge(other);
+
+        let report = stats.report(Duration::from_secs(2));
+        let rows: Vec<Vec<&str>> = report
+            .lines()
+            .map(|line| line.split_whitespace().collect())
+            .collect();
+        assert_eq!(
+            rows[1],
+            ["sign-in", "2", "0", "1.0", "10.0ms", "20.0ms", "20.0ms", "20.0ms"]
+        );
+        assert_eq!(rows[2][..4], ["validate", "1", "1", "0.5"]);
+        assert_eq!(rows[3][..4], ["total", "3", "1", "1.5"]);
+    }
+}
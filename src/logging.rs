@@ -0,0 +1,58 @@
+use std::str::FromStr;
+
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::util::SubscriberInitExt;
+use tracing_subscriber::{EnvFilter, Layer, Registry};
+
+/// Events logged by default.
+pub const DEFAULT_LOG_LEVEL: &str = "info";
+
+/// How log lines are written to stdout.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum LogFormat {
+    /// Human readable lines.
+    #[default]
+    Text,
+    /// One JSON object per line, for log collectors. Needs the `json-logs` feature.
+    Json,
+}
+
+impl FromStr for LogFormat {
+    type Err = String;
+
+    fn from_str(format: &str) -> Result<Self, Self::Err> {
+        match format {
+            "text" => Ok(LogFormat::Text),
+            "json" => Ok(LogFormat::Json),
+            _ => Err(format!("Error, unknown LOG_FORMAT: {}", format)),
+        }
+    }
+}
+
+/// A layer spans and events are also handed to, e.g. to export traces or report errors.
+pub type Exporter = Box<dyn Layer<Registry> + Send + Sync>;
+
+/// Logs events allowed by `level` to stdout. `level` takes comma separated `EnvFilter`
+/// directives, e.g. `info,auth::sessions=debug`.
+pub fn init(level: &str, format: LogFormat, mut exporters: Vec<Exporter>) -> Result<(), String> {
+    let filter =
+        EnvFilter::try_new(level).map_err(|e| format!("Error, invalid LOG_LEVEL: {}", e))?;
+    let logs = tracing_subscriber::fmt::layer();
+    let logs = match format {
+        LogFormat::Text => logs.boxed(),
+        #[cfg(feature = "json-logs")]
+        LogFormat::Json => logs.json().boxed(),
+        #[cfg(not(feature = "json-logs"))]
+        LogFormat::Json => {
+            return Err("JSON logs are configured but the `json-logs` feature is disabled".into())
+        }
+    };
+
+    // Logs go in with the exporters, as an empty list of layers would disable every event.
+    exporters.push(logs);
+    tracing_subscriber::registry()
+        .with(exporters)
+        .with(filter)
+        .try_init()
+        .map_err(|e| format!("Failed to set up logging.\n{e:?}"))
+}
@@ -0,0 +1,20 @@
+use http::{HeaderMap, HeaderValue};
+use uuid::Uuid;
+
+/// Header, and gRPC metadata key, identifying a request across services.
+pub const REQUEST_ID_HEADER: &str = "x-request-id";
+
+/// Longest request ID accepted from callers.
+const MAX_REQUEST_ID_LENGTH: usize = 128;
+
+/// The request ID the caller sent, or a new one if it sent none or one that's too long or not
+/// printable.
+pub fn request_id(headers: &HeaderMap) -> HeaderValue {
+    headers
+        .get(REQUEST_ID_HEADER)
+        .filter(|id| !id.is_empty() && id.len() <= MAX_REQUEST_ID_LENGTH && id.to_str().is_ok())
+        .cloned()
+        .unwrap_or_else(|| {
+            HeaderValue::from_str(&Uuid::new_v4().to_string()).expect("UUIDs are valid headers")
+        })
+}
@@ -0,0 +1,102 @@
+use std::convert::Infallible;
+use std::env;
+use std::net::{Ipv6Addr, SocketAddr};
+use std::sync::Arc;
+
+use auth_client::{AuthClient, CircuitBreaker, RetryPolicy};
+use http::Uri;
+use hyper::service::{make_service_fn, service_fn};
+use hyper::Server;
+
+use crate::logging::LogFormat;
+use crate::proxy::SessionGateway;
+use crate::settings::{Settings, DEFAULT_AUTH_SERVICE_PORT};
+
+#[path = "../logging.rs"]
+mod logging;
+mod proxy;
+#[path = "../request_id.rs"]
+mod request_id;
+#[path = "../settings.rs"]
+mod settings;
+
+/// Port the session gateway listens on by default.
+const DEFAULT_SESSION_GATEWAY_PORT: u16 = 8082;
+
+/// Example gateway putting a service behind the auth service. Requests need an
+/// `Authorization: Bearer <session token>` header with an active session and are forwarded to
+/// UPSTREAM_URL with the user's UUID in `x-user-uuid` instead of the token. Setting
+/// REQUIRED_GROUPS, comma separated, also requires the user to be a member of those groups, which
+/// are forwarded in `x-user-groups`.
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    // Flags override environment variables, which override the CONFIG_FILE
+    let settings = Settings::load(env::args().skip(1))?;
+    logging::init(
+        &settings
+            .var("LOG_LEVEL")
+            .unwrap_or(logging::DEFAULT_LOG_LEVEL.to_owned()),
+        match settings.var("LOG_FORMAT") {
+            Some(format) => format.parse()?,
+            None => LogFormat::Text,
+        },
+        Vec::new(),
+    )?;
+
+    let addr = match settings.var("SESSION_GATEWAY_BIND_ADDRESS") {
+        Some(addr) => addr.parse()?,
+        None => SocketAddr::from((Ipv6Addr::UNSPECIFIED, DEFAULT_SESSION_GATEWAY_PORT)),
+    };
+    let upstream: Uri = settings
+        .var("UPSTREAM_URL")
+        .filter(|url| !url.is_empty())
+        .ok_or("Error, UPSTREAM_URL is required")?
+        .parse()
+        .map_err(|e| format!("Error, invalid UPSTREAM_URL.\n{e:?}"))?;
+    if upstream.scheme_str() != Some("http") {
+        return Err("Error, UPSTREAM_URL has to be an http:// URL".into());
+    }
+    let required_groups = settings
+        .var("REQUIRED_GROUPS")
+        .unwrap_or_default()
+        .split(',')
+        .map(str::trim)
+        .filter(|group| !group.is_empty())
+        .map(str::to_owned)
+        .collect();
+
+    // Connect lazily so the gateway can start before the auth service does
+    let auth_hostname = settings
+        .var("AUTH_SERVICE_HOST_NAME")
+        .unwrap_or("[::0]".to_owned());
+    let auth_port: u16 = match settings.var("AUTH_SERVICE_PORT") {
+        Some(port) => port.parse()?,
+        None => DEFAULT_AUTH_SERVICE_PORT,
+    };
+    let auth_client = AuthClient::connect_lazy(format!("http://{}:{}", auth_hostname, auth_port))?
+        .with_retry_policy(RetryPolicy::default())
+        .with_circuit_breaker(CircuitBreaker::default());
+
+    let gateway = Arc::new(SessionGateway::new(
+        auth_client,
+        upstream.clone(),
+        required_groups,
+    ));
+    let make_service = make_service_fn(move |_| {
+        let gateway = gateway.clone();
+        async move {
+            Ok::<_, Infallible>(service_fn(move |request| {
+                let gateway = gateway.clone();
+                async move { Ok::<_, Infallible>(gateway.handle(request).await) }
+            }))
+        }
+    });
+
+    tracing::info!(%addr, %upstream, "Session gateway listening");
+    Server::try_bind(&addr)
+        .map_err(|e| format!("Failed to listen on SESSION_GATEWAY_BIND_ADDRESS {addr}.\n{e:?}"))?
+        .serve(make_service)
+        .await?;
+
+    Ok(())
+}
@@ -0,0 +1,309 @@
+use auth_client::{AuthClient, AuthError};
+use http::header::{self, HeaderName, AUTHORIZATION, RETRY_AFTER, WWW_AUTHENTICATE};
+use http::uri::{PathAndQuery, Uri};
+use http::{HeaderMap, HeaderValue, Request, Response, StatusCode};
+use hyper::client::HttpConnector;
+use hyper::{Body, Client};
+use tracing::Instrument;
+
+use crate::request_id::{request_id, REQUEST_ID_HEADER};
+
+/// Header telling the upstream whose session the request was made with.
+pub const USER_UUID_HEADER: &str = "x-user-uuid";
+/// Header listing the user's groups, comma separated, when `REQUIRED_GROUPS` is set.
+pub const USER_GROUPS_HEADER: &str = "x-user-groups";
+
+/// Headers describing a single connection rather than the request, which proxies don't forward.
+const HOP_BY_HOP_HEADERS: [HeaderName; 8] = [
+    header::CONNECTION,
+    HeaderName::from_static("keep-alive"),
+    header::PROXY_AUTHENTICATE,
+    header::PROXY_AUTHORIZATION,
+    header::TE,
+    header::TRAILER,
+    header::TRANSFER_ENCODING,
+    header::UPGRADE,
+];
+
+/// The user a request was authenticated as.
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct Caller {
+    user_uuid: String,
+    /// Only looked up when groups are required.
+    groups: Option<Vec<String>>,
+}
+
+/// Forwards requests with an active session to the upstream, answering the others itself.
+pub struct SessionGateway {
+    auth_client: AuthClient,
+    http_client: Client<HttpConnector>,
+    upstream: Uri,
+    required_groups: Vec<String>,
+}
+
+impl SessionGateway {
+    /// `upstream` is the base URL requests are forwarded to, their path is appended to its path.
+    /// Callers have to be members of every group in `required_groups`.
+    pub fn new(auth_client: AuthClient, upstream: Uri, required_groups: Vec<String>) -> Self {
+        Self {
+            auth_client,
+            http_client: Client::new(),
+            upstream,
+            required_groups,
+        }
+    }
+
+    pub async fn handle(&self, request: Request<Body>) -> Response<Body> {
+        let request_id = request_id(request.headers());
+        let span = tracing::info_span!(
+            "request",
+            method = %request.method(),
+            path = request.uri().path(),
+            request_id = request_id.to_str().unwrap_or_default(),
+        );
+
+        let mut response = async {
+            match self.authenticate(request.headers()).await {
+                Ok(caller) => self.forward(request, caller, request_id.clone()).await,
+                Err(response) => response,
+            }
+        }
+        .instrument(span)
+        .await;
+        response.headers_mut().insert(REQUEST_ID_HEADER, request_id);
+        response
+    }
+
+    /// Validates the bearer session token, then introspects it for the user's groups if any are
+    /// required. Introspection alone would also accept refresh tokens.
+    async fn authenticate(&self, headers: &HeaderMap) -> Result<Caller, Response<Body>> {
+        let session_token = bearer_token(headers)
+            .ok_or_else(|| unauthorized("Error, a bearer session token is required"))?;
+
+        let session = self
+            .auth_client
+            .validate_session(session_token)
+            .await
+            .map_err(auth_error)?;
+        if self.required_groups.is_empty() {
+            return Ok(Caller {
+                user_uuid: session.user_uuid,
+                groups: None,
+            });
+        }
+
+        let token_info = self
+            .auth_client
+            .introspect_token(session_token)
+            .await
+            .map_err(auth_error)?
+            .ok_or_else(|| unauthorized("Error, the session is not active"))?;
+        if let Some(missing) = self
+            .required_groups
+            .iter()
+            .find(|group| !token_info.groups.contains(group))
+        {
+            tracing::info!(user_uuid = token_info.user_uuid, missing, "Missing group");
+            return Err(error_response(
+                StatusCode::FORBIDDEN,
+                "Error, the user is not allowed to access this",
+            ));
+        }
+        Ok(Caller {
+            user_uuid: token_info.user_uuid,
+            groups: Some(token_info.groups),
+        })
+    }
+
+    async fn forward(
+        &self,
+        mut request: Request<Body>,
+        caller: Caller,
+        request_id: HeaderValue,
+    ) -> Response<Body> {
+        let uri = match upstream_uri(&self.upstream, request.uri()) {
+            Ok(uri) => uri,
+            Err(e) => {
+                tracing::warn!("Failed to build the upstream URL.\n{e:?}");
+                return error_response(StatusCode::BAD_REQUEST, "Error, invalid request path");
+            }
+        };
+        *request.uri_mut() = uri;
+        if let Err(e) = forwarded_headers(request.headers_mut(), &caller, request_id) {
+            tracing::warn!("Failed to set forwarded headers.\n{e:?}");
+            return error_response(StatusCode::BAD_GATEWAY, "Error, invalid user");
+        }
+
+        match self.http_client.request(request).await {
+            Ok(mut response) => {
+                remove_hop_by_hop_headers(response.headers_mut());
+                response
+            }
+            Err(e) => {
+                tracing::warn!("Failed to reach the upstream.\n{e:?}");
+                error_response(
+                    StatusCode::BAD_GATEWAY,
+                    "Error, the upstream is unavailable",
+                )
+            }
+        }
+    }
+}
+
+/// The token of an `Authorization: Bearer <token>` header.
+fn bearer_token(headers: &HeaderMap) -> Option<&str> {
+    let (scheme, token) = headers.get(AUTHORIZATION)?.to_str().ok()?.split_once(' ')?;
+    let token = token.trim();
+    (scheme.eq_ignore_ascii_case("bearer") && !token.is_empty()).then_some(token)
+}
+
+/// `request`'s path and query appended to `upstream`'s path.
+fn upstream_uri(upstream: &Uri, request: &Uri) -> Result<Uri, http::Error> {
+    let base_path = upstream.path().trim_end_matches('/');
+    let path_and_query = request
+        .path_and_query()
+        .map(PathAndQuery::as_str)
+        .unwrap_or("/");
+    let mut parts = upstream.clone().into_parts();
+    parts.path_and_query = Some(format!("{base_path}{path_and_query}").parse()?);
+    Ok(Uri::from_parts(parts)?)
+}
+
+/// Replaces the session token with the caller's identity, so the upstream can trust it without
+/// asking the auth service, and drops headers callers could spoof it with.
+fn forwarded_headers(
+    headers: &mut HeaderMap,
+    caller: &Caller,
+    request_id: HeaderValue,
+) -> Result<(), http::Error> {
+    remove_hop_by_hop_headers(headers);
+    // The upstream's host is set from its URL
+    headers.remove(header::HOST);
+    headers.remove(AUTHORIZATION);
+    headers.remove(USER_GROUPS_HEADER);
+    headers.insert(USER_UUID_HEADER, caller.user_uuid.parse()?);
+    if let Some(groups) = &caller.groups {
+        headers.insert(USER_GROUPS_HEADER, groups.join(",").parse()?);
+    }
+    headers.insert(REQUEST_ID_HEADER, request_id);
+    Ok(())
+}
+
+fn remove_hop_by_hop_headers(headers: &mut HeaderMap) {
+    // Connection also names headers that only apply to this connection
+    let named: Vec<HeaderName> = headers
+        .get_all(header::CONNECTION)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .filter_map(|name| name.trim().parse().ok())
+        .collect();
+    for name in HOP_BY_HOP_HEADERS.iter().chain(&named) {
+        headers.remove(name);
+    }
+}
+
+fn auth_error(e: AuthError) -> Response<Body> {
+    match e {
+        AuthError::Rejected => unauthorized("Error, the session is not active"),
+        // The auth service seems down, so it isn't called until it may be back
+        AuthError::CircuitOpen { retry_after } => {
+            let mut response = error_response(
+                StatusCode::SERVICE_UNAVAILABLE,
+                "Error, the auth service is unavailable",
+            );
+            response
+                .headers_mut()
+                .insert(RETRY_AFTER, HeaderValue::from(retry_after.as_secs().max(1)));
+            response
+        }
+        e if e.is_transient() => {
+            tracing::warn!("Failed to reach the auth service.\n{e}");
+            error_response(
+                StatusCode::SERVICE_UNAVAILABLE,
+                "Error, the auth service is unavailable",
+            )
+        }
+        e => {
+            tracing::error!("Failed to validate the session.\n{e}");
+            error_response(
+                StatusCode::BAD_GATEWAY,
+                "Error, the session could not be validated",
+            )
+        }
+    }
+}
+
+fn unauthorized(message: &'static str) -> Response<Body> {
+    let mut response = error_response(StatusCode::UNAUTHORIZED, message);
+    response
+        .headers_mut()
+        .insert(WWW_AUTHENTICATE, HeaderValue::from_static("Bearer"));
+    response
+}
+
+fn error_response(status: StatusCode, message: &'static str) -> Response<Body> {
+    let mut response = Response::new(Body::from(message));
+    *response.status_mut() = status;
+    response
+        .headers_mut()
+        .insert(header::CONTENT_TYPE, HeaderValue::from_static("text/plain"));
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn should_read_bearer_token() {
+        let mut headers = HeaderMap::new();
+        assert_eq!(bearer_token(&headers), None);
+
+        headers.insert(AUTHORIZATION, HeaderValue::from_static("bearer  token "));
+        assert_eq!(bearer_token(&headers), Some("token"));
+        headers.insert(AUTHORIZATION, HeaderValue::from_static("Basic dXNlcg=="));
+        assert_eq!(bearer_token(&headers), None);
+        headers.insert(AUTHORIZATION, HeaderValue::from_static("Bearer "));
+        assert_eq!(bearer_token(&headers), None);
+    }
+
+    #[test]
+    fn should_append_path_to_upstream() {
+        let request: Uri = "/orders/1?expand=items".parse().unwrap();
+
+        let uri = upstream_uri(&"http://orders:8000".parse().unwrap(), &request).unwrap();
+        assert_eq!(uri, "http://orders:8000/orders/1?expand=items");
+        let uri = upstream_uri(&"http://backend/api/".parse().unwrap(), &request).unwrap();
+        assert_eq!(uri, "http://backend/api/orders/1?expand=items");
+    }
+
+    #[test]
+    fn should_replace_token_with_caller() {
+        let mut headers = HeaderMap::new();
+        headers.insert(AUTHORIZATION, HeaderValue::from_static("Bearer token"));
+        headers.insert(USER_UUID_HEADER, HeaderValue::from_static("spoofed"));
+        headers.insert(USER_GROUPS_HEADER, HeaderValue::from_static("admins"));
+        headers.insert(header::CONNECTION, HeaderValue::from_static("x-secret"));
+        headers.insert("x-secret", HeaderValue::from_static("1"));
+        headers.insert(header::ACCEPT, HeaderValue::from_static("text/plain"));
+        let caller = Caller {
+            user_uuid: "uuid".to_owned(),
+            groups: None,
+        };
+
+        forwarded_headers(&mut headers, &caller, HeaderValue::from_static("id")).unwrap();
+
+        let mut names: Vec<&str> = headers.keys().map(HeaderName::as_str).collect();
+        names.sort();
+        assert_eq!(names, ["accept", REQUEST_ID_HEADER, USER_UUID_HEADER]);
+        assert_eq!(headers[USER_UUID_HEADER], "uuid");
+
+        let caller = Caller {
+            groups: Some(vec!["admins".to_owned(), "staff".to_owned()]),
+            ..caller
+        };
+        forwarded_headers(&mut headers, &caller, HeaderValue::from_static("id")).unwrap();
+        assert_eq!(headers[USER_GROUPS_HEADER], "admins,staff");
+    }
+}
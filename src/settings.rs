@@ -0,0 +1,299 @@
+//! Layered settings shared by the binaries. Each setting is looked up by its environment
+//! variable name, e.g. `TLS_CERT_PATH`, in order from:
+//!
+//! 1. Command line flags, named after the variable in kebab case: `--tls-cert-path cert.pem`.
+//! 2. Environment variables.
+//! 3. The TOML file at `CONFIG_FILE` (set as a flag or environment variable), where tables
+//!    prefix their keys: `cert_path` under `[tls]` is `TLS_CERT_PATH`. Arrays are joined with
+//!    commas.
+
+use std::collections::HashMap;
+use std::env;
+use std::fs;
+
+/// Port the auth service listens on by default, the recommended gRPC port.
+pub const DEFAULT_AUTH_SERVICE_PORT: u16 = 50051;
+
+#[derive(Debug, Default)]
+pub struct Settings {
+    flags: HashMap<String, String>,
+    vars: HashMap<String, String>,
+    file: HashMap<String, String>,
+}
+
+impl Settings {
+    /// Loads settings from `args` (without the program name), the environment and the config
+    /// file. Fails if a flag is missing its value or the file can't be read or parsed.
+    pub fn load(args: impl IntoIterator<Item = String>) -> Result<Self, String> {
+        Self::from_sources(args, env::vars(), |path| {
+            fs::read_to_string(path).map_err(|e| format!("Failed to read {path}.\n{e:?}"))
+        })
+    }
+
+    fn from_sources(
+        args: impl IntoIterator<Item = String>,
+        vars: impl IntoIterator<Item = (String, String)>,
+        read_file: impl Fn(&str) -> Result<String, String>,
+    ) -> Result<Self, String> {
+        let mut settings = Self {
+            flags: parse_flags(args)?,
+            vars: vars.into_iter().collect(),
+            file: HashMap::new(),
+        };
+
+        if let Some(path) = settings.var("CONFIG_FILE").filter(|path| !path.is_empty()) {
+            settings.file = parse_toml(&read_file(&path)?)
+                .map_err(|e| format!("Error, invalid config file {path}: {e}"))?;
+        }
+
+        Ok(settings)
+    }
+
+    /// The value of the setting with the highest precedence.
+    pub fn var(&self, key: &str) -> Option<String> {
+        self.flags
+            .get(key)
+            .or_else(|| self.vars.get(key))
+            .or_else(|| self.file.get(key))
+            .cloned()
+    }
+}
+
+/// Reads `--some-key value` and `--some-key=value` flags as `SOME_KEY`.
+fn parse_flags(args: impl IntoIterator<Item = String>) -> Result<HashMap<String, String>, String> {
+    let mut flags = HashMap::new();
+    let mut args = args.into_iter();
+
+    while let Some(arg) = args.next() {
+        let flag = arg
+            .strip_prefix("--")
+            .filter(|flag| !flag.is_empty())
+            .ok_or(format!("Error, unexpected argument: {arg}"))?;
+
+        let (name, value) = match flag.split_once('=') {
+            Some((name, value)) => (name, value.to_owned()),
+            None => (
+                flag,
+                args.next()
+                    .ok_or(format!("Error, --{flag} is missing a value"))?,
+            ),
+        };
+
+        flags.insert(name.replace('-', "_").to_uppercase(), value);
+    }
+
+    Ok(flags)
+}
+
+/// Flattens the subset of TOML config files need: tables of strings, numbers, booleans and
+/// single line arrays of them.
+fn parse_toml(contents: &str) -> Result<HashMap<String, String>, String> {
+    let mut settings = HashMap::new();
+    let mut prefix = String::new();
+
+    for (number, line) in contents.lines().enumerate() {
+        let line = strip_comment(line).trim();
+        let line_error = |e: &str| format!("line {}: {}", number + 1, e);
+
+        if line.is_empty() {
+            continue;
+        }
+
+        if let Some(table) = line.strip_prefix('[') {
+            let table = table
+                .strip_suffix(']')
+                .filter(|table| !table.trim().is_empty())
+                .ok_or(line_error("invalid table header"))?;
+            prefix = format!("{}_", table.trim().replace('.', "_").to_uppercase());
+            continue;
+        }
+
+        let (key, value) = line
+            .split_once('=')
+            .ok_or(line_error("expected key = value"))?;
+        let key = format!("{}{}", prefix, key.trim().to_uppercase());
+        let value = match value.trim() {
+            array if array.starts_with('[') => array
+                .strip_suffix(']')
+                .ok_or(line_error("unterminated array"))?[1..]
+                .split(',')
+                .map(str::trim)
+                .filter(|item| !item.is_empty())
+                .map(parse_value)
+                .collect::<Result<Vec<String>, String>>()
+                .map_err(|e| line_error(&e))?
+                .join(","),
+            value => parse_value(value).map_err(|e| line_error(&e))?,
+        };
+
+        if settings.insert(key.clone(), value).is_some() {
+            return Err(line_error(&format!("duplicate key {key}")));
+        }
+    }
+
+    Ok(settings)
+}
+
+/// Reads a TOML string, number or boolean as written in a config file.
+pub fn parse_value(value: &str) -> Result<String, String> {
+    if let Some(literal) = value.strip_prefix('\'') {
+        return literal
+            .strip_suffix('\'')
+            .map(str::to_owned)
+            .ok_or("unterminated string".to_string());
+    }
+
+    let Some(string) = value.strip_prefix('"') else {
+        if value.is_empty() || value.contains(char::is_whitespace) {
+            return Err(format!("invalid value {value}"));
+        }
+        // Numbers and booleans are passed on as written.
+        return Ok(value.to_owned());
+    };
+
+    let string = string
+        .strip_suffix('"')
+        .ok_or("unterminated string".to_string())?;
+    let mut unescaped = String::with_capacity(string.len());
+    let mut chars = string.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            unescaped.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('n') => unescaped.push('\n'),
+            Some('t') => unescaped.push('\t'),
+            Some(c @ ('"' | '\\')) => unescaped.push(c),
+            _ => return Err("invalid escape in string".to_string()),
+        }
+    }
+
+    Ok(unescaped)
+}
+
+/// Drops a trailing `#` comment, leaving `#` inside strings alone.
+pub fn strip_comment(line: &str) -> &str {
+    let mut quote = None;
+    let mut escaped = false;
+
+    for (i, c) in line.char_indices() {
+        match (quote, c) {
+            (Some('"'), '\\') if !escaped => {
+                escaped = true;
+                continue;
+            }
+            (Some(q), c) if c == q && !escaped => quote = None,
+            (None, '"' | '\'') => quote = Some(c),
+            (None, '#') => return &line[..i],
+            _ => (),
+        }
+        escaped = false;
+    }
+
+    line
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn settings(args: &[&str], vars: &[(&str, &str)], file: &str) -> Result<Settings, String> {
+        let file = file.to_owned();
+        Settings::from_sources(
+            args.iter().map(|arg| arg.to_string()),
+            vars.iter().map(|(k, v)| (k.to_string(), v.to_string())),
+            move |_| Ok(file.clone()),
+        )
+    }
+
+    #[test]
+    fn should_flatten_config_file() {
+        let settings = settings(
+            &[],
+            &[("CONFIG_FILE", "auth.toml")],
+            r#"
+            # Listen on every interface
+            bind_address = "[::0]:50051"
+            session_ttl_secs = 3600
+
+            [tls]
+            cert_path = '/etc/auth/cert.pem' # PEM
+            allowed_client_names = ["users.internal", "spiffe://example.com/#health"]
+
+            [oidc.google]
+            issuer = "https://accounts.google.com"
+            breached_password_check = true
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(settings.var("BIND_ADDRESS").unwrap(), "[::0]:50051");
+        assert_eq!(settings.var("SESSION_TTL_SECS").unwrap(), "3600");
+        assert_eq!(settings.var("TLS_CERT_PATH").unwrap(), "/etc/auth/cert.pem");
+        assert_eq!(
+            settings.var("TLS_ALLOWED_CLIENT_NAMES").unwrap(),
+            "users.internal,spiffe://example.com/#health"
+        );
+        assert_eq!(
+            settings.var("OIDC_GOOGLE_ISSUER").unwrap(),
+            "https://accounts.google.com"
+        );
+        assert_eq!(
+            settings.var("OIDC_GOOGLE_BREACHED_PASSWORD_CHECK").unwrap(),
+            "true"
+        );
+        assert_eq!(settings.var("TLS_KEY_PATH"), None);
+    }
+
+    #[test]
+    fn should_prefer_flags_then_environment_then_file() {
+        let settings = settings(
+            &["--session-ttl-secs", "60", "--users-backend=sqlite"],
+            &[
+                ("CONFIG_FILE", "auth.toml"),
+                ("SESSION_TTL_SECS", "120"),
+                ("LOCKOUT_THRESHOLD", "3"),
+            ],
+            "session_ttl_secs = 180\nlockout_threshold = 5\nlockout_duration_secs = 600",
+        )
+        .unwrap();
+
+        assert_eq!(settings.var("SESSION_TTL_SECS").unwrap(), "60");
+        assert_eq!(settings.var("USERS_BACKEND").unwrap(), "sqlite");
+        assert_eq!(settings.var("LOCKOUT_THRESHOLD").unwrap(), "3");
+        assert_eq!(settings.var("LOCKOUT_DURATION_SECS").unwrap(), "600");
+    }
+
+    #[test]
+    fn should_read_config_file_path_from_flag() {
+        let with_path = settings(
+            &["--config-file", "auth.toml"],
+            &[],
+            "lockout_threshold = 5",
+        )
+        .unwrap();
+        assert_eq!(with_path.var("LOCKOUT_THRESHOLD").unwrap(), "5");
+
+        // Without a path, no file is read.
+        let without_path = settings(&[], &[], "lockout_threshold = 5").unwrap();
+        assert_eq!(without_path.var("LOCKOUT_THRESHOLD"), None);
+    }
+
+    #[test]
+    fn should_reject_invalid_input() {
+        assert!(settings(&["--session-ttl-secs"], &[], "").is_err());
+        assert!(settings(&["session-ttl-secs"], &[], "").is_err());
+
+        let file = |contents: &str| settings(&["--config-file", "auth.toml"], &[], contents);
+        assert_eq!(
+            file("[tls]\ncert_path").unwrap_err(),
+            "Error, invalid config file auth.toml: line 2: expected key = value"
+        );
+        assert!(file("[tls").is_err());
+        assert!(file("cert_path = \"cert.pem").is_err());
+        assert!(file("cert_path = cert file.pem").is_err());
+        assert!(file("names = [\"a\", \"b\"").is_err());
+        assert!(file("a = 1\na = 2").is_err());
+    }
+}